@@ -0,0 +1,54 @@
+//! Delegated proof of knowledge of an EdDSA signature.
+//!
+//! A wallet holds a BabyJubJub signature on a message and proves it has one
+//! without revealing it. The MSMs go to the (here in-process) server, which
+//! only ever sees LPN-masked witness vectors.
+//!
+//! cargo run --release --example eddsa
+
+use std::time::Instant;
+
+use ark_bn254::{Bn254, Fr};
+use ark_groth16::Groth16;
+use ark_snark::SNARK;
+use stealthsnark::groth16::circuits::eddsa::{verify, EdDSACircuit, SigningKey};
+use stealthsnark::groth16::circuits::merkle::poseidon_config;
+use stealthsnark::groth16::server_aided::{
+    client_decrypt, client_encrypt, server_evaluate, ServerAidedProvingKey,
+};
+use stealthsnark::rng::OsRng;
+
+fn main() -> anyhow::Result<()> {
+    let mut rng = OsRng;
+    let config = poseidon_config::<Fr>();
+
+    let key = SigningKey::rand(&mut rng);
+    let message = Fr::from(0xdead_beefu64);
+    let signature = key.sign(&config, message, &mut rng);
+    assert!(verify(&config, &key.public_key(), message, &signature));
+
+    let start = Instant::now();
+    let (pk, vk) =
+        Groth16::<Bn254>::circuit_specific_setup(EdDSACircuit::blank(config.clone()), &mut rng)?;
+    let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+    println!(
+        "setup: {:?} ({} H-query generators)",
+        start.elapsed(),
+        sapk.emsm_h.generators.len()
+    );
+
+    let start = Instant::now();
+    let circuit = EdDSACircuit::new(config, key.public_key(), message, signature);
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng)?;
+    let response = server_evaluate(&sapk, &request)?;
+    let proof = client_decrypt(&sapk, &response, &state)?;
+    println!("delegated prove: {:?}", start.elapsed());
+
+    let inputs = EdDSACircuit::public_inputs(&key.public_key(), message);
+    anyhow::ensure!(
+        Groth16::<Bn254>::verify(&vk, &inputs, &proof)?,
+        "proof did not verify"
+    );
+    println!("proof verifies for public key and message {message}");
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Delegated Semaphore (v4) proof with the published circuit and key.
+//!
+//! Proves membership of a one-member group and emits the nullifier for
+//! `message` and `scope`, with the MSMs evaluated by an in-process server. The
+//! artifacts are not in this repository: take `semaphore-<depth>.zkey` and
+//! `semaphore-<depth>.wasm` from the Semaphore snark-artifacts release, and
+//! compile the matching `.r1cs` from `@semaphore-protocol/circuits` with the
+//! same circom version and `MAX_DEPTH`.
+//!
+//! cargo run --release --example semaphore -- --zkey semaphore-10.zkey \
+//!     --wasm semaphore-10.wasm --r1cs semaphore-10.r1cs --depth 10
+
+use std::time::Instant;
+
+use ark_bn254::Bn254;
+use ark_circom::CircomReduction;
+use ark_groth16::Groth16;
+use ark_snark::SNARK;
+use num_bigint::BigInt;
+use stealthsnark::groth16::circom::{build_circuit, get_public_inputs, load_zkey};
+use stealthsnark::groth16::server_aided::{
+    client_decrypt, client_encrypt, server_evaluate, QapReduction, ServerAidedProvingKey,
+    SetupOptions,
+};
+use stealthsnark::rng::OsRng;
+
+const USAGE: &str = "\
+Usage: semaphore --zkey PATH --wasm PATH --r1cs PATH --depth N [--secret N] [--message N] [--scope N]
+
+  --zkey     Published semaphore-<depth>.zkey
+  --wasm     Witness generator semaphore-<depth>.wasm
+  --r1cs     Constraint system the zkey was made for
+  --depth    MAX_DEPTH the circuit was compiled with
+  --secret   Identity secret scalar, below the BabyJubJub subgroup order (default: 1234)
+  --message  Signal being broadcast (default: 1)
+  --scope    External nullifier (default: 2)";
+
+struct Args {
+    zkey: String,
+    wasm: String,
+    r1cs: String,
+    depth: usize,
+    secret: BigInt,
+    message: BigInt,
+    scope: BigInt,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let (mut zkey, mut wasm, mut r1cs, mut depth) = (None, None, None, None);
+    let (mut secret, mut message, mut scope) =
+        (BigInt::from(1234), BigInt::from(1), BigInt::from(2));
+    let mut it = std::env::args().skip(1);
+    while let Some(flag) = it.next() {
+        let mut value = || {
+            it.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {flag}\n\n{USAGE}"))
+        };
+        match flag.as_str() {
+            "--zkey" => zkey = Some(value()?),
+            "--wasm" => wasm = Some(value()?),
+            "--r1cs" => r1cs = Some(value()?),
+            "--depth" => depth = Some(value()?.parse()?),
+            "--secret" => secret = value()?.parse()?,
+            "--message" => message = value()?.parse()?,
+            "--scope" => scope = value()?.parse()?,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => anyhow::bail!("unknown argument {other}\n\n{USAGE}"),
+        }
+    }
+    let missing = |name: &str| anyhow::anyhow!("{name} is required\n\n{USAGE}");
+    Ok(Args {
+        zkey: zkey.ok_or_else(|| missing("--zkey"))?,
+        wasm: wasm.ok_or_else(|| missing("--wasm"))?,
+        r1cs: r1cs.ok_or_else(|| missing("--r1cs"))?,
+        depth: depth.ok_or_else(|| missing("--depth"))?,
+        secret,
+        message,
+        scope,
+    })
+}
+
+/// Circuit inputs for a group whose only member is the prover: a proof of
+/// length 0, so the root is the identity commitment itself.
+fn witness_inputs(args: &Args) -> Vec<(&'static str, BigInt)> {
+    let mut inputs = vec![
+        ("secret", args.secret.clone()),
+        ("merkleProofLength", BigInt::from(0)),
+        ("merkleProofIndex", BigInt::from(0)),
+    ];
+    inputs.extend((0..args.depth).map(|_| ("merkleProofSiblings", BigInt::from(0))));
+    inputs.push(("message", args.message.clone()));
+    inputs.push(("scope", args.scope.clone()));
+    inputs
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let mut rng = OsRng;
+
+    let start = Instant::now();
+    let pk = load_zkey(&args.zkey)?;
+    let vk = pk.vk.clone();
+    // snarkjs keys always use the Circom reduction
+    let options = SetupOptions {
+        reduction: Some(QapReduction::Circom),
+        ..Default::default()
+    };
+    let sapk = ServerAidedProvingKey::setup_with(pk, &mut rng, &options)?;
+    println!(
+        "loaded {} and preprocessed in {:?}",
+        args.zkey,
+        start.elapsed()
+    );
+
+    let circuit = build_circuit(&args.wasm, &args.r1cs, &witness_inputs(&args))?;
+    // Outputs first, then public inputs: merkleRoot, nullifier, message, scope
+    let public_inputs = get_public_inputs(&circuit)
+        .ok_or_else(|| anyhow::anyhow!("witness generation produced no public inputs"))?;
+    anyhow::ensure!(
+        public_inputs.len() + 1 == vk.gamma_abc_g1.len(),
+        "{} has {} public inputs but the zkey expects {}; is the r1cs from the same build?",
+        args.r1cs,
+        public_inputs.len(),
+        vk.gamma_abc_g1.len() - 1
+    );
+
+    let start = Instant::now();
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng)?;
+    let response = server_evaluate(&sapk, &request)?;
+    let proof = client_decrypt(&sapk, &response, &state)?;
+    println!("delegated prove: {:?}", start.elapsed());
+
+    anyhow::ensure!(
+        Groth16::<Bn254, CircomReduction>::verify(&vk, &public_inputs, &proof)?,
+        "proof does not verify under the published verifying key"
+    );
+    println!("merkle root: {}", public_inputs[0]);
+    println!("nullifier:   {}", public_inputs[1]);
+    println!("proof verifies under the published verifying key");
+    Ok(())
+}
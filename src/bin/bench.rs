@@ -0,0 +1,326 @@
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+
+use ark_bn254::{Bn254, Fr, G2Affine};
+use ark_groth16::{Groth16, VerifyingKey};
+use ark_snark::SNARK;
+use ark_std::UniformRand;
+use serde::Serialize;
+
+use stealthsnark::groth16::circuit::SquaringChainCircuit;
+use stealthsnark::groth16::server_aided::{
+    client_decrypt, client_encrypt, ServerAidedProvingKey, ServerResponse,
+};
+use stealthsnark::protocol::client::EmsmClient;
+use stealthsnark::protocol::messages::*;
+use stealthsnark::protocol::server::{create_router, ServerConfig, ServerState};
+use stealthsnark::protocol::transport::{LocalTransport, Transport};
+use stealthsnark::rng::{OsRng, Rng};
+
+const USAGE: &str = "\
+Usage: bench [--sizes N,N,...] [--server URL | --local] [--format json|csv] [--output PATH]
+
+  --sizes   Constraint counts to sweep (default: 1024,4096,16384,65536)
+  --server  Benchmark against a running server instead of an in-process one
+  --local   Evaluate MSMs through LocalTransport, without HTTP or serialization
+  --format  Report format (default: json)
+  --output  Write the report to a file instead of stdout";
+
+/// Timings for one circuit size, in milliseconds.
+#[derive(Serialize)]
+struct BenchRow {
+    constraints: usize,
+    /// Standard Groth16 prove on this machine.
+    local_prove_ms: f64,
+    /// One-off: EMSM preprocessing and generator upload.
+    sapk_setup_ms: f64,
+    upload_ms: f64,
+    /// Per proof: encrypt + network + server + decrypt.
+    encrypt_ms: f64,
+    network_ms: f64,
+    server_ms: f64,
+    decrypt_ms: f64,
+    delegated_prove_ms: f64,
+    /// local_prove_ms / delegated_prove_ms
+    speedup: f64,
+}
+
+const CSV_HEADER: &str = "constraints,local_prove_ms,sapk_setup_ms,upload_ms,encrypt_ms,\
+network_ms,server_ms,decrypt_ms,delegated_prove_ms,speedup";
+
+impl BenchRow {
+    fn csv(&self) -> String {
+        format!(
+            "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            self.constraints,
+            self.local_prove_ms,
+            self.sapk_setup_ms,
+            self.upload_ms,
+            self.encrypt_ms,
+            self.network_ms,
+            self.server_ms,
+            self.decrypt_ms,
+            self.delegated_prove_ms,
+            self.speedup
+        )
+    }
+}
+
+struct Args {
+    sizes: Vec<usize>,
+    server: Option<String>,
+    local: bool,
+    csv: bool,
+    output: Option<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        sizes: vec![1 << 10, 1 << 12, 1 << 14, 1 << 16],
+        server: None,
+        local: false,
+        csv: false,
+        output: None,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(flag) = it.next() {
+        let mut value = || {
+            it.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {flag}\n\n{USAGE}"))
+        };
+        match flag.as_str() {
+            "--sizes" => {
+                args.sizes = value()?
+                    .split(',')
+                    .map(|s| s.trim().parse())
+                    .collect::<Result<_, _>>()?;
+            }
+            "--server" => args.server = Some(value()?),
+            "--local" => args.local = true,
+            "--format" => {
+                args.csv = match value()?.as_str() {
+                    "json" => false,
+                    "csv" => true,
+                    other => anyhow::bail!("unknown format {other}\n\n{USAGE}"),
+                }
+            }
+            "--output" => args.output = Some(value()?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => anyhow::bail!("unknown argument {other}\n\n{USAGE}"),
+        }
+    }
+    if args.local && args.server.is_some() {
+        anyhow::bail!("--server and --local are exclusive\n\n{USAGE}");
+    }
+    if args.sizes.contains(&0) {
+        anyhow::bail!("circuit sizes must be positive");
+    }
+    Ok(args)
+}
+
+/// Spawn a server on a random local port with no body size limits.
+async fn spawn_local_server() -> anyhow::Result<String> {
+    let config = ServerConfig {
+        max_setup_bytes: usize::MAX,
+        max_prove_bytes: usize::MAX,
+        ..Default::default()
+    };
+    let state = Arc::new(RwLock::new(ServerState::with_config(config)));
+    let app = create_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    Ok(format!("http://{addr}"))
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Benchmark one circuit size against the server at `server_url`, or through
+/// `LocalTransport` if there is none.
+async fn bench_size(server_url: Option<&str>, constraints: usize) -> anyhow::Result<BenchRow> {
+    let mut rng = OsRng;
+    let setup_circuit = SquaringChainCircuit::<Fr> {
+        num_constraints: constraints,
+        x: None,
+    };
+    let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(setup_circuit, &mut rng)?;
+
+    let x = Fr::rand(&mut rng);
+    let circuit = SquaringChainCircuit {
+        num_constraints: constraints,
+        x: Some(x),
+    };
+    let public_inputs = [SquaringChainCircuit::output(x, constraints)];
+
+    // Local baseline
+    let start = Instant::now();
+    let proof = Groth16::<Bn254>::prove(&pk, circuit.clone(), &mut rng)?;
+    let local_prove_ms = elapsed_ms(start);
+    anyhow::ensure!(
+        Groth16::<Bn254>::verify(&vk, &public_inputs, &proof)?,
+        "local proof failed to verify"
+    );
+
+    // One-off delegated setup
+    let start = Instant::now();
+    let sapk = Arc::new(ServerAidedProvingKey::setup(pk, &mut rng));
+    let sapk_setup_ms = elapsed_ms(start);
+
+    let Some(server_url) = server_url else {
+        return bench_local(
+            constraints,
+            local_prove_ms,
+            sapk_setup_ms,
+            sapk,
+            circuit,
+            &vk,
+        )
+        .await;
+    };
+    let session_id = format!("bench-{constraints}-{:016x}", OsRng.gen::<u64>());
+    let client = EmsmClient::new(server_url, session_id);
+    let start = Instant::now();
+    client
+        .send_setup(&SetupRequest {
+            h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+            l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+            a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+            b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+            b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+            public_generators: Vec::new(),
+            key_fingerprint: None,
+        })
+        .await?;
+    let upload_ms = elapsed_ms(start);
+
+    // Delegated proof
+    let start = Instant::now();
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng)?;
+    let prove_request = ProveRequest::from(&request);
+    let encrypt_ms = elapsed_ms(start);
+
+    let start = Instant::now();
+    let prove_response = client.send_prove(&prove_request).await?;
+    let round_trip_ms = elapsed_ms(start);
+    let server_ms = prove_response.metadata.server_ms as f64;
+
+    let start = Instant::now();
+    let server_response = ServerResponse::try_from(&prove_response)?;
+    let proof = client_decrypt(&sapk, &server_response, &state)?;
+    let decrypt_ms = elapsed_ms(start);
+    anyhow::ensure!(
+        Groth16::<Bn254>::verify(&vk, &public_inputs, &proof)?,
+        "delegated proof failed to verify"
+    );
+
+    let delegated_prove_ms = encrypt_ms + round_trip_ms + decrypt_ms;
+    Ok(BenchRow {
+        constraints,
+        local_prove_ms,
+        sapk_setup_ms,
+        upload_ms,
+        encrypt_ms,
+        network_ms: (round_trip_ms - server_ms).max(0.0),
+        server_ms,
+        decrypt_ms,
+        delegated_prove_ms,
+        speedup: local_prove_ms / delegated_prove_ms,
+    })
+}
+
+/// `bench_size` through `LocalTransport`: the MSMs run in this process on the
+/// masked vectors as they are, so there is no upload and no network time.
+async fn bench_local(
+    constraints: usize,
+    local_prove_ms: f64,
+    sapk_setup_ms: f64,
+    sapk: Arc<ServerAidedProvingKey>,
+    circuit: SquaringChainCircuit<Fr>,
+    vk: &VerifyingKey<Bn254>,
+) -> anyhow::Result<BenchRow> {
+    let mut rng = OsRng;
+    let x = circuit.x.expect("bench circuits carry a witness");
+    let public_inputs = [SquaringChainCircuit::output(x, constraints)];
+    let transport = LocalTransport::new(sapk.clone());
+
+    let start = Instant::now();
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng)?;
+    let encrypt_ms = elapsed_ms(start);
+
+    let start = Instant::now();
+    let server_response = transport.evaluate(request).await?;
+    let server_ms = elapsed_ms(start);
+
+    let start = Instant::now();
+    let proof = client_decrypt(&sapk, &server_response, &state)?;
+    let decrypt_ms = elapsed_ms(start);
+    anyhow::ensure!(
+        Groth16::<Bn254>::verify(vk, &public_inputs, &proof)?,
+        "delegated proof failed to verify"
+    );
+
+    let delegated_prove_ms = encrypt_ms + server_ms + decrypt_ms;
+    Ok(BenchRow {
+        constraints,
+        local_prove_ms,
+        sapk_setup_ms,
+        upload_ms: 0.0,
+        encrypt_ms,
+        network_ms: 0.0,
+        server_ms,
+        decrypt_ms,
+        delegated_prove_ms,
+        speedup: local_prove_ms / delegated_prove_ms,
+    })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Logs go to stderr so the report on stdout stays machine-readable
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+    let args = parse_args()?;
+
+    let server_url = match &args.server {
+        _ if args.local => None,
+        Some(url) => Some(url.clone()),
+        None => Some(spawn_local_server().await?),
+    };
+    let target = server_url.as_deref().unwrap_or("in-process, no HTTP");
+    eprintln!("=== StealthSnark Benchmark (server: {target}) ===");
+
+    let mut rows = Vec::with_capacity(args.sizes.len());
+    for &constraints in &args.sizes {
+        eprintln!("[{constraints} constraints] proving locally and delegated...");
+        let row = bench_size(server_url.as_deref(), constraints).await?;
+        eprintln!(
+            "  local {:.1} ms, delegated {:.1} ms ({:.2}x)",
+            row.local_prove_ms, row.delegated_prove_ms, row.speedup
+        );
+        rows.push(row);
+    }
+
+    let report = if args.csv {
+        let mut out = String::from(CSV_HEADER);
+        for row in &rows {
+            out.push('\n');
+            out.push_str(&row.csv());
+        }
+        out
+    } else {
+        serde_json::to_string_pretty(&rows)?
+    };
+
+    match &args.output {
+        Some(path) => std::fs::write(path, report + "\n")?,
+        None => println!("{report}"),
+    }
+    Ok(())
+}
@@ -1,37 +1,93 @@
-use ark_bn254::{Bn254, G1Affine, G2Affine};
+use std::io::Write;
+use std::sync::Arc;
+
+use ark_bn254::{Bn254, G2Affine};
 use ark_circom::CircomReduction;
-use ark_groth16::Groth16;
+use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_snark::SNARK;
-use rand::rngs::OsRng;
 
+use stealthsnark::emsm::cache::PreprocessCache;
+use stealthsnark::emsm::params::{LpnParamSet, LPN_PARAMS_ENV};
 use stealthsnark::groth16::circom::{build_circuit, circom_setup, get_public_inputs};
-use stealthsnark::groth16::server_aided::{
-    client_decrypt, client_encrypt, ServerAidedProvingKey,
-};
+use stealthsnark::groth16::fingerprint::Fingerprint;
+use stealthsnark::groth16::server_aided::{ServerAidedProvingKey, SetupOptions};
 use stealthsnark::protocol::client::EmsmClient;
 use stealthsnark::protocol::messages::*;
+use stealthsnark::rng::{OsRng, Rng};
 
 const MULTIPLIER2_WASM: &str = "circuits/build/multiplier2_js/multiplier2.wasm";
 const MULTIPLIER2_R1CS: &str = "circuits/build/multiplier2.r1cs";
 
+/// Groth16 keys for multiplier2, kept next to the preprocessing cache so that
+/// repeated runs see the same proving key and hit the cache.
+fn cached_keys(
+    cache: &PreprocessCache,
+    rng: &mut OsRng,
+) -> anyhow::Result<(ProvingKey<Bn254>, VerifyingKey<Bn254>)> {
+    let path = cache.dir().join("multiplier2.keys");
+    if let Ok(bytes) = std::fs::read(&path) {
+        if let Ok(keys) =
+            <(ProvingKey<Bn254>, VerifyingKey<Bn254>)>::deserialize_uncompressed(bytes.as_slice())
+        {
+            return Ok(keys);
+        }
+    }
+    let keys = circom_setup(MULTIPLIER2_WASM, MULTIPLIER2_R1CS, rng)?;
+    let mut bytes = Vec::new();
+    keys.serialize_uncompressed(&mut bytes)?;
+    std::fs::write(&path, bytes)?;
+    Ok(keys)
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
+    // Noise weights from a newer analysis: STEALTHSNARK_LPN_PARAMS=/path/to/lpn.json
+    let lpn_params = LpnParamSet::from_env()?;
+    if let Some(params) = &lpn_params {
+        println!(
+            "Using {} LPN parameter tables from {LPN_PARAMS_ENV}",
+            params.tables.len()
+        );
+    }
 
     let mut rng = OsRng;
     let server_url = "http://127.0.0.1:3000";
-    let session_id = format!("{:016x}", rand::random::<u64>());
+    let session_id = format!("{:016x}", rng.gen::<u64>());
 
     println!("=== StealthSnark Client (Circom multiplier2) ===");
     println!("Session: {session_id}");
 
     // Step 1: Groth16 setup with Circom circuit
+    // STEALTHSNARK_CACHE_DIR keeps the keys and EMSM preprocessing across runs
+    let cache = match std::env::var("STEALTHSNARK_CACHE_DIR") {
+        Ok(dir) => Some(PreprocessCache::new(dir)?),
+        Err(_) => None,
+    };
     println!("[1/6] Running Groth16 trusted setup (Circom multiplier2)...");
-    let (pk, vk) = circom_setup(MULTIPLIER2_WASM, MULTIPLIER2_R1CS, &mut rng)?;
+    let (pk, vk) = match &cache {
+        Some(cache) => cached_keys(cache, &mut rng)?,
+        None => circom_setup(MULTIPLIER2_WASM, MULTIPLIER2_R1CS, &mut rng)?,
+    };
 
     // Step 2: Create server-aided proving key (EMSM preprocessing)
     println!("[2/6] Creating server-aided proving key (EMSM preprocessing)...");
-    let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+    let progress = |msm: &str, fraction: f64| {
+        print!("\r      {msm:>4}: {:>3.0}%", fraction * 100.0);
+        if fraction >= 1.0 {
+            println!();
+        }
+        let _ = std::io::stdout().flush();
+    };
+    let options = SetupOptions {
+        progress: Some(&progress),
+        cache: cache.as_ref(),
+        lpn_params: lpn_params.as_ref(),
+        ..Default::default()
+    };
+    let sapk = ServerAidedProvingKey::setup_with(pk, &mut rng, &options)?;
+    print!("{}", sapk.stats());
 
     // Step 3: Send generators to server
     println!("[3/6] Sending generators to server...");
@@ -42,43 +98,26 @@ async fn main() -> anyhow::Result<()> {
         a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
         b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
         b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: Some(sapk.fingerprint()),
     };
     http_client.send_setup(&setup_request).await?;
 
-    // Step 4: Build Circom circuit and encrypt
-    println!("[4/6] Building Circom circuit (a=3, b=11) and encrypting...");
+    // Step 4: Build Circom circuit with witness
+    println!("[4/6] Building Circom circuit (a=3, b=11)...");
     let circuit = build_circuit(
         MULTIPLIER2_WASM,
         MULTIPLIER2_R1CS,
         &[("a", 3.into()), ("b", 11.into())],
     )?;
     let public_inputs = get_public_inputs(&circuit).expect("no public inputs");
-    let (request, state) = client_encrypt::<CircomReduction, _, _>(&sapk, circuit, &mut rng)?;
 
-    // Step 5: Send masked vectors to server, receive MSM results
+    // Steps 5-6: masking, the server round trip and unmasking; the CPU-heavy
+    // client work runs on the blocking pool, off the runtime's workers
     println!("[5/6] Delegating MSM computation to server...");
-    let prove_request = ProveRequest {
-        v_h: ark_vec_to_bytes(&request.v_h),
-        v_l: ark_vec_to_bytes(&request.v_l),
-        v_a: ark_vec_to_bytes(&request.v_a),
-        v_b_g1: ark_vec_to_bytes(&request.v_b_g1),
-        v_b_g2: ark_vec_to_bytes(&request.v_b_g2),
-    };
-    let prove_response = http_client.send_prove(&prove_request).await?;
-
-    // Decode server response back to group elements
-    let server_response = stealthsnark::groth16::server_aided::ServerResponse {
-        em_h: ark_from_bytes::<G1Affine>(&prove_response.em_h)?.into(),
-        em_l: ark_from_bytes::<G1Affine>(&prove_response.em_l)?.into(),
-        em_a: ark_from_bytes::<G1Affine>(&prove_response.em_a)?.into(),
-        em_b_g1: ark_from_bytes::<G1Affine>(&prove_response.em_b_g1)?.into(),
-        em_b_g2: ark_from_bytes::<G2Affine>(&prove_response.em_b_g2)?.into(),
-    };
-
-    // Step 6: Decrypt and verify
-    println!("[6/6] Decrypting proof and verifying...");
-    let proof = client_decrypt(&sapk, &server_response, &state);
+    let proof = http_client.prove(Arc::new(sapk), circuit, rng).await?;
 
+    println!("[6/6] Verifying proof...");
     let valid = Groth16::<Bn254, CircomReduction>::verify(&vk, &public_inputs, &proof)?;
 
     if valid {
@@ -0,0 +1,288 @@
+use ark_bn254::{Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::CurveGroup;
+use ark_serialize::CanonicalSerialize;
+use ark_std::UniformRand;
+use serde::Serialize;
+
+use stealthsnark::emsm::emsm::{encrypt, EmsmPublicParams};
+use stealthsnark::emsm::pedersen::Pedersen;
+use stealthsnark::emsm::raa_code::PermutationMode;
+use stealthsnark::groth16::server_aided::ServerResponse;
+use stealthsnark::protocol::audit::to_hex;
+use stealthsnark::protocol::messages::*;
+use stealthsnark::protocol::server::{ProveEnvelope, SetupEnvelope, DEFAULT_CIRCUIT};
+use stealthsnark::rng::{ChaCha20Rng, Rng, SeedableRng};
+
+const USAGE: &str = "\
+Usage: golden [--seed N] [--sizes N,N,...] [--output PATH]
+
+  --seed    Seed all vectors are derived from (default: 0)
+  --sizes   Witness lengths to emit EMSM vectors for, in G1 and G2 (default: 4,16)
+  --output  Write the vectors to a file instead of stdout
+
+Emits conformance vectors for the encodings in docs/wire-format.md. All byte
+strings are lowercase hex.";
+
+/// Bumped whenever the wire format or the vector layout changes.
+const FORMAT_VERSION: u32 = 8;
+
+/// Generator set length of the sample envelopes.
+const ENVELOPE_LEN: usize = 2;
+
+#[derive(Serialize)]
+struct GoldenVectors {
+    format_version: u32,
+    seed: u64,
+    msm: Vec<MsmVector>,
+    envelopes: EnvelopeVectors,
+}
+
+/// One EMSM round: the client masks `witness`, the server returns
+/// `server_msm` = MSM(masked, generators), and the client subtracts `noise_term`
+/// to get `msm` = MSM(witness, generators).
+#[derive(Serialize)]
+struct MsmVector {
+    group: &'static str,
+    n: usize,
+    /// Code length N = 4n, the length of the noise vector.
+    big_n: usize,
+    /// Noise weight.
+    t: usize,
+    /// `EmsmPublicParams::from_seed` seed (stored permutations). Only the client
+    /// uses it; a server needs nothing beyond `generators` and `masked`.
+    params_seed: String,
+    generators: Vec<String>,
+    witness: Vec<String>,
+    noise: Vec<NoiseEntry>,
+    /// r = T * e.
+    mask: Vec<String>,
+    /// v = witness + mask.
+    masked: Vec<String>,
+    /// `masked` as a length-prefixed vector, as it appears in a `ProveRequest`.
+    masked_bytes: String,
+    server_msm: String,
+    /// <e, h> with h = T^T * generators, equal to MSM(mask, generators).
+    noise_term: String,
+    msm: String,
+}
+
+#[derive(Serialize)]
+struct NoiseEntry {
+    index: usize,
+    value: String,
+}
+
+/// A setup and prove exchange for one session, as bincode bodies. A conforming
+/// server answers `prove_envelope` with `prove_response` (up to `metadata`).
+#[derive(Serialize)]
+struct EnvelopeVectors {
+    session_id: String,
+    setup_request: String,
+    setup_envelope: String,
+    generators_hash: String,
+    setup_response: String,
+    prove_request: String,
+    prove_envelope: String,
+    /// With `ProveMetadata::default()`.
+    prove_response: String,
+}
+
+struct Args {
+    seed: u64,
+    sizes: Vec<usize>,
+    output: Option<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        seed: 0,
+        sizes: vec![4, 16],
+        output: None,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(flag) = it.next() {
+        let mut value = || {
+            it.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {flag}\n\n{USAGE}"))
+        };
+        match flag.as_str() {
+            "--seed" => args.seed = value()?.parse()?,
+            "--sizes" => {
+                args.sizes = value()?
+                    .split(',')
+                    .map(|s| s.trim().parse())
+                    .collect::<Result<_, _>>()?;
+            }
+            "--output" => args.output = Some(value()?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => anyhow::bail!("unknown argument {other}\n\n{USAGE}"),
+        }
+    }
+    if args.sizes.contains(&0) {
+        anyhow::bail!("witness lengths must be positive");
+    }
+    Ok(args)
+}
+
+fn hex<T: CanonicalSerialize>(val: &T) -> String {
+    to_hex(&ark_to_bytes(val))
+}
+
+fn hex_all<T: CanonicalSerialize>(vals: &[T]) -> Vec<String> {
+    vals.iter().map(hex).collect()
+}
+
+fn msm_vector<G: CurveGroup>(
+    group: &'static str,
+    n: usize,
+    rng: &mut ChaCha20Rng,
+) -> anyhow::Result<MsmVector> {
+    let generators: Vec<G::Affine> = (0..n).map(|_| G::rand(rng).into_affine()).collect();
+    let params_seed: [u8; 32] = rng.gen();
+    let params =
+        EmsmPublicParams::<G>::from_seed(generators.clone(), params_seed, PermutationMode::Stored);
+    let witness: Vec<G::ScalarField> = (0..n).map(|_| G::ScalarField::rand(rng)).collect();
+
+    let (masked, noise) = encrypt(&params, &witness, rng);
+    let mask: Vec<G::ScalarField> = masked.iter().zip(&witness).map(|(v, z)| *v - z).collect();
+    let server_msm = params.server_computation(&masked)?;
+    let noise_term = params.preprocess().pedersen_h.commit_sparse(&noise)?;
+    let msm = Pedersen::<G>::from_generators(generators.clone()).commit(&witness)?;
+    anyhow::ensure!(
+        server_msm - noise_term == msm,
+        "{group} n={n}: unmasked MSM does not match the plaintext MSM"
+    );
+
+    Ok(MsmVector {
+        group,
+        n,
+        big_n: params.t_operator.big_n,
+        t: params.t,
+        params_seed: to_hex(&params_seed),
+        generators: hex_all(&generators),
+        witness: hex_all(&witness),
+        noise: noise
+            .iter()
+            .map(|(index, value)| NoiseEntry {
+                index,
+                value: hex(&value),
+            })
+            .collect(),
+        mask: hex_all(&mask),
+        masked: hex_all(&masked),
+        masked_bytes: to_hex(&ark_vec_to_bytes(&masked)),
+        server_msm: hex(&server_msm.into_affine()),
+        noise_term: hex(&noise_term.into_affine()),
+        msm: hex(&msm.into_affine()),
+    })
+}
+
+fn envelope_vectors(seed: u64, rng: &mut ChaCha20Rng) -> anyhow::Result<EnvelopeVectors> {
+    let g1 = |rng: &mut ChaCha20Rng| -> Vec<G1Affine> {
+        (0..ENVELOPE_LEN)
+            .map(|_| G1Projective::rand(rng).into_affine())
+            .collect()
+    };
+    let scalars =
+        |rng: &mut ChaCha20Rng| -> Vec<Fr> { (0..ENVELOPE_LEN).map(|_| Fr::rand(rng)).collect() };
+    let g1_sets = [g1(rng), g1(rng), g1(rng), g1(rng)];
+    let g2_set: Vec<G2Affine> = (0..ENVELOPE_LEN)
+        .map(|_| G2Projective::rand(rng).into_affine())
+        .collect();
+    let g1_vectors = [scalars(rng), scalars(rng), scalars(rng), scalars(rng)];
+    let g2_vector = scalars(rng);
+
+    let session_id = format!("golden-{seed}");
+    let setup_request = SetupRequest {
+        h_generators: ark_vec_to_bytes(&g1_sets[0]),
+        l_generators: ark_vec_to_bytes(&g1_sets[1]),
+        a_generators: ark_vec_to_bytes(&g1_sets[2]),
+        b_g1_generators: ark_vec_to_bytes(&g1_sets[3]),
+        b_g2_generators: ark_vec_to_bytes(&g2_set),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    let setup_request_bytes = bincode::serialize(&setup_request)?;
+    let setup_envelope = SetupEnvelope {
+        session_id: session_id.clone(),
+        circuit: DEFAULT_CIRCUIT.to_string(),
+        curve: CurveId::Bn254,
+        request: &setup_request,
+    };
+    let generators_hash = setup_request.generators_hash();
+    let setup_response = SetupResponse {
+        generators_hash,
+        session_token: SessionToken(rng.gen()),
+    };
+
+    let prove_request = ProveRequest {
+        v_h: ark_vec_to_bytes(&g1_vectors[0]),
+        v_l: ark_vec_to_bytes(&g1_vectors[1]),
+        v_a: ark_vec_to_bytes(&g1_vectors[2]),
+        v_b_g1: ark_vec_to_bytes(&g1_vectors[3]),
+        v_b_g2: ark_vec_to_bytes(&g2_vector),
+        hint: None,
+    };
+    let prove_request_bytes = bincode::serialize(&prove_request)?;
+    let prove_envelope = ProveEnvelope {
+        session_id: session_id.clone(),
+        circuit: DEFAULT_CIRCUIT.to_string(),
+        curve: CurveId::Bn254,
+        generators_hash,
+        request: &prove_request,
+    };
+
+    let g1_msm = |i: usize| {
+        Pedersen::<G1Projective>::from_generators(g1_sets[i].clone()).commit(&g1_vectors[i])
+    };
+    let response = ServerResponse {
+        em_h: g1_msm(0)?,
+        em_l: g1_msm(1)?,
+        em_a: g1_msm(2)?,
+        em_b_g1: g1_msm(3)?,
+        em_b_g2: Pedersen::<G2Projective>::from_generators(g2_set).commit(&g2_vector)?,
+    };
+    let prove_response = ProveResponse::new(&response, ProveMetadata::default());
+
+    Ok(EnvelopeVectors {
+        session_id,
+        setup_request: to_hex(&setup_request_bytes),
+        setup_envelope: to_hex(&bincode::serialize(&setup_envelope)?),
+        generators_hash: to_hex(&generators_hash),
+        setup_response: to_hex(&bincode::serialize(&setup_response)?),
+        prove_request: to_hex(&prove_request_bytes),
+        prove_envelope: to_hex(&bincode::serialize(&prove_envelope)?),
+        prove_response: to_hex(&bincode::serialize(&prove_response)?),
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    // Golden vectors must be reproducible, so everything comes from the seed
+    // rather than OsRng.
+    let mut rng = ChaCha20Rng::seed_from_u64(args.seed);
+
+    let mut msm = Vec::with_capacity(2 * args.sizes.len());
+    for &n in &args.sizes {
+        eprintln!("[n = {n}] generating G1 and G2 vectors...");
+        msm.push(msm_vector::<G1Projective>("g1", n, &mut rng)?);
+        msm.push(msm_vector::<G2Projective>("g2", n, &mut rng)?);
+    }
+    let envelopes = envelope_vectors(args.seed, &mut rng)?;
+
+    let vectors = GoldenVectors {
+        format_version: FORMAT_VERSION,
+        seed: args.seed,
+        msm,
+        envelopes,
+    };
+    let report = serde_json::to_string_pretty(&vectors)?;
+    match &args.output {
+        Some(path) => std::fs::write(path, report + "\n")?,
+        None => println!("{report}"),
+    }
+    Ok(())
+}
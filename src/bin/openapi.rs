@@ -0,0 +1,9 @@
+use stealthsnark::protocol::openapi::ApiDoc;
+use utoipa::OpenApi;
+
+/// Print the server's OpenAPI document, for generating clients in other
+/// languages.
+fn main() -> anyhow::Result<()> {
+    println!("{}", ApiDoc::openapi().to_pretty_json()?);
+    Ok(())
+}
@@ -1,13 +1,217 @@
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-use stealthsnark::protocol::server::{create_router, ServerState};
+use stealthsnark::emsm::parallel::ParallelConfig;
+use stealthsnark::protocol::audit::AuditLog;
+#[cfg(feature = "fault-injection")]
+use stealthsnark::protocol::fault::{Fault, FaultInjector};
+use stealthsnark::protocol::gate::SetupGate;
+use stealthsnark::protocol::scheduler::{QosWeights, SchedulerConfig};
+use stealthsnark::protocol::server::{create_router, ServerConfig, ServerState};
+use stealthsnark::protocol::store::{DirectoryStore, ObjectStore};
+use stealthsnark::protocol::tenant::Tenant;
+
+/// Deliberately wrong answers for testing clients: `STEALTHSNARK_FAULT=negate:h`,
+/// `add:<msm>` or `stale` (see `Fault`).
+#[cfg(feature = "fault-injection")]
+fn fault_from_env() -> Option<Arc<FaultInjector>> {
+    let fault: Fault = std::env::var("STEALTHSNARK_FAULT")
+        .ok()?
+        .parse()
+        .expect("STEALTHSNARK_FAULT is not a valid fault");
+    tracing::warn!("Fault injection: {fault:?}; proofs will not verify");
+    Some(Arc::new(FaultInjector::new(fault)))
+}
+
+/// Read the /setup admission policy from the environment:
+/// `STEALTHSNARK_SETUP_API_KEYS` (comma-separated) or `STEALTHSNARK_SETUP_POW_BITS`.
+fn setup_gate_from_env() -> SetupGate {
+    if let Ok(keys) = std::env::var("STEALTHSNARK_SETUP_API_KEYS") {
+        let keys: HashSet<String> = keys
+            .split(',')
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .map(String::from)
+            .collect();
+        return SetupGate::ApiKeys(keys);
+    }
+    if let Ok(bits) = std::env::var("STEALTHSNARK_SETUP_POW_BITS") {
+        let difficulty = bits
+            .parse()
+            .expect("STEALTHSNARK_SETUP_POW_BITS must be an integer");
+        return SetupGate::ProofOfWork { difficulty };
+    }
+    SetupGate::Open
+}
+
+/// Read the applications sharing the server from `STEALTHSNARK_TENANTS`, the
+/// path of a JSON array of `Tenant`s.
+fn tenants_from_env() -> Vec<Tenant> {
+    let Ok(path) = std::env::var("STEALTHSNARK_TENANTS") else {
+        return Vec::new();
+    };
+    let json = std::fs::read(&path).unwrap_or_else(|e| panic!("cannot read {path}: {e}"));
+    let tenants: Vec<Tenant> =
+        serde_json::from_slice(&json).expect("STEALTHSNARK_TENANTS is not a JSON array of tenants");
+    tracing::info!(tenants = tenants.len(), "Tenants loaded");
+    tenants
+}
+
+/// Queue prove and MSM requests by QoS class once `STEALTHSNARK_PROVE_SLOTS` of
+/// them are being evaluated, with the default 4:1 interactive to batch weights.
+/// Past `STEALTHSNARK_MAX_QUEUED` queued requests, new ones are turned away.
+fn scheduler_from_env() -> Option<SchedulerConfig> {
+    let slots = std::env::var("STEALTHSNARK_PROVE_SLOTS").ok()?;
+    let slots = slots
+        .parse()
+        .expect("STEALTHSNARK_PROVE_SLOTS must be a positive integer");
+    let max_queued = std::env::var("STEALTHSNARK_MAX_QUEUED").ok().map(|max| {
+        max.parse()
+            .expect("STEALTHSNARK_MAX_QUEUED must be an integer")
+    });
+    Some(SchedulerConfig {
+        slots,
+        weights: QosWeights::default(),
+        max_queued,
+    })
+}
+
+/// Read the MSM thread budget from the environment: `STEALTHSNARK_NUMA_NODES`
+/// (the core IDs of each node, comma-separated, nodes separated by `;`),
+/// `STEALTHSNARK_PIN_CORES` (comma-separated core IDs) or `STEALTHSNARK_THREADS`.
+fn parallel_from_env() -> ParallelConfig {
+    let parse_cores = |cores: &str| -> Vec<usize> {
+        cores
+            .split(',')
+            .map(|c| c.trim().parse().expect("expected comma-separated core IDs"))
+            .collect()
+    };
+    let parallel = if let Ok(nodes) = std::env::var("STEALTHSNARK_NUMA_NODES") {
+        let nodes: Vec<Vec<usize>> = nodes.split(';').map(parse_cores).collect();
+        ParallelConfig::numa(&nodes).expect("failed to build NUMA thread pools")
+    } else if let Ok(cores) = std::env::var("STEALTHSNARK_PIN_CORES") {
+        ParallelConfig::pinned(&parse_cores(&cores)).expect("failed to build pinned thread pool")
+    } else if let Ok(threads) = std::env::var("STEALTHSNARK_THREADS") {
+        let threads = threads
+            .parse()
+            .expect("STEALTHSNARK_THREADS must be an integer");
+        ParallelConfig::with_threads(threads).expect("failed to build thread pool")
+    } else {
+        ParallelConfig::default()
+    };
+    tracing::info!(threads = parallel.num_threads(), "MSM thread pool ready");
+    parallel
+}
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let state = Arc::new(RwLock::new(ServerState::new()));
+    // Optional append-only audit trail: STEALTHSNARK_AUDIT_LOG=/path/to/audit.jsonl
+    let audit = std::env::var("STEALTHSNARK_AUDIT_LOG").ok().map(|path| {
+        let log = AuditLog::open_file(&path).expect("failed to open audit log");
+        tracing::info!("Audit log: {path}");
+        Arc::new(log)
+    });
+
+    // Optional stateless mode, with generators in a bucket mounted at
+    // STEALTHSNARK_STORE_DIR=/mnt/generators and shared by every replica
+    let store = std::env::var("STEALTHSNARK_STORE_DIR").ok().map(|dir| {
+        tracing::info!(dir, "Object store");
+        Arc::new(DirectoryStore::new(dir)) as Arc<dyn ObjectStore>
+    });
+
+    // Optional deadline for evaluating a prove request: STEALTHSNARK_PROVE_TIMEOUT_SECS=30
+    let prove_timeout = std::env::var("STEALTHSNARK_PROVE_TIMEOUT_SECS")
+        .ok()
+        .map(|secs| {
+            let secs = secs
+                .parse()
+                .expect("STEALTHSNARK_PROVE_TIMEOUT_SECS must be an integer");
+            Duration::from_secs(secs)
+        });
+
+    // Optional idle expiry of sessions: STEALTHSNARK_SESSION_TTL_SECS=3600
+    let session_ttl = std::env::var("STEALTHSNARK_SESSION_TTL_SECS")
+        .ok()
+        .map(|secs| {
+            let secs = secs
+                .parse()
+                .expect("STEALTHSNARK_SESSION_TTL_SECS must be an integer");
+            Duration::from_secs(secs)
+        });
+
+    // Optional replay window for retried prove requests: STEALTHSNARK_PROVE_CACHE_SECS=600
+    let prove_cache_ttl = std::env::var("STEALTHSNARK_PROVE_CACHE_SECS")
+        .ok()
+        .map(|secs| {
+            let secs = secs
+                .parse()
+                .expect("STEALTHSNARK_PROVE_CACHE_SECS must be an integer");
+            Duration::from_secs(secs)
+        });
+
+    // Body size limits, raised for large circuits:
+    // STEALTHSNARK_MAX_SETUP_BYTES / STEALTHSNARK_MAX_PROVE_BYTES
+    let defaults = ServerConfig::default();
+    let byte_limit = |var: &str, default: usize| match std::env::var(var) {
+        Ok(bytes) => bytes
+            .parse()
+            .unwrap_or_else(|_| panic!("{var} must be an integer")),
+        Err(_) => default,
+    };
+
+    let config = ServerConfig {
+        setup_gate: setup_gate_from_env(),
+        max_setup_bytes: byte_limit("STEALTHSNARK_MAX_SETUP_BYTES", defaults.max_setup_bytes),
+        max_prove_bytes: byte_limit("STEALTHSNARK_MAX_PROVE_BYTES", defaults.max_prove_bytes),
+        audit,
+        prove_timeout,
+        session_ttl,
+        prove_cache_ttl,
+        parallel: parallel_from_env(),
+        tenants: tenants_from_env(),
+        scheduler: scheduler_from_env(),
+        store,
+        // Optional admin API (GET /admin/sessions, /admin/metrics): STEALTHSNARK_ADMIN_KEY
+        admin_key: std::env::var("STEALTHSNARK_ADMIN_KEY").ok(),
+        // Optional fixed-base MSM tables, built after setup: STEALTHSNARK_FIXED_BASE_WINDOW=12
+        fixed_base_window: std::env::var("STEALTHSNARK_FIXED_BASE_WINDOW")
+            .ok()
+            .map(|bits| {
+                bits.parse()
+                    .expect("STEALTHSNARK_FIXED_BASE_WINDOW must be an integer")
+            }),
+        #[cfg(feature = "fault-injection")]
+        fault: fault_from_env(),
+        ..defaults
+    };
+    match &config.setup_gate {
+        SetupGate::Open => tracing::info!("Setup gate: open"),
+        SetupGate::ApiKeys(keys) => tracing::info!("Setup gate: {} API key(s)", keys.len()),
+        SetupGate::ProofOfWork { difficulty } => {
+            tracing::info!("Setup gate: proof-of-work ({difficulty} bits)")
+        }
+    }
+    let state = Arc::new(RwLock::new(ServerState::with_config(config)));
+
+    // Free the generators of expired sessions even when no setups arrive
+    if let Some(ttl) = session_ttl {
+        tracing::info!("Session TTL: {ttl:?}");
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ttl);
+            loop {
+                interval.tick().await;
+                let evicted = state.write().await.evict_expired();
+                if evicted > 0 {
+                    tracing::info!(evicted, "Evicted expired sessions");
+                }
+            }
+        });
+    }
     let app = create_router(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
@@ -15,7 +219,5 @@ async fn main() {
         .expect("failed to bind to port 3000");
 
     tracing::info!("StealthSnark server listening on :3000");
-    axum::serve(listener, app)
-        .await
-        .expect("server error");
+    axum::serve(listener, app).await.expect("server error");
 }
@@ -0,0 +1,60 @@
+//! Grumpkin: the short Weierstrass curve y^2 = x^3 - 17 over BN254's scalar
+//! field, whose group order is BN254's base field modulus. The two curves form a
+//! cycle, so Grumpkin commitments are cheap to open inside BN254 circuits.
+//! Parameters and generator (1, sqrt(-16)) follow Aztec's barretenberg.
+
+use ark_bn254::{Fq, Fr};
+use ark_ec::short_weierstrass::{Affine, Projective, SWCurveConfig};
+use ark_ec::CurveConfig;
+use ark_ff::{MontFp, Zero};
+
+/// Grumpkin's scalar field: BN254's base field.
+pub type ScalarField = Fq;
+/// Grumpkin's base field: BN254's scalar field.
+pub type BaseField = Fr;
+
+pub type GrumpkinAffine = Affine<GrumpkinConfig>;
+pub type GrumpkinProjective = Projective<GrumpkinConfig>;
+
+pub struct GrumpkinConfig;
+
+impl CurveConfig for GrumpkinConfig {
+    type BaseField = Fr;
+    type ScalarField = Fq;
+
+    const COFACTOR: &'static [u64] = &[1];
+    const COFACTOR_INV: Fq = MontFp!("1");
+}
+
+impl SWCurveConfig for GrumpkinConfig {
+    const COEFF_A: Fr = MontFp!("0");
+    const COEFF_B: Fr = MontFp!("-17");
+    const GENERATOR: GrumpkinAffine = GrumpkinAffine::new_unchecked(
+        MontFp!("1"),
+        MontFp!("17631683881184975370165255887551781615748388533673675138860"),
+    );
+
+    #[inline(always)]
+    fn mul_by_a(_: Self::BaseField) -> Self::BaseField {
+        Self::BaseField::zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ec::{AffineRepr, CurveGroup, PrimeGroup};
+    use ark_ff::PrimeField;
+
+    #[test]
+    fn test_generator_has_prime_order() {
+        let g = GrumpkinAffine::generator();
+        assert!(g.is_on_curve());
+        assert!(g.is_in_correct_subgroup_assuming_on_curve());
+        assert!(g.mul_bigint(ScalarField::MODULUS).into_affine().is_zero());
+        assert_eq!(
+            GrumpkinProjective::generator() * ScalarField::from(2u64),
+            GrumpkinProjective::generator() + GrumpkinProjective::generator()
+        );
+    }
+}
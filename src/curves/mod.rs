@@ -0,0 +1,7 @@
+//! Curves without a pairing that the EMSM machinery and the server's generic
+//! delegated MSM (POST /emsm/eval) work over, for Pedersen-style commitments
+//! outside Groth16. The EMSM core is generic over `CurveGroup`, so any arkworks
+//! curve can be masked and unmasked locally; the ones here also have a
+//! `CurveId` on the wire.
+
+pub mod grumpkin;
@@ -0,0 +1,240 @@
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ark_ec::CurveGroup;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use ark_std::rand::{CryptoRng, Rng};
+use sha2::{Digest, Sha256};
+
+use super::cancel::Cancelled;
+use super::emsm::{EmsmPublicParams, PreprocessOptions, PreprocessedCommitments};
+use super::pedersen::Pedersen;
+use super::raa_code::{PermutationMode, DEFAULT_FOLD};
+
+/// Bumped whenever the cached data or the seed-to-TOperator derivation changes.
+const CACHE_DOMAIN: &[u8] = b"stealthsnark-emsm-cache-v1";
+
+/// On-disk cache of EMSM preprocessing, keyed by a hash of the generators.
+///
+/// Each entry stores the seed the `TOperator` was derived from and the preprocessed
+/// generators h = G^T * g, so a later run over the same generators rebuilds the
+/// identical `EmsmPublicParams` and skips the transpose entirely. Entries are read
+/// back without curve checks (only their length is verified), so the directory must
+/// be as trusted as the proving key itself.
+#[derive(Clone, Debug)]
+pub struct PreprocessCache {
+    dir: PathBuf,
+}
+
+impl PreprocessCache {
+    /// Use `dir` as the cache directory, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cache entry for `generators` with permutations represented as
+    /// `permutations`.
+    pub fn entry<G: CurveGroup>(
+        &self,
+        generators: &[G::Affine],
+        permutations: PermutationMode,
+    ) -> CacheEntry {
+        CacheEntry {
+            path: self
+                .dir
+                .join(format!("{}.emsm", cache_key::<G>(generators, permutations))),
+            n: generators.len(),
+        }
+    }
+
+    /// Build `EmsmPublicParams` for `generators` and preprocess them, reusing a
+    /// cached entry when one exists. On a miss the `TOperator` seed is drawn from
+    /// `rng` and the result is written back; failing to write only logs a warning.
+    pub fn params_and_preprocess<G: CurveGroup, R: Rng + CryptoRng>(
+        &self,
+        generators: Vec<G::Affine>,
+        permutations: PermutationMode,
+        msm: &str,
+        rng: &mut R,
+        options: &PreprocessOptions,
+    ) -> Result<(EmsmPublicParams<G>, PreprocessedCommitments<G>), Cancelled> {
+        let entry = self.entry::<G>(&generators, permutations);
+
+        if let Some((seed, pre)) = entry.load::<G>() {
+            tracing::debug!(msm, path = %entry.path.display(), "EMSM preprocessing cache hit");
+            if let Some(sink) = options.progress {
+                sink.on_progress(msm, 1.0);
+            }
+            return Ok((
+                EmsmPublicParams::from_seed(generators, seed, permutations),
+                pre,
+            ));
+        }
+
+        let seed: [u8; 32] = rng.gen();
+        let params = EmsmPublicParams::from_seed(generators, seed, permutations);
+        let pre = params.preprocess_with(msm, options)?;
+        entry.store(&seed, &pre);
+        Ok((params, pre))
+    }
+}
+
+/// One cache file, for one generator vector.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    path: PathBuf,
+    n: usize,
+}
+
+impl CacheEntry {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The `TOperator` seed and preprocessed commitments, if the entry exists and
+    /// has the expected length.
+    pub fn load<G: CurveGroup>(&self) -> Option<([u8; 32], PreprocessedCommitments<G>)> {
+        let (seed, h) = read_entry::<G>(&self.path, self.n)?;
+        let pre = PreprocessedCommitments {
+            pedersen_h: Pedersen::from_generators(h),
+        };
+        Some((seed, pre))
+    }
+
+    /// Write the entry; failures are logged rather than returned, since the cache
+    /// is only an optimisation.
+    pub fn store<G: CurveGroup>(&self, seed: &[u8; 32], pre: &PreprocessedCommitments<G>) {
+        if let Err(e) = write_entry::<G>(&self.path, seed, pre.h()) {
+            tracing::warn!(path = %self.path.display(), "failed to write EMSM cache entry: {e}");
+        }
+    }
+}
+
+/// Hex SHA-256 of the domain tag and the compressed generators. The same seed
+/// yields a different `TOperator` under implicit permutations, so those entries
+/// are keyed separately.
+fn cache_key<G: CurveGroup>(generators: &[G::Affine], permutations: PermutationMode) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_DOMAIN);
+    if permutations == PermutationMode::Implicit {
+        hasher.update(b"feistel");
+    }
+    generators
+        .serialize_compressed(HashWriter(&mut hasher))
+        .expect("writing to a hasher cannot fail");
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Entries are stored uncompressed so loading needs no square roots.
+fn read_entry<G: CurveGroup>(path: &Path, n: usize) -> Option<([u8; 32], Vec<G::Affine>)> {
+    let mut reader = BufReader::new(fs::File::open(path).ok()?);
+    let seed = <[u8; 32]>::deserialize_with_mode(&mut reader, Compress::No, Validate::No).ok()?;
+    let h =
+        Vec::<G::Affine>::deserialize_with_mode(&mut reader, Compress::No, Validate::No).ok()?;
+    (h.len() == DEFAULT_FOLD * n).then_some((seed, h))
+}
+
+/// Write to a temporary file and rename, so a crashed run never leaves a torn entry.
+fn write_entry<G: CurveGroup>(path: &Path, seed: &[u8; 32], h: &[G::Affine]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    let mut writer = BufWriter::new(fs::File::create(&tmp)?);
+    seed.serialize_uncompressed(&mut writer)
+        .and_then(|_| h.serialize_uncompressed(&mut writer))
+        .map_err(io::Error::other)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp, path)
+}
+
+/// Feeds serialized bytes straight into a hasher instead of buffering them.
+pub(crate) struct HashWriter<'a>(pub(crate) &'a mut Sha256);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emsm::cancel::CancelToken;
+    use ark_bn254::G1Projective as G1;
+    use crate::rng::test_rng;
+    use ark_std::UniformRand;
+
+    #[test]
+    fn test_cache_hit_rebuilds_identical_params() {
+        let mut rng = test_rng();
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..16).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let dir = std::env::temp_dir().join(format!("stealthsnark-cache-{}", u64::rand(&mut rng)));
+        let cache = PreprocessCache::new(&dir).unwrap();
+        let options = PreprocessOptions::default();
+
+        let (params, pre) = cache
+            .params_and_preprocess::<G1, _>(
+                generators.clone(),
+                PermutationMode::Stored,
+                "h",
+                &mut rng,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // A hit must not consult preprocessing at all
+        let cancelled = CancelToken::new();
+        cancelled.cancel();
+        let hit_options = PreprocessOptions {
+            progress: None,
+            cancel: Some(&cancelled),
+        };
+        let (cached_params, cached_pre) = cache
+            .params_and_preprocess::<G1, _>(
+                generators.clone(),
+                PermutationMode::Stored,
+                "h",
+                &mut rng,
+                &hit_options,
+            )
+            .unwrap();
+        assert_eq!(cached_params.t_operator.perm_p, params.t_operator.perm_p);
+        assert_eq!(cached_params.t_operator.perm_q, params.t_operator.perm_q);
+        assert_eq!(cached_pre.h(), pre.h());
+
+        // Implicit permutations miss the stored entry and get one of their own
+        let (implicit, _) = cache
+            .params_and_preprocess::<G1, _>(
+                generators,
+                PermutationMode::Implicit,
+                "h",
+                &mut rng,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(
+            implicit.t_operator.permutation_mode(),
+            PermutationMode::Implicit
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use ark_std::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// Cooperative cancellation for long-running operations.
+///
+/// Clones share the same flag, so one handle can be given to the operation and
+/// another kept to cancel it. A token can also carry a deadline, after which it
+/// reports itself cancelled (with `std` only). Operations poll the token between stages, so a cancel
+/// takes effect at the next stage boundary rather than instantly.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("operation cancelled")]
+pub struct Cancelled;
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that cancels itself `timeout` from now.
+    #[cfg(feature = "std")]
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// A token that cancels itself at `deadline`.
+    #[cfg(feature = "std")]
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            deadline: Some(deadline),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        #[cfg(feature = "std")]
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return true;
+        }
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return `Err(Cancelled)` if the token has been cancelled or its deadline passed.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A guard that cancels this token when dropped, e.g. when the future awaiting
+    /// work on another thread is dropped before the work finishes.
+    pub fn drop_guard(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+}
+
+/// Cancels its token on drop; see `CancelToken::drop_guard`.
+#[derive(Debug)]
+pub struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_shared_between_clones() {
+        let token = CancelToken::new();
+        let handle = token.clone();
+        assert!(token.check().is_ok());
+        handle.cancel();
+        assert!(token.check().is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_deadline() {
+        assert!(CancelToken::with_timeout(Duration::ZERO).is_cancelled());
+        assert!(!CancelToken::with_timeout(Duration::from_secs(60)).is_cancelled());
+    }
+
+    #[test]
+    fn test_drop_guard() {
+        let token = CancelToken::new();
+        let guard = token.drop_guard();
+        assert!(!token.is_cancelled());
+        drop(guard);
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ark_ec::CurveGroup;
+
+use super::emsm::PreprocessedCommitments;
+
+/// When EMSM preprocessing runs during setup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreprocessMode {
+    /// Preprocess every MSM before setup returns.
+    #[default]
+    Eager,
+    /// Preprocess an MSM the first time its commitments are needed (at decrypt).
+    Lazy,
+    /// Start preprocessing every MSM on its own thread and return immediately;
+    /// decrypt waits for any MSM that has not finished yet.
+    Background,
+}
+
+type Init<G> = Box<dyn FnOnce() -> PreprocessedCommitments<G> + Send>;
+
+/// Preprocessed commitments that may still be pending.
+///
+/// `get` returns the commitments, computing them on the calling thread or waiting
+/// for a background computation already in progress. Only one computation ever runs.
+pub struct DeferredPreprocessing<G: CurveGroup> {
+    inner: Arc<Inner<G>>,
+}
+
+struct Inner<G: CurveGroup> {
+    cell: OnceLock<PreprocessedCommitments<G>>,
+    init: Mutex<Option<Init<G>>>,
+}
+
+impl<G: CurveGroup> Inner<G> {
+    fn force(&self) -> &PreprocessedCommitments<G> {
+        self.cell.get_or_init(|| {
+            let init = self
+                .init
+                .lock()
+                .unwrap()
+                .take()
+                .expect("EMSM preprocessing panicked on an earlier attempt");
+            init()
+        })
+    }
+}
+
+impl<G: CurveGroup> DeferredPreprocessing<G> {
+    /// Already computed.
+    pub fn ready(preprocessed: PreprocessedCommitments<G>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cell: OnceLock::from(preprocessed),
+                init: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Computed by `init` on the first call to `get`.
+    pub fn lazy(init: impl FnOnce() -> PreprocessedCommitments<G> + Send + 'static) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cell: OnceLock::new(),
+                init: Mutex::new(Some(Box::new(init))),
+            }),
+        }
+    }
+
+    /// Computed by `init` on a new background thread, starting now.
+    pub fn background(init: impl FnOnce() -> PreprocessedCommitments<G> + Send + 'static) -> Self {
+        let deferred = Self::lazy(init);
+        let inner = deferred.inner.clone();
+        std::thread::spawn(move || {
+            inner.force();
+        });
+        deferred
+    }
+
+    /// The commitments, blocking until they are available.
+    pub fn get(&self) -> &PreprocessedCommitments<G> {
+        self.inner.force()
+    }
+
+    /// Whether `get` would return without blocking.
+    pub fn is_ready(&self) -> bool {
+        self.inner.cell.get().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emsm::emsm::EmsmPublicParams;
+    use ark_bn254::G1Projective as G1;
+    use crate::rng::test_rng;
+    use ark_std::UniformRand;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_deferred_preprocessing_runs_once() {
+        let mut rng = test_rng();
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..16).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let params = Arc::new(EmsmPublicParams::<G1>::new(generators, &mut rng));
+        let expected = params.preprocess();
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let init = |params: Arc<EmsmPublicParams<G1>>, runs: Arc<AtomicUsize>| {
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                params.preprocess()
+            }
+        };
+
+        let lazy = DeferredPreprocessing::lazy(init(params.clone(), runs.clone()));
+        assert!(!lazy.is_ready());
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        assert_eq!(lazy.get().h(), expected.h());
+        assert_eq!(lazy.get().h(), expected.h());
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let background = DeferredPreprocessing::background(init(params, runs.clone()));
+        assert_eq!(background.get().h(), expected.h());
+        assert!(background.is_ready());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,17 +1,52 @@
 use ark_ff::Field;
 use ark_std::rand::Rng;
+use ark_std::vec::Vec;
 
-use super::raa_code::TOperator;
+use super::raa_code::{EncryptScratch, TOperator};
 use super::sparse_vec::SparseVector;
 
+/// Distribution of the sparse noise vector e. Security arguments and parameter
+/// tables are stated per distribution; the bundled tables assume `Regular`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoiseDistribution {
+    /// Exactly one nonzero entry in each of t equal-size chunks.
+    #[default]
+    Regular,
+    /// Exactly t nonzero entries at a uniformly random support.
+    ExactWeight,
+    /// Each entry nonzero independently with probability t / N.
+    Bernoulli,
+}
+
+impl NoiseDistribution {
+    /// Sample a noise vector of length `size` with (expected) weight `t`.
+    pub fn sample<F: Field, R: Rng>(self, size: usize, t: usize, rng: &mut R) -> SparseVector<F> {
+        match self {
+            NoiseDistribution::Regular => SparseVector::error_vec(size, t, rng),
+            NoiseDistribution::ExactWeight => SparseVector::exact_weight_vec(size, t, rng),
+            NoiseDistribution::Bernoulli => SparseVector::bernoulli_vec(size, t, rng),
+        }
+    }
+}
+
 /// A Dual-LPN instance: noise vector e (sparse) and mask vector r = T * e (dense).
 /// Used to mask witness vectors: v = z + r, where the server sees v but not z.
-#[derive(Clone, Debug)]
+///
+/// An instance masks exactly one witness: two maskings z + r and z' + r reveal
+/// z - z'. It is deliberately not `Clone`, its fields are private, and
+/// `mask_witness` consumes it, handing back only the e that unmasking needs.
 pub struct DualLPNInstance<F: Field> {
-    /// Sparse noise vector e of dimension N = 4n
-    pub noise: SparseVector<F>,
+    /// Sparse noise vector e of dimension N = fold * n
+    noise: SparseVector<F>,
     /// Dense mask vector r = T * e of dimension n
-    pub lpn_vector: Vec<F>,
+    lpn_vector: Vec<F>,
+}
+
+/// e and r are secret, so they are not printed.
+impl<F: Field> core::fmt::Debug for DualLPNInstance<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("DualLPNInstance(..)")
+    }
 }
 
 impl<F: Field> DualLPNInstance<F> {
@@ -19,18 +54,57 @@ impl<F: Field> DualLPNInstance<F> {
     /// 1. Sample sparse e with t nonzero entries across N-dimensional space
     /// 2. Compute r = T * e (dense n-dimensional vector)
     pub fn sample<R: Rng>(t_operator: &TOperator, t: usize, rng: &mut R) -> Self {
-        let noise = SparseVector::error_vec(t_operator.big_n, t, rng);
-        let lpn_vector = t_operator.multiply_sparse(&noise.entries);
+        Self::sample_with(t_operator, t, NoiseDistribution::Regular, rng)
+    }
+
+    /// `sample` with e drawn from `distribution`.
+    pub fn sample_with<R: Rng>(
+        t_operator: &TOperator,
+        t: usize,
+        distribution: NoiseDistribution,
+        rng: &mut R,
+    ) -> Self {
+        let noise = distribution.sample(t_operator.big_n, t, rng);
+        Self::from_noise(t_operator, noise)
+    }
+
+    /// `sample_with`, computing r in the buffers of `scratch`.
+    pub fn sample_in<R: Rng>(
+        t_operator: &TOperator,
+        t: usize,
+        distribution: NoiseDistribution,
+        scratch: &mut EncryptScratch<F>,
+        rng: &mut R,
+    ) -> Self {
+        let noise = distribution.sample(t_operator.big_n, t, rng);
+        Self::from_noise_in(t_operator, noise, scratch)
+    }
+
+    /// Build the instance for an already-sampled noise vector e.
+    pub fn from_noise(t_operator: &TOperator, noise: SparseVector<F>) -> Self {
+        Self::from_noise_in(t_operator, noise, &mut EncryptScratch::default())
+    }
+
+    /// `from_noise`, computing r in the buffers of `scratch`.
+    pub fn from_noise_in(
+        t_operator: &TOperator,
+        noise: SparseVector<F>,
+        scratch: &mut EncryptScratch<F>,
+    ) -> Self {
+        let lpn_vector = t_operator.multiply_sparse_with(&noise.entries, scratch);
         Self { noise, lpn_vector }
     }
 
-    /// Mask a witness vector z: returns v = z + r
-    pub fn mask_witness(&self, z: &[F]) -> Vec<F> {
+    /// Mask a witness vector z: returns v = z + r, along with the noise e needed
+    /// to decrypt. Consumes the instance so r cannot mask a second witness, and
+    /// adds z into r's buffer instead of allocating another vector.
+    pub fn mask_witness(self, z: &[F]) -> (Vec<F>, SparseVector<F>) {
         assert_eq!(z.len(), self.lpn_vector.len(), "z must have same length as lpn_vector");
-        z.iter()
-            .zip(self.lpn_vector.iter())
-            .map(|(zi, ri)| *zi + *ri)
-            .collect()
+        let mut masked = self.lpn_vector;
+        for (vi, zi) in masked.iter_mut().zip(z) {
+            *vi += zi;
+        }
+        (masked, self.noise)
     }
 }
 
@@ -53,6 +127,38 @@ mod tests {
         assert_eq!(instance.lpn_vector.len(), n);
     }
 
+    #[test]
+    fn test_sample_with_distribution() {
+        let mut rng = test_rng();
+        let t_op = TOperator::rand(64, &mut rng);
+        for distribution in [NoiseDistribution::Regular, NoiseDistribution::ExactWeight] {
+            let instance = DualLPNInstance::<Fr>::sample_with(&t_op, 8, distribution, &mut rng);
+            assert_eq!(instance.noise.entries.len(), 8);
+            assert_eq!(
+                instance.lpn_vector,
+                t_op.multiply_sparse(&instance.noise.entries)
+            );
+        }
+        let bernoulli =
+            DualLPNInstance::<Fr>::sample_with(&t_op, 8, NoiseDistribution::Bernoulli, &mut rng);
+        assert_eq!(bernoulli.lpn_vector.len(), 64);
+
+        let mut scratch = EncryptScratch::default();
+        for _ in 0..2 {
+            let instance = DualLPNInstance::<Fr>::sample_in(
+                &t_op,
+                8,
+                NoiseDistribution::Regular,
+                &mut scratch,
+                &mut rng,
+            );
+            assert_eq!(
+                instance.lpn_vector,
+                t_op.multiply_sparse(&instance.noise.entries)
+            );
+        }
+    }
+
     #[test]
     fn test_mask_witness() {
         let mut rng = test_rng();
@@ -61,12 +167,15 @@ mod tests {
         let instance = DualLPNInstance::<Fr>::sample(&t_op, 4, &mut rng);
 
         let z: Vec<Fr> = (0..n).map(|i| Fr::from(i as u64)).collect();
-        let v = instance.mask_witness(&z);
+        let r = instance.lpn_vector.clone();
+        let noise_entries = instance.noise.entries.clone();
+        let (v, noise) = instance.mask_witness(&z);
         assert_eq!(v.len(), n);
+        assert_eq!(noise.entries, noise_entries);
 
         // v - r should equal z
         for i in 0..n {
-            assert_eq!(v[i] - instance.lpn_vector[i], z[i]);
+            assert_eq!(v[i] - r[i], z[i]);
         }
     }
 }
@@ -1,10 +1,33 @@
 use ark_ec::CurveGroup;
-use ark_std::rand::Rng;
+use ark_ff::Zero;
+use ark_std::rand::{CryptoRng, Rng, SeedableRng};
+use ark_std::sync::Arc;
+use ark_std::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rand_chacha::ChaCha20Rng;
 
-use super::dual_lpn::DualLPNInstance;
-use super::params::get_lpn_params;
-use super::pedersen::Pedersen;
-use super::raa_code::TOperator;
+use super::cancel::{CancelToken, Cancelled};
+use super::dual_lpn::{DualLPNInstance, NoiseDistribution};
+use super::sparse_vec::{Index, SparseVector};
+use super::parallel::ParallelConfig;
+use super::params::{get_lpn_params_for, try_get_lpn_params_at, LpnParamsError, SecurityLevel};
+use super::pedersen::{Pedersen, PedersenError};
+use super::progress::ProgressSink;
+use super::raa_code::{EncryptScratch, PermutationMode, TOperator, DEFAULT_FOLD};
+#[cfg(feature = "std")]
+use super::security::{estimate_security, SecurityEstimate};
+
+/// Number of progress updates emitted during the affine conversion in preprocessing.
+const PROGRESS_STEPS: usize = 10;
+
+/// Optional hooks for long-running preprocessing.
+#[derive(Clone, Copy, Default)]
+pub struct PreprocessOptions<'a> {
+    /// Receives (msm name, fraction complete) updates.
+    pub progress: Option<&'a dyn ProgressSink>,
+    /// Checked between preprocessing stages; aborts with `Cancelled` once set.
+    pub cancel: Option<&'a CancelToken>,
+}
 
 /// Public parameters for EMSM, created from generators (proving key elements).
 #[derive(Clone, Debug)]
@@ -15,6 +38,14 @@ pub struct EmsmPublicParams<G: CurveGroup> {
     pub generators: Vec<G::Affine>,
     /// LPN sparsity parameter
     pub t: usize,
+    /// Security level `t` was chosen for
+    pub security: SecurityLevel,
+    /// Distribution the masking noise is drawn from
+    pub noise: NoiseDistribution,
+    /// Thread pool and thresholds for preprocessing, masking and the server MSM
+    pub parallel: ParallelConfig,
+    /// Witnesses masked with `t_operator` so far, shared by clones
+    queries: Arc<AtomicUsize>,
 }
 
 /// Preprocessed commitments h = G^T * g.
@@ -22,37 +53,222 @@ pub struct EmsmPublicParams<G: CurveGroup> {
 /// Used during decryption to remove the noise contribution.
 #[derive(Clone, Debug)]
 pub struct PreprocessedCommitments<G: CurveGroup> {
-    /// h[i] = sum over j of G^T[i][j] * generators[j]
-    /// Stored as projective points for efficient sparse MSM later.
-    pub h: Vec<G>,
-    /// Pedersen instance over preprocessed generators (for sparse MSM during decryption)
+    /// Pedersen instance over preprocessed generators (for sparse MSM during decryption).
+    /// Its generators are h[i] = sum over j of G^T[i][j] * generators[j], in affine form.
     pub pedersen_h: Pedersen<G>,
 }
 
+impl<G: CurveGroup> PreprocessedCommitments<G> {
+    /// The preprocessed generators h = G^T * g (length N).
+    pub fn h(&self) -> &[G::Affine] {
+        &self.pedersen_h.generators
+    }
+}
+
 impl<G: CurveGroup> EmsmPublicParams<G> {
     /// Create EMSM public parameters from generators.
     /// `generators` are the proving key elements (e.g., h_query, l_query points).
-    pub fn new<R: Rng>(generators: Vec<G::Affine>, rng: &mut R) -> Self {
-        let n = generators.len();
-        let params = get_lpn_params(n);
-        let t_operator = TOperator::rand(n, rng);
+    pub fn new<R: Rng + CryptoRng>(generators: Vec<G::Affine>, rng: &mut R) -> Self {
+        Self::new_with_permutations(generators, PermutationMode::Stored, rng)
+    }
+
+    /// `new` with the `TOperator` permutations represented as `permutations` says.
+    /// `PermutationMode::Implicit` drops the four N-entry index tables (over a
+    /// gigabyte at N = 2^24) for Feistel permutations evaluated on the fly, at the
+    /// cost of slower masking and preprocessing.
+    pub fn new_with_permutations<R: Rng + CryptoRng>(
+        generators: Vec<G::Affine>,
+        permutations: PermutationMode,
+        rng: &mut R,
+    ) -> Self {
+        let t = get_lpn_params_for(generators.len(), SecurityLevel::default()).t;
+        Self::sample(generators, DEFAULT_FOLD, t, permutations, rng)
+    }
+
+    /// `new_with_permutations` over the code of rate 1/`fold` (N = fold * n)
+    /// instead of 1/4, with the noise weight of the built-in rows. Rates other
+    /// than 1/4 have none: choose `t` with `new_with_fold_and_t`, e.g. from
+    /// `LpnParamSet::params`.
+    pub fn new_with_fold<R: Rng + CryptoRng>(
+        generators: Vec<G::Affine>,
+        fold: usize,
+        permutations: PermutationMode,
+        rng: &mut R,
+    ) -> Result<Self, LpnParamsError> {
+        let t = try_get_lpn_params_at(generators.len(), SecurityLevel::default(), fold)?.t;
+        Ok(Self::sample(generators, fold, t, permutations, rng))
+    }
+
+    /// `new_with_fold` with the noise weight fixed at `t`, checked against N
+    /// before the code is sampled. `security` is left at the default and does
+    /// not describe `t`; check it with `estimated_security`, which accounts for
+    /// the rate.
+    pub fn new_with_fold_and_t<R: Rng + CryptoRng>(
+        generators: Vec<G::Affine>,
+        fold: usize,
+        t: usize,
+        permutations: PermutationMode,
+        rng: &mut R,
+    ) -> Result<Self, LpnParamsError> {
+        if fold == 0 {
+            return Err(LpnParamsError::ZeroFold);
+        }
+        let big_n = (fold * generators.len()).max(1);
+        if t == 0 || t > big_n {
+            return Err(LpnParamsError::NoiseWeightOutOfRange { t, big_n });
+        }
+        Ok(Self::sample(generators, fold, t, permutations, rng))
+    }
+
+    /// Parameters over a freshly sampled code of rate 1/`fold` with noise
+    /// weight `t`, capped at N.
+    fn sample<R: Rng + CryptoRng>(
+        generators: Vec<G::Affine>,
+        fold: usize,
+        t: usize,
+        permutations: PermutationMode,
+        rng: &mut R,
+    ) -> Self {
+        let t_operator = TOperator::rand_with_fold(generators.len(), fold, permutations, rng);
         Self {
+            t: t.min(t_operator.big_n.max(1)),
             t_operator,
             generators,
-            t: params.t,
+            security: SecurityLevel::default(),
+            noise: NoiseDistribution::default(),
+            parallel: ParallelConfig::default(),
+            queries: Arc::default(),
         }
     }
 
+    /// `new` with the noise weight fixed at `t` instead of read from the parameter
+    /// table, trading security margin for bandwidth and decrypt time (or the other
+    /// way round). Returns the estimated security of the chosen `t` alongside, so
+    /// the tradeoff is explicit; `security` is left at the default and no longer
+    /// describes `t`. Fails, before sampling the code, if `t` is zero or exceeds N.
+    #[cfg(feature = "std")]
+    pub fn new_with_t<R: Rng + CryptoRng>(
+        generators: Vec<G::Affine>,
+        t: usize,
+        rng: &mut R,
+    ) -> Result<(Self, SecurityEstimate), LpnParamsError> {
+        let permutations = PermutationMode::Stored;
+        let params = Self::new_with_fold_and_t(generators, DEFAULT_FOLD, t, permutations, rng)?;
+        let estimate = params.estimated_security(1);
+        Ok((params, estimate))
+    }
+
+    /// Estimated security of these parameters against `queries` masks.
+    #[cfg(feature = "std")]
+    pub fn estimated_security(&self, queries: u64) -> SecurityEstimate {
+        let n = self.t_operator.n;
+        let rate = n as f64 / self.t_operator.big_n.max(1) as f64;
+        estimate_security(n, rate, self.t, queries)
+    }
+
+    /// Number of vectors `encrypt` has masked with these parameters (or clones of
+    /// them). Each is an LPN sample under the same code, and security estimates
+    /// degrade with their number.
+    pub fn queries(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed) as u64
+    }
+
+    /// Choose the noise weight `t` for `security` instead of the default 100 bits,
+    /// from the table for the code's rate.
+    pub fn with_security(mut self, security: SecurityLevel) -> Result<Self, LpnParamsError> {
+        let (n, fold) = (self.generators.len(), self.t_operator.fold);
+        self.t = try_get_lpn_params_at(n, security, fold)?.t;
+        self.security = security;
+        Ok(self)
+    }
+
+    /// `new_with_permutations` with the `TOperator` derived deterministically from
+    /// `seed`, so the same parameters can be rebuilt later (see
+    /// `cache::PreprocessCache`).
+    pub fn from_seed(
+        generators: Vec<G::Affine>,
+        seed: [u8; 32],
+        permutations: PermutationMode,
+    ) -> Self {
+        let rng = &mut ChaCha20Rng::from_seed(seed);
+        Self::new_with_permutations(generators, permutations, rng)
+    }
+
+    /// `from_seed` over the code of rate 1/`fold` with noise weight `t`, as
+    /// `new_with_fold_and_t`.
+    pub fn from_seed_with_fold(
+        generators: Vec<G::Affine>,
+        seed: [u8; 32],
+        fold: usize,
+        t: usize,
+        permutations: PermutationMode,
+    ) -> Result<Self, LpnParamsError> {
+        let rng = &mut ChaCha20Rng::from_seed(seed);
+        Self::new_with_fold_and_t(generators, fold, t, permutations, rng)
+    }
+
+    /// Draw masking noise from `noise` instead of the regular distribution.
+    pub fn with_noise(mut self, noise: NoiseDistribution) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Run this instance's operations under `parallel` instead of the global pool.
+    pub fn with_parallel(mut self, parallel: ParallelConfig) -> Self {
+        self.set_parallel(parallel);
+        self
+    }
+
+    /// In-place form of `with_parallel`.
+    pub fn set_parallel(&mut self, parallel: ParallelConfig) {
+        self.t_operator.thresholds = parallel.thresholds;
+        self.parallel = parallel;
+    }
+
     /// Preprocess: compute h = G^T * g (expand generators through transpose of RAA code).
-    /// h has dimension N = 4n. Used by client to remove noise during decryption.
+    /// h has dimension N = fold * n. Used by client to remove noise during decryption.
     pub fn preprocess(&self) -> PreprocessedCommitments<G> {
-        let h: Vec<G> = self.t_operator.multiply_transpose_group::<G>(&self.generators);
+        self.preprocess_with("", &PreprocessOptions::default())
+            .expect("preprocessing without a cancel token cannot be cancelled")
+    }
+
+    /// `preprocess` for the MSM named `msm`, reporting progress and honouring
+    /// cancellation as configured in `options`. The transpose and the affine
+    /// conversion each account for half of the reported progress.
+    pub fn preprocess_with(
+        &self,
+        msm: &str,
+        options: &PreprocessOptions,
+    ) -> Result<PreprocessedCommitments<G>, Cancelled> {
+        let report = |fraction: f64| -> Result<(), Cancelled> {
+            if let Some(cancel) = options.cancel {
+                cancel.check()?;
+            }
+            if let Some(sink) = options.progress {
+                sink.on_progress(msm, fraction);
+            }
+            Ok(())
+        };
+        report(0.0)?;
+
+        let h: Vec<G> = self.parallel.install(|| {
+            self.t_operator
+                .multiply_transpose_group::<G>(&self.generators)
+        });
+        report(0.5)?;
 
         // Convert to affine for Pedersen
-        let h_affine: Vec<G::Affine> = h.iter().map(|p| p.into_affine()).collect();
+        let chunk_size = h.len().div_ceil(PROGRESS_STEPS).max(1);
+        let num_chunks = h.len().div_ceil(chunk_size);
+        let mut h_affine: Vec<G::Affine> = Vec::with_capacity(h.len());
+        for (i, chunk) in h.chunks(chunk_size).enumerate() {
+            h_affine.extend(chunk.iter().map(|p| p.into_affine()));
+            report(0.5 + 0.5 * (i + 1) as f64 / num_chunks as f64)?;
+        }
+        drop(h);
         let pedersen_h = Pedersen::from_generators(h_affine);
 
-        PreprocessedCommitments { h, pedersen_h }
+        Ok(PreprocessedCommitments { pedersen_h })
     }
 
     /// Server-side computation: MSM(masked_scalars, generators).
@@ -61,40 +277,167 @@ impl<G: CurveGroup> EmsmPublicParams<G> {
         &self,
         masked_scalars: &[G::ScalarField],
     ) -> Result<G, crate::emsm::pedersen::PedersenError> {
-        let ped = Pedersen::<G>::from_generators(self.generators.clone());
-        ped.commit(masked_scalars)
+        self.parallel
+            .msm(&self.generators, masked_scalars, &CancelToken::default())
     }
 }
 
-/// Encrypt (mask) a witness vector and return the masked vector + decryption material.
-pub fn encrypt<G: CurveGroup, R: Rng>(
+/// A masked witness and the noise that unmasks it, as `encrypt` returns them.
+pub type Masked<F> = (Vec<F>, SparseVector<F>);
+
+/// Encrypt (mask) a witness vector and return the masked vector + decryption material
+/// (the sparse noise e).
+pub fn encrypt<G: CurveGroup, R: Rng + CryptoRng>(
+    params: &EmsmPublicParams<G>,
+    witness: &[G::ScalarField],
+    rng: &mut R,
+) -> (Vec<G::ScalarField>, SparseVector<G::ScalarField>) {
+    encrypt_in(params, witness, &mut EncryptScratch::default(), rng)
+}
+
+/// `encrypt`, masking in the buffers of `scratch`. Pass one workspace to every
+/// call when masking many witnesses, over these or other parameters.
+pub fn encrypt_in<G: CurveGroup, R: Rng + CryptoRng>(
+    params: &EmsmPublicParams<G>,
+    witness: &[G::ScalarField],
+    scratch: &mut EncryptScratch<G::ScalarField>,
+    rng: &mut R,
+) -> (Vec<G::ScalarField>, SparseVector<G::ScalarField>) {
+    params.queries.fetch_add(1, Ordering::Relaxed);
+    let noise = params.noise.sample(params.t_operator.big_n, params.t, rng);
+    params.parallel.install(|| {
+        DualLPNInstance::from_noise_in(&params.t_operator, noise, scratch).mask_witness(witness)
+    })
+}
+
+/// `encrypt` with the noise e given instead of sampled, e.g. drawn from a
+/// committed seed (`seeded::encrypt_seeded`). Like a sampled one, each noise
+/// vector may mask only one witness.
+pub fn encrypt_with_noise<G: CurveGroup>(
+    params: &EmsmPublicParams<G>,
+    witness: &[G::ScalarField],
+    noise: SparseVector<G::ScalarField>,
+) -> Masked<G::ScalarField> {
+    params.queries.fetch_add(1, Ordering::Relaxed);
+    params.parallel.install(|| {
+        DualLPNInstance::from_noise(&params.t_operator, noise).mask_witness(witness)
+    })
+}
+
+/// `encrypt` of k witnesses under independent noise, for provers masking many
+/// witnesses against the same generators: the k masks are computed together
+/// (`TOperator::multiply_sparse_batch`), traversing each permutation once
+/// instead of k times. Returns each masked witness with its noise, in order.
+///
+/// # Panics
+/// If a witness is not of length n.
+pub fn encrypt_batch<G: CurveGroup, R: Rng + CryptoRng, W: AsRef<[G::ScalarField]>>(
+    params: &EmsmPublicParams<G>,
+    witnesses: &[W],
+    rng: &mut R,
+) -> Vec<Masked<G::ScalarField>> {
+    encrypt_batch_in(params, witnesses, &mut EncryptScratch::default(), rng)
+}
+
+/// `encrypt_batch`, masking in the buffers of `scratch`, which grow to k
+/// vectors of N.
+pub fn encrypt_batch_in<G: CurveGroup, R: Rng + CryptoRng, W: AsRef<[G::ScalarField]>>(
+    params: &EmsmPublicParams<G>,
+    witnesses: &[W],
+    scratch: &mut EncryptScratch<G::ScalarField>,
+    rng: &mut R,
+) -> Vec<Masked<G::ScalarField>> {
+    for witness in witnesses {
+        assert_eq!(
+            witness.as_ref().len(),
+            params.t_operator.n,
+            "witness must have length n"
+        );
+    }
+    params.queries.fetch_add(witnesses.len(), Ordering::Relaxed);
+    let noises: Vec<SparseVector<G::ScalarField>> = witnesses
+        .iter()
+        .map(|_| params.noise.sample(params.t_operator.big_n, params.t, rng))
+        .collect();
+    let entries: Vec<&[(Index, G::ScalarField)]> =
+        noises.iter().map(|e| e.entries.as_slice()).collect();
+    let masks = params
+        .parallel
+        .install(|| params.t_operator.multiply_sparse_batch(&entries, scratch));
+    masks
+        .into_iter()
+        .zip(witnesses)
+        .zip(noises)
+        .map(|((mut masked, witness), noise)| {
+            for (vi, zi) in masked.iter_mut().zip(witness.as_ref()) {
+                *vi += zi;
+            }
+            (masked, noise)
+        })
+        .collect()
+}
+
+/// `encrypt_in`, handing the masked vector to `emit` in consecutive chunks of at
+/// most `chunk_len` scalars instead of returning it. Each chunk of z + r is
+/// computed as it is emitted (`TOperator::multiply_sparse_lazy`), so neither r
+/// nor the masked vector is held whole: masking takes one vector of N in
+/// `scratch` plus a chunk, where `encrypt_in` takes two vectors of N and the
+/// masked vector.
+pub fn encrypt_chunked<G: CurveGroup, R: Rng + CryptoRng, E>(
     params: &EmsmPublicParams<G>,
     witness: &[G::ScalarField],
+    chunk_len: usize,
+    scratch: &mut EncryptScratch<G::ScalarField>,
     rng: &mut R,
-) -> (Vec<G::ScalarField>, DualLPNInstance<G::ScalarField>) {
-    let lpn = DualLPNInstance::sample(&params.t_operator, params.t, rng);
-    let masked = lpn.mask_witness(witness);
-    (masked, lpn)
+    mut emit: impl FnMut(&[G::ScalarField]) -> Result<(), E>,
+) -> Result<SparseVector<G::ScalarField>, E> {
+    assert_eq!(
+        witness.len(),
+        params.t_operator.n,
+        "witness must have length n"
+    );
+    assert!(chunk_len > 0, "chunks must not be empty");
+    params.queries.fetch_add(1, Ordering::Relaxed);
+    let noise = params.noise.sample(params.t_operator.big_n, params.t, rng);
+    let product = params.parallel.install(|| {
+        params
+            .t_operator
+            .multiply_sparse_lazy(&noise.entries, scratch)
+    });
+    let mut chunk = Vec::with_capacity(chunk_len.min(witness.len()));
+    for (k, z) in witness.chunks(chunk_len).enumerate() {
+        chunk.resize(z.len(), G::ScalarField::zero());
+        params
+            .parallel
+            .install(|| product.chunk_into(k * chunk_len, &mut chunk));
+        for (vi, zi) in chunk.iter_mut().zip(z) {
+            *vi += zi;
+        }
+        emit(&chunk)?;
+    }
+    Ok(noise)
 }
 
 /// Decrypt: remove noise contribution from server's MSM result.
 /// result = server_msm - <e, h>
 /// where e is the sparse noise and h = G^T * g (preprocessed commitments).
+/// Fails if `noise` does not fit h, i.e. was not sampled by `encrypt` with the
+/// parameters `preprocessed` belongs to.
 pub fn decrypt<G: CurveGroup>(
     server_result: G,
-    lpn: &DualLPNInstance<G::ScalarField>,
+    noise: &SparseVector<G::ScalarField>,
     preprocessed: &PreprocessedCommitments<G>,
-) -> G {
+) -> Result<G, PedersenError> {
     // Compute <e, h> = sparse MSM of noise against preprocessed generators
-    let noise_contribution = preprocessed.pedersen_h.commit_sparse(&lpn.noise);
-    server_result - noise_contribution
+    let noise_contribution = preprocessed.pedersen_h.commit_sparse(noise)?;
+    Ok(server_result - noise_contribution)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use ark_bn254::{Fr, G1Projective as G1};
-    use ark_std::test_rng;
+    use crate::rng::test_rng;
     use ark_std::UniformRand;
 
     #[test]
@@ -126,11 +469,44 @@ mod tests {
         let server_result = params.server_computation(&masked).unwrap();
 
         // Decrypt
-        let actual = decrypt(server_result, &lpn, &preprocessed);
+        let actual = decrypt(server_result, &lpn, &preprocessed).unwrap();
 
         assert_eq!(actual, expected, "EMSM roundtrip failed!");
     }
 
+    #[test]
+    fn test_encrypt_chunked_roundtrip() {
+        let mut rng = test_rng();
+        let n = 100;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+        let params = EmsmPublicParams::<G1>::new(generators.clone(), &mut rng);
+        let expected = Pedersen::<G1>::from_generators(generators)
+            .commit(&witness)
+            .unwrap();
+
+        let mut scratch = EncryptScratch::default();
+        let mut masked = Vec::new();
+        let mut chunk_lens = Vec::new();
+        let lpn = encrypt_chunked(&params, &witness, 32, &mut scratch, &mut rng, |chunk| {
+            chunk_lens.push(chunk.len());
+            masked.extend_from_slice(chunk);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(chunk_lens, [32, 32, 32, 4]);
+        let server_result = params.server_computation(&masked).unwrap();
+        assert_eq!(
+            decrypt(server_result, &lpn, &params.preprocess()).unwrap(),
+            expected
+        );
+
+        // An error from `emit` stops masking
+        let stopped = encrypt_chunked(&params, &witness, 32, &mut scratch, &mut rng, |_| Err(()));
+        assert_eq!(stopped.unwrap_err(), ());
+    }
+
     #[test]
     fn test_emsm_different_witnesses() {
         let mut rng = test_rng();
@@ -150,9 +526,232 @@ mod tests {
 
             let (masked, lpn) = encrypt(&params, &witness, &mut rng);
             let server_result = params.server_computation(&masked).unwrap();
-            let actual = decrypt(server_result, &lpn, &preprocessed);
+            let actual = decrypt(server_result, &lpn, &preprocessed).unwrap();
 
             assert_eq!(actual, expected);
         }
     }
+
+    #[test]
+    fn test_encrypt_batch_roundtrip() {
+        let mut rng = test_rng();
+        let n = 48;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let params = EmsmPublicParams::<G1>::new(generators.clone(), &mut rng);
+        let preprocessed = params.preprocess();
+        let ped = Pedersen::<G1>::from_generators(generators);
+
+        let witnesses: Vec<Vec<Fr>> = (0..3)
+            .map(|_| (0..n).map(|_| Fr::rand(&mut rng)).collect())
+            .collect();
+        let masked = encrypt_batch(&params, &witnesses, &mut rng);
+        assert_eq!(params.queries(), 3);
+        for (witness, (masked, lpn)) in witnesses.iter().zip(&masked) {
+            let server_result = params.server_computation(masked).unwrap();
+            let actual = decrypt(server_result, lpn, &preprocessed).unwrap();
+            assert_eq!(actual, ped.commit(witness).unwrap());
+        }
+        // Independent noise: equal witnesses get different masks
+        let same = encrypt_batch(&params, &[&witnesses[0], &witnesses[0]], &mut rng);
+        assert_ne!(same[0].0, same[1].0);
+    }
+
+    #[test]
+    fn test_emsm_roundtrip_at_each_security_level() {
+        let mut rng = test_rng();
+        let n = 2048;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+        let expected = Pedersen::<G1>::from_generators(generators.clone())
+            .commit(&witness)
+            .unwrap();
+
+        let base = EmsmPublicParams::<G1>::new(generators, &mut rng);
+        let preprocessed = base.preprocess();
+        for (security, t) in [
+            (SecurityLevel::Bits80, 27),
+            (SecurityLevel::Bits100, 33),
+            (SecurityLevel::Bits128, 43),
+        ] {
+            let params = base.clone().with_security(security).unwrap();
+            assert_eq!(params.t, t);
+            let (masked, lpn) = encrypt(&params, &witness, &mut rng);
+            let server_result = params.server_computation(&masked).unwrap();
+            assert_eq!(
+                decrypt(server_result, &lpn, &preprocessed).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_decrypt_rejects_foreign_noise() {
+        let mut rng = test_rng();
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..64).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..64).map(|_| Fr::rand(&mut rng)).collect();
+        let small = EmsmPublicParams::<G1>::new(generators[..8].to_vec(), &mut rng);
+        let params = EmsmPublicParams::<G1>::new(generators, &mut rng);
+
+        // Noise over N = 256 entries does not fit the 32 of the smaller h
+        let (masked, lpn) = encrypt(&params, &witness, &mut rng);
+        let server_result = params.server_computation(&masked).unwrap();
+        assert!(matches!(
+            decrypt(server_result, &lpn, &small.preprocess()),
+            Err(PedersenError::LengthMismatch {
+                scalars: 256,
+                generators: 32
+            })
+        ));
+    }
+
+    #[test]
+    fn test_emsm_roundtrip_with_implicit_permutations() {
+        let mut rng = test_rng();
+        let n = 300;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+        let expected = Pedersen::<G1>::from_generators(generators.clone())
+            .commit(&witness)
+            .unwrap();
+
+        let params = EmsmPublicParams::<G1>::new_with_permutations(
+            generators,
+            PermutationMode::Implicit,
+            &mut rng,
+        );
+        let preprocessed = params.preprocess();
+        let (masked, lpn) = encrypt(&params, &witness, &mut rng);
+        let server_result = params.server_computation(&masked).unwrap();
+        assert_eq!(
+            decrypt(server_result, &lpn, &preprocessed).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_emsm_roundtrip_with_fold() {
+        let mut rng = test_rng();
+        let n = 50;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+        let expected = Pedersen::<G1>::from_generators(generators.clone())
+            .commit(&witness)
+            .unwrap();
+
+        for fold in [2, 8] {
+            let params = EmsmPublicParams::<G1>::new_with_fold_and_t(
+                generators.clone(),
+                fold,
+                20,
+                PermutationMode::Stored,
+                &mut rng,
+            )
+            .unwrap();
+            let preprocessed = params.preprocess();
+            assert_eq!(preprocessed.h().len(), fold * n);
+            let (masked, lpn) = encrypt(&params, &witness, &mut rng);
+            let server_result = params.server_computation(&masked).unwrap();
+            assert_eq!(
+                decrypt(server_result, &lpn, &preprocessed).unwrap(),
+                expected
+            );
+        }
+
+        // Only rate 1/4 has built-in rows; other rates need t chosen in range
+        let stored = PermutationMode::Stored;
+        let err = EmsmPublicParams::<G1>::new_with_fold(generators.clone(), 2, stored, &mut rng)
+            .unwrap_err();
+        let security = SecurityLevel::Bits100;
+        assert_eq!(err, LpnParamsError::NoTable { security, fold: 2 });
+        let err =
+            EmsmPublicParams::<G1>::new_with_fold_and_t(generators, 2, 2 * n + 1, stored, &mut rng)
+                .unwrap_err();
+        let (t, big_n) = (2 * n + 1, 2 * n);
+        assert_eq!(err, LpnParamsError::NoiseWeightOutOfRange { t, big_n });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_new_with_t_reports_margin() {
+        let mut rng = test_rng();
+        let n = 1 << 12;
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+
+        let (light, light_est) =
+            EmsmPublicParams::<G1>::new_with_t(generators.clone(), 20, &mut rng).unwrap();
+        let (heavy, heavy_est) =
+            EmsmPublicParams::<G1>::new_with_t(generators.clone(), 80, &mut rng).unwrap();
+        assert_eq!((light.t, heavy.t), (20, 80));
+        assert!(light_est.bits() < heavy_est.bits());
+        assert_eq!(heavy_est, heavy.estimated_security(1));
+
+        // Out of range, refused before any randomness is drawn
+        let before = rng.clone();
+        for t in [0, 4 * n + 1] {
+            let result = EmsmPublicParams::<G1>::new_with_t(generators.clone(), t, &mut rng);
+            let expected = LpnParamsError::NoiseWeightOutOfRange { t, big_n: 4 * n };
+            assert_eq!(result.unwrap_err(), expected);
+        }
+        assert_eq!(rng, before);
+
+        let expected = Pedersen::<G1>::from_generators(generators)
+            .commit(&witness)
+            .unwrap();
+        let (masked, lpn) = encrypt(&light, &witness, &mut rng);
+        assert_eq!(lpn.entries.len(), 20);
+        let server_result = light.server_computation(&masked).unwrap();
+        assert_eq!(
+            decrypt(server_result, &lpn, &light.preprocess()).unwrap(),
+            expected
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_preprocess_reports_progress() {
+        let mut rng = test_rng();
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..32).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let params = EmsmPublicParams::<G1>::new(generators, &mut rng);
+
+        let updates = std::sync::Mutex::new(Vec::new());
+        let sink = |msm: &str, fraction: f64| {
+            assert_eq!(msm, "h");
+            updates.lock().unwrap().push(fraction);
+        };
+        let options = PreprocessOptions {
+            progress: Some(&sink),
+            cancel: None,
+        };
+        let preprocessed = params.preprocess_with("h", &options).unwrap();
+        assert_eq!(preprocessed.h(), params.preprocess().h());
+
+        let updates = updates.into_inner().unwrap();
+        assert_eq!(updates.first(), Some(&0.0));
+        assert_eq!(updates.last(), Some(&1.0));
+        assert!(updates.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_preprocess_cancelled() {
+        let mut rng = test_rng();
+        let generators: Vec<<G1 as CurveGroup>::Affine> =
+            (0..32).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let params = EmsmPublicParams::<G1>::new(generators, &mut rng);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let options = PreprocessOptions {
+            progress: None,
+            cancel: Some(&cancel),
+        };
+        assert!(params.preprocess_with("h", &options).is_err());
+    }
 }
@@ -1,24 +1,35 @@
 use ark_ec::CurveGroup;
 use ark_ff::PrimeField;
-use ark_std::rand::Rng;
+use ark_std::rand::{CryptoRng, Rng};
 use ark_std::UniformRand;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use super::dual_lpn::DualLPNInstance;
 use super::emsm::{decrypt, encrypt, EmsmPublicParams, PreprocessedCommitments};
+use super::pedersen::PedersenError;
+use super::sparse_vec::SparseVector;
+use crate::serialization::ark_serde_vec;
 
 #[derive(Debug, Error)]
 pub enum MaliciousError {
     #[error("server cheated: consistency check failed")]
     ConsistencyCheckFailed,
+    #[error("expected {expected} server responses, got {got}")]
+    ResponseCount { expected: usize, got: usize },
+    /// The decryption state does not belong to the parameters.
+    #[error(transparent)]
+    Msm(#[from] PedersenError),
 }
 
 /// Encrypted data for the malicious-secure variant.
 /// Contains two masked vectors: one for the actual computation and one for the check.
+#[derive(Serialize, Deserialize)]
 pub struct MaliciousEncrypted<F: PrimeField> {
     /// v = z + r (masked witness)
+    #[serde(with = "ark_serde_vec")]
     pub masked: Vec<F>,
     /// v_ck = c * z + r' (check vector)
+    #[serde(with = "ark_serde_vec")]
     pub masked_check: Vec<F>,
 }
 
@@ -26,15 +37,15 @@ pub struct MaliciousEncrypted<F: PrimeField> {
 pub struct MaliciousDecryptState<F: PrimeField> {
     /// Random challenge scalar
     pub challenge: F,
-    /// LPN instance for the main query
-    pub lpn: DualLPNInstance<F>,
-    /// LPN instance for the check query
-    pub lpn_check: DualLPNInstance<F>,
+    /// LPN noise for the main query
+    pub lpn: SparseVector<F>,
+    /// LPN noise for the check query
+    pub lpn_check: SparseVector<F>,
 }
 
 /// Encrypt for malicious-secure EMSM.
 /// Sends two queries: v = z + r and v_ck = c*z + r' with independent LPN noise.
-pub fn malicious_encrypt<G: CurveGroup, R: Rng>(
+pub fn malicious_encrypt<G: CurveGroup, R: Rng + CryptoRng>(
     params: &EmsmPublicParams<G>,
     witness: &[G::ScalarField],
     rng: &mut R,
@@ -70,7 +81,7 @@ pub fn malicious_encrypt<G: CurveGroup, R: Rng>(
 pub fn malicious_server_evaluate<G: CurveGroup>(
     params: &EmsmPublicParams<G>,
     encrypted: &MaliciousEncrypted<G::ScalarField>,
-) -> Result<(G, G), crate::emsm::pedersen::PedersenError> {
+) -> Result<(G, G), PedersenError> {
     let em = params.server_computation(&encrypted.masked)?;
     let em_ck = params.server_computation(&encrypted.masked_check)?;
     Ok((em, em_ck))
@@ -84,8 +95,8 @@ pub fn malicious_decrypt<G: CurveGroup>(
     state: &MaliciousDecryptState<G::ScalarField>,
     preprocessed: &PreprocessedCommitments<G>,
 ) -> Result<G, MaliciousError> {
-    let dm = decrypt(server_result, &state.lpn, preprocessed);
-    let dm_ck = decrypt(server_result_check, &state.lpn_check, preprocessed);
+    let dm = decrypt(server_result, &state.lpn, preprocessed)?;
+    let dm_ck = decrypt(server_result_check, &state.lpn_check, preprocessed)?;
 
     // Check: dm_ck == c * dm
     let expected_ck = dm * state.challenge;
@@ -100,7 +111,7 @@ pub fn malicious_decrypt<G: CurveGroup>(
 mod tests {
     use super::*;
     use ark_bn254::{Fr, G1Projective as G1};
-    use ark_std::test_rng;
+    use crate::rng::test_rng;
 
     #[test]
     fn test_malicious_honest_server() {
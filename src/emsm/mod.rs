@@ -1,3 +1,12 @@
+//! Encrypted MSM: LPN masking of the witness and unmasking of the server's
+//! result.
+//!
+//! Without the `std` feature only the core math is built, under `no_std` +
+//! `alloc`: `sparse_vec`, `params`, `raa_code`, `pedersen`, `dual_lpn`, `emsm`
+//! and the `progress`, `cancel` and `parallel` hooks they take. Cancellation
+//! deadlines, security estimates and the modules marked below need `std`; thread
+//! pools need `parallel`.
+
 pub mod sparse_vec;
 pub mod params;
 pub mod raa_code;
@@ -5,4 +14,16 @@ pub mod pedersen;
 pub mod dual_lpn;
 #[allow(clippy::module_inception)]
 pub mod emsm;
+#[cfg(feature = "std")]
 pub mod malicious;
+pub mod progress;
+pub mod cancel;
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod deferred;
+#[cfg(feature = "std")]
+pub mod security;
+#[cfg(feature = "std")]
+pub mod seeded;
@@ -0,0 +1,258 @@
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
+
+use ark_ec::CurveGroup;
+#[cfg(feature = "parallel")]
+use ark_std::vec::Vec;
+#[cfg(feature = "parallel")]
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use super::cancel::CancelToken;
+use super::pedersen::{msm_chunked, PedersenError};
+
+/// Default input length from which the RAA steps run in parallel.
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 1 << 16;
+
+/// Default smallest chunk a parallel accumulate is split into.
+pub const DEFAULT_MIN_CHUNK: usize = 1 << 10;
+
+/// Input lengths (N = 4n) from which each step of the RAA code runs in
+/// parallel. The defaults suit a desktop; small cores want them higher, and
+/// many-core servers lower. Without the `parallel` feature every step runs on
+/// the calling thread and the thresholds have no effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParallelThresholds {
+    /// Accumulate (suffix sum).
+    pub accumulate: usize,
+    /// Permutation by p or q.
+    pub permute: usize,
+    /// Fold of N entries down to n.
+    pub fold: usize,
+    /// Smallest chunk a parallel accumulate is split into, bounding the number
+    /// of chunks by N / `min_chunk` as well as by the thread count.
+    pub min_chunk: usize,
+}
+
+impl ParallelThresholds {
+    /// Never parallelize the RAA steps.
+    pub const SEQUENTIAL: Self = Self {
+        accumulate: usize::MAX,
+        permute: usize::MAX,
+        fold: usize::MAX,
+        min_chunk: DEFAULT_MIN_CHUNK,
+    };
+
+    /// Every step parallel from `threshold` on.
+    pub const fn uniform(threshold: usize) -> Self {
+        Self {
+            accumulate: threshold,
+            permute: threshold,
+            fold: threshold,
+            min_chunk: DEFAULT_MIN_CHUNK,
+        }
+    }
+}
+
+impl Default for ParallelThresholds {
+    fn default() -> Self {
+        Self::uniform(DEFAULT_PARALLEL_THRESHOLD)
+    }
+}
+
+/// Where and how EMSM operations use threads.
+///
+/// By default work runs on the global rayon pool. Embedders sharing the process with
+/// other workloads can give EMSM a dedicated pool (optionally pinned to specific
+/// cores) so it never uses more than its budget. Without the `parallel` feature
+/// everything runs on the calling thread.
+#[derive(Clone, Debug, Default)]
+pub struct ParallelConfig {
+    /// Dedicated pool; `None` uses the global rayon pool.
+    #[cfg(feature = "parallel")]
+    pub pool: Option<Arc<ThreadPool>>,
+    /// One pool per NUMA node, for `msm`; empty on uniform memory.
+    #[cfg(feature = "parallel")]
+    pub numa_nodes: Vec<Arc<ThreadPool>>,
+    /// When the masking steps go parallel.
+    pub thresholds: ParallelThresholds,
+}
+
+impl ParallelConfig {
+    /// Run on a dedicated pool of `num_threads` threads.
+    #[cfg(feature = "parallel")]
+    pub fn with_threads(num_threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|i| format!("emsm-{i}"))
+            .build()?;
+        Ok(Self {
+            pool: Some(Arc::new(pool)),
+            ..Self::default()
+        })
+    }
+
+    /// Run on a dedicated pool with one thread pinned to each of `core_ids`.
+    /// Cores that do not exist on this machine are left unpinned.
+    #[cfg(feature = "parallel")]
+    pub fn pinned(core_ids: &[usize]) -> Result<Self, ThreadPoolBuildError> {
+        Ok(Self {
+            pool: Some(Arc::new(pinned_pool(core_ids, "emsm")?)),
+            ..Self::default()
+        })
+    }
+
+    /// Run on one pool per NUMA node, with a thread pinned to each core of the
+    /// node (`nodes[i]` lists the cores of node i). `msm` splits the bases into
+    /// one part per node and adds up the nodes' partial sums, so each node's
+    /// buckets and scalar copies stay in its local memory instead of bouncing
+    /// between sockets. Everything else runs on the first node.
+    #[cfg(feature = "parallel")]
+    pub fn numa(nodes: &[Vec<usize>]) -> Result<Self, ThreadPoolBuildError> {
+        let numa_nodes = nodes
+            .iter()
+            .enumerate()
+            .map(|(node, cores)| Ok(Arc::new(pinned_pool(cores, &format!("emsm-n{node}"))?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            pool: numa_nodes.first().cloned(),
+            numa_nodes,
+            ..Self::default()
+        })
+    }
+
+    /// Keep the masking steps on the calling thread whatever their length. The
+    /// MSMs still run on the pool.
+    pub fn sequential() -> Self {
+        Self::default().with_thresholds(ParallelThresholds::SEQUENTIAL)
+    }
+
+    /// Parallelize every masking step from `parallel_threshold` on.
+    pub fn with_parallel_threshold(self, parallel_threshold: usize) -> Self {
+        self.with_thresholds(ParallelThresholds::uniform(parallel_threshold))
+    }
+
+    pub fn with_thresholds(mut self, thresholds: ParallelThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Number of threads work will be spread over.
+    pub fn num_threads(&self) -> usize {
+        #[cfg(feature = "parallel")]
+        if !self.numa_nodes.is_empty() {
+            return self
+                .numa_nodes
+                .iter()
+                .map(|p| p.current_num_threads())
+                .sum();
+        }
+        #[cfg(feature = "parallel")]
+        return match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        };
+        #[cfg(not(feature = "parallel"))]
+        1
+    }
+
+    /// Run `op` on the configured pool.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = &self.pool {
+            return pool.install(op);
+        }
+        op()
+    }
+
+    /// `msm_chunked` on the configured pool or, with NUMA nodes, on all of them:
+    /// each node sums its own contiguous part of the bases.
+    pub fn msm<G: CurveGroup>(
+        &self,
+        generators: &[G::Affine],
+        scalars: &[G::ScalarField],
+        cancel: &CancelToken,
+    ) -> Result<G, PedersenError> {
+        #[cfg(feature = "parallel")]
+        if self.numa_nodes.len() > 1 {
+            if scalars.len() != generators.len() {
+                return Err(PedersenError::LengthMismatch {
+                    scalars: scalars.len(),
+                    generators: generators.len(),
+                });
+            }
+            let part = generators.len().div_ceil(self.numa_nodes.len()).max(1);
+            let parts = generators.chunks(part).zip(scalars.chunks(part));
+            let sums: Vec<_> = std::thread::scope(|scope| {
+                let nodes: Vec<_> = self
+                    .numa_nodes
+                    .iter()
+                    .zip(parts)
+                    .map(|(pool, (bases, chunk))| {
+                        scope.spawn(move || pool.install(|| msm_chunked::<G>(bases, chunk, cancel)))
+                    })
+                    .collect();
+                nodes
+                    .into_iter()
+                    .map(|node| node.join().expect("NUMA node MSM panicked"))
+                    .collect()
+            });
+            return sums.into_iter().sum();
+        }
+        self.install(|| msm_chunked(generators, scalars, cancel))
+    }
+}
+
+/// A pool with one thread pinned to each of `core_ids`, named `{name}-{i}`.
+/// Cores that do not exist on this machine are left unpinned.
+#[cfg(feature = "parallel")]
+fn pinned_pool(core_ids: &[usize], name: &str) -> Result<ThreadPool, ThreadPoolBuildError> {
+    let core_ids = core_ids.to_vec();
+    let name = name.to_string();
+    ThreadPoolBuilder::new()
+        .num_threads(core_ids.len())
+        .thread_name(move |i| format!("{name}-{i}"))
+        .start_handler(move |i| {
+            if !core_affinity::set_for_current(core_affinity::CoreId { id: core_ids[i] }) {
+                tracing::warn!("failed to pin EMSM thread {i} to core {}", core_ids[i]);
+            }
+        })
+        .build()
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedicated_pool() {
+        let config = ParallelConfig::with_threads(2).unwrap();
+        assert_eq!(config.num_threads(), 2);
+        assert_eq!(config.install(rayon::current_num_threads), 2);
+    }
+
+    #[test]
+    fn test_numa_msm() {
+        use crate::emsm::pedersen::Pedersen;
+        use ark_bn254::{Fr, G1Projective as G1};
+        use ark_std::{test_rng, UniformRand, Zero};
+
+        let config = ParallelConfig::numa(&[vec![0, 0], vec![0]]).unwrap();
+        assert_eq!(config.num_threads(), 3);
+        assert_eq!(config.install(rayon::current_num_threads), 2);
+
+        let mut rng = test_rng();
+        let ped = Pedersen::<G1>::rand(33, &mut rng);
+        let scalars: Vec<Fr> = (0..33).map(|_| Fr::rand(&mut rng)).collect();
+        let cancel = CancelToken::new();
+        assert_eq!(
+            config
+                .msm::<G1>(&ped.generators, &scalars, &cancel)
+                .unwrap(),
+            ped.commit(&scalars).unwrap()
+        );
+        assert!(config
+            .msm::<G1>(&ped.generators, &scalars[1..], &cancel)
+            .is_err());
+        assert!(config.msm::<G1>(&[], &[], &cancel).unwrap().is_zero());
+    }
+}
@@ -1,44 +1,437 @@
-/// LPN parameters for 100-bit security.
+use ark_std::{format, string::String, vec::Vec};
+
+use super::raa_code::DEFAULT_FOLD;
+#[cfg(feature = "std")]
+use crate::error::StealthSnarkError;
+
+/// LPN parameters for a given vector length and security level.
 /// Based on Table 3 of the paper (R = 1/4, delta = 0.05).
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LpnParams {
     /// Original vector length
     pub n: usize,
-    /// Expanded length: N = 4n (rate R = 1/4)
+    /// Expanded length: N = fold * n (rate R = 1/fold, 1/4 by default)
     pub big_n: usize,
     /// Sparsity parameter (number of nonzero entries in error vector)
     pub t: usize,
 }
 
+/// Largest vector length covered by Table 3 of the paper.
+pub const MAX_TABULATED_N: usize = 1 << 24;
+
+/// Largest vector length with any LPN parameters. Lengths above
+/// `MAX_TABULATED_N` use extrapolated rows; anything larger is rejected.
+pub const MAX_LPN_N: usize = 1 << 28;
+
+/// Target security level for the LPN masking.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SecurityLevel {
+    /// For benchmarking only.
+    Bits80,
+    /// The paper's parameter set.
+    #[default]
+    Bits100,
+    Bits128,
+}
+
+impl SecurityLevel {
+    pub fn bits(self) -> u32 {
+        match self {
+            SecurityLevel::Bits80 => 80,
+            SecurityLevel::Bits100 => 100,
+            SecurityLevel::Bits128 => 128,
+        }
+    }
+
+    /// The level of `bits` security, if it is one of 80, 100 and 128.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            80 => Some(SecurityLevel::Bits80),
+            100 => Some(SecurityLevel::Bits100),
+            128 => Some(SecurityLevel::Bits128),
+            _ => None,
+        }
+    }
+
+    /// (log2 n, t) rows. Row k covers 2^(k-1) < n <= 2^k; the first row also
+    /// covers everything below it.
+    fn table(self) -> &'static [(u32, usize); 15] {
+        match self {
+            SecurityLevel::Bits80 => &TABLE_80,
+            SecurityLevel::Bits100 => &TABLE_100,
+            SecurityLevel::Bits128 => &TABLE_128,
+        }
+    }
+}
+
+/// Table 3 values from the paper for 100-bit security, R=1/4, delta=0.05.
+const TABLE_100: [(u32, usize); 15] = [
+    (10, 29),
+    (11, 33),
+    (12, 38),
+    (13, 43),
+    (14, 48),
+    (15, 54),
+    (16, 60),
+    (17, 67),
+    (18, 74),
+    (19, 82),
+    (20, 90),
+    (21, 99),
+    (22, 108),
+    (23, 118),
+    (24, 128),
+];
+
+/// 128-bit rows: the paper's 100-bit rows scaled by 128/100, rounded up. At a
+/// fixed length and rate the cost of the attacks the paper's Table 3 accounts
+/// for grows linearly in t, so scaling t scales the security level; every row
+/// is above the 100-bit one.
+const TABLE_128: [(u32, usize); 15] = [
+    (10, 38),
+    (11, 43),
+    (12, 49),
+    (13, 56),
+    (14, 62),
+    (15, 70),
+    (16, 77),
+    (17, 86),
+    (18, 95),
+    (19, 105),
+    (20, 116),
+    (21, 127),
+    (22, 139),
+    (23, 152),
+    (24, 164),
+];
+
+/// 80-bit rows: the paper's 100-bit rows scaled by 80/100, rounded up, as for
+/// `TABLE_128`.
+const TABLE_80: [(u32, usize); 15] = [
+    (10, 24),
+    (11, 27),
+    (12, 31),
+    (13, 35),
+    (14, 39),
+    (15, 44),
+    (16, 48),
+    (17, 54),
+    (18, 60),
+    (19, 66),
+    (20, 72),
+    (21, 80),
+    (22, 87),
+    (23, 95),
+    (24, 103),
+];
+
+/// Growth of t per doubling of n beyond the last row of the built-in tables.
+const GROWTH_PERCENT: usize = 10;
+
+/// Noise weights for one security level at one code rate, as (log2 n, t) rows
+/// like those of the built-in tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+pub struct LpnTable {
+    /// 80, 100 or 128.
+    pub security_bits: u32,
+    /// The code's rate is 1/`fold`.
+    #[cfg_attr(feature = "std", serde(default = "default_fold"))]
+    pub fold: usize,
+    /// Row k covers 2^(k-1) < n <= 2^k; the first row also covers everything
+    /// below it. Increasing in k, and in t.
+    pub rows: Vec<(u32, usize)>,
+}
+
+#[cfg(feature = "std")]
+fn default_fold() -> usize {
+    DEFAULT_FOLD
+}
+
+#[cfg(feature = "std")]
+fn default_growth() -> usize {
+    GROWTH_PERCENT
+}
+
+/// A parameter table to use instead of the built-in one, e.g. with the noise
+/// weights of a newer analysis than the crate's (see `SetupOptions::lpn_params`).
+/// Security levels it has no rate-1/4 table for keep the built-in rows; other
+/// rates only have the rows it gives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+pub struct LpnParamSet {
+    pub tables: Vec<LpnTable>,
+    /// Growth of t in percent per doubling of n beyond the last row of a table.
+    #[cfg_attr(feature = "std", serde(default = "default_growth"))]
+    pub growth_percent: usize,
+}
+
+/// Why an `LpnParamSet` was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid LPN parameter table: {0}")]
+pub struct InvalidLpnTable(pub String);
+
+impl LpnParamSet {
+    /// The built-in tables, at rate 1/4, as a starting point for edits.
+    pub fn builtin() -> Self {
+        let tables = [
+            SecurityLevel::Bits80,
+            SecurityLevel::Bits100,
+            SecurityLevel::Bits128,
+        ]
+        .map(|security| LpnTable {
+            security_bits: security.bits(),
+            fold: DEFAULT_FOLD,
+            rows: security.table().to_vec(),
+        });
+        Self {
+            tables: tables.to_vec(),
+            growth_percent: GROWTH_PERCENT,
+        }
+    }
+
+    /// Check that every table is for a known security level and a nonzero
+    /// fold, with nonempty rows increasing in log2 n and in t up to
+    /// `MAX_LPN_N`, that no two tables are for the same level and rate, and that
+    /// t grows by at most 100% per doubling.
+    pub fn validate(&self) -> Result<(), InvalidLpnTable> {
+        if self.growth_percent > 100 {
+            return Err(InvalidLpnTable(format!(
+                "growth of {}% per doubling is above 100%",
+                self.growth_percent
+            )));
+        }
+        for (i, table) in self.tables.iter().enumerate() {
+            let name = format!("{}-bit table at rate 1/{}", table.security_bits, table.fold);
+            let invalid = |why: &str| Err(InvalidLpnTable(format!("{name}: {why}")));
+            if SecurityLevel::from_bits(table.security_bits).is_none() {
+                return invalid("security must be 80, 100 or 128 bits");
+            }
+            if table.fold == 0 {
+                return invalid("fold must be nonzero");
+            }
+            if table.rows.is_empty() {
+                return invalid("no rows");
+            }
+            if table.rows.iter().any(|&(_, t)| t == 0) {
+                return invalid("t must be nonzero");
+            }
+            if table
+                .rows
+                .iter()
+                .any(|&(k, _)| k > MAX_LPN_N.trailing_zeros())
+            {
+                return invalid("log2 n must be at most log2 MAX_LPN_N");
+            }
+            let increasing = table
+                .rows
+                .windows(2)
+                .all(|rows| rows[0].0 < rows[1].0 && rows[0].1 <= rows[1].1);
+            if !increasing {
+                return invalid("rows must increase in log2 n and in t");
+            }
+            let duplicate = self.tables[..i].iter().any(|other| {
+                (other.security_bits, other.fold) == (table.security_bits, table.fold)
+            });
+            if duplicate {
+                return invalid("given twice");
+            }
+        }
+        Ok(())
+    }
+
+    /// The noise weight for vectors of length n at `security` over the code of
+    /// rate 1/`fold`, from the table for that level and rate, or `None` if there
+    /// is none. Rows at one rate say nothing about another.
+    pub fn t(&self, n: usize, security: SecurityLevel, fold: usize) -> Option<usize> {
+        let table = self
+            .tables
+            .iter()
+            .find(|table| table.security_bits == security.bits() && table.fold == fold)?;
+        Some(lookup(&table.rows, self.growth_percent, n))
+    }
+
+    /// `try_get_lpn_params_at` with the noise weight from this set's table for
+    /// the level and rate, else from the built-in rows at rate 1/4.
+    pub fn params(
+        &self,
+        n: usize,
+        security: SecurityLevel,
+        fold: usize,
+    ) -> Result<LpnParams, LpnParamsError> {
+        lpn_params(n, security, fold, || {
+            self.t(n, security, fold)
+                .or_else(|| builtin_t(n, security, fold))
+        })
+    }
+}
+
+/// t of the row of `rows` covering n, extrapolated by `growth_percent` per
+/// doubling past the last row.
+fn lookup(rows: &[(u32, usize)], growth_percent: usize, n: usize) -> usize {
+    let log_n = n.max(1).next_power_of_two().trailing_zeros();
+    match rows.iter().find(|&&(k, _)| log_n <= k) {
+        Some(&(_, t)) => t,
+        None => {
+            let (last_k, last_t) = rows[rows.len() - 1];
+            (last_k..log_n).fold(last_t, |t, _| (t * (100 + growth_percent)).div_ceil(100))
+        }
+    }
+}
+
+/// Environment variable naming a JSON `LpnParamSet` file for
+/// `LpnParamSet::from_env`.
+#[cfg(feature = "std")]
+pub const LPN_PARAMS_ENV: &str = "STEALTHSNARK_LPN_PARAMS";
+
+#[cfg(feature = "std")]
+impl LpnParamSet {
+    /// Rate every row of every table, and every row extrapolated from it up to
+    /// `MAX_LPN_N`, with `security::row_security`. The estimator is advisory:
+    /// rows it rates under their table's level are logged, or refused if
+    /// `strict`.
+    pub fn check_security(&self, strict: bool) -> Result<(), InvalidLpnTable> {
+        for table in &self.tables {
+            let last = table.rows[table.rows.len() - 1].0;
+            let extrapolated = (last + 1..=MAX_LPN_N.trailing_zeros())
+                .map(|k| (k, lookup(&table.rows, self.growth_percent, 1 << k)));
+            let mut below = None;
+            for (k, t) in table.rows.iter().copied().chain(extrapolated) {
+                let bits = super::security::row_security(below, k, t, table.fold);
+                if bits < table.security_bits as f64 {
+                    let shortfall = format!(
+                        "{}-bit table at rate 1/{}: t = {t} at n = 2^{k} is estimated at \
+                         {bits:.1} bits",
+                        table.security_bits, table.fold
+                    );
+                    if strict {
+                        return Err(InvalidLpnTable(shortfall));
+                    }
+                    tracing::warn!("{shortfall}");
+                }
+                below = Some(k);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a table from JSON, e.g.
+    /// `{"tables": [{"security_bits": 100, "rows": [[10, 48], [11, 50]]}]}`,
+    /// logging the rows the estimator rates under their level.
+    pub fn from_json(json: &str) -> Result<Self, StealthSnarkError> {
+        let params: Self = serde_json::from_str(json)?;
+        params.validate()?;
+        params.check_security(false)?;
+        Ok(params)
+    }
+
+    /// `from_json` of the file at `path`.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, StealthSnarkError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// `load` of the file `STEALTHSNARK_LPN_PARAMS` names, if it is set.
+    pub fn from_env() -> Result<Option<Self>, StealthSnarkError> {
+        match std::env::var_os(LPN_PARAMS_ENV) {
+            Some(path) => Self::load(path).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Requested vector length exceeds `MAX_LPN_N`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("no LPN parameters for vectors of length {n} (maximum {MAX_LPN_N})")]
+pub struct UnsupportedLength {
+    pub n: usize,
+}
+
+/// Why there are no LPN parameters for a vector length, security level and
+/// code rate, or why chosen ones are out of range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LpnParamsError {
+    #[error(transparent)]
+    UnsupportedLength(#[from] UnsupportedLength),
+    /// Only rate 1/4 has built-in rows; other rates need a table for the level
+    /// or an explicit noise weight.
+    #[error("no {}-bit LPN table at rate 1/{fold}; give one or choose t", security.bits())]
+    NoTable {
+        security: SecurityLevel,
+        fold: usize,
+    },
+    #[error("the code rate 1/fold needs a nonzero fold")]
+    ZeroFold,
+    #[error("noise weight {t} is outside 1..={big_n}")]
+    NoiseWeightOutOfRange { t: usize, big_n: usize },
+}
+
 /// Get LPN parameters for a given vector length n.
-/// Returns (N = 4n, t) from Table 3 of the paper for 100-bit security.
+/// Returns (N = 4n, t) from the 100-bit table.
+///
+/// # Panics
+/// If `n > MAX_LPN_N`; use `try_get_lpn_params` to handle that case.
 pub fn get_lpn_params(n: usize) -> LpnParams {
-    // Table 3 values from the paper for 100-bit security, R=1/4, delta=0.05
-    // n -> t (approximate, interpolated for sizes not in table)
-    // For very small n, we clamp t so that N = 4n >= t (needed for error_vec chunking)
-    let big_n = 4 * n;
-    let raw_t = match n {
-        0..=1024 => 29,           // 2^10
-        1025..=2048 => 33,        // 2^11
-        2049..=4096 => 38,        // 2^12
-        4097..=8192 => 43,        // 2^13
-        8193..=16384 => 48,       // 2^14
-        16385..=32768 => 54,      // 2^15
-        32769..=65536 => 60,      // 2^16
-        65537..=131072 => 67,     // 2^17
-        131073..=262144 => 74,    // 2^18
-        262145..=524288 => 82,    // 2^19
-        524289..=1048576 => 90,   // 2^20
-        1048577..=2097152 => 99,  // 2^21
-        2097153..=4194304 => 108, // 2^22
-        4194305..=8388608 => 118, // 2^23
-        _ => 128,                 // 2^24+
-    };
-
-    // Clamp t so that the expanded vector size N = 4n >= t
+    get_lpn_params_for(n, SecurityLevel::default())
+}
+
+/// `get_lpn_params` at the given security level.
+///
+/// # Panics
+/// If `n > MAX_LPN_N`.
+pub fn get_lpn_params_for(n: usize, security: SecurityLevel) -> LpnParams {
+    try_get_lpn_params(n, security).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// `get_lpn_params_for`, returning an error instead of panicking past `MAX_LPN_N`.
+///
+/// Beyond the table (n > 2^24), t grows by 10% per doubling, rounded up. The last
+/// tabulated doublings grow by 8-9%, so this over-provisions noise rather than
+/// under-provisioning it.
+pub fn try_get_lpn_params(
+    n: usize,
+    security: SecurityLevel,
+) -> Result<LpnParams, UnsupportedLength> {
+    // At rate 1/4 there are always rows, so only the length can fail
+    try_get_lpn_params_at(n, security, DEFAULT_FOLD).map_err(|_| UnsupportedLength { n })
+}
+
+/// `try_get_lpn_params` over the code of rate 1/`fold`, N = fold * n. Only
+/// rate 1/4 has built-in rows; other rates are `LpnParamsError::NoTable`
+/// unless an `LpnParamSet` has rows for them (`LpnParamSet::params`).
+pub fn try_get_lpn_params_at(
+    n: usize,
+    security: SecurityLevel,
+    fold: usize,
+) -> Result<LpnParams, LpnParamsError> {
+    lpn_params(n, security, fold, || builtin_t(n, security, fold))
+}
+
+/// t of the built-in rows, which are for rate 1/4 only.
+fn builtin_t(n: usize, security: SecurityLevel, fold: usize) -> Option<usize> {
+    (fold == DEFAULT_FOLD).then(|| lookup(security.table(), GROWTH_PERCENT, n))
+}
+
+/// The parameters for length n at rate 1/`fold` with the noise weight `t`
+/// finds for the level and rate, if any.
+fn lpn_params(
+    n: usize,
+    security: SecurityLevel,
+    fold: usize,
+    t: impl FnOnce() -> Option<usize>,
+) -> Result<LpnParams, LpnParamsError> {
+    if fold == 0 {
+        return Err(LpnParamsError::ZeroFold);
+    }
+    if n > MAX_LPN_N {
+        return Err(UnsupportedLength { n }.into());
+    }
+    let big_n = fold * n;
+    let raw_t = t().ok_or(LpnParamsError::NoTable { security, fold })?;
+
+    // Clamp t so that the expanded vector size N >= t
     // (for tiny circuits, security is naturally limited by the small dimension)
     let t = raw_t.min(big_n.max(1));
-    LpnParams { n, big_n, t }
+    Ok(LpnParams { n, big_n, t })
 }
 
 #[cfg(test)]
@@ -68,4 +461,164 @@ mod tests {
         let p = get_lpn_params(4096);
         assert_eq!(p.big_n, 4 * p.n); // R = 1/4
     }
+
+    #[test]
+    fn test_table_covers_every_size_up_to_max() {
+        // Row boundaries: 2^k uses row k, 2^k + 1 uses row k + 1
+        for &(k, t) in &TABLE_100 {
+            assert_eq!(get_lpn_params(1 << k).t, t);
+        }
+        assert_eq!(get_lpn_params(MAX_TABULATED_N).t, 128);
+        assert_eq!(get_lpn_params(MAX_TABULATED_N + 1).t, 141);
+
+        // Never decreasing across a doubling, tabulated or extrapolated
+        for security in [
+            SecurityLevel::Bits80,
+            SecurityLevel::Bits100,
+            SecurityLevel::Bits128,
+        ] {
+            let mut prev = 0;
+            for k in 10..=MAX_LPN_N.trailing_zeros() {
+                let t = get_lpn_params_for(1 << k, security).t;
+                assert!(t >= prev, "t decreasing at 2^{k} for {security:?}");
+                prev = t;
+            }
+        }
+        assert_eq!(get_lpn_params(MAX_LPN_N).t, 190);
+    }
+
+    #[test]
+    fn test_higher_levels_have_more_noise() {
+        // Strictly, at every tabulated and extrapolated length
+        for k in 10..=MAX_LPN_N.trailing_zeros() {
+            let t = |security| get_lpn_params_for(1 << k, security).t;
+            assert!(
+                t(SecurityLevel::Bits80) < t(SecurityLevel::Bits100),
+                "at 2^{k}"
+            );
+            assert!(
+                t(SecurityLevel::Bits100) < t(SecurityLevel::Bits128),
+                "at 2^{k}"
+            );
+        }
+        for ((k, t100), (_, t128)) in TABLE_100.iter().zip(&TABLE_128) {
+            assert!(t128 > t100, "128-bit row 2^{k}");
+        }
+    }
+
+    #[test]
+    fn test_security_levels() {
+        for (n, t80, t100, t128) in [
+            (1 << 10, 24, 29, 38),
+            (1 << 16, 48, 60, 77),
+            (1 << 20, 72, 90, 116),
+            (1 << 24, 103, 128, 164),
+        ] {
+            assert_eq!(get_lpn_params_for(n, SecurityLevel::Bits80).t, t80);
+            assert_eq!(get_lpn_params_for(n, SecurityLevel::Bits100).t, t100);
+            assert_eq!(get_lpn_params_for(n, SecurityLevel::Bits128).t, t128);
+        }
+        // Tiny vectors are clamped at every level
+        assert_eq!(get_lpn_params_for(4, SecurityLevel::Bits128).t, 16);
+    }
+
+    #[test]
+    fn test_lpn_param_set() {
+        // The built-in set reproduces the built-in lookups
+        let builtin = LpnParamSet::builtin();
+        builtin.validate().unwrap();
+        for k in [4, 10, 17, 24, 25, 28] {
+            for security in [SecurityLevel::Bits80, SecurityLevel::Bits128] {
+                let t = builtin.t(1 << k, security, DEFAULT_FOLD).unwrap();
+                assert_eq!(t, get_lpn_params_for(1 << k, security).t);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lpn_param_set_from_json() {
+        // A table for another rate, and no other level or rate
+        let json =
+            r#"{"tables": [{"security_bits": 100, "fold": 3, "rows": [[10, 52], [12, 60]]}]}"#;
+        let params = LpnParamSet::from_json(json).unwrap();
+        assert_eq!(params.growth_percent, 10);
+        assert_eq!(params.t(1 << 11, SecurityLevel::Bits100, 3), Some(60));
+        assert_eq!(params.t(1 << 13, SecurityLevel::Bits100, 3), Some(66));
+        assert_eq!(params.t(1 << 11, SecurityLevel::Bits128, 3), None);
+        assert_eq!(
+            params.t(1 << 11, SecurityLevel::Bits100, DEFAULT_FOLD),
+            None
+        );
+
+        // Its lookups use it at that rate and the built-in rows at rate 1/4
+        let at = |security, fold| params.params(1 << 11, security, fold);
+        let no_table = |security, fold| Err(LpnParamsError::NoTable { security, fold });
+        assert_eq!(at(SecurityLevel::Bits100, 3).unwrap().t, 60);
+        assert_eq!(at(SecurityLevel::Bits100, 3).unwrap().big_n, 3 << 11);
+        assert_eq!(
+            at(SecurityLevel::Bits128, 3),
+            no_table(SecurityLevel::Bits128, 3)
+        );
+        assert_eq!(
+            at(SecurityLevel::Bits100, DEFAULT_FOLD),
+            try_get_lpn_params_at(1 << 11, SecurityLevel::Bits100, DEFAULT_FOLD)
+        );
+        assert_eq!(
+            try_get_lpn_params_at(1 << 11, SecurityLevel::Bits100, 3),
+            no_table(SecurityLevel::Bits100, 3)
+        );
+
+        for invalid in [
+            r#"{"tables": [{"security_bits": 90, "rows": [[10, 40]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "fold": 0, "rows": [[10, 40]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": [[11, 40], [10, 50]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": [[10, 40], [11, 30]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": []}]}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": [[10, 48]]},
+                           {"security_bits": 100, "fold": 4, "rows": [[10, 49]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": [[10, 48]]}], "growth_percent": 101}"#,
+            r#"{"tables": [{"security_bits": 100, "rows": [[10, 48], [29, 200]]}]}"#,
+        ] {
+            assert!(LpnParamSet::from_json(invalid).is_err(), "{invalid}");
+        }
+
+        // Below their level by the estimator: loaded with a warning, refused if strict
+        for weak in [
+            r#"{"tables": [{"security_bits": 100, "rows": [[10, 40], [12, 48]]}]}"#,
+            r#"{"tables": [{"security_bits": 128, "rows": [[10, 48], [12, 50]]}]}"#,
+            r#"{"tables": [{"security_bits": 100, "fold": 2, "rows": [[10, 48]]}]}"#,
+        ] {
+            let params = LpnParamSet::from_json(weak).unwrap();
+            assert!(params.check_security(true).is_err(), "{weak}");
+        }
+    }
+
+    #[test]
+    fn test_beyond_max_is_an_error() {
+        assert!(try_get_lpn_params(MAX_LPN_N, SecurityLevel::Bits100).is_ok());
+        assert_eq!(
+            try_get_lpn_params(MAX_LPN_N + 1, SecurityLevel::Bits128).unwrap_err(),
+            UnsupportedLength { n: MAX_LPN_N + 1 }
+        );
+    }
+
+    #[test]
+    fn test_rates_without_a_table_are_an_error() {
+        let security = SecurityLevel::Bits100;
+        for fold in [2, 8] {
+            assert_eq!(
+                try_get_lpn_params_at(1 << 10, security, fold).unwrap_err(),
+                LpnParamsError::NoTable { security, fold }
+            );
+        }
+        assert_eq!(
+            try_get_lpn_params_at(1 << 10, security, 0).unwrap_err(),
+            LpnParamsError::ZeroFold
+        );
+        assert_eq!(
+            try_get_lpn_params_at(MAX_LPN_N + 1, security, DEFAULT_FOLD).unwrap_err(),
+            LpnParamsError::UnsupportedLength(UnsupportedLength { n: MAX_LPN_N + 1 })
+        );
+    }
 }
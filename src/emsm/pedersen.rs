@@ -1,7 +1,267 @@
-use ark_ec::CurveGroup;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::PrimeField;
 use ark_std::rand::Rng;
+use ark_std::{vec, vec::Vec};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
-use super::sparse_vec::SparseVector;
+use super::cancel::{CancelToken, Cancelled};
+use super::sparse_vec::{from_index, SparseVector};
+
+/// Bases per MSM call in `msm_chunked`. arkworks converts every scalar to its
+/// bigint form before bucketing, so chunking bounds that copy (128 MiB per chunk
+/// for BN254) at the cost of slightly narrower Pippenger windows.
+pub const MSM_CHUNK: usize = 1 << 22;
+
+/// sum(scalars[i] * generators[i]) over borrowed generators, `MSM_CHUNK` at a
+/// time, checking `cancel` before each chunk.
+pub fn msm_chunked<G: CurveGroup>(
+    generators: &[G::Affine],
+    scalars: &[G::ScalarField],
+    cancel: &CancelToken,
+) -> Result<G, PedersenError> {
+    if scalars.len() != generators.len() {
+        return Err(PedersenError::LengthMismatch {
+            scalars: scalars.len(),
+            generators: generators.len(),
+        });
+    }
+    let mut sum = G::zero();
+    for (bases, chunk) in generators.chunks(MSM_CHUNK).zip(scalars.chunks(MSM_CHUNK)) {
+        cancel.check()?;
+        sum += G::msm(bases, chunk).map_err(|_| PedersenError::MsmFailed)?;
+    }
+    Ok(sum)
+}
+
+/// `msm_chunked` on at most `threads` threads of the current pool: the bases are
+/// split into `threads` slices, each summed by a single-threaded Pippenger. The
+/// rest of the pool stays free for other requests, at the cost of a slower MSM
+/// than `msm_chunked` on the whole pool. `cancel` is checked before every
+/// `MSM_CHUNK` of each slice.
+pub fn msm_on_threads<G: CurveGroup>(
+    generators: &[G::Affine],
+    scalars: &[G::ScalarField],
+    threads: usize,
+    cancel: &CancelToken,
+) -> Result<G, PedersenError> {
+    if scalars.len() != generators.len() {
+        return Err(PedersenError::LengthMismatch {
+            scalars: scalars.len(),
+            generators: generators.len(),
+        });
+    }
+    let slice_len = scalars.len().div_ceil(threads.max(1)).max(1);
+    let slice_sum = |(bases, slice): (&[G::Affine], &[G::ScalarField])| {
+        let mut sum = G::zero();
+        for (bases, chunk) in bases.chunks(MSM_CHUNK).zip(slice.chunks(MSM_CHUNK)) {
+            cancel.check()?;
+            sum += msm_serial::<G>(bases, chunk);
+        }
+        Ok(sum)
+    };
+    #[cfg(feature = "parallel")]
+    let sums: Vec<Result<G, PedersenError>> = generators
+        .par_chunks(slice_len)
+        .zip(scalars.par_chunks(slice_len))
+        .map(slice_sum)
+        .collect();
+    #[cfg(not(feature = "parallel"))]
+    let sums: Vec<Result<G, PedersenError>> = generators
+        .chunks(slice_len)
+        .zip(scalars.chunks(slice_len))
+        .map(slice_sum)
+        .collect();
+    sums.into_iter().sum()
+}
+
+/// Pippenger's bucket method on the calling thread, with the window width
+/// arkworks picks for `bases.len()`.
+fn msm_serial<G: CurveGroup>(bases: &[G::Affine], scalars: &[G::ScalarField]) -> G {
+    if bases.is_empty() {
+        return G::zero();
+    }
+    let width = if bases.len() < 32 {
+        3
+    } else {
+        (usize::BITS - bases.len().leading_zeros()) as usize * 69 / 100 + 2
+    };
+    let scalars: Vec<_> = scalars.iter().map(|s| s.into_bigint()).collect();
+    let num_bits = G::ScalarField::MODULUS_BIT_SIZE as usize;
+
+    let mut sum = G::zero();
+    let mut buckets = vec![G::zero(); (1 << width) - 1];
+    for start in (0..num_bits).step_by(width).rev() {
+        for _ in 0..width {
+            sum.double_in_place();
+        }
+        buckets.iter_mut().for_each(|b| *b = G::zero());
+        for (base, scalar) in bases.iter().zip(&scalars) {
+            let digit = window_digit(scalar.as_ref(), start, width);
+            if digit != 0 {
+                buckets[digit - 1] += base;
+            }
+        }
+        // sum(i * bucket[i - 1]) as a running sum of running sums
+        let (mut running, mut window) = (G::zero(), G::zero());
+        for bucket in buckets.iter().rev() {
+            running += bucket;
+            window += &running;
+        }
+        sum += window;
+    }
+    sum
+}
+
+/// Bits `start..start + width` of the little-endian `limbs`.
+fn window_digit(limbs: &[u64], start: usize, width: usize) -> usize {
+    let (limb, shift) = (start / 64, start % 64);
+    let mut bits = limbs[limb] >> shift;
+    if shift + width > 64 && limb + 1 < limbs.len() {
+        bits |= limbs[limb + 1] << (64 - shift);
+    }
+    (bits & ((1 << width) - 1)) as usize
+}
+
+/// Widest window of a `FixedBaseTable`: each thread of its MSM keeps 2^window
+/// buckets.
+pub const MAX_TABLE_WINDOW: usize = 16;
+
+/// Generators a `FixedBaseTable` doubles before normalizing them, which bounds
+/// the projective copy it builds from.
+const TABLE_BUILD_CHUNK: usize = 1 << 14;
+
+/// Fixed-base table of a set of generators: 2^(window·j)·g for each generator g
+/// and each `window`-bit window j of a scalar. An MSM over the table needs a
+/// single bucket pass over all windows at once, with no doublings and one
+/// bucket reduction per thread, where Pippenger's method makes a pass, a
+/// reduction and `window` doublings per window. The table takes
+/// ⌈MODULUS_BIT_SIZE / window⌉ times the memory of the generators, and as
+/// many doublings per generator as the scalars have bits to build.
+#[derive(Clone, Debug)]
+pub struct FixedBaseTable<G: CurveGroup> {
+    window: usize,
+    /// Windows per scalar.
+    windows: usize,
+    /// The `windows` multiples of each generator, generator by generator.
+    multiples: Vec<G::Affine>,
+}
+
+impl<G: CurveGroup> FixedBaseTable<G> {
+    /// An empty table of `window`-bit windows, for `extend` to fill.
+    ///
+    /// # Panics
+    /// If `window` is 0 or larger than `MAX_TABLE_WINDOW`.
+    pub fn new(window: usize) -> Self {
+        assert!(
+            (1..=MAX_TABLE_WINDOW).contains(&window),
+            "table windows must be 1 to {MAX_TABLE_WINDOW} bits, got {window}"
+        );
+        Self {
+            window,
+            windows: (G::ScalarField::MODULUS_BIT_SIZE as usize).div_ceil(window),
+            multiples: Vec::new(),
+        }
+    }
+
+    /// Memory a table of `window`-bit windows over `len` generators takes, in
+    /// bytes.
+    pub fn bytes_for(len: usize, window: usize) -> usize {
+        let windows = (G::ScalarField::MODULUS_BIT_SIZE as usize).div_ceil(window);
+        windows * len * core::mem::size_of::<G::Affine>()
+    }
+
+    /// Append the multiples of `generators`, doubling them in parallel.
+    pub fn extend(&mut self, generators: &[G::Affine]) {
+        let (window, windows) = (self.window, self.windows);
+        let row = |g: &G::Affine| {
+            let mut point = g.into_group();
+            (0..windows).map(move |j| {
+                if j > 0 {
+                    (0..window).for_each(|_| {
+                        point.double_in_place();
+                    });
+                }
+                point
+            })
+        };
+        self.multiples.reserve(generators.len() * windows);
+        for chunk in generators.chunks(TABLE_BUILD_CHUNK) {
+            #[cfg(feature = "parallel")]
+            let projective: Vec<G> = chunk.par_iter().flat_map_iter(row).collect();
+            #[cfg(not(feature = "parallel"))]
+            let projective: Vec<G> = chunk.iter().flat_map(row).collect();
+            self.multiples.extend(G::normalize_batch(&projective));
+        }
+    }
+
+    /// Number of generators in the table.
+    pub fn len(&self) -> usize {
+        self.multiples.len() / self.windows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.multiples.is_empty()
+    }
+
+    /// sum(scalars[i] * generators[i]) over the table, split into up to
+    /// `threads` slices with buckets of their own, checking `cancel` before
+    /// every `MSM_CHUNK` of each slice. A slice holds at least as many
+    /// generators as it has buckets, so that reducing them stays cheap.
+    pub fn msm(
+        &self,
+        scalars: &[G::ScalarField],
+        threads: usize,
+        cancel: &CancelToken,
+    ) -> Result<G, PedersenError> {
+        if scalars.len() != self.len() {
+            return Err(PedersenError::LengthMismatch {
+                scalars: scalars.len(),
+                generators: self.len(),
+            });
+        }
+        let (window, windows) = (self.window, self.windows);
+        let slice_len = scalars.len().div_ceil(threads.max(1)).max(1 << window);
+        let slice_sum = |(multiples, slice): (&[G::Affine], &[G::ScalarField])| {
+            let mut buckets = vec![G::zero(); (1 << window) - 1];
+            let chunks = multiples.chunks(MSM_CHUNK * windows).zip(slice.chunks(MSM_CHUNK));
+            for (multiples, chunk) in chunks {
+                cancel.check()?;
+                for (row, scalar) in multiples.chunks_exact(windows).zip(chunk) {
+                    let scalar = scalar.into_bigint();
+                    for (j, point) in row.iter().enumerate() {
+                        let digit = window_digit(scalar.as_ref(), j * window, window);
+                        if digit != 0 {
+                            buckets[digit - 1] += point;
+                        }
+                    }
+                }
+            }
+            // sum(i * bucket[i - 1]) as a running sum of running sums
+            let (mut running, mut sum) = (G::zero(), G::zero());
+            for bucket in buckets.iter().rev() {
+                running += bucket;
+                sum += &running;
+            }
+            Ok(sum)
+        };
+        #[cfg(feature = "parallel")]
+        let sums: Vec<Result<G, PedersenError>> = self
+            .multiples
+            .par_chunks(slice_len * windows)
+            .zip(scalars.par_chunks(slice_len))
+            .map(slice_sum)
+            .collect();
+        #[cfg(not(feature = "parallel"))]
+        let sums: Vec<Result<G, PedersenError>> = self
+            .multiples
+            .chunks(slice_len * windows)
+            .zip(scalars.chunks(slice_len))
+            .map(slice_sum)
+            .collect();
+        sums.into_iter().sum()
+    }
+}
 
 /// Pedersen-style commitment scheme: MSM wrapper over generators.
 #[derive(Clone, Debug)]
@@ -24,25 +284,35 @@ impl<G: CurveGroup> Pedersen<G> {
     /// Compute MSM: sum(scalars[i] * generators[i]).
     /// Returns an error if lengths don't match.
     pub fn commit(&self, scalars: &[G::ScalarField]) -> Result<G, PedersenError> {
-        if scalars.len() != self.generators.len() {
+        msm_chunked(&self.generators, scalars, &CancelToken::default())
+    }
+
+    /// Compute sparse MSM: sum over nonzero entries only.
+    /// Returns an error if the vector is longer than the generators or any
+    /// index lies outside it.
+    pub fn commit_sparse(&self, sparse: &SparseVector<G::ScalarField>) -> Result<G, PedersenError> {
+        if sparse.size > self.generators.len() {
             return Err(PedersenError::LengthMismatch {
-                scalars: scalars.len(),
+                scalars: sparse.size,
                 generators: self.generators.len(),
             });
         }
-        G::msm(&self.generators, scalars).map_err(|_| PedersenError::MsmFailed)
-    }
-
-    /// Compute sparse MSM: sum over nonzero entries only.
-    pub fn commit_sparse(&self, sparse: &SparseVector<G::ScalarField>) -> G {
-        assert!(sparse.size <= self.generators.len());
+        if let Some(index) = sparse.indices().find(|&i| i >= sparse.size) {
+            return Err(PedersenError::IndexOutOfRange {
+                index,
+                size: sparse.size,
+            });
+        }
         if sparse.entries.is_empty() {
-            return G::zero();
+            return Ok(G::zero());
         }
 
         let (indices, values): (Vec<_>, Vec<_>) = sparse.entries.iter().cloned().unzip();
-        let bases: Vec<G::Affine> = indices.iter().map(|&i| self.generators[i]).collect();
-        G::msm(&bases, &values).expect("sparse MSM failed")
+        let bases: Vec<G::Affine> = indices
+            .iter()
+            .map(|&i| self.generators[from_index(i)])
+            .collect();
+        G::msm(&bases, &values).map_err(|_| PedersenError::MsmFailed)
     }
 }
 
@@ -50,8 +320,12 @@ impl<G: CurveGroup> Pedersen<G> {
 pub enum PedersenError {
     #[error("scalar/generator length mismatch: {scalars} scalars vs {generators} generators")]
     LengthMismatch { scalars: usize, generators: usize },
+    #[error("sparse vector index {index} out of range for length {size}")]
+    IndexOutOfRange { index: usize, size: usize },
     #[error("MSM computation failed")]
     MsmFailed,
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
 }
 
 #[cfg(test)]
@@ -59,7 +333,7 @@ mod tests {
     use super::*;
     use ark_bn254::{Fr, G1Projective as G1};
     use ark_std::test_rng;
-    use ark_std::Zero;
+    use ark_std::{UniformRand, Zero};
 
     #[test]
     fn test_commit_zero() {
@@ -78,7 +352,7 @@ mod tests {
 
         // Create a sparse vector
         let sparse = SparseVector::new(n, vec![(2, Fr::from(5u64)), (7, Fr::from(3u64))]);
-        let sparse_result = ped.commit_sparse(&sparse);
+        let sparse_result = ped.commit_sparse(&sparse).unwrap();
 
         // Compare with dense
         let dense = sparse.into_dense();
@@ -87,6 +361,73 @@ mod tests {
         assert_eq!(sparse_result, dense_result);
     }
 
+    #[test]
+    fn test_msm_chunked_cancelled() {
+        let mut rng = test_rng();
+        let ped = Pedersen::<G1>::rand(8, &mut rng);
+        let scalars = vec![Fr::from(1u64); 8];
+        let cancel = CancelToken::new();
+        assert_eq!(
+            msm_chunked::<G1>(&ped.generators, &scalars, &cancel).unwrap(),
+            ped.commit(&scalars).unwrap()
+        );
+        cancel.cancel();
+        assert!(matches!(
+            msm_chunked::<G1>(&ped.generators, &scalars, &cancel),
+            Err(PedersenError::Cancelled(_))
+        ));
+    }
+
+    #[test]
+    fn test_msm_on_threads() {
+        let mut rng = test_rng();
+        let ped = Pedersen::<G1>::rand(100, &mut rng);
+        let scalars: Vec<Fr> = (0..100).map(|_| Fr::rand(&mut rng)).collect();
+        let expected = ped.commit(&scalars).unwrap();
+        for threads in [1, 3, 200] {
+            let sum = msm_on_threads::<G1>(&ped.generators, &scalars, threads, &CancelToken::new());
+            assert_eq!(sum.unwrap(), expected);
+        }
+        assert_eq!(
+            msm_on_threads::<G1>(&[], &[], 2, &CancelToken::new()).unwrap(),
+            G1::zero()
+        );
+        assert!(
+            msm_on_threads::<G1>(&ped.generators, &scalars[1..], 2, &CancelToken::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_fixed_base_table() {
+        let mut rng = test_rng();
+        let ped = Pedersen::<G1>::rand(100, &mut rng);
+        let scalars: Vec<Fr> = (0..100).map(|_| Fr::rand(&mut rng)).collect();
+        let expected = ped.commit(&scalars).unwrap();
+        for window in [1, 2, 7, MAX_TABLE_WINDOW] {
+            let mut table = FixedBaseTable::<G1>::new(window);
+            table.extend(&ped.generators[..30]);
+            table.extend(&ped.generators[30..]);
+            assert_eq!(table.len(), 100);
+            for threads in [1, 3, 200] {
+                let sum = table.msm(&scalars, threads, &CancelToken::new());
+                assert_eq!(sum.unwrap(), expected);
+            }
+            assert!(table.msm(&scalars[1..], 2, &CancelToken::new()).is_err());
+        }
+
+        let empty = FixedBaseTable::<G1>::new(4);
+        assert!(empty.is_empty());
+        assert_eq!(empty.msm(&[], 2, &CancelToken::new()).unwrap(), G1::zero());
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut table = FixedBaseTable::<G1>::new(4);
+        table.extend(&ped.generators);
+        assert!(matches!(
+            table.msm(&scalars, 2, &cancel),
+            Err(PedersenError::Cancelled(_))
+        ));
+    }
+
     #[test]
     fn test_commit_length_mismatch_returns_error() {
         let mut rng = test_rng();
@@ -95,4 +436,29 @@ mod tests {
         let result = ped.commit(&scalars);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_commit_sparse_rejects_bad_indices() {
+        let mut rng = test_rng();
+        let ped = Pedersen::<G1>::rand(8, &mut rng);
+
+        let too_long = SparseVector::new(9, vec![(0, Fr::from(1u64))]);
+        assert!(matches!(
+            ped.commit_sparse(&too_long),
+            Err(PedersenError::LengthMismatch {
+                scalars: 9,
+                generators: 8
+            })
+        ));
+
+        // Built directly so the constructor's debug check is bypassed
+        let out_of_range = SparseVector {
+            size: 4,
+            entries: vec![(1, Fr::from(1u64)), (6, Fr::from(2u64))],
+        };
+        assert!(matches!(
+            ped.commit_sparse(&out_of_range),
+            Err(PedersenError::IndexOutOfRange { index: 6, size: 4 })
+        ));
+    }
 }
@@ -0,0 +1,13 @@
+/// Receives progress updates during EMSM preprocessing, e.g. to drive a progress bar.
+///
+/// `msm` names the MSM being preprocessed ("h", "l", "a", "b_g1", "b_g2" for Groth16)
+/// and `fraction` rises from 0.0 to 1.0 for each of them.
+pub trait ProgressSink: Send + Sync {
+    fn on_progress(&self, msm: &str, fraction: f64);
+}
+
+impl<F: Fn(&str, f64) + Send + Sync> ProgressSink for F {
+    fn on_progress(&self, msm: &str, fraction: f64) {
+        self(msm, fraction)
+    }
+}
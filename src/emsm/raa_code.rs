@@ -1,72 +1,237 @@
-use ark_ff::Field;
-use ark_std::rand::Rng;
+use ark_ec::CurveGroup;
+use ark_ff::{Field, UniformRand};
+use ark_std::rand::{Rng, RngCore, SeedableRng};
+use ark_std::{vec, vec::Vec};
+use rand_chacha::ChaCha20Rng;
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
-const PARALLEL_THRESHOLD: usize = 1 << 16;
+use super::parallel::ParallelThresholds;
+use super::sparse_vec::{fits_index, from_index, to_index, Index};
+
+/// How a `TOperator` represents its permutations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PermutationMode {
+    /// Random permutation tables plus their inverses: four `Index` vectors of
+    /// length N, the fastest to apply.
+    #[default]
+    Stored,
+    /// Seed-keyed Feistel permutations evaluated on the fly in O(1) memory.
+    /// Every lookup runs ChaCha20 for each Feistel round, on the order of
+    /// microseconds against nanoseconds for a table.
+    Implicit,
+}
+
+/// Fold width of the paper's parameter tables: N = 4n, rate R = 1/4.
+pub const DEFAULT_FOLD: usize = 4;
 
 /// TOperator implements the RAA (Random Accumulate and Add) code.
 /// G = F_r * M_p * A * M_q * A
-/// where A = accumulate (suffix-sum), M_p/M_q = permute, F_r = fold (4:1).
+/// where A = accumulate (suffix-sum), M_p/M_q = permute, F_r = fold (fold:1).
 ///
 /// Maps N-dimensional sparse vectors to n-dimensional dense vectors,
-/// where N = 4n (rate R = 1/4).
+/// where N = fold * n (rate R = 1/fold, 1/4 by default).
 #[derive(Clone, Debug)]
 pub struct TOperator {
     /// Permutation p of size N
-    pub perm_p: Vec<usize>,
+    pub perm_p: Permutation,
     /// Permutation q of size N
-    pub perm_q: Vec<usize>,
-    /// Inverse of perm_p
-    pub inv_perm_p: Vec<usize>,
-    /// Inverse of perm_q
-    pub inv_perm_q: Vec<usize>,
-    /// N = 4n (expanded dimension)
+    pub perm_q: Permutation,
+    /// N = fold * n (expanded dimension)
     pub big_n: usize,
     /// n (original dimension)
     pub n: usize,
+    /// Entries of N summed into each of n
+    pub fold: usize,
+    /// When `multiply_sparse` goes parallel (with the `parallel` feature).
+    pub thresholds: ParallelThresholds,
 }
 
 impl TOperator {
     /// Create a new TOperator with random permutations.
     pub fn rand<R: Rng>(n: usize, rng: &mut R) -> Self {
-        let big_n = 4 * n;
-        let perm_p = random_permutation(big_n, rng);
-        let perm_q = random_permutation(big_n, rng);
-        let inv_perm_p = inverse_permutation(&perm_p);
-        let inv_perm_q = inverse_permutation(&perm_q);
+        Self::rand_with(n, PermutationMode::Stored, rng)
+    }
+
+    /// `rand` with the permutations represented as `mode` says.
+    pub fn rand_with<R: Rng>(n: usize, mode: PermutationMode, rng: &mut R) -> Self {
+        Self::rand_with_fold(n, DEFAULT_FOLD, mode, rng)
+    }
+
+    /// `rand_with` for the code of rate 1/`fold`: N = fold * n. The parameter
+    /// tables of `params` are for `DEFAULT_FOLD`; other rates need a noise
+    /// weight of their own.
+    ///
+    /// # Panics
+    /// If `fold` is zero, or N has indices past the range of `Index`.
+    pub fn rand_with_fold<R: Rng>(
+        n: usize,
+        fold: usize,
+        mode: PermutationMode,
+        rng: &mut R,
+    ) -> Self {
+        assert!(fold > 0, "fold must be at least 1");
+        let big_n = fold * n;
+        assert!(
+            fits_index(big_n),
+            "N = {big_n} does not fit the index type; enable the wide-indices feature"
+        );
+        let perm_p = Permutation::random(big_n, mode, rng);
+        let perm_q = Permutation::random(big_n, mode, rng);
         Self {
             perm_p,
             perm_q,
-            inv_perm_p,
-            inv_perm_q,
             big_n,
             n,
+            fold,
+            thresholds: ParallelThresholds::default(),
         }
     }
 
+    /// How the permutations are represented.
+    pub fn permutation_mode(&self) -> PermutationMode {
+        self.perm_p.mode()
+    }
+
     /// Multiply a sparse vector by the TOperator: G * e.
     /// Computes F_r * M_p * A * M_q * A * e in O(N) additions.
-    pub fn multiply_sparse<F: Field>(&self, sparse_entries: &[(usize, F)]) -> Vec<F> {
-        // Start with dense representation of sparse input
-        let mut v = vec![F::zero(); self.big_n];
-        for &(i, ref val) in sparse_entries {
-            v[i] += *val;
+    pub fn multiply_sparse<F: Field>(&self, sparse_entries: &[(Index, F)]) -> Vec<F> {
+        self.multiply_sparse_with(sparse_entries, &mut EncryptScratch::default())
+    }
+
+    /// `multiply_sparse`, working in the buffers of `workspace` instead of
+    /// allocating its own. Only the returned vector of n is allocated.
+    pub fn multiply_sparse_with<F: Field>(
+        &self,
+        sparse_entries: &[(Index, F)],
+        workspace: &mut EncryptScratch<F>,
+    ) -> Vec<F> {
+        let EncryptScratch {
+            v, scratch, suffix, ..
+        } = workspace;
+        self.accumulate_permuted(sparse_entries, v, suffix);
+        // Every entry of `scratch` is overwritten, so resizing leaves whatever
+        // an earlier call put there.
+        scratch.resize(self.big_n, F::zero());
+
+        // Step 4: M_p (permute by p)
+        self.perm_p
+            .permute_into(v, scratch, self.thresholds.permute);
+
+        // Step 5: F_r (fold: sum groups of `fold` to go from N -> n)
+        apply_f_fold(scratch, self.fold, self.thresholds.fold)
+    }
+
+    /// G * e, computed a chunk at a time with `SparseProduct::chunk_into`. Only
+    /// the vector of N that steps 1 to 3 leave is built (in `workspace`); each
+    /// chunk reads the permutation by p and the fold straight out of it. Masking
+    /// a chunk at a time then holds one vector of N plus a chunk, where
+    /// `multiply_sparse` holds two vectors of N plus the n of its result.
+    pub fn multiply_sparse_lazy<'a, F: Field>(
+        &'a self,
+        sparse_entries: &[(Index, F)],
+        workspace: &'a mut EncryptScratch<F>,
+    ) -> SparseProduct<'a, F> {
+        let EncryptScratch { v, suffix, .. } = workspace;
+        self.accumulate_permuted(sparse_entries, v, suffix);
+        SparseProduct {
+            perm_p: &self.perm_p,
+            accumulated: v,
+            fold: self.fold,
+            parallel_threshold: self.thresholds.permute,
         }
+    }
 
-        // Step 1: A (accumulate / suffix-sum)
-        accumulate_inplace(&mut v);
+    /// `multiply_sparse` of k sparse vectors at once: G * e_1, ..., G * e_k.
+    /// The k vectors are laid out side by side, k entries per index of N, so
+    /// each permutation is traversed once for all of them, with one lookup (or
+    /// Feistel evaluation) per index instead of k. Steps 4 and 5 read the
+    /// accumulated rows through p, as `SparseProduct::chunk_into` does. The
+    /// buffers of `workspace` grow to k vectors of N plus k of n.
+    pub fn multiply_sparse_batch<F: Field>(
+        &self,
+        batch: &[&[(Index, F)]],
+        workspace: &mut EncryptScratch<F>,
+    ) -> Vec<Vec<F>> {
+        let k = batch.len();
+        if k == 0 {
+            return Vec::new();
+        }
+        let EncryptScratch {
+            v,
+            scratch,
+            suffixes,
+            ..
+        } = workspace;
+        if suffixes.len() < k {
+            suffixes.resize_with(k, SparseSuffixSum::default);
+        }
+        for (suffix, entries) in suffixes.iter_mut().zip(batch) {
+            suffix.fill(entries, self.big_n);
+        }
+        let suffixes = &suffixes[..k];
 
-        // Step 2: M_q (permute by q)
-        v = permute_safe(&v, &self.perm_q);
+        // Steps 1 and 2: A, then M_q, for row i of all k vectors at once
+        v.resize(self.big_n * k, F::zero());
+        let perm_q = &self.perm_q;
+        gather_rows(
+            v,
+            k,
+            |i, row| {
+                let j = perm_q.apply(i);
+                for (out, suffix) in row.iter_mut().zip(suffixes) {
+                    *out = suffix.get(j);
+                }
+            },
+            self.thresholds.permute,
+        );
 
-        // Step 3: A (accumulate again)
-        accumulate_inplace(&mut v);
+        // Step 3: A, down each of the k columns
+        accumulate_rows(v, k, &self.thresholds);
 
-        // Step 4: M_p (permute by p)
-        v = permute_safe(&v, &self.perm_p);
+        // Steps 4 and 5: M_p and F_r, into rows of n
+        let (v, perm_p, fold) = (&v[..], &self.perm_p, self.fold);
+        scratch.resize(self.n * k, F::zero());
+        gather_rows(
+            scratch,
+            k,
+            |i, row| {
+                row.fill(F::zero());
+                for l in fold * i..fold * (i + 1) {
+                    let src = perm_p.apply(l) * k;
+                    for (out, x) in row.iter_mut().zip(&v[src..src + k]) {
+                        *out += x;
+                    }
+                }
+            },
+            self.thresholds.permute / fold,
+        );
+        (0..k)
+            .map(|j| scratch.iter().skip(j).step_by(k).copied().collect())
+            .collect()
+    }
+
+    /// Steps 1 to 3 of `multiply_sparse`, into `v`: A * M_q * A * e.
+    fn accumulate_permuted<F: Field>(
+        &self,
+        sparse_entries: &[(Index, F)],
+        v: &mut Vec<F>,
+        suffix: &mut SparseSuffixSum<F>,
+    ) {
+        // Every entry of `v` is overwritten, so resizing leaves whatever an
+        // earlier call put there.
+        v.resize(self.big_n, F::zero());
 
-        // Step 5: F_r (fold: sum groups of 4 to go from N -> n)
-        apply_f_fold(&v)
+        // Steps 1 and 2: A (accumulate / suffix-sum), then M_q (permute by q).
+        // The suffix sum of t entries is constant between them, so each entry
+        // of the permuted vector is looked up among t sums instead of summing
+        // a dense vector of N first.
+        suffix.fill(sparse_entries, self.big_n);
+        self.perm_q
+            .permute_fn_into(|i| suffix.get(i), v, self.thresholds.permute);
+
+        // Step 3: A (accumulate again)
+        accumulate_inplace(v, &self.thresholds);
     }
 
     /// Apply the transpose G^T to a vector of group elements.
@@ -75,108 +240,606 @@ impl TOperator {
     pub fn multiply_transpose_group<G: ark_ec::CurveGroup>(&self, g: &[G::Affine]) -> Vec<G> {
         assert_eq!(g.len(), self.n, "input must have length n");
 
-        // F_r^T: expand n -> N by placing each element at positions [4i, 4i+1, 4i+2, 4i+3]
+        // F_r^T: expand n -> N by placing each element at positions
+        // [fold * i, fold * (i + 1))
         let mut v: Vec<G> = vec![G::zero(); self.big_n];
-        for (i, gi) in g.iter().enumerate() {
-            let gi_proj: G = (*gi).into();
-            for k in 0..4 {
-                v[4 * i + k] = gi_proj;
-            }
+        let mut scratch: Vec<G> = vec![G::zero(); self.big_n];
+        for (copies, gi) in v.chunks_exact_mut(self.fold).zip(g) {
+            copies.fill((*gi).into());
         }
 
         // M_p^T = M_{p^{-1}}: permute by inverse of p
-        v = permute_safe_group::<G>(&v, &self.inv_perm_p);
+        self.perm_p.permute_inverse_into(&v, &mut scratch, usize::MAX);
+        core::mem::swap(&mut v, &mut scratch);
 
         // A^T = prefix-sum
         prefix_sum_inplace_group::<G>(&mut v);
 
         // M_q^T = M_{q^{-1}}: permute by inverse of q
-        v = permute_safe_group::<G>(&v, &self.inv_perm_q);
+        self.perm_q.permute_inverse_into(&v, &mut scratch, usize::MAX);
+        core::mem::swap(&mut v, &mut scratch);
 
         // A^T = prefix-sum
         prefix_sum_inplace_group::<G>(&mut v);
 
         v
     }
+
+    /// Check that `multiply_transpose_group` is the transpose of
+    /// `multiply_sparse`: <G * e, g> == <e, G^T * g> for a random sparse e and
+    /// random g in `G`. Decryption subtracts <e, h> with h = G^T * g, so a
+    /// transpose that disagrees (a new code, or permutation tables restored
+    /// wrongly) leaves noise in every result and proofs fail without saying
+    /// why. Costs one `multiply_transpose_group`, n fixed-base
+    /// multiplications and an MSM of n.
+    pub fn check_transpose_consistency<G: CurveGroup, R: Rng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(), TransposeMismatch> {
+        let e: Vec<(Index, G::ScalarField)> = (0..TRANSPOSE_CHECK_WEIGHT.min(self.big_n))
+            .map(|_| {
+                let i = rng.gen_range(0..self.big_n);
+                (to_index(i), G::ScalarField::rand(rng))
+            })
+            .collect();
+        let scalars: Vec<G::ScalarField> = (0..self.n).map(|_| G::ScalarField::rand(rng)).collect();
+        let g: Vec<G::Affine> = G::generator().batch_mul(&scalars);
+
+        let ge = self.multiply_sparse(&e);
+        let h = self.multiply_transpose_group::<G>(&g);
+        let lhs = G::msm(&g, &ge).expect("G * e has length n");
+        let rhs: G = e.iter().map(|&(i, x)| h[from_index(i)] * x).sum();
+        if lhs == rhs {
+            Ok(())
+        } else {
+            Err(TransposeMismatch)
+        }
+    }
+}
+
+/// Noise entries of the random e drawn by `TOperator::check_transpose_consistency`.
+const TRANSPOSE_CHECK_WEIGHT: usize = 8;
+
+/// `TOperator::check_transpose_consistency` found the transpose to disagree
+/// with the forward map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("TOperator transpose is inconsistent: <G * e, g> != <e, G^T * g>")]
+pub struct TransposeMismatch;
+
+/// A permutation of 0..len, either tabulated or computed on demand.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Permutation {
+    /// The permutation and its inverse as lookup tables.
+    Stored {
+        forward: Vec<Index>,
+        inverse: Vec<Index>,
+    },
+    /// A keyed Feistel network; see `FeistelPermutation`.
+    Feistel(FeistelPermutation),
+}
+
+impl Permutation {
+    /// A uniformly random stored permutation, or a Feistel permutation under a
+    /// random key.
+    pub fn random<R: Rng>(len: usize, mode: PermutationMode, rng: &mut R) -> Self {
+        match mode {
+            PermutationMode::Stored => {
+                let forward = random_permutation(len, rng);
+                let inverse = inverse_permutation(&forward);
+                Permutation::Stored { forward, inverse }
+            }
+            PermutationMode::Implicit => {
+                Permutation::Feistel(FeistelPermutation::new(len, rng.gen()))
+            }
+        }
+    }
+
+    pub fn mode(&self) -> PermutationMode {
+        match self {
+            Permutation::Stored { .. } => PermutationMode::Stored,
+            Permutation::Feistel(_) => PermutationMode::Implicit,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Permutation::Stored { forward, .. } => forward.len(),
+            Permutation::Feistel(f) => f.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// pi(i)
+    pub fn apply(&self, i: usize) -> usize {
+        match self {
+            Permutation::Stored { forward, .. } => from_index(forward[i]),
+            Permutation::Feistel(f) => f.apply(i),
+        }
+    }
+
+    /// pi^-1(i)
+    pub fn invert(&self, i: usize) -> usize {
+        match self {
+            Permutation::Stored { inverse, .. } => from_index(inverse[i]),
+            Permutation::Feistel(f) => f.invert(i),
+        }
+    }
+
+    /// out[i] = v[pi(i)], written into `out`
+    pub fn permute_into<T: Copy + Send + Sync>(
+        &self,
+        v: &[T],
+        out: &mut [T],
+        parallel_threshold: usize,
+    ) {
+        match self {
+            Permutation::Stored { forward, .. } => {
+                permute_safe(v, out, |i| from_index(forward[i]), parallel_threshold)
+            }
+            Permutation::Feistel(f) => permute_safe(v, out, |i| f.apply(i), parallel_threshold),
+        }
+    }
+
+    /// out[i] = f(pi(i)): `permute_into` of the vector with entries `f(j)`,
+    /// without building it.
+    pub fn permute_fn_into<T: Copy + Send + Sync>(
+        &self,
+        f: impl Fn(usize) -> T + Sync,
+        out: &mut [T],
+        parallel_threshold: usize,
+    ) {
+        assert_eq!(self.len(), out.len());
+        match self {
+            Permutation::Stored { forward, .. } => {
+                gather(out, |i| f(from_index(forward[i])), parallel_threshold)
+            }
+            Permutation::Feistel(p) => gather(out, |i| f(p.apply(i)), parallel_threshold),
+        }
+    }
+
+    /// out[i] = v[pi^-1(i)], written into `out`
+    pub fn permute_inverse_into<T: Copy + Send + Sync>(
+        &self,
+        v: &[T],
+        out: &mut [T],
+        parallel_threshold: usize,
+    ) {
+        match self {
+            Permutation::Stored { inverse, .. } => {
+                permute_safe(v, out, |i| from_index(inverse[i]), parallel_threshold)
+            }
+            Permutation::Feistel(f) => permute_safe(v, out, |i| f.invert(i), parallel_threshold),
+        }
+    }
+}
+
+/// Number of Feistel rounds. Four already give a strong pseudorandom permutation
+/// for a pseudorandom round function (Luby-Rackoff); the rest are margin for the
+/// small domains of short codes, where the Luby-Rackoff bound is loose.
+const FEISTEL_ROUNDS: u64 = 8;
+
+/// A format-preserving permutation of 0..len: a balanced Feistel network over
+/// the smallest even-width bit domain covering `len`, restricted to 0..len by
+/// cycle walking. The domain is less than 4 * len, so a lookup takes under four
+/// network evaluations on average.
+///
+/// The round function is ChaCha20 keyed by a 32-byte seed, so the whole
+/// permutation is stored in a few dozen bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeistelPermutation {
+    len: usize,
+    half_bits: u32,
+    key: [u8; 32],
+}
+
+impl FeistelPermutation {
+    pub fn new(len: usize, seed: [u8; 32]) -> Self {
+        let bits = len.max(2).next_power_of_two().trailing_zeros();
+        let half_bits = bits.div_ceil(2);
+        Self {
+            len,
+            half_bits,
+            key: seed,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// pi(i)
+    pub fn apply(&self, i: usize) -> usize {
+        assert!(i < self.len, "index {i} out of range for permutation of {}", self.len);
+        let mut x = self.encrypt(i as u64);
+        while x >= self.len as u64 {
+            x = self.encrypt(x);
+        }
+        x as usize
+    }
+
+    /// pi^-1(i)
+    pub fn invert(&self, i: usize) -> usize {
+        assert!(i < self.len, "index {i} out of range for permutation of {}", self.len);
+        let mut x = self.decrypt(i as u64);
+        while x >= self.len as u64 {
+            x = self.decrypt(x);
+        }
+        x as usize
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.half_bits) - 1
+    }
+
+    fn encrypt(&self, x: u64) -> u64 {
+        let mask = self.mask();
+        let (mut left, mut right) = (x >> self.half_bits, x & mask);
+        for round in 0..FEISTEL_ROUNDS {
+            (left, right) = (right, left ^ (self.round_function(round, right) & mask));
+        }
+        (left << self.half_bits) | right
+    }
+
+    fn decrypt(&self, x: u64) -> u64 {
+        let mask = self.mask();
+        let (mut left, mut right) = (x >> self.half_bits, x & mask);
+        for round in (0..FEISTEL_ROUNDS).rev() {
+            (left, right) = (right ^ (self.round_function(round, left) & mask), left);
+        }
+        (left << self.half_bits) | right
+    }
+
+    /// The first keystream word of ChaCha20 under the key, on the stream
+    /// numbered by the round and the half-block: a PRF of both. Half-blocks are
+    /// at most 32 bits wide, so the two never collide.
+    fn round_function(&self, round: u64, half: u64) -> u64 {
+        let mut rng = ChaCha20Rng::from_seed(self.key);
+        rng.set_stream((round << 32) | half);
+        rng.next_u64()
+    }
+}
+
+/// Bytes of a parallel accumulate chunk, small enough to stay in a core's L2
+/// between the two passes over it.
+#[cfg(feature = "parallel")]
+const ACCUMULATE_CHUNK_BYTES: usize = 1 << 19;
+
+/// Length of the chunks a parallel accumulate of `n` elements of `elem_bytes`
+/// each is split into: one chunk per thread, shrunk to fit
+/// `ACCUMULATE_CHUNK_BYTES` but never below `min_chunk`.
+#[cfg(feature = "parallel")]
+fn accumulate_chunk_len(n: usize, elem_bytes: usize, threads: usize, min_chunk: usize) -> usize {
+    let cached = (ACCUMULATE_CHUNK_BYTES / elem_bytes.max(1)).max(1);
+    n.div_ceil(threads.max(1)).min(cached).max(min_chunk.max(1))
 }
 
 /// Compute suffix-sum in-place: v[i] = sum(v[i..N])
-fn accumulate_inplace<F: Field>(v: &mut [F]) {
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn accumulate_inplace<F: Field>(v: &mut [F], thresholds: &ParallelThresholds) {
     let n = v.len();
     if n <= 1 {
         return;
     }
 
-    if n >= PARALLEL_THRESHOLD {
-        // Parallel: chunk-wise suffix sums then fix up
-        let num_chunks = rayon::current_num_threads().min(n / 1024).max(1);
-        let chunk_size = n.div_ceil(num_chunks);
+    #[cfg(feature = "parallel")]
+    let threads = rayon::current_num_threads();
+    #[cfg(feature = "parallel")]
+    let chunk_len =
+        accumulate_chunk_len(n, core::mem::size_of::<F>(), threads, thresholds.min_chunk);
+    // A single chunk is the sequential sum, which adds every element once
+    // instead of twice
+    #[cfg(feature = "parallel")]
+    if n >= thresholds.accumulate && threads > 1 && chunk_len < n {
+        // Blocked scan, from the end of `v`, a round of one chunk per thread
+        // at a time: each thread takes the suffix sums of its chunk, then adds
+        // the sum of everything after the chunk while it is still in cache
+        let mut carry = F::zero();
+        for block in v.rchunks_mut(chunk_len * threads) {
+            let chunk_sums: Vec<F> = block
+                .par_chunks_mut(chunk_len)
+                .map(|chunk| {
+                    let mut sum = F::zero();
+                    for elem in chunk.iter_mut().rev() {
+                        sum += *elem;
+                        *elem = sum;
+                    }
+                    sum
+                })
+                .collect();
+            let mut carries = vec![F::zero(); chunk_sums.len()];
+            for (c, sum) in carries.iter_mut().zip(&chunk_sums).rev() {
+                *c = carry;
+                carry += sum;
+            }
+            block
+                .par_chunks_mut(chunk_len)
+                .zip(carries)
+                .for_each(|(chunk, c)| {
+                    if !c.is_zero() {
+                        chunk.iter_mut().for_each(|elem| *elem += c);
+                    }
+                });
+        }
+        return;
+    }
+
+    // Sequential suffix-sum
+    let mut sum = F::zero();
+    for i in (0..n).rev() {
+        sum += v[i];
+        v[i] = sum;
+    }
+}
 
-        // Phase 1: local suffix sums within each chunk
-        let chunk_sums: Vec<F> = v
-            .par_chunks_mut(chunk_size)
-            .map(|chunk| {
-                let mut sum = F::zero();
-                for elem in chunk.iter_mut().rev() {
-                    sum += *elem;
-                    *elem = sum;
-                }
-                sum
-            })
-            .collect();
+/// `accumulate_inplace` down each column of `v`, a matrix of rows of `k`
+/// entries: v[i][j] = sum of v[i'][j] over i' >= i.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn accumulate_rows<F: Field>(v: &mut [F], k: usize, thresholds: &ParallelThresholds) {
+    let rows = v.len() / k;
+    if rows <= 1 {
+        return;
+    }
 
-        // Phase 2: compute suffix sums of chunk totals
-        let mut corrections = vec![F::zero(); num_chunks];
-        let mut running = F::zero();
-        for i in (0..chunk_sums.len()).rev() {
-            if i + 1 < chunk_sums.len() {
-                corrections[i] = running;
+    #[cfg(feature = "parallel")]
+    let threads = rayon::current_num_threads();
+    #[cfg(feature = "parallel")]
+    let chunk_rows = accumulate_chunk_len(
+        rows,
+        k * core::mem::size_of::<F>(),
+        threads,
+        thresholds.min_chunk,
+    );
+    // The blocked scan of `accumulate_inplace`, carrying a row of k sums
+    #[cfg(feature = "parallel")]
+    if rows >= thresholds.accumulate && threads > 1 && chunk_rows < rows {
+        let mut carry = vec![F::zero(); k];
+        for block in v.rchunks_mut(chunk_rows * threads * k) {
+            let chunk_sums: Vec<Vec<F>> = block
+                .par_chunks_mut(chunk_rows * k)
+                .map(|chunk| {
+                    let mut sums = vec![F::zero(); k];
+                    suffix_sum_rows(chunk, &mut sums);
+                    sums
+                })
+                .collect();
+            let mut carries = vec![Vec::new(); chunk_sums.len()];
+            for (c, sums) in carries.iter_mut().zip(&chunk_sums).rev() {
+                c.clone_from(&carry);
+                for (total, sum) in carry.iter_mut().zip(sums) {
+                    *total += sum;
+                }
             }
-            running += chunk_sums[i];
+            block
+                .par_chunks_mut(chunk_rows * k)
+                .zip(carries)
+                .for_each(|(chunk, c)| {
+                    for row in chunk.chunks_exact_mut(k) {
+                        for (elem, c) in row.iter_mut().zip(&c) {
+                            *elem += c;
+                        }
+                    }
+                });
         }
-        // corrections[0] should be sum of chunk_sums[1..], etc.
-        // Recalculate properly
-        let mut suffix = F::zero();
-        for i in (0..num_chunks).rev() {
-            corrections[i] = suffix;
-            suffix += chunk_sums[i];
+        return;
+    }
+
+    suffix_sum_rows(v, &mut vec![F::zero(); k]);
+}
+
+/// Suffix sums down the columns of `v`, rows of `sums.len()` entries, each
+/// column starting from its entry of `sums`, which ends at the column's total.
+fn suffix_sum_rows<F: Field>(v: &mut [F], sums: &mut [F]) {
+    for row in v.rchunks_exact_mut(sums.len()) {
+        for (elem, sum) in row.iter_mut().zip(sums.iter_mut()) {
+            *sum += *elem;
+            *elem = *sum;
         }
+    }
+}
+
+/// Apply permutation into a caller-provided buffer: out[i] = v[perm(i)]
+fn permute_safe<T: Copy + Send + Sync>(
+    v: &[T],
+    out: &mut [T],
+    perm: impl Fn(usize) -> usize + Sync,
+    parallel_threshold: usize,
+) {
+    assert_eq!(v.len(), out.len());
+    gather(out, |i| v[perm(i)], parallel_threshold)
+}
 
-        // Phase 3: add corrections to each chunk
-        v.par_chunks_mut(chunk_size)
+/// `row(i, out[i * k..(i + 1) * k])` for each row of `k` entries of `out`.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn gather_rows<T: Send>(
+    out: &mut [T],
+    k: usize,
+    row: impl Fn(usize, &mut [T]) + Sync,
+    parallel_threshold: usize,
+) {
+    #[cfg(feature = "parallel")]
+    if out.len() / k >= parallel_threshold {
+        out.par_chunks_exact_mut(k)
             .enumerate()
-            .for_each(|(idx, chunk)| {
-                let c = corrections[idx];
-                if !c.is_zero() {
-                    for elem in chunk.iter_mut() {
-                        *elem += c;
-                    }
-                }
-            });
-    } else {
-        // Sequential suffix-sum
-        let mut sum = F::zero();
-        for i in (0..n).rev() {
-            sum += v[i];
-            v[i] = sum;
+            .for_each(|(i, r)| row(i, r));
+        return;
+    }
+    for (i, r) in out.chunks_exact_mut(k).enumerate() {
+        row(i, r);
+    }
+}
+
+/// out[i] = value(i)
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn gather<T: Copy + Send + Sync>(
+    out: &mut [T],
+    value: impl Fn(usize) -> T + Sync,
+    parallel_threshold: usize,
+) {
+    #[cfg(feature = "parallel")]
+    if out.len() >= parallel_threshold {
+        out.par_iter_mut()
+            .enumerate()
+            .for_each(|(i, o)| *o = value(i));
+        return;
+    }
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = value(i);
+    }
+}
+
+/// Buffers for `TOperator::multiply_sparse_with`, kept between calls so that
+/// masking one witness after another (e.g. the five MSMs of a proof, or proof
+/// after proof) reuses two vectors of N instead of allocating them each time.
+/// The buffers grow to the largest N masked with them (k times that for
+/// `multiply_sparse_batch`) and are freed on drop.
+#[derive(Debug)]
+pub struct EncryptScratch<F> {
+    v: Vec<F>,
+    scratch: Vec<F>,
+    suffix: SparseSuffixSum<F>,
+    /// One per vector of a `multiply_sparse_batch`.
+    suffixes: Vec<SparseSuffixSum<F>>,
+}
+
+impl<F> Default for EncryptScratch<F> {
+    fn default() -> Self {
+        Self {
+            v: Vec::new(),
+            scratch: Vec::new(),
+            suffix: SparseSuffixSum::default(),
+            suffixes: Vec::new(),
+        }
+    }
+}
+
+impl<F> EncryptScratch<F> {
+    /// Bytes held by the buffers.
+    pub fn capacity_bytes(&self) -> usize {
+        (self.v.capacity() + self.scratch.capacity()) * core::mem::size_of::<F>()
+            + self.suffix.capacity_bytes()
+            + self
+                .suffixes
+                .iter()
+                .map(SparseSuffixSum::capacity_bytes)
+                .sum::<usize>()
+    }
+}
+
+/// G * e as returned by `TOperator::multiply_sparse_lazy`: the accumulated
+/// vector before the permutation by p and the fold.
+#[derive(Debug)]
+pub struct SparseProduct<'a, F> {
+    perm_p: &'a Permutation,
+    accumulated: &'a [F],
+    fold: usize,
+    parallel_threshold: usize,
+}
+
+impl<F: Field> SparseProduct<'_, F> {
+    /// n, the length of G * e.
+    pub fn len(&self) -> usize {
+        self.accumulated.len() / self.fold
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// out = (G * e)[start..start + out.len()]: steps 4 and 5 of
+    /// `multiply_sparse` for those entries only.
+    pub fn chunk_into(&self, start: usize, out: &mut [F]) {
+        assert!(
+            start + out.len() <= self.len(),
+            "chunk {start}..{} out of range for length {}",
+            start + out.len(),
+            self.len()
+        );
+        let (v, perm_p, fold) = (self.accumulated, self.perm_p, self.fold);
+        // The threshold counts entries of N read, `fold` per entry written
+        gather(
+            out,
+            |i| {
+                let j = fold * (start + i);
+                (j..j + fold).map(|k| v[perm_p.apply(k)]).sum()
+            },
+            self.parallel_threshold / fold,
+        );
+    }
+}
+
+/// The suffix sums of a sparse vector, v[j] = sum of its entries at indices
+/// >= j, kept as one sum per entry: v is constant between entries.
+#[derive(Debug)]
+struct SparseSuffixSum<F> {
+    /// The entries, sorted by index.
+    sorted: Vec<(Index, F)>,
+    /// Entry indices, ascending and distinct.
+    indices: Vec<Index>,
+    /// `sums[k]` = v[j] for every j in (indices[k - 1], indices[k]].
+    sums: Vec<F>,
+}
+
+impl<F> Default for SparseSuffixSum<F> {
+    fn default() -> Self {
+        Self {
+            sorted: Vec::new(),
+            indices: Vec::new(),
+            sums: Vec::new(),
         }
     }
 }
 
-/// Apply permutation: out[i] = v[perm[i]]
-fn permute_safe<F: Clone + Send + Sync>(v: &[F], perm: &[usize]) -> Vec<F> {
-    assert_eq!(v.len(), perm.len());
-    if v.len() >= PARALLEL_THRESHOLD {
-        perm.par_iter().map(|&p| v[p].clone()).collect()
-    } else {
-        perm.iter().map(|&p| v[p].clone()).collect()
+impl<F> SparseSuffixSum<F> {
+    fn capacity_bytes(&self) -> usize {
+        self.sorted.capacity() * core::mem::size_of::<(Index, F)>()
+            + self.indices.capacity() * core::mem::size_of::<Index>()
+            + self.sums.capacity() * core::mem::size_of::<F>()
     }
 }
 
-/// Permute group elements: out[i] = v[perm[i]]
-fn permute_safe_group<G: ark_ec::CurveGroup>(v: &[G], perm: &[usize]) -> Vec<G> {
-    assert_eq!(v.len(), perm.len());
-    perm.iter().map(|&p| v[p]).collect()
+impl<F: Field> SparseSuffixSum<F> {
+    /// Replace the sums with those of `entries`. Entries at equal indices add
+    /// up, as in a dense vector.
+    ///
+    /// # Panics
+    /// If an index is not below `len`.
+    fn fill(&mut self, entries: &[(Index, F)], len: usize) {
+        let Self {
+            sorted,
+            indices,
+            sums,
+        } = self;
+        sorted.clear();
+        sorted.extend_from_slice(entries);
+        sorted.sort_unstable_by_key(|&(i, _)| i);
+        indices.clear();
+        sums.clear();
+        for &(i, value) in sorted.iter() {
+            assert!(from_index(i) < len, "index {i} out of range for length {len}");
+            if indices.last() == Some(&i) {
+                *sums.last_mut().expect("one sum per index") += value;
+            } else {
+                indices.push(i);
+                sums.push(value);
+            }
+        }
+        for k in (1..sums.len()).rev() {
+            let later = sums[k];
+            sums[k - 1] += later;
+        }
+    }
+
+    fn get(&self, j: usize) -> F {
+        let k = self.indices.partition_point(|&i| from_index(i) < j);
+        self.sums.get(k).copied().unwrap_or_else(F::zero)
+    }
 }
 
 /// Prefix-sum in-place on group elements: v[i] = sum(v[0..=i])
@@ -186,34 +849,30 @@ fn prefix_sum_inplace_group<G: ark_ec::CurveGroup>(v: &mut [G]) {
     }
 }
 
-/// Fold: sum groups of 4 to reduce from N=4n to n.
-fn apply_f_fold<F: Field>(v: &[F]) -> Vec<F> {
-    assert!(v.len().is_multiple_of(4));
-    let n = v.len() / 4;
-    if n >= PARALLEL_THRESHOLD / 4 {
-        (0..n)
-            .into_par_iter()
-            .map(|i| v[4 * i] + v[4 * i + 1] + v[4 * i + 2] + v[4 * i + 3])
-            .collect()
-    } else {
-        (0..n)
-            .map(|i| v[4 * i] + v[4 * i + 1] + v[4 * i + 2] + v[4 * i + 3])
-            .collect()
+/// Fold: sum groups of `fold` to reduce from N = fold * n to n.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn apply_f_fold<F: Field>(v: &[F], fold: usize, parallel_threshold: usize) -> Vec<F> {
+    assert!(v.len().is_multiple_of(fold));
+    #[cfg(feature = "parallel")]
+    if v.len() >= parallel_threshold {
+        return v.par_chunks_exact(fold).map(|c| c.iter().sum()).collect();
     }
+    v.chunks_exact(fold).map(|c| c.iter().sum()).collect()
 }
 
 /// Compute inverse of a permutation.
-pub fn inverse_permutation(perm: &[usize]) -> Vec<usize> {
+pub fn inverse_permutation(perm: &[Index]) -> Vec<Index> {
     let mut inv = vec![0; perm.len()];
     for (i, &p) in perm.iter().enumerate() {
-        inv[p] = i;
+        inv[from_index(p)] = to_index(i);
     }
     inv
 }
 
-/// Generate a random permutation using Fisher-Yates.
-fn random_permutation<R: Rng>(n: usize, rng: &mut R) -> Vec<usize> {
-    let mut perm: Vec<usize> = (0..n).collect();
+/// Generate a random permutation using Fisher-Yates. The swaps draw `usize`
+/// positions, so a seed gives the same permutation whatever `Index` is.
+fn random_permutation<R: Rng>(n: usize, rng: &mut R) -> Vec<Index> {
+    let mut perm: Vec<Index> = (0..n).map(to_index).collect();
     for i in (1..n).rev() {
         let j = rng.gen_range(0..=i);
         perm.swap(i, j);
@@ -227,17 +886,57 @@ mod tests {
     use ark_bn254::Fr;
     use ark_ff::Zero;
     use ark_std::test_rng;
+    use ark_std::UniformRand;
 
     #[test]
     fn test_suffix_sum() {
         let mut v = vec![Fr::from(1u64), Fr::from(2u64), Fr::from(3u64), Fr::from(4u64)];
-        accumulate_inplace(&mut v);
+        accumulate_inplace(&mut v, &ParallelThresholds::default());
         assert_eq!(v[0], Fr::from(10u64)); // 1+2+3+4
         assert_eq!(v[1], Fr::from(9u64));  // 2+3+4
         assert_eq!(v[2], Fr::from(7u64));  // 3+4
         assert_eq!(v[3], Fr::from(4u64));  // 4
     }
 
+    #[test]
+    fn test_parallel_suffix_sum_matches_sequential() {
+        let mut rng = test_rng();
+        // Long enough for several rounds of cache-sized chunks
+        let v: Vec<Fr> = (0..(1 << 17) + 5).map(|_| Fr::rand(&mut rng)).collect();
+        let mut sequential = v.clone();
+        accumulate_inplace(&mut sequential, &ParallelThresholds::SEQUENTIAL);
+        let mut parallel = v;
+        let eager = ParallelThresholds {
+            min_chunk: 16,
+            ..ParallelThresholds::uniform(1)
+        };
+        // Several threads, so the blocked scan runs even on a single core
+        #[cfg(feature = "parallel")]
+        crate::emsm::parallel::ParallelConfig::with_threads(4)
+            .unwrap()
+            .install(|| accumulate_inplace(&mut parallel, &eager));
+        #[cfg(not(feature = "parallel"))]
+        accumulate_inplace(&mut parallel, &eager);
+        assert_eq!(sequential, parallel);
+
+        let mut t_op = TOperator::rand(1000, &mut rng);
+        let sparse: Vec<(Index, Fr)> = (0..20).map(|i| (i * 150, Fr::rand(&mut rng))).collect();
+        t_op.thresholds = ParallelThresholds::SEQUENTIAL;
+        let sequential = t_op.multiply_sparse(&sparse);
+        t_op.thresholds = eager;
+        assert_eq!(t_op.multiply_sparse(&sparse), sequential);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_accumulate_chunk_len() {
+        // 32-byte elements: 2^14 per chunk that fits the cache
+        assert_eq!(accumulate_chunk_len(1 << 20, 32, 8, 1 << 10), 1 << 14);
+        assert_eq!(accumulate_chunk_len(1 << 14, 32, 8, 1 << 10), 1 << 11);
+        assert_eq!(accumulate_chunk_len(1 << 14, 32, 64, 1 << 10), 1 << 10);
+        assert_eq!(accumulate_chunk_len(100, 32, 1, 1 << 10), 1 << 10);
+    }
+
     #[test]
     fn test_permutation_inverse() {
         let perm = vec![2, 0, 3, 1];
@@ -247,7 +946,7 @@ mod tests {
 
         // Composing perm and inv should give identity
         for i in 0..4 {
-            assert_eq!(inv[perm[i]], i);
+            assert_eq!(from_index(inv[from_index(perm[i])]), i);
         }
     }
 
@@ -257,10 +956,12 @@ mod tests {
             Fr::from(1u64), Fr::from(2u64), Fr::from(3u64), Fr::from(4u64),
             Fr::from(5u64), Fr::from(6u64), Fr::from(7u64), Fr::from(8u64),
         ];
-        let folded = apply_f_fold(&v);
+        let folded = apply_f_fold(&v, 4, ParallelThresholds::default().fold);
         assert_eq!(folded.len(), 2);
         assert_eq!(folded[0], Fr::from(10u64)); // 1+2+3+4
         assert_eq!(folded[1], Fr::from(26u64)); // 5+6+7+8
+        let folded = apply_f_fold(&v, 8, usize::MAX);
+        assert_eq!(folded, [Fr::from(36u64)]);
     }
 
     #[test]
@@ -270,7 +971,7 @@ mod tests {
         let t_op = TOperator::rand(n, &mut rng);
 
         // Create a sparse vector with a single entry
-        let sparse = vec![(10usize, Fr::from(5u64))];
+        let sparse = vec![(10, Fr::from(5u64))];
         let result = t_op.multiply_sparse::<Fr>(&sparse);
         assert_eq!(result.len(), n);
 
@@ -279,6 +980,135 @@ mod tests {
         assert!(is_nonzero, "TOperator output should be nonzero for nonzero input");
     }
 
+    #[test]
+    fn test_multiply_sparse_matches_dense() {
+        let mut rng = test_rng();
+        for mode in [PermutationMode::Stored, PermutationMode::Implicit] {
+            let t_op = TOperator::rand_with(50, mode, &mut rng);
+            let big_n = 4 * 50;
+            // Both ends, and an index twice
+            let sparse = vec![
+                (to_index(big_n - 1), Fr::rand(&mut rng)),
+                (0, Fr::rand(&mut rng)),
+                (77, Fr::rand(&mut rng)),
+                (77, Fr::rand(&mut rng)),
+                (120, Fr::rand(&mut rng)),
+            ];
+
+            let mut v = vec![Fr::zero(); big_n];
+            for &(i, x) in &sparse {
+                v[from_index(i)] += x;
+            }
+            let thresholds = ParallelThresholds::SEQUENTIAL;
+            let mut scratch = vec![Fr::zero(); big_n];
+            accumulate_inplace(&mut v, &thresholds);
+            t_op.perm_q.permute_into(&v, &mut scratch, usize::MAX);
+            accumulate_inplace(&mut scratch, &thresholds);
+            t_op.perm_p.permute_into(&scratch, &mut v, usize::MAX);
+            assert_eq!(
+                t_op.multiply_sparse(&sparse),
+                apply_f_fold(&v, 4, usize::MAX)
+            );
+            assert_eq!(t_op.multiply_sparse::<Fr>(&[]), vec![Fr::zero(); 50]);
+        }
+    }
+
+    #[test]
+    fn test_multiply_sparse_lazy() {
+        let mut rng = test_rng();
+        for mode in [PermutationMode::Stored, PermutationMode::Implicit] {
+            let t_op = TOperator::rand_with(50, mode, &mut rng);
+            let sparse: Vec<(Index, Fr)> = (0..8)
+                .map(|_| (to_index(rng.gen_range(0..t_op.big_n)), Fr::rand(&mut rng)))
+                .collect();
+            let expected = t_op.multiply_sparse(&sparse);
+            let mut workspace = EncryptScratch::default();
+            let product = t_op.multiply_sparse_lazy(&sparse, &mut workspace);
+            assert_eq!(product.len(), 50);
+            // Uneven chunks, the last one short
+            let mut chunks = Vec::new();
+            for start in (0..50).step_by(16) {
+                let mut chunk = vec![Fr::zero(); 16.min(50 - start)];
+                product.chunk_into(start, &mut chunk);
+                chunks.extend(chunk);
+            }
+            assert_eq!(chunks, expected);
+        }
+    }
+
+    #[test]
+    fn test_multiply_sparse_with_reused_scratch() {
+        let mut rng = test_rng();
+        let small = TOperator::rand(16, &mut rng);
+        let large = TOperator::rand(40, &mut rng);
+        let mut workspace = EncryptScratch::<Fr>::default();
+        // Shrinking and growing between operators leaves no stale entries
+        for t_op in [&large, &small, &large, &small] {
+            let sparse: Vec<(Index, Fr)> = (0..6)
+                .map(|_| (to_index(rng.gen_range(0..t_op.big_n)), Fr::rand(&mut rng)))
+                .collect();
+            assert_eq!(
+                t_op.multiply_sparse_with(&sparse, &mut workspace),
+                t_op.multiply_sparse(&sparse)
+            );
+        }
+        assert!(workspace.capacity_bytes() >= 2 * 160 * core::mem::size_of::<Fr>());
+    }
+
+    #[test]
+    fn test_multiply_sparse_batch() {
+        let mut rng = test_rng();
+        let mut workspace = EncryptScratch::<Fr>::default();
+        for mode in [PermutationMode::Stored, PermutationMode::Implicit] {
+            let t_op = TOperator::rand_with(40, mode, &mut rng);
+            for k in [1, 3, 2] {
+                let batch: Vec<Vec<(Index, Fr)>> = (0..k)
+                    .map(|_| {
+                        (0..6)
+                            .map(|_| (to_index(rng.gen_range(0..t_op.big_n)), Fr::rand(&mut rng)))
+                            .collect()
+                    })
+                    .collect();
+                let entries: Vec<&[(Index, Fr)]> = batch.iter().map(Vec::as_slice).collect();
+                let expected: Vec<Vec<Fr>> =
+                    batch.iter().map(|e| t_op.multiply_sparse(e)).collect();
+                assert_eq!(
+                    t_op.multiply_sparse_batch(&entries, &mut workspace),
+                    expected
+                );
+            }
+            assert!(t_op.multiply_sparse_batch(&[], &mut workspace).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parallel_accumulate_rows_matches_sequential() {
+        let mut rng = test_rng();
+        let v: Vec<Fr> = (0..3 * 1000 + 3).map(|_| Fr::rand(&mut rng)).collect();
+        let mut sequential = v.clone();
+        accumulate_rows(&mut sequential, 3, &ParallelThresholds::SEQUENTIAL);
+        let mut parallel = v.clone();
+        let eager = ParallelThresholds {
+            min_chunk: 16,
+            ..ParallelThresholds::uniform(1)
+        };
+        #[cfg(feature = "parallel")]
+        crate::emsm::parallel::ParallelConfig::with_threads(4)
+            .unwrap()
+            .install(|| accumulate_rows(&mut parallel, 3, &eager));
+        #[cfg(not(feature = "parallel"))]
+        accumulate_rows(&mut parallel, 3, &eager);
+        assert_eq!(parallel, sequential);
+
+        // Each column is the suffix sum of its own
+        for j in 0..3 {
+            let mut column: Vec<Fr> = v.iter().skip(j).step_by(3).copied().collect();
+            accumulate_inplace(&mut column, &ParallelThresholds::SEQUENTIAL);
+            let accumulated: Vec<Fr> = sequential.iter().skip(j).step_by(3).copied().collect();
+            assert_eq!(accumulated, column);
+        }
+    }
+
     #[test]
     fn test_toperator_linearity() {
         let mut rng = test_rng();
@@ -286,9 +1116,9 @@ mod tests {
         let t_op = TOperator::rand(n, &mut rng);
 
         // T(e1 + e2) == T(e1) + T(e2)
-        let e1 = vec![(5usize, Fr::from(3u64))];
-        let e2 = vec![(20usize, Fr::from(7u64))];
-        let e_combined = vec![(5usize, Fr::from(3u64)), (20usize, Fr::from(7u64))];
+        let e1 = vec![(5, Fr::from(3u64))];
+        let e2 = vec![(20, Fr::from(7u64))];
+        let e_combined = vec![(5, Fr::from(3u64)), (20, Fr::from(7u64))];
 
         let r1 = t_op.multiply_sparse::<Fr>(&e1);
         let r2 = t_op.multiply_sparse::<Fr>(&e2);
@@ -298,4 +1128,118 @@ mod tests {
             assert_eq!(r1[i] + r2[i], r_combined[i], "linearity failed at index {i}");
         }
     }
+
+    #[test]
+    fn test_feistel_permutation_is_a_bijection() {
+        for len in [1, 2, 3, 100, 1 << 10, (1 << 11) + 5] {
+            let perm = FeistelPermutation::new(len, [len as u8; 32]);
+            let mut seen = vec![false; len];
+            for i in 0..len {
+                let p = perm.apply(i);
+                assert!(!seen[p], "{p} hit twice for len {len}");
+                seen[p] = true;
+                assert_eq!(perm.invert(p), i);
+            }
+        }
+
+        // Different seeds, different permutations
+        let a = FeistelPermutation::new(1000, [1; 32]);
+        let b = FeistelPermutation::new(1000, [2; 32]);
+        assert!((0..1000).any(|i| a.apply(i) != b.apply(i)));
+    }
+
+    #[test]
+    fn test_implicit_toperator_transpose() {
+        use ark_bn254::G1Projective as G1;
+        use ark_ec::{CurveGroup, VariableBaseMSM};
+
+        // <G * e, g> == <e, G^T * g> ties multiply_sparse to multiply_transpose_group
+        let mut rng = test_rng();
+        let n = 40;
+        let t_op = TOperator::rand_with(n, PermutationMode::Implicit, &mut rng);
+        assert_eq!(t_op.permutation_mode(), PermutationMode::Implicit);
+
+        let g: Vec<_> = (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let e = vec![(3, Fr::rand(&mut rng)), (97, Fr::rand(&mut rng))];
+        let ge = t_op.multiply_sparse::<Fr>(&e);
+        let h = t_op.multiply_transpose_group::<G1>(&g);
+
+        let lhs = G1::msm(&g, &ge).unwrap();
+        let rhs: G1 = e.iter().map(|&(i, x)| h[from_index(i)] * x).sum();
+        assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn test_check_transpose_consistency() {
+        use ark_bn254::{G1Projective as G1, G2Projective as G2};
+
+        let mut rng = test_rng();
+        for mode in [PermutationMode::Stored, PermutationMode::Implicit] {
+            for fold in [2, DEFAULT_FOLD] {
+                let t_op = TOperator::rand_with_fold(30, fold, mode, &mut rng);
+                assert_eq!(t_op.check_transpose_consistency::<G1, _>(&mut rng), Ok(()));
+                assert_eq!(t_op.check_transpose_consistency::<G2, _>(&mut rng), Ok(()));
+            }
+        }
+
+        // An inverse table that no longer inverts p, as after a bad restore
+        let mut t_op = TOperator::rand(30, &mut rng);
+        let Permutation::Stored { inverse, .. } = &mut t_op.perm_p else {
+            unreachable!("rand stores its permutations")
+        };
+        inverse.swap(0, 1);
+        assert_eq!(
+            t_op.check_transpose_consistency::<G1, _>(&mut rng),
+            Err(TransposeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_toperator_fold() {
+        use ark_bn254::G1Projective as G1;
+        use ark_ec::{CurveGroup, VariableBaseMSM};
+
+        let mut rng = test_rng();
+        let n = 24;
+        for fold in [1, 2, 8] {
+            let t_op = TOperator::rand_with_fold(n, fold, PermutationMode::Stored, &mut rng);
+            assert_eq!(t_op.big_n, fold * n);
+            let e = vec![
+                (0, Fr::rand(&mut rng)),
+                (to_index(fold * n - 1), Fr::rand(&mut rng)),
+            ];
+            let ge = t_op.multiply_sparse::<Fr>(&e);
+            assert_eq!(ge.len(), n);
+
+            let mut workspace = EncryptScratch::default();
+            let product = t_op.multiply_sparse_lazy(&e, &mut workspace);
+            let mut lazy = vec![Fr::zero(); n];
+            product.chunk_into(0, &mut lazy);
+            assert_eq!(lazy, ge);
+
+            // The transpose expands by the same fold
+            let g: Vec<_> = (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+            let h = t_op.multiply_transpose_group::<G1>(&g);
+            assert_eq!(h.len(), fold * n);
+            let rhs: G1 = e.iter().map(|&(i, x)| h[from_index(i)] * x).sum();
+            assert_eq!(G1::msm(&g, &ge).unwrap(), rhs);
+        }
+    }
+
+    #[test]
+    fn test_permute_into_reuses_buffer() {
+        let mut rng = test_rng();
+        for mode in [PermutationMode::Stored, PermutationMode::Implicit] {
+            let perm = Permutation::random(1000, mode, &mut rng);
+            let v: Vec<u64> = (0..1000).collect();
+            let mut out = vec![0u64; 1000];
+            let mut back = vec![0u64; 1000];
+            for threshold in [1, usize::MAX] {
+                perm.permute_into(&v, &mut out, threshold);
+                assert!((0..1000).all(|i| out[i] == perm.apply(i) as u64));
+                perm.permute_inverse_into(&out, &mut back, threshold);
+                assert_eq!(back, v);
+            }
+        }
+    }
 }
@@ -0,0 +1,234 @@
+use super::params::{LpnParams, SecurityLevel};
+
+/// Estimated cost, in bits, of the best known generic attacks on a dual-LPN
+/// instance: recovering or distinguishing r = T * e for a t-sparse e of length N,
+/// given the n-dimensional r.
+///
+/// These are textbook models for a random code over a large field; they ignore
+/// the regular structure of the noise and of the RAA code, so treat the result
+/// as a sanity check rather than a proof.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SecurityEstimate {
+    /// Prange information set decoding, including the decoding-one-out-of-many
+    /// speedup across queries: log2(C(N, t) / C(n, t)) + log2(n^2) - log2(queries).
+    pub isd_bits: f64,
+    /// Statistical decoding with dual codewords of weight N - n + 1 (the MDS
+    /// bound): bias (1 - t/N)^w, needing about bias^-2 samples.
+    pub statistical_bits: f64,
+}
+
+impl SecurityEstimate {
+    /// The cheaper of the two attacks.
+    pub fn bits(&self) -> f64 {
+        self.isd_bits.min(self.statistical_bits)
+    }
+}
+
+/// Estimate the security of dual LPN with syndrome length `n`, rate `rate` = n / N,
+/// noise weight `t`, and `queries` masks drawn against the same code.
+///
+/// Once t >= n the syndrome is statistically close to uniform and both estimates
+/// are infinite.
+pub fn estimate_security(n: usize, rate: f64, t: usize, queries: u64) -> SecurityEstimate {
+    assert!(
+        rate > 0.0 && rate <= 1.0,
+        "rate must be in (0, 1], got {rate}"
+    );
+    let big_n = (n as f64 / rate).round() as usize;
+    if t >= n || t >= big_n {
+        return SecurityEstimate {
+            isd_bits: f64::INFINITY,
+            statistical_bits: f64::INFINITY,
+        };
+    }
+
+    let isd_bits = log2_binomial(big_n, t) - log2_binomial(n, t) + 2.0 * (n as f64).log2()
+        - (queries.max(1) as f64).log2();
+
+    let dual_weight = (big_n - n + 1) as f64;
+    let statistical_bits = -2.0 * dual_weight * (1.0 - t as f64 / big_n as f64).log2();
+
+    SecurityEstimate {
+        isd_bits: isd_bits.max(0.0),
+        statistical_bits,
+    }
+}
+
+/// The lowest `estimate_security` of a parameter table row: noise weight `t`
+/// over the code of rate 1/`fold` for the lengths 2^`below` < n <= 2^k it
+/// covers, `below` being the previous row's log2 n, or every length up to 2^k
+/// for the table's first row. The statistical estimate falls and the ISD
+/// estimate rises with n, so the ends of a row bound it; the first row is also
+/// estimated at every power of two below. Setup clamps t to N, and so does this.
+pub fn row_security(below: Option<u32>, k: u32, t: usize, fold: usize) -> f64 {
+    let lengths: Vec<usize> = match below {
+        None => (0..=k).map(|j| 1 << j).collect(),
+        Some(below) => vec![(1 << below) + 1, 1 << k],
+    };
+    lengths
+        .into_iter()
+        .map(|n| estimate_security(n, 1.0 / fold as f64, t.min(fold * n), 1).bits())
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// log2 of the binomial coefficient C(a, b), for b <= a.
+fn log2_binomial(a: usize, b: usize) -> f64 {
+    (0..b)
+        .map(|i| ((a - i) as f64 / (i + 1) as f64).log2())
+        .sum()
+}
+
+/// What setup does when the estimated security of an MSM's LPN parameters falls
+/// short of the requested `SecurityLevel`.
+#[derive(Clone, Copy, Debug)]
+pub struct SecurityCheck {
+    /// Number of proofs expected per key, each drawing fresh noise.
+    pub queries: u64,
+    /// Fail setup instead of logging a warning.
+    pub strict: bool,
+}
+
+impl Default for SecurityCheck {
+    fn default() -> Self {
+        Self {
+            queries: 1,
+            strict: false,
+        }
+    }
+}
+
+/// The LPN parameters for an MSM fall short of the requested security level.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error(
+    "{msm} MSM: estimated LPN security {estimated:.1} bits is below the requested \
+     {requested} bits (n = {n}, t = {t})"
+)]
+pub struct InsufficientSecurity {
+    pub msm: String,
+    pub n: usize,
+    pub t: usize,
+    pub estimated: f64,
+    pub requested: u32,
+}
+
+impl SecurityCheck {
+    /// Estimate the security of the LPN parameters `params` looked up for the
+    /// MSM named `msm`, at their rate n / N, warning (or failing, if strict)
+    /// when it is below `security`.
+    pub fn check(
+        &self,
+        msm: &str,
+        params: &LpnParams,
+        security: SecurityLevel,
+    ) -> Result<SecurityEstimate, InsufficientSecurity> {
+        let rate = params.n as f64 / params.big_n.max(1) as f64;
+        self.check_params(msm, params.n, rate, params.t, security)
+    }
+
+    /// `check` for parameters chosen by hand: noise weight `t` over a code of
+    /// rate `rate` = n / N.
+    pub fn check_params(
+        &self,
+        msm: &str,
+        n: usize,
+        rate: f64,
+        t: usize,
+        security: SecurityLevel,
+    ) -> Result<SecurityEstimate, InsufficientSecurity> {
+        let estimate = estimate_security(n, rate, t, self.queries);
+        if estimate.bits() < security.bits() as f64 {
+            let shortfall = InsufficientSecurity {
+                msm: msm.to_string(),
+                n,
+                t,
+                estimated: estimate.bits(),
+                requested: security.bits(),
+            };
+            if self.strict {
+                return Err(shortfall);
+            }
+            tracing::warn!("{shortfall}");
+        }
+        Ok(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emsm::params::{get_lpn_params_for, LpnParamSet};
+
+    #[test]
+    fn test_estimate_security() {
+        // Prange at rate 1/4 costs about 4^t decoding attempts
+        let e = estimate_security(1 << 16, 0.25, 60, 1);
+        assert!((e.isd_bits - (120.0 + 32.0)).abs() < 1.0, "{e:?}");
+        assert!(e.statistical_bits > 100.0, "{e:?}");
+
+        // More noise is harder, more queries are easier
+        assert!(estimate_security(1 << 16, 0.25, 80, 1).bits() > e.bits());
+        assert!(estimate_security(1 << 16, 0.25, 60, 1 << 20).isd_bits < e.isd_bits);
+
+        // Clamped noise on tiny vectors covers the whole syndrome
+        assert_eq!(estimate_security(4, 0.25, 16, 1).bits(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_builtin_tables_meet_their_levels_from_2_14() {
+        // Below 2^14 these generic models are more pessimistic than the paper
+        for security in [
+            SecurityLevel::Bits80,
+            SecurityLevel::Bits100,
+            SecurityLevel::Bits128,
+        ] {
+            let rows = LpnParamSet::builtin()
+                .tables
+                .into_iter()
+                .find(|table| table.security_bits == security.bits())
+                .unwrap()
+                .rows;
+            let level = security.bits() as f64;
+            for (i, &(k, t)) in rows.iter().enumerate().filter(|(_, &(k, _))| k > 14) {
+                let bits = row_security(Some(rows[i - 1].0), k, t, 4);
+                assert!(bits >= level, "{security:?} row 2^{k}: {bits:.1} bits");
+            }
+            let (k, t) = rows[4];
+            assert_eq!(k, 14);
+            assert!(estimate_security(1 << k, 0.25, t, 1).bits() >= level);
+        }
+    }
+
+    #[test]
+    fn test_security_check() {
+        let lenient = SecurityCheck::default();
+        let strict = SecurityCheck {
+            strict: true,
+            ..lenient
+        };
+        let security = SecurityLevel::Bits128;
+        let table = |n| get_lpn_params_for(n, security);
+        // Tiny MSMs are statistically hidden
+        assert!(strict.check("a", &table(2), security).is_ok());
+
+        // Below 2^14 the estimate is under the table's level, which only warns
+        assert!(strict.check("h", &table(1 << 10), security).is_err());
+        assert!(lenient.check("h", &table(1 << 10), security).is_ok());
+        assert!(strict.check("h", &table(1 << 16), security).is_ok());
+        // The estimate is at the parameters' rate, not at 1/4
+        let params = LpnParams {
+            n: 1 << 10,
+            big_n: 8 << 10,
+            t: 38,
+        };
+        let estimate = lenient.check("h", &params, security).unwrap();
+        assert_eq!(estimate, estimate_security(1 << 10, 0.125, 38, 1));
+
+        let err = strict
+            .check_params("h", 1 << 10, 0.25, 38, SecurityLevel::Bits128)
+            .unwrap_err();
+        assert_eq!((err.msm.as_str(), err.t, err.requested), ("h", 38, 128));
+        assert!(lenient
+            .check_params("h", 1 << 10, 0.25, 38, SecurityLevel::Bits128)
+            .is_ok());
+    }
+}
@@ -0,0 +1,201 @@
+use ark_ec::CurveGroup;
+use ark_std::rand::{CryptoRng, Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::emsm::{encrypt_with_noise, EmsmPublicParams};
+use super::sparse_vec::SparseVector;
+
+/// Domain of the seed commitment; bumped whenever the seed-to-noise derivation
+/// changes.
+const SEED_DOMAIN: &[u8] = b"stealthsnark-noise-seed-v1";
+
+/// A 32-byte seed the masking noise of one witness is drawn from. The client
+/// publishes `commitment` before masking and keeps the seed; revealing it later
+/// lets anyone re-derive the noise and the mask and check the transcript
+/// (`audit`). Until then the commitment hides the seed, as long as the seed
+/// itself is secret and uniformly random.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NoiseSeed([u8; 32]);
+
+impl NoiseSeed {
+    pub fn new(seed: [u8; 32]) -> Self {
+        Self(seed)
+    }
+
+    pub fn random<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        Self(rng.gen())
+    }
+
+    /// The seed for a client nonce: SHA-256 of the domain and the nonce. The
+    /// noise is only as secret as the nonce, which must be high-entropy and
+    /// kept until the dispute.
+    pub fn from_nonce(nonce: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(SEED_DOMAIN);
+        hasher.update(b"nonce");
+        hasher.update(nonce);
+        Self(hasher.finalize().into())
+    }
+
+    /// The seed of the `index`th witness masked under this one, e.g. one per
+    /// MSM of a proof. Revealing a derived seed reveals nothing of the others.
+    pub fn derive(&self, index: u64) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(SEED_DOMAIN);
+        hasher.update(b"derive");
+        hasher.update(self.0);
+        hasher.update(index.to_le_bytes());
+        Self(hasher.finalize().into())
+    }
+
+    /// SHA-256 of the domain and the seed, published before masking.
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(SEED_DOMAIN);
+        hasher.update(b"commit");
+        hasher.update(self.0);
+        hasher.finalize().into()
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// The noise `encrypt_seeded` masks with under `params`.
+    pub fn noise<G: CurveGroup>(
+        &self,
+        params: &EmsmPublicParams<G>,
+    ) -> SparseVector<G::ScalarField> {
+        let mut rng = ChaCha20Rng::from_seed(self.0);
+        params
+            .noise
+            .sample(params.t_operator.big_n, params.t, &mut rng)
+    }
+}
+
+/// The seed is secret until revealed, so it is not printed.
+impl core::fmt::Debug for NoiseSeed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("NoiseSeed(..)")
+    }
+}
+
+/// `encrypt` with the noise drawn from `seed`. Each seed must mask only one
+/// witness; `NoiseSeed::derive` gives one per witness.
+pub fn encrypt_seeded<G: CurveGroup>(
+    params: &EmsmPublicParams<G>,
+    witness: &[G::ScalarField],
+    seed: &NoiseSeed,
+) -> (Vec<G::ScalarField>, SparseVector<G::ScalarField>) {
+    encrypt_with_noise(params, witness, seed.noise(params))
+}
+
+/// Why `audit` rejected a transcript.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum AuditError {
+    #[error("the revealed seed does not open the commitment")]
+    SeedMismatch,
+    #[error("masked vector of length {actual}, expected {expected}")]
+    LengthMismatch { expected: usize, actual: usize },
+}
+
+/// What a revealed seed shows about one delegated MSM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeededAudit<G: CurveGroup> {
+    /// The witness the client masked: the masked vector minus the re-derived
+    /// mask.
+    pub witness: Vec<G::ScalarField>,
+    /// The MSM of the masked vector, which the server should have answered.
+    pub expected_result: G,
+    /// Whether the server's answer was `expected_result`.
+    pub server_correct: bool,
+}
+
+/// Check the transcript of a seeded masking once its seed is revealed: that
+/// `seed` opens `commitment`, which witness `masked` hides, and whether the
+/// server answered its MSM with `server_result`. Takes a full MSM over the
+/// generators of `params`.
+pub fn audit<G: CurveGroup>(
+    params: &EmsmPublicParams<G>,
+    commitment: &[u8; 32],
+    seed: &NoiseSeed,
+    masked: &[G::ScalarField],
+    server_result: G,
+) -> Result<SeededAudit<G>, AuditError> {
+    if seed.commitment() != *commitment {
+        return Err(AuditError::SeedMismatch);
+    }
+    let n = params.t_operator.n;
+    if masked.len() != n {
+        return Err(AuditError::LengthMismatch {
+            expected: n,
+            actual: masked.len(),
+        });
+    }
+    let mask = params
+        .t_operator
+        .multiply_sparse(&seed.noise(params).entries);
+    let witness = masked.iter().zip(&mask).map(|(v, r)| *v - r).collect();
+    let expected_result = G::msm_unchecked(&params.generators, masked);
+    Ok(SeededAudit {
+        witness,
+        expected_result,
+        server_correct: server_result == expected_result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emsm::emsm::decrypt;
+    use ark_bn254::{Fr, G1Projective as G1};
+    use ark_ec::VariableBaseMSM;
+    use crate::rng::test_rng;
+    use ark_std::UniformRand;
+
+    #[test]
+    fn test_seeded_transcript_audit() {
+        let mut rng = test_rng();
+        let n = 32;
+        let generators = (0..n).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let params = EmsmPublicParams::<G1>::new(generators, &mut rng);
+        let witness: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+
+        let seed = NoiseSeed::random(&mut rng).derive(2);
+        let commitment = seed.commitment();
+        let (masked, noise) = encrypt_seeded(&params, &witness, &seed);
+        assert_eq!(noise.entries, seed.noise(&params).entries);
+        let server_result = params.server_computation(&masked).unwrap();
+        let expected = G1::msm_unchecked(&params.generators, &witness);
+        assert_eq!(
+            decrypt(server_result, &noise, &params.preprocess()).unwrap(),
+            expected
+        );
+
+        let report = audit(&params, &commitment, &seed, &masked, server_result).unwrap();
+        assert_eq!(report.witness, witness);
+        assert!(report.server_correct);
+        let wrong = server_result + G1::rand(&mut rng);
+        let report = audit(&params, &commitment, &seed, &masked, wrong).unwrap();
+        assert!(!report.server_correct);
+
+        // Another seed does not open the commitment
+        assert_eq!(
+            audit(
+                &params,
+                &commitment,
+                &seed.derive(0),
+                &masked,
+                server_result
+            ),
+            Err(AuditError::SeedMismatch)
+        );
+        assert_eq!(
+            NoiseSeed::from_nonce(b"nonce"),
+            NoiseSeed::from_nonce(b"nonce")
+        );
+        assert_ne!(seed, seed.derive(0));
+    }
+}
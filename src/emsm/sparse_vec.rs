@@ -1,16 +1,50 @@
 use ark_ff::Field;
+use ark_std::collections::BTreeSet;
 use ark_std::rand::Rng;
+use ark_std::{vec, vec::Vec};
+
+/// How sparse-vector entries and `Permutation` tables store indices: u32, half
+/// the memory of `usize` on 64-bit targets, which covers every N of the LPN
+/// tables (at most 2^30). The `wide-indices` feature stores `usize` instead,
+/// for codes past 2^32.
+#[cfg(not(feature = "wide-indices"))]
+pub type Index = u32;
+#[cfg(feature = "wide-indices")]
+pub type Index = usize;
+
+// The casts below are no-ops with `wide-indices`
+
+/// Whether every index below `len` fits an `Index`.
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn fits_index(len: usize) -> bool {
+    len.saturating_sub(1) <= Index::MAX as usize
+}
+
+/// `i` as an `Index`. Callers keep indices below the length of a code, which
+/// `TOperator` checks `fits_index`.
+#[inline]
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn to_index(i: usize) -> Index {
+    debug_assert!(fits_index(i + 1), "index {i} does not fit an Index");
+    i as Index
+}
+
+#[inline]
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn from_index(i: Index) -> usize {
+    i as usize
+}
 
 /// Sparse vector: stores (index, value) pairs over a field F.
 #[derive(Clone, Debug)]
 pub struct SparseVector<F: Field> {
     pub size: usize,
-    pub entries: Vec<(usize, F)>,
+    pub entries: Vec<(Index, F)>,
 }
 
 impl<F: Field> SparseVector<F> {
-    pub fn new(size: usize, entries: Vec<(usize, F)>) -> Self {
-        debug_assert!(entries.iter().all(|(i, _)| *i < size));
+    pub fn new(size: usize, entries: Vec<(Index, F)>) -> Self {
+        debug_assert!(entries.iter().all(|&(i, _)| from_index(i) < size));
         Self { size, entries }
     }
 
@@ -18,11 +52,61 @@ impl<F: Field> SparseVector<F> {
     pub fn into_dense(&self) -> Vec<F> {
         let mut dense = vec![F::zero(); self.size];
         for &(i, ref v) in &self.entries {
-            dense[i] += *v;
+            dense[from_index(i)] += *v;
         }
         dense
     }
 
+    /// Number of stored entries. Counts duplicate indices and explicit zeros
+    /// unless the vector has been through `deduplicate`.
+    pub fn nnz(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The stored (index, value) pairs, in storage order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, F)> + '_ {
+        self.entries.iter().map(|&(i, v)| (from_index(i), v))
+    }
+
+    /// The stored indices, in storage order.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().map(|&(i, _)| from_index(i))
+    }
+
+    /// Sort entries by index, sum the values of repeated indices and drop zeros.
+    /// The dense vector is unchanged.
+    pub fn deduplicate(mut self) -> Self {
+        self.entries.sort_unstable_by_key(|&(i, _)| i);
+        let mut merged: Vec<(Index, F)> = Vec::with_capacity(self.entries.len());
+        for (i, v) in self.entries {
+            match merged.last_mut() {
+                Some((last, sum)) if *last == i => *sum += v,
+                _ => merged.push((i, v)),
+            }
+        }
+        merged.retain(|(_, v)| !v.is_zero());
+        Self {
+            size: self.size,
+            entries: merged,
+        }
+    }
+
+    /// self + other, deduplicated.
+    pub fn add(&self, other: &Self) -> Self {
+        assert_eq!(self.size, other.size, "sparse vectors differ in length");
+        let entries = self.entries.iter().chain(&other.entries).copied().collect();
+        Self::new(self.size, entries).deduplicate()
+    }
+
+    /// c * self. Scaling by zero leaves an empty vector.
+    pub fn scale(&self, c: F) -> Self {
+        if c.is_zero() {
+            return Self::new(self.size, Vec::new());
+        }
+        let entries = self.entries.iter().map(|&(i, v)| (i, v * c)).collect();
+        Self::new(self.size, entries)
+    }
+
     /// Generate a sparse error vector for LPN.
     /// Splits [0, size) into size/t chunks, picks one random index per chunk
     /// with a random nonzero field element.
@@ -38,13 +122,57 @@ impl<F: Field> SparseVector<F> {
             let base = chunk_idx * chunk_size;
             let offset = rng.gen_range(0..chunk_size);
             let val = F::rand(rng);
-            entries.push((base + offset, val));
+            entries.push((to_index(base + offset), val));
+        }
+
+        Self { size, entries }
+    }
+
+    /// Generate an error vector of exact weight t, uniform over all supports:
+    /// t distinct indices in [0, size) (Floyd's algorithm), each with a random
+    /// field element.
+    pub fn exact_weight_vec<R: Rng>(size: usize, t: usize, rng: &mut R) -> Self {
+        assert!(size >= t, "need size >= t, got size={size}, t={t}");
+        let mut support = BTreeSet::new();
+        for j in size - t..size {
+            let i = rng.gen_range(0..=j);
+            if !support.insert(i) {
+                support.insert(j);
+            }
         }
+        let entries = support
+            .into_iter()
+            .map(|i| (to_index(i), F::rand(rng)))
+            .collect();
+        Self { size, entries }
+    }
 
+    /// Generate a Bernoulli error vector: each index is nonzero independently with
+    /// probability t / size, so the weight is t only in expectation.
+    pub fn bernoulli_vec<R: Rng>(size: usize, t: usize, rng: &mut R) -> Self {
+        if size == 0 {
+            return Self { size, entries: Vec::new() };
+        }
+        let rate = (t as f64 / size as f64).min(1.0);
+        let mut entries = Vec::new();
+        for i in 0..size {
+            if rng.gen_bool(rate) {
+                entries.push((to_index(i), F::rand(rng)));
+            }
+        }
         Self { size, entries }
     }
 }
 
+impl<F: Field> IntoIterator for SparseVector<F> {
+    type Item = (Index, F);
+    type IntoIter = vec::IntoIter<(Index, F)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,8 +201,55 @@ mod tests {
 
         // Each entry should be in its own chunk
         let chunk_size = size / t;
-        for (i, &(idx, _)) in ev.entries.iter().enumerate() {
+        for (i, idx) in ev.indices().enumerate() {
             assert!(idx >= i * chunk_size && idx < (i + 1) * chunk_size);
         }
     }
+
+    #[test]
+    fn test_exact_weight_and_bernoulli_vecs() {
+        let mut rng = test_rng();
+        let ev = SparseVector::<Fr>::exact_weight_vec(64, 64, &mut rng);
+        let indices: Vec<usize> = ev.indices().collect();
+        assert_eq!(indices, (0..64).collect::<Vec<_>>());
+
+        let ev = SparseVector::<Fr>::exact_weight_vec(4096, 29, &mut rng);
+        assert_eq!(ev.entries.len(), 29);
+        assert!(ev.entries.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let ev = SparseVector::<Fr>::bernoulli_vec(1 << 16, 64, &mut rng);
+        assert!((16..256).contains(&ev.entries.len()));
+        let empty = SparseVector::<Fr>::bernoulli_vec(16, 0, &mut rng);
+        assert!(empty.entries.is_empty());
+    }
+
+    #[test]
+    fn test_sparse_arithmetic() {
+        let a = SparseVector::<Fr>::new(
+            8,
+            vec![(5, Fr::from(2u64)), (1, Fr::from(3u64)), (5, Fr::from(4u64))],
+        );
+        let b = SparseVector::<Fr>::new(8, vec![(1, -Fr::from(3u64)), (7, Fr::from(1u64))]);
+
+        let dedup = a.clone().deduplicate();
+        assert_eq!(dedup.entries, vec![(1, Fr::from(3u64)), (5, Fr::from(6u64))]);
+        assert_eq!(dedup.into_dense(), a.into_dense());
+
+        // Cancelling entries disappear
+        let sum = a.add(&b);
+        assert_eq!(sum.entries, vec![(5, Fr::from(6u64)), (7, Fr::from(1u64))]);
+        let dense_sum: Vec<Fr> = a
+            .into_dense()
+            .iter()
+            .zip(b.into_dense())
+            .map(|(x, y)| *x + y)
+            .collect();
+        assert_eq!(sum.into_dense(), dense_sum);
+
+        let scaled = b.scale(Fr::from(2u64));
+        assert_eq!(scaled.indices().collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(scaled.iter().nth(1), Some((7, Fr::from(2u64))));
+        assert_eq!(b.scale(Fr::zero()).nnz(), 0);
+        assert_eq!(b.into_iter().count(), 2);
+    }
 }
@@ -0,0 +1,179 @@
+//! The error of the crate's public API. Each variant stands for one way a
+//! proof can fail, so callers can tell a dimension mismatch from a circuit that
+//! does not synthesize, from a server that turned the request away, without
+//! matching on messages. Errors of a single stage keep their own types
+//! (`SetupError`, `MaliciousError`, `ServerError`, ...) and convert into
+//! `StealthSnarkError` with `?`.
+
+use ark_relations::r1cs::SynthesisError;
+use ark_serialize::SerializationError;
+
+use crate::emsm::cancel::Cancelled;
+use crate::emsm::malicious::MaliciousError;
+use crate::emsm::params::InvalidLpnTable;
+use crate::emsm::pedersen::PedersenError;
+use crate::groth16::fingerprint::KeyMismatch;
+use crate::groth16::server_aided::{InvalidResponse, SetupError};
+use crate::protocol::attestation::AttestationError;
+use crate::protocol::client::{CommitmentMismatch, DeadlineExceeded, ServerError};
+use crate::protocol::messages::InvalidPoint;
+use crate::protocol::policy::PolicyViolation;
+
+/// `Result` with `StealthSnarkError` as the default error.
+pub type Result<T, E = StealthSnarkError> = core::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StealthSnarkError {
+    /// Vectors, keys or responses of lengths that do not fit together.
+    #[error(transparent)]
+    DimensionMismatch(#[from] DimensionMismatch),
+    /// The circuit failed to synthesize, or its QAP domain is too large.
+    #[error("constraint synthesis failed: {0}")]
+    Synthesis(#[from] SynthesisError),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    Setup(#[from] SetupError),
+    /// A local MSM failed other than by its lengths or a cancellation.
+    #[error(transparent)]
+    Msm(PedersenError),
+    /// A malicious-secure response failed its consistency check.
+    #[error(transparent)]
+    Malicious(#[from] MaliciousError),
+    /// Bytes that do not decode: a wire message, a scalar or point vector, or
+    /// a serialized key.
+    #[error("{0}")]
+    Malformed(String),
+    /// Points that decode but are not on the curve or not in its subgroup.
+    #[error(transparent)]
+    InvalidPoint(#[from] InvalidPoint),
+    /// MSM results the client refused to unmask.
+    #[error(transparent)]
+    InvalidResponse(#[from] InvalidResponse),
+    /// The request did not get an answer: connection, TLS or timeout.
+    #[error(transparent)]
+    Transport(#[from] reqwest::Error),
+    /// The server answered with an error status. Boxed, as its problem
+    /// details would make every `Result` of the crate large.
+    #[error(transparent)]
+    Server(Box<ServerError>),
+    /// The server aborted the request at its deadline or timeout.
+    #[error(transparent)]
+    DeadlineExceeded(DeadlineExceeded),
+    #[error(transparent)]
+    Policy(#[from] PolicyViolation),
+    #[error(transparent)]
+    Attestation(#[from] AttestationError),
+    /// A key is not the one its counterpart was made for.
+    #[error(transparent)]
+    KeyMismatch(#[from] KeyMismatch),
+    /// Boxed like `Server`: it carries both commitments.
+    #[error(transparent)]
+    CommitmentMismatch(Box<CommitmentMismatch>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A task on tokio's blocking pool was cancelled by the runtime.
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+    /// The call cannot be made as configured, e.g. proving before the
+    /// generators hash is known, or a circom circuit that does not load.
+    #[error("{0}")]
+    InvalidArgument(String),
+}
+
+/// A vector of `actual` elements where `expected` were needed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{what}: expected {expected}, got {actual}")]
+pub struct DimensionMismatch {
+    pub what: &'static str,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl StealthSnarkError {
+    /// The server's answer, if it turned the request away.
+    pub fn server_error(&self) -> Option<&ServerError> {
+        match self {
+            Self::Server(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ServerError> for StealthSnarkError {
+    fn from(e: ServerError) -> Self {
+        match DeadlineExceeded::from_server_error(&e) {
+            Some(exceeded) => Self::DeadlineExceeded(exceeded),
+            None => Self::Server(Box::new(e)),
+        }
+    }
+}
+
+impl From<CommitmentMismatch> for StealthSnarkError {
+    fn from(e: CommitmentMismatch) -> Self {
+        Self::CommitmentMismatch(Box::new(e))
+    }
+}
+
+impl From<PedersenError> for StealthSnarkError {
+    fn from(e: PedersenError) -> Self {
+        match e {
+            PedersenError::Cancelled(e) => Self::Cancelled(e),
+            PedersenError::LengthMismatch {
+                scalars,
+                generators,
+            } => Self::DimensionMismatch(DimensionMismatch {
+                what: "MSM scalars",
+                expected: generators,
+                actual: scalars,
+            }),
+            e => Self::Msm(e),
+        }
+    }
+}
+
+impl From<InvalidLpnTable> for StealthSnarkError {
+    fn from(e: InvalidLpnTable) -> Self {
+        Self::InvalidArgument(e.to_string())
+    }
+}
+
+impl From<SerializationError> for StealthSnarkError {
+    fn from(e: SerializationError) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for StealthSnarkError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StealthSnarkError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msm_errors_keep_their_kind() {
+        let err = StealthSnarkError::from(PedersenError::LengthMismatch {
+            scalars: 3,
+            generators: 4,
+        });
+        let StealthSnarkError::DimensionMismatch(mismatch) = &err else {
+            panic!("expected a dimension mismatch, got {err}");
+        };
+        assert_eq!((mismatch.expected, mismatch.actual), (4, 3));
+        assert_eq!(err.to_string(), "MSM scalars: expected 4, got 3");
+
+        let err = StealthSnarkError::from(PedersenError::Cancelled(Cancelled));
+        assert!(matches!(err, StealthSnarkError::Cancelled(_)));
+        assert!(err.server_error().is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ark_bn254::{Bn254, Fr};
+use ark_groth16::{Groth16, Proof, VerifyingKey};
+use ark_relations::r1cs::SynthesisError;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_snark::SNARK;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::messages::ProveMetadata;
+use crate::serialization::{ark_serde, ark_serde_vec, ark_to_bytes};
+
+/// SHA-256 of the compressed verifying key, identifying the circuit a proof is for.
+pub fn vk_hash(vk: &VerifyingKey<Bn254>) -> [u8; 32] {
+    Sha256::digest(ark_to_bytes(vk)).into()
+}
+
+/// How a proof was produced.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Serialize, Deserialize, CanonicalSerialize, CanonicalDeserialize,
+)]
+pub struct ProverMetadata {
+    /// `stealthsnark` version that assembled the proof.
+    pub prover_version: String,
+    /// Unix time the proof was assembled, in milliseconds.
+    pub created_at_ms: u64,
+    /// Server accounting when the MSMs were delegated; `None` for a local proof.
+    pub server: Option<ProveMetadata>,
+}
+
+impl ProverMetadata {
+    /// Metadata for a proof computed entirely on this machine.
+    pub fn local() -> Self {
+        Self {
+            prover_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            server: None,
+        }
+    }
+
+    /// Metadata for a proof whose MSMs the server evaluated, as it reported them
+    /// in `ProveResponse::metadata`.
+    pub fn delegated(server: ProveMetadata) -> Self {
+        Self {
+            server: Some(server),
+            ..Self::local()
+        }
+    }
+}
+
+/// A proof with everything a verifier needs besides the verifying key.
+#[derive(
+    Clone, Debug, PartialEq, Serialize, Deserialize, CanonicalSerialize, CanonicalDeserialize,
+)]
+pub struct ProofWithPublicInputs {
+    #[serde(with = "ark_serde")]
+    pub proof: Proof<Bn254>,
+    #[serde(with = "ark_serde_vec")]
+    pub public_inputs: Vec<Fr>,
+    /// `vk_hash` of the key the proof verifies under.
+    pub vk_hash: [u8; 32],
+    pub metadata: ProverMetadata,
+}
+
+/// Errors checking a `ProofWithPublicInputs`.
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("proof was made for a different verifying key")]
+    VkMismatch,
+    #[error("verification failed: {0}")]
+    Verification(#[from] SynthesisError),
+}
+
+impl ProofWithPublicInputs {
+    pub fn new(
+        proof: Proof<Bn254>,
+        public_inputs: Vec<Fr>,
+        vk: &VerifyingKey<Bn254>,
+        metadata: ProverMetadata,
+    ) -> Self {
+        Self {
+            proof,
+            public_inputs,
+            vk_hash: vk_hash(vk),
+            metadata,
+        }
+    }
+
+    /// Verify the proof under `vk`, which must be the key it was bundled for.
+    pub fn verify(&self, vk: &VerifyingKey<Bn254>) -> Result<bool, BundleError> {
+        if vk_hash(vk) != self.vk_hash {
+            return Err(BundleError::VkMismatch);
+        }
+        Ok(Groth16::<Bn254>::verify(
+            vk,
+            &self.public_inputs,
+            &self.proof,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::CubeCircuit;
+    use crate::protocol::messages::RequestId;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_bundle_roundtrip_and_verify() {
+        let mut rng = ChaCha20Rng::seed_from_u64(11);
+        let setup = |rng: &mut ChaCha20Rng| {
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, rng).unwrap()
+        };
+        let (pk, vk) = setup(&mut rng);
+        let circuit = CubeCircuit {
+            x: Some(Fr::from(3u64)),
+        };
+        let proof = Groth16::<Bn254>::prove(&pk, circuit, &mut rng).unwrap();
+        let metadata = ProverMetadata::delegated(ProveMetadata {
+            compute_units: 12,
+            server_ms: 3,
+            request_id: RequestId([9; 16]),
+        });
+        let bundle = ProofWithPublicInputs::new(proof, vec![Fr::from(35u64)], &vk, metadata);
+
+        let json: ProofWithPublicInputs =
+            serde_json::from_str(&serde_json::to_string(&bundle).unwrap()).unwrap();
+        assert_eq!(json, bundle);
+        let bin: ProofWithPublicInputs =
+            bincode::deserialize(&bincode::serialize(&bundle).unwrap()).unwrap();
+        assert_eq!(bin, bundle);
+        let canonical = ark_to_bytes(&bundle);
+        assert_eq!(
+            ProofWithPublicInputs::deserialize_compressed(&canonical[..]).unwrap(),
+            bundle
+        );
+
+        assert!(bundle.verify(&vk).unwrap());
+        let (_, other_vk) = setup(&mut rng);
+        assert!(matches!(
+            bundle.verify(&other_vk),
+            Err(BundleError::VkMismatch)
+        ));
+    }
+}
@@ -1,25 +1,45 @@
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
 
 use ark_bn254::{Bn254, Fr};
-use ark_circom::{CircomBuilder, CircomCircuit, CircomConfig, CircomReduction};
+use ark_circom::{read_zkey, CircomBuilder, CircomCircuit, CircomConfig, CircomReduction};
 use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
-use ark_snark::SNARK;
 use ark_std::rand::{CryptoRng, Rng};
 use num_bigint::BigInt;
 
+use crate::error::{Result, StealthSnarkError};
+
 /// Run Groth16 trusted setup for a Circom circuit using `CircomReduction`.
+/// Takes an `Rng + CryptoRng`, like the crate's other setups (see `crate::rng`).
 pub fn circom_setup<R: Rng + CryptoRng>(
     wasm: impl AsRef<Path>,
     r1cs: impl AsRef<Path>,
     rng: &mut R,
-) -> anyhow::Result<(ProvingKey<Bn254>, VerifyingKey<Bn254>)> {
-    let cfg = CircomConfig::<Fr>::new(wasm, r1cs).map_err(|e| anyhow::anyhow!("{e}"))?;
+) -> Result<(ProvingKey<Bn254>, VerifyingKey<Bn254>)> {
+    let cfg = CircomConfig::<Fr>::new(wasm, r1cs)
+        .map_err(|e| StealthSnarkError::InvalidArgument(e.to_string()))?;
     let builder = CircomBuilder::new(cfg);
     let setup_circuit = builder.setup();
-    let (pk, vk) = Groth16::<Bn254, CircomReduction>::circuit_specific_setup(setup_circuit, rng)?;
+    let pk = Groth16::<Bn254, CircomReduction>::generate_random_parameters_with_reduction(
+        setup_circuit,
+        rng,
+    )?;
+    let vk = pk.vk.clone();
     Ok((pk, vk))
 }
 
+/// Load a proving key from a snarkjs `.zkey`, such as the ones published with
+/// production circuits. The verifying key is `pk.vk`.
+///
+/// snarkjs keys use the Circom QAP reduction: their H query has one point per
+/// domain element, which `ServerAidedProvingKey::setup` detects.
+pub fn load_zkey(zkey: impl AsRef<Path>) -> Result<ProvingKey<Bn254>> {
+    let mut reader = BufReader::new(File::open(zkey)?);
+    let (pk, _matrices) = read_zkey(&mut reader)?;
+    Ok(pk)
+}
+
 /// Build a Circom circuit with witness from the given inputs.
 ///
 /// Each input is `(name, value)`. For array inputs, push multiple times with
@@ -28,13 +48,16 @@ pub fn build_circuit(
     wasm: impl AsRef<Path>,
     r1cs: impl AsRef<Path>,
     inputs: &[(&str, BigInt)],
-) -> anyhow::Result<CircomCircuit<Fr>> {
-    let cfg = CircomConfig::<Fr>::new(wasm, r1cs).map_err(|e| anyhow::anyhow!("{e}"))?;
+) -> Result<CircomCircuit<Fr>> {
+    let cfg = CircomConfig::<Fr>::new(wasm, r1cs)
+        .map_err(|e| StealthSnarkError::InvalidArgument(e.to_string()))?;
     let mut builder = CircomBuilder::new(cfg);
     for (name, val) in inputs {
         builder.push_input(*name, val.clone());
     }
-    let circuit = builder.build().map_err(|e| anyhow::anyhow!("{e}"))?;
+    let circuit = builder
+        .build()
+        .map_err(|e| StealthSnarkError::InvalidArgument(e.to_string()))?;
     Ok(circuit)
 }
 
@@ -48,6 +71,7 @@ mod tests {
     use super::*;
     use crate::groth16::server_aided::*;
     use ark_circom::CircomReduction;
+    use ark_snark::SNARK;
     use rand::SeedableRng;
     use rand_chacha::ChaCha20Rng;
 
@@ -92,10 +116,10 @@ mod tests {
 
         // Encrypt → server evaluate → decrypt
         let (request, state) =
-            client_encrypt::<CircomReduction, _, _>(&sapk, circuit, &mut rng)
+            client_encrypt(&sapk, circuit, &mut rng)
                 .expect("encrypt failed");
         let response = server_evaluate(&sapk, &request).expect("server evaluate failed");
-        let proof = client_decrypt(&sapk, &response, &state);
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
 
         // Verify
         let valid = Groth16::<Bn254, CircomReduction>::verify(&vk, &public_inputs, &proof)
@@ -128,10 +152,10 @@ mod tests {
 
         // Encrypt → server evaluate → decrypt
         let (request, state) =
-            client_encrypt::<CircomReduction, _, _>(&sapk, circuit, &mut rng)
+            client_encrypt(&sapk, circuit, &mut rng)
                 .expect("encrypt failed");
         let response = server_evaluate(&sapk, &request).expect("server evaluate failed");
-        let proof = client_decrypt(&sapk, &response, &state);
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
 
         // Verify
         let valid = Groth16::<Bn254, CircomReduction>::verify(&vk, &public_inputs, &proof)
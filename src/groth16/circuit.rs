@@ -52,6 +52,44 @@ impl<F: PrimeField> ConstraintSynthesizer<F> for CubeCircuit<F> {
     }
 }
 
+/// Synthetic benchmark circuit: a chain of `num_constraints` squarings,
+/// x_{i+1} = x_i * x_i, exposing the final value as the public input.
+#[derive(Clone)]
+pub struct SquaringChainCircuit<F: PrimeField> {
+    pub num_constraints: usize,
+    pub x: Option<F>,
+}
+
+impl<F: PrimeField> SquaringChainCircuit<F> {
+    /// Public input for a chain of `num_constraints` squarings starting at `x`.
+    pub fn output(x: F, num_constraints: usize) -> F {
+        (0..num_constraints).fold(x, |acc, _| acc.square())
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for SquaringChainCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        assert!(self.num_constraints > 0, "chain needs at least one constraint");
+
+        let mut val = self.x;
+        let mut var = cs.new_witness_variable(|| val.ok_or(SynthesisError::AssignmentMissing))?;
+
+        for i in 0..self.num_constraints {
+            let next_val = val.map(|v| v.square());
+            let next_var = if i + 1 == self.num_constraints {
+                cs.new_input_variable(|| next_val.ok_or(SynthesisError::AssignmentMissing))?
+            } else {
+                cs.new_witness_variable(|| next_val.ok_or(SynthesisError::AssignmentMissing))?
+            };
+            cs.enforce_constraint(lc!() + var, lc!() + var, lc!() + next_var)?;
+            val = next_val;
+            var = next_var;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,4 +124,22 @@ mod tests {
         let instance = &cs_inner.deref().instance_assignment;
         assert_eq!(instance[1], Fr::from(135u64));
     }
+
+    #[test]
+    fn test_squaring_chain_satisfied() {
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let circuit = SquaringChainCircuit {
+            num_constraints: 3,
+            x: Some(Fr::from(2u64)),
+        };
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.num_constraints(), 3);
+
+        // 2^(2^3) = 256
+        let cs_inner = cs.borrow().unwrap();
+        let instance = &cs_inner.deref().instance_assignment;
+        assert_eq!(instance[1], Fr::from(256u64));
+        assert_eq!(SquaringChainCircuit::output(Fr::from(2u64), 3), instance[1]);
+    }
 }
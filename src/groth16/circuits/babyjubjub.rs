@@ -0,0 +1,74 @@
+//! BabyJubJub: the twisted Edwards curve a x^2 + y^2 = 1 + d x^2 y^2 with
+//! a = 168700, d = 168696 over BN254's scalar field, so its arithmetic is native
+//! inside BN254 circuits. Parameters and generator (`Base8`) follow EIP-2494 and
+//! circomlib.
+
+use ark_bn254::Fr;
+use ark_ec::twisted_edwards::{Affine, MontCurveConfig, Projective, TECurveConfig};
+use ark_ec::CurveConfig;
+use ark_ff::fields::{Fp256, MontBackend, MontConfig};
+use ark_ff::MontFp;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::groups::curves::twisted_edwards::AffineVar;
+
+/// Order of BabyJubJub's prime-order subgroup.
+#[derive(MontConfig)]
+#[modulus = "2736030358979909402780800718157159386076813972158567259200215660948447373041"]
+#[generator = "31"]
+pub struct ScalarFieldConfig;
+pub type ScalarField = Fp256<MontBackend<ScalarFieldConfig, 4>>;
+
+pub type EdwardsAffine = Affine<BabyJubJubConfig>;
+pub type EdwardsProjective = Projective<BabyJubJubConfig>;
+/// In-circuit point over BN254's scalar field.
+pub type EdwardsVar = AffineVar<BabyJubJubConfig, FpVar<Fr>>;
+
+pub struct BabyJubJubConfig;
+
+impl CurveConfig for BabyJubJubConfig {
+    type BaseField = Fr;
+    type ScalarField = ScalarField;
+
+    const COFACTOR: &'static [u64] = &[8];
+    const COFACTOR_INV: ScalarField =
+        MontFp!("2394026564107420727433200628387514462817212225638746351800188703329891451411");
+}
+
+impl TECurveConfig for BabyJubJubConfig {
+    const COEFF_A: Fr = MontFp!("168700");
+    const COEFF_D: Fr = MontFp!("168696");
+    const GENERATOR: EdwardsAffine = EdwardsAffine::new_unchecked(
+        MontFp!("5299619240641551281634865583518297030282874472190772894086521144482721001553"),
+        MontFp!("16950150798460657717958625567821834550301663161624707787222815936182638968203"),
+    );
+
+    type MontCurveConfig = BabyJubJubConfig;
+}
+
+/// The birationally equivalent Montgomery curve y^2 = x^3 + 168698 x^2 + x.
+impl MontCurveConfig for BabyJubJubConfig {
+    const COEFF_A: Fr = MontFp!("168698");
+    const COEFF_B: Fr = MontFp!("1");
+
+    type TECurveConfig = BabyJubJubConfig;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ec::{AffineRepr, CurveGroup, PrimeGroup};
+    use ark_ff::PrimeField;
+
+    #[test]
+    fn test_generator_has_prime_order() {
+        let g = EdwardsAffine::generator();
+        assert!(g.is_on_curve());
+        assert!(g.is_in_correct_subgroup_assuming_on_curve());
+        assert!(g.mul_bigint(ScalarField::MODULUS).into_affine().is_zero());
+        assert_eq!(
+            EdwardsProjective::generator()
+                * (ScalarField::from(8u64) * BabyJubJubConfig::COFACTOR_INV),
+            EdwardsProjective::generator()
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! EdDSA over BabyJubJub with a Poseidon challenge, and a circuit proving
+//! knowledge of a valid signature on a public message under a public key. The
+//! signature itself stays private, which is the wallet use case for delegated
+//! proving: the server learns neither the signature nor anything derived from it.
+//!
+//! A signature (R, s) on m under A = sk * B verifies when s * B = R + h * A, with
+//! h = Poseidon(R.x, R.y, A.x, A.y, m). This is not circomlib's EdDSAPoseidon:
+//! the Poseidon parameters are `merkle::poseidon_config` and there is no cofactor
+//! multiplication in the check.
+
+use ark_bn254::Fr;
+use ark_crypto_primitives::crh::poseidon::constraints::{CRHGadget, CRHParametersVar};
+use ark_crypto_primitives::crh::poseidon::CRH;
+use ark_crypto_primitives::crh::{CRHScheme, CRHSchemeGadget};
+use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{AdditiveGroup, BigInteger, PrimeField, UniformRand};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::Rng;
+
+use super::babyjubjub::{EdwardsAffine, EdwardsProjective, EdwardsVar, ScalarField};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub r: EdwardsAffine,
+    pub s: ScalarField,
+}
+
+pub struct SigningKey {
+    secret: ScalarField,
+    public: EdwardsAffine,
+}
+
+impl SigningKey {
+    pub fn rand<R: Rng>(rng: &mut R) -> Self {
+        let secret = ScalarField::rand(rng);
+        Self {
+            secret,
+            public: (EdwardsAffine::generator() * secret).into_affine(),
+        }
+    }
+
+    pub fn public_key(&self) -> EdwardsAffine {
+        self.public
+    }
+
+    /// Sign `message` with a fresh random nonce.
+    pub fn sign<R: Rng>(&self, config: &PoseidonConfig<Fr>, message: Fr, rng: &mut R) -> Signature {
+        let k = ScalarField::rand(rng);
+        let r = (EdwardsAffine::generator() * k).into_affine();
+        let h = challenge_scalar(challenge(config, &r, &self.public, message));
+        Signature {
+            r,
+            s: k + h * self.secret,
+        }
+    }
+}
+
+/// h = Poseidon(R.x, R.y, A.x, A.y, m), as a base field element.
+pub fn challenge(
+    config: &PoseidonConfig<Fr>,
+    r: &EdwardsAffine,
+    public_key: &EdwardsAffine,
+    message: Fr,
+) -> Fr {
+    CRH::<Fr>::evaluate(config, [r.x, r.y, public_key.x, public_key.y, message])
+        .expect("Poseidon hashing is infallible")
+}
+
+/// `h` reduced into the scalar field. The circuit multiplies by all of h's bits
+/// instead, which is the same for points of prime order.
+fn challenge_scalar(h: Fr) -> ScalarField {
+    ScalarField::from_le_bytes_mod_order(&h.into_bigint().to_bytes_le())
+}
+
+/// Native verification of `signature` on `message` under `public_key`.
+pub fn verify(
+    config: &PoseidonConfig<Fr>,
+    public_key: &EdwardsAffine,
+    message: Fr,
+    signature: &Signature,
+) -> bool {
+    if !public_key.is_in_correct_subgroup_assuming_on_curve() {
+        return false;
+    }
+    let h = challenge_scalar(challenge(config, &signature.r, public_key, message));
+    EdwardsAffine::generator() * signature.s == signature.r + *public_key * h
+}
+
+/// Proves knowledge of a valid `signature` on `message` under `public_key`.
+/// The public inputs are the key's coordinates and the message
+/// (`public_inputs`). The verifier must check that the public key is in the
+/// prime-order subgroup, as `verify` does; the circuit only checks R.
+#[derive(Clone)]
+pub struct EdDSACircuit {
+    pub config: PoseidonConfig<Fr>,
+    pub public_key: Option<EdwardsAffine>,
+    pub message: Option<Fr>,
+    pub signature: Option<Signature>,
+}
+
+impl EdDSACircuit {
+    /// Circuit for setup, without a witness.
+    pub fn blank(config: PoseidonConfig<Fr>) -> Self {
+        Self {
+            config,
+            public_key: None,
+            message: None,
+            signature: None,
+        }
+    }
+
+    pub fn new(
+        config: PoseidonConfig<Fr>,
+        public_key: EdwardsAffine,
+        message: Fr,
+        signature: Signature,
+    ) -> Self {
+        Self {
+            config,
+            public_key: Some(public_key),
+            message: Some(message),
+            signature: Some(signature),
+        }
+    }
+
+    /// Public inputs in allocation order: A.x, A.y, m.
+    pub fn public_inputs(public_key: &EdwardsAffine, message: Fr) -> Vec<Fr> {
+        vec![public_key.x, public_key.y, message]
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for EdDSACircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let params = CRHParametersVar::new_constant(cs.clone(), &self.config)?;
+
+        // Public inputs: the key and the message
+        let public_key = EdwardsVar::new_input(cs.clone(), || {
+            self.public_key.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let message = FpVar::new_input(cs.clone(), || {
+            self.message.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // Private witness: the signature, R checked to be in the prime-order subgroup
+        let r = EdwardsVar::new_witness(cs.clone(), || {
+            self.signature
+                .map(|sig| sig.r)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let s_bits = (0..ScalarField::MODULUS_BIT_SIZE as usize)
+            .map(|i| {
+                Boolean::new_witness(cs.clone(), || {
+                    self.signature
+                        .map(|sig| sig.s.into_bigint().get_bit(i))
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let h = CRHGadget::<Fr>::evaluate(
+            &params,
+            &[
+                r.x.clone(),
+                r.y.clone(),
+                public_key.x.clone(),
+                public_key.y.clone(),
+                message,
+            ],
+        )?;
+        let h_a = public_key.scalar_mul_le(h.to_bits_le()?.iter())?;
+
+        // s * B over the fixed generator's precomputed doublings
+        let bases: Vec<EdwardsProjective> = std::iter::successors(
+            Some(EdwardsProjective::from(EdwardsAffine::generator())),
+            |base| Some(base.double()),
+        )
+        .take(s_bits.len())
+        .collect();
+        let mut s_b = EdwardsVar::zero();
+        s_b.precomputed_base_scalar_mul_le(s_bits.iter().zip(&bases))?;
+
+        s_b.enforce_equal(&(r + h_a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuits::merkle::poseidon_config;
+    use ark_relations::r1cs::ConstraintSystem;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_signature_circuit() {
+        let mut rng = ChaCha20Rng::seed_from_u64(23);
+        let config = poseidon_config::<Fr>();
+        let key = SigningKey::rand(&mut rng);
+        let message = Fr::from(1234u64);
+        let signature = key.sign(&config, message, &mut rng);
+        assert!(verify(&config, &key.public_key(), message, &signature));
+        assert!(!verify(
+            &config,
+            &key.public_key(),
+            message + Fr::from(1u64),
+            &signature
+        ));
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        EdDSACircuit::new(config.clone(), key.public_key(), message, signature)
+            .generate_constraints(cs.clone())
+            .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.num_instance_variables(), 4);
+
+        // Same signature, other message
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        EdDSACircuit::new(
+            config,
+            key.public_key(),
+            message + Fr::from(1u64),
+            signature,
+        )
+        .generate_constraints(cs.clone())
+        .unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+}
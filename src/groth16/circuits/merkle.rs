@@ -0,0 +1,255 @@
+//! Merkle membership: knowledge of a leaf and an authentication path to a public
+//! root, hashed with Poseidon. A depth-d circuit has about 245 * (d + 1)
+//! constraints, so depth 20 gives MSMs of a few thousand elements.
+
+use ark_crypto_primitives::crh::poseidon::constraints::{
+    CRHGadget, CRHParametersVar, TwoToOneCRHGadget,
+};
+use ark_crypto_primitives::crh::poseidon::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::crh::{
+    CRHScheme, CRHSchemeGadget, TwoToOneCRHScheme, TwoToOneCRHSchemeGadget,
+};
+use ark_crypto_primitives::sponge::poseidon::{find_poseidon_ark_and_mds, PoseidonConfig};
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+/// Poseidon over a width-3 state (rate 2), x^5 S-box, 8 full and 57 partial
+/// rounds: the 128-bit parameters for 254-bit fields such as BN254's Fr.
+pub fn poseidon_config<F: PrimeField>() -> PoseidonConfig<F> {
+    let (full_rounds, partial_rounds, rate) = (8, 57, 2);
+    let (ark, mds) = find_poseidon_ark_and_mds::<F>(
+        F::MODULUS_BIT_SIZE as u64,
+        rate,
+        full_rounds,
+        partial_rounds,
+        0,
+    );
+    PoseidonConfig::new(
+        full_rounds as usize,
+        partial_rounds as usize,
+        5,
+        mds,
+        ark,
+        rate,
+        1,
+    )
+}
+
+/// Hash of a leaf value, the bottom level of the tree.
+pub fn hash_leaf<F: PrimeField + Absorb>(config: &PoseidonConfig<F>, leaf: F) -> F {
+    CRH::<F>::evaluate(config, [leaf]).expect("Poseidon hashing is infallible")
+}
+
+/// Hash of two sibling nodes.
+pub fn hash_pair<F: PrimeField + Absorb>(config: &PoseidonConfig<F>, left: F, right: F) -> F {
+    TwoToOneCRH::<F>::compress(config, left, right).expect("Poseidon hashing is infallible")
+}
+
+/// Authentication path of one leaf: its index and the sibling at each level,
+/// bottom first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerklePath<F> {
+    pub index: usize,
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField + Absorb> MerklePath<F> {
+    /// The root this path leads to from `leaf`.
+    pub fn root(&self, config: &PoseidonConfig<F>, leaf: F) -> F {
+        self.siblings
+            .iter()
+            .enumerate()
+            .fold(hash_leaf(config, leaf), |node, (level, &sibling)| {
+                if (self.index >> level) & 1 == 1 {
+                    hash_pair(config, sibling, node)
+                } else {
+                    hash_pair(config, node, sibling)
+                }
+            })
+    }
+}
+
+/// A complete binary tree over 2^depth leaves, padded with zero leaves.
+pub struct MerkleTree<F> {
+    /// `levels[0]` holds the leaf hashes, the last level the root.
+    levels: Vec<Vec<F>>,
+}
+
+impl<F: PrimeField + Absorb> MerkleTree<F> {
+    /// # Panics
+    /// If there are more than 2^depth leaves.
+    pub fn new(config: &PoseidonConfig<F>, leaves: &[F], depth: usize) -> Self {
+        let width = 1usize << depth;
+        assert!(
+            leaves.len() <= width,
+            "{} leaves do not fit in a tree of depth {depth}",
+            leaves.len()
+        );
+        let mut level: Vec<F> = (0..width)
+            .map(|i| hash_leaf(config, leaves.get(i).copied().unwrap_or_default()))
+            .collect();
+        let mut levels = Vec::with_capacity(depth + 1);
+        while level.len() > 1 {
+            let next = level
+                .chunks(2)
+                .map(|pair| hash_pair(config, pair[0], pair[1]))
+                .collect();
+            levels.push(std::mem::replace(&mut level, next));
+        }
+        levels.push(level);
+        Self { levels }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn root(&self) -> F {
+        self.levels[self.depth()][0]
+    }
+
+    /// # Panics
+    /// If `index` is not a leaf position.
+    pub fn path(&self, index: usize) -> MerklePath<F> {
+        assert!(index < self.levels[0].len(), "leaf {index} out of range");
+        let siblings = self.levels[..self.depth()]
+            .iter()
+            .enumerate()
+            .map(|(level, nodes)| nodes[(index >> level) ^ 1])
+            .collect();
+        MerklePath { index, siblings }
+    }
+}
+
+/// Proves knowledge of a leaf and path in the tree with public root `root`.
+/// The leaf and its position stay private. Leave `root`, `leaf` and `path` unset
+/// for setup; `depth` alone fixes the circuit shape.
+#[derive(Clone)]
+pub struct MerkleMembershipCircuit<F: PrimeField + Absorb> {
+    pub config: PoseidonConfig<F>,
+    pub depth: usize,
+    pub root: Option<F>,
+    pub leaf: Option<F>,
+    pub path: Option<MerklePath<F>>,
+}
+
+impl<F: PrimeField + Absorb> MerkleMembershipCircuit<F> {
+    /// Circuit for setup, without a witness.
+    pub fn blank(config: PoseidonConfig<F>, depth: usize) -> Self {
+        Self {
+            config,
+            depth,
+            root: None,
+            leaf: None,
+            path: None,
+        }
+    }
+
+    /// Circuit proving that `tree` holds `leaf` at `index`.
+    pub fn new(config: PoseidonConfig<F>, tree: &MerkleTree<F>, leaf: F, index: usize) -> Self {
+        Self {
+            config,
+            depth: tree.depth(),
+            root: Some(tree.root()),
+            leaf: Some(leaf),
+            path: Some(tree.path(index)),
+        }
+    }
+}
+
+impl<F: PrimeField + Absorb> ConstraintSynthesizer<F> for MerkleMembershipCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let params = CRHParametersVar::new_constant(cs.clone(), &self.config)?;
+
+        // Public input: the root
+        let root = FpVar::new_input(cs.clone(), || {
+            self.root.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // Private witness: the leaf, its position bits and the siblings
+        let leaf = FpVar::new_witness(cs.clone(), || {
+            self.leaf.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = self.path.as_ref();
+        let mut node = CRHGadget::<F>::evaluate(&params, &[leaf])?;
+        for level in 0..self.depth {
+            let is_right = Boolean::new_witness(cs.clone(), || {
+                path.map(|p| (p.index >> level) & 1 == 1)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            let sibling = FpVar::new_witness(cs.clone(), || {
+                path.and_then(|p| p.siblings.get(level).copied())
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            let left = is_right.select(&sibling, &node)?;
+            let right = is_right.select(&node, &sibling)?;
+            node = TwoToOneCRHGadget::<F>::compress(&params, &left, &right)?;
+        }
+
+        node.enforce_equal(&root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::server_aided::{
+        client_decrypt, client_encrypt, server_evaluate, ServerAidedProvingKey,
+    };
+    use ark_bn254::{Bn254, Fr};
+    use ark_groth16::Groth16;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    const DEPTH: usize = 8;
+
+    fn tree() -> (PoseidonConfig<Fr>, MerkleTree<Fr>, Vec<Fr>) {
+        let config = poseidon_config::<Fr>();
+        let leaves: Vec<Fr> = (0..100u64).map(Fr::from).collect();
+        let tree = MerkleTree::new(&config, &leaves, DEPTH);
+        (config, tree, leaves)
+    }
+
+    #[test]
+    fn test_membership_constraints() {
+        let (config, tree, leaves) = tree();
+        assert_eq!(tree.path(37).root(&config, leaves[37]), tree.root());
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        MerkleMembershipCircuit::new(config.clone(), &tree, leaves[37], 37)
+            .generate_constraints(cs.clone())
+            .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // A leaf that is not at index 37
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        MerkleMembershipCircuit::new(config, &tree, leaves[38], 37)
+            .generate_constraints(cs.clone())
+            .unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn test_delegated_merkle_membership_proof() {
+        let mut rng = ChaCha20Rng::seed_from_u64(19);
+        let (config, tree, leaves) = tree();
+
+        let blank = MerkleMembershipCircuit::blank(config.clone(), DEPTH);
+        let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(blank, &mut rng).unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        assert!(sapk.emsm_h.generators.len() >= 2048);
+
+        let circuit = MerkleMembershipCircuit::new(config, &tree, leaves[42], 42);
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+
+        assert!(Groth16::<Bn254>::verify(&vk, &[tree.root()], &proof).unwrap());
+        assert!(!Groth16::<Bn254>::verify(&vk, &[tree.root() + Fr::from(1u64)], &proof).unwrap());
+    }
+}
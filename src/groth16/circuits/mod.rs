@@ -0,0 +1,5 @@
+//! Demo circuits built on `ark-r1cs-std`, large enough to exercise realistic MSM sizes.
+
+pub mod babyjubjub;
+pub mod eddsa;
+pub mod merkle;
@@ -0,0 +1,189 @@
+//! EVM encodings of Groth16 proofs for the BN254 precompiles (EIP-196/197).
+//!
+//! Field elements are 32-byte big-endian words. A G1 point is `x || y` and the
+//! point at infinity is all zeros. A G2 point puts the imaginary part first:
+//! `x.c1 || x.c0 || y.c1 || y.c0`. Getting that order wrong is the usual reason
+//! a proof that verifies in arkworks fails on chain.
+
+use ark_bn254::{Bn254, Fq, Fr, G1Affine, G1Projective, G2Affine};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{BigInteger, PrimeField};
+use ark_groth16::{Proof, VerifyingKey};
+
+/// Length of one (G1, G2) pair in pairing precompile input.
+pub const PAIR_LEN: usize = 192;
+
+/// Public input count does not match the verifying key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("verifying key takes {expected} public inputs, got {got}")]
+pub struct PublicInputCount {
+    pub expected: usize,
+    pub got: usize,
+}
+
+fn fq_word(f: &Fq) -> [u8; 32] {
+    f.into_bigint()
+        .to_bytes_be()
+        .try_into()
+        .expect("Fq is 32 bytes")
+}
+
+/// A scalar as a `uint256` word.
+pub fn fr_to_evm(f: &Fr) -> [u8; 32] {
+    f.into_bigint()
+        .to_bytes_be()
+        .try_into()
+        .expect("Fr is 32 bytes")
+}
+
+/// A G1 point as `ecAdd` / `ecMul` / `ecPairing` read it.
+pub fn g1_to_evm(p: &G1Affine) -> [u8; 64] {
+    let mut out = [0u8; 64];
+    if let Some((x, y)) = p.xy() {
+        out[..32].copy_from_slice(&fq_word(&x));
+        out[32..].copy_from_slice(&fq_word(&y));
+    }
+    out
+}
+
+/// A G2 point as `ecPairing` reads it, imaginary parts first.
+pub fn g2_to_evm(p: &G2Affine) -> [u8; 128] {
+    let mut out = [0u8; 128];
+    if let Some((x, y)) = p.xy() {
+        for (word, f) in out.chunks_mut(32).zip([x.c1, x.c0, y.c1, y.c0]) {
+            word.copy_from_slice(&fq_word(&f));
+        }
+    }
+    out
+}
+
+/// ABI encoding of `(uint256[2] a, uint256[2][2] b, uint256[2] c, uint256[n] input)`,
+/// the arguments of the common `verifyProof` Solidity verifiers (without the
+/// function selector). `a` is not negated: those verifiers negate it themselves.
+pub fn proof_calldata(proof: &Proof<Bn254>, public_inputs: &[Fr]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(256 + 32 * public_inputs.len());
+    out.extend(g1_to_evm(&proof.a));
+    out.extend(g2_to_evm(&proof.b));
+    out.extend(g1_to_evm(&proof.c));
+    for input in public_inputs {
+        out.extend(fr_to_evm(input));
+    }
+    out
+}
+
+/// Input to the pairing precompile (address `0x08`) that checks `proof`:
+///
+/// e(-A, B) * e(alpha, beta) * e(vk_x, gamma) * e(C, delta) == 1
+///
+/// with vk_x = IC[0] + sum input[i] * IC[i + 1] computed here. The call returns
+/// 1 exactly when the proof verifies. A is negated because the precompile only
+/// checks that the product of pairings is one.
+pub fn pairing_input(
+    vk: &VerifyingKey<Bn254>,
+    proof: &Proof<Bn254>,
+    public_inputs: &[Fr],
+) -> Result<Vec<u8>, PublicInputCount> {
+    let expected = vk.gamma_abc_g1.len().saturating_sub(1);
+    if public_inputs.len() != expected {
+        return Err(PublicInputCount {
+            expected,
+            got: public_inputs.len(),
+        });
+    }
+    let vk_x = public_inputs
+        .iter()
+        .zip(&vk.gamma_abc_g1[1..])
+        .fold(G1Projective::from(vk.gamma_abc_g1[0]), |acc, (x, ic)| {
+            acc + *ic * x
+        })
+        .into_affine();
+
+    let mut out = Vec::with_capacity(4 * PAIR_LEN);
+    for (g1, g2) in [
+        (-proof.a, proof.b),
+        (vk.alpha_g1, vk.beta_g2),
+        (vk_x, vk.gamma_g2),
+        (proof.c, vk.delta_g2),
+    ] {
+        out.extend(g1_to_evm(&g1));
+        out.extend(g2_to_evm(&g2));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::CubeCircuit;
+    use ark_bn254::{Fq2, G2Affine};
+    use ark_ec::pairing::Pairing;
+    use ark_ff::{One, Zero};
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn fq(word: &[u8]) -> Fq {
+        Fq::from_be_bytes_mod_order(word)
+    }
+
+    /// What the precompile does with `input`.
+    fn ec_pairing(input: &[u8]) -> bool {
+        let (g1s, g2s): (Vec<G1Affine>, Vec<G2Affine>) = input
+            .chunks(PAIR_LEN)
+            .map(|pair| {
+                let w: Vec<Fq> = pair.chunks(32).map(fq).collect();
+                let g1 = if w[..2].iter().all(Zero::is_zero) {
+                    G1Affine::zero()
+                } else {
+                    G1Affine::new(w[0], w[1])
+                };
+                let g2 = G2Affine::new(Fq2::new(w[3], w[2]), Fq2::new(w[5], w[4]));
+                (g1, g2)
+            })
+            .unzip();
+        Bn254::multi_pairing(g1s, g2s).0.is_one()
+    }
+
+    #[test]
+    fn test_point_layout() {
+        let g1 = g1_to_evm(&G1Affine::generator());
+        assert_eq!(g1[31], 1);
+        assert_eq!(g1[63], 2);
+        assert!(g1_to_evm(&G1Affine::zero()).iter().all(|&b| b == 0));
+
+        let g2 = G2Affine::generator();
+        let words = g2_to_evm(&g2);
+        let (x, _) = g2.xy().unwrap();
+        assert_eq!(fq(&words[..32]), x.c1);
+        assert_eq!(fq(&words[32..64]), x.c0);
+    }
+
+    #[test]
+    fn test_pairing_input_verifies_proof() {
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let circuit = CubeCircuit {
+            x: Some(Fr::from(3u64)),
+        };
+        let proof = Groth16::<Bn254>::prove(&pk, circuit, &mut rng).unwrap();
+
+        let input = pairing_input(&vk, &proof, &[Fr::from(35u64)]).unwrap();
+        assert_eq!(input.len(), 4 * PAIR_LEN);
+        assert!(ec_pairing(&input));
+
+        let wrong = pairing_input(&vk, &proof, &[Fr::from(36u64)]).unwrap();
+        assert!(!ec_pairing(&wrong));
+
+        assert_eq!(
+            pairing_input(&vk, &proof, &[]),
+            Err(PublicInputCount {
+                expected: 1,
+                got: 0
+            })
+        );
+        assert_eq!(proof_calldata(&proof, &[Fr::from(35u64)]).len(), 288);
+    }
+}
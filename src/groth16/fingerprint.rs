@@ -0,0 +1,134 @@
+//! Fingerprints of Groth16 keys: SHA-256 digests naming a key without shipping
+//! it, so a client, server and verifier holding different keys for one circuit
+//! find out at setup or audit time rather than from a proof that fails to
+//! verify.
+
+use ark_bn254::Bn254;
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_serialize::CanonicalSerialize;
+use sha2::{Digest, Sha256};
+
+use super::bundle::vk_hash;
+use super::server_aided::ServerAidedProvingKey;
+use crate::emsm::cache::HashWriter;
+use crate::protocol::audit::to_hex;
+
+/// A stable digest of a key: the same key always has the same fingerprint,
+/// across processes and versions that keep its serialization.
+pub trait Fingerprint {
+    fn fingerprint(&self) -> [u8; 32];
+}
+
+/// `vk_hash`, the fingerprint `ProofWithPublicInputs` carries.
+impl Fingerprint for VerifyingKey<Bn254> {
+    fn fingerprint(&self) -> [u8; 32] {
+        vk_hash(self)
+    }
+}
+
+/// SHA-256 of the compressed key, streamed rather than buffered.
+impl Fingerprint for ProvingKey<Bn254> {
+    fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        self.serialize_compressed(HashWriter(&mut hasher))
+            .expect("writing to a hasher cannot fail");
+        hasher.finalize().into()
+    }
+}
+
+/// The fingerprint of the proving key the key was set up from, so it does not
+/// depend on the masking codes, which never leave the client. Hashes the bytes
+/// `proving_key()` would serialize to without reassembling it.
+impl Fingerprint for ServerAidedProvingKey {
+    fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let mut writer = HashWriter(&mut hasher);
+        let pk = &self.pk;
+        pk.vk
+            .serialize_compressed(&mut writer)
+            .and_then(|_| pk.beta_g1.serialize_compressed(&mut writer))
+            .and_then(|_| pk.delta_g1.serialize_compressed(&mut writer))
+            .expect("writing to a hasher cannot fail");
+        write_query(&mut writer, &pk.a_query, &self.emsm_a.generators);
+        write_query(&mut writer, &pk.b_g1_query, &self.emsm_b_g1.generators);
+        write_query(&mut writer, &pk.b_g2_query, &self.emsm_b_g2.generators);
+        write_query(&mut writer, &[], &self.emsm_h.generators);
+        write_query(&mut writer, &[], &self.emsm_l.generators);
+        hasher.finalize().into()
+    }
+}
+
+/// `public || witness` as `Vec::serialize_compressed` writes it.
+fn write_query<P: CanonicalSerialize>(writer: &mut HashWriter, public: &[P], witness: &[P]) {
+    let len = (public.len() + witness.len()) as u64;
+    len.serialize_compressed(&mut *writer)
+        .expect("writing to a hasher cannot fail");
+    for point in public.iter().chain(witness) {
+        point
+            .serialize_compressed(&mut *writer)
+            .expect("writing to a hasher cannot fail");
+    }
+}
+
+/// A key whose fingerprint is not the one expected of it.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{key} has fingerprint {}, expected {}", to_hex(.actual), to_hex(.expected))]
+pub struct KeyMismatch {
+    /// Which key, e.g. "proving key" or "verifying key".
+    pub key: &'static str,
+    pub expected: [u8; 32],
+    pub actual: [u8; 32],
+}
+
+/// Fail with `KeyMismatch` unless `value` has the fingerprint `expected`.
+pub fn check_fingerprint<K: Fingerprint + ?Sized>(
+    key: &'static str,
+    value: &K,
+    expected: [u8; 32],
+) -> Result<(), KeyMismatch> {
+    let actual = value.fingerprint();
+    if actual != expected {
+        return Err(KeyMismatch {
+            key,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::CubeCircuit;
+    use ark_bn254::Fr;
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_fingerprints() {
+        let mut rng = ChaCha20Rng::seed_from_u64(55);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let (other_pk, _) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        assert_eq!(vk.fingerprint(), vk_hash(&vk));
+        assert_ne!(pk.fingerprint(), other_pk.fingerprint());
+
+        // Keys set up from the same proving key share its fingerprint, whatever
+        // their masking codes
+        let sapk = ServerAidedProvingKey::setup(pk.clone(), &mut rng);
+        let again = ServerAidedProvingKey::setup(pk.clone(), &mut rng);
+        assert_eq!(sapk.fingerprint(), pk.fingerprint());
+        assert_eq!(again.fingerprint(), pk.fingerprint());
+
+        check_fingerprint("proving key", &sapk, pk.fingerprint()).unwrap();
+        let err = check_fingerprint("proving key", &sapk, other_pk.fingerprint()).unwrap_err();
+        assert_eq!(err.actual, pk.fingerprint());
+        assert!(err.to_string().starts_with("proving key has fingerprint "));
+    }
+}
@@ -0,0 +1,397 @@
+//! Groth16 proof encodings of other proving stacks, so delegated proofs can be
+//! handed to verifiers built on gnark or bellman without re-encoding points by hand.
+//!
+//! Both libraries write coordinates big-endian (arkworks writes them
+//! little-endian) and put x.c1 before x.c0 for G2, but they flag compressed
+//! points differently:
+//!
+//! | Library | y <= -y | y > -y | Infinity |
+//! |---------|---------|--------|----------|
+//! | gnark   | `0x80`  | `0xC0` | `0x40`   |
+//! | bellman | `0x00`  | `0x80` | `0x40`   |
+//!
+//! The flags are OR'd into the first byte. Uncompressed points carry no flags
+//! except `0x40` for infinity. "bellman" here means the BN254 (`bn256`) engine of
+//! `bellman_ce`, since upstream bellman only supports BLS12-381.
+
+use ark_bn254::{Bn254, Fq, Fq2};
+use ark_ec::short_weierstrass::{Affine, SWCurveConfig};
+use ark_ff::{BigInt, BigInteger, PrimeField};
+use ark_groth16::Proof;
+
+const FLAG_MASK: u8 = 0xC0;
+const INFINITY: u8 = 0x40;
+
+/// How points are written: x with flag bits, or x and y.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointEncoding {
+    /// gnark's `WriteTo`, bellman's `Proof::write`.
+    #[default]
+    Compressed,
+    /// gnark's `WriteRawTo`.
+    Uncompressed,
+}
+
+/// Errors decoding a proof from another library's format.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InteropError {
+    #[error("proof is {got} bytes, expected {expected}")]
+    Length { expected: usize, got: usize },
+    #[error("invalid encoding of proof point {0}")]
+    InvalidPoint(&'static str),
+    #[error("gnark proof carries {0} Pedersen commitments, which arkworks Groth16 cannot verify")]
+    Commitments(u32),
+}
+
+#[derive(Clone, Copy)]
+enum Library {
+    Gnark,
+    Bellman,
+}
+
+impl Library {
+    fn compressed_flags(self, largest: bool) -> u8 {
+        match (self, largest) {
+            (Library::Gnark, false) => 0x80,
+            (Library::Gnark, true) => 0xC0,
+            (Library::Bellman, false) => 0x00,
+            (Library::Bellman, true) => 0x80,
+        }
+    }
+
+    fn largest_from_flags(self, flags: u8) -> Option<bool> {
+        [false, true]
+            .into_iter()
+            .find(|&largest| self.compressed_flags(largest) == flags)
+    }
+}
+
+/// Big-endian coordinate encoding shared by gnark and bellman.
+trait BeField: Sized {
+    const SIZE: usize;
+    fn write_be(&self, out: &mut Vec<u8>);
+    /// `None` unless `bytes` is a canonical encoding.
+    fn read_be(bytes: &[u8]) -> Option<Self>;
+}
+
+impl BeField for Fq {
+    const SIZE: usize = 32;
+
+    fn write_be(&self, out: &mut Vec<u8>) {
+        out.extend(self.into_bigint().to_bytes_be());
+    }
+
+    fn read_be(bytes: &[u8]) -> Option<Self> {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.rchunks(8)) {
+            *limb = u64::from_be_bytes(chunk.try_into().ok()?);
+        }
+        Fq::from_bigint(BigInt(limbs))
+    }
+}
+
+impl BeField for Fq2 {
+    const SIZE: usize = 64;
+
+    fn write_be(&self, out: &mut Vec<u8>) {
+        self.c1.write_be(out);
+        self.c0.write_be(out);
+    }
+
+    fn read_be(bytes: &[u8]) -> Option<Self> {
+        let (c1, c0) = bytes.split_at(Fq::SIZE);
+        Some(Fq2::new(Fq::read_be(c0)?, Fq::read_be(c1)?))
+    }
+}
+
+fn point_size<P: SWCurveConfig>(encoding: PointEncoding) -> usize
+where
+    P::BaseField: BeField,
+{
+    match encoding {
+        PointEncoding::Compressed => P::BaseField::SIZE,
+        PointEncoding::Uncompressed => 2 * P::BaseField::SIZE,
+    }
+}
+
+fn write_point<P: SWCurveConfig>(
+    point: &Affine<P>,
+    library: Library,
+    encoding: PointEncoding,
+    out: &mut Vec<u8>,
+) where
+    P::BaseField: BeField,
+{
+    let start = out.len();
+    if point.infinity {
+        out.resize(start + point_size::<P>(encoding), 0);
+        out[start] = INFINITY;
+        return;
+    }
+    point.x.write_be(out);
+    match encoding {
+        PointEncoding::Compressed => {
+            out[start] |= library.compressed_flags(point.y > -point.y);
+        }
+        PointEncoding::Uncompressed => point.y.write_be(out),
+    }
+}
+
+/// Read one point from the front of `bytes`, checking it is on the curve and in
+/// the prime-order subgroup.
+fn read_point<P: SWCurveConfig>(
+    bytes: &mut &[u8],
+    library: Library,
+    encoding: PointEncoding,
+    name: &'static str,
+) -> Result<Affine<P>, InteropError>
+where
+    P::BaseField: BeField,
+{
+    let size = point_size::<P>(encoding);
+    if bytes.len() < size {
+        return Err(InteropError::InvalidPoint(name));
+    }
+    let (raw, rest) = bytes.split_at(size);
+    *bytes = rest;
+
+    let mut raw = raw.to_vec();
+    let flags = raw[0] & FLAG_MASK;
+    raw[0] &= !FLAG_MASK;
+    if flags == INFINITY {
+        return if raw.iter().all(|&b| b == 0) {
+            Ok(Affine::identity())
+        } else {
+            Err(InteropError::InvalidPoint(name))
+        };
+    }
+
+    let (x, y) = raw.split_at(P::BaseField::SIZE);
+    let x = P::BaseField::read_be(x).ok_or(InteropError::InvalidPoint(name))?;
+    let point = match encoding {
+        PointEncoding::Compressed => {
+            let largest = library
+                .largest_from_flags(flags)
+                .ok_or(InteropError::InvalidPoint(name))?;
+            Affine::get_point_from_x_unchecked(x, largest)
+        }
+        PointEncoding::Uncompressed if flags == 0 => {
+            P::BaseField::read_be(y).map(|y| Affine::new_unchecked(x, y))
+        }
+        PointEncoding::Uncompressed => None,
+    }
+    .ok_or(InteropError::InvalidPoint(name))?;
+
+    if point.is_on_curve() && point.is_in_correct_subgroup_assuming_on_curve() {
+        Ok(point)
+    } else {
+        Err(InteropError::InvalidPoint(name))
+    }
+}
+
+fn write_proof(proof: &Proof<Bn254>, library: Library, encoding: PointEncoding) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_point(&proof.a, library, encoding, &mut out);
+    write_point(&proof.b, library, encoding, &mut out);
+    write_point(&proof.c, library, encoding, &mut out);
+    out
+}
+
+fn read_proof(
+    bytes: &mut &[u8],
+    library: Library,
+    encoding: PointEncoding,
+) -> Result<Proof<Bn254>, InteropError> {
+    Ok(Proof {
+        a: read_point(bytes, library, encoding, "A")?,
+        b: read_point(bytes, library, encoding, "B")?,
+        c: read_point(bytes, library, encoding, "C")?,
+    })
+}
+
+/// Length of a proof's A, B and C points in `encoding`.
+fn points_len(encoding: PointEncoding) -> usize {
+    2 * point_size::<ark_bn254::g1::Config>(encoding)
+        + point_size::<ark_bn254::g2::Config>(encoding)
+}
+
+/// Encode `proof` as gnark's BN254 `groth16.Proof` writes itself: Ar, Bs, Krs,
+/// an empty commitment list (big-endian `u32` count) and a zero commitment
+/// proof of knowledge.
+pub fn proof_to_gnark(proof: &Proof<Bn254>, encoding: PointEncoding) -> Vec<u8> {
+    let mut out = write_proof(proof, Library::Gnark, encoding);
+    out.extend(0u32.to_be_bytes());
+    write_point(
+        &ark_bn254::G1Affine::identity(),
+        Library::Gnark,
+        encoding,
+        &mut out,
+    );
+    out
+}
+
+/// Decode a gnark BN254 Groth16 proof. Proofs written before gnark added
+/// commitments (Ar, Bs and Krs only) are accepted too. Proofs that carry
+/// commitments are rejected: they verify against a commitment key that
+/// arkworks' verifier does not check.
+pub fn proof_from_gnark(
+    bytes: &[u8],
+    encoding: PointEncoding,
+) -> Result<Proof<Bn254>, InteropError> {
+    let points = points_len(encoding);
+    let full = points + 4 + point_size::<ark_bn254::g1::Config>(encoding);
+    let length_error = InteropError::Length {
+        expected: full,
+        got: bytes.len(),
+    };
+    if bytes.len() != points && bytes.len() < points + 4 {
+        return Err(length_error);
+    }
+
+    let mut cursor = bytes;
+    let proof = read_proof(&mut cursor, Library::Gnark, encoding)?;
+    if cursor.is_empty() {
+        return Ok(proof);
+    }
+    let (count, mut rest) = cursor.split_at(4);
+    let count = u32::from_be_bytes(count.try_into().expect("split at 4"));
+    if count != 0 {
+        return Err(InteropError::Commitments(count));
+    }
+    if bytes.len() != full {
+        return Err(length_error);
+    }
+    // Only meaningful alongside commitments, so it is checked and dropped
+    let _commitment_pok =
+        read_point::<ark_bn254::g1::Config>(&mut rest, Library::Gnark, encoding, "CommitmentPok")?;
+    Ok(proof)
+}
+
+/// Encode `proof` as bellman's `Proof::write` does: A, B and C back to back.
+pub fn proof_to_bellman(proof: &Proof<Bn254>, encoding: PointEncoding) -> Vec<u8> {
+    write_proof(proof, Library::Bellman, encoding)
+}
+
+/// Decode a proof written by bellman's `Proof::write` (or its uncompressed
+/// equivalent).
+pub fn proof_from_bellman(
+    bytes: &[u8],
+    encoding: PointEncoding,
+) -> Result<Proof<Bn254>, InteropError> {
+    let expected = points_len(encoding);
+    if bytes.len() != expected {
+        return Err(InteropError::Length {
+            expected,
+            got: bytes.len(),
+        });
+    }
+    read_proof(&mut &bytes[..], Library::Bellman, encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bn254::{G1Affine, G1Projective, G2Projective};
+    use ark_ec::{AffineRepr, CurveGroup};
+    use ark_std::{test_rng, UniformRand};
+
+    fn random_proof() -> Proof<Bn254> {
+        let mut rng = test_rng();
+        Proof {
+            a: G1Projective::rand(&mut rng).into_affine(),
+            b: G2Projective::rand(&mut rng).into_affine(),
+            c: G1Projective::rand(&mut rng).into_affine(),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_both_libraries() {
+        let proof = random_proof();
+        for encoding in [PointEncoding::Compressed, PointEncoding::Uncompressed] {
+            let gnark = proof_to_gnark(&proof, encoding);
+            assert_eq!(proof_from_gnark(&gnark, encoding).unwrap(), proof);
+            // Pre-commitment gnark proofs stop after Krs
+            let legacy = &gnark[..points_len(encoding)];
+            assert_eq!(proof_from_gnark(legacy, encoding).unwrap(), proof);
+
+            let bellman = proof_to_bellman(&proof, encoding);
+            assert_eq!(bellman.len(), points_len(encoding));
+            assert_eq!(proof_from_bellman(&bellman, encoding).unwrap(), proof);
+        }
+        assert_eq!(proof_to_gnark(&proof, PointEncoding::Compressed).len(), 164);
+        assert_eq!(
+            proof_to_bellman(&proof, PointEncoding::Compressed).len(),
+            128
+        );
+    }
+
+    #[test]
+    fn test_generator_encoding() {
+        // G1 generator is (1, 2), and 2 < q - 2, so y is the smaller root
+        let g = G1Affine::generator();
+        let proof = Proof {
+            a: g,
+            b: ark_bn254::G2Affine::generator(),
+            c: G1Affine::identity(),
+        };
+
+        let mut x = [0u8; 32];
+        x[31] = 1;
+        let gnark = proof_to_gnark(&proof, PointEncoding::Compressed);
+        let mut expected = x;
+        expected[0] |= 0x80;
+        assert_eq!(gnark[..32], expected);
+        let bellman = proof_to_bellman(&proof, PointEncoding::Compressed);
+        assert_eq!(bellman[..32], x);
+
+        let raw = proof_to_gnark(&proof, PointEncoding::Uncompressed);
+        assert_eq!(raw[..32], x);
+        assert_eq!(raw[63], 2);
+
+        // C is the point at infinity
+        let mut infinity = [0u8; 32];
+        infinity[0] = INFINITY;
+        assert_eq!(bellman[96..128], infinity);
+        assert_eq!(gnark[96..128], infinity);
+    }
+
+    #[test]
+    fn test_rejects_malformed_proofs() {
+        let proof = random_proof();
+        let encoding = PointEncoding::Compressed;
+
+        let mut gnark = proof_to_gnark(&proof, encoding);
+        gnark[128..132].copy_from_slice(&1u32.to_be_bytes());
+        assert_eq!(
+            proof_from_gnark(&gnark, encoding),
+            Err(InteropError::Commitments(1))
+        );
+
+        let bellman = proof_to_bellman(&proof, encoding);
+        assert!(matches!(
+            proof_from_bellman(&bellman[..127], encoding),
+            Err(InteropError::Length { .. })
+        ));
+
+        // A gnark compressed A has a flag bellman never writes
+        let mut flagged = bellman.clone();
+        flagged[0] |= 0xC0;
+        assert_eq!(
+            proof_from_bellman(&flagged, encoding),
+            Err(InteropError::InvalidPoint("A"))
+        );
+
+        // (1, 3) is not on y^2 = x^3 + 3
+        let uncompressed = PointEncoding::Uncompressed;
+        let generator = Proof {
+            a: G1Affine::generator(),
+            ..proof
+        };
+        let mut off_curve = proof_to_bellman(&generator, uncompressed);
+        off_curve[63] = 3;
+        assert_eq!(
+            proof_from_bellman(&off_curve, uncompressed),
+            Err(InteropError::InvalidPoint("A"))
+        );
+    }
+}
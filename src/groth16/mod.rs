@@ -1,3 +1,10 @@
+pub mod bundle;
 pub mod circuit;
+pub mod circuits;
+pub mod evm;
+pub mod fingerprint;
 pub mod circom;
+pub mod interop;
 pub mod server_aided;
+pub mod stats;
+pub mod witness_map;
@@ -1,62 +1,405 @@
 use ark_bn254::{Bn254, Fr, G1Affine, G1Projective as G1, G2Affine, G2Projective as G2};
-use ark_ec::CurveGroup;
-use ark_ff::Zero;
-use ark_groth16::r1cs_to_qap::R1CSToQAP;
-use ark_groth16::{Proof, ProvingKey};
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ff::{One, Zero};
+use ark_circom::CircomReduction;
+use ark_groth16::r1cs_to_qap::{LibsnarkReduction, R1CSToQAP};
+use ark_groth16::{Proof, ProvingKey, VerifyingKey};
 use ark_poly::GeneralEvaluationDomain;
 use ark_relations::r1cs::{
-    ConstraintSynthesizer, ConstraintSystem, OptimizationGoal, SynthesisMode,
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, OptimizationGoal, SynthesisMode,
 };
-use ark_std::rand::Rng;
+use ark_serialize::{
+    CanonicalDeserialize, CanonicalSerialize, Compress, SerializationError, Validate,
+};
+use ark_std::rand::{CryptoRng, Rng};
 use ark_std::UniformRand;
-use core::ops::Deref;
-
-use crate::emsm::dual_lpn::DualLPNInstance;
-use crate::emsm::emsm::{decrypt, encrypt, EmsmPublicParams, PreprocessedCommitments};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::emsm::cache::PreprocessCache;
+use crate::emsm::cancel::{CancelToken, Cancelled};
+use crate::emsm::deferred::{DeferredPreprocessing, PreprocessMode};
+use crate::emsm::dual_lpn::NoiseDistribution;
+use crate::emsm::emsm::{decrypt, encrypt, encrypt_chunked, EmsmPublicParams, PreprocessOptions};
+use crate::emsm::parallel::ParallelConfig;
+use crate::emsm::params::{
+    try_get_lpn_params_at, InvalidLpnTable, LpnParamSet, LpnParamsError, SecurityLevel,
+};
+use crate::emsm::pedersen::PedersenError;
+use crate::emsm::security::{InsufficientSecurity, SecurityCheck};
+use crate::emsm::progress::ProgressSink;
+use crate::emsm::raa_code::{EncryptScratch, PermutationMode, DEFAULT_FOLD};
+use crate::emsm::sparse_vec::SparseVector;
+use crate::error::{DimensionMismatch, StealthSnarkError};
+use crate::protocol::messages::{validate_points, InvalidPoint, MsmKind};
+use crate::serialization::{ark_serde, ark_serde_vec};
 use crate::emsm::malicious::{
     malicious_decrypt, malicious_encrypt, MaliciousDecryptState, MaliciousEncrypted, MaliciousError,
 };
 
-/// Server-aided proving key: wraps the standard Groth16 proving key with
-/// EMSM parameters for each of the 5 MSMs.
-pub struct ServerAidedProvingKey {
-    pub pk: ProvingKey<Bn254>,
-    pub emsm_h: EmsmPublicParams<G1>,
-    pub emsm_l: EmsmPublicParams<G1>,
-    pub emsm_a: EmsmPublicParams<G1>,
-    pub emsm_b_g1: EmsmPublicParams<G1>,
-    pub emsm_b_g2: EmsmPublicParams<G2>,
-    pub pre_h: PreprocessedCommitments<G1>,
-    pub pre_l: PreprocessedCommitments<G1>,
-    pub pre_a: PreprocessedCommitments<G1>,
-    pub pre_b_g1: PreprocessedCommitments<G1>,
-    pub pre_b_g2: PreprocessedCommitments<G2>,
+/// Options for `ServerAidedProvingKey::setup_with`.
+#[derive(Clone, Default)]
+pub struct SetupOptions<'a> {
+    /// Receives (msm name, fraction complete) updates during preprocessing.
+    pub progress: Option<&'a dyn ProgressSink>,
+    /// Checked between preprocessing stages; setup fails with `Cancelled` once set.
+    pub cancel: Option<&'a CancelToken>,
+    /// Thread pool for preprocessing and for later encrypt / server MSMs on this key.
+    pub parallel: ParallelConfig,
+    /// Reuse preprocessing from earlier runs over the same proving key.
+    pub cache: Option<&'a PreprocessCache>,
+    /// LPN security level for masking with this key.
+    pub security: SecurityLevel,
+    /// Noise weights to use instead of the built-in tables, e.g. from a newer
+    /// analysis (`LpnParamSet::load`). Levels and rates it has no rows for keep
+    /// the built-in ones, which are for rate 1/4 only.
+    pub lpn_params: Option<&'a LpnParamSet>,
+    /// Distribution of the masking noise. The parameter tables assume the default.
+    pub noise: NoiseDistribution,
+    /// Whether an MSM whose estimated security falls short of `security` only
+    /// logs a warning or fails setup.
+    pub security_check: SecurityCheck,
+    /// Preprocess during setup (default), on first use, or in background threads.
+    /// `progress` and `cancel` only cover preprocessing that runs during setup.
+    pub preprocess: PreprocessMode,
+    /// Store the RAA code permutations as index tables (default) or compute them
+    /// from a Feistel network, trading speed for O(1) memory per permutation.
+    pub permutations: PermutationMode,
+    /// QAP reduction the proving key was generated for; detected from the length
+    /// of its h query when `None`.
+    pub reduction: Option<QapReduction>,
 }
 
-impl ServerAidedProvingKey {
-    pub fn setup<R: Rng>(pk: ProvingKey<Bn254>, rng: &mut R) -> Self {
-        let emsm_h = EmsmPublicParams::<G1>::new(pk.h_query.clone(), rng);
-        let pre_h = emsm_h.preprocess();
+/// LPN parameters of one MSM for `ServerAidedProvingKey::setup_with_params`.
+/// Unset fields fall back to `SetupOptions` and the parameter tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MsmLpnParams {
+    /// Security level the noise weight is looked up for, instead of
+    /// `SetupOptions::security`.
+    pub security: Option<SecurityLevel>,
+    /// Noise weight, instead of the table's value for the security level.
+    pub t: Option<usize>,
+    /// Mask with the code of rate 1/`fold` instead of 1/4. MSMs at another rate
+    /// bypass `SetupOptions::cache`, and need `t` unless `SetupOptions::lpn_params`
+    /// has rows for the rate.
+    pub fold: Option<usize>,
+}
 
-        let emsm_l = EmsmPublicParams::<G1>::new(pk.l_query.clone(), rng);
-        let pre_l = emsm_l.preprocess();
+/// Per-MSM LPN parameters for `ServerAidedProvingKey::setup_with_params`, e.g.
+/// a higher security level for a large h query than for a handful of l bases.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpnOverrides {
+    pub h: MsmLpnParams,
+    pub l: MsmLpnParams,
+    pub a: MsmLpnParams,
+    pub b_g1: MsmLpnParams,
+    pub b_g2: MsmLpnParams,
+}
 
-        let num_pub = pk.vk.gamma_abc_g1.len();
+/// Which of the five MSMs a prove sends to the server, for
+/// `ServerAidedProvingKey::with_delegation`. The others go out as empty vectors
+/// and `client_decrypt` computes them from the witness and the key's
+/// generators. Each delegated MSM costs n scalars of upload and saves the
+/// client an n-point MSM, a trade that differs by deployment and by MSM: a G2
+/// point costs about three times a G1 point to add.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delegation {
+    pub h: bool,
+    pub l: bool,
+    pub a: bool,
+    pub b_g1: bool,
+    pub b_g2: bool,
+}
 
-        let a_witness: Vec<G1Affine> = pk.a_query[num_pub..].to_vec();
-        let emsm_a = EmsmPublicParams::<G1>::new(a_witness, rng);
-        let pre_a = emsm_a.preprocess();
+impl Delegation {
+    /// Every MSM to the server, the default.
+    pub const ALL: Self = Self {
+        h: true,
+        l: true,
+        a: true,
+        b_g1: true,
+        b_g2: true,
+    };
 
-        let b_g1_witness: Vec<G1Affine> = pk.b_g1_query[num_pub..].to_vec();
-        let emsm_b_g1 = EmsmPublicParams::<G1>::new(b_g1_witness, rng);
-        let pre_b_g1 = emsm_b_g1.preprocess();
+    /// No MSM to the server, e.g. to name the delegated ones with
+    /// `Delegation { h: true, b_g2: true, ..Delegation::NONE }`.
+    pub const NONE: Self = Self {
+        h: false,
+        l: false,
+        a: false,
+        b_g1: false,
+        b_g2: false,
+    };
 
-        let b_g2_witness: Vec<G2Affine> = pk.b_g2_query[num_pub..].to_vec();
-        let emsm_b_g2 = EmsmPublicParams::<G2>::new(b_g2_witness, rng);
-        let pre_b_g2 = emsm_b_g2.preprocess();
+    /// Whether all five MSMs go to the server.
+    pub fn is_full(&self) -> bool {
+        *self == Self::ALL
+    }
 
-        Self {
-            pk,
+    /// Whether each MSM goes to the server, in `MsmKind::ALL` order.
+    pub fn msms(&self) -> [bool; 5] {
+        [self.h, self.l, self.a, self.b_g1, self.b_g2]
+    }
+}
+
+impl Default for Delegation {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// The R1CS-to-QAP reduction a proving key was generated for. The witness maps
+/// differ, so masking a witness with the wrong one yields proofs that do not verify.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QapReduction {
+    /// arkworks' `LibsnarkReduction`, used by `Groth16::<Bn254>` setups.
+    #[default]
+    Libsnark,
+    /// `CircomReduction`, for keys from snarkjs zkeys or `circom_setup`.
+    Circom,
+}
+
+impl QapReduction {
+    /// Infer the reduction from the number of h query bases: `LibsnarkReduction`
+    /// emits domain size - 1 of them and `CircomReduction` the domain size, which
+    /// is a power of two.
+    pub fn detect(h_query_len: usize) -> Option<Self> {
+        if (h_query_len + 1).is_power_of_two() {
+            Some(Self::Libsnark)
+        } else if h_query_len.is_power_of_two() {
+            Some(Self::Circom)
+        } else {
+            None
+        }
+    }
+
+    /// `options.reduction`, or the one detected from `h_query_len`.
+    fn resolve(options: &SetupOptions, h_query_len: usize) -> Result<Self, SetupError> {
+        let detected = Self::detect(h_query_len);
+        match (options.reduction, detected) {
+            (Some(reduction), Some(detected)) if reduction != detected => {
+                tracing::warn!(
+                    "Using {reduction:?} QAP reduction, but an h query of {h_query_len} bases suggests {detected:?}"
+                );
+                Ok(reduction)
+            }
+            (Some(reduction), _) => Ok(reduction),
+            (None, Some(detected)) => Ok(detected),
+            (None, None) => Err(SetupError::UnknownReduction { h_query_len }),
+        }
+    }
+}
+
+/// The part of a Groth16 proving key the client still needs once the witness
+/// queries live in the EMSM parameters: the verifying key, the blinding bases and
+/// the public-input rows (index 0 is the constant "1") of the A / B queries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofAssemblyKey {
+    pub vk: VerifyingKey<Bn254>,
+    pub beta_g1: G1Affine,
+    pub delta_g1: G1Affine,
+    pub a_query: Vec<G1Affine>,
+    pub b_g1_query: Vec<G1Affine>,
+    pub b_g2_query: Vec<G2Affine>,
+}
+
+/// Why `ServerAidedProvingKey::setup_with` failed.
+#[derive(Debug, thiserror::Error)]
+pub enum SetupError {
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    InsufficientSecurity(#[from] InsufficientSecurity),
+    #[error("cannot tell the QAP reduction from an h query of {h_query_len} bases; set SetupOptions::reduction")]
+    UnknownReduction { h_query_len: usize },
+    #[error("{msm} MSM: the code rate 1/fold needs a nonzero fold")]
+    ZeroFold { msm: String },
+    #[error("{msm} MSM: noise weight {t} is outside 1..={big_n}")]
+    NoiseWeightOutOfRange { msm: String, t: usize, big_n: usize },
+    /// No noise weight for the MSM's length, level and rate; see `MsmLpnParams::t`.
+    #[error("{msm} MSM: {source}")]
+    LpnParams { msm: String, source: LpnParamsError },
+    #[error(transparent)]
+    InvalidLpnTable(#[from] InvalidLpnTable),
+}
+
+/// A server result `client_decrypt` refuses to unmask. A point outside the
+/// prime-order subgroup survives unmasking and would end up in the proof.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidResponse {
+    #[error("the server's {0:?} MSM result is not on the curve")]
+    NotOnCurve(MsmKind),
+    #[error("the server's {0:?} MSM result is not in the prime-order subgroup")]
+    NotInSubgroup(MsmKind),
+    /// The MSM of a masked vector is a uniformly random point, so an honest
+    /// server returns the identity with negligible probability. Not reported
+    /// for MSMs computed locally, or where `can_be_identity` holds.
+    #[error("the server's {0:?} MSM result is the identity")]
+    Identity(MsmKind),
+}
+
+/// Server-aided proving key: wraps the standard Groth16 proving key with
+/// EMSM parameters for each of the 5 MSMs. The witness portions of the proving
+/// key are moved into the EMSM parameters rather than copied; `proving_key`
+/// reassembles the original key when needed.
+pub struct ServerAidedProvingKey {
+    pub pk: ProofAssemblyKey,
+    pub emsm_h: Arc<EmsmPublicParams<G1>>,
+    pub emsm_l: Arc<EmsmPublicParams<G1>>,
+    pub emsm_a: Arc<EmsmPublicParams<G1>>,
+    pub emsm_b_g1: Arc<EmsmPublicParams<G1>>,
+    pub emsm_b_g2: Arc<EmsmPublicParams<G2>>,
+    pub pre_h: DeferredPreprocessing<G1>,
+    pub pre_l: DeferredPreprocessing<G1>,
+    pub pre_a: DeferredPreprocessing<G1>,
+    pub pre_b_g1: DeferredPreprocessing<G1>,
+    pub pre_b_g2: DeferredPreprocessing<G2>,
+    /// QAP reduction `client_encrypt` computes the h polynomial with.
+    pub reduction: QapReduction,
+    /// MSMs `client_encrypt` masks for the server; `client_decrypt` computes
+    /// the others.
+    pub delegation: Delegation,
+}
+
+impl ServerAidedProvingKey {
+    /// # Panics
+    /// If the key's QAP reduction cannot be detected (see `QapReduction::detect`);
+    /// `setup_with` takes it explicitly.
+    pub fn setup<R: Rng + CryptoRng>(pk: ProvingKey<Bn254>, rng: &mut R) -> Self {
+        Self::setup_with(pk, rng, &SetupOptions::default())
+            .expect("setup with default options only fails for an unknown QAP reduction")
+    }
+
+    /// `setup` with progress reporting, cancellation and a thread-pool budget.
+    /// Progress is reported for each of the 5 MSMs ("h", "l", "a", "b_g1", "b_g2",
+    /// in that order).
+    pub fn setup_with<R: Rng + CryptoRng>(
+        pk: ProvingKey<Bn254>,
+        rng: &mut R,
+        options: &SetupOptions,
+    ) -> Result<Self, SetupError> {
+        Self::setup_with_params(pk, rng, options, &LpnOverrides::default())
+    }
+
+    /// `setup_with` with the LPN parameters of each MSM overridden as `params`
+    /// says. The five queries can differ in size by orders of magnitude, and the
+    /// security check runs against the parameters each one ends up with.
+    pub fn setup_with_params<R: Rng + CryptoRng>(
+        pk: ProvingKey<Bn254>,
+        rng: &mut R,
+        options: &SetupOptions,
+        params: &LpnOverrides,
+    ) -> Result<Self, SetupError> {
+        let ProvingKey {
+            vk,
+            beta_g1,
+            delta_g1,
+            mut a_query,
+            mut b_g1_query,
+            mut b_g2_query,
+            h_query,
+            l_query,
+        } = pk;
+
+        let reduction = QapReduction::resolve(options, h_query.len())?;
+        let (emsm_h, pre_h) = preprocess_msm::<G1, _>(h_query, "h", rng, options, &params.h)?;
+        let (emsm_l, pre_l) = preprocess_msm::<G1, _>(l_query, "l", rng, options, &params.l)?;
+
+        // Split each query into public-input rows (kept) and witness rows (moved
+        // into the EMSM parameters).
+        let num_pub = vk.gamma_abc_g1.len();
+
+        let a_witness: Vec<G1Affine> = a_query.split_off(num_pub);
+        let (emsm_a, pre_a) = preprocess_msm::<G1, _>(a_witness, "a", rng, options, &params.a)?;
+
+        let b_g1_witness: Vec<G1Affine> = b_g1_query.split_off(num_pub);
+        let (emsm_b_g1, pre_b_g1) =
+            preprocess_msm::<G1, _>(b_g1_witness, "b_g1", rng, options, &params.b_g1)?;
+
+        let b_g2_witness: Vec<G2Affine> = b_g2_query.split_off(num_pub);
+        let (emsm_b_g2, pre_b_g2) =
+            preprocess_msm::<G2, _>(b_g2_witness, "b_g2", rng, options, &params.b_g2)?;
+
+        // `split_off` leaves the original capacity behind
+        a_query.shrink_to_fit();
+        b_g1_query.shrink_to_fit();
+        b_g2_query.shrink_to_fit();
+
+        Ok(Self {
+            pk: ProofAssemblyKey {
+                vk,
+                beta_g1,
+                delta_g1,
+                a_query,
+                b_g1_query,
+                b_g2_query,
+            },
+            emsm_h,
+            emsm_l,
+            emsm_a,
+            emsm_b_g1,
+            emsm_b_g2,
+            pre_h,
+            pre_l,
+            pre_a,
+            pre_b_g1,
+            pre_b_g2,
+            reduction,
+            delegation: Delegation::ALL,
+        })
+    }
+
+    /// Build the key from a proving key serialized with `CanonicalSerialize`
+    /// (`compress` must match how it was written), reading and preprocessing one
+    /// query at a time so that the full `ProvingKey` is never held in memory.
+    /// Peak memory is the finished EMSM instances plus one query being preprocessed.
+    ///
+    /// Queries are consumed in serialization order, so progress is reported for
+    /// "a", "b_g1", "b_g2", "h", "l", and the EMSM randomness is drawn in that order
+    /// (the same `rng` seed yields different masks than `setup_with`).
+    pub fn setup_from_reader<Rd: Read, R: Rng + CryptoRng>(
+        mut reader: Rd,
+        compress: Compress,
+        rng: &mut R,
+        options: &SetupOptions,
+    ) -> Result<Self, StealthSnarkError> {
+        let validate = Validate::Yes;
+        let default = MsmLpnParams::default();
+
+        let vk = VerifyingKey::<Bn254>::deserialize_with_mode(&mut reader, compress, validate)?;
+        let beta_g1 = G1Affine::deserialize_with_mode(&mut reader, compress, validate)?;
+        let delta_g1 = G1Affine::deserialize_with_mode(&mut reader, compress, validate)?;
+        let num_pub = vk.gamma_abc_g1.len();
+
+        let (a_query, a_witness) = read_query(&mut reader, num_pub, compress)?;
+        let (emsm_a, pre_a) = preprocess_msm::<G1, _>(a_witness, "a", rng, options, &default)?;
+
+        let (b_g1_query, b_g1_witness) = read_query(&mut reader, num_pub, compress)?;
+        let (emsm_b_g1, pre_b_g1) =
+            preprocess_msm::<G1, _>(b_g1_witness, "b_g1", rng, options, &default)?;
+
+        let (b_g2_query, b_g2_witness) = read_query(&mut reader, num_pub, compress)?;
+        let (emsm_b_g2, pre_b_g2) =
+            preprocess_msm::<G2, _>(b_g2_witness, "b_g2", rng, options, &default)?;
+
+        let (_, h_query) = read_query(&mut reader, 0, compress)?;
+        let reduction = QapReduction::resolve(options, h_query.len())?;
+        let (emsm_h, pre_h) = preprocess_msm::<G1, _>(h_query, "h", rng, options, &default)?;
+
+        let (_, l_query) = read_query(&mut reader, 0, compress)?;
+        let (emsm_l, pre_l) = preprocess_msm::<G1, _>(l_query, "l", rng, options, &default)?;
+
+        Ok(Self {
+            pk: ProofAssemblyKey {
+                vk,
+                beta_g1,
+                delta_g1,
+                a_query,
+                b_g1_query,
+                b_g2_query,
+            },
             emsm_h,
             emsm_l,
             emsm_a,
@@ -67,99 +410,705 @@ impl ServerAidedProvingKey {
             pre_a,
             pre_b_g1,
             pre_b_g2,
+            reduction,
+            delegation: Delegation::ALL,
+        })
+    }
+
+    /// Reassemble the standard Groth16 proving key, e.g. to prove locally when no
+    /// server is available.
+    pub fn proving_key(&self) -> ProvingKey<Bn254> {
+        ProvingKey {
+            vk: self.pk.vk.clone(),
+            beta_g1: self.pk.beta_g1,
+            delta_g1: self.pk.delta_g1,
+            a_query: [&self.pk.a_query[..], &self.emsm_a.generators].concat(),
+            b_g1_query: [&self.pk.b_g1_query[..], &self.emsm_b_g1.generators].concat(),
+            b_g2_query: [&self.pk.b_g2_query[..], &self.emsm_b_g2.generators].concat(),
+            h_query: self.emsm_h.generators.clone(),
+            l_query: self.emsm_l.generators.clone(),
+        }
+    }
+
+    /// Run the 5 EMSM instances under `parallel`. Parameters still shared with
+    /// pending deferred preprocessing are copied rather than updated in place.
+    pub fn with_parallel(mut self, parallel: ParallelConfig) -> Self {
+        Arc::make_mut(&mut self.emsm_h).set_parallel(parallel.clone());
+        Arc::make_mut(&mut self.emsm_l).set_parallel(parallel.clone());
+        Arc::make_mut(&mut self.emsm_a).set_parallel(parallel.clone());
+        Arc::make_mut(&mut self.emsm_b_g1).set_parallel(parallel.clone());
+        Arc::make_mut(&mut self.emsm_b_g2).set_parallel(parallel);
+        self
+    }
+
+    /// Send only the MSMs `delegation` names to the server and compute the
+    /// rest on the client. Only semi-honest and cut-and-choose proving support
+    /// a partial delegation: the check queries of malicious and covert mode
+    /// cover all five MSMs.
+    pub fn with_delegation(mut self, delegation: Delegation) -> Self {
+        self.delegation = delegation;
+        self
+    }
+
+    /// Whether an honest server's result for the `kind` MSM can be the
+    /// identity, whatever the masked vector: only if all of its generators
+    /// are, e.g. the B rows of witnesses that appear in no B term.
+    pub fn can_be_identity(&self, kind: MsmKind) -> bool {
+        fn all_identity<A: AffineRepr>(generators: &[A]) -> bool {
+            generators.iter().all(AffineRepr::is_zero)
+        }
+        match kind {
+            MsmKind::H => all_identity(&self.emsm_h.generators),
+            MsmKind::L => all_identity(&self.emsm_l.generators),
+            MsmKind::A => all_identity(&self.emsm_a.generators),
+            MsmKind::BG1 => all_identity(&self.emsm_b_g1.generators),
+            MsmKind::BG2 => all_identity(&self.emsm_b_g2.generators),
+            // Not masked: l + 1 public inputs, which may all be zero but the constant
+            MsmKind::Public => true,
+        }
+    }
+}
+
+/// Build the EMSM instance for one MSM with the LPN parameters `lpn` resolves
+/// to, and preprocess it as `options.preprocess` says, going through the cache
+/// if `options` has one.
+fn preprocess_msm<G: CurveGroup, R: Rng + CryptoRng>(
+    generators: Vec<G::Affine>,
+    msm: &str,
+    rng: &mut R,
+    options: &SetupOptions,
+    lpn: &MsmLpnParams,
+) -> Result<(Arc<EmsmPublicParams<G>>, DeferredPreprocessing<G>), SetupError> {
+    if let Some(table) = options.lpn_params {
+        table.validate()?;
+    }
+    let n = generators.len();
+    let security = lpn.security.unwrap_or(options.security);
+    let fold = lpn.fold.unwrap_or(DEFAULT_FOLD);
+    let lpn_error = |source| SetupError::LpnParams {
+        msm: msm.to_string(),
+        source,
+    };
+    if fold == 0 {
+        return Err(SetupError::ZeroFold {
+            msm: msm.to_string(),
+        });
+    }
+    let t = match lpn.t {
+        Some(t) if t == 0 || t > (fold * n).max(1) => {
+            return Err(SetupError::NoiseWeightOutOfRange {
+                msm: msm.to_string(),
+                t,
+                big_n: (fold * n).max(1),
+            })
+        }
+        Some(t) => t,
+        None => {
+            let params = match options.lpn_params {
+                Some(table) => table.params(n, security, fold),
+                None => try_get_lpn_params_at(n, security, fold),
+            };
+            params.map_err(lpn_error)?.t
+        }
+    };
+    options
+        .security_check
+        .check_params(msm, n, 1.0 / fold as f64, t, security)?;
+    let configure = |params: EmsmPublicParams<G>| {
+        let mut params = params
+            .with_noise(options.noise)
+            .with_parallel(options.parallel.clone());
+        (params.t, params.security) = (t, security);
+        params
+    };
+    // Cache entries are for the rate-1/4 code
+    let cache = options.cache.filter(|_| fold == DEFAULT_FOLD);
+
+    if options.preprocess == PreprocessMode::Eager {
+        let preprocess = PreprocessOptions {
+            progress: options.progress,
+            cancel: options.cancel,
+        };
+        let (params, pre) = match cache {
+            Some(cache) => cache.params_and_preprocess(
+                generators,
+                options.permutations,
+                msm,
+                rng,
+                &preprocess,
+            )?,
+            None => {
+                let permutations = options.permutations;
+                let params =
+                    EmsmPublicParams::new_with_fold_and_t(generators, fold, t, permutations, rng)
+                        .map_err(lpn_error)?;
+                let pre = params.preprocess_with(msm, &preprocess)?;
+                (params, pre)
+            }
+        };
+        return Ok((
+            Arc::new(configure(params)),
+            DeferredPreprocessing::ready(pre),
+        ));
+    }
+
+    // Deferred: a cache hit is used right away; on a miss the seed is fixed now so
+    // the entry can be written once preprocessing finishes.
+    let entry = cache.map(|cache| cache.entry::<G>(&generators, options.permutations));
+    let permutations = options.permutations;
+    if let Some((seed, pre)) = entry.as_ref().and_then(|entry| entry.load::<G>()) {
+        let params = EmsmPublicParams::from_seed_with_fold(generators, seed, fold, t, permutations)
+            .map_err(lpn_error)?;
+        return Ok((
+            Arc::new(configure(params)),
+            DeferredPreprocessing::ready(pre),
+        ));
+    }
+    let store = entry.map(|entry| (entry, rng.gen::<[u8; 32]>()));
+    let params = match &store {
+        Some((_, seed)) => {
+            EmsmPublicParams::from_seed_with_fold(generators, *seed, fold, t, permutations)
         }
+        None => EmsmPublicParams::new_with_fold_and_t(generators, fold, t, permutations, rng),
     }
+    .map_err(lpn_error)?;
+    let params = Arc::new(configure(params));
+
+    let shared = params.clone();
+    let init = move || {
+        let pre = shared.preprocess();
+        if let Some((entry, seed)) = store {
+            entry.store(&seed, &pre);
+        }
+        pre
+    };
+    let pre = match options.preprocess {
+        PreprocessMode::Lazy => DeferredPreprocessing::lazy(init),
+        _ => DeferredPreprocessing::background(init),
+    };
+    Ok((params, pre))
+}
+
+/// Read one serialized query vector, splitting off its first `num_pub` rows.
+/// Both halves are allocated at their exact size up front, so a bogus length
+/// prefix fails instead of aborting on allocation.
+fn read_query<T: CanonicalDeserialize, Rd: Read>(
+    reader: &mut Rd,
+    num_pub: usize,
+    compress: Compress,
+) -> Result<(Vec<T>, Vec<T>), SerializationError> {
+    let len = u64::deserialize_with_mode(&mut *reader, compress, Validate::Yes)? as usize;
+    if len < num_pub {
+        return Err(SerializationError::InvalidData);
+    }
+    let mut public = Vec::new();
+    let mut witness = Vec::new();
+    public
+        .try_reserve_exact(num_pub)
+        .and_then(|_| witness.try_reserve_exact(len - num_pub))
+        .map_err(|_| SerializationError::InvalidData)?;
+    for i in 0..len {
+        let row = T::deserialize_with_mode(&mut *reader, compress, Validate::Yes)?;
+        if i < num_pub {
+            public.push(row);
+        } else {
+            witness.push(row);
+        }
+    }
+    Ok((public, witness))
 }
 
 /// Client-side state kept during proving (between encrypt and decrypt).
 pub struct ClientDecryptionState {
     pub r: Fr,
     pub s: Fr,
-    pub lpn_h: DualLPNInstance<Fr>,
-    pub lpn_l: DualLPNInstance<Fr>,
-    pub lpn_a: DualLPNInstance<Fr>,
-    pub lpn_b_g1: DualLPNInstance<Fr>,
-    pub lpn_b_g2: DualLPNInstance<Fr>,
+    /// LPN noise for each masked MSM, needed to decrypt its result; empty for
+    /// the MSMs computed locally.
+    pub lpn_h: SparseVector<Fr>,
+    pub lpn_l: SparseVector<Fr>,
+    pub lpn_a: SparseVector<Fr>,
+    pub lpn_b_g1: SparseVector<Fr>,
+    pub lpn_b_g2: SparseVector<Fr>,
     pub num_instance_variables: usize,
     pub full_assignment: Vec<Fr>,
+    /// The MSMs that were masked for the server, as the key's delegation said
+    /// at encrypt time.
+    pub delegation: Delegation,
+    /// The h polynomial, kept for `client_decrypt` when it computes the h MSM
+    /// itself, or recomputes it under `debug-msm`. Empty otherwise.
+    pub h_poly: Vec<Fr>,
+}
+
+impl ClientDecryptionState {
+    /// The circuit's public inputs, without the leading constant 1.
+    pub fn public_inputs(&self) -> &[Fr] {
+        &self.full_assignment[1..self.num_instance_variables]
+    }
+}
+
+/// The public-input parts of the A and B queries, constant term first, for
+/// evaluating the public-input MSMs on a server. They are not secret, so the
+/// inputs are sent in the clear. Worth it for circuits with tens of thousands of
+/// public inputs.
+#[derive(Clone, CanonicalSerialize, CanonicalDeserialize)]
+pub struct PublicGenerators {
+    pub a: Vec<G1Affine>,
+    pub b_g1: Vec<G1Affine>,
+    pub b_g2: Vec<G2Affine>,
+}
+
+impl From<&ServerAidedProvingKey> for PublicGenerators {
+    fn from(sapk: &ServerAidedProvingKey) -> Self {
+        let num_instance_variables = sapk.pk.vk.gamma_abc_g1.len();
+        Self {
+            a: sapk.pk.a_query[..num_instance_variables].to_vec(),
+            b_g1: sapk.pk.b_g1_query[..num_instance_variables].to_vec(),
+            b_g2: sapk.pk.b_g2_query[..num_instance_variables].to_vec(),
+        }
+    }
+}
+
+impl PublicGenerators {
+    /// Check that the three queries have the same length, and at least the
+    /// constant term, as `evaluate` needs. A server runs this on upload.
+    pub fn validate(&self) -> Result<(), StealthSnarkError> {
+        if self.a.is_empty() {
+            return Err(StealthSnarkError::Malformed(
+                "public generators without the constant term".to_string(),
+            ));
+        }
+        for (what, len) in [
+            ("B (G1) public generators", self.b_g1.len()),
+            ("B (G2) public generators", self.b_g2.len()),
+        ] {
+            if len != self.a.len() {
+                return Err(DimensionMismatch {
+                    what,
+                    expected: self.a.len(),
+                    actual: len,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The public-input contributions to A and B for `public_inputs`, which
+    /// exclude the constant 1.
+    pub fn evaluate(&self, public_inputs: &[Fr]) -> Result<PublicInputResponse, StealthSnarkError> {
+        self.validate()?;
+        if public_inputs.len() + 1 != self.a.len() {
+            return Err(DimensionMismatch {
+                what: "public inputs",
+                expected: self.a.len().saturating_sub(1),
+                actual: public_inputs.len(),
+            }
+            .into());
+        }
+        Ok(public_input_msms(&self.a, &self.b_g1, &self.b_g2, public_inputs))
+    }
+}
+
+/// Σ generators[i] · [1 || public_inputs][i] for each query; all of the same
+/// length, one more than `public_inputs`.
+fn public_input_msms(
+    a: &[G1Affine],
+    b_g1: &[G1Affine],
+    b_g2: &[G2Affine],
+    public_inputs: &[Fr],
+) -> PublicInputResponse {
+    let scalars: Vec<Fr> = std::iter::once(Fr::one())
+        .chain(public_inputs.iter().copied())
+        .collect();
+    PublicInputResponse {
+        a: G1::msm_unchecked(a, &scalars),
+        b_g1: G1::msm_unchecked(b_g1, &scalars),
+        b_g2: G2::msm_unchecked(b_g2, &scalars),
+    }
+}
+
+/// Public-input contributions to A (G1) and B (G1 and G2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicInputResponse {
+    pub a: G1,
+    pub b_g1: G1,
+    pub b_g2: G2,
+}
+
+impl PublicInputResponse {
+    /// Check the results before they enter the proof: every point on the
+    /// curve and in its prime-order subgroup. They are not masked, so the
+    /// identity is a valid result. `client_decrypt_with_public` runs this
+    /// first.
+    pub fn validate(&self) -> Result<(), InvalidResponse> {
+        let invalid = |e: InvalidPoint| match e {
+            InvalidPoint::NotOnCurve(_) => InvalidResponse::NotOnCurve(MsmKind::Public),
+            InvalidPoint::NotInSubgroup(_) => InvalidResponse::NotInSubgroup(MsmKind::Public),
+        };
+        validate_points(&G1::normalize_batch(&[self.a, self.b_g1])).map_err(invalid)?;
+        validate_points(&[self.b_g2.into_affine()]).map_err(invalid)
+    }
+}
+
+/// Assignment of a synthesized circuit: `[1 || public inputs || witness]`.
+pub struct SynthesizedWitness {
+    pub full_assignment: Vec<Fr>,
+    pub num_instance_variables: usize,
 }
 
 /// Data sent to the server: 5 masked scalar vectors.
+#[derive(Serialize, Deserialize)]
 pub struct EncryptedRequest {
+    #[serde(with = "ark_serde_vec")]
     pub v_h: Vec<Fr>,
+    #[serde(with = "ark_serde_vec")]
     pub v_l: Vec<Fr>,
+    #[serde(with = "ark_serde_vec")]
     pub v_a: Vec<Fr>,
+    #[serde(with = "ark_serde_vec")]
     pub v_b_g1: Vec<Fr>,
+    #[serde(with = "ark_serde_vec")]
     pub v_b_g2: Vec<Fr>,
 }
 
 /// Server's response: 5 MSM results.
+#[derive(Serialize, Deserialize)]
 pub struct ServerResponse {
+    #[serde(with = "ark_serde")]
     pub em_h: G1,
+    #[serde(with = "ark_serde")]
     pub em_l: G1,
+    #[serde(with = "ark_serde")]
     pub em_a: G1,
+    #[serde(with = "ark_serde")]
     pub em_b_g1: G1,
+    #[serde(with = "ark_serde")]
     pub em_b_g2: G2,
 }
 
+impl ServerResponse {
+    /// Check the results before they are unmasked: every point on the curve
+    /// and in its prime-order subgroup, and none the identity where the MSM
+    /// was delegated (see `InvalidResponse::Identity`). The G1 results are
+    /// normalized with one inversion and checked as a batch. `client_decrypt`
+    /// runs this first.
+    pub fn validate(
+        &self,
+        sapk: &ServerAidedProvingKey,
+        delegation: Delegation,
+    ) -> Result<(), InvalidResponse> {
+        let invalid = |kinds: &[MsmKind], e: InvalidPoint| match e {
+            InvalidPoint::NotOnCurve(i) => InvalidResponse::NotOnCurve(kinds[i]),
+            InvalidPoint::NotInSubgroup(i) => InvalidResponse::NotInSubgroup(kinds[i]),
+        };
+        let g1 = G1::normalize_batch(&[self.em_h, self.em_l, self.em_a, self.em_b_g1]);
+        validate_points(&g1).map_err(|e| invalid(&MsmKind::ALL[..4], e))?;
+        let g2 = self.em_b_g2.into_affine();
+        validate_points(&[g2]).map_err(|e| invalid(&[MsmKind::BG2], e))?;
+
+        let identity = g1.iter().map(AffineRepr::is_zero).chain([g2.is_zero()]);
+        let results = MsmKind::ALL
+            .into_iter()
+            .zip(delegation.msms())
+            .zip(identity);
+        for ((kind, delegated), identity) in results {
+            if identity && delegated && !sapk.can_be_identity(kind) {
+                return Err(InvalidResponse::Identity(kind));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Client encrypt: synthesize circuit, extract witness, compute QAP, mask vectors.
-pub fn client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R: Rng>(
+/// The QAP reduction is the one recorded in `sapk.reduction`.
+pub fn client_encrypt<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    rng: &mut R,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError> {
+    client_encrypt_with_cancel(sapk, circuit, rng, &CancelToken::default())
+}
+
+/// `client_encrypt`, checking `cancel` between synthesis, the QAP reduction and each
+/// masking step. A cancelled run fails with a `Cancelled` error.
+pub fn client_encrypt_with_cancel<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    rng: &mut R,
+    cancel: &CancelToken,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError> {
+    match sapk.reduction {
+        QapReduction::Libsnark => {
+            client_encrypt_with_reduction::<LibsnarkReduction, C, R>(sapk, circuit, rng, cancel)
+        }
+        QapReduction::Circom => {
+            client_encrypt_with_reduction::<CircomReduction, C, R>(sapk, circuit, rng, cancel)
+        }
+    }
+}
+
+/// `client_encrypt_with_cancel` computing the h polynomial with `QAP` instead of
+/// `sapk.reduction`.
+pub fn client_encrypt_with_reduction<
+    QAP: R1CSToQAP,
+    C: ConstraintSynthesizer<Fr>,
+    R: Rng + CryptoRng,
+>(
     sapk: &ServerAidedProvingKey,
     circuit: C,
     rng: &mut R,
-) -> Result<(EncryptedRequest, ClientDecryptionState), anyhow::Error> {
+    cancel: &CancelToken,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError> {
+    let mut masked = Vec::with_capacity(5);
+    let state = client_encrypt_each::<QAP, C, R>(sapk, circuit, rng, cancel, |piece| {
+        collect_masked(&mut masked, piece);
+        Ok(())
+    })?;
+    let [v_h, v_l, v_a, v_b_g1, v_b_g2]: [Vec<Fr>; 5] =
+        masked.try_into().expect("one masked vector per MSM");
+
+    let request = EncryptedRequest {
+        v_h,
+        v_l,
+        v_a,
+        v_b_g1,
+        v_b_g2,
+    };
+    Ok((request, state))
+}
+
+/// `client_encrypt`, writing the request to `writer` as it is masked instead of
+/// returning it. The bytes are exactly `bincode::serialize(&ProveRequest::from(&request))`,
+/// ready for `EmsmClient::send_prove_encoded`, but no masked vector is held
+/// whole: each is masked and written `STREAM_CHUNK` scalars at a time. For
+/// circuits with millions of witnesses this avoids holding all five masked
+/// vectors plus their encoding.
+pub fn client_encrypt_to_writer<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng, W: Write>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    rng: &mut R,
+    writer: &mut W,
+) -> Result<ClientDecryptionState, StealthSnarkError> {
+    let cancel = CancelToken::default();
+    let mut buf = Vec::new();
+    let emit = |piece: MaskedPiece<'_>| {
+        match piece {
+            MaskedPiece::Start(len) => write_masked_header(writer, len)?,
+            MaskedPiece::Chunk(chunk) => write_masked_chunk(writer, chunk, &mut buf)?,
+        }
+        Ok(())
+    };
+    let state = match sapk.reduction {
+        QapReduction::Libsnark => {
+            client_encrypt_each::<LibsnarkReduction, C, R>(sapk, circuit, rng, &cancel, emit)
+        }
+        QapReduction::Circom => {
+            client_encrypt_each::<CircomReduction, C, R>(sapk, circuit, rng, &cancel, emit)
+        }
+    }?;
+    // bincode's tag of `ProveRequest::hint: None`
+    writer.write_all(&[0])?;
+    Ok(state)
+}
+
+/// Synthesize, reduce and mask, handing the masked vectors to `emit` piece by
+/// piece as they are masked, in request order (h, l, a, b_g1, b_g2).
+fn client_encrypt_each<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    rng: &mut R,
+    cancel: &CancelToken,
+    emit: impl FnMut(MaskedPiece<'_>) -> Result<(), StealthSnarkError>,
+) -> Result<ClientDecryptionState, StealthSnarkError> {
+    let (witness, h_poly) = synthesize_with_reduction::<QAP, C>(circuit, cancel)?;
+    cancel.check()?;
+    let mut scratch = EncryptScratch::default();
+    encrypt_witness(sapk, witness, h_poly, &mut scratch, rng, cancel, emit)
+}
+
+/// The first half of `client_encrypt`: synthesize `circuit` and compute its h
+/// polynomial with `sapk.reduction`, for `client_encrypt_with_h`. Nothing is
+/// masked yet, so the witness can still be inspected (e.g. to look the proof up
+/// in a `ProofCache`) without spending an LPN query.
+pub fn client_synthesize<C: ConstraintSynthesizer<Fr>>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    cancel: &CancelToken,
+) -> Result<(SynthesizedWitness, Vec<Fr>), StealthSnarkError> {
+    match sapk.reduction {
+        QapReduction::Libsnark => {
+            synthesize_with_reduction::<LibsnarkReduction, C>(circuit, cancel)
+        }
+        QapReduction::Circom => synthesize_with_reduction::<CircomReduction, C>(circuit, cancel),
+    }
+}
+
+fn synthesize_with_reduction<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>>(
+    circuit: C,
+    cancel: &CancelToken,
+) -> Result<(SynthesizedWitness, Vec<Fr>), StealthSnarkError> {
     let cs = ConstraintSystem::<Fr>::new_ref();
     cs.set_optimization_goal(OptimizationGoal::Constraints);
     cs.set_mode(SynthesisMode::Prove { construct_matrices: true });
     circuit.generate_constraints(cs.clone())?;
     cs.finalize();
+    cancel.check()?;
 
     let num_instance_variables = cs.num_instance_variables();
 
     // Use arkworks' own QAP witness map to compute h polynomial
     let h_poly = QAP::witness_map::<Fr, GeneralEvaluationDomain<Fr>>(cs.clone())?;
 
-    // Get the full assignment from the constraint system
-    let cs_inner = cs.borrow().unwrap();
-    let prover = cs_inner.deref();
-    let instance = prover.instance_assignment.clone();
-    let witness = prover.witness_assignment.clone();
-    let mut full_assignment = instance.clone();
-    full_assignment.extend_from_slice(&witness);
-    drop(cs_inner);
+    // Move the assignment out of the constraint system; the masked vectors
+    // borrow the witness from it rather than copying it
+    let witness = SynthesizedWitness {
+        full_assignment: take_full_assignment(cs),
+        num_instance_variables,
+    };
+    Ok((witness, h_poly))
+}
+
+/// Client encrypt for a circuit already synthesized, with its h polynomial
+/// computed elsewhere, e.g. delegated with `witness_map::WitnessMapMasks`.
+pub fn client_encrypt_with_h<R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    witness: SynthesizedWitness,
+    h_poly: Vec<Fr>,
+    rng: &mut R,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError> {
+    client_encrypt_with_h_in(sapk, witness, h_poly, &mut EncryptScratch::default(), rng)
+}
+
+/// `client_encrypt_with_h`, masking in the buffers of `scratch`. A client proving
+/// one witness after another keeps a workspace and passes it to every call, so
+/// the buffers of the largest MSM are allocated once rather than per proof.
+pub fn client_encrypt_with_h_in<R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    witness: SynthesizedWitness,
+    h_poly: Vec<Fr>,
+    scratch: &mut EncryptScratch<Fr>,
+    rng: &mut R,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError> {
+    let mut masked = Vec::with_capacity(5);
+    let cancel = CancelToken::default();
+    let state = encrypt_witness(sapk, witness, h_poly, scratch, rng, &cancel, |piece| {
+        collect_masked(&mut masked, piece);
+        Ok(())
+    })?;
+    let [v_h, v_l, v_a, v_b_g1, v_b_g2]: [Vec<Fr>; 5] =
+        masked.try_into().expect("one masked vector per MSM");
+
+    let request = EncryptedRequest {
+        v_h,
+        v_l,
+        v_a,
+        v_b_g1,
+        v_b_g2,
+    };
+    Ok((request, state))
+}
+
+/// A piece of one masked vector, as `encrypt_witness` emits them.
+enum MaskedPiece<'a> {
+    /// The next vector begins, with this many scalars.
+    Start(usize),
+    /// Its next scalars, at most `STREAM_CHUNK`.
+    Chunk(&'a [Fr]),
+}
+
+/// Collect each masked vector into a `Vec` of its own.
+fn collect_masked(masked: &mut Vec<Vec<Fr>>, piece: MaskedPiece<'_>) {
+    match piece {
+        MaskedPiece::Start(len) => masked.push(Vec::with_capacity(len)),
+        MaskedPiece::Chunk(chunk) => masked
+            .last_mut()
+            .expect("a chunk follows its start")
+            .extend_from_slice(chunk),
+    }
+}
+
+/// Mask `scalars` for `params` in chunks, handing them to `emit` as the pieces
+/// of one vector.
+fn mask_streamed<G: CurveGroup<ScalarField = Fr>, R: Rng + CryptoRng>(
+    params: &EmsmPublicParams<G>,
+    scalars: &[Fr],
+    scratch: &mut EncryptScratch<Fr>,
+    rng: &mut R,
+    emit: &mut impl FnMut(MaskedPiece<'_>) -> Result<(), StealthSnarkError>,
+) -> Result<SparseVector<Fr>, StealthSnarkError> {
+    emit(MaskedPiece::Start(scalars.len()))?;
+    encrypt_chunked(params, scalars, STREAM_CHUNK, scratch, rng, |chunk| {
+        emit(MaskedPiece::Chunk(chunk))
+    })
+}
+
+/// `mask_streamed` for an MSM sent to the server, with `scalars` padded or
+/// trimmed to its generators. An MSM the client computes itself goes out as an
+/// empty vector, with empty noise.
+fn mask_delegated<G: CurveGroup<ScalarField = Fr>, R: Rng + CryptoRng>(
+    delegated: bool,
+    params: &EmsmPublicParams<G>,
+    scalars: &[Fr],
+    scratch: &mut EncryptScratch<Fr>,
+    rng: &mut R,
+    emit: &mut impl FnMut(MaskedPiece<'_>) -> Result<(), StealthSnarkError>,
+) -> Result<SparseVector<Fr>, StealthSnarkError> {
+    if !delegated {
+        emit(MaskedPiece::Start(0))?;
+        return Ok(SparseVector::new(0, Vec::new()));
+    }
+    let scalars = pad_or_trim(scalars, params.generators.len());
+    mask_streamed(params, &scalars, scratch, rng, emit)
+}
+
+/// Mask the h polynomial and the witness in `scratch`, handing the masked
+/// vectors to `emit` in request order, a chunk at a time.
+fn encrypt_witness<R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    witness: SynthesizedWitness,
+    h_poly: Vec<Fr>,
+    scratch: &mut EncryptScratch<Fr>,
+    rng: &mut R,
+    cancel: &CancelToken,
+    mut emit: impl FnMut(MaskedPiece<'_>) -> Result<(), StealthSnarkError>,
+) -> Result<ClientDecryptionState, StealthSnarkError> {
+    let SynthesizedWitness {
+        full_assignment,
+        num_instance_variables,
+    } = witness;
+    let witness = &full_assignment[num_instance_variables..];
 
     // Random blinding factors for zero-knowledge
     let r = Fr::rand(rng);
     let s = Fr::rand(rng);
 
-    // Mask h polynomial
-    let h_scalars = pad_or_trim(&h_poly, sapk.emsm_h.generators.len());
-    let (v_h, lpn_h) = encrypt(&sapk.emsm_h, &h_scalars, rng);
+    // Mask h polynomial, keeping it only if the client computes its MSM
+    let delegation = sapk.delegation;
+    let lpn_h = mask_delegated(delegation.h, &sapk.emsm_h, &h_poly, scratch, rng, &mut emit)?;
+    let h_poly = match delegation.h && !cfg!(feature = "debug-msm") {
+        true => Vec::new(),
+        false => h_poly,
+    };
+    cancel.check()?;
 
     // Mask witness scalars for l_query
-    let l_scalars = pad_or_trim(&witness, sapk.emsm_l.generators.len());
-    let (v_l, lpn_l) = encrypt(&sapk.emsm_l, &l_scalars, rng);
+    let lpn_l = mask_delegated(delegation.l, &sapk.emsm_l, witness, scratch, rng, &mut emit)?;
+    cancel.check()?;
 
     // Mask witness scalars for a_query (witness portion only)
-    let a_scalars = pad_or_trim(&witness, sapk.emsm_a.generators.len());
-    let (v_a, lpn_a) = encrypt(&sapk.emsm_a, &a_scalars, rng);
+    let lpn_a = mask_delegated(delegation.a, &sapk.emsm_a, witness, scratch, rng, &mut emit)?;
+    cancel.check()?;
 
     // Mask witness scalars for b_g1 and b_g2 (independent LPN instances)
-    let b_g1_scalars = pad_or_trim(&witness, sapk.emsm_b_g1.generators.len());
-    let (v_b_g1, lpn_b_g1) = encrypt(&sapk.emsm_b_g1, &b_g1_scalars, rng);
+    let emsm_b_g1 = &sapk.emsm_b_g1;
+    let lpn_b_g1 = mask_delegated(delegation.b_g1, emsm_b_g1, witness, scratch, rng, &mut emit)?;
+    cancel.check()?;
 
-    let b_g2_scalars = pad_or_trim(&witness, sapk.emsm_b_g2.generators.len());
-    let (v_b_g2, lpn_b_g2) = encrypt(&sapk.emsm_b_g2, &b_g2_scalars, rng);
+    let emsm_b_g2 = &sapk.emsm_b_g2;
+    let lpn_b_g2 = mask_delegated(delegation.b_g2, emsm_b_g2, witness, scratch, rng, &mut emit)?;
 
-    let request = EncryptedRequest {
-        v_h,
-        v_l,
-        v_a,
-        v_b_g1,
-        v_b_g2,
-    };
-
-    let state = ClientDecryptionState {
+    Ok(ClientDecryptionState {
         r,
         s,
         lpn_h,
@@ -169,21 +1118,49 @@ pub fn client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R: Rng>(
         lpn_b_g2,
         num_instance_variables,
         full_assignment,
-    };
+        delegation,
+        h_poly,
+    })
+}
 
-    Ok((request, state))
+/// Scalars masked, and encoded per write by `client_encrypt_to_writer`, at a
+/// time.
+const STREAM_CHUNK: usize = 1 << 16;
+
+/// Start one `ProveRequest` field of `len` scalars: bincode's u64 byte length,
+/// then the u64 count of the `ark_vec_to_bytes` encoding. The compressed
+/// scalars follow with `write_masked_chunk`.
+fn write_masked_header<W: Write>(writer: &mut W, len: usize) -> std::io::Result<()> {
+    let scalar_len = Fr::zero().compressed_size();
+    writer.write_all(&((8 + scalar_len * len) as u64).to_le_bytes())?;
+    writer.write_all(&(len as u64).to_le_bytes())
+}
+
+/// Write the compressed scalars of `chunk`, encoding them in `buf`.
+fn write_masked_chunk<W: Write>(
+    writer: &mut W,
+    chunk: &[Fr],
+    buf: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    buf.clear();
+    for x in chunk {
+        x.serialize_compressed(&mut *buf)
+            .map_err(std::io::Error::other)?;
+    }
+    writer.write_all(buf)
 }
 
-/// Server evaluate: compute 5 MSMs on masked vectors.
+/// Server evaluate: compute 5 MSMs on masked vectors. An empty vector stands
+/// for an MSM the client computes itself, and evaluates to the identity.
 pub fn server_evaluate(
     sapk: &ServerAidedProvingKey,
     request: &EncryptedRequest,
-) -> Result<ServerResponse, anyhow::Error> {
-    let em_h = sapk.emsm_h.server_computation(&request.v_h)?;
-    let em_l = sapk.emsm_l.server_computation(&request.v_l)?;
-    let em_a = sapk.emsm_a.server_computation(&request.v_a)?;
-    let em_b_g1 = sapk.emsm_b_g1.server_computation(&request.v_b_g1)?;
-    let em_b_g2 = sapk.emsm_b_g2.server_computation(&request.v_b_g2)?;
+) -> Result<ServerResponse, StealthSnarkError> {
+    let em_h = evaluate_masked(&sapk.emsm_h, &request.v_h)?;
+    let em_l = evaluate_masked(&sapk.emsm_l, &request.v_l)?;
+    let em_a = evaluate_masked(&sapk.emsm_a, &request.v_a)?;
+    let em_b_g1 = evaluate_masked(&sapk.emsm_b_g1, &request.v_b_g1)?;
+    let em_b_g2 = evaluate_masked(&sapk.emsm_b_g2, &request.v_b_g2)?;
 
     Ok(ServerResponse {
         em_h,
@@ -194,46 +1171,199 @@ pub fn server_evaluate(
     })
 }
 
+fn evaluate_masked<G: CurveGroup<ScalarField = Fr>>(
+    params: &EmsmPublicParams<G>,
+    masked: &[Fr],
+) -> Result<G, PedersenError> {
+    if masked.is_empty() {
+        return Ok(G::zero());
+    }
+    params.server_computation(masked)
+}
+
 /// Client decrypt: unmask server results and assemble the Groth16 proof.
+/// The MSMs the key does not delegate are computed here, from the witness.
+/// Fails without unmasking anything if `response` does not pass
+/// `ServerResponse::validate`.
 pub fn client_decrypt(
     sapk: &ServerAidedProvingKey,
     response: &ServerResponse,
     state: &ClientDecryptionState,
+) -> Result<Proof<Bn254>, StealthSnarkError> {
+    response.validate(sapk, state.delegation)?;
+    let cancel = CancelToken::default();
+    let proof = decrypt_and_assemble(sapk, response, None, state, &cancel)?;
+    Ok(proof)
+}
+
+/// `client_decrypt`, checking `cancel` between the 5 unmasking or local MSMs.
+pub fn client_decrypt_with_cancel(
+    sapk: &ServerAidedProvingKey,
+    response: &ServerResponse,
+    state: &ClientDecryptionState,
+    cancel: &CancelToken,
+) -> Result<Proof<Bn254>, StealthSnarkError> {
+    response.validate(sapk, state.delegation)?;
+    Ok(decrypt_and_assemble(sapk, response, None, state, cancel)?)
+}
+
+/// `client_decrypt` with the public-input MSMs evaluated by the server
+/// (`PublicGenerators::evaluate` on `state.public_inputs()`) instead of locally.
+pub fn client_decrypt_with_public(
+    sapk: &ServerAidedProvingKey,
+    response: &ServerResponse,
+    public: &PublicInputResponse,
+    state: &ClientDecryptionState,
+) -> Result<Proof<Bn254>, StealthSnarkError> {
+    response.validate(sapk, state.delegation)?;
+    public.validate()?;
+    let cancel = CancelToken::default();
+    let proof = decrypt_and_assemble(sapk, response, Some(public), state, &cancel)?;
+    Ok(proof)
+}
+
+/// The unmasked results of the five MSMs of a prove request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnmaskedMsms {
+    pub h: G1,
+    pub l: G1,
+    pub a: G1,
+    pub b_g1: G1,
+    pub b_g2: G2,
+}
+
+/// Assemble the proof from MSM results the caller unmasked itself, one at a
+/// time as the server returned them (see `EmsmClient::prove_split`).
+/// `client_decrypt` unmasks and assembles in one go.
+pub fn assemble_proof(
+    sapk: &ServerAidedProvingKey,
+    msms: &UnmaskedMsms,
+    state: &ClientDecryptionState,
 ) -> Proof<Bn254> {
-    let h_msm = decrypt(response.em_h, &state.lpn_h, &sapk.pre_h);
-    let l_msm = decrypt(response.em_l, &state.lpn_l, &sapk.pre_l);
-    let a_witness_msm = decrypt(response.em_a, &state.lpn_a, &sapk.pre_a);
-    let b_g1_witness_msm = decrypt(response.em_b_g1, &state.lpn_b_g1, &sapk.pre_b_g1);
-    let b_g2_witness_msm: G2 = decrypt(response.em_b_g2, &state.lpn_b_g2, &sapk.pre_b_g2);
+    assemble(sapk, msms, None, state)
+}
 
-    // Compute the public-input portions locally
-    let num_pub = state.num_instance_variables;
-    let public_inputs = &state.full_assignment[1..num_pub]; // skip "1" constant
+fn decrypt_and_assemble(
+    sapk: &ServerAidedProvingKey,
+    response: &ServerResponse,
+    public: Option<&PublicInputResponse>,
+    state: &ClientDecryptionState,
+    cancel: &CancelToken,
+) -> Result<Proof<Bn254>, PedersenError> {
+    // The server's results for MSMs computed locally are ignored
+    let local = local_msms(sapk, state, cancel)?;
+    let delegation = state.delegation;
+    cancel.check()?;
+    let h = match delegation.h {
+        true => decrypt(response.em_h, &state.lpn_h, sapk.pre_h.get())?,
+        false => local.h,
+    };
+    cancel.check()?;
+    let l = match delegation.l {
+        true => decrypt(response.em_l, &state.lpn_l, sapk.pre_l.get())?,
+        false => local.l,
+    };
+    cancel.check()?;
+    let a = match delegation.a {
+        true => decrypt(response.em_a, &state.lpn_a, sapk.pre_a.get())?,
+        false => local.a,
+    };
+    cancel.check()?;
+    let b_g1 = match delegation.b_g1 {
+        true => decrypt(response.em_b_g1, &state.lpn_b_g1, sapk.pre_b_g1.get())?,
+        false => local.b_g1,
+    };
+    cancel.check()?;
+    let b_g2 = match delegation.b_g2 {
+        true => decrypt(response.em_b_g2, &state.lpn_b_g2, sapk.pre_b_g2.get())?,
+        false => local.b_g2,
+    };
+    let msms = UnmaskedMsms {
+        h,
+        l,
+        a,
+        b_g1,
+        b_g2,
+    };
+    Ok(assemble(sapk, &msms, public, state))
+}
 
-    // A: public input contribution
-    let mut a_pub = G1::zero();
-    for (i, &input) in public_inputs.iter().enumerate() {
-        if !input.is_zero() {
-            a_pub += sapk.pk.a_query[i + 1] * input;
+/// The MSMs `state` did not delegate, computed from its witness and the key's
+/// generators, checking `cancel` between them. The delegated ones are the
+/// identity; `client_decrypt` and `EmsmClient::prove_split` take those from
+/// the server.
+pub fn local_msms(
+    sapk: &ServerAidedProvingKey,
+    state: &ClientDecryptionState,
+    cancel: &CancelToken,
+) -> Result<UnmaskedMsms, Cancelled> {
+    fn local<G: CurveGroup<ScalarField = Fr>>(
+        delegated: bool,
+        params: &EmsmPublicParams<G>,
+        scalars: &[Fr],
+        cancel: &CancelToken,
+    ) -> Result<G, Cancelled> {
+        cancel.check()?;
+        if delegated {
+            return Ok(G::zero());
         }
+        let scalars = pad_or_trim(scalars, params.generators.len());
+        Ok(params
+            .server_computation(&scalars)
+            .expect("scalars are padded to the generators"))
     }
-    // a_query[0] * 1 (the constant)
-    let a_const: G1 = sapk.pk.a_query[0].into();
-    a_pub += a_const;
+    let delegation = state.delegation;
+    let witness = &state.full_assignment[state.num_instance_variables..];
+    Ok(UnmaskedMsms {
+        h: local(delegation.h, &sapk.emsm_h, &state.h_poly, cancel)?,
+        l: local(delegation.l, &sapk.emsm_l, witness, cancel)?,
+        a: local(delegation.a, &sapk.emsm_a, witness, cancel)?,
+        b_g1: local(delegation.b_g1, &sapk.emsm_b_g1, witness, cancel)?,
+        b_g2: local(delegation.b_g2, &sapk.emsm_b_g2, witness, cancel)?,
+    })
+}
 
-    // B: public input contribution (G1 and G2)
-    let mut b_g1_pub = G1::zero();
-    let mut b_g2_pub = G2::zero();
-    for (i, &input) in public_inputs.iter().enumerate() {
-        if !input.is_zero() {
-            b_g1_pub += sapk.pk.b_g1_query[i + 1] * input;
-            b_g2_pub += sapk.pk.b_g2_query[i + 1] * input;
-        }
+fn assemble(
+    sapk: &ServerAidedProvingKey,
+    msms: &UnmaskedMsms,
+    public: Option<&PublicInputResponse>,
+    state: &ClientDecryptionState,
+) -> Proof<Bn254> {
+    let &UnmaskedMsms {
+        h: h_msm,
+        l: l_msm,
+        a: a_witness_msm,
+        b_g1: b_g1_witness_msm,
+        b_g2: b_g2_witness_msm,
+    } = msms;
+    #[cfg(feature = "debug-msm")]
+    for msm in diverging_msms(
+        sapk,
+        state,
+        [h_msm, l_msm, a_witness_msm, b_g1_witness_msm],
+        b_g2_witness_msm,
+    ) {
+        tracing::error!("the server's {msm} MSM differs from the local one");
     }
-    let b_g1_const: G1 = sapk.pk.b_g1_query[0].into();
-    let b_g2_const: G2 = sapk.pk.b_g2_query[0].into();
-    b_g1_pub += b_g1_const;
-    b_g2_pub += b_g2_const;
+
+    // Compute the public-input portions locally, unless the server did
+    let public = match public {
+        Some(public) => *public,
+        None => {
+            let num_pub = state.num_instance_variables;
+            public_input_msms(
+                &sapk.pk.a_query[..num_pub],
+                &sapk.pk.b_g1_query[..num_pub],
+                &sapk.pk.b_g2_query[..num_pub],
+                state.public_inputs(),
+            )
+        }
+    };
+    let PublicInputResponse {
+        a: a_pub,
+        b_g1: b_g1_pub,
+        b_g2: b_g2_pub,
+    } = public;
 
     // Assemble proof components
     // pi_a = alpha + a_pub + a_witness + r * delta_g1
@@ -261,11 +1391,99 @@ pub fn client_decrypt(
     }
 }
 
+/// Recompute the five MSMs locally from the proving key and the witness, and
+/// name those whose unmasked server results (h, l, a, b_g1, b_g2) differ. This
+/// costs the client every MSM delegation saves.
+#[cfg(feature = "debug-msm")]
+fn diverging_msms(
+    sapk: &ServerAidedProvingKey,
+    state: &ClientDecryptionState,
+    [h, l, a, b_g1]: [G1; 4],
+    b_g2: G2,
+) -> Vec<&'static str> {
+    fn local<G: CurveGroup<ScalarField = Fr>>(params: &EmsmPublicParams<G>, scalars: &[Fr]) -> G {
+        let scalars = pad_or_trim(scalars, params.generators.len());
+        G::msm(&params.generators, &scalars).expect("scalars are padded to the generators")
+    }
+    let witness = &state.full_assignment[state.num_instance_variables..];
+    let checks = [
+        ("h", h == local(&sapk.emsm_h, &state.h_poly)),
+        ("l", l == local(&sapk.emsm_l, witness)),
+        ("a", a == local(&sapk.emsm_a, witness)),
+        ("b_g1", b_g1 == local(&sapk.emsm_b_g1, witness)),
+        ("b_g2", b_g2 == local(&sapk.emsm_b_g2, witness)),
+    ];
+    checks
+        .into_iter()
+        .filter(|(_, matches)| !matches)
+        .map(|(msm, _)| msm)
+        .collect()
+}
+
+// ─── Async wrappers ──────────────────────────────────────────────────────────
+// Encrypt and decrypt are CPU-bound (synthesis, QAP, LPN masking, unmasking MSMs)
+// and would stall a tokio worker, so these run them on the blocking pool.
+// Dropping the returned future cancels the work at its next stage boundary.
+
+/// Await a blocking task, re-raising its panic on this task.
+async fn join_blocking<T>(task: tokio::task::JoinHandle<T>) -> Result<T, StealthSnarkError> {
+    match task.await {
+        Ok(value) => Ok(value),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `client_encrypt` on tokio's blocking pool. Must be called within a tokio runtime.
+pub async fn client_encrypt_async<C, R>(
+    sapk: Arc<ServerAidedProvingKey>,
+    circuit: C,
+    mut rng: R,
+) -> Result<(EncryptedRequest, ClientDecryptionState), StealthSnarkError>
+where
+    C: ConstraintSynthesizer<Fr> + Send + 'static,
+    R: Rng + CryptoRng + Send + 'static,
+{
+    let cancel = CancelToken::new();
+    let _cancel_on_drop = cancel.drop_guard();
+    join_blocking(tokio::task::spawn_blocking(move || {
+        client_encrypt_with_cancel(&sapk, circuit, &mut rng, &cancel)
+    }))
+    .await?
+}
+
+/// `server_evaluate` on tokio's blocking pool, for proving without a server.
+/// Must be called within a tokio runtime.
+pub async fn server_evaluate_async(
+    sapk: Arc<ServerAidedProvingKey>,
+    request: EncryptedRequest,
+) -> Result<ServerResponse, StealthSnarkError> {
+    join_blocking(tokio::task::spawn_blocking(move || {
+        server_evaluate(&sapk, &request)
+    }))
+    .await?
+}
+
+/// `client_decrypt` on tokio's blocking pool. Must be called within a tokio runtime.
+pub async fn client_decrypt_async(
+    sapk: Arc<ServerAidedProvingKey>,
+    response: ServerResponse,
+    state: ClientDecryptionState,
+) -> Result<Proof<Bn254>, StealthSnarkError> {
+    let cancel = CancelToken::new();
+    let _cancel_on_drop = cancel.drop_guard();
+    join_blocking(tokio::task::spawn_blocking(move || {
+        client_decrypt_with_cancel(&sapk, &response, &state, &cancel)
+    }))
+    .await?
+}
+
 // ─── Malicious-secure variants ───────────────────────────────────────────────
 // These use double-query EMSM (main + check) per MSM so that a cheating server
 // is detected with overwhelming probability.
 
 /// Data sent to the server in malicious mode: 10 masked vectors (5 main + 5 check).
+#[derive(Serialize, Deserialize)]
 pub struct MaliciousEncryptedRequest {
     pub h: MaliciousEncrypted<Fr>,
     pub l: MaliciousEncrypted<Fr>,
@@ -288,25 +1506,59 @@ pub struct MaliciousClientState {
 }
 
 /// Server response in malicious mode: 10 MSM results (5 main + 5 check).
+#[derive(Serialize, Deserialize)]
 pub struct MaliciousServerResponse {
+    #[serde(with = "ark_serde")]
     pub em_h: G1,
+    #[serde(with = "ark_serde")]
     pub em_h_ck: G1,
+    #[serde(with = "ark_serde")]
     pub em_l: G1,
+    #[serde(with = "ark_serde")]
     pub em_l_ck: G1,
+    #[serde(with = "ark_serde")]
     pub em_a: G1,
+    #[serde(with = "ark_serde")]
     pub em_a_ck: G1,
+    #[serde(with = "ark_serde")]
     pub em_b_g1: G1,
+    #[serde(with = "ark_serde")]
     pub em_b_g1_ck: G1,
+    #[serde(with = "ark_serde")]
     pub em_b_g2: G2,
+    #[serde(with = "ark_serde")]
     pub em_b_g2_ck: G2,
 }
 
-/// Malicious-secure client encrypt: double-query per MSM.
-pub fn malicious_client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R: Rng>(
+/// Malicious-secure client encrypt: double-query per MSM, with the QAP reduction
+/// recorded in `sapk.reduction`. All five MSMs are delegated, whatever
+/// `sapk.delegation` says.
+pub fn malicious_client_encrypt<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    rng: &mut R,
+) -> Result<(MaliciousEncryptedRequest, MaliciousClientState), StealthSnarkError> {
+    match sapk.reduction {
+        QapReduction::Libsnark => {
+            malicious_client_encrypt_with_reduction::<LibsnarkReduction, C, R>(sapk, circuit, rng)
+        }
+        QapReduction::Circom => {
+            malicious_client_encrypt_with_reduction::<CircomReduction, C, R>(sapk, circuit, rng)
+        }
+    }
+}
+
+/// `malicious_client_encrypt` computing the h polynomial with `QAP` instead of
+/// `sapk.reduction`.
+pub fn malicious_client_encrypt_with_reduction<
+    QAP: R1CSToQAP,
+    C: ConstraintSynthesizer<Fr>,
+    R: Rng + CryptoRng,
+>(
     sapk: &ServerAidedProvingKey,
     circuit: C,
     rng: &mut R,
-) -> Result<(MaliciousEncryptedRequest, MaliciousClientState), anyhow::Error> {
+) -> Result<(MaliciousEncryptedRequest, MaliciousClientState), StealthSnarkError> {
     let cs = ConstraintSystem::<Fr>::new_ref();
     cs.set_optimization_goal(OptimizationGoal::Constraints);
     cs.set_mode(SynthesisMode::Prove { construct_matrices: true });
@@ -316,13 +1568,8 @@ pub fn malicious_client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R:
     let num_instance_variables = cs.num_instance_variables();
     let h_poly = QAP::witness_map::<Fr, GeneralEvaluationDomain<Fr>>(cs.clone())?;
 
-    let cs_inner = cs.borrow().unwrap();
-    let prover = cs_inner.deref();
-    let instance = prover.instance_assignment.clone();
-    let witness = prover.witness_assignment.clone();
-    let mut full_assignment = instance.clone();
-    full_assignment.extend_from_slice(&witness);
-    drop(cs_inner);
+    let full_assignment = take_full_assignment(cs);
+    let witness = &full_assignment[num_instance_variables..];
 
     let r = Fr::rand(rng);
     let s = Fr::rand(rng);
@@ -330,16 +1577,16 @@ pub fn malicious_client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R:
     let h_scalars = pad_or_trim(&h_poly, sapk.emsm_h.generators.len());
     let (enc_h, ds_h) = malicious_encrypt(&sapk.emsm_h, &h_scalars, rng);
 
-    let l_scalars = pad_or_trim(&witness, sapk.emsm_l.generators.len());
+    let l_scalars = pad_or_trim(witness, sapk.emsm_l.generators.len());
     let (enc_l, ds_l) = malicious_encrypt(&sapk.emsm_l, &l_scalars, rng);
 
-    let a_scalars = pad_or_trim(&witness, sapk.emsm_a.generators.len());
+    let a_scalars = pad_or_trim(witness, sapk.emsm_a.generators.len());
     let (enc_a, ds_a) = malicious_encrypt(&sapk.emsm_a, &a_scalars, rng);
 
-    let b_g1_scalars = pad_or_trim(&witness, sapk.emsm_b_g1.generators.len());
+    let b_g1_scalars = pad_or_trim(witness, sapk.emsm_b_g1.generators.len());
     let (enc_b_g1, ds_b_g1) = malicious_encrypt(&sapk.emsm_b_g1, &b_g1_scalars, rng);
 
-    let b_g2_scalars = pad_or_trim(&witness, sapk.emsm_b_g2.generators.len());
+    let b_g2_scalars = pad_or_trim(witness, sapk.emsm_b_g2.generators.len());
     let (enc_b_g2, ds_b_g2) = malicious_encrypt(&sapk.emsm_b_g2, &b_g2_scalars, rng);
 
     let request = MaliciousEncryptedRequest {
@@ -369,7 +1616,7 @@ pub fn malicious_client_encrypt<QAP: R1CSToQAP, C: ConstraintSynthesizer<Fr>, R:
 pub fn malicious_server_evaluate_groth16(
     sapk: &ServerAidedProvingKey,
     request: &MaliciousEncryptedRequest,
-) -> Result<MaliciousServerResponse, anyhow::Error> {
+) -> Result<MaliciousServerResponse, StealthSnarkError> {
     let (em_h, em_h_ck) = (
         sapk.emsm_h.server_computation(&request.h.masked)?,
         sapk.emsm_h.server_computation(&request.h.masked_check)?,
@@ -412,21 +1659,21 @@ pub fn malicious_client_decrypt(
     response: &MaliciousServerResponse,
     state: &MaliciousClientState,
 ) -> Result<Proof<Bn254>, MaliciousError> {
-    let h_msm = malicious_decrypt(response.em_h, response.em_h_ck, &state.ds_h, &sapk.pre_h)?;
-    let l_msm = malicious_decrypt(response.em_l, response.em_l_ck, &state.ds_l, &sapk.pre_l)?;
+    let h_msm = malicious_decrypt(response.em_h, response.em_h_ck, &state.ds_h, sapk.pre_h.get())?;
+    let l_msm = malicious_decrypt(response.em_l, response.em_l_ck, &state.ds_l, sapk.pre_l.get())?;
     let a_witness_msm =
-        malicious_decrypt(response.em_a, response.em_a_ck, &state.ds_a, &sapk.pre_a)?;
+        malicious_decrypt(response.em_a, response.em_a_ck, &state.ds_a, sapk.pre_a.get())?;
     let b_g1_witness_msm = malicious_decrypt(
         response.em_b_g1,
         response.em_b_g1_ck,
         &state.ds_b_g1,
-        &sapk.pre_b_g1,
+        sapk.pre_b_g1.get(),
     )?;
     let b_g2_witness_msm: G2 = malicious_decrypt(
         response.em_b_g2,
         response.em_b_g2_ck,
         &state.ds_b_g2,
-        &sapk.pre_b_g2,
+        sapk.pre_b_g2.get(),
     )?;
 
     // Assemble proof (same logic as semi-honest client_decrypt)
@@ -476,9 +1723,270 @@ pub fn malicious_client_decrypt(
     })
 }
 
-/// Adjust a vector to exactly `target_len` by zero-padding or trimming.
-/// Logs a warning if the lengths don't match, since this may indicate a setup misconfiguration.
-fn pad_or_trim(v: &[Fr], target_len: usize) -> Vec<Fr> {
+// ─── Proving modes ───────────────────────────────────────────────────────────
+// The check query of the malicious variant is itself an ordinary masked vector,
+// so it can travel as a separate semi-honest request. The server then cannot
+// tell checked proofs from unchecked ones, which is what lets covert mode check
+// only some of them.
+
+/// How a delegated prove guards against a server returning wrong MSM results.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProvingMode {
+    /// One request per proof. A wrong result only shows as a proof that fails
+    /// to verify.
+    #[default]
+    SemiHonest,
+    /// A main and a check request per proof, at twice the server cost. A wrong
+    /// result is detected with overwhelming probability.
+    Malicious,
+    /// Send the check request with a random `deterrence` fraction of proofs,
+    /// in [0, 1]. A server tampering with a request is caught with that
+    /// probability, at `1 + deterrence` times the semi-honest server cost.
+    Covert { deterrence: f64 },
+    /// Cut-and-choose: send `queries` requests per proof (at least 2), at
+    /// `queries` times the semi-honest server cost. One at a random position
+    /// carries the witness; the others mask the zero vector, and the client
+    /// opens them all and checks that they unmask to the identity. A server
+    /// tampering with one request is caught with probability
+    /// `(queries - 1) / queries`.
+    CutAndChoose { queries: usize },
+}
+
+/// LPN noise of a cut-and-choose test query, which masks the zero vector.
+pub struct ZeroQueryState {
+    pub lpn_h: SparseVector<Fr>,
+    pub lpn_l: SparseVector<Fr>,
+    pub lpn_a: SparseVector<Fr>,
+    pub lpn_b_g1: SparseVector<Fr>,
+    pub lpn_b_g2: SparseVector<Fr>,
+}
+
+impl ZeroQueryState {
+    /// Mask the zero vector of each delegated MSM's length. MSMs the key does
+    /// not delegate are empty, as in the request carrying the witness.
+    fn encrypt<R: Rng + CryptoRng>(
+        sapk: &ServerAidedProvingKey,
+        rng: &mut R,
+    ) -> (EncryptedRequest, Self) {
+        fn zeros<G: CurveGroup<ScalarField = Fr>, R: Rng + CryptoRng>(
+            delegated: bool,
+            params: &EmsmPublicParams<G>,
+            rng: &mut R,
+        ) -> (Vec<Fr>, SparseVector<Fr>) {
+            match delegated {
+                true => encrypt(params, &vec![Fr::zero(); params.generators.len()], rng),
+                false => (Vec::new(), SparseVector::new(0, Vec::new())),
+            }
+        }
+        let delegation = sapk.delegation;
+        let (v_h, lpn_h) = zeros(delegation.h, &sapk.emsm_h, rng);
+        let (v_l, lpn_l) = zeros(delegation.l, &sapk.emsm_l, rng);
+        let (v_a, lpn_a) = zeros(delegation.a, &sapk.emsm_a, rng);
+        let (v_b_g1, lpn_b_g1) = zeros(delegation.b_g1, &sapk.emsm_b_g1, rng);
+        let (v_b_g2, lpn_b_g2) = zeros(delegation.b_g2, &sapk.emsm_b_g2, rng);
+        let request = EncryptedRequest {
+            v_h,
+            v_l,
+            v_a,
+            v_b_g1,
+            v_b_g2,
+        };
+        let state = Self {
+            lpn_h,
+            lpn_l,
+            lpn_a,
+            lpn_b_g1,
+            lpn_b_g2,
+        };
+        (request, state)
+    }
+
+    /// Whether every MSM result of `response` unmasks to the identity.
+    fn opens_to_zero(
+        &self,
+        sapk: &ServerAidedProvingKey,
+        response: &ServerResponse,
+    ) -> Result<bool, PedersenError> {
+        Ok(
+            decrypt(response.em_h, &self.lpn_h, sapk.pre_h.get())?.is_zero()
+                && decrypt(response.em_l, &self.lpn_l, sapk.pre_l.get())?.is_zero()
+                && decrypt(response.em_a, &self.lpn_a, sapk.pre_a.get())?.is_zero()
+                && decrypt(response.em_b_g1, &self.lpn_b_g1, sapk.pre_b_g1.get())?.is_zero()
+                && decrypt(response.em_b_g2, &self.lpn_b_g2, sapk.pre_b_g2.get())?.is_zero(),
+        )
+    }
+}
+
+/// Client-side state for `client_decrypt_with_mode`. Whether the proof is
+/// checked is known only to the client.
+pub enum ModeClientState {
+    Unchecked(Box<ClientDecryptionState>),
+    Checked(Box<MaliciousClientState>),
+    CutAndChoose {
+        state: Box<ClientDecryptionState>,
+        /// Position of the request carrying the witness.
+        real: usize,
+        tests: Vec<ZeroQueryState>,
+    },
+}
+
+impl ModeClientState {
+    /// Whether the proof carries a check request.
+    pub fn checked(&self) -> bool {
+        !matches!(self, ModeClientState::Unchecked(_))
+    }
+
+    /// Number of server responses `client_decrypt_with_mode` expects.
+    pub fn num_requests(&self) -> usize {
+        match self {
+            ModeClientState::Unchecked(_) => 1,
+            ModeClientState::Checked(_) => 2,
+            ModeClientState::CutAndChoose { tests, .. } => tests.len() + 1,
+        }
+    }
+}
+
+/// Client encrypt under `mode`: one request per proof, a main request and a
+/// check request, or cut-and-choose queries. Each is evaluated by the server like any other
+/// `EncryptedRequest`, with `server_evaluate`.
+pub fn client_encrypt_with_mode<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+    mode: ProvingMode,
+    rng: &mut R,
+) -> Result<(Vec<EncryptedRequest>, ModeClientState), StealthSnarkError> {
+    if !sapk.delegation.is_full()
+        && matches!(mode, ProvingMode::Malicious | ProvingMode::Covert { .. })
+    {
+        return Err(StealthSnarkError::InvalidArgument(
+            "malicious and covert proving delegate all five MSMs, not a key's partial delegation"
+                .to_string(),
+        ));
+    }
+    let checked = match mode {
+        ProvingMode::SemiHonest => false,
+        ProvingMode::Malicious => true,
+        ProvingMode::Covert { deterrence } => {
+            if !(0.0..=1.0).contains(&deterrence) {
+                return Err(StealthSnarkError::InvalidArgument(format!(
+                    "deterrence factor {deterrence} is not in [0, 1]"
+                )));
+            }
+            rng.gen_bool(deterrence)
+        }
+        ProvingMode::CutAndChoose { queries } => {
+            if queries < 2 {
+                return Err(StealthSnarkError::InvalidArgument(
+                    "cut-and-choose needs at least 2 queries".to_string(),
+                ));
+            }
+            let (request, state) = client_encrypt(sapk, circuit, rng)?;
+            let (mut requests, tests): (Vec<_>, Vec<_>) = (1..queries)
+                .map(|_| ZeroQueryState::encrypt(sapk, rng))
+                .unzip();
+            let real = rng.gen_range(0..queries);
+            requests.insert(real, request);
+            let state = ModeClientState::CutAndChoose {
+                state: Box::new(state),
+                real,
+                tests,
+            };
+            return Ok((requests, state));
+        }
+    };
+    if !checked {
+        let (request, state) = client_encrypt(sapk, circuit, rng)?;
+        return Ok((vec![request], ModeClientState::Unchecked(Box::new(state))));
+    }
+
+    let (request, state) = malicious_client_encrypt(sapk, circuit, rng)?;
+    let MaliciousEncryptedRequest {
+        h,
+        l,
+        a,
+        b_g1,
+        b_g2,
+    } = request;
+    let main = EncryptedRequest {
+        v_h: h.masked,
+        v_l: l.masked,
+        v_a: a.masked,
+        v_b_g1: b_g1.masked,
+        v_b_g2: b_g2.masked,
+    };
+    let check = EncryptedRequest {
+        v_h: h.masked_check,
+        v_l: l.masked_check,
+        v_a: a.masked_check,
+        v_b_g1: b_g1.masked_check,
+        v_b_g2: b_g2.masked_check,
+    };
+    Ok((vec![main, check], ModeClientState::Checked(Box::new(state))))
+}
+
+/// Client decrypt under the mode `state` was encrypted with. `responses` answer
+/// the requests of `client_encrypt_with_mode`, in order. Returns
+/// `MaliciousError::ConsistencyCheckFailed` if a checked proof's results do not
+/// match or a cut-and-choose test query does not open to zero, and
+/// `InvalidResponse` if an unchecked or cut-and-choose proof's results do not
+/// validate.
+pub fn client_decrypt_with_mode(
+    sapk: &ServerAidedProvingKey,
+    responses: &[ServerResponse],
+    state: &ModeClientState,
+) -> Result<Proof<Bn254>, StealthSnarkError> {
+    if responses.len() != state.num_requests() {
+        return Err(MaliciousError::ResponseCount {
+            expected: state.num_requests(),
+            got: responses.len(),
+        }
+        .into());
+    }
+    match state {
+        ModeClientState::Unchecked(state) => Ok(client_decrypt(sapk, &responses[0], state)?),
+        ModeClientState::Checked(state) => {
+            let (main, check) = (&responses[0], &responses[1]);
+            let response = MaliciousServerResponse {
+                em_h: main.em_h,
+                em_h_ck: check.em_h,
+                em_l: main.em_l,
+                em_l_ck: check.em_l,
+                em_a: main.em_a,
+                em_a_ck: check.em_a,
+                em_b_g1: main.em_b_g1,
+                em_b_g1_ck: check.em_b_g1,
+                em_b_g2: main.em_b_g2,
+                em_b_g2_ck: check.em_b_g2,
+            };
+            Ok(malicious_client_decrypt(sapk, &response, state)?)
+        }
+        ModeClientState::CutAndChoose { state, real, tests } => {
+            let test_responses = responses[..*real].iter().chain(&responses[real + 1..]);
+            for (test, response) in tests.iter().zip(test_responses) {
+                if !test.opens_to_zero(sapk, response)? {
+                    return Err(MaliciousError::ConsistencyCheckFailed.into());
+                }
+            }
+            Ok(client_decrypt(sapk, &responses[*real], state)?)
+        }
+    }
+}
+
+/// Take `[instance || witness]` out of a finalized constraint system, leaving
+/// its assignment vectors empty. `cs` should be the last handle, so the matrices
+/// are freed too.
+fn take_full_assignment(cs: ConstraintSystemRef<Fr>) -> Vec<Fr> {
+    let mut inner = cs.borrow_mut().expect("constraint system is not None");
+    let instance = std::mem::take(&mut inner.instance_assignment);
+    let mut full_assignment = std::mem::take(&mut inner.witness_assignment);
+    full_assignment.splice(0..0, instance);
+    full_assignment
+}
+
+/// View a vector as exactly `target_len` long, borrowing it unless it needs
+/// zero-padding. Logs a warning if the lengths don't match, since this may
+/// indicate a setup misconfiguration.
+fn pad_or_trim(v: &[Fr], target_len: usize) -> Cow<'_, [Fr]> {
     if v.len() != target_len {
         tracing::warn!(
             "pad_or_trim: vector length {} != target {}, adjusting",
@@ -487,19 +1995,19 @@ fn pad_or_trim(v: &[Fr], target_len: usize) -> Vec<Fr> {
         );
     }
     if v.len() >= target_len {
-        v[..target_len].to_vec()
+        Cow::Borrowed(&v[..target_len])
     } else {
         let mut padded = v.to_vec();
         padded.resize(target_len, Fr::zero());
-        padded
+        Cow::Owned(padded)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::groth16::circuit::CubeCircuit;
-    use ark_groth16::r1cs_to_qap::LibsnarkReduction;
+    use crate::emsm::params::{get_lpn_params, LpnTable};
+    use crate::groth16::circuit::{CubeCircuit, SquaringChainCircuit};
     use ark_groth16::Groth16;
     use ark_snark::SNARK;
     use rand::SeedableRng;
@@ -520,13 +2028,13 @@ mod tests {
         // Client: encrypt (x = 3, so y = 3^3 + 3 + 5 = 35)
         let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
         let (request, state) =
-            client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit, &mut rng).expect("encrypt failed");
+            client_encrypt(&sapk, circuit, &mut rng).expect("encrypt failed");
 
         // Server: evaluate 5 MSMs
         let response = server_evaluate(&sapk, &request).expect("server evaluate failed");
 
         // Client: decrypt and assemble proof
-        let proof = client_decrypt(&sapk, &response, &state);
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
 
         // Verify the proof
         let public_inputs = vec![Fr::from(35u64)];
@@ -535,6 +2043,423 @@ mod tests {
         assert!(valid, "Server-aided Groth16 proof should verify!");
     }
 
+    #[test]
+    fn test_delegated_public_inputs() {
+        let mut rng = ChaCha20Rng::seed_from_u64(43);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let generators = PublicGenerators::from(&sapk);
+        assert_eq!(generators.a.len(), vk.gamma_abc_g1.len());
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        assert_eq!(state.public_inputs(), [Fr::from(35u64)]);
+        let public = generators.evaluate(state.public_inputs()).unwrap();
+
+        let proof = client_decrypt_with_public(&sapk, &response, &public, &state).unwrap();
+        assert_eq!(proof, client_decrypt(&sapk, &response, &state).unwrap());
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+        assert!(generators.evaluate(&[]).is_err());
+
+        let empty = PublicGenerators {
+            a: vec![],
+            b_g1: vec![],
+            b_g2: vec![],
+        };
+        assert!(empty.validate().is_err());
+        let mut short = generators.clone();
+        short.b_g2.pop();
+        assert!(short.validate().is_err());
+        assert!(short.evaluate(state.public_inputs()).is_err());
+
+        // A point off the curve never reaches the proof
+        let p = G1Affine::rand(&mut rng);
+        let q = G1Affine::rand(&mut rng);
+        let off_curve = G1Affine::new_unchecked(p.x, q.y);
+        let bad = PublicInputResponse {
+            a: off_curve.into(),
+            ..public
+        };
+        let err = client_decrypt_with_public(&sapk, &response, &bad, &state).unwrap_err();
+        let expected = InvalidResponse::NotOnCurve(MsmKind::Public);
+        assert!(matches!(err, StealthSnarkError::InvalidResponse(e) if e == expected));
+    }
+
+    #[test]
+    fn test_encrypt_to_writer_matches_prove_request() {
+        let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(
+            CubeCircuit::<Fr> { x: None },
+            &mut ChaCha20Rng::seed_from_u64(5),
+        )
+        .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut ChaCha20Rng::seed_from_u64(6));
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+
+        let (request, _) =
+            client_encrypt(&sapk, circuit.clone(), &mut ChaCha20Rng::seed_from_u64(7)).unwrap();
+        let mut streamed = Vec::new();
+        let state = client_encrypt_to_writer(
+            &sapk,
+            circuit,
+            &mut ChaCha20Rng::seed_from_u64(7),
+            &mut streamed,
+        )
+        .unwrap();
+        assert_eq!(
+            streamed,
+            bincode::serialize(&crate::protocol::messages::ProveRequest::from(&request)).unwrap()
+        );
+
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+
+    #[test]
+    fn test_assignment_taken_without_copies() {
+        let mut rng = ChaCha20Rng::seed_from_u64(42);
+        let (pk, _vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (_request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+
+        // [1, y] then the witness, starting with x
+        assert_eq!(state.num_instance_variables, 2);
+        assert_eq!(
+            state.full_assignment[..3],
+            [Fr::from(1u64), Fr::from(35u64), Fr::from(3u64)]
+        );
+        let witness = &state.full_assignment[2..];
+        assert_eq!(witness.len(), sapk.emsm_l.generators.len());
+
+        // Exact and trimmed lengths borrow; only padding allocates
+        assert!(matches!(pad_or_trim(witness, witness.len()), Cow::Borrowed(_)));
+        assert!(matches!(pad_or_trim(witness, 1), Cow::Borrowed(_)));
+        let padded = pad_or_trim(witness, witness.len() + 2);
+        assert!(matches!(padded, Cow::Owned(_)));
+        assert_eq!(padded[witness.len()..], [Fr::zero(), Fr::zero()]);
+    }
+
+    #[test]
+    fn test_setup_keeps_only_public_rows_and_reassembles_pk() {
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let (pk, _vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .expect("setup failed");
+        let num_pub = pk.vk.gamma_abc_g1.len();
+
+        let sapk = ServerAidedProvingKey::setup(pk.clone(), &mut rng);
+        assert_eq!(sapk.pk.a_query.len(), num_pub);
+        assert_eq!(sapk.pk.b_g1_query.len(), num_pub);
+        assert_eq!(sapk.pk.b_g2_query.len(), num_pub);
+        assert_eq!(sapk.proving_key(), pk);
+    }
+
+    #[test]
+    fn test_setup_from_reader() {
+        let mut rng = ChaCha20Rng::seed_from_u64(9);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .expect("setup failed");
+        let mut bytes = Vec::new();
+        pk.serialize_compressed(&mut bytes).unwrap();
+
+        let sapk = ServerAidedProvingKey::setup_from_reader(
+            bytes.as_slice(),
+            Compress::Yes,
+            &mut rng,
+            &SetupOptions::default(),
+        )
+        .expect("streaming setup failed");
+        assert_eq!(sapk.proving_key(), pk);
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) =
+            client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+        // Truncated input is an error, not a panic
+        let truncated = &bytes[..bytes.len() / 2];
+        assert!(ServerAidedProvingKey::setup_from_reader(
+            truncated,
+            Compress::Yes,
+            &mut rng,
+            &SetupOptions::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_deferred_preprocessing_e2e() {
+        for mode in [PreprocessMode::Lazy, PreprocessMode::Background] {
+            let mut rng = ChaCha20Rng::seed_from_u64(11);
+            let (pk, vk) =
+                Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                    .expect("setup failed");
+            let options = SetupOptions {
+                preprocess: mode,
+                ..Default::default()
+            };
+            let sapk = ServerAidedProvingKey::setup_with(pk, &mut rng, &options).unwrap();
+            if mode == PreprocessMode::Lazy {
+                assert!(!sapk.pre_h.is_ready());
+            }
+
+            let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+            let (request, state) =
+                client_encrypt(&sapk, circuit, &mut rng).unwrap();
+            let response = server_evaluate(&sapk, &request).unwrap();
+            let proof = client_decrypt(&sapk, &response, &state).unwrap();
+            assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+            assert!(sapk.pre_b_g2.is_ready());
+        }
+    }
+
+    #[test]
+    fn test_deferred_preprocessing_off_rate_with_cache() {
+        let mut rng = ChaCha20Rng::seed_from_u64(19);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .expect("setup failed");
+        let dir = std::env::temp_dir().join(format!("stealthsnark-fold-{}", u64::rand(&mut rng)));
+        let cache = PreprocessCache::new(&dir).unwrap();
+        let h_len = pk.h_query.len();
+        // A noise weight only the rate-1/8 code has room for
+        let params = LpnOverrides {
+            h: MsmLpnParams {
+                fold: Some(8),
+                t: Some(6 * h_len),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for mode in [PreprocessMode::Lazy, PreprocessMode::Background] {
+            let options = SetupOptions {
+                preprocess: mode,
+                cache: Some(&cache),
+                ..Default::default()
+            };
+            let sapk =
+                ServerAidedProvingKey::setup_with_params(pk.clone(), &mut rng, &options, &params)
+                    .unwrap();
+            assert_eq!(sapk.emsm_h.t_operator.big_n, 8 * h_len);
+            assert_eq!(sapk.emsm_h.t, 6 * h_len);
+
+            let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+            let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+            let response = server_evaluate(&sapk, &request).unwrap();
+            let proof = client_decrypt(&sapk, &response, &state).unwrap();
+            assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_implicit_permutations_e2e() {
+        let mut rng = ChaCha20Rng::seed_from_u64(12);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .expect("setup failed");
+        let options = SetupOptions {
+            permutations: PermutationMode::Implicit,
+            ..Default::default()
+        };
+        let sapk = ServerAidedProvingKey::setup_with(pk, &mut rng, &options).unwrap();
+        assert_eq!(
+            sapk.emsm_h.t_operator.permutation_mode(),
+            PermutationMode::Implicit
+        );
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) =
+            client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+
+    #[test]
+    fn test_strict_security_check_rejects_weak_parameters() {
+        let mut rng = ChaCha20Rng::seed_from_u64(13);
+        let circuit = SquaringChainCircuit::<Fr> {
+            num_constraints: 1024,
+            x: None,
+        };
+        let (pk, _vk) = Groth16::<Bn254>::circuit_specific_setup(circuit, &mut rng).unwrap();
+        let options = SetupOptions {
+            security: SecurityLevel::Bits128,
+            security_check: SecurityCheck {
+                strict: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let weak_h = LpnOverrides {
+            h: MsmLpnParams {
+                t: Some(38),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match ServerAidedProvingKey::setup_with_params(pk.clone(), &mut rng, &options, &weak_h) {
+            Err(SetupError::InsufficientSecurity(e)) => {
+                assert_eq!((e.msm.as_str(), e.t), ("h", 38))
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("strict setup should reject t = 38 at 128 bits and n = 1024"),
+        }
+        // The estimate is advisory unless strict
+        let lenient = SetupOptions {
+            security_check: SecurityCheck::default(),
+            ..options
+        };
+        ServerAidedProvingKey::setup_with_params(pk, &mut rng, &lenient, &weak_h).unwrap();
+    }
+
+    #[test]
+    fn test_per_msm_lpn_params() {
+        let mut rng = ChaCha20Rng::seed_from_u64(18);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let params = LpnOverrides {
+            h: MsmLpnParams {
+                security: Some(SecurityLevel::Bits128),
+                t: Some(40),
+                fold: Some(8),
+            },
+            l: MsmLpnParams {
+                t: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sapk = ServerAidedProvingKey::setup_with_params(
+            pk.clone(),
+            &mut rng,
+            &SetupOptions::default(),
+            &params,
+        )
+        .unwrap();
+        let h_len = sapk.emsm_h.generators.len();
+        assert_eq!(sapk.emsm_h.t_operator.big_n, 8 * h_len);
+        assert_eq!(sapk.emsm_h.security, SecurityLevel::Bits128);
+        assert_eq!((sapk.emsm_h.t, sapk.emsm_l.t), (40, 3));
+        assert_eq!(
+            sapk.emsm_a.t_operator.big_n,
+            4 * sapk.emsm_a.generators.len()
+        );
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+        let zero_t = LpnOverrides {
+            b_g2: MsmLpnParams {
+                t: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match ServerAidedProvingKey::setup_with_params(
+            pk.clone(),
+            &mut rng,
+            &SetupOptions::default(),
+            &zero_t,
+        ) {
+            Err(SetupError::NoiseWeightOutOfRange { msm, t, .. }) => {
+                assert_eq!((msm.as_str(), t), ("b_g2", 0))
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("a zero noise weight should be rejected"),
+        }
+
+        // Rate 1/8 has no table, so its noise weight must be given
+        let untabled = LpnOverrides {
+            a: MsmLpnParams {
+                fold: Some(8),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match ServerAidedProvingKey::setup_with_params(
+            pk.clone(),
+            &mut rng,
+            &SetupOptions::default(),
+            &untabled,
+        ) {
+            Err(SetupError::LpnParams { msm, source }) => {
+                assert_eq!(msm, "a");
+                let security = SecurityLevel::Bits100;
+                assert_eq!(source, LpnParamsError::NoTable { security, fold: 8 });
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("rate 1/8 should need a noise weight"),
+        }
+
+        // ... or a table for that rate in the options
+        let mut table = LpnParamSet {
+            tables: vec![LpnTable {
+                security_bits: 100,
+                fold: 8,
+                rows: vec![(10, 7)],
+            }],
+            growth_percent: 10,
+        };
+        let options = SetupOptions {
+            lpn_params: Some(&table),
+            ..Default::default()
+        };
+        let sapk =
+            ServerAidedProvingKey::setup_with_params(pk.clone(), &mut rng, &options, &untabled)
+                .unwrap();
+        let (a_len, h_len) = (sapk.emsm_a.generators.len(), sapk.emsm_h.generators.len());
+        assert_eq!(sapk.emsm_a.t_operator.big_n, 8 * a_len);
+        assert_eq!(sapk.emsm_a.t, 7.min(8 * a_len));
+        assert_eq!(sapk.emsm_h.t, get_lpn_params(h_len).t);
+
+        table.tables[0].rows.clear();
+        let options = SetupOptions {
+            lpn_params: Some(&table),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ServerAidedProvingKey::setup_with_params(pk, &mut rng, &options, &untabled),
+            Err(SetupError::InvalidLpnTable(_))
+        ));
+    }
+
+    #[test]
+    fn test_qap_reduction_detected_from_key() {
+        assert_eq!(QapReduction::detect(7), Some(QapReduction::Libsnark));
+        assert_eq!(QapReduction::detect(8), Some(QapReduction::Circom));
+        assert_eq!(QapReduction::detect(6), None);
+
+        let mut rng = ChaCha20Rng::seed_from_u64(17);
+        let (pk, _vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk.clone(), &mut rng);
+        assert_eq!(sapk.reduction, QapReduction::Libsnark);
+
+        let mut truncated = pk;
+        truncated.h_query.truncate(5);
+        match ServerAidedProvingKey::setup_with(truncated, &mut rng, &SetupOptions::default()) {
+            Err(SetupError::UnknownReduction { h_query_len }) => assert_eq!(h_query_len, 5),
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("an h query of 5 bases matches neither reduction"),
+        }
+    }
+
     #[test]
     fn test_malicious_server_aided_groth16_e2e() {
         let mut rng = ChaCha20Rng::seed_from_u64(77);
@@ -547,7 +2472,7 @@ mod tests {
 
         let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
         let (request, state) =
-            malicious_client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit, &mut rng)
+            malicious_client_encrypt(&sapk, circuit, &mut rng)
                 .expect("encrypt failed");
 
         let response = malicious_server_evaluate_groth16(&sapk, &request)
@@ -574,7 +2499,7 @@ mod tests {
 
         let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
         let (request, state) =
-            malicious_client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit, &mut rng)
+            malicious_client_encrypt(&sapk, circuit, &mut rng)
                 .expect("encrypt failed");
 
         let mut response = malicious_server_evaluate_groth16(&sapk, &request)
@@ -586,4 +2511,201 @@ mod tests {
         let result = malicious_client_decrypt(&sapk, &response, &state);
         assert!(result.is_err(), "Should detect tampered MSM result");
     }
+
+    #[test]
+    fn test_proving_modes() {
+        let mut rng = ChaCha20Rng::seed_from_u64(89);
+
+        let circuit_for_setup = CubeCircuit::<Fr> { x: None };
+        let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(circuit_for_setup, &mut rng)
+            .expect("setup failed");
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let prove = |mode, tamper: bool, rng: &mut ChaCha20Rng| {
+            let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+            let (requests, state) =
+                client_encrypt_with_mode(&sapk, circuit, mode, rng).expect("encrypt failed");
+            let mut responses: Vec<ServerResponse> = requests
+                .iter()
+                .map(|request| server_evaluate(&sapk, request).expect("server evaluate failed"))
+                .collect();
+            if tamper {
+                responses[0].em_a += G1::rand(rng);
+            }
+            (
+                state.checked(),
+                client_decrypt_with_mode(&sapk, &responses, &state),
+            )
+        };
+
+        for mode in [ProvingMode::SemiHonest, ProvingMode::Malicious] {
+            let (_, proof) = prove(mode, false, &mut rng);
+            let valid = Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof.unwrap()).unwrap();
+            assert!(valid, "{mode:?} proof should verify");
+        }
+        let (checked, result) = prove(ProvingMode::Malicious, true, &mut rng);
+        assert!(checked);
+        assert!(matches!(
+            result,
+            Err(StealthSnarkError::Malicious(MaliciousError::ConsistencyCheckFailed))
+        ));
+        let (checked, result) = prove(ProvingMode::SemiHonest, true, &mut rng);
+        assert!(!checked && result.is_ok());
+
+        // Tampering is caught exactly on the proofs that carried a check
+        let covert = ProvingMode::Covert { deterrence: 0.5 };
+        let outcomes: Vec<_> = (0..8).map(|_| prove(covert, true, &mut rng)).collect();
+        for (checked, result) in &outcomes {
+            assert_eq!(*checked, result.is_err());
+        }
+        assert!(outcomes.iter().any(|(checked, _)| *checked));
+        assert!(outcomes.iter().any(|(checked, _)| !*checked));
+
+        // A tampered request is either the real one or a test query that no
+        // longer opens to zero
+        let cut_and_choose = ProvingMode::CutAndChoose { queries: 3 };
+        let (_, proof) = prove(cut_and_choose, false, &mut rng);
+        let valid = Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof.unwrap()).unwrap();
+        assert!(valid, "cut-and-choose proof should verify");
+        let outcomes: Vec<_> = (0..8)
+            .map(|_| prove(cut_and_choose, true, &mut rng).1)
+            .collect();
+        assert!(outcomes.iter().any(|result| result.is_err()));
+        for proof in outcomes.into_iter().filter_map(Result::ok) {
+            assert!(!Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+        }
+
+        for invalid in [
+            ProvingMode::Covert { deterrence: 1.5 },
+            ProvingMode::CutAndChoose { queries: 1 },
+        ] {
+            let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+            assert!(client_encrypt_with_mode(&sapk, circuit, invalid, &mut rng).is_err());
+        }
+    }
+
+    #[test]
+    fn test_partial_delegation() {
+        let mut rng = ChaCha20Rng::seed_from_u64(90);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        assert!(sapk.delegation.is_full());
+        let delegation = Delegation {
+            h: true,
+            b_g2: true,
+            ..Delegation::NONE
+        };
+        let sapk = sapk.with_delegation(delegation);
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        assert_eq!(request.v_h.len(), sapk.emsm_h.generators.len());
+        assert_eq!(request.v_b_g2.len(), sapk.emsm_b_g2.generators.len());
+        assert!(request.v_l.is_empty() && request.v_a.is_empty() && request.v_b_g1.is_empty());
+        assert_eq!(state.h_poly.is_empty(), !cfg!(feature = "debug-msm"));
+
+        // The server's results for the local MSMs are not used
+        let mut response = server_evaluate(&sapk, &request).unwrap();
+        assert!(response.em_l.is_zero());
+        response.em_a += G1::rand(&mut rng);
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+        // Cut-and-choose test queries leave out the same MSMs as the real one
+        let sapk = sapk.with_delegation(Delegation::NONE);
+        let mode = ProvingMode::CutAndChoose { queries: 2 };
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (requests, state) = client_encrypt_with_mode(&sapk, circuit, mode, &mut rng).unwrap();
+        assert!(requests.iter().all(|request| request.v_h.is_empty()));
+        let responses: Vec<ServerResponse> = requests
+            .iter()
+            .map(|request| server_evaluate(&sapk, request).unwrap())
+            .collect();
+        let proof = client_decrypt_with_mode(&sapk, &responses, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let result = client_encrypt_with_mode(&sapk, circuit, ProvingMode::Malicious, &mut rng);
+        assert!(matches!(result, Err(StealthSnarkError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_invalid_response_rejected() {
+        let mut rng = ChaCha20Rng::seed_from_u64(91);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let decrypt = |tamper: &dyn Fn(&mut ServerResponse)| {
+            let mut response = server_evaluate(&sapk, &request).unwrap();
+            tamper(&mut response);
+            match client_decrypt(&sapk, &response, &state) {
+                Err(StealthSnarkError::InvalidResponse(e)) => Err(e),
+                result => Ok(result.unwrap()),
+            }
+        };
+        let proof = decrypt(&|_| {}).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+        let result = decrypt(&|response| response.em_a = G1::zero());
+        assert_eq!(result, Err(InvalidResponse::Identity(MsmKind::A)));
+
+        let p = G1Affine::rand(&mut rng);
+        let q = G1Affine::rand(&mut rng);
+        let off_curve = G1Affine::new_unchecked(p.x, q.y);
+        let result = decrypt(&|response| response.em_l = off_curve.into());
+        assert_eq!(result, Err(InvalidResponse::NotOnCurve(MsmKind::L)));
+
+        // BN254's G2 has a cofactor, so a point of the twist can have a
+        // low-order component
+        let outside = loop {
+            let x = ark_bn254::Fq2::rand(&mut rng);
+            if let Some(point) = G2Affine::get_point_from_x_unchecked(x, false) {
+                if !point.is_in_correct_subgroup_assuming_on_curve() {
+                    break point;
+                }
+            }
+        };
+        let result = decrypt(&|response| response.em_b_g2 = outside.into());
+        assert_eq!(result, Err(InvalidResponse::NotInSubgroup(MsmKind::BG2)));
+    }
+
+    #[cfg(feature = "debug-msm")]
+    #[test]
+    fn test_diverging_msms() {
+        use ark_ec::PrimeGroup;
+
+        let mut rng = ChaCha20Rng::seed_from_u64(46);
+        let (pk, _) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let circuit = CubeCircuit {
+            x: Some(Fr::from(3u64)),
+        };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let decrypted = |response: &ServerResponse| {
+            let g1 = [
+                decrypt(response.em_h, &state.lpn_h, sapk.pre_h.get()).unwrap(),
+                decrypt(response.em_l, &state.lpn_l, sapk.pre_l.get()).unwrap(),
+                decrypt(response.em_a, &state.lpn_a, sapk.pre_a.get()).unwrap(),
+                decrypt(response.em_b_g1, &state.lpn_b_g1, sapk.pre_b_g1.get()).unwrap(),
+            ];
+            let g2 = decrypt(response.em_b_g2, &state.lpn_b_g2, sapk.pre_b_g2.get()).unwrap();
+            (g1, g2)
+        };
+
+        let (g1, g2) = decrypted(&response);
+        assert!(diverging_msms(&sapk, &state, g1, g2).is_empty());
+
+        let mut tampered = response;
+        tampered.em_l += G1::generator();
+        tampered.em_b_g2 += G2::generator();
+        let (g1, g2) = decrypted(&tampered);
+        assert_eq!(diverging_msms(&sapk, &state, g1, g2), ["l", "b_g2"]);
+    }
 }
@@ -0,0 +1,194 @@
+//! Sizes of what a `ServerAidedProvingKey` delegates, for integrators to show
+//! or log before the first prove: how large the circuit is, how much each
+//! masked MSM sends, and what the client keeps in memory to unmask them.
+
+use core::fmt;
+
+use ark_bn254::Fr;
+use ark_ec::CurveGroup;
+use ark_ff::Zero;
+use ark_serialize::CanonicalSerialize;
+
+use super::server_aided::{QapReduction, ServerAidedProvingKey};
+use crate::emsm::emsm::EmsmPublicParams;
+use crate::emsm::params::SecurityLevel;
+
+/// One of the five masked MSMs of a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsmStats {
+    /// Generators of the MSM, n, and scalars of its masked vector.
+    pub generators: usize,
+    /// Length of the masking noise, N = fold * n.
+    pub noise_len: usize,
+    /// Noise weight t.
+    pub t: usize,
+    pub security: SecurityLevel,
+    /// Bytes of its masked vector in a prove request.
+    pub request_bytes: usize,
+    /// Bytes of its preprocessed commitments (N points) in the client's memory.
+    pub preprocessed_bytes: usize,
+}
+
+impl MsmStats {
+    fn new<G: CurveGroup>(params: &EmsmPublicParams<G>) -> Self {
+        let (n, noise_len) = (params.generators.len(), params.t_operator.big_n);
+        Self {
+            generators: n,
+            noise_len,
+            t: params.t,
+            security: params.security,
+            request_bytes: 8 + n * Fr::zero().compressed_size(),
+            preprocessed_bytes: noise_len * core::mem::size_of::<G::Affine>(),
+        }
+    }
+}
+
+/// `ServerAidedProvingKey::stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyStats {
+    /// Size of the QAP domain: the constraint count, plus the public inputs,
+    /// rounded up to a power of two. Proving keys do not record the exact
+    /// count.
+    pub domain_size: usize,
+    /// Public inputs, without the constant 1.
+    pub public_inputs: usize,
+    /// Private witness variables.
+    pub witnesses: usize,
+    pub h: MsmStats,
+    pub l: MsmStats,
+    pub a: MsmStats,
+    pub b_g1: MsmStats,
+    pub b_g2: MsmStats,
+    /// Bytes of the five masked vectors of a `ProveRequest`, without its
+    /// envelope. MSMs the key does not delegate go out as empty vectors.
+    pub request_bytes: usize,
+    /// Bytes of the preprocessed commitments of the five MSMs, whether or not
+    /// they have been computed yet.
+    pub preprocessed_bytes: usize,
+}
+
+impl KeyStats {
+    /// The five MSMs, in `MsmKind::ALL` order.
+    pub fn msms(&self) -> [MsmStats; 5] {
+        [self.h, self.l, self.a, self.b_g1, self.b_g2]
+    }
+}
+
+impl fmt::Display for KeyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "domain {}, {} public inputs, {} witnesses; {} bytes per prove request, {} bytes preprocessed",
+            self.domain_size,
+            self.public_inputs,
+            self.witnesses,
+            self.request_bytes,
+            self.preprocessed_bytes
+        )?;
+        for (name, msm) in ["h", "l", "a", "b_g1", "b_g2"].iter().zip(self.msms()) {
+            writeln!(
+                f,
+                "  {name}: n = {}, N = {}, t = {} ({} bits)",
+                msm.generators,
+                msm.noise_len,
+                msm.t,
+                msm.security.bits()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ServerAidedProvingKey {
+    /// Sizes of the circuit and of what proving with the key delegates.
+    pub fn stats(&self) -> KeyStats {
+        let domain_size = match self.reduction {
+            QapReduction::Libsnark => self.emsm_h.generators.len() + 1,
+            QapReduction::Circom => self.emsm_h.generators.len(),
+        };
+        let msms = [
+            MsmStats::new(&self.emsm_h),
+            MsmStats::new(&self.emsm_l),
+            MsmStats::new(&self.emsm_a),
+            MsmStats::new(&self.emsm_b_g1),
+            MsmStats::new(&self.emsm_b_g2),
+        ];
+        let [h, l, a, b_g1, b_g2] = msms;
+        KeyStats {
+            domain_size,
+            public_inputs: self.pk.a_query.len() - 1,
+            witnesses: self.emsm_l.generators.len(),
+            h,
+            l,
+            a,
+            b_g1,
+            b_g2,
+            request_bytes: msms
+                .iter()
+                .zip(self.delegation.msms())
+                .map(|(msm, delegated)| if delegated { msm.request_bytes } else { 8 })
+                .sum(),
+            preprocessed_bytes: msms.iter().map(|msm| msm.preprocessed_bytes).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::CubeCircuit;
+    use crate::groth16::server_aided::{client_encrypt, Delegation};
+    use crate::protocol::messages::ProveRequest;
+    use ark_bn254::Bn254;
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_key_stats() {
+        let mut rng = ChaCha20Rng::seed_from_u64(58);
+        let circuit = CubeCircuit::<Fr> { x: None };
+        let (pk, _vk) = Groth16::<Bn254>::circuit_specific_setup(circuit, &mut rng).unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let stats = sapk.stats();
+
+        assert!(stats.domain_size.is_power_of_two());
+        assert_eq!(stats.public_inputs, 1);
+        assert_eq!(stats.witnesses, sapk.emsm_l.generators.len());
+        assert_eq!(stats.a.generators, sapk.emsm_a.generators.len());
+        assert_eq!(stats.b_g2.noise_len, sapk.pre_b_g2.get().h().len());
+
+        // The request size is that of the masked vectors of a real request
+        let circuit = CubeCircuit {
+            x: Some(Fr::from(3u64)),
+        };
+        let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let request = ProveRequest::from(&request);
+        let sent = [
+            &request.v_h,
+            &request.v_l,
+            &request.v_a,
+            &request.v_b_g1,
+            &request.v_b_g2,
+        ];
+        assert_eq!(
+            stats.request_bytes,
+            sent.iter().map(|v| v.len()).sum::<usize>()
+        );
+        assert!(stats.to_string().contains("b_g2: n = "));
+
+        // Vectors of MSMs computed locally are empty
+        let sapk = sapk.with_delegation(Delegation {
+            h: true,
+            ..Delegation::NONE
+        });
+        let circuit = CubeCircuit {
+            x: Some(Fr::from(3u64)),
+        };
+        let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let request = ProveRequest::from(&request);
+        assert_eq!(sapk.stats().request_bytes, request.v_h.len() + 4 * 8);
+        assert_eq!(request.v_b_g2.len(), 8);
+    }
+}
@@ -0,0 +1,350 @@
+//! Delegated QAP witness map: the FFTs behind the h polynomial, evaluated by the
+//! server on one-time-padded vectors.
+//!
+//! The libsnark witness map takes the constraint evaluations a, b, c over a
+//! domain of size n, extends each to a coset (`ifft` then coset `fft`), computes
+//! (a·b − c) / Z pointwise and interpolates the result back (coset `ifft`). Both
+//! transforms are linear, so the client sends v + r for a uniformly random r and
+//! subtracts the transform of r from the answer. The pads and their transforms
+//! are computed ahead of time (`WitnessMapMasks::generate`, as costly as the
+//! witness map itself), leaving O(n) work on the client while proving. Each set
+//! of masks hides exactly one witness map and must not be reused.
+//!
+//! The server is trusted to compute correctly, as for the MSMs: a wrong answer
+//! yields an h that makes the proof fail to verify.
+
+use ark_bn254::Fr;
+use ark_ff::{FftField, Field, UniformRand, Zero};
+use ark_groth16::r1cs_to_qap::evaluate_constraint;
+use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
+use ark_relations::r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, OptimizationGoal, SynthesisError, SynthesisMode,
+};
+use ark_std::rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::server_aided::{QapReduction, ServerAidedProvingKey, SynthesizedWitness};
+use crate::error::{DimensionMismatch, StealthSnarkError};
+
+/// Largest domain a server evaluates, matching the largest supported MSM.
+pub const MAX_LOG_DOMAIN: u32 = 28;
+
+/// A linear transform of the witness map, as evaluated by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum FftStep {
+    /// Evaluations over the domain to evaluations over its coset: `ifft`, then
+    /// coset `fft`.
+    Extend,
+    /// Evaluations over the coset to coefficients: coset `ifft`.
+    Interpolate,
+}
+
+impl FftStep {
+    /// Apply the transform in place. `v.len()` must be a power of two of at
+    /// most 2^`MAX_LOG_DOMAIN`.
+    pub fn apply(self, v: &mut Vec<Fr>) -> Result<(), SynthesisError> {
+        let domain = domain(v.len())?;
+        if domain.size() != v.len() {
+            return Err(SynthesisError::PolynomialDegreeTooLarge);
+        }
+        let coset = domain
+            .get_coset(Fr::GENERATOR)
+            .ok_or(SynthesisError::PolynomialDegreeTooLarge)?;
+        match self {
+            FftStep::Extend => {
+                domain.ifft_in_place(v);
+                coset.fft_in_place(v);
+            }
+            FftStep::Interpolate => coset.ifft_in_place(v),
+        }
+        Ok(())
+    }
+}
+
+fn domain(size: usize) -> Result<GeneralEvaluationDomain<Fr>, SynthesisError> {
+    if size > 1 << MAX_LOG_DOMAIN {
+        return Err(SynthesisError::PolynomialDegreeTooLarge);
+    }
+    GeneralEvaluationDomain::new(size).ok_or(SynthesisError::PolynomialDegreeTooLarge)
+}
+
+/// Constraint evaluations a, b, c over the QAP domain, zero-padded to its size.
+pub struct QapEvaluations {
+    pub a: Vec<Fr>,
+    pub b: Vec<Fr>,
+    pub c: Vec<Fr>,
+}
+
+impl QapEvaluations {
+    pub fn domain_size(&self) -> usize {
+        self.a.len()
+    }
+}
+
+/// Synthesize `circuit` and evaluate its constraints, as the first half of the
+/// libsnark witness map. Circom keys use another reduction, which is not
+/// supported.
+pub fn synthesize<C: ConstraintSynthesizer<Fr>>(
+    sapk: &ServerAidedProvingKey,
+    circuit: C,
+) -> Result<(SynthesizedWitness, QapEvaluations), StealthSnarkError> {
+    if sapk.reduction != QapReduction::Libsnark {
+        return Err(StealthSnarkError::InvalidArgument(
+            "the delegated witness map only supports the libsnark reduction".to_string(),
+        ));
+    }
+    let cs = ConstraintSystem::<Fr>::new_ref();
+    cs.set_optimization_goal(OptimizationGoal::Constraints);
+    cs.set_mode(SynthesisMode::Prove {
+        construct_matrices: true,
+    });
+    circuit.generate_constraints(cs.clone())?;
+    cs.finalize();
+
+    let matrices = cs.to_matrices().ok_or(SynthesisError::MissingCS)?;
+    let num_instance_variables = cs.num_instance_variables();
+    let num_constraints = cs.num_constraints();
+    let full_assignment = {
+        let inner = cs.borrow().ok_or(SynthesisError::MissingCS)?;
+        [
+            inner.instance_assignment.as_slice(),
+            inner.witness_assignment.as_slice(),
+        ]
+        .concat()
+    };
+    drop(cs);
+
+    let domain_size = domain(num_constraints + num_instance_variables)?.size();
+    let evaluate = |rows: &[Vec<(Fr, usize)>]| {
+        let mut v = vec![Fr::zero(); domain_size];
+        for (v, row) in v.iter_mut().zip(rows) {
+            *v = evaluate_constraint(row, &full_assignment);
+        }
+        v
+    };
+    let mut a = evaluate(&matrices.a);
+    let b = evaluate(&matrices.b);
+    let c = evaluate(&matrices.c);
+    // The instance sits in a's rows after the constraints, as in libsnark
+    a[num_constraints..num_constraints + num_instance_variables]
+        .copy_from_slice(&full_assignment[..num_instance_variables]);
+
+    let witness = SynthesizedWitness {
+        full_assignment,
+        num_instance_variables,
+    };
+    Ok((witness, QapEvaluations { a, b, c }))
+}
+
+/// One-time pads for one delegated witness map over a domain of `domain_size`,
+/// with their transforms.
+///
+/// Masks pad exactly one witness map: two maskings share their pads, and the
+/// difference of the padded vectors reveals the difference of the witnesses.
+/// They are deliberately not `Clone`, and `mask`, `combine` and `unmask` each
+/// consume the state left by the step before.
+pub struct WitnessMapMasks {
+    /// Pads of a, b, c and their `FftStep::Extend` transforms.
+    pads: [Vec<Fr>; 3],
+    extended: [Vec<Fr>; 3],
+    /// Pad of (a·b − c) / Z over the coset and its `FftStep::Interpolate` transform.
+    quotient_pad: Vec<Fr>,
+    interpolated: Vec<Fr>,
+}
+
+impl WitnessMapMasks {
+    /// Sample pads and transform them, at the cost of four FFT steps. Run this
+    /// ahead of time, e.g. while idle between proofs.
+    pub fn generate<R: Rng + CryptoRng>(
+        domain_size: usize,
+        rng: &mut R,
+    ) -> Result<Self, SynthesisError> {
+        let sample = |rng: &mut R| -> Vec<Fr> { (0..domain_size).map(|_| Fr::rand(rng)).collect() };
+        let pads = [sample(rng), sample(rng), sample(rng)];
+        let mut extended = pads.clone();
+        for v in &mut extended {
+            FftStep::Extend.apply(v)?;
+        }
+        let quotient_pad = sample(rng);
+        let mut interpolated = quotient_pad.clone();
+        FftStep::Interpolate.apply(&mut interpolated)?;
+        Ok(Self {
+            pads,
+            extended,
+            quotient_pad,
+            interpolated,
+        })
+    }
+
+    pub fn domain_size(&self) -> usize {
+        self.quotient_pad.len()
+    }
+
+    /// First request: a, b, c padded, for `FftStep::Extend`, with the state
+    /// that unpads the server's answer.
+    pub fn mask(
+        self,
+        evaluations: QapEvaluations,
+    ) -> Result<([Vec<Fr>; 3], ExtendState), StealthSnarkError> {
+        if evaluations.domain_size() != self.domain_size() {
+            return Err(DimensionMismatch {
+                what: "QAP domain of the witness map masks",
+                expected: evaluations.domain_size(),
+                actual: self.domain_size(),
+            }
+            .into());
+        }
+        let QapEvaluations { a, b, c } = evaluations;
+        let mut masked = [a, b, c];
+        for (v, pad) in masked.iter_mut().zip(&self.pads) {
+            add_assign(v, pad);
+        }
+        let state = ExtendState {
+            extended: self.extended,
+            quotient_pad: self.quotient_pad,
+            interpolated: self.interpolated,
+        };
+        Ok((masked, state))
+    }
+}
+
+/// The pads left after `WitnessMapMasks::mask`, for the `FftStep::Extend`
+/// answer.
+pub struct ExtendState {
+    extended: [Vec<Fr>; 3],
+    quotient_pad: Vec<Fr>,
+    interpolated: Vec<Fr>,
+}
+
+impl ExtendState {
+    pub fn domain_size(&self) -> usize {
+        self.quotient_pad.len()
+    }
+
+    /// Second request: unpad the extended a, b, c, and pad (a·b − c) / Z over
+    /// the coset, for `FftStep::Interpolate`.
+    pub fn combine(
+        self,
+        extended: [Vec<Fr>; 3],
+    ) -> Result<(Vec<Fr>, InterpolateState), StealthSnarkError> {
+        check_len(self.domain_size(), extended.iter())?;
+        let [mut a, mut b, mut c] = extended;
+        sub_assign(&mut a, &self.extended[0]);
+        sub_assign(&mut b, &self.extended[1]);
+        sub_assign(&mut c, &self.extended[2]);
+
+        let vanishing_inverse = domain(self.domain_size())?
+            .evaluate_vanishing_polynomial(Fr::GENERATOR)
+            .inverse()
+            .expect("the coset does not meet the domain");
+        for ((a, b), c) in a.iter_mut().zip(&b).zip(&c) {
+            *a = (*a * b - c) * vanishing_inverse;
+        }
+        add_assign(&mut a, &self.quotient_pad);
+        let state = InterpolateState {
+            interpolated: self.interpolated,
+        };
+        Ok((a, state))
+    }
+}
+
+/// The pad left after `ExtendState::combine`, for the `FftStep::Interpolate`
+/// answer.
+pub struct InterpolateState {
+    interpolated: Vec<Fr>,
+}
+
+impl InterpolateState {
+    /// Unpad the interpolated quotient: the coefficients of h, as
+    /// `LibsnarkReduction::witness_map` returns them.
+    pub fn unmask(self, mut interpolated: Vec<Fr>) -> Result<Vec<Fr>, StealthSnarkError> {
+        check_len(self.interpolated.len(), std::iter::once(&interpolated))?;
+        sub_assign(&mut interpolated, &self.interpolated);
+        Ok(interpolated)
+    }
+}
+
+fn check_len<'a>(
+    domain_size: usize,
+    vs: impl Iterator<Item = &'a Vec<Fr>>,
+) -> Result<(), StealthSnarkError> {
+    for v in vs {
+        if v.len() != domain_size {
+            return Err(DimensionMismatch {
+                what: "evaluations returned by the server",
+                expected: domain_size,
+                actual: v.len(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn add_assign(v: &mut [Fr], pad: &[Fr]) {
+    v.iter_mut().zip(pad).for_each(|(v, pad)| *v += pad);
+}
+
+fn sub_assign(v: &mut [Fr], pad: &[Fr]) {
+    v.iter_mut().zip(pad).for_each(|(v, pad)| *v -= pad);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::SquaringChainCircuit;
+    use crate::groth16::server_aided::{client_decrypt, client_encrypt_with_h, server_evaluate};
+    use ark_bn254::Bn254;
+    use ark_groth16::r1cs_to_qap::{LibsnarkReduction, R1CSToQAP};
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_delegated_witness_map() {
+        let mut rng = ChaCha20Rng::seed_from_u64(31);
+        let steps = 20;
+        let blank = SquaringChainCircuit::<Fr> {
+            num_constraints: steps,
+            x: None,
+        };
+        let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(blank, &mut rng).unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let circuit = SquaringChainCircuit {
+            num_constraints: steps,
+            x: Some(Fr::from(3u64)),
+        };
+        let public_inputs = [SquaringChainCircuit::output(Fr::from(3u64), steps)];
+
+        // The local witness map, for comparison
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        cs.set_optimization_goal(OptimizationGoal::Constraints);
+        cs.set_mode(SynthesisMode::Prove {
+            construct_matrices: true,
+        });
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        cs.finalize();
+        let expected =
+            LibsnarkReduction::witness_map::<Fr, GeneralEvaluationDomain<Fr>>(cs).unwrap();
+
+        let (witness, evaluations) = synthesize(&sapk, circuit).unwrap();
+        let masks = WitnessMapMasks::generate(evaluations.domain_size(), &mut rng).unwrap();
+        let (mut first, state) = masks.mask(evaluations).unwrap();
+        for v in &mut first {
+            FftStep::Extend.apply(v).unwrap();
+        }
+        let (mut second, state) = state.combine(first).unwrap();
+        FftStep::Interpolate.apply(&mut second).unwrap();
+        let h = state.unmask(second).unwrap();
+        assert_eq!(h, expected);
+
+        let (request, state) = client_encrypt_with_h(&sapk, witness, h, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &public_inputs, &proof).unwrap());
+
+        assert!(FftStep::Extend.apply(&mut vec![Fr::zero(); 3]).is_err());
+    }
+}
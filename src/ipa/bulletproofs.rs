@@ -0,0 +1,251 @@
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{Field, PrimeField};
+use ark_serialize::CanonicalSerialize;
+use ark_std::rand::Rng;
+use sha2::{Digest, Sha256};
+
+/// Generators of an inner-product argument over vectors of length n, a power of
+/// two: G and H for the two vectors and U for their inner product.
+#[derive(Clone, Debug)]
+pub struct IpaGenerators<G: CurveGroup> {
+    pub g: Vec<G::Affine>,
+    pub h: Vec<G::Affine>,
+    pub u: G::Affine,
+}
+
+impl<G: CurveGroup> IpaGenerators<G> {
+    /// # Panics
+    /// If `g` and `h` differ in length, or their length is not a power of two.
+    pub fn new(g: Vec<G::Affine>, h: Vec<G::Affine>, u: G::Affine) -> Self {
+        assert_eq!(g.len(), h.len(), "G and H must have the same length");
+        assert!(
+            g.len().is_power_of_two(),
+            "the vector length must be a power of two, got {}",
+            g.len()
+        );
+        Self { g, h, u }
+    }
+
+    /// Random multiples of the curve's generator, for tests and benchmarks:
+    /// whoever holds `rng` knows their discrete logarithms and can open
+    /// commitments to anything. Deployments hash to the curve instead.
+    pub fn rand<R: Rng>(n: usize, rng: &mut R) -> Self {
+        let points: Vec<G> = (0..2 * n + 1).map(|_| G::rand(rng)).collect();
+        let mut points = G::normalize_batch(&points);
+        let u = points.pop().expect("2n + 1 points");
+        let h = points.split_off(n);
+        Self::new(points, h, u)
+    }
+
+    pub fn len(&self) -> usize {
+        self.g.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.g.is_empty()
+    }
+
+    /// G followed by H: <a, G> + <b, H> is the single MSM <a || b, G || H>.
+    pub fn concatenated(&self) -> Vec<G::Affine> {
+        [self.g.as_slice(), self.h.as_slice()].concat()
+    }
+
+    /// P = <a, G> + <b, H> + <a, b> U, computed locally.
+    ///
+    /// # Panics
+    /// If `a` or `b` is not of length n.
+    pub fn commit(&self, a: &[G::ScalarField], b: &[G::ScalarField]) -> G {
+        assert_eq!(a.len(), self.len(), "a must have length n");
+        assert_eq!(b.len(), self.len(), "b must have length n");
+        G::msm_unchecked(&self.g, a) + G::msm_unchecked(&self.h, b) + self.u * inner_product(a, b)
+    }
+}
+
+pub fn inner_product<F: Field>(a: &[F], b: &[F]) -> F {
+    a.iter().zip(b).map(|(x, y)| *x * y).sum()
+}
+
+/// The Bulletproofs argument that the prover knows (a, b) with
+/// P = <a, G> + <b, H> + <a, b> U: the L and R of each of the log2(n) halving
+/// rounds, then the folded a and b. It is not zero-knowledge on its own;
+/// protocols blind a and b before committing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InnerProductProof<G: CurveGroup> {
+    pub l: Vec<G::Affine>,
+    pub r: Vec<G::Affine>,
+    pub a: G::ScalarField,
+    pub b: G::ScalarField,
+}
+
+/// Fiat-Shamir transcript: each challenge hashes n, P and every L and R so far.
+struct Transcript(Sha256);
+
+impl Transcript {
+    fn new<G: CurveGroup>(n: usize, commitment: &G) -> Self {
+        let mut transcript = Self(Sha256::new());
+        transcript.0.update(b"stealthsnark ipa");
+        transcript.0.update((n as u64).to_le_bytes());
+        transcript.append(&commitment.into_affine());
+        transcript
+    }
+
+    fn append<T: CanonicalSerialize>(&mut self, value: &T) {
+        let mut bytes = Vec::new();
+        value
+            .serialize_compressed(&mut bytes)
+            .expect("serialization failed");
+        self.0.update(bytes);
+    }
+
+    /// The next challenge x, with its inverse.
+    fn challenge<F: PrimeField>(&mut self) -> (F, F) {
+        let digest = self.0.clone().finalize();
+        self.0.update(digest);
+        let x = F::from_le_bytes_mod_order(&digest);
+        let x_inv = x
+            .inverse()
+            .expect("a zero challenge has negligible probability");
+        (x, x_inv)
+    }
+}
+
+/// lo[i] * lo_factor + hi[i] * hi_factor.
+fn fold<G: CurveGroup>(
+    lo: &[G::Affine],
+    hi: &[G::Affine],
+    lo_factor: G::ScalarField,
+    hi_factor: G::ScalarField,
+) -> Vec<G::Affine> {
+    let folded: Vec<G> = lo
+        .iter()
+        .zip(hi)
+        .map(|(lo, hi)| *lo * lo_factor + *hi * hi_factor)
+        .collect();
+    G::normalize_batch(&folded)
+}
+
+/// Prove knowledge of `a` and `b` opening `commitment`, which must be
+/// `generators.commit(a, b)`, e.g. as delegated by `DelegatedIpaKey::commit`.
+/// Each round takes four MSMs over half the remaining generators.
+///
+/// # Panics
+/// If `a` or `b` is not of length n.
+pub fn prove<G: CurveGroup>(
+    generators: &IpaGenerators<G>,
+    commitment: &G,
+    mut a: Vec<G::ScalarField>,
+    mut b: Vec<G::ScalarField>,
+) -> InnerProductProof<G> {
+    assert_eq!(a.len(), generators.len(), "a must have length n");
+    assert_eq!(b.len(), generators.len(), "b must have length n");
+    let u = generators.u;
+    let mut transcript = Transcript::new(generators.len(), commitment);
+    let (mut g, mut h) = (generators.g.clone(), generators.h.clone());
+    let (mut l_vec, mut r_vec) = (Vec::new(), Vec::new());
+    while a.len() > 1 {
+        let half = a.len() / 2;
+        let (a_lo, a_hi) = a.split_at(half);
+        let (b_lo, b_hi) = b.split_at(half);
+        let (g_lo, g_hi) = g.split_at(half);
+        let (h_lo, h_hi) = h.split_at(half);
+
+        let l = G::msm_unchecked(g_hi, a_lo)
+            + G::msm_unchecked(h_lo, b_hi)
+            + u * inner_product(a_lo, b_hi);
+        let r = G::msm_unchecked(g_lo, a_hi)
+            + G::msm_unchecked(h_hi, b_lo)
+            + u * inner_product(a_hi, b_lo);
+        let lr = G::normalize_batch(&[l, r]);
+        transcript.append(&lr[0]);
+        transcript.append(&lr[1]);
+        let (x, x_inv) = transcript.challenge::<G::ScalarField>();
+
+        a = a_lo
+            .iter()
+            .zip(a_hi)
+            .map(|(lo, hi)| *lo * x + *hi * x_inv)
+            .collect();
+        b = b_lo
+            .iter()
+            .zip(b_hi)
+            .map(|(lo, hi)| *lo * x_inv + *hi * x)
+            .collect();
+        g = fold::<G>(g_lo, g_hi, x_inv, x);
+        h = fold::<G>(h_lo, h_hi, x, x_inv);
+        l_vec.push(lr[0]);
+        r_vec.push(lr[1]);
+    }
+    InnerProductProof {
+        l: l_vec,
+        r: r_vec,
+        a: a[0],
+        b: b[0],
+    }
+}
+
+/// Whether `proof` opens `commitment` under `generators`. Folds the generators
+/// the way the prover did, in O(n) group operations.
+pub fn verify<G: CurveGroup>(
+    generators: &IpaGenerators<G>,
+    commitment: &G,
+    proof: &InnerProductProof<G>,
+) -> bool {
+    let rounds = generators.len().trailing_zeros() as usize;
+    if proof.l.len() != rounds || proof.r.len() != rounds {
+        return false;
+    }
+    let mut transcript = Transcript::new(generators.len(), commitment);
+    let mut p = *commitment;
+    let (mut g, mut h) = (generators.g.clone(), generators.h.clone());
+    for (l, r) in proof.l.iter().zip(&proof.r) {
+        transcript.append(l);
+        transcript.append(r);
+        let (x, x_inv) = transcript.challenge::<G::ScalarField>();
+        p += *l * x.square() + *r * x_inv.square();
+
+        let half = g.len() / 2;
+        g = fold::<G>(&g[..half], &g[half..], x_inv, x);
+        h = fold::<G>(&h[..half], &h[half..], x, x_inv);
+    }
+    p == g[0].into_group() * proof.a
+        + h[0].into_group() * proof.b
+        + generators.u * (proof.a * proof.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curves::grumpkin::GrumpkinProjective;
+    use ark_bn254::G1Projective;
+    use ark_std::UniformRand;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn roundtrip<G: CurveGroup>(n: usize, rng: &mut ChaCha20Rng) {
+        let generators = IpaGenerators::<G>::rand(n, rng);
+        let a: Vec<G::ScalarField> = (0..n).map(|_| UniformRand::rand(rng)).collect();
+        let b: Vec<G::ScalarField> = (0..n).map(|_| UniformRand::rand(rng)).collect();
+        let commitment = generators.commit(&a, &b);
+        let proof = prove(&generators, &commitment, a.clone(), b.clone());
+        assert_eq!(proof.l.len(), n.trailing_zeros() as usize);
+        assert!(verify(&generators, &commitment, &proof));
+
+        // Another commitment, or a tampered proof, does not verify
+        let other = generators.commit(&b, &a);
+        assert!(!verify(&generators, &other, &proof));
+        let mut tampered = proof.clone();
+        tampered.a += G::ScalarField::ONE;
+        assert!(!verify(&generators, &commitment, &tampered));
+        tampered = proof;
+        tampered.l.push(generators.u);
+        assert!(!verify(&generators, &commitment, &tampered));
+    }
+
+    #[test]
+    fn test_inner_product_argument() {
+        let mut rng = ChaCha20Rng::seed_from_u64(53);
+        roundtrip::<G1Projective>(16, &mut rng);
+        roundtrip::<GrumpkinProjective>(8, &mut rng);
+        roundtrip::<GrumpkinProjective>(1, &mut rng);
+    }
+}
@@ -0,0 +1,139 @@
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_std::rand::{CryptoRng, Rng};
+
+use super::bulletproofs::{inner_product, prove, InnerProductProof, IpaGenerators};
+use crate::emsm::emsm::{decrypt, encrypt, EmsmPublicParams, PreprocessedCommitments};
+use crate::emsm::pedersen::PedersenError;
+use crate::emsm::sparse_vec::SparseVector;
+use crate::error::Result;
+use crate::protocol::client::EmsmClient;
+use crate::protocol::messages::{DelegatedCurve, SetupResponse};
+
+/// `IpaGenerators` with EMSM parameters over G || H, so that the commitment
+/// <a, G> + <b, H> is one masked MSM of a || b, evaluated by the server. The
+/// <a, b> U term is a single scalar multiplication and stays local.
+pub struct DelegatedIpaKey<G: CurveGroup> {
+    pub generators: IpaGenerators<G>,
+    pub params: EmsmPublicParams<G>,
+    pub preprocessed: PreprocessedCommitments<G>,
+}
+
+/// What `DelegatedIpaKey::unmask` needs of a masked commitment.
+pub struct IpaMask<F: PrimeField> {
+    noise: SparseVector<F>,
+    inner_product: F,
+}
+
+impl<G: CurveGroup> DelegatedIpaKey<G> {
+    /// Sample the masking code over G || H and preprocess it.
+    pub fn new<R: Rng + CryptoRng>(generators: IpaGenerators<G>, rng: &mut R) -> Self {
+        let params = EmsmPublicParams::new(generators.concatenated(), rng);
+        let preprocessed = params.preprocess();
+        Self {
+            generators,
+            params,
+            preprocessed,
+        }
+    }
+
+    /// a || b masked for the server's MSM over G || H.
+    ///
+    /// # Panics
+    /// If `a` or `b` is not of length n.
+    pub fn mask<R: Rng + CryptoRng>(
+        &self,
+        a: &[G::ScalarField],
+        b: &[G::ScalarField],
+        rng: &mut R,
+    ) -> (Vec<G::ScalarField>, IpaMask<G::ScalarField>) {
+        assert_eq!(a.len(), self.generators.len(), "a must have length n");
+        assert_eq!(b.len(), self.generators.len(), "b must have length n");
+        let (masked, noise) = encrypt(&self.params, &[a, b].concat(), rng);
+        let mask = IpaMask {
+            noise,
+            inner_product: inner_product(a, b),
+        };
+        (masked, mask)
+    }
+
+    /// The commitment <a, G> + <b, H> + <a, b> U from the server's MSM of the
+    /// masked vector. Fails if `mask` is not from this key's `mask`.
+    pub fn unmask(&self, result: G, mask: &IpaMask<G::ScalarField>) -> Result<G, PedersenError> {
+        let msm = decrypt(result, &mask.noise, &self.preprocessed)?;
+        Ok(msm + self.generators.u * mask.inner_product)
+    }
+
+    /// Upload G || H as the generator set named by the client's `with_circuit`.
+    pub async fn upload(&self, client: &EmsmClient) -> Result<SetupResponse>
+    where
+        G: DelegatedCurve,
+    {
+        client
+            .send_generator_set::<G>(&self.params.generators)
+            .await
+    }
+
+    /// <a, G> + <b, H> + <a, b> U, with the MSM delegated to the server of
+    /// `client` (`EmsmClient::send_eval`). The generators must have been
+    /// uploaded with `upload`.
+    pub async fn commit<R: Rng + CryptoRng>(
+        &self,
+        client: &EmsmClient,
+        a: &[G::ScalarField],
+        b: &[G::ScalarField],
+        rng: &mut R,
+    ) -> Result<G>
+    where
+        G: DelegatedCurve,
+    {
+        let (masked, mask) = self.mask(a, b, rng);
+        let result = client.send_eval::<G>(&masked).await?;
+        Ok(self.unmask(result, &mask)?)
+    }
+
+    /// `commit`, then the inner-product argument for it, whose halving rounds
+    /// run on the calling task.
+    pub async fn prove<R: Rng + CryptoRng>(
+        &self,
+        client: &EmsmClient,
+        a: Vec<G::ScalarField>,
+        b: Vec<G::ScalarField>,
+        rng: &mut R,
+    ) -> Result<(G, InnerProductProof<G>)>
+    where
+        G: DelegatedCurve,
+    {
+        let commitment = self.commit(client, &a, &b, rng).await?;
+        let proof = prove(&self.generators, &commitment, a, b);
+        Ok((commitment, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curves::grumpkin::{GrumpkinProjective, ScalarField};
+    use crate::ipa::bulletproofs::verify;
+    use ark_std::UniformRand;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_delegated_ipa_commitment() {
+        let mut rng = ChaCha20Rng::seed_from_u64(54);
+        let generators = IpaGenerators::<GrumpkinProjective>::rand(32, &mut rng);
+        let key = DelegatedIpaKey::new(generators, &mut rng);
+        let a: Vec<ScalarField> = (0..32).map(|_| ScalarField::rand(&mut rng)).collect();
+        let b: Vec<ScalarField> = (0..32).map(|_| ScalarField::rand(&mut rng)).collect();
+
+        let (masked, mask) = key.mask(&a, &b, &mut rng);
+        assert_eq!(masked.len(), 64);
+        let result = key.params.server_computation(&masked).unwrap();
+        let commitment = key.unmask(result, &mask).unwrap();
+        assert_eq!(commitment, key.generators.commit(&a, &b));
+
+        let proof = prove(&key.generators, &commitment, a, b);
+        assert!(verify(&key.generators, &commitment, &proof));
+    }
+}
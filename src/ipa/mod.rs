@@ -0,0 +1,8 @@
+//! Inner-product arguments (Bulletproofs) with their vector commitments
+//! delegated through EMSM. The commitment <a, G> + <b, H> is one MSM over
+//! fixed generators, twice the vector length, and dominates a prover's cost;
+//! the halving rounds of the argument fold the generators with each challenge,
+//! so they run locally.
+
+pub mod bulletproofs;
+pub mod delegated;
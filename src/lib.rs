@@ -1,3 +1,16 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
 pub mod emsm;
+#[cfg(feature = "std")]
+pub mod curves;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
 pub mod groth16;
+#[cfg(feature = "std")]
+pub mod ipa;
+#[cfg(feature = "std")]
 pub mod protocol;
+pub mod rng;
+#[cfg(feature = "std")]
+pub mod serialization;
@@ -0,0 +1,312 @@
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+/// Domain separator for the attestation report-data binding.
+const BINDING_DOMAIN: &[u8] = b"stealthsnark-attestation-v1";
+
+/// Trusted execution environment that produced a quote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum TeePlatform {
+    Sgx,
+    SevSnp,
+}
+
+/// Attestation evidence returned by POST /attestation.
+///
+/// The hardware-signed `quote` covers `measurement` (MRENCLAVE / launch digest) and
+/// `report_data`, which must equal `report_data_binding(nonce, tls_key_fingerprint)`.
+/// This ties the quote to the client's fresh nonce and to the server's TLS key.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AttestationReport {
+    pub platform: TeePlatform,
+    pub measurement: Vec<u8>,
+    pub report_data: [u8; 32],
+    /// SHA-256 of the server's TLS public key (SPKI DER).
+    pub tls_key_fingerprint: [u8; 32],
+    /// Raw platform quote (SGX DCAP quote or SEV-SNP attestation report).
+    pub quote: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttestationError {
+    #[error("server does not provide attestation")]
+    Unavailable,
+    #[error("quote verification failed: {0}")]
+    InvalidQuote(String),
+    #[error("unexpected TEE platform {0:?}")]
+    PlatformMismatch(TeePlatform),
+    #[error("code measurement is not in the allowlist")]
+    MeasurementNotAllowed,
+    #[error("report data does not bind the nonce and TLS key")]
+    BindingMismatch,
+    #[error("attested TLS key does not match the pinned key")]
+    TlsKeyMismatch,
+    #[error("attestation needs an https:// server, whose TLS key it binds")]
+    PlaintextTransport,
+    #[error("the TLS connection is not to the attested key")]
+    ConnectionKeyMismatch,
+}
+
+/// Server-side source of attestation evidence (e.g. a wrapper around the SGX
+/// quoting enclave or `/dev/sev-guest`).
+pub trait AttestationProvider: Send + Sync {
+    /// Produce a report whose `report_data` binds `nonce` and the server's TLS key.
+    fn attest(&self, nonce: &[u8; 32]) -> Result<AttestationReport, AttestationError>;
+}
+
+/// Client-side verification of the platform signature on a quote (DCAP / VCEK
+/// certificate chain). Platform-specific, so supplied by the embedder.
+pub trait QuoteVerifier: Send + Sync {
+    /// Check the quote's signature chain and that it covers the report's
+    /// `measurement` and `report_data`.
+    fn verify_quote(&self, report: &AttestationReport) -> Result<(), AttestationError>;
+}
+
+/// What the client requires of a server's attestation before sending it any data.
+#[derive(Clone)]
+pub struct AttestationPolicy {
+    pub platform: TeePlatform,
+    /// Accepted code measurements.
+    pub allowed_measurements: Vec<Vec<u8>>,
+    /// Pinned TLS key fingerprint; `None` accepts any attested key.
+    pub tls_key_fingerprint: Option<[u8; 32]>,
+    pub verifier: Arc<dyn QuoteVerifier>,
+}
+
+impl AttestationPolicy {
+    /// Verify a report obtained with `nonce` against this policy.
+    pub fn verify(
+        &self,
+        report: &AttestationReport,
+        nonce: &[u8; 32],
+    ) -> Result<(), AttestationError> {
+        if report.platform != self.platform {
+            return Err(AttestationError::PlatformMismatch(report.platform));
+        }
+        self.verifier.verify_quote(report)?;
+        if !self.allowed_measurements.contains(&report.measurement) {
+            return Err(AttestationError::MeasurementNotAllowed);
+        }
+        if report.report_data != report_data_binding(nonce, &report.tls_key_fingerprint) {
+            return Err(AttestationError::BindingMismatch);
+        }
+        if self
+            .tls_key_fingerprint
+            .is_some_and(|pinned| pinned != report.tls_key_fingerprint)
+        {
+            return Err(AttestationError::TlsKeyMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// `tls_key_fingerprint` of a certificate: SHA-256 of its SPKI DER.
+pub fn tls_key_fingerprint(cert: &CertificateDer<'_>) -> Result<[u8; 32], CertificateError> {
+    let cert = webpki::EndEntityCert::try_from(cert).map_err(|_| CertificateError::BadEncoding)?;
+    Ok(Sha256::digest(cert.subject_public_key_info()).into())
+}
+
+/// Verifier of the server certificates of an attested server. An enclave's
+/// certificate is usually self-signed, so no chain is checked: the attestation
+/// vouches for the key instead. With a pin, only certificates of that key are
+/// accepted; the fingerprint of the last certificate seen is kept either way,
+/// so that the connection the report came over can be checked against it.
+/// Handshake signatures are checked as usual, so the peer must hold the key.
+#[derive(Debug)]
+pub(crate) struct AttestedKeyVerifier {
+    pinned: Option<[u8; 32]>,
+    seen: Mutex<Option<[u8; 32]>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl AttestedKeyVerifier {
+    /// Accept any certificate, recording its key for `seen`.
+    pub(crate) fn unpinned() -> Arc<Self> {
+        Self::new(None)
+    }
+
+    /// Accept only certificates of the key with fingerprint `pinned`.
+    pub(crate) fn pinned(pinned: [u8; 32]) -> Arc<Self> {
+        Self::new(Some(pinned))
+    }
+
+    fn new(pinned: Option<[u8; 32]>) -> Arc<Self> {
+        Arc::new(Self {
+            pinned,
+            seen: Mutex::new(None),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        })
+    }
+
+    /// Fingerprint of the key of the last certificate accepted.
+    pub(crate) fn seen(&self) -> Option<[u8; 32]> {
+        *self.seen.lock().unwrap()
+    }
+
+    /// A rustls client configuration verifying servers with `self`, offering
+    /// `alpn_protocols`.
+    pub(crate) fn client_config(
+        self: &Arc<Self>,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> rustls::ClientConfig {
+        let mut config = rustls::ClientConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .dangerous()
+            .with_custom_certificate_verifier(self.clone())
+            .with_no_client_auth();
+        config.alpn_protocols = alpn_protocols;
+        config
+    }
+}
+
+impl ServerCertVerifier for AttestedKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = tls_key_fingerprint(end_entity)?;
+        if self.pinned.is_some_and(|pinned| pinned != fingerprint) {
+            return Err(CertificateError::ApplicationVerificationFailure.into());
+        }
+        *self.seen.lock().unwrap() = Some(fingerprint);
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Expected `report_data`: SHA-256(domain || nonce || tls_key_fingerprint).
+pub fn report_data_binding(nonce: &[u8; 32], tls_key_fingerprint: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(BINDING_DOMAIN)
+        .chain_update(nonce)
+        .chain_update(tls_key_fingerprint)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AcceptAll;
+
+    impl QuoteVerifier for AcceptAll {
+        fn verify_quote(&self, _report: &AttestationReport) -> Result<(), AttestationError> {
+            Ok(())
+        }
+    }
+
+    fn policy() -> AttestationPolicy {
+        AttestationPolicy {
+            platform: TeePlatform::Sgx,
+            allowed_measurements: vec![vec![7u8; 32]],
+            tls_key_fingerprint: Some([9u8; 32]),
+            verifier: Arc::new(AcceptAll),
+        }
+    }
+
+    fn report(nonce: &[u8; 32]) -> AttestationReport {
+        AttestationReport {
+            platform: TeePlatform::Sgx,
+            measurement: vec![7u8; 32],
+            report_data: report_data_binding(nonce, &[9u8; 32]),
+            tls_key_fingerprint: [9u8; 32],
+            quote: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_policy_accepts_bound_report() {
+        let nonce = [1u8; 32];
+        assert!(policy().verify(&report(&nonce), &nonce).is_ok());
+    }
+
+    #[test]
+    fn test_policy_rejects_mismatches() {
+        let nonce = [1u8; 32];
+
+        // Replayed report for a different nonce
+        let stale = report(&[2u8; 32]);
+        assert!(matches!(
+            policy().verify(&stale, &nonce),
+            Err(AttestationError::BindingMismatch)
+        ));
+
+        let mut unknown_code = report(&nonce);
+        unknown_code.measurement = vec![8u8; 32];
+        assert!(matches!(
+            policy().verify(&unknown_code, &nonce),
+            Err(AttestationError::MeasurementNotAllowed)
+        ));
+
+        let mut other_key = report(&nonce);
+        other_key.tls_key_fingerprint = [3u8; 32];
+        other_key.report_data = report_data_binding(&nonce, &[3u8; 32]);
+        assert!(matches!(
+            policy().verify(&other_key, &nonce),
+            Err(AttestationError::TlsKeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_verifier_pins_the_attested_key() {
+        let cert = |name: &str| {
+            let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+            cert.cert.der().clone()
+        };
+        let (attested, relay) = (cert("enclave"), cert("relay"));
+        let fingerprint = tls_key_fingerprint(&attested).unwrap();
+        let name = ServerName::try_from("enclave").unwrap();
+        let verify = |verifier: &AttestedKeyVerifier, cert: &CertificateDer<'_>| {
+            verifier.verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+        };
+
+        let pinned = AttestedKeyVerifier::pinned(fingerprint);
+        assert!(verify(&pinned, &attested).is_ok());
+        assert!(verify(&pinned, &relay).is_err());
+
+        // Unpinned, any key is accepted and recorded
+        let unpinned = AttestedKeyVerifier::unpinned();
+        verify(&unpinned, &relay).unwrap();
+        assert_ne!(unpinned.seen(), Some(fingerprint));
+        verify(&unpinned, &attested).unwrap();
+        assert_eq!(unpinned.seen(), Some(fingerprint));
+    }
+}
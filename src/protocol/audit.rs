@@ -0,0 +1,306 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// An auditable server event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Generators were stored for a circuit or generator set of a session
+    /// (`replaced` if it already existed). A generator set's `sizes` are its
+    /// length, then zeros.
+    SessionCreated {
+        session_id: String,
+        circuit: String,
+        generators_hash: String,
+        sizes: [usize; 5],
+        replaced: bool,
+    },
+    /// A setup request was refused.
+    SetupRejected {
+        session_id: Option<String>,
+        status: u16,
+    },
+    /// A prove request was answered.
+    ProveServed {
+        session_id: String,
+        request_hash: String,
+        response_hash: String,
+        duration_ms: u64,
+    },
+    /// A prove request was refused.
+    ProveRejected {
+        session_id: Option<String>,
+        status: u16,
+    },
+}
+
+/// One line of the audit trail.
+///
+/// Records are hash-chained: `prev_hash` is the SHA-256 of the previous record's
+/// serialized line, so truncating or editing the trail is detectable.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub prev_hash: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Destination for serialized audit records. Sinks must only ever append.
+pub trait AuditSink: Send + Sync {
+    fn append(&self, line: &str) -> std::io::Result<()>;
+}
+
+impl<S: AuditSink + ?Sized> AuditSink for Arc<S> {
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        (**self).append(line)
+    }
+}
+
+/// Appends one JSON record per line to a file.
+///
+/// The file is written by a thread of its own, so that `append` only queues the
+/// line and request handlers never wait on the disk. A line that fails to write
+/// is logged there; the next record still chains to it, so `verify_chain`
+/// points at the gap. Dropping the sink waits for the queued lines.
+pub struct FileAuditSink {
+    lines: Option<Sender<String>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl FileAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (lines, queued) = mpsc::channel::<String>();
+        let writer = std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || {
+                for line in queued {
+                    let written = file
+                        .write_all(line.as_bytes())
+                        .and_then(|()| file.write_all(b"\n"))
+                        .and_then(|()| file.flush());
+                    if let Err(e) = written {
+                        tracing::warn!(error = %e, "Audit record failed to write");
+                    }
+                }
+            })?;
+        Ok(Self {
+            lines: Some(lines),
+            writer: Some(writer),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        let lines = self.lines.as_ref().expect("set until the sink is dropped");
+        lines.send(line.to_string()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "audit writer stopped")
+        })
+    }
+}
+
+impl Drop for FileAuditSink {
+    fn drop(&mut self) {
+        // Closing the channel ends the writer once it has drained it
+        drop(self.lines.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Keeps audit lines in memory (for embedding and tests).
+#[derive(Default)]
+pub struct MemoryAuditSink {
+    lines: Mutex<Vec<String>>,
+}
+
+impl MemoryAuditSink {
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        self.lines.lock().unwrap().push(line.to_string());
+        Ok(())
+    }
+}
+
+/// Sequences, chains, and writes audit records to a sink.
+pub struct AuditLog {
+    sink: Box<dyn AuditSink>,
+    /// (next sequence number, hash of the last written line)
+    chain: Mutex<(u64, [u8; 32])>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (seq, _) = *self.chain.lock().unwrap();
+        f.debug_struct("AuditLog").field("seq", &seq).finish()
+    }
+}
+
+impl AuditLog {
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            chain: Mutex::new((0, [0u8; 32])),
+        }
+    }
+
+    /// Open (or create) a JSON-lines audit file, continuing the chain from its last record.
+    pub fn open_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let mut chain = (0, [0u8; 32]);
+        if path.exists() {
+            let contents = std::fs::read_to_string(path)?;
+            if let Some(last) = contents.lines().rev().find(|l| !l.is_empty()) {
+                let record: AuditRecord = serde_json::from_str(last)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                chain = (record.seq + 1, sha256(last.as_bytes()));
+            }
+        }
+        let log = Self::new(FileAuditSink::open(path)?);
+        *log.chain.lock().unwrap() = chain;
+        Ok(log)
+    }
+
+    /// Append an event. Sink failures are logged, not propagated: an unavailable
+    /// audit sink should not take the proving service down.
+    pub fn record(&self, event: AuditEvent) {
+        let mut chain = self.chain.lock().unwrap();
+        let record = AuditRecord {
+            seq: chain.0,
+            timestamp_ms: now_ms(),
+            prev_hash: to_hex(&chain.1),
+            event,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!(error = %e, "Audit record failed to serialize");
+                return;
+            }
+        };
+        if let Err(e) = self.sink.append(&line) {
+            tracing::warn!(seq = record.seq, error = %e, "Audit record failed to append");
+            return;
+        }
+        *chain = (chain.0 + 1, sha256(line.as_bytes()));
+    }
+}
+
+/// Parse an audit trail and check its sequence numbers and hash chain.
+/// Returns the parsed records, or the index of the first line that breaks the chain.
+pub fn verify_chain<S: AsRef<str>>(lines: &[S]) -> Result<Vec<AuditRecord>, usize> {
+    let mut prev = [0u8; 32];
+    let mut records = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.as_ref();
+        let record: AuditRecord = serde_json::from_str(line).map_err(|_| i)?;
+        if record.seq != i as u64 || record.prev_hash != to_hex(&prev) {
+            return Err(i);
+        }
+        prev = sha256(line.as_bytes());
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Lowercase hex encoding.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_log() -> (AuditLog, Arc<MemoryAuditSink>) {
+        let sink = Arc::new(MemoryAuditSink::default());
+        let log = AuditLog::new(sink.clone());
+        log.record(AuditEvent::SessionCreated {
+            session_id: "s".to_string(),
+            circuit: "default".to_string(),
+            generators_hash: to_hex(&sha256(b"gens")),
+            sizes: [4, 3, 3, 3, 3],
+            replaced: false,
+        });
+        log.record(AuditEvent::ProveServed {
+            session_id: "s".to_string(),
+            request_hash: to_hex(&sha256(b"req")),
+            response_hash: to_hex(&sha256(b"resp")),
+            duration_ms: 12,
+        });
+        (log, sink)
+    }
+
+    #[test]
+    fn test_chain_verifies() {
+        let (_log, sink) = sample_log();
+        let records = verify_chain(&sink.lines()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(
+            records[1].event,
+            AuditEvent::ProveServed {
+                duration_ms: 12,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_file_log_resumes_chain() {
+        let path =
+            std::env::temp_dir().join(format!("stealthsnark-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for _ in 0..2 {
+            let log = AuditLog::open_file(&path).unwrap();
+            log.record(AuditEvent::SetupRejected {
+                session_id: None,
+                status: 401,
+            });
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(verify_chain(&lines).unwrap().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let (_log, sink) = sample_log();
+        let mut lines = sink.lines();
+        lines[0] = lines[0].replace("\"replaced\":false", "\"replaced\":true");
+        assert_eq!(verify_chain(&lines), Err(1));
+
+        // Dropping a record breaks the sequence
+        let lines = sink.lines();
+        assert_eq!(verify_chain(&lines[1..]), Err(0));
+    }
+}
@@ -1,13 +1,365 @@
-use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use super::messages::{ProveRequest, ProveResponse, SetupRequest};
-use super::server::{ProveEnvelope, SetupEnvelope};
+use ark_bn254::{Bn254, Fr};
+use ark_ec::CurveGroup;
+use ark_groth16::Proof;
+use ark_relations::r1cs::ConstraintSynthesizer;
+use ark_std::rand::{CryptoRng, Rng};
+use axum::body::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use super::attestation::{
+    AttestationError, AttestationPolicy, AttestationReport, AttestedKeyVerifier,
+};
+use super::codec::{WireCodec, CODEC_HEADER};
+use super::gate::{solve_pow, SetupCredential, API_KEY_HEADER, POW_HEADER};
+use super::messages::{
+    ark_from_bytes, ark_vec_from_bytes, ark_vec_to_bytes, Capabilities, CrsCommitment, CurveId,
+    DelegatedCurve, EstimateRequest, EstimateResponse, EvalRequest, EvalResponse, FftRequest,
+    FftResponse, GeneratorChunks, GeneratorSetRequest, KeepaliveRequest, KeepaliveResponse,
+    MaliciousEvalRequest, MaliciousEvalResponse, MsmKind, MsmRequest, MsmResponse, ParallelismHint,
+    ProveRequest, ProveResponse, RequestId, ScalarEncoding, SessionToken, SetupChunk, SetupPatch,
+    SetupRequest, SetupResponse, DEADLINE_HEADER, REQUEST_ID_HEADER, SCALAR_ENCODING_HEADER,
+    SESSION_TOKEN_HEADER,
+};
+use super::metering::Usage;
+use super::policy::SecurityPolicy;
+use super::problem::{ErrorCode, Problem, PROBLEM_CONTENT_TYPE};
+use super::proof_cache::{ProofCache, ProofKey};
+use super::scheduler::{QosClass, QOS_HEADER};
+use super::server::{ProveEnvelope, SetupEnvelope, DEFAULT_CIRCUIT};
+use crate::emsm::cancel::CancelToken;
+use crate::emsm::deferred::DeferredPreprocessing;
+use crate::emsm::emsm::{decrypt, encrypt, EmsmPublicParams, PreprocessedCommitments};
+use crate::emsm::malicious::{malicious_decrypt, malicious_encrypt, MaliciousEncrypted};
+use crate::emsm::sparse_vec::SparseVector;
+use crate::error::{DimensionMismatch, Result, StealthSnarkError};
+use crate::groth16::fingerprint::KeyMismatch;
+use crate::groth16::server_aided::{
+    assemble_proof, client_decrypt_async, client_encrypt_async, client_encrypt_with_h,
+    client_synthesize, local_msms, server_evaluate_async, ClientDecryptionState, EncryptedRequest,
+    InvalidResponse, ProvingMode, PublicInputResponse, ServerAidedProvingKey, ServerResponse,
+    UnmaskedMsms,
+};
+use crate::groth16::witness_map::{FftStep, QapEvaluations, WitnessMapMasks};
+
+/// The server's published commitment for a session does not match the generators
+/// the client derived from its own proving key.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("server holds different generators for session {session_id} than the proving key")]
+pub struct CommitmentMismatch {
+    pub session_id: String,
+    pub curve: CurveId,
+    pub expected_hash: [u8; 32],
+    pub published: CrsCommitment,
+}
+
+/// A setup or prove request the server refused. `request_id` finds the
+/// server's log lines for it.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ServerError {
+    /// The server described the failure in a problem body.
+    #[error("{endpoint} failed with status: {problem} (request {request_id})")]
+    Problem {
+        endpoint: String,
+        request_id: RequestId,
+        problem: Problem,
+        /// From the `Retry-After` header.
+        retry_after: Option<Duration>,
+    },
+    /// Only a status came back, e.g. from a proxy in front of the server.
+    #[error("{endpoint} failed with status: {status} (request {request_id})")]
+    Status {
+        endpoint: String,
+        request_id: RequestId,
+        status: u16,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl ServerError {
+    pub fn request_id(&self) -> RequestId {
+        match self {
+            ServerError::Problem { request_id, .. } | ServerError::Status { request_id, .. } => {
+                *request_id
+            }
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            ServerError::Problem { problem, .. } => problem.status,
+            ServerError::Status { status, .. } => *status,
+        }
+    }
+
+    /// The problem's error code, if the server sent a problem body.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ServerError::Problem { problem, .. } => Some(problem.code),
+            ServerError::Status { .. } => None,
+        }
+    }
+
+    /// How long the server asked to be left alone before a retry, if it sent a
+    /// `Retry-After` in seconds.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ServerError::Problem { retry_after, .. } | ServerError::Status { retry_after, .. } => {
+                *retry_after
+            }
+        }
+    }
+
+    /// Whether the server no longer holds the session or its circuit, as after
+    /// a restart or an eviction.
+    pub fn is_lost_session(&self) -> bool {
+        matches!(
+            self.code(),
+            Some(ErrorCode::UnknownSession | ErrorCode::UnknownCircuit)
+        )
+    }
+
+    /// Read the error of a failed `endpoint` request from `resp`.
+    async fn from_response(
+        endpoint: String,
+        request_id: RequestId,
+        resp: reqwest::Response,
+    ) -> Self {
+        let status = resp.status().as_u16();
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        let is_problem = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .is_some_and(|value| {
+                value
+                    .as_bytes()
+                    .starts_with(PROBLEM_CONTENT_TYPE.as_bytes())
+            });
+        if is_problem {
+            if let Ok(problem) = resp.json::<Problem>().await {
+                return ServerError::Problem {
+                    endpoint,
+                    request_id,
+                    problem,
+                    retry_after,
+                };
+            }
+        }
+        ServerError::Status {
+            endpoint,
+            request_id,
+            status,
+            retry_after,
+        }
+    }
+}
+
+/// The server gave up on a request at its deadline (`EmsmClient::with_deadline`)
+/// or its own prove timeout, freeing the workers it held.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{endpoint} exceeded its deadline (request {request_id})")]
+pub struct DeadlineExceeded {
+    pub endpoint: String,
+    pub request_id: RequestId,
+    /// MSM elements the server evaluated before giving up, out of `total`;
+    /// unset by servers that do not report progress.
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+}
+
+impl DeadlineExceeded {
+    /// The timeout of `err`, if it is one.
+    pub fn from_server_error(err: &ServerError) -> Option<Self> {
+        let ServerError::Problem {
+            endpoint,
+            request_id,
+            problem,
+            ..
+        } = err
+        else {
+            return None;
+        };
+        (problem.code == ErrorCode::Timeout).then(|| Self {
+            endpoint: endpoint.clone(),
+            request_id: *request_id,
+            completed: problem.completed,
+            total: problem.expected,
+        })
+    }
+
+    /// Share of the request the server evaluated, from 0 to 1, e.g. to retry
+    /// with a longer deadline a request that came close, and prove locally one
+    /// that did not.
+    pub fn completed_fraction(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(_), Some(0)) => Some(1.0),
+            (Some(completed), Some(total)) => Some(completed as f64 / total as f64),
+            _ => None,
+        }
+    }
+}
+
+/// How `EmsmClient::with_retry_policy` retries prove and MSM requests that a
+/// busy server turned away (429 or 503) with a `Retry-After`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    /// Longest `Retry-After` the client waits out; a server asking for longer
+    /// fails the request right away.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `retries + 1` after `err`, or
+    /// `None` to give up.
+    fn delay(&self, err: &ServerError, retries: u32) -> Option<Duration> {
+        if retries >= self.max_retries || !matches!(err.status(), 429 | 503) {
+            return None;
+        }
+        err.retry_after().filter(|delay| *delay <= self.max_delay)
+    }
+}
+
+/// Where the MSMs of a proof from `EmsmClient::prove_with_report` were evaluated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProvePath {
+    /// By the server.
+    Delegated,
+    /// In-process, after the server could not be reached or answered with an
+    /// error (see `EmsmClient::with_local_fallback`).
+    Local,
+    /// Nowhere: the statement was proven before (see
+    /// `EmsmClient::with_proof_cache`).
+    Cached,
+}
+
+/// A proof together with how it was produced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProveReport {
+    pub proof: Proof<Bn254>,
+    pub path: ProvePath,
+    pub bandwidth: Bandwidth,
+}
+
+/// Bytes a proof moved to and from the server, for budgeting data usage. HTTP
+/// bodies are counted; headers and TLS framing are not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// Bodies of the requests the server got, envelopes included. A failed
+    /// attempt counts, and so do the generators of a session set up again on
+    /// the way (see `EmsmClient::with_auto_resetup`).
+    pub sent: u64,
+    /// Bodies of the responses to those that succeeded.
+    pub received: u64,
+    /// The share of each MSM in the last prove request, in `MsmKind::ALL` order.
+    pub msms: [MsmBandwidth; 5],
+}
+
+impl Bandwidth {
+    /// 32 bytes per masked scalar: the O(n) transfer any encoding of the prove
+    /// request needs, to compare `sent` against.
+    pub fn scalar_bytes(&self) -> u64 {
+        self.msms.iter().map(|msm| 32 * msm.scalars).sum()
+    }
+}
+
+/// What one MSM of a prove request took on the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MsmBandwidth {
+    /// Scalars of the masked vector.
+    pub scalars: u64,
+    /// Bytes of the encoded vector.
+    pub sent: u64,
+    /// Bytes of the encoded result.
+    pub received: u64,
+}
+
+/// Outcome of looking a statement up in a `ProofCache` before delegating it.
+enum CachedEncrypt {
+    Hit(Proof<Bn254>),
+    /// The statement's masked request, and its key for storing the proof.
+    Miss(EncryptedRequest, Box<ClientDecryptionState>, ProofKey),
+}
+
+/// Synthesize `circuit` and answer it from `cache`, or mask it on a miss.
+fn cached_encrypt<C: ConstraintSynthesizer<Fr>, R: Rng + CryptoRng>(
+    sapk: &ServerAidedProvingKey,
+    cache: &ProofCache,
+    circuit: C,
+    mut rng: R,
+) -> Result<CachedEncrypt> {
+    let (witness, h_poly) = client_synthesize(sapk, circuit, &CancelToken::default())?;
+    let key = ProofKey::new(sapk, &witness);
+    if let Some(proof) = cache.get(sapk, &key, &mut rng) {
+        return Ok(CachedEncrypt::Hit(proof));
+    }
+    let (request, state) = client_encrypt_with_h(sapk, witness, h_poly, &mut rng)?;
+    Ok(CachedEncrypt::Miss(request, Box::new(state), key))
+}
+
+/// What the sub-requests of `EmsmClient::prove_split` share.
+struct SplitProve {
+    generators_hash: [u8; 32],
+    request_id: RequestId,
+    sapk: Arc<ServerAidedProvingKey>,
+    state: Arc<ClientDecryptionState>,
+}
 
 /// HTTP client for communicating with the EMSM server.
 pub struct EmsmClient {
     base_url: String,
     session_id: String,
+    /// Circuit of the session this client sets up and proves against.
+    circuit: String,
+    curve: CurveId,
+    /// Generators hash of the circuit, echoed in prove requests.
+    generators_hash: Mutex<Option<[u8; 32]>>,
+    /// Token of the session, sent with every request once known.
+    session_token: Mutex<Option<SessionToken>>,
     client: reqwest::Client,
+    http2: bool,
+    setup_credential: Option<SetupCredential>,
+    attestation: Option<AttestationPolicy>,
+    /// Client that only connects to the attested TLS key, once attested.
+    attested: OnceCell<reqwest::Client>,
+    local_fallback: bool,
+    security_policy: Option<SecurityPolicy>,
+    /// Encoded `SetupRequest` of the last setup, kept with `with_auto_resetup`.
+    setup_payload: Option<Mutex<Option<Vec<u8>>>>,
+    /// Offer `ScalarEncoding::Montgomery` on setup.
+    raw_scalars: bool,
+    /// Encoding agreed on at the last setup, for `prove`.
+    scalar_encoding: Mutex<ScalarEncoding>,
+    /// Codec offered on setup.
+    offered_codec: WireCodec,
+    /// Codec agreed on at the last setup, for prove and MSM requests.
+    codec: Mutex<WireCodec>,
+    proof_cache: Option<Arc<ProofCache>>,
+    /// Sent on the prove requests `prove` builds.
+    parallelism_hint: Option<ParallelismHint>,
+    /// Sent as `QOS_HEADER` on prove and MSM requests.
+    qos: Option<QosClass>,
+    /// Sent as `DEADLINE_HEADER` on prove and MSM requests.
+    deadline: Option<Duration>,
+    retry_policy: Option<RetryPolicy>,
 }
 
 impl EmsmClient {
@@ -15,59 +367,1276 @@ impl EmsmClient {
         Self {
             base_url: base_url.trim_end_matches('/').to_string(),
             session_id,
+            circuit: DEFAULT_CIRCUIT.to_string(),
+            curve: CurveId::default(),
+            generators_hash: Mutex::new(None),
+            session_token: Mutex::new(None),
             client: reqwest::Client::new(),
+            http2: false,
+            setup_credential: None,
+            attestation: None,
+            attested: OnceCell::new(),
+            local_fallback: false,
+            security_policy: None,
+            setup_payload: None,
+            raw_scalars: false,
+            scalar_encoding: Mutex::new(ScalarEncoding::Canonical),
+            offered_codec: WireCodec::Bincode,
+            codec: Mutex::new(WireCodec::Bincode),
+            proof_cache: None,
+            parallelism_hint: None,
+            qos: None,
+            deadline: None,
+            retry_policy: None,
         }
     }
 
-    /// Send setup request: transmit generators to server.
-    pub async fn send_setup(&self, request: &SetupRequest) -> Result<()> {
-        let url = format!("{}/setup", self.base_url);
+    /// Let `prove` evaluate the MSMs in-process when the server fails, instead of
+    /// returning the error. This costs the client the full MSM work of a local
+    /// Groth16 prove; `prove_with_report` tells which path a proof took.
+    pub fn with_local_fallback(mut self) -> Self {
+        self.local_fallback = true;
+        self
+    }
+
+    /// Keep the payload of the last `send_setup` and, when a prove finds that the
+    /// server no longer holds the session or circuit (it restarted or evicted
+    /// it), set it up again and retry the prove once. Costs a copy of the
+    /// generators for as long as the client lives.
+    pub fn with_auto_resetup(mut self) -> Self {
+        self.setup_payload = Some(Mutex::new(None));
+        self
+    }
+
+    /// Offer the server raw Montgomery-form scalars at setup. If it accepts,
+    /// `prove` sends its masked vectors as `ScalarEncoding::Montgomery`, which
+    /// spares both sides a conversion per scalar; otherwise they stay canonical.
+    pub fn with_raw_scalars(mut self) -> Self {
+        self.raw_scalars = true;
+        self
+    }
+
+    /// Scalar encoding agreed on with the server at the last setup.
+    pub fn scalar_encoding(&self) -> ScalarEncoding {
+        *self.scalar_encoding.lock().unwrap()
+    }
+
+    /// Offer the server `codec` at setup. If it accepts, prove and MSM requests
+    /// and their responses are encoded with it; otherwise they stay bincode.
+    /// Setup itself is always sent as bincode.
+    pub fn with_codec(mut self, codec: WireCodec) -> Self {
+        self.offered_codec = codec;
+        self
+    }
+
+    /// Codec agreed on with the server at the last setup.
+    pub fn codec(&self) -> WireCodec {
+        *self.codec.lock().unwrap()
+    }
+
+    /// Look each `prove` up in `cache` before masking anything, and store the
+    /// proofs it delegates there. A statement proven before with the same
+    /// witness is answered from the cache without a request to the server. The
+    /// cache may be shared between clients.
+    pub fn with_proof_cache(mut self, cache: Arc<ProofCache>) -> Self {
+        self.proof_cache = Some(cache);
+        self
+    }
+
+    /// Ask the server to evaluate the prove requests `prove` builds on no more of
+    /// its pool than `hint` allows, e.g. for background proofs that may take
+    /// longer. Requests passed to `send_prove` carry their own hint, and split
+    /// and pre-encoded requests none.
+    pub fn with_parallelism_hint(mut self, hint: ParallelismHint) -> Self {
+        self.parallelism_hint = Some(hint);
+        self
+    }
+
+    /// Queue prove and MSM requests as `class` on servers that schedule them,
+    /// e.g. `QosClass::Batch` for bulk proving that should not hold up wallets
+    /// sharing the server. Unset, the server treats them as interactive.
+    pub fn with_qos(mut self, class: QosClass) -> Self {
+        self.qos = Some(class);
+        self
+    }
+
+    /// Have the server abort prove and MSM requests still running `deadline`
+    /// after it receives them, freeing its workers, rather than keep the client
+    /// waiting. They fail with `StealthSnarkError::DeadlineExceeded`, which
+    /// tells how much the server had evaluated. Each retry gets the full
+    /// deadline again.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Retry prove and MSM requests the server turns away as overloaded after
+    /// the `Retry-After` it asks for, as `policy` allows, instead of failing
+    /// them. Retries keep the request's `RequestId`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Talk HTTP/2 from the first request on (prior knowledge, no upgrade), so
+    /// the sub-requests of `send_prove_split` share one connection. The server
+    /// must accept HTTP/2, as this crate's does.
+    pub fn with_http2(mut self) -> Self {
+        self.client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .expect("failed to build HTTP/2 client");
+        self.http2 = true;
+        self
+    }
+
+    /// Set up and prove against the circuit `name` of the session instead of
+    /// `DEFAULT_CIRCUIT`. Clients sharing a session ID but not a circuit name hold
+    /// separate generator sets on the server.
+    pub fn with_circuit(mut self, name: impl Into<String>) -> Self {
+        self.circuit = name.into();
+        self
+    }
+
+    /// Tag setup and prove requests as encoded for `curve` instead of BN254.
+    pub fn with_curve(mut self, curve: CurveId) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Prove against a session set up elsewhere (e.g. by another process), whose
+    /// setup returned `generators_hash`. Otherwise `send_setup` records it.
+    pub fn with_generators_hash(self, generators_hash: [u8; 32]) -> Self {
+        *self.generators_hash.lock().unwrap() = Some(generators_hash);
+        self
+    }
+
+    /// Use a session set up elsewhere (e.g. by another process, or by the
+    /// client of another of its circuits), whose first setup returned `token`.
+    /// Otherwise `send_setup` records it.
+    pub fn with_session_token(self, token: SessionToken) -> Self {
+        *self.session_token.lock().unwrap() = Some(token);
+        self
+    }
+
+    /// The token of this client's session, once set up or given with
+    /// `with_session_token`.
+    pub fn session_token(&self) -> Option<SessionToken> {
+        *self.session_token.lock().unwrap()
+    }
+
+    /// Attach a credential to setup requests, for servers that gate POST /setup.
+    /// An API key is sent with every request, as servers with tenants require.
+    pub fn with_setup_credential(mut self, credential: SetupCredential) -> Self {
+        self.setup_credential = Some(credential);
+        self
+    }
+
+    /// Require the server to pass TEE attestation under `policy` before any
+    /// generators or masked vectors are sent to it. The server must be reached
+    /// over `https://`: the report is only accepted if it came over a connection
+    /// to the attested TLS key, and every later request goes to that key alone,
+    /// so a relayed report is of no use to a man in the middle.
+    pub fn with_attestation(mut self, policy: AttestationPolicy) -> Self {
+        self.attestation = Some(policy);
+        self
+    }
+
+    /// Refuse to delegate unless `policy` holds: it is checked against the server
+    /// before every request, and against the key and proving mode before `prove`
+    /// and `prove_with_mode` mask anything. Violations fail with a
+    /// `PolicyViolation`.
+    pub fn with_security_policy(mut self, policy: SecurityPolicy) -> Self {
+        self.security_policy = Some(policy);
+        self
+    }
+
+    /// Check the server against the security policy, then run the attestation
+    /// handshake once per client, if a policy is configured. The handshake
+    /// leaves a client pinned to the attested TLS key for `post` and `get`.
+    async fn ensure_attested(&self) -> Result<()> {
+        if let Some(policy) = &self.security_policy {
+            policy.check_server(&self.base_url, self.attestation.is_some())?;
+        }
+        let Some(policy) = &self.attestation else {
+            return Ok(());
+        };
+        if !self.base_url.starts_with("https://") {
+            return Err(AttestationError::PlaintextTransport.into());
+        }
+        self.attested
+            .get_or_try_init(|| async {
+                let nonce: [u8; 32] = rand::random();
+                let url = format!("{}/attestation", self.base_url);
+                let request_id = RequestId::random();
+                // A client of its own, so the report comes over a fresh
+                // connection whose key the verifier records. The peer is not
+                // verified yet, so it gets the nonce and no credentials.
+                let verifier = AttestedKeyVerifier::unpinned();
+                let resp = self
+                    .tls_client(&verifier)?
+                    .post(&url)
+                    .header(REQUEST_ID_HEADER, request_id.to_string())
+                    .body(nonce.to_vec())
+                    .send()
+                    .await?;
+                if resp.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Err(StealthSnarkError::from(AttestationError::Unavailable));
+                }
+                if !resp.status().is_success() {
+                    let endpoint = "Attestation".to_string();
+                    return Err(ServerError::from_response(endpoint, request_id, resp)
+                        .await
+                        .into());
+                }
+                let report: AttestationReport = bincode::deserialize(&resp.bytes().await?)?;
+                policy.verify(&report, &nonce)?;
+                if verifier.seen() != Some(report.tls_key_fingerprint) {
+                    return Err(AttestationError::ConnectionKeyMismatch.into());
+                }
+                let pinned = AttestedKeyVerifier::pinned(report.tls_key_fingerprint);
+                Ok(self.tls_client(&pinned)?)
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// A client that checks server certificates with `verifier` instead of the
+    /// web PKI, talking HTTP/2 if `with_http2` was set.
+    fn tls_client(&self, verifier: &Arc<AttestedKeyVerifier>) -> reqwest::Result<reqwest::Client> {
+        let alpn = if self.http2 {
+            vec![b"h2".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        let builder =
+            reqwest::Client::builder().use_preconfigured_tls(verifier.client_config(alpn));
+        if self.http2 {
+            builder.http2_prior_knowledge().build()
+        } else {
+            builder.build()
+        }
+    }
+
+    /// The client requests go out on: the one pinned to the attested TLS key
+    /// once attested.
+    fn http(&self) -> &reqwest::Client {
+        self.attested.get().unwrap_or(&self.client)
+    }
+
+    /// Send setup request: transmit generators to server. The returned generators
+    /// hash is kept for later prove requests.
+    pub async fn send_setup(&self, request: &SetupRequest) -> Result<SetupResponse> {
         let inner = bincode::serialize(request)?;
+        let kept = self.setup_payload.as_ref().map(|_| inner.clone());
+        let response = self.send_setup_encoded("setup", self.curve, inner).await?;
+        if let (Some(payload), Some(kept)) = (&self.setup_payload, kept) {
+            *payload.lock().unwrap() = Some(kept);
+        }
+        Ok(response)
+    }
+
+    /// `send_setup` for a circuit set up before, e.g. recompiled after a change
+    /// to a few constraints: only the chunks of the generators that differ from
+    /// the ones the server holds (`fetch_generator_chunks`) are uploaded, as a
+    /// `SetupPatch`. Falls back to `send_setup` if the server holds none for
+    /// this circuit, or its generators changed before the patch arrived.
+    pub async fn send_setup_diff(&self, request: &SetupRequest) -> Result<SetupResponse> {
+        let base = match self.fetch_generator_chunks().await {
+            Ok(base) => base,
+            Err(e) if e.server_error().is_some_and(|e| e.status() == 404) => {
+                return self.send_setup(request).await
+            }
+            Err(e) => return Err(e),
+        };
+        let patch = SetupPatch::new(&base, request)?;
+        tracing::info!(
+            chunks = patch.chunks.len(),
+            bytes = patch.points_len(),
+            "Uploading changed generator chunks"
+        );
+        let response = match self
+            .send_setup_encoded("setup/patch", self.curve, bincode::serialize(&patch)?)
+            .await
+        {
+            Ok(response) => response,
+            Err(e)
+                if e.server_error().is_some_and(|e| {
+                    e.is_lost_session() || e.code() == Some(ErrorCode::GeneratorsMismatch)
+                }) =>
+            {
+                return self.send_setup(request).await
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(payload) = &self.setup_payload {
+            *payload.lock().unwrap() = Some(bincode::serialize(request)?);
+        }
+        Ok(response)
+    }
+
+    /// `send_setup` in parts of at most `part_bytes` bytes of points each
+    /// (`SetupChunk::split`), for generators larger than the server takes in one
+    /// request body (`ServerConfig::max_setup_bytes`). Each part is a request of
+    /// its own to POST /setup/chunk; the server sets the circuit up once the
+    /// last one arrives.
+    pub async fn send_setup_chunked(
+        &self,
+        request: &SetupRequest,
+        part_bytes: usize,
+    ) -> Result<SetupResponse> {
+        let mut parts = SetupChunk::split(request, part_bytes)?;
+        tracing::info!(parts = parts.len(), "Uploading generators in parts");
+        let last = parts.next_back().expect("a setup has at least one part");
+        for part in parts {
+            self.post_setup_encoded("setup/chunk", self.curve, bincode::serialize(&part)?)
+                .await?;
+        }
+        let response = self
+            .send_setup_encoded("setup/chunk", self.curve, bincode::serialize(&last)?)
+            .await?;
+        if let Some(payload) = &self.setup_payload {
+            *payload.lock().unwrap() = Some(bincode::serialize(request)?);
+        }
+        Ok(response)
+    }
+
+    /// Setup at `path` (`setup`, `setup/patch`, `setup/chunk` or `emsm/setup`)
+    /// with a bincode-encoded `SetupRequest`, `SetupPatch`, `SetupChunk` or
+    /// `GeneratorSetRequest` for `curve`.
+    async fn send_setup_encoded(
+        &self,
+        path: &str,
+        curve: CurveId,
+        request: Vec<u8>,
+    ) -> Result<SetupResponse> {
+        let resp = self.post_setup_encoded(path, curve, request).await?;
+        let codec = resp
+            .headers()
+            .get(CODEC_HEADER)
+            .and_then(|value| WireCodec::from_name(value.as_bytes()))
+            .unwrap_or_default();
+        let encoding = resp
+            .headers()
+            .get(SCALAR_ENCODING_HEADER)
+            .and_then(|value| ScalarEncoding::from_header_value(value.as_bytes()))
+            .unwrap_or_default();
+        let response: SetupResponse = bincode::deserialize(&resp.bytes().await?)?;
+        *self.generators_hash.lock().unwrap() = Some(response.generators_hash);
+        *self.session_token.lock().unwrap() = Some(response.session_token);
+        *self.scalar_encoding.lock().unwrap() = encoding;
+        *self.codec.lock().unwrap() = codec;
+        Ok(response)
+    }
+
+    /// Post the setup of `send_setup_encoded`, returning the server's response
+    /// if it succeeded.
+    async fn post_setup_encoded(
+        &self,
+        path: &str,
+        curve: CurveId,
+        request: Vec<u8>,
+    ) -> Result<reqwest::Response> {
+        self.ensure_attested().await?;
+        let url = format!("{}/{path}", self.base_url);
         let envelope = SetupEnvelope {
             session_id: self.session_id.clone(),
-            request: inner,
+            circuit: self.circuit.clone(),
+            curve,
+            request: (),
         };
-        let body = bincode::serialize(&envelope)?;
+        // bincode writes nothing for `()`, so the envelope's other fields
+        // followed by the request are the envelope of the request
+        let mut body = bincode::serialize(&envelope)?;
+        body.extend_from_slice(&request);
 
-        let resp = self
-            .client
-            .post(&url)
+        let request_id = RequestId::random();
+        let mut builder = self
+            .post(&url, request_id)
             .body(body)
-            .header("Content-Type", "application/octet-stream")
-            .send()
-            .await?;
+            .header("Content-Type", WireCodec::Bincode.content_type());
+        if self.offered_codec != WireCodec::Bincode {
+            builder = builder.header(CODEC_HEADER, self.offered_codec.name());
+        }
+        if self.raw_scalars {
+            builder = builder.header(
+                SCALAR_ENCODING_HEADER,
+                ScalarEncoding::Montgomery.header_value(),
+            );
+        }
+        // An API key goes out with every request (see `post`)
+        if let Some(SetupCredential::ProofOfWork { difficulty }) = &self.setup_credential {
+            let session_id = self.session_id.clone();
+            let difficulty = *difficulty;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let nonce =
+                tokio::task::spawn_blocking(move || solve_pow(&session_id, timestamp, difficulty))
+                    .await?;
+            let solution = format!("{}:{timestamp}:{nonce}", self.session_id);
+            builder = builder.header(POW_HEADER, solution);
+        }
+        let resp = builder.send().await?;
 
         if !resp.status().is_success() {
-            anyhow::bail!("Setup failed with status: {}", resp.status());
+            let endpoint = "Setup".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
         }
-
-        Ok(())
+        Ok(resp)
     }
 
     /// Send prove request: transmit masked vectors, receive MSM results.
     pub async fn send_prove(&self, request: &ProveRequest) -> Result<ProveResponse> {
+        let codec = self.codec();
+        let body = codec.encode(&self.prove_envelope(request)?)?;
+        let bandwidth = &mut Bandwidth::default();
+        self.send_prove_body(body, codec, ScalarEncoding::Canonical, bandwidth)
+            .await
+    }
+
+    /// `send_prove` in the scalar encoding agreed on at setup, retried once
+    /// after setting the session up again if the server lost it and the client
+    /// was built `with_auto_resetup`. The bytes moved are added to `bandwidth`.
+    pub(crate) async fn send_prove_with_resetup(
+        &self,
+        request: &EncryptedRequest,
+        bandwidth: &mut Bandwidth,
+    ) -> Result<ProveResponse> {
+        let err = match self.send_prove_negotiated(request, bandwidth).await {
+            Err(e) if e.server_error().is_some_and(ServerError::is_lost_session) => e,
+            result => return result,
+        };
+        let payload = self
+            .setup_payload
+            .as_ref()
+            .and_then(|payload| payload.lock().unwrap().clone());
+        let Some(payload) = payload else {
+            return Err(err);
+        };
+        tracing::warn!(
+            "Session {} is gone from the server, setting it up again: {err:#}",
+            self.session_id
+        );
+        let payload_len = payload.len() as u64;
+        self.send_setup_encoded("setup", self.curve, payload)
+            .await?;
+        bandwidth.sent += payload_len;
+        self.send_prove_negotiated(request, bandwidth).await
+    }
+
+    async fn send_prove_negotiated(
+        &self,
+        request: &EncryptedRequest,
+        bandwidth: &mut Bandwidth,
+    ) -> Result<ProveResponse> {
+        let (codec, encoding) = (self.codec(), self.scalar_encoding());
+        let encoded = ProveRequest {
+            hint: self.parallelism_hint,
+            ..ProveRequest::encode(request, encoding)
+        };
+        let scalars = [
+            &request.v_h,
+            &request.v_l,
+            &request.v_a,
+            &request.v_b_g1,
+            &request.v_b_g2,
+        ];
+        let vectors = [
+            &encoded.v_h,
+            &encoded.v_l,
+            &encoded.v_a,
+            &encoded.v_b_g1,
+            &encoded.v_b_g2,
+        ];
+        for ((msm, scalars), vector) in bandwidth.msms.iter_mut().zip(scalars).zip(vectors) {
+            *msm = MsmBandwidth {
+                scalars: scalars.len() as u64,
+                sent: vector.len() as u64,
+                received: 0,
+            };
+        }
+        let body = codec.encode(&self.prove_envelope(&encoded)?)?;
+        drop(encoded);
+        let response = self
+            .send_prove_body(body, codec, encoding, bandwidth)
+            .await?;
+        let results = [
+            &response.em_h,
+            &response.em_l,
+            &response.em_a,
+            &response.em_b_g1,
+            &response.em_b_g2,
+        ];
+        for (msm, result) in bandwidth.msms.iter_mut().zip(results) {
+            msm.received = result.len() as u64;
+        }
+        Ok(response)
+    }
+
+    /// `send_prove` for a request already bincode-encoded, e.g. by
+    /// `client_encrypt_to_writer`. It is sent as bincode whatever codec was
+    /// agreed on.
+    pub async fn send_prove_encoded(&self, request: Vec<u8>) -> Result<ProveResponse> {
+        // As in `send_setup_encoded`, the request is appended to the envelope
+        let mut body = bincode::serialize(&self.prove_envelope(())?)?;
+        body.extend_from_slice(&request);
+        drop(request);
+        let bandwidth = &mut Bandwidth::default();
+        self.send_prove_body(
+            body,
+            WireCodec::Bincode,
+            ScalarEncoding::Canonical,
+            bandwidth,
+        )
+        .await
+    }
+
+    fn prove_envelope<R>(&self, request: R) -> Result<ProveEnvelope<R>> {
+        Ok(ProveEnvelope {
+            session_id: self.session_id.clone(),
+            circuit: self.circuit.clone(),
+            curve: self.curve,
+            generators_hash: self.prove_generators_hash()?,
+            request,
+        })
+    }
+
+    /// POST /prove with an envelope encoded with `codec`, adding the bodies to
+    /// `bandwidth`.
+    async fn send_prove_body(
+        &self,
+        body: Vec<u8>,
+        codec: WireCodec,
+        encoding: ScalarEncoding,
+        bandwidth: &mut Bandwidth,
+    ) -> Result<ProveResponse> {
+        self.ensure_attested().await?;
         let url = format!("{}/prove", self.base_url);
-        let inner = bincode::serialize(request)?;
+
+        let request_id = RequestId::random();
+        let body = Bytes::from(body);
+        let mut retries = 0;
+        let resp = loop {
+            let mut builder = self
+                .post(&url, request_id)
+                .body(body.clone())
+                .header("Content-Type", codec.content_type());
+            if encoding != ScalarEncoding::Canonical {
+                builder = builder.header(SCALAR_ENCODING_HEADER, encoding.header_value());
+            }
+            let resp = self.with_scheduling_headers(builder).send().await?;
+            bandwidth.sent += body.len() as u64;
+            if resp.status().is_success() {
+                break resp;
+            }
+            let endpoint = "Prove".to_string();
+            let err = ServerError::from_response(endpoint, request_id, resp).await;
+            self.wait_to_retry(err, &mut retries).await?;
+        };
+
+        let bytes = resp.bytes().await?;
+        bandwidth.received += bytes.len() as u64;
+        let response: ProveResponse = codec.decode(&bytes)?;
+        Ok(response)
+    }
+
+    /// `send_prove` as five concurrent `POST /msm` sub-requests, one per MSM.
+    /// Over HTTP/2 (`with_http2`) they are multiplexed on one connection and the
+    /// server starts on the first MSM while the rest upload. Behind a load
+    /// balancer they can be served by different instances, each of which must
+    /// hold this client's circuit. The sub-requests share one `RequestId`.
+    pub async fn send_prove_split(&self, request: ProveRequest) -> Result<ProveResponse> {
+        let generators_hash = self.prove_generators_hash()?;
+        self.ensure_attested().await?;
+        let [h, l, a, b_g1, b_g2] = request.into_msms();
+        let id = RequestId::random();
+        let (h, l, a, b_g1, b_g2) = tokio::try_join!(
+            self.send_msm(h, generators_hash, id),
+            self.send_msm(l, generators_hash, id),
+            self.send_msm(a, generators_hash, id),
+            self.send_msm(b_g1, generators_hash, id),
+            self.send_msm(b_g2, generators_hash, id),
+        )?;
+        ProveResponse::from_msms([h, l, a, b_g1, b_g2])
+    }
+
+    /// The public-input MSMs for `public_inputs` (without the constant 1), for
+    /// `client_decrypt_with_public`. Needs a setup `with_public_generators`.
+    pub async fn send_public_inputs(&self, public_inputs: &[Fr]) -> Result<PublicInputResponse> {
+        let generators_hash = self.prove_generators_hash()?;
+        self.ensure_attested().await?;
+        let request = MsmRequest {
+            kind: MsmKind::Public,
+            vector: ark_vec_to_bytes(public_inputs),
+        };
+        let response = self
+            .send_msm(request, generators_hash, RequestId::random())
+            .await?;
+        PublicInputResponse::try_from(&response)
+    }
+
+    async fn send_msm(
+        &self,
+        request: MsmRequest,
+        generators_hash: [u8; 32],
+        request_id: RequestId,
+    ) -> Result<MsmResponse> {
+        let url = format!("{}/msm", self.base_url);
+        let kind = request.kind;
+        let codec = self.codec();
         let envelope = ProveEnvelope {
             session_id: self.session_id.clone(),
-            request: inner,
+            circuit: self.circuit.clone(),
+            curve: self.curve,
+            generators_hash,
+            request,
+        };
+        let body = Bytes::from(codec.encode(&envelope)?);
+        drop(envelope);
+
+        let mut retries = 0;
+        let resp = loop {
+            let builder = self
+                .post(&url, request_id)
+                .body(body.clone())
+                .header("Content-Type", codec.content_type());
+            let resp = self.with_scheduling_headers(builder).send().await?;
+            if resp.status().is_success() {
+                break resp;
+            }
+            let endpoint = format!("{kind:?} MSM");
+            let err = ServerError::from_response(endpoint, request_id, resp).await;
+            self.wait_to_retry(err, &mut retries).await?;
+        };
+
+        codec.decode(&resp.bytes().await?)
+    }
+
+    /// `send_msm` for one MSM of `split`, then unmask its result with `noise`
+    /// and `pre` on the blocking pool, while the server still works on the
+    /// other MSMs. `None`, without a request, for an MSM that is not
+    /// `delegated`. The result is decoded with its subgroup check and refused
+    /// if it is the identity, as `ServerResponse::validate` would.
+    async fn send_and_unmask<G: CurveGroup<ScalarField = Fr>>(
+        &self,
+        split: &SplitProve,
+        delegated: bool,
+        request: MsmRequest,
+        noise: fn(&ClientDecryptionState) -> &SparseVector<Fr>,
+        pre: fn(&ServerAidedProvingKey) -> &DeferredPreprocessing<G>,
+    ) -> Result<Option<G>> {
+        if !delegated {
+            return Ok(None);
+        }
+        let kind = request.kind;
+        let response = self
+            .send_msm(request, split.generators_hash, split.request_id)
+            .await?;
+        let masked: G = ark_from_bytes::<G::Affine>(&response.result)?.into();
+        if masked.is_zero() && !split.sapk.can_be_identity(kind) {
+            return Err(InvalidResponse::Identity(kind).into());
+        }
+        let (sapk, state) = (split.sapk.clone(), split.state.clone());
+        let unmasked =
+            tokio::task::spawn_blocking(move || decrypt(masked, noise(&state), pre(&sapk).get()))
+                .await??;
+        Ok(Some(unmasked))
+    }
+
+    /// Upload `generators` as the generator set named by `with_circuit`, for
+    /// `send_eval` and `commit` over `G` (whatever `with_curve` says), e.g. the
+    /// generators of `EmsmPublicParams`. The returned generators hash is kept for
+    /// eval requests.
+    pub async fn send_generator_set<G: DelegatedCurve>(
+        &self,
+        generators: &[G::Affine],
+    ) -> Result<SetupResponse> {
+        let request = bincode::serialize(&GeneratorSetRequest::new::<G>(generators))?;
+        self.send_setup_encoded("emsm/setup", G::CURVE, request)
+            .await
+    }
+
+    /// The MSM of `scalars` against the generator set, evaluated by the server
+    /// (`POST /emsm/eval`). The scalars should be masked; `commit` masks and
+    /// unmasks them.
+    pub async fn send_eval<G: DelegatedCurve>(&self, scalars: &[G::ScalarField]) -> Result<G> {
+        let request = EvalRequest {
+            vector: ark_vec_to_bytes(scalars),
+        };
+        let response: EvalResponse = self
+            .send_eval_request("emsm/eval", "Eval", G::CURVE, request)
+            .await?;
+        let point: G::Affine = ark_from_bytes(&response.result)?;
+        Ok(point.into())
+    }
+
+    /// The MSMs of the main and check vectors of `encrypted` against the
+    /// generator set, evaluated by the server in one request
+    /// (`POST /emsm/eval/malicious`), for `malicious::malicious_decrypt`.
+    /// `commit_malicious` masks, sends and checks.
+    pub async fn send_malicious_eval<G: DelegatedCurve>(
+        &self,
+        encrypted: &MaliciousEncrypted<G::ScalarField>,
+    ) -> Result<(G, G)> {
+        let request = MaliciousEvalRequest {
+            vector: ark_vec_to_bytes(&encrypted.masked),
+            check_vector: ark_vec_to_bytes(&encrypted.masked_check),
+        };
+        let response: MaliciousEvalResponse = self
+            .send_eval_request("emsm/eval/malicious", "Malicious eval", G::CURVE, request)
+            .await?;
+        let point: G::Affine = ark_from_bytes(&response.result)?;
+        let check_point: G::Affine = ark_from_bytes(&response.check_result)?;
+        Ok((point.into(), check_point.into()))
+    }
+
+    async fn send_eval_request<R: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        endpoint: &str,
+        curve: CurveId,
+        request: R,
+    ) -> Result<T> {
+        let generators_hash = self.prove_generators_hash()?;
+        self.ensure_attested().await?;
+        let url = format!("{}/{path}", self.base_url);
+        let codec = self.codec();
+        let envelope = ProveEnvelope {
+            session_id: self.session_id.clone(),
+            circuit: self.circuit.clone(),
+            curve,
+            generators_hash,
+            request,
+        };
+        let body = Bytes::from(codec.encode(&envelope)?);
+        drop(envelope);
+
+        let request_id = RequestId::random();
+        let mut retries = 0;
+        let resp = loop {
+            let builder = self
+                .post(&url, request_id)
+                .body(body.clone())
+                .header("Content-Type", codec.content_type());
+            let resp = self.with_scheduling_headers(builder).send().await?;
+            if resp.status().is_success() {
+                break resp;
+            }
+            let err = ServerError::from_response(endpoint.to_string(), request_id, resp).await;
+            self.wait_to_retry(err, &mut retries).await?;
+        };
+
+        codec.decode(&resp.bytes().await?)
+    }
+
+    /// The Pedersen commitment to `witness` under the generators of `params`,
+    /// delegated to the server: masked with `emsm::encrypt`, evaluated by
+    /// `send_eval` and unmasked with `emsm::decrypt`. The generators must have
+    /// been uploaded with `send_generator_set`. Masking and unmasking run on the
+    /// calling task.
+    pub async fn commit<G: DelegatedCurve, R: Rng + CryptoRng>(
+        &self,
+        params: &EmsmPublicParams<G>,
+        preprocessed: &PreprocessedCommitments<G>,
+        witness: &[G::ScalarField],
+        rng: &mut R,
+    ) -> Result<G> {
+        let (masked, noise) = encrypt(params, witness, rng);
+        let result = self.send_eval::<G>(&masked).await?;
+        Ok(decrypt(result, &noise, preprocessed)?)
+    }
+
+    /// `commit` against a malicious server: masks a check vector along with
+    /// the witness (`malicious::malicious_encrypt`), evaluates both with
+    /// `send_malicious_eval` and fails with `MaliciousError` if the unmasked
+    /// results are inconsistent, at twice the server cost.
+    pub async fn commit_malicious<G: DelegatedCurve, R: Rng + CryptoRng>(
+        &self,
+        params: &EmsmPublicParams<G>,
+        preprocessed: &PreprocessedCommitments<G>,
+        witness: &[G::ScalarField],
+        rng: &mut R,
+    ) -> Result<G> {
+        let (encrypted, state) = malicious_encrypt(params, witness, rng);
+        let (result, check_result) = self.send_malicious_eval::<G>(&encrypted).await?;
+        let commitment = malicious_decrypt(result, check_result, &state, preprocessed)?;
+        Ok(commitment)
+    }
+
+    /// Wait out the `Retry-After` of `err` if the retry policy allows another
+    /// attempt, and count it in `retries`; otherwise return `err`.
+    async fn wait_to_retry(&self, err: ServerError, retries: &mut u32) -> Result<()> {
+        let delay = self
+            .retry_policy
+            .and_then(|policy| policy.delay(&err, *retries));
+        let Some(delay) = delay else {
+            return Err(err.into());
+        };
+        *retries += 1;
+        tracing::warn!(
+            retry = *retries,
+            delay_ms = delay.as_millis() as u64,
+            "Server busy, retrying: {err}"
+        );
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    /// A POST to `url` tagged with `request_id`, carrying the API key of the
+    /// setup credential: servers with tenants look sessions up by it.
+    fn post(&self, url: &str, request_id: RequestId) -> reqwest::RequestBuilder {
+        self.with_credentials(self.http().post(url), request_id)
+    }
+
+    /// Tag a prove or MSM request with the QoS class and deadline, if set.
+    fn with_scheduling_headers(
+        &self,
+        mut builder: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        if let Some(class) = self.qos {
+            builder = builder.header(QOS_HEADER, class.header_value());
+        }
+        if let Some(deadline) = self.deadline {
+            builder = builder.header(DEADLINE_HEADER, deadline.as_millis().to_string());
+        }
+        builder
+    }
+
+    /// A GET of `url`, like `post`.
+    fn get(&self, url: &str, request_id: RequestId) -> reqwest::RequestBuilder {
+        self.with_credentials(self.http().get(url), request_id)
+    }
+
+    /// Tag a request with `request_id`, the API key and the session token.
+    fn with_credentials(
+        &self,
+        builder: reqwest::RequestBuilder,
+        request_id: RequestId,
+    ) -> reqwest::RequestBuilder {
+        let mut builder = builder.header(REQUEST_ID_HEADER, request_id.to_string());
+        if let Some(token) = self.session_token() {
+            builder = builder.header(SESSION_TOKEN_HEADER, token.to_string());
+        }
+        match &self.setup_credential {
+            Some(SetupCredential::ApiKey(key)) => builder.header(API_KEY_HEADER, key),
+            _ => builder,
+        }
+    }
+
+    fn prove_generators_hash(&self) -> Result<[u8; 32]> {
+        self.generators_hash.lock().unwrap().ok_or_else(|| {
+            StealthSnarkError::InvalidArgument(
+                "no generators hash: call send_setup or with_generators_hash first".to_string(),
+            )
+        })
+    }
+
+    /// Prove `circuit` with this session's server: encrypt, `send_prove`, decrypt.
+    /// The CPU-heavy client steps run on tokio's blocking pool, so this is safe to
+    /// await on a runtime worker. The session must already be set up for `sapk`.
+    pub async fn prove<C, R>(
+        &self,
+        sapk: Arc<ServerAidedProvingKey>,
+        circuit: C,
+        rng: R,
+    ) -> Result<Proof<Bn254>>
+    where
+        C: ConstraintSynthesizer<Fr> + Send + 'static,
+        R: Rng + CryptoRng + Send + 'static,
+    {
+        Ok(self.prove_with_report(sapk, circuit, rng).await?.proof)
+    }
+
+    /// `prove`, also reporting whether the server evaluated the MSMs or the client
+    /// fell back to evaluating them itself, and the bytes it moved.
+    pub async fn prove_with_report<C, R>(
+        &self,
+        sapk: Arc<ServerAidedProvingKey>,
+        circuit: C,
+        rng: R,
+    ) -> Result<ProveReport>
+    where
+        C: ConstraintSynthesizer<Fr> + Send + 'static,
+        R: Rng + CryptoRng + Send + 'static,
+    {
+        self.check_prove_policy(&sapk, ProvingMode::SemiHonest)?;
+        let (request, state, key) = match &self.proof_cache {
+            Some(cache) => {
+                let (cache, key_sapk) = (cache.clone(), sapk.clone());
+                let lookup = tokio::task::spawn_blocking(move || {
+                    cached_encrypt(&key_sapk, &cache, circuit, rng)
+                })
+                .await??;
+                match lookup {
+                    CachedEncrypt::Hit(proof) => {
+                        return Ok(ProveReport {
+                            proof,
+                            path: ProvePath::Cached,
+                            bandwidth: Bandwidth::default(),
+                        })
+                    }
+                    CachedEncrypt::Miss(request, state, key) => (request, *state, Some(key)),
+                }
+            }
+            None => {
+                let (request, state) = client_encrypt_async(sapk.clone(), circuit, rng).await?;
+                (request, state, None)
+            }
+        };
+        let mut bandwidth = Bandwidth::default();
+        let delegated = async {
+            let response = self
+                .send_prove_with_resetup(&request, &mut bandwidth)
+                .await?;
+            let response = ServerResponse::try_from(&response)?;
+            // Invalid points fall back like a failed request
+            response.validate(&sapk, state.delegation)?;
+            Ok(response)
+        }
+        .await;
+        let (response, path) = match delegated {
+            Ok(response) => (response, ProvePath::Delegated),
+            Err(e) if self.local_fallback => {
+                tracing::warn!("Delegated prove failed, evaluating locally: {e:#}");
+                let response = server_evaluate_async(sapk.clone(), request).await?;
+                (response, ProvePath::Local)
+            }
+            Err(e) => return Err(e),
+        };
+        let proof = client_decrypt_async(sapk, response, state).await?;
+        if let (Some(cache), Some(key)) = (&self.proof_cache, key) {
+            cache.insert(key, proof.clone());
+        }
+        Ok(ProveReport {
+            proof,
+            path,
+            bandwidth,
+        })
+    }
+
+    /// `prove` under `mode` (see `transport::prove_with_mode`), without the local
+    /// fallback.
+    pub async fn prove_with_mode<C, R>(
+        &self,
+        sapk: Arc<ServerAidedProvingKey>,
+        circuit: C,
+        mode: ProvingMode,
+        rng: R,
+    ) -> Result<Proof<Bn254>>
+    where
+        C: ConstraintSynthesizer<Fr> + Send + 'static,
+        R: Rng + CryptoRng + Send + 'static,
+    {
+        self.check_prove_policy(&sapk, mode)?;
+        super::transport::prove_with_mode(self, sapk, circuit, mode, rng).await
+    }
+
+    /// `prove` over `send_prove_split`'s five `POST /msm` sub-requests, unmasking
+    /// each MSM's result as soon as it arrives instead of once all five are in,
+    /// so the client's unmasking (and any deferred preprocessing it waits for)
+    /// overlaps the server's work on the rest. MSMs the key does not delegate
+    /// are computed meanwhile. Without the local fallback or the proof cache.
+    pub async fn prove_split<C, R>(
+        &self,
+        sapk: Arc<ServerAidedProvingKey>,
+        circuit: C,
+        rng: R,
+    ) -> Result<Proof<Bn254>>
+    where
+        C: ConstraintSynthesizer<Fr> + Send + 'static,
+        R: Rng + CryptoRng + Send + 'static,
+    {
+        self.check_prove_policy(&sapk, ProvingMode::SemiHonest)?;
+        let generators_hash = self.prove_generators_hash()?;
+        let (request, state) = client_encrypt_async(sapk.clone(), circuit, rng).await?;
+        self.ensure_attested().await?;
+        let [h, l, a, b_g1, b_g2] = ProveRequest::from(&request).into_msms();
+        drop(request);
+        let split = SplitProve {
+            generators_hash,
+            request_id: RequestId::random(),
+            sapk,
+            state: Arc::new(state),
         };
-        let body = bincode::serialize(&envelope)?;
+        let local = async {
+            let (sapk, state) = (split.sapk.clone(), split.state.clone());
+            let cancel = CancelToken::new();
+            let _cancel_on_drop = cancel.drop_guard();
+            let task = tokio::task::spawn_blocking(move || local_msms(&sapk, &state, &cancel));
+            Ok::<_, StealthSnarkError>(task.await??)
+        };
+        let d = split.state.delegation;
+        let (local, h, l, a, b_g1, b_g2) = tokio::try_join!(
+            local,
+            self.send_and_unmask(&split, d.h, h, |s| &s.lpn_h, |k| &k.pre_h),
+            self.send_and_unmask(&split, d.l, l, |s| &s.lpn_l, |k| &k.pre_l),
+            self.send_and_unmask(&split, d.a, a, |s| &s.lpn_a, |k| &k.pre_a),
+            self.send_and_unmask(&split, d.b_g1, b_g1, |s| &s.lpn_b_g1, |k| &k.pre_b_g1),
+            self.send_and_unmask(&split, d.b_g2, b_g2, |s| &s.lpn_b_g2, |k| &k.pre_b_g2),
+        )?;
+        let msms = UnmaskedMsms {
+            h: h.unwrap_or(local.h),
+            l: l.unwrap_or(local.l),
+            a: a.unwrap_or(local.a),
+            b_g1: b_g1.unwrap_or(local.b_g1),
+            b_g2: b_g2.unwrap_or(local.b_g2),
+        };
+        Ok(assemble_proof(&split.sapk, &msms, &split.state))
+    }
+
+    fn check_prove_policy(&self, sapk: &ServerAidedProvingKey, mode: ProvingMode) -> Result<()> {
+        if let Some(policy) = &self.security_policy {
+            policy.check_server(&self.base_url, self.attestation.is_some())?;
+            policy.check_prove(sapk, mode)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the server which curves and codecs it accepts, e.g. to check that it
+    /// serves this client's curve before uploading generators.
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        self.ensure_attested().await?;
+        let url = format!("{}/capabilities", self.base_url);
+        let request_id = RequestId::random();
+        let resp = self.get(&url, request_id).send().await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "Capabilities query".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        Ok(resp.json().await?)
+    }
 
+    /// Fetch the server's commitment to the generators it holds for this client's
+    /// circuit.
+    pub async fn fetch_commitment(&self) -> Result<CrsCommitment> {
+        self.ensure_attested().await?;
+        let url = format!(
+            "{}/commitment/{}/{}",
+            self.base_url, self.session_id, self.circuit
+        );
+        let request_id = RequestId::random();
+        let resp = self.get(&url, request_id).send().await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "Commitment query".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        Ok(resp.json().await?)
+    }
+
+    /// Fetch the hashes of the chunks of the generators the server holds for
+    /// this client's circuit, which `send_setup_diff` diffs against.
+    pub async fn fetch_generator_chunks(&self) -> Result<GeneratorChunks> {
+        self.ensure_attested().await?;
+        let url = format!(
+            "{}/commitment/{}/{}/chunks",
+            self.base_url, self.session_id, self.circuit
+        );
+        let request_id = RequestId::random();
+        let resp = self.get(&url, request_id).send().await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "Generator chunks query".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        Ok(resp.json().await?)
+    }
+
+    /// Check that the server holds exactly the generators of `expected` (e.g.
+    /// `SetupRequest::from(&sapk)`) for this session, on this client's curve.
+    /// Fails with `CommitmentMismatch` otherwise, or with `KeyMismatch` if both
+    /// name a proving key `Fingerprint` and those differ. On success the hash is
+    /// kept for prove requests, so a session set up elsewhere can be audited,
+    /// then used.
+    pub async fn audit_commitment(&self, expected: &SetupRequest) -> Result<CrsCommitment> {
+        let published = self.fetch_commitment().await?;
+        let expected_hash = expected.generators_hash();
+        if published.curve != self.curve || published.generators_hash != expected_hash {
+            return Err(CommitmentMismatch {
+                session_id: self.session_id.clone(),
+                curve: self.curve,
+                expected_hash,
+                published,
+            }
+            .into());
+        }
+        if let (Some(ours), Some(theirs)) = (expected.key_fingerprint, published.key_fingerprint) {
+            if ours != theirs {
+                return Err(KeyMismatch {
+                    key: "session's proving key",
+                    expected: ours,
+                    actual: theirs,
+                }
+                .into());
+            }
+        }
+        *self.generators_hash.lock().unwrap() = Some(expected_hash);
+        Ok(published)
+    }
+
+    /// Ask the server how long a prove request of the given sizes would take under its
+    /// current load, e.g. to pick a server or fall back to local proving.
+    pub async fn estimate(&self, request: &EstimateRequest) -> Result<EstimateResponse> {
+        self.ensure_attested().await?;
+        let url = format!("{}/estimate", self.base_url);
+        let body = bincode::serialize(request)?;
+
+        let request_id = RequestId::random();
         let resp = self
-            .client
-            .post(&url)
+            .post(&url, request_id)
             .body(body)
             .header("Content-Type", "application/octet-stream")
             .send()
             .await?;
 
         if !resp.status().is_success() {
-            anyhow::bail!("Prove failed with status: {}", resp.status());
+            let endpoint = "Estimate".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
         }
 
         let bytes = resp.bytes().await?;
-        let response: ProveResponse = bincode::deserialize(&bytes)?;
-        Ok(response)
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    /// Reset the server's idle timer for this session, e.g. during a long local
+    /// preprocess between setup and prove. Returns the time until the session
+    /// expires unless it is used again, or `None` if the server keeps sessions
+    /// indefinitely. Fails with 404 once the session has expired.
+    pub async fn extend_session(&self) -> Result<Option<Duration>> {
+        let status = self.session_status().await?;
+        Ok(status.expires_in_ms.map(Duration::from_millis))
+    }
+
+    /// The session's status (`POST /keepalive`), which also resets its idle
+    /// timer: its time left and which circuits' fixed-base tables are built.
+    pub async fn session_status(&self) -> Result<KeepaliveResponse> {
+        self.ensure_attested().await?;
+        let url = format!("{}/keepalive", self.base_url);
+        let body = bincode::serialize(&KeepaliveRequest {
+            session_id: self.session_id.clone(),
+        })?;
+
+        let request_id = RequestId::random();
+        let resp = self
+            .post(&url, request_id)
+            .body(body)
+            .header("Content-Type", "application/octet-stream")
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "Keepalive".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        Ok(bincode::deserialize(&resp.bytes().await?)?)
+    }
+
+    /// Apply `step` to each vector on the server (`POST /fft`). The vectors must
+    /// already be padded; see `witness_map`.
+    pub async fn send_fft(&self, step: FftStep, vectors: &[Vec<Fr>]) -> Result<Vec<Vec<Fr>>> {
+        self.ensure_attested().await?;
+        let url = format!("{}/fft", self.base_url);
+        let body = bincode::serialize(&FftRequest {
+            session_id: self.session_id.clone(),
+            step,
+            vectors: vectors.iter().map(|v| ark_vec_to_bytes(v)).collect(),
+        })?;
+
+        let request_id = RequestId::random();
+        let resp = self
+            .post(&url, request_id)
+            .body(body)
+            .header("Content-Type", "application/octet-stream")
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "FFT".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        let response: FftResponse = bincode::deserialize(&resp.bytes().await?)?;
+        if response.vectors.len() != vectors.len() {
+            return Err(DimensionMismatch {
+                what: "FFT response vectors",
+                expected: vectors.len(),
+                actual: response.vectors.len(),
+            }
+            .into());
+        }
+        response
+            .vectors
+            .iter()
+            .map(|bytes| ark_vec_from_bytes(bytes))
+            .collect()
+    }
+
+    /// The h polynomial of `evaluations`, with both FFT steps evaluated by the
+    /// server on vectors padded with `masks`, which are used up.
+    pub async fn witness_map(
+        &self,
+        evaluations: QapEvaluations,
+        masks: WitnessMapMasks,
+    ) -> Result<Vec<Fr>> {
+        let (masked, state) = masks.mask(evaluations)?;
+        let extended: [Vec<Fr>; 3] = self
+            .send_fft(FftStep::Extend, &masked)
+            .await?
+            .try_into()
+            .expect("send_fft checks the vector count");
+        let (quotient, state) = state.combine(extended)?;
+        let [interpolated]: [Vec<Fr>; 1] = self
+            .send_fft(FftStep::Interpolate, &[quotient])
+            .await?
+            .try_into()
+            .expect("send_fft checks the vector count");
+        state.unmask(interpolated)
+    }
+
+    /// Query the server's usage totals for this session.
+    pub async fn session_usage(&self) -> Result<Usage> {
+        let url = format!("{}/usage/{}", self.base_url, self.session_id);
+        self.get_usage(&url).await
+    }
+
+    /// Query the server's usage totals for this client's API key.
+    pub async fn account_usage(&self) -> Result<Usage> {
+        let url = format!("{}/usage", self.base_url);
+        self.get_usage(&url).await
+    }
+
+    async fn get_usage(&self, url: &str) -> Result<Usage> {
+        self.ensure_attested().await?;
+        let request_id = RequestId::random();
+        let resp = self.get(url, request_id).send().await?;
+
+        if !resp.status().is_success() {
+            let endpoint = "Usage query".to_string();
+            return Err(ServerError::from_response(endpoint, request_id, resp)
+                .await
+                .into());
+        }
+
+        Ok(resp.json().await?)
     }
 }
@@ -0,0 +1,149 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::StealthSnarkError;
+
+/// Header a client sets on POST /setup to offer a codec for its prove and MSM
+/// requests. A server that supports the codec echoes it on the setup response.
+pub const CODEC_HEADER: &str = "x-stealthsnark-codec";
+
+/// Encoding of setup, prove and MSM bodies. A body names its codec in
+/// `Content-Type` and is answered in the same codec.
+pub trait Codec {
+    /// `Content-Type` of bodies in this codec.
+    const CONTENT_TYPE: &'static str;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StealthSnarkError>;
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StealthSnarkError>;
+}
+
+/// bincode 1.3 with its default options, as laid out in `docs/wire-format.md`.
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const CONTENT_TYPE: &'static str = "application/octet-stream";
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StealthSnarkError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StealthSnarkError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// JSON, for clients without a bincode implementation. Byte fields are arrays
+/// of numbers, so bodies are several times larger than bincode's.
+pub struct Json;
+
+impl Codec for Json {
+    const CONTENT_TYPE: &'static str = "application/json";
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StealthSnarkError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StealthSnarkError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// A `Codec` picked at runtime: negotiated at setup, or named by a request's
+/// `Content-Type`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WireCodec {
+    #[default]
+    Bincode,
+    Json,
+}
+
+impl WireCodec {
+    pub const ALL: [WireCodec; 2] = [WireCodec::Bincode, WireCodec::Json];
+
+    /// Value of `CODEC_HEADER`.
+    pub fn name(self) -> &'static str {
+        match self {
+            WireCodec::Bincode => "bincode",
+            WireCodec::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name().as_bytes() == name)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireCodec::Bincode => Bincode::CONTENT_TYPE,
+            WireCodec::Json => Json::CONTENT_TYPE,
+        }
+    }
+
+    /// The codec of a body sent with `content_type`, ignoring parameters. Bodies
+    /// without one are bincode.
+    pub fn from_content_type(content_type: Option<&[u8]>) -> Option<Self> {
+        let Some(content_type) = content_type else {
+            return Some(WireCodec::Bincode);
+        };
+        let media_type = content_type
+            .split(|&b| b == b';')
+            .next()
+            .unwrap_or_default();
+        let media_type = media_type.trim_ascii();
+        Self::ALL
+            .into_iter()
+            .find(|c| c.content_type().as_bytes().eq_ignore_ascii_case(media_type))
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, StealthSnarkError> {
+        match self {
+            WireCodec::Bincode => Bincode::encode(value),
+            WireCodec::Json => Json::encode(value),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, StealthSnarkError> {
+        match self {
+            WireCodec::Bincode => Bincode::decode(bytes),
+            WireCodec::Json => Json::decode(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::messages::{SessionToken, SetupResponse};
+
+    #[test]
+    fn test_wire_codecs() {
+        let response = SetupResponse {
+            generators_hash: [7; 32],
+            session_token: SessionToken([9; 32]),
+        };
+        for codec in WireCodec::ALL {
+            let bytes = codec.encode(&response).unwrap();
+            assert_eq!(codec.decode::<SetupResponse>(&bytes).unwrap(), response);
+            assert_eq!(WireCodec::from_name(codec.name().as_bytes()), Some(codec));
+            assert_eq!(
+                WireCodec::from_content_type(Some(codec.content_type().as_bytes())),
+                Some(codec)
+            );
+        }
+        assert_eq!(
+            WireCodec::Bincode.encode(&response).unwrap(),
+            bincode::serialize(&response).unwrap()
+        );
+
+        assert_eq!(
+            WireCodec::from_content_type(Some(b"Application/JSON; charset=utf-8")),
+            Some(WireCodec::Json)
+        );
+        assert_eq!(WireCodec::from_content_type(None), Some(WireCodec::Bincode));
+        assert_eq!(
+            WireCodec::from_content_type(Some(b"application/cbor")),
+            None
+        );
+    }
+}
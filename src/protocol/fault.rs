@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ark_ec::CurveGroup;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+use super::messages::MsmKind;
+use crate::error::StealthSnarkError;
+
+/// How the server tampers with its MSM results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Negate the result of one MSM.
+    Negate(MsmKind),
+    /// Add a random point to the result of one MSM.
+    AddRandomPoint(MsmKind),
+    /// Answer every MSM with its result from the session's previous request.
+    /// The first request of a session is answered honestly.
+    Stale,
+}
+
+impl std::str::FromStr for Fault {
+    type Err = StealthSnarkError;
+
+    /// `negate:<msm>`, `add:<msm>` or `stale`, with `<msm>` one of h, l, a, b_g1, b_g2.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let msm = |name: &str| match name {
+            "h" => Ok(MsmKind::H),
+            "l" => Ok(MsmKind::L),
+            "a" => Ok(MsmKind::A),
+            "b_g1" => Ok(MsmKind::BG1),
+            "b_g2" => Ok(MsmKind::BG2),
+            other => Err(StealthSnarkError::InvalidArgument(format!(
+                "unknown MSM {other}"
+            ))),
+        };
+        match s.split_once(':') {
+            Some(("negate", name)) => Ok(Fault::Negate(msm(name)?)),
+            Some(("add", name)) => Ok(Fault::AddRandomPoint(msm(name)?)),
+            None if s == "stale" => Ok(Fault::Stale),
+            _ => Err(StealthSnarkError::InvalidArgument(format!(
+                "unknown fault {s}; expected negate:<msm>, add:<msm> or stale"
+            ))),
+        }
+    }
+}
+
+/// Applies a `Fault` to the MSM results a server hands out, so integrators can
+/// exercise how their clients handle a misbehaving server over a real network
+/// stack. Never enable the `fault-injection` feature in production.
+pub struct FaultInjector {
+    fault: Fault,
+    /// Last honest result per session and MSM, for `Fault::Stale`.
+    previous: Mutex<HashMap<(String, MsmKind), Vec<u8>>>,
+}
+
+impl FaultInjector {
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            fault,
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn fault(&self) -> Fault {
+        self.fault
+    }
+
+    /// The result to answer with in place of `honest`, the `kind` MSM of a
+    /// request for `session_id`.
+    pub fn tamper<G>(&self, session_id: &str, kind: MsmKind, honest: G) -> G
+    where
+        G: CurveGroup + CanonicalSerialize + CanonicalDeserialize,
+    {
+        match self.fault {
+            Fault::Negate(target) if target == kind => -honest,
+            Fault::AddRandomPoint(target) if target == kind => {
+                honest + G::rand(&mut rand::thread_rng())
+            }
+            Fault::Stale => {
+                let mut bytes = Vec::new();
+                honest
+                    .serialize_compressed(&mut bytes)
+                    .expect("serializing a point to memory cannot fail");
+                let previous = self
+                    .previous
+                    .lock()
+                    .unwrap()
+                    .insert((session_id.to_string(), kind), bytes);
+                previous
+                    .and_then(|bytes| G::deserialize_compressed(&bytes[..]).ok())
+                    .unwrap_or(honest)
+            }
+            _ => honest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bn254::{G1Projective as G1, G2Projective as G2};
+    use ark_std::{UniformRand, Zero};
+
+    #[test]
+    fn test_faults() {
+        let mut rng = rand::thread_rng();
+        let (p, q) = (G1::rand(&mut rng), G1::rand(&mut rng));
+
+        let negate = FaultInjector::new("negate:h".parse().unwrap());
+        assert_eq!(negate.tamper("s", MsmKind::H, p), -p);
+        assert_eq!(negate.tamper("s", MsmKind::L, p), p);
+
+        let add = FaultInjector::new("add:b_g2".parse().unwrap());
+        let r = G2::rand(&mut rng);
+        assert_ne!(add.tamper("s", MsmKind::BG2, r), r);
+        assert_eq!(add.tamper("s", MsmKind::A, p), p);
+
+        let stale = FaultInjector::new("stale".parse().unwrap());
+        assert_eq!(stale.tamper("s", MsmKind::H, p), p);
+        assert_eq!(stale.tamper("s", MsmKind::H, q), p);
+        assert_eq!(stale.tamper("other", MsmKind::H, G1::zero()), G1::zero());
+
+        assert!("flip:h".parse::<Fault>().is_err());
+        assert!("negate:c".parse::<Fault>().is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
+
+/// Header carrying a pre-shared API key for POST /setup.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header carrying a proof-of-work solution for POST /setup, formatted as
+/// `<session_id>:<timestamp>:<nonce>`, the timestamp in Unix seconds.
+pub const POW_HEADER: &str = "x-setup-pow";
+
+/// Header carrying the key of the admin API (`ServerConfig::admin_key`).
+pub const ADMIN_KEY_HEADER: &str = "x-admin-key";
+
+/// Domain separator for the setup proof-of-work puzzle.
+const POW_DOMAIN: &[u8] = b"stealthsnark-setup-pow-v2";
+
+/// How long a proof-of-work solution admits setups after its timestamp.
+/// Timestamps are accepted up to the same span ahead of the server's clock.
+pub const POW_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Admission policy for POST /setup.
+///
+/// Setup uploads carry the full generator sets and are the expensive request to
+/// accept, so the gate is checked from the headers alone, before the body is read.
+/// The prove path is not gated.
+#[derive(Clone, Debug, Default)]
+pub enum SetupGate {
+    /// Accept every setup request.
+    #[default]
+    Open,
+    /// Require an `x-api-key` header matching one of the allowed keys.
+    ApiKeys(HashSet<String>),
+    /// Require an `x-setup-pow` header solving a hash puzzle bound to the session ID
+    /// and a recent timestamp, with `difficulty` leading zero bits. Each solution
+    /// admits one setup.
+    ProofOfWork { difficulty: u32 },
+}
+
+/// Credential the client attaches to POST /setup.
+#[derive(Clone, Debug)]
+pub enum SetupCredential {
+    /// Send a pre-shared API key.
+    ApiKey(String),
+    /// Solve the proof-of-work puzzle at the given difficulty before uploading.
+    ProofOfWork { difficulty: u32 },
+}
+
+/// Outcome of a successful gate check.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Admission {
+    /// Session ID a proof-of-work solution was computed for. The caller must compare it
+    /// against the session ID in the envelope once the body is read.
+    pub bound_session: Option<String>,
+    /// Verified API key the session is accounted to.
+    pub account: Option<String>,
+}
+
+/// Proof-of-work solutions that admitted a setup, kept until they expire so
+/// that a replayed header is refused.
+#[derive(Debug, Default)]
+pub struct SpentSolutions {
+    spent: Mutex<HashSet<(String, u64, u64)>>,
+}
+
+impl SpentSolutions {
+    /// Record the solution, unless it was spent before. Drops solutions that
+    /// have expired at `now`.
+    fn spend(&self, session_id: &str, timestamp: u64, nonce: u64, now: u64) -> bool {
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|(_, timestamp, _)| !pow_expired(*timestamp, now));
+        spent.insert((session_id.to_string(), timestamp, nonce))
+    }
+}
+
+impl SetupGate {
+    /// Check the request headers against the gate, spending a proof-of-work
+    /// solution in `spent`. Missing credentials map to 401, invalid, expired or
+    /// replayed ones to 403.
+    pub fn check(
+        &self,
+        headers: &HeaderMap,
+        spent: &SpentSolutions,
+    ) -> Result<Admission, StatusCode> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.check_at(headers, spent, now)
+    }
+
+    fn check_at(
+        &self,
+        headers: &HeaderMap,
+        spent: &SpentSolutions,
+        now: u64,
+    ) -> Result<Admission, StatusCode> {
+        match self {
+            SetupGate::Open => Ok(Admission::default()),
+            SetupGate::ApiKeys(keys) => {
+                let key = header_str(headers, API_KEY_HEADER)?;
+                // Compare against every key, not a hash lookup, so timing says nothing
+                // about how close a guess came
+                let admitted = keys.iter().fold(Choice::from(0), |found, allowed| {
+                    found | key_eq(allowed, key)
+                });
+                if bool::from(admitted) {
+                    Ok(Admission {
+                        bound_session: None,
+                        account: Some(key.to_string()),
+                    })
+                } else {
+                    Err(StatusCode::FORBIDDEN)
+                }
+            }
+            SetupGate::ProofOfWork { difficulty } => {
+                let value = header_str(headers, POW_HEADER)?;
+                let (rest, nonce) = value.rsplit_once(':').ok_or(StatusCode::FORBIDDEN)?;
+                let (session_id, timestamp) = rest.rsplit_once(':').ok_or(StatusCode::FORBIDDEN)?;
+                let nonce: u64 = nonce.parse().map_err(|_| StatusCode::FORBIDDEN)?;
+                let timestamp: u64 = timestamp.parse().map_err(|_| StatusCode::FORBIDDEN)?;
+                if !pow_expired(timestamp, now)
+                    && verify_pow(session_id, timestamp, nonce, *difficulty)
+                    && spent.spend(session_id, timestamp, nonce, now)
+                {
+                    Ok(Admission {
+                        bound_session: Some(session_id.to_string()),
+                        account: None,
+                    })
+                } else {
+                    Err(StatusCode::FORBIDDEN)
+                }
+            }
+        }
+    }
+}
+
+/// Check a request to the admin API against `admin_key`. Without a key the API
+/// is off (404); a missing key maps to 401, another key to 403.
+pub fn check_admin_key(admin_key: Option<&str>, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let admin_key = admin_key.ok_or(StatusCode::NOT_FOUND)?;
+    if bool::from(key_eq(header_str(headers, ADMIN_KEY_HEADER)?, admin_key)) {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Constant-time equality of two secrets. Comparing their SHA-256 digests keeps
+/// the time independent of the lengths too.
+fn key_eq(a: &str, b: &str) -> Choice {
+    Sha256::digest(a).ct_eq(&Sha256::digest(b))
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, StatusCode> {
+    headers
+        .get(name)
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_str()
+        .map_err(|_| StatusCode::FORBIDDEN)
+}
+
+/// Whether a solution timestamped `timestamp` no longer admits setups at `now`:
+/// it is older than `POW_MAX_AGE`, or as far ahead of `now`.
+fn pow_expired(timestamp: u64, now: u64) -> bool {
+    timestamp.abs_diff(now) > POW_MAX_AGE.as_secs()
+}
+
+/// Number of leading zero bits of
+/// SHA-256(domain || session_id || timestamp || nonce).
+fn pow_leading_zeros(session_id: &str, timestamp: u64, nonce: u64) -> u32 {
+    let digest = Sha256::new()
+        .chain_update(POW_DOMAIN)
+        .chain_update((session_id.len() as u64).to_le_bytes())
+        .chain_update(session_id.as_bytes())
+        .chain_update(timestamp.to_le_bytes())
+        .chain_update(nonce.to_le_bytes())
+        .finalize();
+    let mut zeros = 0;
+    for byte in digest {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Check a proof-of-work solution for `session_id` at `timestamp`.
+pub fn verify_pow(session_id: &str, timestamp: u64, nonce: u64, difficulty: u32) -> bool {
+    pow_leading_zeros(session_id, timestamp, nonce) >= difficulty
+}
+
+/// Find a nonce solving the proof-of-work puzzle for `session_id` at
+/// `timestamp`, which should be the current Unix time.
+/// Expected cost is 2^difficulty hashes.
+pub fn solve_pow(session_id: &str, timestamp: u64, difficulty: u32) -> u64 {
+    (0u64..)
+        .find(|&nonce| verify_pow(session_id, timestamp, nonce, difficulty))
+        .expect("nonce space exhausted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_pow_roundtrip() {
+        let nonce = solve_pow("session-1", 1000, 12);
+        assert!(verify_pow("session-1", 1000, nonce, 12));
+        // Solution is bound to the session ID and the timestamp
+        assert!(!verify_pow("session-2", 1000, nonce, 12));
+        assert!(!verify_pow("session-1", 1001, nonce, 12));
+    }
+
+    #[test]
+    fn test_api_key_gate() {
+        let gate = SetupGate::ApiKeys(HashSet::from(["secret".to_string()]));
+
+        let spent = SpentSolutions::default();
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(gate.check(&headers, &spent), Err(StatusCode::UNAUTHORIZED));
+
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("wrong"));
+        assert_eq!(gate.check(&headers, &spent), Err(StatusCode::FORBIDDEN));
+
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("secret"));
+        assert_eq!(
+            gate.check(&headers, &spent).unwrap().account.as_deref(),
+            Some("secret")
+        );
+    }
+
+    #[test]
+    fn test_pow_gate_returns_bound_session() {
+        let gate = SetupGate::ProofOfWork { difficulty: 6 };
+        let spent = SpentSolutions::default();
+        let now = 1_700_000_000;
+        let nonce = solve_pow("a:b", now, 6);
+
+        let mut headers = HeaderMap::new();
+        let value = format!("a:b:{now}:{nonce}");
+        headers.insert(POW_HEADER, HeaderValue::from_str(&value).unwrap());
+        assert_eq!(
+            gate.check_at(&headers, &spent, now + 1)
+                .unwrap()
+                .bound_session
+                .as_deref(),
+            Some("a:b")
+        );
+        // Each solution admits one setup
+        assert_eq!(
+            gate.check_at(&headers, &spent, now + 2),
+            Err(StatusCode::FORBIDDEN)
+        );
+
+        // Solutions expire
+        let nonce = solve_pow("c", now, 6);
+        let value = format!("c:{now}:{nonce}");
+        headers.insert(POW_HEADER, HeaderValue::from_str(&value).unwrap());
+        let late = now + POW_MAX_AGE.as_secs() + 1;
+        assert_eq!(
+            gate.check_at(&headers, &spent, late),
+            Err(StatusCode::FORBIDDEN)
+        );
+        // and spent ones are forgotten once expired
+        assert!(gate.check_at(&headers, &spent, now).is_ok());
+        assert_eq!(spent.spent.lock().unwrap().len(), 2);
+        let nonce = solve_pow("c", late, 6);
+        let value = format!("c:{late}:{nonce}");
+        headers.insert(POW_HEADER, HeaderValue::from_str(&value).unwrap());
+        assert!(gate.check_at(&headers, &spent, late).is_ok());
+        assert_eq!(spent.spent.lock().unwrap().len(), 1);
+
+        headers.insert(POW_HEADER, HeaderValue::from_static("abc:1:not-a-number"));
+        assert_eq!(gate.check(&headers, &spent), Err(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Assumed cost before the first prove has been timed, in ms per compute unit.
+const INITIAL_MS_PER_UNIT: f64 = 0.002;
+
+/// Weight of the newest sample in the moving average of ms per compute unit.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// Tracks in-flight prove work and observed MSM throughput, for /estimate.
+#[derive(Debug)]
+pub struct LoadTracker {
+    inner: Mutex<LoadInner>,
+}
+
+#[derive(Debug)]
+struct LoadInner {
+    in_flight: u32,
+    pending_units: u64,
+    /// Moving average of ms per compute unit; `None` until a prove has completed.
+    ms_per_unit: Option<f64>,
+}
+
+/// Predicted timing for a prove request under the current load.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadEstimate {
+    pub queue_ms: u64,
+    pub compute_ms: u64,
+    pub in_flight: u32,
+    /// Whether the throughput figure comes from observed proves rather than the prior.
+    pub calibrated: bool,
+}
+
+impl Default for LoadTracker {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(LoadInner {
+                in_flight: 0,
+                pending_units: 0,
+                ms_per_unit: None,
+            }),
+        }
+    }
+}
+
+impl LoadTracker {
+    /// Register `units` of work as in flight until the returned guard is dropped.
+    pub fn begin(&self, units: u64) -> LoadGuard<'_> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.in_flight += 1;
+        inner.pending_units += units;
+        LoadGuard {
+            tracker: self,
+            units,
+            start: Instant::now(),
+            completed: false,
+        }
+    }
+
+    /// Estimate queue delay and compute time for a request of `units` compute units.
+    /// In-flight work is assumed to drain before the new request gets the full machine.
+    pub fn estimate(&self, units: u64) -> LoadEstimate {
+        let inner = self.inner.lock().unwrap();
+        let ms_per_unit = inner.ms_per_unit.unwrap_or(INITIAL_MS_PER_UNIT);
+        LoadEstimate {
+            queue_ms: (inner.pending_units as f64 * ms_per_unit).ceil() as u64,
+            compute_ms: (units as f64 * ms_per_unit).ceil() as u64,
+            in_flight: inner.in_flight,
+            calibrated: inner.ms_per_unit.is_some(),
+        }
+    }
+
+    fn finish(&self, units: u64, elapsed_ms: Option<f64>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.in_flight -= 1;
+        inner.pending_units -= units;
+        if let Some(elapsed_ms) = elapsed_ms.filter(|_| units > 0) {
+            let sample = elapsed_ms / units as f64;
+            inner.ms_per_unit = Some(match inner.ms_per_unit {
+                Some(avg) => avg + EWMA_ALPHA * (sample - avg),
+                None => sample,
+            });
+        }
+    }
+}
+
+/// In-flight prove work. Dropping it without `complete` (e.g. on a failed request)
+/// releases the work without feeding its duration into the throughput average.
+pub struct LoadGuard<'a> {
+    tracker: &'a LoadTracker,
+    units: u64,
+    start: Instant,
+    completed: bool,
+}
+
+impl LoadGuard<'_> {
+    /// Mark the work as done and record how long it took.
+    pub fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for LoadGuard<'_> {
+    fn drop(&mut self) {
+        let elapsed_ms = self
+            .completed
+            .then(|| self.start.elapsed().as_secs_f64() * 1000.0);
+        self.tracker.finish(self.units, elapsed_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_accounts_for_in_flight_work() {
+        let tracker = LoadTracker::default();
+        let idle = tracker.estimate(1000);
+        assert_eq!(idle.queue_ms, 0);
+        assert!(!idle.calibrated);
+
+        let guard = tracker.begin(10_000);
+        let busy = tracker.estimate(1000);
+        assert_eq!(busy.in_flight, 1);
+        assert!(busy.queue_ms > 0);
+        guard.complete();
+
+        let after = tracker.estimate(1000);
+        assert_eq!(after.in_flight, 0);
+        assert_eq!(after.queue_ms, 0);
+        assert!(after.calibrated);
+
+        // Failed work is released without calibrating
+        let fresh = LoadTracker::default();
+        drop(fresh.begin(10_000));
+        assert!(!fresh.estimate(1000).calibrated);
+    }
+}
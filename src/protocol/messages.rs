@@ -1,89 +1,1078 @@
+use std::collections::BTreeMap;
+
+use ark_bn254::{Fr, G1Affine, G2Affine};
+use ark_ec::short_weierstrass::{Affine, Projective, SWCurveConfig};
+use ark_ec::CurveGroup;
+use ark_ff::{BigInt, PrimeField, Zero};
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::curves::grumpkin::GrumpkinProjective;
+use crate::error::StealthSnarkError;
+use crate::groth16::fingerprint::Fingerprint;
+use crate::groth16::server_aided::{
+    EncryptedRequest, PublicGenerators, PublicInputResponse, ServerAidedProvingKey, ServerResponse,
+};
+use crate::groth16::witness_map::FftStep;
+pub use crate::serialization::{
+    ark_from_bytes, ark_serde, ark_serde_vec, ark_to_bytes, ark_vec_from_bytes, ark_vec_to_bytes,
+};
+pub(crate) use crate::serialization::check_vec_len;
+
+/// The length prefix of a vector of fixed-size elements, and the elements'
+/// bytes, which must be `size` per element.
+pub(crate) fn split_fixed_size<'a>(
+    bytes: &'a [u8],
+    size: usize,
+    elements: &str,
+) -> Result<(u64, &'a [u8]), StealthSnarkError> {
+    let (len, body) = bytes
+        .split_first_chunk::<8>()
+        .ok_or_else(|| StealthSnarkError::Malformed("failed to read vec length".to_string()))?;
+    let len = u64::from_le_bytes(*len);
+    check_vec_len(len)?;
+    if body.len() as u64 != size as u64 * len {
+        return Err(StealthSnarkError::Malformed(format!(
+            "{len} {elements} take {} bytes, got {}",
+            size as u64 * len,
+            body.len()
+        )));
+    }
+    Ok((len, body))
+}
+
+/// Points per chunk decompressed or validated on one thread.
+const POINT_CHUNK: usize = 1 << 10;
+
+/// Deserialize a vector of compressed curve points, with the length limit of
+/// `ark_vec_from_bytes`, and `validate_points` it. Every point takes the same
+/// number of bytes, so the buffer is checked against the count up front and
+/// the points are decompressed in parallel chunks, without the per-point
+/// checks of `ark_vec_from_bytes`.
+pub fn ark_points_from_bytes<P: SWCurveConfig>(
+    bytes: &[u8],
+) -> Result<Vec<Affine<P>>, StealthSnarkError> {
+    let size = Affine::<P>::identity().compressed_size();
+    let (len, body) = split_fixed_size(bytes, size, "points")?;
+
+    let mut points = vec![Affine::<P>::identity(); len as usize];
+    let decode_chunk = |(chunk, (points, bytes)): (usize, (&mut [Affine<P>], &[u8]))| {
+        for (i, (point, bytes)) in points.iter_mut().zip(bytes.chunks_exact(size)).enumerate() {
+            *point = Affine::deserialize_compressed_unchecked(bytes).map_err(|e| {
+                let i = chunk * POINT_CHUNK + i;
+                StealthSnarkError::Malformed(format!("failed to deserialize element {i}: {e}"))
+            })?;
+        }
+        Ok::<_, StealthSnarkError>(())
+    };
+    #[cfg(feature = "parallel")]
+    points
+        .par_chunks_mut(POINT_CHUNK)
+        .zip(body.par_chunks(size * POINT_CHUNK))
+        .enumerate()
+        .try_for_each(decode_chunk)?;
+    #[cfg(not(feature = "parallel"))]
+    points
+        .chunks_mut(POINT_CHUNK)
+        .zip(body.chunks(size * POINT_CHUNK))
+        .enumerate()
+        .try_for_each(decode_chunk)?;
+    validate_points(&points)?;
+    Ok(points)
+}
+
+/// A point `validate_points` rejected, by its index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidPoint {
+    #[error("element {0} is not on the curve")]
+    NotOnCurve(usize),
+    #[error("element {0} is not in the prime-order subgroup")]
+    NotInSubgroup(usize),
+}
+
+/// Check that every point is on the curve and in its prime-order subgroup, in
+/// parallel chunks. MSMs assume both; a generator outside the subgroup gives
+/// results the client's unmasking does not cancel. The subgroup check is free
+/// on curves of cofactor 1 (BN254's G1, Grumpkin) and a scalar multiplication
+/// per point on BN254's G2. With several invalid points, any one is reported.
+pub fn validate_points<P: SWCurveConfig>(points: &[Affine<P>]) -> Result<(), InvalidPoint> {
+    let check_chunk = |(chunk, points): (usize, &[Affine<P>])| {
+        for (i, point) in points.iter().enumerate() {
+            let i = chunk * POINT_CHUNK + i;
+            if !point.is_on_curve() {
+                return Err(InvalidPoint::NotOnCurve(i));
+            }
+            if !point.is_in_correct_subgroup_assuming_on_curve() {
+                return Err(InvalidPoint::NotInSubgroup(i));
+            }
+        }
+        Ok(())
+    };
+    #[cfg(feature = "parallel")]
+    let chunks = points.par_chunks(POINT_CHUNK);
+    #[cfg(not(feature = "parallel"))]
+    let chunks = points.chunks(POINT_CHUNK);
+    chunks.enumerate().try_for_each(check_chunk)
+}
+
+/// Header carrying the `RequestId` of a request, in lowercase hex. The server
+/// echoes it on every response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Correlates a request with the server's log lines for it: the server traces
+/// every event of the request under it and reports it in `ProveMetadata`.
+/// `EmsmClient` draws a fresh one per request; the server draws one for requests
+/// that carry none.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+    ToSchema,
+)]
+pub struct RequestId(pub [u8; 16]);
+
+impl RequestId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Parse the value of `REQUEST_ID_HEADER`: 32 hex digits.
+    pub fn from_header_value(value: &[u8]) -> Option<Self> {
+        if value.len() != 32 || !value.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        let mut id = [0u8; 16];
+        for (byte, pair) in id.iter_mut().zip(value.chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self(id))
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// Header carrying the `SessionToken` of the session a request is for, in
+/// lowercase hex.
+pub const SESSION_TOKEN_HEADER: &str = "x-stealthsnark-session-token";
+
+/// Bearer token of a session, drawn by the server at the session's first setup
+/// and returned in `SetupResponse`. Later setups of the session, and its prove,
+/// MSM, eval, FFT and keepalive requests, must carry it in
+/// `SESSION_TOKEN_HEADER`, so knowing a session ID is not enough to use or
+/// overwrite the session. The server keeps only its hash.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SessionToken(pub [u8; 32]);
+
+impl SessionToken {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Parse the value of `SESSION_TOKEN_HEADER`: 64 hex digits.
+    pub fn from_header_value(value: &[u8]) -> Option<Self> {
+        if value.len() != 64 || !value.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        let mut token = [0u8; 32];
+        for (byte, pair) in token.iter_mut().zip(value.chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self(token))
+    }
+
+    /// What the server keeps of the token.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.0).into()
+    }
+}
+
+impl std::fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// The token is a credential, so it is not printed.
+impl std::fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionToken(..)")
+    }
+}
+
+/// Header a client sets on POST /prove, /msm and /eval to have the server give
+/// up on the request this many milliseconds after receiving it. A server also
+/// enforces its own `prove_timeout`, whichever passes first. A request that
+/// runs past it fails with `ErrorCode::Timeout`, reporting how much of it was
+/// evaluated.
+pub const DEADLINE_HEADER: &str = "x-stealthsnark-deadline-ms";
+
+/// Scalars per parallel chunk of `ScalarEncoding::decode`: 128 KiB of input.
+const DECODE_CHUNK: usize = 1 << 12;
+
+/// Header a client sets on POST /setup to offer `ScalarEncoding::Montgomery`,
+/// and on POST /prove and /msm when its vectors use it. A server that accepts
+/// the offer echoes it on the setup response.
+pub const SCALAR_ENCODING_HEADER: &str = "x-stealthsnark-scalar-encoding";
+
+/// How the scalar vectors of prove and MSM requests are encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScalarEncoding {
+    /// `ark_vec_to_bytes`: compressed canonical scalars, which the client
+    /// converts out of Montgomery form and the server back into it.
+    #[default]
+    Canonical,
+    /// The u64 count, then the four limbs of each scalar's Montgomery form as
+    /// arkworks holds it in memory, little-endian. Decoding only checks that
+    /// each scalar is below the modulus.
+    Montgomery,
+}
+
+impl ScalarEncoding {
+    /// Value of `SCALAR_ENCODING_HEADER`.
+    pub fn header_value(self) -> &'static str {
+        match self {
+            ScalarEncoding::Canonical => "canonical",
+            ScalarEncoding::Montgomery => "montgomery",
+        }
+    }
+
+    pub fn from_header_value(value: &[u8]) -> Option<Self> {
+        match value {
+            b"canonical" => Some(ScalarEncoding::Canonical),
+            b"montgomery" => Some(ScalarEncoding::Montgomery),
+            _ => None,
+        }
+    }
+
+    pub fn encode(self, scalars: &[Fr]) -> Vec<u8> {
+        match self {
+            ScalarEncoding::Canonical => ark_vec_to_bytes(scalars),
+            ScalarEncoding::Montgomery => {
+                let mut buf = Vec::with_capacity(8 + 32 * scalars.len());
+                buf.extend_from_slice(&(scalars.len() as u64).to_le_bytes());
+                for x in scalars {
+                    for limb in x.0 .0 {
+                        buf.extend_from_slice(&limb.to_le_bytes());
+                    }
+                }
+                buf
+            }
+        }
+    }
+
+    /// Decode a vector, with the length limit of `ark_vec_from_bytes`. A scalar
+    /// takes 32 bytes in either encoding, so the buffer is checked against the
+    /// count up front and converted straight into a vector of that length, in
+    /// parallel chunks, rather than element by element into a growing one.
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<Fr>, StealthSnarkError> {
+        let (len, body) = split_fixed_size(bytes, 32, "scalars")?;
+
+        let mut scalars = vec![Fr::zero(); len as usize];
+        let decode_chunk = |(chunk, (scalars, bytes)): (usize, (&mut [Fr], &[u8]))| {
+            for (i, (scalar, bytes)) in scalars.iter_mut().zip(bytes.chunks_exact(32)).enumerate() {
+                let mut repr = BigInt::<4>([0; 4]);
+                for (limb, bytes) in repr.0.iter_mut().zip(bytes.chunks_exact(8)) {
+                    *limb = u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+                }
+                // Montgomery forms are reduced like canonical ones
+                if repr >= Fr::MODULUS {
+                    let i = chunk * DECODE_CHUNK + i;
+                    return Err(StealthSnarkError::Malformed(format!(
+                        "element {i} is not below the modulus"
+                    )));
+                }
+                *scalar = match self {
+                    ScalarEncoding::Canonical => Fr::from_bigint(repr).expect("below the modulus"),
+                    ScalarEncoding::Montgomery => Fr::new_unchecked(repr),
+                };
+            }
+            Ok(())
+        };
+        #[cfg(feature = "parallel")]
+        scalars
+            .par_chunks_mut(DECODE_CHUNK)
+            .zip(body.par_chunks(32 * DECODE_CHUNK))
+            .enumerate()
+            .try_for_each(decode_chunk)?;
+        #[cfg(not(feature = "parallel"))]
+        scalars
+            .chunks_mut(DECODE_CHUNK)
+            .zip(body.chunks(32 * DECODE_CHUNK))
+            .enumerate()
+            .try_for_each(decode_chunk)?;
+        Ok(scalars)
+    }
+}
+
+/// Curve the points and scalars of a request are encoded for. Every setup and
+/// prove envelope carries one, so bytes for one curve are never decoded as
+/// another's.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum CurveId {
+    #[default]
+    Bn254,
+    Bls12_381,
+    /// `curves::grumpkin`, which has no pairing: only for generator sets.
+    Grumpkin,
+}
 
-/// Maximum number of elements allowed in a deserialized vector.
-/// Prevents unbounded allocation from attacker-controlled length prefixes.
-/// 2^24 elements is the largest LPN parameter table entry.
-const MAX_VEC_LEN: u64 = 1 << 24;
+impl CurveId {
+    /// Whether the curve has a pairing, as Groth16 circuits need.
+    pub fn has_pairing(self) -> bool {
+        match self {
+            CurveId::Bn254 | CurveId::Bls12_381 => true,
+            CurveId::Grumpkin => false,
+        }
+    }
+}
+
+/// A group whose MSMs a server evaluates against a generator set (POST
+/// /emsm/eval), tagged on the wire with `CURVE`. For BN254 that is G1.
+pub trait DelegatedCurve: CurveGroup {
+    const CURVE: CurveId;
+}
 
-/// Serialize an arkworks type to bytes.
-pub fn ark_to_bytes<T: CanonicalSerialize>(val: &T) -> Vec<u8> {
-    let mut buf = Vec::new();
-    val.serialize_compressed(&mut buf)
-        .expect("serialization failed");
-    buf
+// Named by its config: coherence does not see through the projection in
+// `ark_bn254::G1Projective`
+impl DelegatedCurve for Projective<ark_bn254::g1::Config> {
+    const CURVE: CurveId = CurveId::Bn254;
 }
 
-/// Deserialize an arkworks type from bytes.
-/// Returns an error instead of panicking on malformed input.
-pub fn ark_from_bytes<T: CanonicalDeserialize>(bytes: &[u8]) -> Result<T, anyhow::Error> {
-    T::deserialize_compressed(bytes).map_err(|e| anyhow::anyhow!("deserialization failed: {e}"))
+impl DelegatedCurve for GrumpkinProjective {
+    const CURVE: CurveId = CurveId::Grumpkin;
 }
 
-/// Serialize a vector of arkworks types to bytes.
-pub fn ark_vec_to_bytes<T: CanonicalSerialize>(vals: &[T]) -> Vec<u8> {
-    let mut buf = Vec::new();
-    let len = vals.len() as u64;
-    len.serialize_compressed(&mut buf).unwrap();
-    for v in vals {
-        v.serialize_compressed(&mut buf).unwrap();
+/// A request tagged for a different curve than its session, or than the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("request is encoded for {request:?}, but the session uses {session:?}")]
+pub struct CurveMismatch {
+    pub session: CurveId,
+    pub request: CurveId,
+}
+
+impl CurveMismatch {
+    pub fn check(session: CurveId, request: CurveId) -> Result<(), Self> {
+        if session == request {
+            Ok(())
+        } else {
+            Err(Self { session, request })
+        }
     }
-    buf
 }
 
-/// Deserialize a vector of arkworks types from bytes.
-/// Returns an error on malformed input or if the length exceeds MAX_VEC_LEN.
-pub fn ark_vec_from_bytes<T: CanonicalDeserialize>(bytes: &[u8]) -> Result<Vec<T>, anyhow::Error> {
-    let mut cursor = bytes;
-    let len: u64 = CanonicalDeserialize::deserialize_compressed(&mut cursor)
-        .map_err(|e| anyhow::anyhow!("failed to read vec length: {e}"))?;
-    if len > MAX_VEC_LEN {
-        anyhow::bail!("vec length {len} exceeds maximum {MAX_VEC_LEN}");
+/// What a server offers, from GET /capabilities, so a client can pick a curve
+/// and codec before it uploads generators.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Capabilities {
+    /// Curves sessions may be set up on. Each circuit or generator set is
+    /// evaluated on the curve its setup declared; circuits need one with a
+    /// pairing (`CurveId::has_pairing`).
+    pub curves: Vec<CurveId>,
+    /// `Content-Type`s setup and prove bodies may be encoded in.
+    pub codecs: Vec<String>,
+}
+
+/// Generator set request for POST /emsm/setup: the generators of a delegated
+/// MSM outside Groth16, e.g. the commitment key of a Pedersen commitment, on the
+/// envelope's curve.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct GeneratorSetRequest {
+    /// `ark_vec_to_bytes` encoded affine points.
+    pub generators: Vec<u8>,
+}
+
+impl GeneratorSetRequest {
+    pub fn new<G: DelegatedCurve>(generators: &[G::Affine]) -> Self {
+        Self {
+            generators: ark_vec_to_bytes(generators),
+        }
     }
-    let mut vals = Vec::with_capacity(len as usize);
-    for i in 0..len {
-        let val = T::deserialize_compressed(&mut cursor)
-            .map_err(|e| anyhow::anyhow!("failed to deserialize element {i}: {e}"))?;
-        vals.push(val);
+
+    /// SHA-256 commitment to the serialized generators, separated from the
+    /// hashes of `SetupRequest`s.
+    pub fn generators_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"stealthsnark generator set");
+        hasher.update(&self.generators);
+        hasher.finalize().into()
     }
-    Ok(vals)
+}
+
+/// Eval request for POST /emsm/eval: a masked vector (`emsm::encrypt`) to take
+/// the MSM of against a generator set.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EvalRequest {
+    /// `ark_vec_to_bytes` encoded scalars of the curve's scalar field.
+    pub vector: Vec<u8>,
+}
+
+/// Eval response: the MSM result, a compressed affine point.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EvalResponse {
+    pub result: Vec<u8>,
+    pub metadata: ProveMetadata,
+}
+
+/// Malicious eval request for POST /emsm/eval/malicious: the main and check
+/// vectors of `malicious::malicious_encrypt`, whose MSMs the server takes
+/// against a generator set without learning which is which.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MaliciousEvalRequest {
+    /// `ark_vec_to_bytes` encoded scalars of the curve's scalar field.
+    pub vector: Vec<u8>,
+    /// Same encoding and length as `vector`.
+    pub check_vector: Vec<u8>,
+}
+
+/// Malicious eval response: the MSM results of both vectors, compressed affine
+/// points, for `malicious::malicious_decrypt`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MaliciousEvalResponse {
+    pub result: Vec<u8>,
+    pub check_result: Vec<u8>,
+    pub metadata: ProveMetadata,
 }
 
 /// Setup request: generator points for each of the 5 MSMs.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct SetupRequest {
     pub h_generators: Vec<u8>,
     pub l_generators: Vec<u8>,
     pub a_generators: Vec<u8>,
     pub b_g1_generators: Vec<u8>,
     pub b_g2_generators: Vec<u8>,
+    /// Compressed `PublicGenerators`, or empty if the client computes the
+    /// public-input MSMs itself.
+    pub public_generators: Vec<u8>,
+    /// `Fingerprint` of the proving key the generators come from, which the
+    /// server publishes in `CrsCommitment`. Not part of the generators hash.
+    #[serde(default)]
+    pub key_fingerprint: Option<[u8; 32]>,
+}
+
+impl SetupRequest {
+    /// SHA-256 commitment to the serialized generator sets, in wire order. The
+    /// public generators are only hashed when present.
+    pub fn generators_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for part in [
+            &self.h_generators,
+            &self.l_generators,
+            &self.a_generators,
+            &self.b_g1_generators,
+            &self.b_g2_generators,
+        ] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        if !self.public_generators.is_empty() {
+            hasher.update((self.public_generators.len() as u64).to_le_bytes());
+            hasher.update(&self.public_generators);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Also upload the public-input generators of `sapk`, so the server can
+    /// evaluate the public-input MSMs (`MsmKind::Public`).
+    pub fn with_public_generators(mut self, sapk: &ServerAidedProvingKey) -> Self {
+        self.public_generators = ark_to_bytes(&PublicGenerators::from(sapk));
+        self
+    }
+}
+
+impl From<&ServerAidedProvingKey> for SetupRequest {
+    /// The generators a server needs for `sapk`, as `EmsmClient::send_setup` uploads them.
+    fn from(sapk: &ServerAidedProvingKey) -> Self {
+        Self {
+            h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+            l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+            a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+            b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+            b_g2_generators: ark_vec_to_bytes(&sapk.emsm_b_g2.generators),
+            public_generators: Vec::new(),
+            key_fingerprint: Some(sapk.fingerprint()),
+        }
+    }
+}
+
+/// Points per chunk of a differential setup (`GeneratorChunks`, `SetupPatch`).
+pub const GENERATOR_CHUNK: usize = 1 << 16;
+
+/// SHA-256 of each `chunk_len` points of an `ark_vec_to_bytes` encoded vector
+/// of compressed points `point_size` bytes long; the last chunk may be shorter.
+pub fn chunk_hashes(
+    encoded: &[u8],
+    point_size: usize,
+    chunk_len: usize,
+) -> Result<Vec<[u8; 32]>, StealthSnarkError> {
+    let (_, body) = split_fixed_size(encoded, point_size, "points")?;
+    Ok(body
+        .chunks(chunk_len * point_size)
+        .map(|chunk| Sha256::digest(chunk).into())
+        .collect())
+}
+
+/// Compressed sizes of the points of the h, l, a, b_g1 and b_g2 generators.
+pub(crate) fn generator_point_sizes() -> [usize; 5] {
+    let g1 = G1Affine::identity().compressed_size();
+    let g2 = G2Affine::identity().compressed_size();
+    [g1, g1, g1, g1, g2]
+}
+
+/// What a server publishes about the chunks of a circuit's generators, for a
+/// client to upload only the chunks that changed (`SetupPatch`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct GeneratorChunks {
+    /// `SetupRequest::generators_hash` of the generators.
+    pub generators_hash: [u8; 32],
+    /// Points per chunk.
+    pub chunk_len: usize,
+    /// `chunk_hashes` of the h, l, a, b_g1 and b_g2 generators.
+    pub hashes: [Vec<[u8; 32]>; 5],
+}
+
+/// One chunk of a `SetupPatch` or `SetupChunk`: points `index * chunk_len`
+/// onwards of one MSM's generators.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct GeneratorChunk {
+    /// One of `MsmKind::ALL`.
+    pub kind: MsmKind,
+    pub index: u64,
+    /// The chunk's compressed points, with no length prefix.
+    pub points: Vec<u8>,
+    /// SHA-256 of `points`, as `chunk_hashes` computes it. A server refuses
+    /// a chunk that does not match, naming it, rather than failing on the
+    /// hash of the whole upload.
+    pub checksum: [u8; 32],
+}
+
+impl GeneratorChunk {
+    pub fn new(kind: MsmKind, index: u64, points: Vec<u8>) -> Self {
+        let checksum = Sha256::digest(&points).into();
+        Self {
+            kind,
+            index,
+            points,
+            checksum,
+        }
+    }
+
+    /// Whether `points` still hash to `checksum`.
+    pub fn checksum_matches(&self) -> bool {
+        <[u8; 32]>::from(Sha256::digest(&self.points)) == self.checksum
+    }
+}
+
+/// Differential setup for POST /setup/patch: a circuit's new generators as the
+/// chunks that differ from the ones the server holds for it. The server splices
+/// them into its generators and refuses the patch unless the result hashes to
+/// `generators_hash`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SetupPatch {
+    /// `SetupRequest::generators_hash` of the generators being patched.
+    pub base_hash: [u8; 32],
+    /// `SetupRequest::generators_hash` of the patched generators.
+    pub generators_hash: [u8; 32],
+    /// Point counts of the patched h, l, a, b_g1 and b_g2 generators.
+    pub lens: [u64; 5],
+    /// The chunks that changed, each of `GENERATOR_CHUNK` points except for
+    /// the last of its MSM.
+    pub chunks: Vec<GeneratorChunk>,
+    /// As in `SetupRequest`, sent whole.
+    pub public_generators: Vec<u8>,
+    pub key_fingerprint: Option<[u8; 32]>,
+}
+
+impl SetupPatch {
+    /// The patch taking the generators `base` describes to those of `target`.
+    pub fn new(base: &GeneratorChunks, target: &SetupRequest) -> Result<Self, StealthSnarkError> {
+        if base.chunk_len == 0 {
+            return Err(StealthSnarkError::Malformed(
+                "generator chunks of 0 points".to_string(),
+            ));
+        }
+        let parts = [
+            &target.h_generators,
+            &target.l_generators,
+            &target.a_generators,
+            &target.b_g1_generators,
+            &target.b_g2_generators,
+        ];
+        let mut lens = [0; 5];
+        let mut chunks = Vec::new();
+        for (i, (kind, size)) in MsmKind::ALL
+            .into_iter()
+            .zip(generator_point_sizes())
+            .enumerate()
+        {
+            let (len, body) = split_fixed_size(parts[i], size, "points")?;
+            lens[i] = len;
+            for (index, points) in body.chunks(base.chunk_len * size).enumerate() {
+                let checksum: [u8; 32] = Sha256::digest(points).into();
+                if base.hashes[i].get(index) != Some(&checksum) {
+                    chunks.push(GeneratorChunk {
+                        kind,
+                        index: index as u64,
+                        points: points.to_vec(),
+                        checksum,
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            base_hash: base.generators_hash,
+            generators_hash: target.generators_hash(),
+            lens,
+            chunks,
+            public_generators: target.public_generators.clone(),
+            key_fingerprint: target.key_fingerprint,
+        })
+    }
+
+    /// Bytes of points the patch carries.
+    pub fn points_len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.points.len()).sum()
+    }
+}
+
+/// One part of a chunked setup for POST /setup/chunk: some of the chunks of a
+/// `SetupRequest`'s generators, for uploads too large for one request body. The
+/// server keeps the chunks of an upload until it has all of them, then sets the
+/// circuit up with the generators they make if those hash to `generators_hash`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SetupChunk {
+    /// `SetupRequest::generators_hash` of the whole upload.
+    pub generators_hash: [u8; 32],
+    /// Points per chunk.
+    pub chunk_len: u64,
+    /// Point counts of the h, l, a, b_g1 and b_g2 generators.
+    pub lens: [u64; 5],
+    /// Chunks of `chunk_len` points, except for the last of its MSM.
+    pub chunks: Vec<GeneratorChunk>,
+    /// As in `SetupRequest`. Only those of the part completing the upload are
+    /// used, so `split` puts them in the last part.
+    pub public_generators: Vec<u8>,
+    pub key_fingerprint: Option<[u8; 32]>,
+}
+
+impl SetupChunk {
+    /// `request` as parts of at most `part_bytes` bytes of points each, or one
+    /// chunk where a single point is larger. Each part is copied out of
+    /// `request` only when the iterator reaches it.
+    pub fn split(
+        request: &SetupRequest,
+        part_bytes: usize,
+    ) -> Result<impl DoubleEndedIterator<Item = Self> + ExactSizeIterator + '_, StealthSnarkError>
+    {
+        let sizes = generator_point_sizes();
+        let chunk_len = (part_bytes / sizes.iter().max().unwrap()).max(1);
+        let fields = [
+            &request.h_generators,
+            &request.l_generators,
+            &request.a_generators,
+            &request.b_g1_generators,
+            &request.b_g2_generators,
+        ];
+        let mut lens = [0; 5];
+        let mut bodies = [&[][..]; 5];
+        for i in 0..5 {
+            (lens[i], bodies[i]) = split_fixed_size(fields[i], sizes[i], "points")?;
+        }
+
+        // (field, index) of the chunks of each part
+        let mut parts = vec![Vec::new()];
+        let mut part_len = 0;
+        for (i, body) in bodies.iter().enumerate() {
+            for (index, points) in body.chunks(chunk_len * sizes[i]).enumerate() {
+                let current = parts.last().unwrap();
+                if !current.is_empty() && part_len + points.len() > part_bytes {
+                    parts.push(Vec::new());
+                    part_len = 0;
+                }
+                parts.last_mut().unwrap().push((i, index));
+                part_len += points.len();
+            }
+        }
+
+        let generators_hash = request.generators_hash();
+        let last = parts.len() - 1;
+        Ok(parts.into_iter().enumerate().map(move |(part, chunks)| {
+            let chunks = chunks
+                .into_iter()
+                .map(|(i, index)| {
+                    let start = index * chunk_len * sizes[i];
+                    let end = (start + chunk_len * sizes[i]).min(bodies[i].len());
+                    GeneratorChunk::new(
+                        MsmKind::ALL[i],
+                        index as u64,
+                        bodies[i][start..end].to_vec(),
+                    )
+                })
+                .collect();
+            let is_last = part == last;
+            Self {
+                generators_hash,
+                chunk_len: chunk_len as u64,
+                lens,
+                chunks,
+                public_generators: if is_last {
+                    request.public_generators.clone()
+                } else {
+                    Vec::new()
+                },
+                key_fingerprint: request.key_fingerprint.filter(|_| is_last),
+            }
+        }))
+    }
+}
+
+/// What a server publishes about the generators it holds for a session, so a
+/// client can audit them against its own proving key before delegating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CrsCommitment {
+    pub curve: CurveId,
+    /// `SetupRequest::generators_hash` of the stored generators.
+    pub generators_hash: [u8; 32],
+    /// Lengths of the h, l, a, b_g1 and b_g2 generator sets.
+    pub sizes: [usize; 5],
+    /// `SetupRequest::key_fingerprint` of the upload.
+    #[serde(default)]
+    pub key_fingerprint: Option<[u8; 32]>,
+}
+
+/// Setup response: the server's `SetupRequest::generators_hash` of the upload,
+/// which prove requests echo so they are only evaluated against these generators,
+/// and the session's token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SetupResponse {
+    pub generators_hash: [u8; 32],
+    pub session_token: SessionToken,
 }
 
 /// Prove request: 5 masked scalar vectors.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ProveRequest {
     pub v_h: Vec<u8>,
     pub v_l: Vec<u8>,
     pub v_a: Vec<u8>,
     pub v_b_g1: Vec<u8>,
     pub v_b_g2: Vec<u8>,
+    /// How much of the server's pool the client asks for; `None` takes all of it.
+    #[serde(default)]
+    pub hint: Option<ParallelismHint>,
+}
+
+/// A cooperative client's bound on the threads its prove request runs on. The
+/// server evaluates the MSMs on as few threads as meet both bounds, leaving the
+/// rest of its pool to other requests; it never uses more than its pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ParallelismHint {
+    /// At most this many threads.
+    pub max_threads: Option<u32>,
+    /// Latency the client is content with, in ms: no more threads than the
+    /// server expects to need to finish within it.
+    pub target_latency_ms: Option<u64>,
+}
+
+impl ParallelismHint {
+    /// Threads to evaluate a request on, out of a pool of `pool_threads`, for a
+    /// request the server expects to take `compute_ms` on the whole pool.
+    pub fn threads(&self, pool_threads: usize, compute_ms: u64) -> usize {
+        let mut threads = pool_threads.max(1);
+        if let Some(target_ms) = self.target_latency_ms {
+            // The MSMs scale about linearly with the threads they get
+            let needed = (compute_ms as u128 * threads as u128).div_ceil(target_ms.max(1) as u128);
+            threads = threads.min(needed.max(1) as usize);
+        }
+        if let Some(max_threads) = self.max_threads {
+            threads = threads.min(max_threads.max(1) as usize);
+        }
+        threads
+    }
+}
+
+impl From<&EncryptedRequest> for ProveRequest {
+    fn from(request: &EncryptedRequest) -> Self {
+        Self::encode(request, ScalarEncoding::Canonical)
+    }
+}
+
+impl TryFrom<&ProveRequest> for EncryptedRequest {
+    type Error = StealthSnarkError;
+
+    fn try_from(request: &ProveRequest) -> Result<Self, Self::Error> {
+        request.decode(ScalarEncoding::Canonical)
+    }
+}
+
+impl ProveRequest {
+    /// `ProveRequest::from`, with the vectors in `encoding`.
+    pub fn encode(request: &EncryptedRequest, encoding: ScalarEncoding) -> Self {
+        Self {
+            v_h: encoding.encode(&request.v_h),
+            v_l: encoding.encode(&request.v_l),
+            v_a: encoding.encode(&request.v_a),
+            v_b_g1: encoding.encode(&request.v_b_g1),
+            v_b_g2: encoding.encode(&request.v_b_g2),
+            hint: None,
+        }
+    }
+
+    /// `EncryptedRequest::try_from`, for vectors in `encoding`.
+    pub fn decode(&self, encoding: ScalarEncoding) -> Result<EncryptedRequest, StealthSnarkError> {
+        Ok(EncryptedRequest {
+            v_h: encoding.decode(&self.v_h)?,
+            v_l: encoding.decode(&self.v_l)?,
+            v_a: encoding.decode(&self.v_a)?,
+            v_b_g1: encoding.decode(&self.v_b_g1)?,
+            v_b_g2: encoding.decode(&self.v_b_g2)?,
+        })
+    }
+
+    /// Split into one request per MSM, for `POST /msm`. The hint is dropped.
+    pub fn into_msms(self) -> [MsmRequest; 5] {
+        [
+            (MsmKind::H, self.v_h),
+            (MsmKind::L, self.v_l),
+            (MsmKind::A, self.v_a),
+            (MsmKind::BG1, self.v_b_g1),
+            (MsmKind::BG2, self.v_b_g2),
+        ]
+        .map(|(kind, vector)| MsmRequest { kind, vector })
+    }
+}
+
+/// One of the five MSMs of a prove request, or the unmasked public-input MSMs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum MsmKind {
+    H,
+    L,
+    A,
+    BG1,
+    /// The only masked MSM over G2.
+    BG2,
+    /// The public inputs in the clear against the circuit's `PublicGenerators`.
+    /// Only for circuits set up `with_public_generators`; not part of a prove
+    /// request.
+    Public,
+}
+
+impl MsmKind {
+    /// All five, in `ProveRequest` field order.
+    pub const ALL: [MsmKind; 5] = [Self::H, Self::L, Self::A, Self::BG1, Self::BG2];
+}
+
+/// Single-MSM request: one masked vector of a prove request.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MsmRequest {
+    pub kind: MsmKind,
+    /// Vector of Fr.
+    pub vector: Vec<u8>,
+}
+
+/// Single-MSM response: the MSM result, a G1Affine or for `MsmKind::BG2` a
+/// G2Affine. For `MsmKind::Public`, the A, B (G1) and B (G2) contributions as a
+/// compressed `(G1Affine, G1Affine, G2Affine)`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MsmResponse {
+    pub kind: MsmKind,
+    pub result: Vec<u8>,
+    pub metadata: ProveMetadata,
 }
 
 /// Prove response: 5 MSM results (group elements).
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ProveResponse {
     pub em_h: Vec<u8>,
     pub em_l: Vec<u8>,
     pub em_a: Vec<u8>,
     pub em_b_g1: Vec<u8>,
     pub em_b_g2: Vec<u8>,
+    pub metadata: ProveMetadata,
+}
+
+impl ProveResponse {
+    /// Encode the server's MSM results, with `metadata` alongside.
+    pub fn new(response: &ServerResponse, metadata: ProveMetadata) -> Self {
+        Self {
+            em_h: ark_to_bytes(&response.em_h.into_affine()),
+            em_l: ark_to_bytes(&response.em_l.into_affine()),
+            em_a: ark_to_bytes(&response.em_a.into_affine()),
+            em_b_g1: ark_to_bytes(&response.em_b_g1.into_affine()),
+            em_b_g2: ark_to_bytes(&response.em_b_g2.into_affine()),
+            metadata,
+        }
+    }
+
+    /// Reassemble the responses to `ProveRequest::into_msms`, in that order. The
+    /// compute units add up; the server time is the longest MSM's, since the
+    /// sub-requests run concurrently. The request ID is the first MSM's, which
+    /// `EmsmClient::send_prove_split` shares between all five.
+    pub fn from_msms(msms: [MsmResponse; 5]) -> Result<Self, StealthSnarkError> {
+        let kinds = msms.each_ref().map(|msm| msm.kind);
+        if kinds != MsmKind::ALL {
+            return Err(StealthSnarkError::Malformed(format!(
+                "MSM responses out of order: {kinds:?}"
+            )));
+        }
+        let metadata = ProveMetadata {
+            request_id: msms[0].metadata.request_id,
+            compute_units: msms.iter().map(|msm| msm.metadata.compute_units).sum(),
+            server_ms: msms
+                .iter()
+                .map(|msm| msm.metadata.server_ms)
+                .max()
+                .unwrap_or(0),
+        };
+        let [h, l, a, b_g1, b_g2] = msms;
+        Ok(Self {
+            em_h: h.result,
+            em_l: l.result,
+            em_a: a.result,
+            em_b_g1: b_g1.result,
+            em_b_g2: b_g2.result,
+            metadata,
+        })
+    }
+}
+
+impl TryFrom<&MsmResponse> for PublicInputResponse {
+    type Error = StealthSnarkError;
+
+    fn try_from(response: &MsmResponse) -> Result<Self, Self::Error> {
+        if response.kind != MsmKind::Public {
+            return Err(StealthSnarkError::Malformed(format!(
+                "expected a public-input MSM response, got {:?}",
+                response.kind
+            )));
+        }
+        let (a, b_g1, b_g2) = ark_from_bytes::<(G1Affine, G1Affine, G2Affine)>(&response.result)?;
+        Ok(Self {
+            a: a.into(),
+            b_g1: b_g1.into(),
+            b_g2: b_g2.into(),
+        })
+    }
+}
+
+impl TryFrom<&ProveResponse> for ServerResponse {
+    type Error = StealthSnarkError;
+
+    fn try_from(response: &ProveResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            em_h: ark_from_bytes::<G1Affine>(&response.em_h)?.into(),
+            em_l: ark_from_bytes::<G1Affine>(&response.em_l)?.into(),
+            em_a: ark_from_bytes::<G1Affine>(&response.em_a)?.into(),
+            em_b_g1: ark_from_bytes::<G1Affine>(&response.em_b_g1)?.into(),
+            em_b_g2: ark_from_bytes::<G2Affine>(&response.em_b_g2)?.into(),
+        })
+    }
+}
+
+/// Server-reported accounting for a prove request.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+    ToSchema,
+)]
+pub struct ProveMetadata {
+    /// Compute units charged (see `metering::compute_units`).
+    pub compute_units: u64,
+    /// Server-side MSM evaluation time in milliseconds.
+    pub server_ms: u64,
+    /// The request that was evaluated. A response replayed from the prove
+    /// cache carries the ID of the original request.
+    pub request_id: RequestId,
+}
+
+/// Keepalive request: resets the idle timer of a session.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KeepaliveRequest {
+    pub session_id: String,
+}
+
+/// Keepalive response: the session's status.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KeepaliveResponse {
+    /// Time until the session expires unless it is used again, or `None` if
+    /// the server does not expire sessions.
+    pub expires_in_ms: Option<u64>,
+    /// Whether the fixed-base tables of each of the session's circuits are
+    /// built, on a server that builds them (`ServerConfig::fixed_base_window`).
+    /// Empty on one that does not.
+    #[serde(default)]
+    pub tables_ready: BTreeMap<String, bool>,
+}
+
+/// FFT request: one step of a delegated witness map (see `groth16::witness_map`),
+/// applied to each of up to 3 padded vectors of the same power-of-two length.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FftRequest {
+    pub session_id: String,
+    pub step: FftStep,
+    /// `ark_vec_to_bytes` encoded scalar vectors.
+    pub vectors: Vec<Vec<u8>>,
+}
+
+/// FFT response: the transformed vectors, in request order.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FftResponse {
+    pub vectors: Vec<Vec<u8>>,
+}
+
+/// Estimate request: lengths of the 5 masked vectors a prove request would carry.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, ToSchema)]
+pub struct EstimateRequest {
+    pub h: u64,
+    pub l: u64,
+    pub a: u64,
+    pub b_g1: u64,
+    pub b_g2: u64,
+}
+
+/// Estimate response: expected cost of the request under the server's current load.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, ToSchema)]
+pub struct EstimateResponse {
+    pub compute_units: u64,
+    /// Time until already-admitted work drains, in milliseconds.
+    pub queue_ms: u64,
+    /// Time to evaluate the 5 MSMs once started, in milliseconds.
+    pub compute_ms: u64,
+    /// Prove requests currently being evaluated.
+    pub in_flight: u32,
+    /// `false` until the server has timed a prove; estimates then use a default rate.
+    pub calibrated: bool,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ark_bn254::{Fr, G1Affine, G1Projective as G1};
-    use ark_ec::CurveGroup;
+    use crate::serialization::MAX_VEC_LEN;
+    use ark_bn254::{G1Affine, G1Projective as G1};
     use ark_std::test_rng;
     use ark_std::UniformRand;
 
@@ -105,6 +1094,44 @@ mod tests {
         assert_eq!(points, recovered);
     }
 
+    /// A point of BN254's G2 curve outside its prime-order subgroup.
+    fn g2_point_outside_subgroup<R: ark_std::rand::Rng>(rng: &mut R) -> G2Affine {
+        loop {
+            let x = ark_bn254::Fq2::rand(rng);
+            if let Some(point) = G2Affine::get_point_from_x_unchecked(x, false) {
+                if !point.is_in_correct_subgroup_assuming_on_curve() {
+                    return point;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_point_validation() {
+        let mut rng = test_rng();
+        // Several chunks and a partial one
+        let points: Vec<G1Affine> = (0..2 * POINT_CHUNK + 3)
+            .map(|_| G1::rand(&mut rng).into_affine())
+            .collect();
+        let bytes = ark_vec_to_bytes(&points);
+        assert_eq!(ark_points_from_bytes(&bytes).unwrap(), points);
+        assert!(ark_points_from_bytes::<ark_bn254::g1::Config>(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut g2: Vec<G2Affine> = (0..3).map(|_| G2Affine::rand(&mut rng)).collect();
+        assert_eq!(validate_points(&g2), Ok(()));
+        g2[1] = g2_point_outside_subgroup(&mut rng);
+        let err = ark_points_from_bytes::<ark_bn254::g2::Config>(&ark_vec_to_bytes(&g2));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "element 1 is not in the prime-order subgroup"
+        );
+        let off_curve = G1Affine::new_unchecked(points[0].x, points[1].y);
+        assert_eq!(
+            validate_points(&[points[0], off_curve]),
+            Err(InvalidPoint::NotOnCurve(1))
+        );
+    }
+
     #[test]
     fn test_malformed_bytes_return_error() {
         let result: Result<Vec<Fr>, _> = ark_vec_from_bytes(&[0xff, 0xff]);
@@ -121,4 +1148,274 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("exceeds maximum"));
     }
+
+    #[test]
+    fn test_montgomery_encoding() {
+        use ark_ff::{One, Zero};
+
+        let mut rng = test_rng();
+        let mut scalars: Vec<Fr> = (0..10).map(|_| Fr::rand(&mut rng)).collect();
+        scalars.extend([Fr::zero(), Fr::one(), -Fr::one()]);
+        let bytes = ScalarEncoding::Montgomery.encode(&scalars);
+        assert_eq!(bytes.len(), ark_vec_to_bytes(&scalars).len());
+        assert_eq!(ScalarEncoding::Montgomery.decode(&bytes).unwrap(), scalars);
+
+        // Limbs at or above the modulus are refused, as are stray bytes
+        let mut unreduced = 1u64.to_le_bytes().to_vec();
+        for limb in Fr::MODULUS.0 {
+            unreduced.extend_from_slice(&limb.to_le_bytes());
+        }
+        let err = ScalarEncoding::Montgomery.decode(&unreduced).unwrap_err();
+        assert!(err.to_string().contains("modulus"), "{err}");
+        assert!(ScalarEncoding::Montgomery
+            .decode(&bytes[..bytes.len() - 1])
+            .is_err());
+        let mut huge = (MAX_VEC_LEN + 1).to_le_bytes().to_vec();
+        huge.extend_from_slice(&[0; 32]);
+        assert!(ScalarEncoding::Montgomery.decode(&huge).is_err());
+
+        for encoding in [ScalarEncoding::Canonical, ScalarEncoding::Montgomery] {
+            let value = encoding.header_value().as_bytes();
+            assert_eq!(ScalarEncoding::from_header_value(value), Some(encoding));
+        }
+    }
+
+    #[test]
+    fn test_bulk_scalar_decoding() {
+        let mut rng = test_rng();
+        // Several chunks and a partial one
+        let scalars: Vec<Fr> = (0..3 * DECODE_CHUNK + 5)
+            .map(|_| Fr::rand(&mut rng))
+            .collect();
+        for encoding in [ScalarEncoding::Canonical, ScalarEncoding::Montgomery] {
+            let bytes = encoding.encode(&scalars);
+            assert_eq!(encoding.decode(&bytes).unwrap(), scalars);
+        }
+        let bytes = ark_vec_to_bytes(&scalars);
+        assert_eq!(
+            ScalarEncoding::Canonical.decode(&bytes).unwrap(),
+            ark_vec_from_bytes::<Fr>(&bytes).unwrap()
+        );
+
+        // An unreduced scalar deep in the buffer is found and named
+        let mut unreduced = bytes.clone();
+        let at = 8 + 32 * (2 * DECODE_CHUNK + 1);
+        unreduced[at..at + 32].fill(0xff);
+        let err = ScalarEncoding::Canonical.decode(&unreduced).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("element {} is not below the modulus", 2 * DECODE_CHUNK + 1)
+        );
+        assert!(ScalarEncoding::Canonical
+            .decode(&bytes[..bytes.len() - 1])
+            .is_err());
+    }
+
+    #[test]
+    fn test_request_id_header() {
+        let id = RequestId::random();
+        let header = id.to_string();
+        assert_eq!(header.len(), 32);
+        assert_eq!(RequestId::from_header_value(header.as_bytes()), Some(id));
+        assert_eq!(
+            RequestId::from_header_value(header.to_uppercase().as_bytes()),
+            Some(id)
+        );
+        assert_eq!(RequestId::from_header_value(&header.as_bytes()[1..]), None);
+        assert_eq!(RequestId::from_header_value(&[b'+'; 32]), None);
+    }
+
+    #[test]
+    fn test_session_token_header() {
+        let token = SessionToken::random();
+        let header = token.to_string();
+        assert_eq!(header.len(), 64);
+        assert_eq!(SessionToken::from_header_value(header.as_bytes()), Some(token));
+        assert_eq!(SessionToken::from_header_value(&header.as_bytes()[2..]), None);
+        assert_ne!(token.hash(), SessionToken::random().hash());
+        assert_eq!(format!("{token:?}"), "SessionToken(..)");
+    }
+
+    #[test]
+    fn test_parallelism_hint_threads() {
+        assert_eq!(ParallelismHint::default().threads(16, 1000), 16);
+        let capped = ParallelismHint {
+            max_threads: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(capped.threads(16, 1000), 4);
+        assert_eq!(capped.threads(2, 1000), 2);
+
+        // 1000 ms on 16 threads is 4000 ms on 4
+        let relaxed = ParallelismHint {
+            target_latency_ms: Some(4000),
+            ..Default::default()
+        };
+        assert_eq!(relaxed.threads(16, 1000), 4);
+        assert_eq!(relaxed.threads(16, 0), 1);
+        let urgent = ParallelismHint {
+            target_latency_ms: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(urgent.threads(16, 1000), 16);
+    }
+
+    #[test]
+    fn test_domain_types_roundtrip() {
+        use ark_bn254::G2Projective as G2;
+
+        let mut rng = test_rng();
+        let mut scalars = |n: usize| (0..n).map(|_| Fr::rand(&mut rng)).collect::<Vec<_>>();
+        let request = EncryptedRequest {
+            v_h: scalars(4),
+            v_l: scalars(3),
+            v_a: scalars(2),
+            v_b_g1: scalars(2),
+            v_b_g2: scalars(1),
+        };
+
+        // Direct serde and the wire conversion agree with the original
+        let bytes = bincode::serialize(&request).unwrap();
+        let decoded: EncryptedRequest = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.v_h, request.v_h);
+        let wire = ProveRequest::from(&request);
+        let converted = EncryptedRequest::try_from(&wire).unwrap();
+        assert_eq!(converted.v_b_g2, request.v_b_g2);
+
+        let response = ServerResponse {
+            em_h: G1::rand(&mut rng),
+            em_l: G1::rand(&mut rng),
+            em_a: G1::rand(&mut rng),
+            em_b_g1: G1::rand(&mut rng),
+            em_b_g2: G2::rand(&mut rng),
+        };
+        let bytes = bincode::serialize(&response).unwrap();
+        let decoded: ServerResponse = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.em_b_g2, response.em_b_g2);
+        let wire = ProveResponse::new(&response, ProveMetadata::default());
+        let converted = ServerResponse::try_from(&wire).unwrap();
+        assert_eq!(converted.em_h, response.em_h);
+
+        let mut truncated = ProveRequest::from(&request);
+        truncated.v_l.pop();
+        assert!(EncryptedRequest::try_from(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_setup_patch() {
+        use ark_ec::AffineRepr;
+
+        let mut rng = test_rng();
+        let g1 = |n: usize, rng: &mut _| {
+            let points: Vec<G1Affine> = (0..n).map(|_| G1::rand(rng).into_affine()).collect();
+            ark_vec_to_bytes(&points)
+        };
+        let base = SetupRequest {
+            h_generators: g1(5, &mut rng),
+            l_generators: g1(2, &mut rng),
+            a_generators: g1(3, &mut rng),
+            b_g1_generators: g1(3, &mut rng),
+            b_g2_generators: ark_vec_to_bytes(&[G2Affine::generator(); 3]),
+            public_generators: Vec::new(),
+            key_fingerprint: None,
+        };
+        let sizes = generator_point_sizes();
+        let parts = [
+            &base.h_generators,
+            &base.l_generators,
+            &base.a_generators,
+            &base.b_g1_generators,
+            &base.b_g2_generators,
+        ];
+        let chunks = GeneratorChunks {
+            generators_hash: base.generators_hash(),
+            chunk_len: 2,
+            hashes: core::array::from_fn(|i| chunk_hashes(parts[i], sizes[i], 2).unwrap()),
+        };
+        assert_eq!(chunks.hashes[0].len(), 3);
+        assert!(SetupPatch::new(&chunks, &base).unwrap().chunks.is_empty());
+
+        // Only the chunk holding the changed point, and the one of the new
+        // points, are sent
+        let mut h_generators = base.h_generators.clone();
+        let changed = 8 + 3 * sizes[0];
+        h_generators[changed..changed + sizes[0]]
+            .copy_from_slice(&ark_to_bytes(&G1Affine::generator()));
+        let mut l_generators = g1(3, &mut rng);
+        l_generators[8..8 + 2 * sizes[1]].copy_from_slice(&base.l_generators[8..]);
+        let target = SetupRequest {
+            h_generators,
+            l_generators,
+            a_generators: base.a_generators.clone(),
+            b_g1_generators: base.b_g1_generators.clone(),
+            b_g2_generators: base.b_g2_generators.clone(),
+            public_generators: Vec::new(),
+            key_fingerprint: None,
+        };
+        let patch = SetupPatch::new(&chunks, &target).unwrap();
+        let sent: Vec<_> = patch.chunks.iter().map(|c| (c.kind, c.index)).collect();
+        assert_eq!(sent, [(MsmKind::H, 1), (MsmKind::L, 1)]);
+        assert_eq!(patch.lens, [5, 3, 3, 3, 3]);
+        assert_eq!(patch.points_len(), 3 * sizes[0]);
+        assert_eq!(patch.generators_hash, target.generators_hash());
+        assert!(patch.chunks.iter().all(GeneratorChunk::checksum_matches));
+    }
+
+    #[test]
+    fn test_setup_chunk_split() {
+        use ark_ec::AffineRepr;
+
+        let mut rng = test_rng();
+        let h: Vec<G1Affine> = (0..5).map(|_| G1::rand(&mut rng).into_affine()).collect();
+        let request = SetupRequest {
+            h_generators: ark_vec_to_bytes(&h),
+            l_generators: ark_vec_to_bytes::<G1Affine>(&[]),
+            a_generators: ark_vec_to_bytes(&h[..1]),
+            b_g1_generators: ark_vec_to_bytes(&h[..1]),
+            b_g2_generators: ark_vec_to_bytes(&[G2Affine::generator(); 3]),
+            public_generators: vec![1, 2, 3],
+            key_fingerprint: Some([7; 32]),
+        };
+        let sizes = generator_point_sizes();
+
+        // Two G2 points per chunk, and no more than a G2 chunk per part
+        let parts: Vec<_> = SetupChunk::split(&request, 2 * sizes[4]).unwrap().collect();
+        let chunks: Vec<_> = parts
+            .iter()
+            .map(|part| {
+                let chunks = part.chunks.iter();
+                chunks.map(|c| (c.kind, c.index)).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                vec![(MsmKind::H, 0), (MsmKind::H, 1)],
+                vec![(MsmKind::H, 2), (MsmKind::A, 0), (MsmKind::BG1, 0)],
+                vec![(MsmKind::BG2, 0)],
+                vec![(MsmKind::BG2, 1)],
+            ]
+        );
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.generators_hash, request.generators_hash());
+            assert_eq!((part.chunk_len, part.lens), (2, [5, 0, 1, 1, 3]));
+            let last = i == parts.len() - 1;
+            assert_eq!(part.public_generators.is_empty(), !last);
+            assert_eq!(part.key_fingerprint.is_some(), last);
+        }
+        let h_points: Vec<u8> = parts
+            .iter()
+            .flat_map(|part| &part.chunks)
+            .filter(|c| c.kind == MsmKind::H)
+            .flat_map(|c| c.points.clone())
+            .collect();
+        assert_eq!(h_points, request.h_generators[8..]);
+        let mut chunk = parts[0].chunks[0].clone();
+        assert!(chunk.checksum_matches());
+        chunk.points[0] ^= 1;
+        assert!(!chunk.checksum_matches());
+
+        // A part holds at least one chunk, however small the bound
+        assert_eq!(SetupChunk::split(&request, 1).unwrap().len(), 10);
+    }
 }
@@ -0,0 +1,262 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::messages::MsmKind;
+
+/// Compute-unit weight of one G1 MSM term.
+pub const G1_WEIGHT: u64 = 1;
+
+/// Compute-unit weight of one G2 MSM term. G2 arithmetic over F_p^2 costs roughly
+/// three times as much as G1.
+pub const G2_WEIGHT: u64 = 3;
+
+/// Compute units for one prove request, given the lengths of the four G1 MSMs
+/// (h, l, a, b_g1) and the G2 MSM (b_g2).
+pub fn compute_units(g1_sizes: [usize; 4], g2_size: usize) -> u64 {
+    let g1: u64 = g1_sizes.iter().map(|&n| n as u64).sum();
+    g1 * G1_WEIGHT + g2_size as u64 * G2_WEIGHT
+}
+
+/// Accumulated usage for a session or API key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Usage {
+    pub proves: u64,
+    pub compute_units: u64,
+    /// Scalar-generator terms of the MSMs evaluated.
+    #[serde(default)]
+    pub msm_elements: u64,
+    /// Time spent evaluating MSMs, in ms.
+    #[serde(default)]
+    pub compute_ms: u64,
+}
+
+/// A metered prove request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MeteringEvent {
+    /// Tenant the session belongs to, on a server with tenants.
+    pub tenant: Option<String>,
+    pub session_id: String,
+    /// API key the session was set up with, if the server gates setup by API key.
+    pub account: Option<String>,
+    pub compute_units: u64,
+    /// Scalar-generator terms of the MSMs evaluated.
+    pub msm_elements: u64,
+    /// Time the MSMs took, in ms.
+    pub compute_ms: u64,
+    /// The MSM of a split prove request (`POST /msm`), or `None` for a full prove.
+    /// Usage counts a split prove once, on its H sub-request.
+    pub msm: Option<MsmKind>,
+}
+
+/// Usage of one live session, with how long it has been idle, for the admin API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SessionStats {
+    /// Tenant the session belongs to, on a server with tenants.
+    pub tenant: Option<String>,
+    pub session_id: String,
+    pub usage: Usage,
+    /// Time since the session's last setup, prove or keepalive, in ms.
+    pub idle_ms: u64,
+}
+
+/// `stats` in the Prometheus text format, one series per session, labelled
+/// with its `tenant` (empty without tenants) and `session`.
+pub fn prometheus_text(stats: &[SessionStats]) -> String {
+    let mut text = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, value: fn(&SessionStats) -> String| {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        for session in stats {
+            let tenant = escape_label(session.tenant.as_deref().unwrap_or_default());
+            let id = escape_label(&session.session_id);
+            let value = value(session);
+            let _ = writeln!(
+                text,
+                "{name}{{tenant=\"{tenant}\",session=\"{id}\"}} {value}"
+            );
+        }
+    };
+    family(
+        "stealthsnark_session_proves_total",
+        "counter",
+        "Prove requests served.",
+        |s| s.usage.proves.to_string(),
+    );
+    family(
+        "stealthsnark_session_compute_units_total",
+        "counter",
+        "Compute units of the prove requests served.",
+        |s| s.usage.compute_units.to_string(),
+    );
+    family(
+        "stealthsnark_session_msm_elements_total",
+        "counter",
+        "Scalar-generator terms of the MSMs evaluated.",
+        |s| s.usage.msm_elements.to_string(),
+    );
+    family(
+        "stealthsnark_session_compute_seconds_total",
+        "counter",
+        "Time spent evaluating MSMs.",
+        |s| (s.usage.compute_ms as f64 / 1000.0).to_string(),
+    );
+    family(
+        "stealthsnark_session_idle_seconds",
+        "gauge",
+        "Time since the last setup, prove or keepalive.",
+        |s| (s.idle_ms as f64 / 1000.0).to_string(),
+    );
+    text
+}
+
+/// A label value with `\`, `"` and newlines escaped, as the text format wants.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Called for every served prove request, e.g. to forward usage to a billing system.
+pub trait MeteringHook: Send + Sync {
+    fn on_prove(&self, event: &MeteringEvent);
+}
+
+/// In-memory usage totals per session and per API key.
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    /// By tenant and session ID.
+    sessions: Mutex<HashMap<(Option<String>, String), Usage>>,
+    accounts: Mutex<HashMap<String, Usage>>,
+}
+
+impl UsageLedger {
+    pub fn record(&self, event: &MeteringEvent) {
+        let proves = matches!(event.msm, None | Some(MsmKind::H)) as u64;
+        let session = (event.tenant.clone(), event.session_id.clone());
+        add(&self.sessions, session, proves, event);
+        if let Some(account) = &event.account {
+            add(&self.accounts, account.clone(), proves, event);
+        }
+    }
+
+    /// Usage of the session `session_id` of `tenant`, or of the server's only
+    /// namespace for `None`.
+    pub fn session(&self, tenant: Option<&str>, session_id: &str) -> Usage {
+        let key = (tenant.map(String::from), session_id.to_string());
+        get(&self.sessions, &key)
+    }
+
+    pub fn account(&self, account: &str) -> Usage {
+        get(&self.accounts, account)
+    }
+}
+
+fn add<K: Eq + Hash>(map: &Mutex<HashMap<K, Usage>>, key: K, proves: u64, event: &MeteringEvent) {
+    let mut map = map.lock().unwrap();
+    let usage = map.entry(key).or_default();
+    usage.proves += proves;
+    usage.compute_units += event.compute_units;
+    usage.msm_elements += event.msm_elements;
+    usage.compute_ms += event.compute_ms;
+}
+
+fn get<K: Eq + Hash + Borrow<Q>, Q: Eq + Hash + ?Sized>(
+    map: &Mutex<HashMap<K, Usage>>,
+    key: &Q,
+) -> Usage {
+    map.lock().unwrap().get(key).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_units_weights_g2() {
+        assert_eq!(compute_units([4, 3, 3, 3], 3), 13 + 9);
+    }
+
+    #[test]
+    fn test_ledger_aggregates_per_session_and_account() {
+        let ledger = UsageLedger::default();
+        for (session, account) in [("s1", Some("k")), ("s1", Some("k")), ("s2", None)] {
+            ledger.record(&MeteringEvent {
+                tenant: None,
+                session_id: session.to_string(),
+                account: account.map(String::from),
+                compute_units: 10,
+                msm_elements: 8,
+                compute_ms: 5,
+                msm: None,
+            });
+        }
+        assert_eq!(
+            ledger.session(None, "s1"),
+            Usage {
+                proves: 2,
+                compute_units: 20,
+                msm_elements: 16,
+                compute_ms: 10,
+            }
+        );
+        assert_eq!(ledger.session(None, "s2").proves, 1);
+        assert_eq!(ledger.account("k").compute_units, 20);
+        assert_eq!(ledger.account("other"), Usage::default());
+    }
+
+    #[test]
+    fn test_split_prove_counted_once() {
+        let ledger = UsageLedger::default();
+        for msm in MsmKind::ALL {
+            ledger.record(&MeteringEvent {
+                tenant: None,
+                session_id: "s".to_string(),
+                account: None,
+                compute_units: 4,
+                msm_elements: 4,
+                compute_ms: 1,
+                msm: Some(msm),
+            });
+        }
+        assert_eq!(
+            ledger.session(None, "s"),
+            Usage {
+                proves: 1,
+                compute_units: 20,
+                msm_elements: 20,
+                compute_ms: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let stats = [SessionStats {
+            tenant: None,
+            session_id: "a\"b".to_string(),
+            usage: Usage {
+                proves: 2,
+                compute_units: 30,
+                msm_elements: 24,
+                compute_ms: 1500,
+            },
+            idle_ms: 250,
+        }];
+        let text = prometheus_text(&stats);
+        assert!(text.contains("# TYPE stealthsnark_session_proves_total counter\n"));
+        let series =
+            |name: &str, value: &str| format!("{name}{{tenant=\"\",session=\"a\\\"b\"}} {value}\n");
+        assert!(text.contains(&series("stealthsnark_session_proves_total", "2")));
+        assert!(text.contains(&series("stealthsnark_session_compute_seconds_total", "1.5")));
+        assert!(text.contains(&series("stealthsnark_session_idle_seconds", "0.25")));
+        assert_eq!(text.lines().count(), 5 * 3);
+        assert_eq!(prometheus_text(&[]).lines().count(), 5 * 2);
+    }
+}
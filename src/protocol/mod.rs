@@ -1,3 +1,20 @@
+pub mod attestation;
+pub mod audit;
+pub mod codec;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod gate;
+pub mod load;
 pub mod messages;
+pub mod metering;
+pub mod openapi;
+pub mod policy;
+pub mod problem;
+pub mod proof_cache;
+pub mod prove_cache;
+pub mod scheduler;
 pub mod server;
+pub mod store;
+pub mod tenant;
+pub mod transport;
 pub mod client;
@@ -0,0 +1,237 @@
+use utoipa::openapi::path::{ParameterBuilder, ParameterIn};
+use utoipa::openapi::schema::{ObjectBuilder, Type};
+use utoipa::openapi::Required;
+use utoipa::{Modify, OpenApi};
+
+use super::gate::{ADMIN_KEY_HEADER, API_KEY_HEADER, POW_HEADER};
+use super::messages::{
+    EvalRequest, GeneratorChunk, GeneratorSetRequest, MaliciousEvalRequest, ProveRequest,
+    SetupChunk, SetupPatch, SetupRequest, DEADLINE_HEADER, SESSION_TOKEN_HEADER,
+};
+use super::server::{
+    __path_handle_account_usage, __path_handle_admin_metrics, __path_handle_admin_sessions,
+    __path_handle_attestation, __path_handle_capabilities, __path_handle_commitment,
+    __path_handle_default_commitment, __path_handle_emsm_eval, __path_handle_emsm_eval_malicious,
+    __path_handle_emsm_setup, __path_handle_estimate, __path_handle_fft,
+    __path_handle_generator_chunks, __path_handle_keepalive, __path_handle_msm,
+    __path_handle_prove, __path_handle_session_usage, __path_handle_setup,
+    __path_handle_setup_chunk, __path_handle_setup_patch,
+};
+
+/// OpenAPI document of the routes of `create_router`, generated from their
+/// annotations in `server.rs`. `cargo run --bin openapi` prints it as JSON.
+///
+/// Schemas describe the JSON form of each body. Bodies sent as bincode have the
+/// same fields, laid out as in `docs/wire-format.md`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "stealthsnark EMSM server",
+        description = "Evaluates the masked MSMs of server-aided Groth16 proofs."
+    ),
+    paths(
+        handle_capabilities,
+        handle_attestation,
+        handle_setup,
+        handle_setup_patch,
+        handle_setup_chunk,
+        handle_prove,
+        handle_msm,
+        handle_emsm_setup,
+        handle_emsm_eval,
+        handle_emsm_eval_malicious,
+        handle_fft,
+        handle_keepalive,
+        handle_estimate,
+        handle_account_usage,
+        handle_session_usage,
+        handle_default_commitment,
+        handle_commitment,
+        handle_generator_chunks,
+        handle_admin_sessions,
+        handle_admin_metrics,
+    ),
+    // Only referenced from the generic envelopes
+    components(schemas(
+        SetupRequest,
+        SetupPatch,
+        SetupChunk,
+        GeneratorChunk,
+        ProveRequest,
+        GeneratorSetRequest,
+        EvalRequest,
+        MaliciousEvalRequest
+    )),
+    modifiers(&HeaderParams)
+)]
+pub struct ApiDoc;
+
+const SETUP_PATHS: &[&str] = &["/setup", "/setup/patch", "/setup/chunk", "/emsm/setup"];
+const EVAL_PATHS: &[&str] = &["/prove", "/msm", "/emsm/eval", "/emsm/eval/malicious"];
+const SESSION_PATHS: &[&str] = &["/keepalive", "/fft"];
+const READ_PATHS: &[&str] = &[
+    "/commitment/{session_id}",
+    "/commitment/{session_id}/{circuit}",
+    "/commitment/{session_id}/{circuit}/chunks",
+];
+
+/// Header parameters shared across routes, documented under the names of the
+/// constants the server reads them by: `(header, required, description, paths)`.
+const HEADER_PARAMS: &[(&str, bool, &str, &[&[&str]])] = &[
+    (
+        API_KEY_HEADER,
+        false,
+        "When gated by API keys, and the API key of the tenant, if any",
+        &[SETUP_PATHS],
+    ),
+    (
+        API_KEY_HEADER,
+        false,
+        "API key of the tenant, if any",
+        &[EVAL_PATHS, &["/fft"], READ_PATHS],
+    ),
+    (
+        API_KEY_HEADER,
+        false,
+        "API key the session was set up with, or of its tenant",
+        &[&["/keepalive", "/usage/{session_id}"]],
+    ),
+    (API_KEY_HEADER, true, "API key to report on", &[&["/usage"]]),
+    (
+        POW_HEADER,
+        false,
+        "`<session_id>:<timestamp>:<nonce>`, the timestamp in Unix seconds, when gated by \
+         proof of work",
+        &[SETUP_PATHS],
+    ),
+    (
+        SESSION_TOKEN_HEADER,
+        false,
+        "Token returned by the session's first setup, to set the session up again",
+        &[SETUP_PATHS],
+    ),
+    (
+        SESSION_TOKEN_HEADER,
+        false,
+        "Token returned by the session's first setup, required if it returned one",
+        &[EVAL_PATHS, SESSION_PATHS],
+    ),
+    (
+        DEADLINE_HEADER,
+        false,
+        "Milliseconds after receiving the request to give up on it",
+        &[EVAL_PATHS],
+    ),
+    (
+        ADMIN_KEY_HEADER,
+        true,
+        "`ServerConfig::admin_key`",
+        &[&["/admin/sessions", "/admin/metrics"]],
+    ),
+];
+
+/// Adds `HEADER_PARAMS` to the operations of their paths, after the parameters
+/// of the route annotations, whose names `utoipa` only takes as literals.
+struct HeaderParams;
+
+impl Modify for HeaderParams {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for &(header, required, description, paths) in HEADER_PARAMS {
+            let required = if required {
+                Required::True
+            } else {
+                Required::False
+            };
+            for path in paths.iter().copied().flatten() {
+                let item = openapi
+                    .paths
+                    .paths
+                    .get_mut(*path)
+                    .unwrap_or_else(|| panic!("{path} is not documented"));
+                for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                    let parameter = ParameterBuilder::new()
+                        .name(header)
+                        .parameter_in(ParameterIn::Header)
+                        .required(required.clone())
+                        .description(Some(description))
+                        .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+                        .build();
+                    operation
+                        .parameters
+                        .get_or_insert_with(Vec::new)
+                        .push(parameter);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_document() {
+        let doc = ApiDoc::openapi();
+        let paths: Vec<&str> = doc.paths.paths.keys().map(String::as_str).collect();
+        for path in [
+            "/capabilities",
+            "/attestation",
+            "/setup",
+            "/setup/patch",
+            "/prove",
+            "/msm",
+            "/emsm/setup",
+            "/emsm/eval",
+            "/emsm/eval/malicious",
+            "/fft",
+            "/keepalive",
+            "/estimate",
+            "/usage",
+            "/usage/{session_id}",
+            "/commitment/{session_id}",
+            "/commitment/{session_id}/{circuit}",
+            "/commitment/{session_id}/{circuit}/chunks",
+            "/admin/sessions",
+            "/admin/metrics",
+        ] {
+            assert!(paths.contains(&path), "{path} is not documented");
+        }
+
+        // Every schema referenced is defined
+        let schemas = &doc.components.as_ref().unwrap().schemas;
+        let json = doc.to_json().unwrap();
+        for reference in json.split("\"#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            assert!(schemas.contains_key(name), "no schema for {name}");
+        }
+        assert!(json.contains("application/problem+json"));
+
+        // Shared headers are documented under the names the server reads
+        let headers = |path: &str| -> Vec<String> {
+            let item = doc.paths.get_path_item(path).unwrap();
+            let operation = item.post.as_ref().or(item.get.as_ref()).unwrap();
+            let parameters = operation.parameters.iter().flatten();
+            parameters
+                .filter(|p| p.parameter_in == ParameterIn::Header)
+                .map(|p| p.name.clone())
+                .collect()
+        };
+        for path in SETUP_PATHS {
+            let headers = headers(path);
+            for header in [API_KEY_HEADER, POW_HEADER, SESSION_TOKEN_HEADER] {
+                assert!(headers.iter().any(|h| h == header), "{path} lacks {header}");
+            }
+        }
+        for path in EVAL_PATHS {
+            let headers = headers(path);
+            for header in [API_KEY_HEADER, SESSION_TOKEN_HEADER, DEADLINE_HEADER] {
+                assert!(headers.iter().any(|h| h == header), "{path} lacks {header}");
+            }
+        }
+        assert!(headers("/admin/metrics")
+            .iter()
+            .any(|h| h == ADMIN_KEY_HEADER));
+        assert!(json.contains("<session_id>:<timestamp>:<nonce>"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::emsm::params::SecurityLevel;
+use crate::groth16::server_aided::{ProvingMode, ServerAidedProvingKey};
+
+/// What a client must see before it delegates anything, checked by `EmsmClient`
+/// once configured with `with_security_policy`. The default only enforces the
+/// default security level.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SecurityPolicy {
+    /// Lowest LPN security level any MSM of the key may be masked at.
+    pub min_security: SecurityLevel,
+    /// Only prove in `ProvingMode::Malicious`, which detects a wrong result with
+    /// overwhelming probability.
+    pub require_malicious: bool,
+    /// Most witnesses any one `TOperator` of the key may mask, counting the
+    /// requests a proof is about to send. The LPN security estimates assume a
+    /// bounded number of samples under the same code.
+    pub max_queries_per_operator: Option<u64>,
+    /// Refuse servers reached over plain `http://`.
+    pub require_tls: bool,
+    /// Refuse servers not checked with an `AttestationPolicy`.
+    pub require_attestation: bool,
+}
+
+/// A delegation the `SecurityPolicy` forbids. Nothing has been sent when it is
+/// returned.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum PolicyViolation {
+    #[error("{msm} MSM is masked at {actual} bits, below the required {required} bits")]
+    SecurityLevel {
+        msm: &'static str,
+        actual: u32,
+        required: u32,
+    },
+    #[error("proving mode {0:?} does not detect a cheating server; the policy requires Malicious")]
+    ModeNotMalicious(ProvingMode),
+    #[error("{msm} MSM would exceed its budget of {max} queries ({used} used, {requested} more)")]
+    QueryBudgetExhausted {
+        msm: &'static str,
+        used: u64,
+        requested: u64,
+        max: u64,
+    },
+    #[error("{0} is not an https:// URL")]
+    PlaintextTransport(String),
+    #[error("the policy requires an attested server; configure EmsmClient::with_attestation")]
+    AttestationRequired,
+}
+
+impl SecurityPolicy {
+    /// Require 128-bit masking, malicious mode, TLS and attestation.
+    pub fn strict() -> Self {
+        Self {
+            min_security: SecurityLevel::Bits128,
+            require_malicious: true,
+            max_queries_per_operator: None,
+            require_tls: true,
+            require_attestation: true,
+        }
+    }
+
+    /// Check the server at `base_url`, attested or not, before sending it
+    /// anything.
+    pub fn check_server(&self, base_url: &str, attested: bool) -> Result<(), PolicyViolation> {
+        if self.require_tls && !base_url.starts_with("https://") {
+            return Err(PolicyViolation::PlaintextTransport(base_url.to_string()));
+        }
+        if self.require_attestation && !attested {
+            return Err(PolicyViolation::AttestationRequired);
+        }
+        Ok(())
+    }
+
+    /// Check a proof with `sapk` under `mode` before masking its witness.
+    pub fn check_prove(
+        &self,
+        sapk: &ServerAidedProvingKey,
+        mode: ProvingMode,
+    ) -> Result<(), PolicyViolation> {
+        if self.require_malicious && mode != ProvingMode::Malicious {
+            return Err(PolicyViolation::ModeNotMalicious(mode));
+        }
+        let requested = max_requests(mode);
+        let msms = [
+            ("h", sapk.emsm_h.security, sapk.emsm_h.queries()),
+            ("l", sapk.emsm_l.security, sapk.emsm_l.queries()),
+            ("a", sapk.emsm_a.security, sapk.emsm_a.queries()),
+            ("b_g1", sapk.emsm_b_g1.security, sapk.emsm_b_g1.queries()),
+            ("b_g2", sapk.emsm_b_g2.security, sapk.emsm_b_g2.queries()),
+        ];
+        // MSMs computed locally mask nothing
+        let delegated = sapk.delegation.msms();
+        for ((msm, security, used), _) in msms.into_iter().zip(delegated).filter(|(_, d)| *d) {
+            if security.bits() < self.min_security.bits() {
+                return Err(PolicyViolation::SecurityLevel {
+                    msm,
+                    actual: security.bits(),
+                    required: self.min_security.bits(),
+                });
+            }
+            if let Some(max) = self.max_queries_per_operator {
+                if used + requested > max {
+                    return Err(PolicyViolation::QueryBudgetExhausted {
+                        msm,
+                        used,
+                        requested,
+                        max,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Most masked witnesses a proof under `mode` sends per MSM.
+fn max_requests(mode: ProvingMode) -> u64 {
+    match mode {
+        ProvingMode::SemiHonest => 1,
+        ProvingMode::Malicious | ProvingMode::Covert { .. } => 2,
+        ProvingMode::CutAndChoose { queries } => queries as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groth16::circuit::CubeCircuit;
+    use crate::groth16::server_aided::client_encrypt;
+    use ark_bn254::{Bn254, Fr};
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_security_policy() {
+        let mut rng = ChaCha20Rng::seed_from_u64(44);
+        let (pk, _) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+
+        let strict = SecurityPolicy::strict();
+        assert_eq!(
+            strict.check_server("http://localhost:3000", true),
+            Err(PolicyViolation::PlaintextTransport(
+                "http://localhost:3000".to_string()
+            ))
+        );
+        assert_eq!(
+            strict.check_server("https://prover.example", false),
+            Err(PolicyViolation::AttestationRequired)
+        );
+        assert!(strict.check_server("https://prover.example", true).is_ok());
+        assert!(matches!(
+            strict.check_prove(&sapk, ProvingMode::Malicious),
+            Err(PolicyViolation::SecurityLevel { actual: 100, .. })
+        ));
+        assert!(matches!(
+            SecurityPolicy {
+                require_malicious: true,
+                ..Default::default()
+            }
+            .check_prove(&sapk, ProvingMode::Covert { deterrence: 0.5 }),
+            Err(PolicyViolation::ModeNotMalicious(_))
+        ));
+
+        let budget = SecurityPolicy {
+            max_queries_per_operator: Some(2),
+            ..Default::default()
+        };
+        assert!(budget.check_prove(&sapk, ProvingMode::Malicious).is_ok());
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        assert!(budget.check_prove(&sapk, ProvingMode::SemiHonest).is_ok());
+        assert_eq!(
+            budget.check_prove(&sapk, ProvingMode::Malicious),
+            Err(PolicyViolation::QueryBudgetExhausted {
+                msm: "h",
+                used: 1,
+                requested: 2,
+                max: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Media type of the error bodies of POST /setup, /prove and /msm (RFC 7807).
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Why the server refused a setup or prove request, as the `code` member of its
+/// problem body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// 401: the setup gate wants a credential and none was sent.
+    CredentialMissing,
+    /// 403: the setup credential is unknown, or its proof of work is invalid or
+    /// for another session.
+    CredentialRejected,
+    /// 403: the session was set up with another API key.
+    SessionForbidden,
+    /// 403: the request does not carry the session's token.
+    SessionTokenRejected,
+    /// 413: the body is larger than the server accepts.
+    BodyTooLarge,
+    /// 415: the `Content-Type` names no codec the server supports.
+    UnsupportedMediaType,
+    /// 400: the envelope or the `field` of its request failed to decode.
+    Malformed,
+    /// 400: the circuit name is empty or longer than 128 bytes.
+    InvalidCircuitName,
+    /// 422: the request is for a curve the server or session does not use.
+    CurveMismatch,
+    /// 412: the session was never set up, or expired.
+    UnknownSession,
+    /// 412: the session has no circuit of that name.
+    UnknownCircuit,
+    /// 412: `MsmKind::Public` for a circuit set up without public generators.
+    NoPublicGenerators,
+    /// 409: the circuit was set up again with other generators.
+    GeneratorsMismatch,
+    /// 400: the `field` vector has `actual` scalars for `expected` generators.
+    LengthMismatch,
+    /// 408: evaluating the request ran past the server's timeout or the
+    /// client's `DEADLINE_HEADER`.
+    Timeout,
+    /// 429: the request would take the tenant past one of its limits.
+    TenantLimitExceeded,
+    /// 429: the server has as many requests queued as it accepts; retry after
+    /// `retry_after` seconds.
+    Overloaded,
+    /// 500: the server failed on its own.
+    Internal,
+    /// A code this client does not know, from a newer server.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::CredentialMissing => StatusCode::UNAUTHORIZED,
+            ErrorCode::CredentialRejected
+            | ErrorCode::SessionForbidden
+            | ErrorCode::SessionTokenRejected => StatusCode::FORBIDDEN,
+            ErrorCode::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::Malformed | ErrorCode::InvalidCircuitName | ErrorCode::LengthMismatch => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::CurveMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::UnknownSession
+            | ErrorCode::UnknownCircuit
+            | ErrorCode::NoPublicGenerators => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::GeneratorsMismatch => StatusCode::CONFLICT,
+            ErrorCode::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorCode::TenantLimitExceeded | ErrorCode::Overloaded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Internal | ErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ErrorCode::CredentialMissing => "setup credential missing",
+            ErrorCode::CredentialRejected => "setup credential rejected",
+            ErrorCode::SessionForbidden => "session belongs to another account",
+            ErrorCode::SessionTokenRejected => "session token missing or wrong",
+            ErrorCode::BodyTooLarge => "body too large",
+            ErrorCode::UnsupportedMediaType => "unsupported media type",
+            ErrorCode::Malformed => "malformed request",
+            ErrorCode::InvalidCircuitName => "invalid circuit name",
+            ErrorCode::CurveMismatch => "curve mismatch",
+            ErrorCode::UnknownSession => "unknown session",
+            ErrorCode::UnknownCircuit => "unknown circuit",
+            ErrorCode::NoPublicGenerators => "no public generators",
+            ErrorCode::GeneratorsMismatch => "generators hash mismatch",
+            ErrorCode::LengthMismatch => "vector length mismatch",
+            ErrorCode::Timeout => "prove timed out",
+            ErrorCode::TenantLimitExceeded => "tenant limit exceeded",
+            ErrorCode::Overloaded => "server overloaded",
+            ErrorCode::Internal => "internal server error",
+            ErrorCode::Unknown => "unknown error",
+        }
+    }
+}
+
+/// An RFC 7807 problem body, extended with the error code, the offending field,
+/// for length mismatches the sizes involved and, for overload, when to retry.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Problem {
+    /// `urn:stealthsnark:problem:<code>`.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub code: ErrorCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<u64>,
+    /// Seconds to wait before retrying, also sent as `Retry-After`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// For a timeout, the MSM elements evaluated before it, out of `expected`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+impl Problem {
+    pub fn new(code: ErrorCode) -> Self {
+        let code_name = serde_json::to_value(code)
+            .ok()
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_default();
+        Self {
+            problem_type: format!("urn:stealthsnark:problem:{code_name}"),
+            title: code.title().to_string(),
+            status: code.status().as_u16(),
+            detail: None,
+            code,
+            field: None,
+            expected: None,
+            actual: None,
+            retry_after: None,
+            completed: None,
+        }
+    }
+
+    /// `ErrorCode::Malformed` for `field`.
+    pub fn malformed(field: &str) -> Self {
+        Self::new(ErrorCode::Malformed).with_field(field)
+    }
+
+    /// `ErrorCode::LengthMismatch` for the `field` vector.
+    pub fn length_mismatch(field: &str, expected: usize, actual: usize) -> Self {
+        Self {
+            expected: Some(expected as u64),
+            actual: Some(actual as u64),
+            ..Self::new(ErrorCode::LengthMismatch).with_field(field)
+        }
+    }
+
+    pub fn with_field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// `completed` of the request's `total` MSM elements were evaluated.
+    pub fn with_progress(mut self, completed: u64, total: u64) -> Self {
+        self.completed = Some(completed);
+        self.expected = Some(total);
+        self
+    }
+}
+
+impl std::fmt::Display for Problem {
+    /// `412 unknown session`, then the field, sizes, progress and detail that
+    /// are set.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status, self.title)?;
+        if let Some(field) = &self.field {
+            write!(f, " ({field})")?;
+        }
+        if let (Some(expected), Some(actual)) = (self.expected, self.actual) {
+            write!(f, ": expected {expected}, got {actual}")?;
+        }
+        if let (Some(completed), Some(total)) = (self.completed, self.expected) {
+            write!(f, ": {completed} of {total} MSM elements evaluated")?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, ": {detail}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problem {}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_vec(&self).expect("a problem always serializes");
+        let mut response =
+            (status, [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)], body).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem_json() {
+        let problem = Problem::length_mismatch("v_h", 4, 3);
+        let json = serde_json::to_value(&problem).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "urn:stealthsnark:problem:length_mismatch",
+                "title": "vector length mismatch",
+                "status": 400,
+                "code": "length_mismatch",
+                "field": "v_h",
+                "expected": 4,
+                "actual": 3,
+            })
+        );
+        assert_eq!(serde_json::from_value::<Problem>(json).unwrap(), problem);
+        assert_eq!(
+            problem.to_string(),
+            "400 vector length mismatch (v_h): expected 4, got 3"
+        );
+
+        // Codes from newer servers still parse
+        let newer: Problem = serde_json::from_str(
+            r#"{"type":"about:blank","title":"t","status":429,"code":"rate_limited"}"#,
+        )
+        .unwrap();
+        assert_eq!(newer.code, ErrorCode::Unknown);
+        assert_eq!(newer.detail, None);
+
+        let response = Problem::new(ErrorCode::Overloaded)
+            .with_retry_after(3)
+            .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "3");
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use ark_bn254::{Bn254, Fr};
+use ark_groth16::{Groth16, Proof};
+use ark_std::rand::{CryptoRng, Rng};
+use sha2::{Digest, Sha256};
+
+use super::messages::ark_to_bytes;
+use crate::groth16::bundle::vk_hash;
+use crate::groth16::server_aided::{ServerAidedProvingKey, SynthesizedWitness};
+
+/// Identifies a statement and the witness it was proven with: equal keys prove
+/// the same thing, so one proof serves both.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProofKey {
+    /// `vk_hash` of the circuit's verifying key.
+    pub circuit_hash: [u8; 32],
+    pub public_inputs: Vec<Fr>,
+    /// SHA-256 of the compressed private witness.
+    pub witness_commitment: [u8; 32],
+}
+
+impl ProofKey {
+    pub fn new(sapk: &ServerAidedProvingKey, witness: &SynthesizedWitness) -> Self {
+        let (instance, private) = witness
+            .full_assignment
+            .split_at(witness.num_instance_variables);
+        Self {
+            circuit_hash: vk_hash(&sapk.pk.vk),
+            public_inputs: instance[1..].to_vec(),
+            witness_commitment: Sha256::digest(ark_to_bytes(&private)).into(),
+        }
+    }
+}
+
+/// Completed proofs by `ProofKey`, for `EmsmClient::with_proof_cache`. An
+/// application that proves the same statement over and over (e.g. to
+/// re-authenticate a session) gets the proof back without delegating again. The
+/// oldest proof is evicted once `capacity` are held.
+///
+/// Proofs are handed out rerandomized, so repeated answers to one statement are
+/// not linkable by their bytes. The cache holds the proofs, not the witnesses.
+pub struct ProofCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    proofs: HashMap<ProofKey, Proof<Bn254>>,
+    /// Keys in insertion order, oldest first.
+    order: VecDeque<ProofKey>,
+}
+
+impl ProofCache {
+    /// Hold at most `capacity` proofs.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The proof stored for `key`, rerandomized with `rng`.
+    pub fn get<R: Rng + CryptoRng>(
+        &self,
+        sapk: &ServerAidedProvingKey,
+        key: &ProofKey,
+        rng: &mut R,
+    ) -> Option<Proof<Bn254>> {
+        let proof = self.entries.lock().unwrap().proofs.get(key)?.clone();
+        Some(Groth16::<Bn254>::rerandomize_proof(
+            &sapk.pk.vk,
+            &proof,
+            rng,
+        ))
+    }
+
+    pub fn insert(&self, key: ProofKey, proof: Proof<Bn254>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.proofs.insert(key.clone(), proof).is_some() {
+            return;
+        }
+        entries.order.push_back(key);
+        while entries.order.len() > self.capacity {
+            let oldest = entries.order.pop_front().expect("over capacity");
+            entries.proofs.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().proofs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emsm::cancel::CancelToken;
+    use crate::groth16::circuit::CubeCircuit;
+    use crate::groth16::server_aided::{
+        client_decrypt, client_encrypt_with_h, client_synthesize, server_evaluate,
+    };
+    use ark_groth16::Groth16;
+    use ark_snark::SNARK;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_proof_cache() {
+        let mut rng = ChaCha20Rng::seed_from_u64(45);
+        let (pk, vk) =
+            Groth16::<Bn254>::circuit_specific_setup(CubeCircuit::<Fr> { x: None }, &mut rng)
+                .unwrap();
+        let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+        let synthesize = |x: u64| {
+            let circuit = CubeCircuit {
+                x: Some(Fr::from(x)),
+            };
+            client_synthesize(&sapk, circuit, &CancelToken::default()).unwrap()
+        };
+
+        let (witness, h) = synthesize(3);
+        let key = ProofKey::new(&sapk, &witness);
+        assert_eq!(key, ProofKey::new(&sapk, &synthesize(3).0));
+        assert_ne!(key, ProofKey::new(&sapk, &synthesize(4).0));
+        assert_eq!(key.public_inputs, [Fr::from(35u64)]);
+
+        let cache = ProofCache::new(1);
+        assert!(cache.get(&sapk, &key, &mut rng).is_none());
+        let (request, state) = client_encrypt_with_h(&sapk, witness, h, &mut rng).unwrap();
+        let response = server_evaluate(&sapk, &request).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        cache.insert(key.clone(), proof.clone());
+
+        let cached = cache.get(&sapk, &key, &mut rng).unwrap();
+        assert_ne!(cached, proof);
+        assert!(Groth16::<Bn254>::verify(&vk, &key.public_inputs, &cached).unwrap());
+
+        // The oldest proof makes room for the next
+        let other = ProofKey::new(&sapk, &synthesize(4).0);
+        cache.insert(other.clone(), proof);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&sapk, &key, &mut rng).is_none());
+        assert!(cache.get(&sapk, &other, &mut rng).is_some());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use tokio::sync::watch;
+
+use super::messages::ScalarEncoding;
+use super::problem::{ErrorCode, Problem};
+
+/// Outcome of evaluating a prove request, as sent to the client.
+pub type ProveOutcome = Result<Bytes, Problem>;
+
+/// Identifies a prove request: the same masked vectors against the same generators.
+/// Masked vectors are freshly randomized per encryption, so equal keys only come
+/// from a client resending a request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProveKey {
+    /// Tenant the session belongs to, on a server with tenants.
+    pub tenant: Option<String>,
+    pub session_id: String,
+    pub circuit: String,
+    pub generators_hash: [u8; 32],
+    /// SHA-256 of the request body.
+    pub request_hash: [u8; 32],
+    /// How those bytes encode their scalars.
+    pub encoding: ScalarEncoding,
+}
+
+enum Entry {
+    /// Being evaluated; the outcome is broadcast when it is known.
+    Pending(watch::Receiver<Option<ProveOutcome>>),
+    Ready {
+        response: Bytes,
+        stored: Instant,
+    },
+}
+
+/// Responses to recent prove requests, so a retried request is answered without
+/// evaluating its MSMs again. A retry that arrives while the original is still
+/// being evaluated waits for it. Only successful responses are kept.
+pub struct ProveCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<ProveKey, Entry>>,
+}
+
+/// Result of looking up a prove request in the cache.
+pub enum CacheLookup {
+    /// A response stored less than the TTL ago.
+    Hit(Bytes),
+    /// The same request is being evaluated for another caller.
+    Pending(PendingProve),
+    /// Unknown request: the caller evaluates it and reports through the slot.
+    Miss(ProveSlot),
+}
+
+impl ProveCache {
+    /// Keep responses for `ttl` after they are computed.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn lookup(self: &Arc<Self>, key: ProveKey) -> CacheLookup {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| match entry {
+            Entry::Pending(_) => true,
+            Entry::Ready { stored, .. } => stored.elapsed() < self.ttl,
+        });
+        match entries.get(&key) {
+            Some(Entry::Ready { response, .. }) => CacheLookup::Hit(response.clone()),
+            Some(Entry::Pending(rx)) => CacheLookup::Pending(PendingProve(rx.clone())),
+            None => {
+                let (tx, rx) = watch::channel(None);
+                entries.insert(key.clone(), Entry::Pending(rx));
+                CacheLookup::Miss(ProveSlot {
+                    cache: self.clone(),
+                    key,
+                    tx,
+                    completed: false,
+                })
+            }
+        }
+    }
+}
+
+/// Outcome of a prove request being evaluated elsewhere.
+pub struct PendingProve(watch::Receiver<Option<ProveOutcome>>);
+
+impl PendingProve {
+    pub async fn wait(mut self) -> ProveOutcome {
+        match self.0.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone().expect("waited for an outcome"),
+            // The evaluating side went away without reporting
+            Err(_) => Err(Problem::new(ErrorCode::Internal)),
+        }
+    }
+}
+
+/// Reservation for evaluating a request. Dropping it without `complete` (e.g. on
+/// a panic) forgets the request and fails its waiters with 500.
+pub struct ProveSlot {
+    cache: Arc<ProveCache>,
+    key: ProveKey,
+    tx: watch::Sender<Option<ProveOutcome>>,
+    completed: bool,
+}
+
+impl ProveSlot {
+    /// Wait for the outcome this slot will report.
+    pub fn subscribe(&self) -> PendingProve {
+        PendingProve(self.tx.subscribe())
+    }
+
+    /// Store a successful response and hand the outcome to every waiter. Errors
+    /// are not stored, so the next retry evaluates the request again.
+    pub fn complete(mut self, outcome: ProveOutcome) {
+        self.completed = true;
+        {
+            let mut entries = self.cache.entries.lock().unwrap();
+            match &outcome {
+                Ok(response) => {
+                    entries.insert(
+                        self.key.clone(),
+                        Entry::Ready {
+                            response: response.clone(),
+                            stored: Instant::now(),
+                        },
+                    );
+                }
+                Err(_) => {
+                    entries.remove(&self.key);
+                }
+            }
+        }
+        self.tx.send_replace(Some(outcome));
+    }
+}
+
+impl Drop for ProveSlot {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(request: u8) -> ProveKey {
+        ProveKey {
+            tenant: None,
+            session_id: "s".to_string(),
+            circuit: "default".to_string(),
+            generators_hash: [0; 32],
+            request_hash: [request; 32],
+            encoding: ScalarEncoding::Canonical,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_waits_for_and_reuses_response() {
+        let cache = Arc::new(ProveCache::new(Duration::from_secs(60)));
+        let CacheLookup::Miss(slot) = cache.lookup(key(1)) else {
+            panic!("first lookup should miss");
+        };
+        let CacheLookup::Pending(pending) = cache.lookup(key(1)) else {
+            panic!("lookup during evaluation should wait");
+        };
+        assert!(matches!(cache.lookup(key(2)), CacheLookup::Miss(_)));
+
+        slot.complete(Ok(Bytes::from_static(b"response")));
+        assert_eq!(pending.wait().await.unwrap(), "response");
+        let CacheLookup::Hit(response) = cache.lookup(key(1)) else {
+            panic!("completed request should hit");
+        };
+        assert_eq!(response, "response");
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_expired_responses_are_dropped() {
+        let cache = Arc::new(ProveCache::new(Duration::from_millis(20)));
+        let CacheLookup::Miss(slot) = cache.lookup(key(1)) else {
+            panic!("first lookup should miss");
+        };
+        let pending = slot.subscribe();
+        slot.complete(Err(Problem::new(ErrorCode::Timeout)));
+        assert_eq!(pending.wait().await, Err(Problem::new(ErrorCode::Timeout)));
+        assert!(cache.entries.lock().unwrap().is_empty());
+
+        let CacheLookup::Miss(slot) = cache.lookup(key(1)) else {
+            panic!("failed request should be evaluated again");
+        };
+        slot.complete(Ok(Bytes::from_static(b"response")));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(matches!(cache.lookup(key(1)), CacheLookup::Miss(_)));
+
+        // An abandoned slot fails its waiters instead of leaving them hanging
+        let CacheLookup::Miss(slot) = cache.lookup(key(3)) else {
+            panic!("first lookup should miss");
+        };
+        let pending = slot.subscribe();
+        drop(slot);
+        assert_eq!(pending.wait().await, Err(Problem::new(ErrorCode::Internal)));
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+/// Header a client sets on POST /prove and /msm to pick the `QosClass` of its
+/// request.
+pub const QOS_HEADER: &str = "x-stealthsnark-qos";
+
+/// Priority class of a prove or MSM request. When the server's evaluation slots
+/// are taken, each class waits in a queue of its own and the queues share the
+/// slots by `QosWeights`, so a backlog of batch proves delays wallet proofs only
+/// by its weighted share.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum QosClass {
+    /// Latency-sensitive, e.g. a wallet waiting on its proof.
+    #[default]
+    Interactive,
+    /// Bulk proving that can wait.
+    Batch,
+}
+
+impl QosClass {
+    pub const ALL: [QosClass; 2] = [QosClass::Interactive, QosClass::Batch];
+
+    /// Value of `QOS_HEADER`.
+    pub fn header_value(self) -> &'static str {
+        match self {
+            QosClass::Interactive => "interactive",
+            QosClass::Batch => "batch",
+        }
+    }
+
+    pub fn from_header_value(value: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|class| class.header_value().as_bytes() == value)
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Share of the slots each class gets while both have requests queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QosWeights {
+    pub interactive: u32,
+    pub batch: u32,
+}
+
+impl Default for QosWeights {
+    fn default() -> Self {
+        Self {
+            interactive: 4,
+            batch: 1,
+        }
+    }
+}
+
+impl QosWeights {
+    fn get(&self, class: QosClass) -> f64 {
+        let weight = match class {
+            QosClass::Interactive => self.interactive,
+            QosClass::Batch => self.batch,
+        };
+        weight.max(1) as f64
+    }
+}
+
+/// How many prove and MSM requests the server evaluates at once, how the
+/// classes share them and how many may wait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    pub slots: usize,
+    pub weights: QosWeights,
+    /// Turn new requests away with 429 `ErrorCode::Overloaded` while this many
+    /// are queued; `None` queues without bound.
+    pub max_queued: Option<usize>,
+}
+
+/// Admits requests to a fixed number of evaluation slots by self-clocked
+/// weighted fair queuing: each request is tagged with the virtual time at which
+/// its class would finish it, its compute units divided by the class weight
+/// after the class's previous request, and the queued request with the
+/// earliest tag runs next. Ties go to `QosClass::Interactive`.
+pub struct Scheduler {
+    config: SchedulerConfig,
+    inner: Mutex<SchedulerInner>,
+}
+
+#[derive(Default)]
+struct SchedulerInner {
+    running: usize,
+    /// Tag of the request admitted last.
+    virtual_time: f64,
+    /// Tag of the latest request of each class.
+    last_finish: [f64; 2],
+    /// Waiting requests of each class, oldest first.
+    queues: [VecDeque<Waiter>; 2],
+    /// Compute units of the waiting requests.
+    queued_units: u64,
+}
+
+struct Waiter {
+    finish: f64,
+    units: u64,
+    admit: oneshot::Sender<SlotPermit>,
+}
+
+/// An evaluation slot, freed for the next queued request when dropped.
+pub struct SlotPermit {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+impl Scheduler {
+    /// # Panics
+    /// If `config.slots` is 0.
+    pub fn new(config: SchedulerConfig) -> Arc<Self> {
+        assert!(config.slots > 0, "a scheduler needs at least one slot");
+        Arc::new(Self {
+            config,
+            inner: Mutex::default(),
+        })
+    }
+
+    /// Wait for a slot to evaluate a request of `units` compute units in
+    /// `class`. Dropping the future gives up its place in the queue.
+    pub async fn admit(self: &Arc<Self>, class: QosClass, units: u64) -> SlotPermit {
+        let receiver = {
+            let mut inner = self.inner.lock().unwrap();
+            let start = inner.virtual_time.max(inner.last_finish[class.index()]);
+            let finish = start + units as f64 / self.config.weights.get(class);
+            inner.last_finish[class.index()] = finish;
+            if inner.running < self.config.slots && inner.queues.iter().all(VecDeque::is_empty) {
+                inner.running += 1;
+                inner.virtual_time = finish;
+                return self.permit();
+            }
+            let (admit, receiver) = oneshot::channel();
+            inner.queued_units += units;
+            inner.queues[class.index()].push_back(Waiter {
+                finish,
+                units,
+                admit,
+            });
+            receiver
+        };
+        receiver
+            .await
+            .expect("queued requests are admitted or dropped with the scheduler")
+    }
+
+    /// Requests waiting for a slot, by class in `QosClass::ALL` order.
+    pub fn queued(&self) -> [usize; 2] {
+        let inner = self.inner.lock().unwrap();
+        [inner.queues[0].len(), inner.queues[1].len()]
+    }
+
+    /// Compute units of the requests waiting for a slot.
+    pub fn queued_units(&self) -> u64 {
+        self.inner.lock().unwrap().queued_units
+    }
+
+    /// Whether a new request would have to queue behind `max_queued` others.
+    pub fn is_full(&self) -> bool {
+        let Some(max_queued) = self.config.max_queued else {
+            return false;
+        };
+        let inner = self.inner.lock().unwrap();
+        let queued: usize = inner.queues.iter().map(VecDeque::len).sum();
+        inner.running >= self.config.slots && queued >= max_queued
+    }
+
+    pub fn config(&self) -> &SchedulerConfig {
+        &self.config
+    }
+
+    fn permit(self: &Arc<Self>) -> SlotPermit {
+        SlotPermit {
+            scheduler: self.clone(),
+        }
+    }
+
+    /// Hand a freed slot to the queued request with the earliest tag whose
+    /// caller is still waiting.
+    fn release(self: &Arc<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.running -= 1;
+        loop {
+            let next = (0..inner.queues.len())
+                .filter_map(|class| Some((class, inner.queues[class].front()?.finish)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            let Some((class, finish)) = next else {
+                return;
+            };
+            let waiter = inner.queues[class]
+                .pop_front()
+                .expect("the head was just seen");
+            inner.queued_units -= waiter.units;
+            if waiter.admit.is_closed() {
+                continue;
+            }
+            inner.running += 1;
+            inner.virtual_time = finish;
+            let permit = self.permit();
+            drop(inner);
+            // A caller that went away meanwhile hands the permit back, and
+            // dropping it frees the slot again
+            let _ = waiter.admit.send(permit);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The classes in the order a scheduler with one slot admits `queued`,
+    /// queued in that order behind a running batch request.
+    async fn admission_order(queued: &[QosClass]) -> Vec<QosClass> {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            slots: 1,
+            weights: QosWeights::default(),
+            max_queued: None,
+        });
+        let running = scheduler.admit(QosClass::Batch, 1).await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for &class in queued {
+            let (scheduler, order) = (scheduler.clone(), order.clone());
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.admit(class, 1).await;
+                order.lock().unwrap().push(class);
+            }));
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(scheduler.queued().iter().sum::<usize>(), queued.len());
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_weighted_fair_queuing() {
+        use QosClass::{Batch as B, Interactive as I};
+
+        // Interactive requests overtake a batch backlog
+        assert_eq!(admission_order(&[B, B, B, I, I]).await, [I, I, B, B, B]);
+        // but batch still gets its share: one request for every four
+        assert_eq!(
+            admission_order(&[I, I, I, I, I, I, I, I, B, B]).await,
+            [I, I, I, I, B, I, I, I, I, B]
+        );
+
+        assert_eq!(QosClass::from_header_value(b"batch"), Some(B));
+        assert_eq!(QosClass::from_header_value(b"bulk"), None);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiter_frees_its_place() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            slots: 1,
+            weights: QosWeights::default(),
+            max_queued: None,
+        });
+        let running = scheduler.admit(QosClass::Batch, 1).await;
+        let abandoned = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.admit(QosClass::Interactive, 1).await }
+        });
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+
+        drop(running);
+        let _next = scheduler.admit(QosClass::Batch, 1).await;
+        assert_eq!(scheduler.queued(), [0, 0]);
+        assert_eq!(scheduler.queued_units(), 0);
+        assert_eq!(scheduler.inner.lock().unwrap().running, 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            slots: 1,
+            weights: QosWeights::default(),
+            max_queued: Some(1),
+        });
+        assert!(!scheduler.is_full());
+        let running = scheduler.admit(QosClass::Interactive, 5).await;
+        assert!(!scheduler.is_full());
+        let queued = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                let _permit = scheduler.admit(QosClass::Batch, 7).await;
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.queued_units(), 7);
+        assert!(scheduler.is_full());
+
+        drop(running);
+        queued.await.unwrap();
+        assert!(!scheduler.is_full());
+        assert_eq!(scheduler.queued_units(), 0);
+    }
+}
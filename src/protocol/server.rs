@@ -1,177 +1,3310 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
 
 use ark_bn254::{Fr, G1Affine, G1Projective as G1, G2Affine, G2Projective as G2};
+use ark_ec::short_weierstrass::{Affine, SWCurveConfig};
 use ark_ec::CurveGroup;
-use axum::extract::State;
-use axum::http::StatusCode;
-use axum::routing::post;
-use axum::Router;
-use tokio::sync::RwLock;
+use axum::body::Body;
+use axum::extract::{Extension, Path, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use tokio::sync::{RwLock, Semaphore};
+use tracing::Instrument;
 
+use super::attestation::{AttestationProvider, AttestationReport};
+use super::audit::{sha256, to_hex, AuditEvent, AuditLog};
+use super::codec::{WireCodec, CODEC_HEADER};
+#[cfg(feature = "fault-injection")]
+use super::fault::FaultInjector;
+use super::gate::{check_admin_key, SetupGate, SpentSolutions, API_KEY_HEADER};
+use super::load::LoadTracker;
 use super::messages::*;
-use crate::emsm::pedersen::Pedersen;
+use super::metering::{
+    compute_units, prometheus_text, MeteringEvent, MeteringHook, SessionStats, Usage, UsageLedger,
+};
+use super::problem::{ErrorCode, Problem};
+use super::prove_cache::{CacheLookup, ProveCache, ProveKey};
+use super::scheduler::{QosClass, Scheduler, SchedulerConfig, SlotPermit, QOS_HEADER};
+use super::store::{
+    circuit_object, generators_object, session_object, ObjectStore, StoredCircuit, StoredSession,
+};
+use super::tenant::{LimitExceeded, Tenant, TenantDirectory, TenantState};
+use crate::curves::grumpkin::{self, GrumpkinAffine, GrumpkinProjective};
+use crate::emsm::cancel::CancelToken;
+use crate::emsm::parallel::ParallelConfig;
+use crate::emsm::pedersen::{
+    msm_on_threads, FixedBaseTable, PedersenError, MAX_TABLE_WINDOW, MSM_CHUNK,
+};
+use crate::error::StealthSnarkError;
+use crate::groth16::server_aided::{EncryptedRequest, PublicGenerators, ServerResponse};
 
-/// Per-session state: generators received during setup.
+/// Curves this build has an MSM backend for. `ServerConfig::curves` picks the
+/// ones a server serves.
+pub const SUPPORTED_CURVES: &[CurveId] = &[CurveId::Bn254, CurveId::Grumpkin];
+
+/// Circuit name used when a client does not pick one.
+pub const DEFAULT_CIRCUIT: &str = "default";
+
+/// Longest accepted circuit name, in bytes.
+const MAX_CIRCUIT_NAME_LEN: usize = 128;
+
+/// How long the server keeps the chunks of a chunked setup after its last part
+/// arrived.
+const STAGED_SETUP_TTL: Duration = Duration::from_secs(600);
+
+/// Where a session lives: sessions of different tenants never share a key, even
+/// under equal session IDs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SessionKey {
+    /// `Tenant::id`, or `None` on a server without tenants.
+    tenant: Option<String>,
+    session_id: String,
+}
+
+impl SessionKey {
+    fn new(tenant: Option<&str>, session_id: &str) -> Self {
+        Self {
+            tenant: tenant.map(String::from),
+            session_id: session_id.to_string(),
+        }
+    }
+}
+
+/// Per-session state: the account it belongs to, and its circuits and generator
+/// sets by name.
 struct SessionState {
+    /// API key the session was set up with, for usage accounting. Every circuit of
+    /// the session must be set up with the same key.
+    account: Option<String>,
+    /// `SessionToken::hash` of the session's token, drawn at its first setup.
+    /// `None` only until then, or for a session stored before sessions had
+    /// tokens, which gets one at its next setup.
+    token_hash: Option<[u8; 32]>,
+    circuits: HashMap<String, Arc<CircuitState>>,
+    generator_sets: HashMap<String, Arc<GeneratorSet>>,
+    /// Last setup, prove or keepalive of the session, for TTL eviction.
+    last_activity: Mutex<Instant>,
+}
+
+impl SessionState {
+    fn new(account: Option<String>, token_hash: Option<[u8; 32]>) -> Self {
+        Self {
+            account,
+            token_hash,
+            circuits: HashMap::new(),
+            generator_sets: HashMap::new(),
+            last_activity: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Hold `upload` under `name`, returning whether it replaced one.
+    fn insert(&mut self, name: String, upload: Upload) -> bool {
+        match upload {
+            Upload::Circuit(circuit) => self.circuits.insert(name, Arc::from(circuit)).is_some(),
+            Upload::GeneratorSet(set) => self.generator_sets.insert(name, Arc::new(set)).is_some(),
+        }
+    }
+
+    /// Time left before the session expires if it stays idle, or `None` without a TTL.
+    fn remaining(&self, ttl: Option<Duration>) -> Option<Duration> {
+        ttl.map(|ttl| ttl.saturating_sub(self.idle()))
+    }
+
+    fn idle(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    fn expired(&self, ttl: Option<Duration>) -> bool {
+        self.remaining(ttl).is_some_and(|left| left.is_zero())
+    }
+
+    /// Requests for sessions set up with an API key must carry the same key.
+    fn check_account(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        if let Some(account) = &self.account {
+            let key = headers
+                .get(API_KEY_HEADER)
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            if key.as_bytes() != account.as_bytes() {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+        Ok(())
+    }
+
+    /// Requests for the session must carry its token in `SESSION_TOKEN_HEADER`.
+    fn check_token(&self, headers: &HeaderMap) -> Result<(), ErrorCode> {
+        let Some(token_hash) = self.token_hash else {
+            return Ok(());
+        };
+        match session_token(headers) {
+            Some(token) if token.hash() == token_hash => Ok(()),
+            _ => Err(ErrorCode::SessionTokenRejected),
+        }
+    }
+}
+
+/// The `SessionToken` a request carries, if any.
+fn session_token(headers: &HeaderMap) -> Option<SessionToken> {
+    SessionToken::from_header_value(headers.get(SESSION_TOKEN_HEADER)?.as_bytes())
+}
+
+/// One circuit of a session: generators received during setup.
+struct CircuitState {
+    /// Curve the circuit's generators were uploaded for.
+    curve: CurveId,
+    /// `SetupRequest::generators_hash` of the upload; prove requests must echo it.
+    generators_hash: [u8; 32],
     h_generators: Vec<G1Affine>,
     l_generators: Vec<G1Affine>,
     a_generators: Vec<G1Affine>,
     b_g1_generators: Vec<G1Affine>,
     b_g2_generators: Vec<G2Affine>,
+    /// Generators of the public-input MSMs, if the client uploaded them.
+    public_generators: Option<PublicGenerators>,
+    /// `SetupRequest::key_fingerprint` of the upload.
+    key_fingerprint: Option<[u8; 32]>,
+    /// Fixed-base tables of the generators, once built in the background
+    /// (`ServerConfig::fixed_base_window`).
+    tables: OnceLock<CircuitTables>,
+}
+
+/// Fixed-base tables of the h, l, a, b_g1 and b_g2 generators of a circuit.
+struct CircuitTables {
+    h: FixedBaseTable<G1>,
+    l: FixedBaseTable<G1>,
+    a: FixedBaseTable<G1>,
+    b_g1: FixedBaseTable<G1>,
+    b_g2: FixedBaseTable<G2>,
+    /// The tables' share of `ServerConfig::max_table_bytes`, released with them.
+    _reservation: Option<TableReservation>,
+}
+
+/// Bytes of fixed-base tables taken out of `ServerConfig::max_table_bytes`,
+/// given back on drop.
+struct TableReservation {
+    used: Arc<AtomicUsize>,
+    bytes: usize,
+}
+
+impl TableReservation {
+    /// Take `bytes` out of a budget of `max` of which `used` are taken, or
+    /// `None` if they do not fit.
+    fn take(used: &Arc<AtomicUsize>, bytes: usize, max: usize) -> Option<Self> {
+        used.fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+            taken.checked_add(bytes).filter(|&total| total <= max)
+        })
+        .ok()?;
+        Some(Self {
+            used: used.clone(),
+            bytes,
+        })
+    }
+}
+
+impl Drop for TableReservation {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
+
+impl CircuitTables {
+    /// Tables of `window`-bit windows for the circuit, built `MSM_CHUNK`
+    /// generators at a time. Gives up, returning `None`, once the circuit is
+    /// dropped: replaced, or evicted with its session.
+    fn build(
+        circuit: &Weak<CircuitState>,
+        window: usize,
+        reservation: Option<TableReservation>,
+    ) -> Option<Self> {
+        fn table<G: CurveGroup>(
+            circuit: &Weak<CircuitState>,
+            window: usize,
+            generators: fn(&CircuitState) -> &[G::Affine],
+        ) -> Option<FixedBaseTable<G>> {
+            let mut table = FixedBaseTable::new(window);
+            loop {
+                let circuit = circuit.upgrade()?;
+                let generators = generators(&circuit);
+                let start = table.len();
+                if start == generators.len() {
+                    return Some(table);
+                }
+                table.extend(&generators[start..generators.len().min(start + MSM_CHUNK)]);
+            }
+        }
+        Some(Self {
+            h: table(circuit, window, |c| &c.h_generators)?,
+            l: table(circuit, window, |c| &c.l_generators)?,
+            a: table(circuit, window, |c| &c.a_generators)?,
+            b_g1: table(circuit, window, |c| &c.b_g1_generators)?,
+            b_g2: table(circuit, window, |c| &c.b_g2_generators)?,
+            _reservation: reservation,
+        })
+    }
+
+    fn g1(&self, kind: MsmKind) -> Option<&FixedBaseTable<G1>> {
+        match kind {
+            MsmKind::H => Some(&self.h),
+            MsmKind::L => Some(&self.l),
+            MsmKind::A => Some(&self.a),
+            MsmKind::BG1 => Some(&self.b_g1),
+            MsmKind::BG2 | MsmKind::Public => None,
+        }
+    }
+}
+
+/// The field of an upload that failed to decode, and why.
+type DecodeError = (&'static str, StealthSnarkError);
+
+/// The points of the `field` of an upload, on the curve and in its prime-order
+/// subgroup (`ark_points_from_bytes`).
+fn decode_points<P: SWCurveConfig>(
+    field: &'static str,
+    bytes: &[u8],
+) -> Result<Vec<Affine<P>>, DecodeError> {
+    ark_points_from_bytes(bytes).map_err(|e| (field, e))
+}
+
+impl CircuitState {
+    /// The h, l, a, b_g1 and b_g2 generators as `ark_vec_to_bytes` encodes
+    /// them, which is how they were uploaded.
+    fn encoded_generators(&self) -> [Vec<u8>; 5] {
+        [
+            ark_vec_to_bytes(&self.h_generators),
+            ark_vec_to_bytes(&self.l_generators),
+            ark_vec_to_bytes(&self.a_generators),
+            ark_vec_to_bytes(&self.b_g1_generators),
+            ark_vec_to_bytes(&self.b_g2_generators),
+        ]
+    }
+
+    /// Decode and validate the generators of `request`, uploaded for `curve`,
+    /// or name the field that failed.
+    fn decode(curve: CurveId, request: &SetupRequest) -> Result<Self, DecodeError> {
+        let public_generators = if request.public_generators.is_empty() {
+            None
+        } else {
+            let public: PublicGenerators =
+                ark_from_bytes(&request.public_generators).map_err(|e| ("public_generators", e))?;
+            public.validate().map_err(|e| ("public_generators", e))?;
+            Some(public)
+        };
+        Ok(Self {
+            curve,
+            generators_hash: request.generators_hash(),
+            h_generators: decode_points("h_generators", &request.h_generators)?,
+            l_generators: decode_points("l_generators", &request.l_generators)?,
+            a_generators: decode_points("a_generators", &request.a_generators)?,
+            b_g1_generators: decode_points("b_g1_generators", &request.b_g1_generators)?,
+            b_g2_generators: decode_points("b_g2_generators", &request.b_g2_generators)?,
+            public_generators,
+            key_fingerprint: request.key_fingerprint,
+            tables: OnceLock::new(),
+        })
+    }
+
+    /// Memory held by the generators, for `TenantLimits::max_generator_bytes`.
+    fn generator_bytes(&self) -> usize {
+        let g1 = std::mem::size_of::<G1Affine>();
+        let g2 = std::mem::size_of::<G2Affine>();
+        let public = self
+            .public_generators
+            .as_ref()
+            .map_or(0, |p| (p.a.len() + p.b_g1.len()) * g1 + p.b_g2.len() * g2);
+        (self.h_generators.len()
+            + self.l_generators.len()
+            + self.a_generators.len()
+            + self.b_g1_generators.len())
+            * g1
+            + self.b_g2_generators.len() * g2
+            + public
+    }
+
+    /// Memory the fixed-base tables of `window`-bit windows of the circuit take.
+    fn table_bytes(&self, window: usize) -> usize {
+        let g1 = self.h_generators.len()
+            + self.l_generators.len()
+            + self.a_generators.len()
+            + self.b_g1_generators.len();
+        FixedBaseTable::<G1>::bytes_for(g1, window)
+            + FixedBaseTable::<G2>::bytes_for(self.b_g2_generators.len(), window)
+    }
+
+    /// Memory the generators take, with their fixed-base tables of `window`-bit
+    /// windows if the server builds them, for `TenantLimits::max_generator_bytes`.
+    fn held_bytes(&self, window: Option<usize>) -> usize {
+        self.generator_bytes() + window.map_or(0, |window| self.table_bytes(window))
+    }
+}
+
+/// The chunks of a chunked setup (POST /setup/chunk) received so far.
+struct StagedSetup {
+    /// `SetupChunk::generators_hash`, `chunk_len` and `lens` of the upload.
+    upload: ([u8; 32], u64, [u64; 5]),
+    /// API key of the part that started the upload.
+    account: Option<String>,
+    /// Chunks of the h, l, a, b_g1 and b_g2 generators, by index.
+    chunks: [BTreeMap<u64, Vec<u8>>; 5],
+    /// When the last part arrived, for `STAGED_SETUP_TTL`.
+    touched: Instant,
+}
+
+impl StagedSetup {
+    fn new(upload: ([u8; 32], u64, [u64; 5]), account: Option<String>) -> Self {
+        Self {
+            upload,
+            account,
+            chunks: Default::default(),
+            touched: Instant::now(),
+        }
+    }
+
+    /// Whether every chunk of the upload has arrived.
+    fn complete(&self) -> bool {
+        let (_, chunk_len, lens) = self.upload;
+        (0..5).all(|i| self.chunks[i].len() as u64 == lens[i].div_ceil(chunk_len))
+    }
+}
+
+/// The generators of a generator set, in the group of its curve, so each is
+/// evaluated by the MSM monomorphized for that group.
+enum GeneratorPoints {
+    Bn254(Vec<G1Affine>),
+    Grumpkin(Vec<GrumpkinAffine>),
+}
+
+/// One generator set of a session: generators received on POST /emsm/setup.
+struct GeneratorSet {
+    /// `GeneratorSetRequest::generators_hash` of the upload.
+    generators_hash: [u8; 32],
+    points: GeneratorPoints,
+}
+
+impl GeneratorSet {
+    /// Decode and validate the generators of `request`, uploaded for `curve`,
+    /// or name the field that failed.
+    fn decode(curve: CurveId, request: &GeneratorSetRequest) -> Result<Self, DecodeError> {
+        let generators = &request.generators;
+        let points = match curve {
+            CurveId::Bn254 => GeneratorPoints::Bn254(decode_points("generators", generators)?),
+            CurveId::Grumpkin => {
+                GeneratorPoints::Grumpkin(decode_points("generators", generators)?)
+            }
+            CurveId::Bls12_381 => {
+                let e = format!("no generator sets on {curve:?}");
+                return Err(("curve", StealthSnarkError::InvalidArgument(e)));
+            }
+        };
+        Ok(Self {
+            generators_hash: request.generators_hash(),
+            points,
+        })
+    }
+
+    fn curve(&self) -> CurveId {
+        match self.points {
+            GeneratorPoints::Bn254(_) => CurveId::Bn254,
+            GeneratorPoints::Grumpkin(_) => CurveId::Grumpkin,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match &self.points {
+            GeneratorPoints::Bn254(points) => points.len(),
+            GeneratorPoints::Grumpkin(points) => points.len(),
+        }
+    }
+
+    /// Memory held by the generators, for `TenantLimits::max_generator_bytes`.
+    fn generator_bytes(&self) -> usize {
+        match &self.points {
+            GeneratorPoints::Bn254(points) => std::mem::size_of_val(points.as_slice()),
+            GeneratorPoints::Grumpkin(points) => std::mem::size_of_val(points.as_slice()),
+        }
+    }
 }
 
-/// Server state: stores per-session generator sets.
-#[derive(Default)]
+/// The generators a setup uploads: a Groth16 circuit's (POST /setup) or a
+/// generator set (POST /emsm/setup).
+enum Upload {
+    Circuit(Box<CircuitState>),
+    GeneratorSet(GeneratorSet),
+}
+
+impl Upload {
+    fn generators_hash(&self) -> [u8; 32] {
+        match self {
+            Upload::Circuit(circuit) => circuit.generators_hash,
+            Upload::GeneratorSet(set) => set.generators_hash,
+        }
+    }
+
+    /// Memory the upload takes, with the fixed-base tables of `window`-bit
+    /// windows of a circuit if the server builds them.
+    fn held_bytes(&self, window: Option<usize>) -> usize {
+        match self {
+            Upload::Circuit(circuit) => circuit.held_bytes(window),
+            Upload::GeneratorSet(set) => set.generator_bytes(),
+        }
+    }
+
+    /// Lengths of a circuit's h, l, a, b_g1 and b_g2 generators, or of a
+    /// generator set followed by zeros.
+    fn sizes(&self) -> [usize; 5] {
+        match self {
+            Upload::Circuit(circuit) => [
+                circuit.h_generators.len(),
+                circuit.l_generators.len(),
+                circuit.a_generators.len(),
+                circuit.b_g1_generators.len(),
+                circuit.b_g2_generators.len(),
+            ],
+            Upload::GeneratorSet(set) => [set.len(), 0, 0, 0, 0],
+        }
+    }
+}
+
+/// What a prove envelope is evaluated against: a circuit, or a generator set
+/// for POST /emsm/eval and /emsm/eval/malicious.
+trait Generators {
+    /// What `session` holds under `name`.
+    fn lookup(session: &SessionState, name: &str) -> Option<Arc<Self>>;
+    fn curve(&self) -> CurveId;
+    fn generators_hash(&self) -> [u8; 32];
+}
+
+impl Generators for CircuitState {
+    fn lookup(session: &SessionState, name: &str) -> Option<Arc<Self>> {
+        session.circuits.get(name).cloned()
+    }
+
+    fn curve(&self) -> CurveId {
+        self.curve
+    }
+
+    fn generators_hash(&self) -> [u8; 32] {
+        self.generators_hash
+    }
+}
+
+impl Generators for GeneratorSet {
+    fn lookup(session: &SessionState, name: &str) -> Option<Arc<Self>> {
+        session.generator_sets.get(name).cloned()
+    }
+
+    fn curve(&self) -> CurveId {
+        GeneratorSet::curve(self)
+    }
+
+    fn generators_hash(&self) -> [u8; 32] {
+        self.generators_hash
+    }
+}
+
+/// Server configuration.
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// Admission policy for POST /setup.
+    pub setup_gate: SetupGate,
+    /// Maximum accepted size of a POST /setup body, in bytes.
+    pub max_setup_bytes: usize,
+    /// Maximum accepted size of a POST /prove body, in bytes. A prove request is
+    /// about 32 bytes per generator, so 2^26-constraint circuits need ~10 GiB.
+    pub max_prove_bytes: usize,
+    /// Audit trail for sessions and served proofs.
+    pub audit: Option<Arc<AuditLog>>,
+    /// TEE attestation evidence served on POST /attestation.
+    pub attestation: Option<Arc<dyn AttestationProvider>>,
+    /// Called with the compute units of every served prove request.
+    pub metering_hook: Option<Arc<dyn MeteringHook>>,
+    /// Deadline for evaluating a prove request; exceeded requests fail with 408.
+    pub prove_timeout: Option<Duration>,
+    /// Thread pool the prove MSMs run on.
+    pub parallel: ParallelConfig,
+    /// Sessions idle for longer than this are evicted with their generators.
+    /// Setup, prove and POST /keepalive count as activity. `None` keeps sessions
+    /// until they are replaced.
+    pub session_ttl: Option<Duration>,
+    /// How long prove responses are kept for clients that resend a request, e.g.
+    /// after a client-side timeout. With a cache, prove requests are evaluated to
+    /// completion even if the client disconnects, so a retry can pick up the
+    /// result; only `prove_timeout` cancels them. `None` disables the cache.
+    pub prove_cache_ttl: Option<Duration>,
+    /// Applications sharing the server, each with sessions of its own under its
+    /// limits. With tenants, every request that names a session must carry the
+    /// API key of the tenant the session belongs to. Empty serves everyone from
+    /// one namespace.
+    pub tenants: Vec<Tenant>,
+    /// Evaluate at most `slots` prove and MSM requests at once, queuing the rest
+    /// by `QosClass` (see `Scheduler`). `None` evaluates every request as it
+    /// arrives.
+    pub scheduler: Option<SchedulerConfig>,
+    /// Object storage shared with other replicas of the server. Setup also writes
+    /// the session and its generators there, and a request naming a session or
+    /// circuit this replica does not hold fetches them from there and keeps them
+    /// like set-up ones, so replicas behind a load balancer can serve any session
+    /// without sticky routing. Usage totals stay per replica.
+    pub store: Option<Arc<dyn ObjectStore>>,
+    /// Curves sessions may be set up on, out of `SUPPORTED_CURVES`, as
+    /// advertised on GET /capabilities. Setup for any other curve fails with
+    /// `ErrorCode::CurveMismatch`.
+    pub curves: Vec<CurveId>,
+    /// Key of the admin API (GET /admin/sessions and /admin/metrics), sent in
+    /// `x-admin-key`. `None` turns the API off.
+    pub admin_key: Option<String>,
+    /// Build a `FixedBaseTable` of windows this many bits wide (at most
+    /// `MAX_TABLE_WINDOW`) for each generator set of a circuit after its setup
+    /// returns, on the MSM pool, and evaluate its MSMs over the tables once they
+    /// are ready. POST /keepalive reports which circuits have theirs. The tables
+    /// take ⌈254 / window⌉ times the memory of the generators, which
+    /// `TenantLimits::max_generator_bytes` counts. `None` builds no tables.
+    pub fixed_base_window: Option<usize>,
+    /// Memory the fixed-base tables of all circuits may take at once, in bytes.
+    /// A circuit whose tables would not fit gets none, and its MSMs run without
+    /// them. `None` is unlimited.
+    pub max_table_bytes: Option<usize>,
+    /// Build the fixed-base tables of at most this many circuits at once; the
+    /// others wait their turn.
+    pub max_table_builds: usize,
+    /// Tamper with every MSM result before it is sent (see `fault::Fault`).
+    #[cfg(feature = "fault-injection")]
+    pub fault: Option<Arc<FaultInjector>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            setup_gate: SetupGate::Open,
+            max_setup_bytes: 2 * 1024 * 1024,
+            max_prove_bytes: 64 * 1024 * 1024,
+            audit: None,
+            attestation: None,
+            metering_hook: None,
+            prove_timeout: None,
+            parallel: ParallelConfig::default(),
+            session_ttl: None,
+            prove_cache_ttl: None,
+            tenants: Vec::new(),
+            scheduler: None,
+            store: None,
+            curves: SUPPORTED_CURVES.to_vec(),
+            admin_key: None,
+            fixed_base_window: None,
+            max_table_bytes: None,
+            max_table_builds: 1,
+            #[cfg(feature = "fault-injection")]
+            fault: None,
+        }
+    }
+}
+
+/// Server state: stores per-session generator sets and usage totals.
 pub struct ServerState {
-    sessions: HashMap<String, SessionState>,
+    sessions: HashMap<SessionKey, SessionState>,
+    /// Chunked setups in progress, by session and circuit.
+    staged: HashMap<(SessionKey, String), StagedSetup>,
+    tenants: TenantDirectory,
+    usage: Arc<UsageLedger>,
+    load: Arc<LoadTracker>,
+    prove_cache: Option<Arc<ProveCache>>,
+    scheduler: Option<Arc<Scheduler>>,
+    spent_pow: SpentSolutions,
+    /// Bytes of `ServerConfig::max_table_bytes` taken by built and building tables.
+    table_bytes: Arc<AtomicUsize>,
+    /// Permits of `ServerConfig::max_table_builds`.
+    table_builds: Arc<Semaphore>,
+    config: ServerConfig,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ServerState {
     pub fn new() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    /// # Panics
+    /// If two of `config.tenants` share an ID or an API key, the scheduler has
+    /// no slots, `config.curves` names a curve outside `SUPPORTED_CURVES`,
+    /// `config.fixed_base_window` is 0 or above `MAX_TABLE_WINDOW`, or
+    /// `config.max_table_builds` is 0.
+    pub fn with_config(config: ServerConfig) -> Self {
+        for curve in &config.curves {
+            assert!(
+                SUPPORTED_CURVES.contains(curve),
+                "this build has no MSM backend for {curve:?}"
+            );
+        }
+        if let Some(window) = config.fixed_base_window {
+            assert!(
+                (1..=MAX_TABLE_WINDOW).contains(&window),
+                "fixed-base windows must be 1 to {MAX_TABLE_WINDOW} bits, got {window}"
+            );
+        }
+        assert!(
+            config.max_table_builds > 0,
+            "max_table_builds must be at least 1"
+        );
         Self {
             sessions: HashMap::new(),
+            staged: HashMap::new(),
+            tenants: TenantDirectory::new(&config.tenants),
+            usage: Arc::default(),
+            load: Arc::default(),
+            prove_cache: config.prove_cache_ttl.map(|ttl| Arc::new(ProveCache::new(ttl))),
+            scheduler: config.scheduler.map(Scheduler::new),
+            spent_pow: SpentSolutions::default(),
+            table_bytes: Arc::default(),
+            table_builds: Arc::new(Semaphore::new(config.max_table_builds)),
+            config,
         }
     }
+
+    /// With `ServerConfig::fixed_base_window`, build the tables of `circuit` in
+    /// the background, unless they would not fit in
+    /// `ServerConfig::max_table_bytes`. The build waits for one of
+    /// `ServerConfig::max_table_builds` permits, and holds the circuit only
+    /// while extending a table, so replacing or evicting it stops the build.
+    fn spawn_table_build(&self, circuit: &Arc<CircuitState>) {
+        let Some(window) = self.config.fixed_base_window else {
+            return;
+        };
+        let reservation = match self.config.max_table_bytes {
+            Some(max) => {
+                let bytes = circuit.table_bytes(window);
+                match TableReservation::take(&self.table_bytes, bytes, max) {
+                    Some(reservation) => Some(reservation),
+                    None => {
+                        tracing::warn!(bytes, max, "Fixed-base tables over the table budget");
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+        let (circuit, parallel) = (Arc::downgrade(circuit), self.config.parallel.clone());
+        let builds = self.table_builds.clone();
+        tokio::spawn(async move {
+            let Ok(permit) = builds.acquire_owned().await else {
+                return;
+            };
+            let _ = tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                let start = Instant::now();
+                let tables =
+                    parallel.install(|| CircuitTables::build(&circuit, window, reservation));
+                match (tables, circuit.upgrade()) {
+                    (Some(tables), Some(circuit)) => {
+                        let _ = circuit.tables.set(tables);
+                        let build_ms = start.elapsed().as_millis() as u64;
+                        tracing::info!(window, build_ms, "Fixed-base tables ready");
+                    }
+                    _ => tracing::debug!("Fixed-base tables dropped with their circuit"),
+                }
+            })
+            .await;
+        });
+    }
+
+    /// The session at `key`, unless it was never set up or has expired.
+    fn live_session(&self, key: &SessionKey) -> Option<&SessionState> {
+        self.sessions
+            .get(key)
+            .filter(|session| !session.expired(self.config.session_ttl))
+    }
+
+    /// Key of `session_id` in the namespace of the tenant `headers` act for.
+    fn session_key(&self, headers: &HeaderMap, session_id: &str) -> Result<SessionKey, ErrorCode> {
+        let tenant = self.tenants.resolve(headers)?;
+        Ok(SessionKey::new(
+            tenant.as_ref().map(|t| t.id.as_str()),
+            session_id,
+        ))
+    }
+
+    /// Check that `tenant` may hold `upload` under `name` in the session at
+    /// `key`, replacing the circuit or generator set of that name if there is one.
+    fn check_tenant_limits(
+        &self,
+        tenant: &TenantState,
+        key: &SessionKey,
+        name: &str,
+        upload: &Upload,
+    ) -> Result<(), LimitExceeded> {
+        let window = self.config.fixed_base_window;
+        let (mut sessions, mut bytes, mut known) = (0, upload.held_bytes(window), false);
+        let circuit = matches!(upload, Upload::Circuit(_));
+        for (other, session) in self.tenant_sessions(&tenant.id) {
+            sessions += 1;
+            known |= other == key;
+            let replaced = |kept_circuit: bool, other_name: &String| {
+                other == key && kept_circuit == circuit && other_name.as_str() == name
+            };
+            bytes += session
+                .circuits
+                .iter()
+                .filter(|(other_name, _)| !replaced(true, other_name))
+                .map(|(_, circuit)| circuit.held_bytes(window))
+                .sum::<usize>();
+            bytes += session
+                .generator_sets
+                .iter()
+                .filter(|(other_name, _)| !replaced(false, other_name))
+                .map(|(_, set)| set.generator_bytes())
+                .sum::<usize>();
+        }
+        tenant
+            .limits
+            .check_sessions(sessions + usize::from(!known))?;
+        tenant.limits.check_generator_bytes(bytes)
+    }
+
+    /// Live sessions of `tenant`, for its limits.
+    fn tenant_sessions<'a>(
+        &'a self,
+        tenant: &'a str,
+    ) -> impl Iterator<Item = (&'a SessionKey, &'a SessionState)> + 'a {
+        let ttl = self.config.session_ttl;
+        self.sessions.iter().filter(move |(key, session)| {
+            key.tenant.as_deref() == Some(tenant) && !session.expired(ttl)
+        })
+    }
+
+    /// Usage of every live session, ordered by tenant and session ID.
+    pub fn session_stats(&self) -> Vec<SessionStats> {
+        let ttl = self.config.session_ttl;
+        let mut stats: Vec<SessionStats> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| !session.expired(ttl))
+            .map(|(key, session)| SessionStats {
+                tenant: key.tenant.clone(),
+                session_id: key.session_id.clone(),
+                usage: self.usage.session(key.tenant.as_deref(), &key.session_id),
+                idle_ms: session.idle().as_millis() as u64,
+            })
+            .collect();
+        stats.sort_by(|a, b| (&a.tenant, &a.session_id).cmp(&(&b.tenant, &b.session_id)));
+        stats
+    }
+
+    /// Drop sessions idle past `ServerConfig::session_ttl`, returning how many
+    /// were dropped, and chunked setups with no part for `STAGED_SETUP_TTL`.
+    /// Setup runs this too; servers that see few setups should also call it
+    /// periodically to free the generators of abandoned sessions.
+    pub fn evict_expired(&mut self) -> usize {
+        self.staged
+            .retain(|_, staged| staged.touched.elapsed() < STAGED_SETUP_TTL);
+        let ttl = self.config.session_ttl;
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| !session.expired(ttl));
+        before - self.sessions.len()
+    }
 }
 
 pub type SharedState = Arc<RwLock<ServerState>>;
 
-/// Create the axum router with /setup and /prove endpoints.
+/// Create the axum router with /capabilities, /attestation, /setup, /prove,
+/// /msm, /emsm, /keepalive, /estimate, /usage and /admin endpoints.
 pub fn create_router(state: SharedState) -> Router {
     Router::new()
+        .route("/capabilities", get(handle_capabilities))
+        .route("/attestation", post(handle_attestation))
         .route("/setup", post(handle_setup))
+        .route("/setup/patch", post(handle_setup_patch))
+        .route("/setup/chunk", post(handle_setup_chunk))
         .route("/prove", post(handle_prove))
+        .route("/msm", post(handle_msm))
+        .route("/emsm/setup", post(handle_emsm_setup))
+        .route("/emsm/eval", post(handle_emsm_eval))
+        .route("/emsm/eval/malicious", post(handle_emsm_eval_malicious))
+        .route("/fft", post(handle_fft))
+        .route("/keepalive", post(handle_keepalive))
+        .route("/estimate", post(handle_estimate))
+        .route("/usage", get(handle_account_usage))
+        .route("/usage/{session_id}", get(handle_session_usage))
+        .route("/admin/sessions", get(handle_admin_sessions))
+        .route("/admin/metrics", get(handle_admin_metrics))
+        .route("/commitment/{session_id}", get(handle_default_commitment))
+        .route("/commitment/{session_id}/{circuit}", get(handle_commitment))
+        .route(
+            "/commitment/{session_id}/{circuit}/chunks",
+            get(handle_generator_chunks),
+        )
+        .layer(axum::middleware::from_fn(tag_request))
         .with_state(state)
 }
 
-/// Setup request with session ID.
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct SetupEnvelope {
+/// Run a request under its `RequestId`, the client's or a fresh one: every
+/// tracing event of the request carries it, and the response echoes it.
+async fn tag_request(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| RequestId::from_header_value(value.as_bytes()))
+        .unwrap_or_else(RequestId::random);
+    request.extensions_mut().insert(request_id);
+    let span = tracing::info_span!(
+        "request",
+        id = %request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+        session_id = tracing::field::Empty,
+        circuit = tracing::field::Empty,
+    );
+    let start = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+    tracing::info!(
+        parent: &span,
+        status = response.status().as_u16(),
+        duration_ms = start.elapsed().as_millis() as u64,
+        "Request served"
+    );
+    let value =
+        HeaderValue::from_str(&request_id.to_string()).expect("hex is a valid header value");
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+/// Record the session and circuit a request names on its span, so every event
+/// logged while serving it carries them.
+fn record_session(session_id: &str, circuit: Option<&str>) {
+    let span = tracing::Span::current();
+    span.record("session_id", session_id);
+    if let Some(circuit) = circuit {
+        span.record("circuit", circuit);
+    }
+}
+
+/// Setup request with session ID. The request is encoded in place, in the
+/// envelope's codec.
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct SetupEnvelope<R = SetupRequest> {
     pub session_id: String,
-    pub request: Vec<u8>, // bincode-serialized SetupRequest
+    /// Circuit (or for POST /emsm/setup generator set) of the session the
+    /// generators are for; setting up an existing one replaces its generators.
+    pub circuit: String,
+    pub curve: CurveId,
+    pub request: R,
+}
+
+impl<R> SetupEnvelope<R> {
+    fn map<S>(self, f: impl FnOnce(R) -> S) -> SetupEnvelope<S> {
+        SetupEnvelope {
+            session_id: self.session_id,
+            circuit: self.circuit,
+            curve: self.curve,
+            request: f(self.request),
+        }
+    }
+}
+
+/// Which generators a setup envelope uploads.
+#[derive(Clone, Copy)]
+enum SetupEndpoint {
+    /// POST /setup: a circuit's, in a `SetupRequest`.
+    Circuit,
+    /// POST /emsm/setup: a generator set, in a `GeneratorSetRequest`.
+    GeneratorSet,
+    /// POST /setup/patch: a circuit's, as a `SetupPatch` of the ones it has.
+    Patch,
+    /// POST /setup/chunk: part of a circuit's, in a `SetupChunk`.
+    Chunk,
+}
+
+/// The request of a decoded setup envelope.
+enum UploadRequest {
+    Circuit(SetupRequest),
+    GeneratorSet(GeneratorSetRequest),
+    /// Set up as a `Circuit` once the upload is complete.
+    Chunk(SetupChunk),
 }
 
-/// Prove request with session ID.
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct ProveEnvelope {
+/// Prove request with session ID: a `ProveRequest` for POST /prove, an
+/// `MsmRequest` for POST /msm, an `EvalRequest` for POST /emsm/eval or a
+/// `MaliciousEvalRequest` for POST /emsm/eval/malicious, encoded in place.
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ProveEnvelope<R = ProveRequest> {
     pub session_id: String,
-    pub request: Vec<u8>, // bincode-serialized ProveRequest
+    /// Circuit (or for POST /emsm/eval and /emsm/eval/malicious generator set)
+    /// of the session to evaluate against.
+    pub circuit: String,
+    pub curve: CurveId,
+    /// Generators hash from the session's `SetupResponse`.
+    pub generators_hash: [u8; 32],
+    pub request: R,
+}
+
+impl<R> ProveEnvelope<R> {
+    fn map<S>(self, f: impl FnOnce(R) -> S) -> ProveEnvelope<S> {
+        ProveEnvelope {
+            session_id: self.session_id,
+            circuit: self.circuit,
+            curve: self.curve,
+            generators_hash: self.generators_hash,
+            request: f(self.request),
+        }
+    }
+}
+
+/// The codec named by a request's `Content-Type`.
+fn request_codec(headers: &HeaderMap) -> Option<WireCodec> {
+    WireCodec::from_content_type(headers.get(header::CONTENT_TYPE).map(|v| v.as_bytes()))
+}
+
+/// Problem for a `Content-Type` no codec answers to.
+fn unsupported_codec() -> Problem {
+    Problem::new(ErrorCode::UnsupportedMediaType).with_detail(format!(
+        "supported: {}",
+        WireCodec::ALL.map(WireCodec::content_type).join(", ")
+    ))
+}
+
+/// GET /capabilities: the curves and codecs the server accepts.
+#[utoipa::path(
+    get,
+    path = "/capabilities",
+    operation_id = "capabilities",
+    responses((status = 200, body = Capabilities))
+)]
+async fn handle_capabilities(State(state): State<SharedState>) -> Json<Capabilities> {
+    Json(Capabilities {
+        curves: state.read().await.config.curves.clone(),
+        codecs: WireCodec::ALL
+            .map(|codec| codec.content_type().to_string())
+            .to_vec(),
+    })
+}
+
+/// POST /attestation: return TEE evidence bound to the client's 32-byte nonce.
+#[utoipa::path(
+    post,
+    path = "/attestation",
+    operation_id = "attest",
+    request_body(
+        content = [u8; 32],
+        description = "The client's nonce, as 32 raw bytes",
+        content_type = "application/octet-stream"
+    ),
+    responses(
+        (status = 200, body = AttestationReport, content_type = "application/octet-stream"),
+        (status = 400, description = "The body is not 32 bytes"),
+        (status = 404, description = "The server has no attestation provider"),
+    )
+)]
+async fn handle_attestation(
+    State(state): State<SharedState>,
+    body: axum::body::Bytes,
+) -> Result<axum::body::Bytes, StatusCode> {
+    let nonce: [u8; 32] = body
+        .as_ref()
+        .try_into()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let provider = state
+        .read()
+        .await
+        .config
+        .attestation
+        .clone()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let report = provider.attest(&nonce).map_err(|e| {
+        tracing::warn!(error = %e, "Attestation failed");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let bytes = bincode::serialize(&report).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(axum::body::Bytes::from(bytes))
 }
 
 /// POST /setup: receive and store generators for a session.
+/// The setup gate is checked before the body is read. Errors are problem bodies.
+/// Offers of a `WireCodec` or of `ScalarEncoding::Montgomery` are accepted by
+/// echoing them.
+#[utoipa::path(
+    post,
+    path = "/setup",
+    operation_id = "setup",
+    params(
+        ("x-stealthsnark-codec" = Option<String>, Header,
+            description = "Codec offered for prove and MSM requests"),
+        ("x-stealthsnark-scalar-encoding" = Option<String>, Header,
+            description = "Scalar encoding offered for prove and MSM requests"),
+    ),
+    request_body(content(
+        (SetupEnvelope = "application/octet-stream"),
+        (SetupEnvelope = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec, echoing the offers the server accepts",
+            content(
+                (SetupResponse = "application/octet-stream"),
+                (SetupResponse = "application/json"),
+            ),
+            headers(
+                ("x-stealthsnark-codec" = String, description = "The codec offered, if accepted"),
+                ("x-stealthsnark-scalar-encoding" = String,
+                    description = "The scalar encoding offered, if accepted"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
 async fn handle_setup(
     State(state): State<SharedState>,
-    body: axum::body::Bytes,
-) -> StatusCode {
-    let envelope: SetupEnvelope = match bincode::deserialize(&body) {
-        Ok(r) => r,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, HeaderMap, axum::body::Bytes), Problem> {
+    serve_setup(state, headers, body, SetupEndpoint::Circuit).await
+}
+
+/// POST /setup/patch: replace the generators of a circuit that was set up
+/// before with a `SetupPatch` of them, so that a recompiled circuit only
+/// uploads the chunks of its generators that changed (see GET
+/// /commitment/{session_id}/{circuit}/chunks). Admitted and answered like
+/// POST /setup, which a client falls back to when the patch is refused.
+#[utoipa::path(
+    post,
+    path = "/setup/patch",
+    operation_id = "setup_patch",
+    params(
+        ("x-stealthsnark-codec" = Option<String>, Header,
+            description = "Codec offered for prove and MSM requests"),
+        ("x-stealthsnark-scalar-encoding" = Option<String>, Header,
+            description = "Scalar encoding offered for prove and MSM requests"),
+    ),
+    request_body(content(
+        (SetupEnvelope<SetupPatch> = "application/octet-stream"),
+        (SetupEnvelope<SetupPatch> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec, echoing the offers the server accepts",
+            content(
+                (SetupResponse = "application/octet-stream"),
+                (SetupResponse = "application/json"),
+            ),
+            headers(
+                ("x-stealthsnark-codec" = String, description = "The codec offered, if accepted"),
+                ("x-stealthsnark-scalar-encoding" = String,
+                    description = "The scalar encoding offered, if accepted"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_setup_patch(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, HeaderMap, axum::body::Bytes), Problem> {
+    serve_setup(state, headers, body, SetupEndpoint::Patch).await
+}
+
+/// POST /setup/chunk: receive part of a circuit's generators, as a
+/// `SetupChunk`, for uploads larger than `ServerConfig::max_setup_bytes`. Each
+/// part is admitted like POST /setup. The server keeps the chunks until every
+/// one has arrived, answering 202, and then sets the circuit up with the
+/// generators they make and answers like POST /setup. Parts must reach the same
+/// replica, within `STAGED_SETUP_TTL` of each other.
+#[utoipa::path(
+    post,
+    path = "/setup/chunk",
+    operation_id = "setup_chunk",
+    params(
+        ("x-stealthsnark-codec" = Option<String>, Header,
+            description = "Codec offered for prove and MSM requests"),
+        ("x-stealthsnark-scalar-encoding" = Option<String>, Header,
+            description = "Scalar encoding offered for prove and MSM requests"),
+    ),
+    request_body(content(
+        (SetupEnvelope<SetupChunk> = "application/octet-stream"),
+        (SetupEnvelope<SetupChunk> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "The upload is complete; answered like POST /setup",
+            content(
+                (SetupResponse = "application/octet-stream"),
+                (SetupResponse = "application/json"),
+            ),
+            headers(
+                ("x-stealthsnark-codec" = String, description = "The codec offered, if accepted"),
+                ("x-stealthsnark-scalar-encoding" = String,
+                    description = "The scalar encoding offered, if accepted"),
+            )
+        ),
+        (status = 202, description = "The part was kept; the upload is not complete yet"),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_setup_chunk(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, HeaderMap, axum::body::Bytes), Problem> {
+    serve_setup(state, headers, body, SetupEndpoint::Chunk).await
+}
+
+/// POST /emsm/setup: receive and store a generator set for POST /emsm/eval,
+/// e.g. the commitment key of a Pedersen commitment, on any curve of
+/// `ServerConfig::curves`. Admitted like POST /setup; generator sets count
+/// towards the same tenant limits.
+#[utoipa::path(
+    post,
+    path = "/emsm/setup",
+    operation_id = "emsm_setup",
+    params(
+        ("x-stealthsnark-codec" = Option<String>, Header,
+            description = "Codec offered for eval requests"),
+    ),
+    request_body(content(
+        (SetupEnvelope<GeneratorSetRequest> = "application/octet-stream"),
+        (SetupEnvelope<GeneratorSetRequest> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec",
+            content(
+                (SetupResponse = "application/octet-stream"),
+                (SetupResponse = "application/json"),
+            ),
+            headers(
+                ("x-stealthsnark-codec" = String, description = "The codec offered, if accepted"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_emsm_setup(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, HeaderMap, axum::body::Bytes), Problem> {
+    serve_setup(state, headers, body, SetupEndpoint::GeneratorSet).await
+}
+
+/// Admit a setup envelope and store the generators it uploads, auditing the
+/// outcome.
+async fn serve_setup(
+    state: SharedState,
+    headers: HeaderMap,
+    body: Body,
+    endpoint: SetupEndpoint,
+) -> Result<(StatusCode, HeaderMap, axum::body::Bytes), Problem> {
+    let (gate_result, tenant, max_setup_bytes, audit, curves) = {
+        let state = state.read().await;
+        (
+            state.config.setup_gate.check(&headers, &state.spent_pow),
+            state.tenants.resolve(&headers),
+            state.config.max_setup_bytes,
+            state.config.audit.clone(),
+            state.config.curves.clone(),
+        )
+    };
+    let reject = |session_id: Option<&str>, problem: Problem| {
+        if let Some(audit) = &audit {
+            audit.record(AuditEvent::SetupRejected {
+                session_id: session_id.map(String::from),
+                status: problem.status,
+            });
+        }
+        problem
     };
 
-    let request: SetupRequest = match bincode::deserialize(&envelope.request) {
-        Ok(r) => r,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    let admission = match gate_result {
+        Ok(admission) => admission,
+        Err(StatusCode::UNAUTHORIZED) => {
+            return Err(reject(None, Problem::new(ErrorCode::CredentialMissing)))
+        }
+        Err(_) => return Err(reject(None, Problem::new(ErrorCode::CredentialRejected))),
     };
+    let tenant = tenant.map_err(|code| reject(None, Problem::new(code)))?;
 
-    let h_gens: Vec<G1Affine> = match ark_vec_from_bytes(&request.h_generators) {
-        Ok(v) => v,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    let codec = request_codec(&headers).ok_or_else(|| reject(None, unsupported_codec()))?;
+    let body = match axum::body::to_bytes(body, max_setup_bytes).await {
+        Ok(b) => b,
+        Err(_) => return Err(reject(None, Problem::new(ErrorCode::BodyTooLarge))),
     };
-    let l_gens: Vec<G1Affine> = match ark_vec_from_bytes(&request.l_generators) {
-        Ok(v) => v,
-        Err(_) => return StatusCode::BAD_REQUEST,
+
+    let envelope = match endpoint {
+        SetupEndpoint::Circuit => codec
+            .decode::<SetupEnvelope>(&body)
+            .map(|envelope| envelope.map(UploadRequest::Circuit)),
+        SetupEndpoint::GeneratorSet => codec
+            .decode::<SetupEnvelope<GeneratorSetRequest>>(&body)
+            .map(|envelope| envelope.map(UploadRequest::GeneratorSet)),
+        SetupEndpoint::Patch => match codec.decode::<SetupEnvelope<SetupPatch>>(&body) {
+            Ok(envelope) => {
+                let tenant = tenant.as_ref().map(|t| t.id.as_str());
+                let key = SessionKey::new(tenant, &envelope.session_id);
+                let request = patched_request(&state, &key, &headers, &envelope)
+                    .await
+                    .map_err(|problem| reject(Some(&envelope.session_id), problem))?;
+                Ok(envelope.map(|_| UploadRequest::Circuit(request)))
+            }
+            Err(e) => Err(e),
+        },
+        SetupEndpoint::Chunk => codec
+            .decode::<SetupEnvelope<SetupChunk>>(&body)
+            .map(|envelope| envelope.map(UploadRequest::Chunk)),
     };
-    let a_gens: Vec<G1Affine> = match ark_vec_from_bytes(&request.a_generators) {
-        Ok(v) => v,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    let envelope = match envelope {
+        Ok(r) => r,
+        Err(_) => return Err(reject(None, Problem::malformed("envelope"))),
     };
-    let b_g1_gens: Vec<G1Affine> = match ark_vec_from_bytes(&request.b_g1_generators) {
-        Ok(v) => v,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    let session_id = Some(envelope.session_id.as_str());
+    record_session(&envelope.session_id, Some(&envelope.circuit));
+
+    // A proof-of-work solution only admits the session it was computed for
+    if admission
+        .bound_session
+        .is_some_and(|id| id != envelope.session_id)
+    {
+        let problem = Problem::new(ErrorCode::CredentialRejected)
+            .with_detail("the proof of work was computed for another session");
+        return Err(reject(session_id, problem));
+    }
+
+    if envelope.circuit.is_empty() || envelope.circuit.len() > MAX_CIRCUIT_NAME_LEN {
+        let problem = Problem::new(ErrorCode::InvalidCircuitName)
+            .with_field("circuit")
+            .with_detail(format!(
+                "must be 1 to {MAX_CIRCUIT_NAME_LEN} bytes, got {}",
+                envelope.circuit.len()
+            ));
+        return Err(reject(session_id, problem));
+    }
+
+    if !curves.contains(&envelope.curve) {
+        tracing::warn!(curve = ?envelope.curve, "Setup rejected for an unserved curve");
+        let problem = Problem::new(ErrorCode::CurveMismatch)
+            .with_field("curve")
+            .with_detail(format!(
+                "the server does not serve {:?}; it serves {curves:?}",
+                envelope.curve
+            ));
+        return Err(reject(session_id, problem));
+    }
+    let circuit = matches!(
+        envelope.request,
+        UploadRequest::Circuit(_) | UploadRequest::Chunk(_)
+    );
+    if circuit && !envelope.curve.has_pairing() {
+        tracing::warn!(curve = ?envelope.curve, "Setup rejected for a curve without a pairing");
+        let problem = Problem::new(ErrorCode::CurveMismatch)
+            .with_field("curve")
+            .with_detail(format!(
+                "Groth16 circuits need a pairing, which {:?} does not have",
+                envelope.curve
+            ));
+        return Err(reject(session_id, problem));
+    }
+
+    // A part of a chunked setup is kept until the upload is complete, which is
+    // then set up like the `SetupRequest` of its chunks
+    let envelope = match envelope.request {
+        UploadRequest::Chunk(part) => {
+            let tenant = tenant.as_ref().map(|t| t.id.as_str());
+            let key = SessionKey::new(tenant, &envelope.session_id);
+            let staged = stage_chunk(
+                &state,
+                key,
+                &envelope.circuit,
+                &admission.account,
+                &headers,
+                part,
+            )
+            .await
+            .map_err(|problem| reject(Some(&envelope.session_id), problem))?;
+            match staged {
+                Some(request) => SetupEnvelope {
+                    request: UploadRequest::Circuit(request),
+                    ..envelope
+                },
+                None => {
+                    return Ok((
+                        StatusCode::ACCEPTED,
+                        HeaderMap::new(),
+                        axum::body::Bytes::new(),
+                    ))
+                }
+            }
+        }
+        request => SetupEnvelope {
+            request,
+            ..envelope
+        },
     };
-    let b_g2_gens: Vec<G2Affine> = match ark_vec_from_bytes(&request.b_g2_generators) {
-        Ok(v) => v,
-        Err(_) => return StatusCode::BAD_REQUEST,
+    let session_id = Some(envelope.session_id.as_str());
+
+    let upload = match &envelope.request {
+        UploadRequest::Circuit(request) => CircuitState::decode(envelope.curve, request)
+            .map(|circuit| Upload::Circuit(Box::new(circuit))),
+        UploadRequest::GeneratorSet(request) => {
+            GeneratorSet::decode(envelope.curve, request).map(Upload::GeneratorSet)
+        }
+        UploadRequest::Chunk(_) => unreachable!("chunked setups are assembled above"),
+    }
+    .map_err(|(field, e)| {
+        tracing::warn!(field, error = %e, "Setup rejected");
+        reject(
+            session_id,
+            Problem::malformed(field).with_detail(e.to_string()),
+        )
+    })?;
+    let sizes = upload.sizes();
+    match &upload {
+        Upload::Circuit(circuit) => tracing::info!(
+            h = sizes[0],
+            l = sizes[1],
+            a = sizes[2],
+            b_g1 = sizes[3],
+            b_g2 = sizes[4],
+            public = circuit.public_generators.as_ref().map_or(0, |g| g.a.len()),
+            "Setup received"
+        ),
+        Upload::GeneratorSet(set) => tracing::info!(
+            curve = ?set.curve(),
+            len = sizes[0],
+            "Generator set received"
+        ),
+    }
+    let generators_hash = upload.generators_hash();
+
+    let key = SessionKey::new(tenant.as_ref().map(|t| t.id.as_str()), &envelope.session_id);
+    // A session set up on another replica keeps its account
+    hydrate(&state, &key, None, &headers)
+        .await
+        .map_err(|problem| reject(session_id, problem))?;
+    let store = state.read().await.config.store.clone();
+    let mut state = state.write().await;
+    state.evict_expired();
+    if let Some(tenant) = &tenant {
+        let limits = state.check_tenant_limits(tenant, &key, &envelope.circuit, &upload);
+        if let Err(exceeded) = limits {
+            drop(state);
+            tracing::warn!(error = %exceeded, "Setup rejected");
+            return Err(reject(session_id, exceeded.into()));
+        }
+    }
+    let session = state
+        .sessions
+        .entry(key.clone())
+        .or_insert_with(|| SessionState::new(admission.account.clone(), None));
+    // Only the session's own account may add or replace its circuits
+    if session.account != admission.account {
+        drop(state);
+        return Err(reject(
+            session_id,
+            Problem::new(ErrorCode::SessionForbidden),
+        ));
+    }
+    // and only with the token its first setup returned
+    let session_token = match session.token_hash {
+        None => {
+            let token = SessionToken::random();
+            session.token_hash = Some(token.hash());
+            token
+        }
+        Some(token_hash) => match session_token(&headers) {
+            Some(token) if token.hash() == token_hash => token,
+            _ => {
+                drop(state);
+                tracing::warn!("Setup rejected without the session's token");
+                let problem = Problem::new(ErrorCode::SessionTokenRejected);
+                return Err(reject(session_id, problem));
+            }
+        },
+    };
+    session.touch();
+    let is_circuit = matches!(upload, Upload::Circuit(_));
+    let replaced = session.insert(envelope.circuit.clone(), upload);
+    if is_circuit {
+        let circuit = session.circuits[&envelope.circuit].clone();
+        state.spawn_table_build(&circuit);
+    }
+    drop(state);
+
+    if let Some(store) = store {
+        let session = StoredSession {
+            account_hash: admission
+                .account
+                .as_deref()
+                .map(|account| sha256(account.as_bytes())),
+            token_hash: Some(session_token.hash()),
+        };
+        let objects = stored_objects(&key, &envelope, &session);
+        tokio::task::spawn_blocking(move || {
+            objects
+                .iter()
+                .try_for_each(|(object, bytes)| store.put(object, bytes))
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+        .map_err(|e| reject(session_id, store_problem(e)))?;
+    }
+
+    if let Some(audit) = &audit {
+        audit.record(AuditEvent::SessionCreated {
+            session_id: envelope.session_id,
+            circuit: envelope.circuit,
+            generators_hash: to_hex(&generators_hash),
+            sizes,
+            replaced,
+        });
+    }
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(codec.content_type()),
+    );
+    if let Some(offer) = headers.get(CODEC_HEADER) {
+        if WireCodec::from_name(offer.as_bytes()).is_some() {
+            response_headers.insert(CODEC_HEADER, offer.clone());
+        }
+    }
+    if let Some(offer) = headers.get(SCALAR_ENCODING_HEADER) {
+        if ScalarEncoding::from_header_value(offer.as_bytes()).is_some() {
+            response_headers.insert(SCALAR_ENCODING_HEADER, offer.clone());
+        }
+    }
+    let response = SetupResponse {
+        generators_hash,
+        session_token,
     };
+    let bytes = codec
+        .encode(&response)
+        .map_err(|_| Problem::new(ErrorCode::Internal))?;
+    Ok((
+        StatusCode::OK,
+        response_headers,
+        axum::body::Bytes::from(bytes),
+    ))
+}
 
+/// The objects a setup leaves in `ServerConfig::store`: the session and, for a
+/// circuit, its generators and the circuit, by object key. Generator sets stay
+/// on the replica they were uploaded to.
+fn stored_objects(
+    key: &SessionKey,
+    envelope: &SetupEnvelope<UploadRequest>,
+    session: &StoredSession,
+) -> Vec<(String, Vec<u8>)> {
+    let tenant = key.tenant.as_deref();
+    let encoded = "stored objects always serialize";
+    let mut objects = vec![(
+        session_object(tenant, &key.session_id),
+        serde_json::to_vec(session).expect(encoded),
+    )];
+    if let UploadRequest::Circuit(request) = &envelope.request {
+        let circuit = StoredCircuit {
+            curve: envelope.curve,
+            generators_hash: request.generators_hash(),
+            key_fingerprint: request.key_fingerprint,
+        };
+        objects.push((
+            generators_object(&circuit.generators_hash),
+            bincode::serialize(request).expect(encoded),
+        ));
+        objects.push((
+            circuit_object(tenant, &key.session_id, &envelope.circuit),
+            serde_json::to_vec(&circuit).expect(encoded),
+        ));
+    }
+    objects
+}
+
+/// The `SetupRequest` a POST /setup/patch envelope makes of the generators of
+/// its circuit, which must be the ones the patch was made against.
+async fn patched_request(
+    state: &SharedState,
+    key: &SessionKey,
+    headers: &HeaderMap,
+    envelope: &SetupEnvelope<SetupPatch>,
+) -> Result<SetupRequest, Problem> {
+    hydrate(state, key, Some(&envelope.circuit), headers).await?;
+    let base = {
+        let state = state.read().await;
+        let session = state
+            .live_session(key)
+            .ok_or_else(|| Problem::new(ErrorCode::UnknownSession))?;
+        session
+            .circuits
+            .get(&envelope.circuit)
+            .cloned()
+            .ok_or_else(|| Problem::new(ErrorCode::UnknownCircuit).with_field("circuit"))?
+    };
+    CurveMismatch::check(base.curve, envelope.curve).map_err(|mismatch| {
+        Problem::new(ErrorCode::CurveMismatch)
+            .with_field("curve")
+            .with_detail(mismatch.to_string())
+    })?;
+    let patch = &envelope.request;
+    if patch.base_hash != base.generators_hash {
+        return Err(Problem::new(ErrorCode::GeneratorsMismatch).with_field("base_hash"));
+    }
+    let request = apply_patch(&base, patch).map_err(|e| {
+        tracing::warn!(error = %e, "Setup patch rejected");
+        Problem::malformed("chunks").with_detail(e)
+    })?;
+    if request.generators_hash() != patch.generators_hash {
+        return Err(Problem::new(ErrorCode::GeneratorsMismatch)
+            .with_field("generators_hash")
+            .with_detail("the patched generators have another hash"));
+    }
     tracing::info!(
-        "Setup [session={}]: h={}, l={}, a={}, b_g1={}, b_g2={}",
-        envelope.session_id,
-        h_gens.len(),
-        l_gens.len(),
-        a_gens.len(),
-        b_g1_gens.len(),
-        b_g2_gens.len()
+        chunks = patch.chunks.len(),
+        bytes = patch.points_len(),
+        "Setup patch applied"
     );
+    Ok(request)
+}
 
-    let session = SessionState {
-        h_generators: h_gens,
-        l_generators: l_gens,
-        a_generators: a_gens,
-        b_g1_generators: b_g1_gens,
-        b_g2_generators: b_g2_gens,
+/// The generators of `base` with the chunks of `patch` in place of its own:
+/// every chunk of the patched lengths the patch does not carry must be one
+/// `base` has in full.
+fn apply_patch(base: &CircuitState, patch: &SetupPatch) -> Result<SetupRequest, String> {
+    let mut chunks: [HashMap<u64, &[u8]>; 5] = Default::default();
+    for chunk in &patch.chunks {
+        let i = MsmKind::ALL
+            .iter()
+            .position(|kind| *kind == chunk.kind)
+            .ok_or_else(|| format!("{:?} generators cannot be patched", chunk.kind))?;
+        if !chunk.checksum_matches() {
+            return Err(format!(
+                "chunk {} of {:?} does not match its checksum",
+                chunk.index, chunk.kind
+            ));
+        }
+        if chunks[i].insert(chunk.index, &chunk.points).is_some() {
+            return Err(format!(
+                "chunk {} of {:?} sent twice",
+                chunk.index, chunk.kind
+            ));
+        }
+    }
+
+    let base_parts = base.encoded_generators();
+    let sizes = generator_point_sizes();
+    let mut parts: [Vec<u8>; 5] = Default::default();
+    for (i, kind) in MsmKind::ALL.into_iter().enumerate() {
+        let len = patch.lens[i];
+        check_vec_len(len).map_err(|e| e.to_string())?;
+        let base_body = &base_parts[i][8..];
+        let mut encoded = len.to_le_bytes().to_vec();
+        for index in 0..len.div_ceil(GENERATOR_CHUNK as u64) {
+            let start = index as usize * GENERATOR_CHUNK;
+            let points = (len as usize - start).min(GENERATOR_CHUNK) * sizes[i];
+            let chunk = match chunks[i].remove(&index) {
+                Some(chunk) => chunk,
+                None => base_body
+                    .get(start * sizes[i]..)
+                    .and_then(|rest| rest.get(..points))
+                    .ok_or_else(|| format!("chunk {index} of {kind:?} is missing"))?,
+            };
+            if chunk.len() != points {
+                return Err(format!(
+                    "chunk {index} of {kind:?} takes {points} bytes, got {}",
+                    chunk.len()
+                ));
+            }
+            encoded.extend_from_slice(chunk);
+        }
+        if let Some(index) = chunks[i].keys().next() {
+            return Err(format!(
+                "chunk {index} of {kind:?} is past its {len} points"
+            ));
+        }
+        parts[i] = encoded;
+    }
+    let [h_generators, l_generators, a_generators, b_g1_generators, b_g2_generators] = parts;
+    Ok(SetupRequest {
+        h_generators,
+        l_generators,
+        a_generators,
+        b_g1_generators,
+        b_g2_generators,
+        public_generators: patch.public_generators.clone(),
+        key_fingerprint: patch.key_fingerprint,
+    })
+}
+
+/// Keep the chunks of `part` for the chunked setup of `circuit` in the session
+/// at `key`, and return the `SetupRequest` they make once every chunk of the
+/// upload has arrived. A part of another upload than the one kept (another
+/// generators hash, chunk length or point counts) starts over. Parts for an
+/// existing session must come from its account with its token, and parts of an
+/// upload in progress from the account that started it.
+async fn stage_chunk(
+    state: &SharedState,
+    key: SessionKey,
+    circuit: &str,
+    account: &Option<String>,
+    headers: &HeaderMap,
+    part: SetupChunk,
+) -> Result<Option<SetupRequest>, Problem> {
+    let (chunk_len, lens) = (part.chunk_len, part.lens);
+    if chunk_len == 0 {
+        return Err(Problem::malformed("chunk_len").with_detail("chunks of 0 points"));
+    }
+    for len in lens {
+        check_vec_len(len).map_err(|e| Problem::malformed("lens").with_detail(e.to_string()))?;
+    }
+
+    let mut guard = state.write().await;
+    let state = &mut *guard;
+    state.evict_expired();
+    if let Some(session) = state.live_session(&key) {
+        if session.account != *account {
+            return Err(Problem::new(ErrorCode::SessionForbidden));
+        }
+        let token = session_token(headers).map(|token| token.hash());
+        if session.token_hash.is_some_and(|hash| token != Some(hash)) {
+            return Err(Problem::new(ErrorCode::SessionTokenRejected));
+        }
+    }
+    let slot = (key, circuit.to_string());
+    let upload = (part.generators_hash, chunk_len, lens);
+    let staged = state
+        .staged
+        .entry(slot.clone())
+        .or_insert_with(|| StagedSetup::new(upload, account.clone()));
+    if staged.account != *account {
+        return Err(Problem::new(ErrorCode::SessionForbidden)
+            .with_detail("another account's chunked setup of this circuit is in progress"));
+    }
+    if staged.upload != upload {
+        *staged = StagedSetup::new(upload, account.clone());
+    }
+    staged.touched = Instant::now();
+
+    let sizes = generator_point_sizes();
+    for chunk in part.chunks {
+        let malformed = |detail: String| Problem::malformed("chunks").with_detail(detail);
+        let (index, kind) = (chunk.index, chunk.kind);
+        let i = MsmKind::ALL
+            .iter()
+            .position(|other| *other == kind)
+            .ok_or_else(|| {
+                malformed(format!("{kind:?} generators cannot be uploaded in chunks"))
+            })?;
+        if !chunk.checksum_matches() {
+            return Err(malformed(format!(
+                "chunk {index} of {kind:?} does not match its checksum"
+            )));
+        }
+        if index >= lens[i].div_ceil(chunk_len) {
+            let len = lens[i];
+            return Err(malformed(format!(
+                "chunk {index} of {kind:?} is past its {len} points"
+            )));
+        }
+        let points = (lens[i] - index * chunk_len).min(chunk_len) as usize * sizes[i];
+        if chunk.points.len() != points {
+            return Err(malformed(format!(
+                "chunk {index} of {kind:?} takes {points} bytes, got {}",
+                chunk.points.len()
+            )));
+        }
+        staged.chunks[i].insert(index, chunk.points);
+    }
+    if !staged.complete() {
+        tracing::debug!(circuit, "Setup chunks kept");
+        return Ok(None);
+    }
+    let staged = state
+        .staged
+        .remove(&slot)
+        .expect("the upload was kept above");
+    drop(guard);
+
+    let mut parts = staged.chunks.into_iter().zip(lens).map(|(chunks, len)| {
+        let mut encoded = len.to_le_bytes().to_vec();
+        chunks
+            .into_values()
+            .for_each(|points| encoded.extend_from_slice(&points));
+        encoded
+    });
+    let mut next = || parts.next().expect("five generator fields");
+    let request = SetupRequest {
+        h_generators: next(),
+        l_generators: next(),
+        a_generators: next(),
+        b_g1_generators: next(),
+        b_g2_generators: next(),
+        public_generators: part.public_generators,
+        key_fingerprint: part.key_fingerprint,
+    };
+    if request.generators_hash() != part.generators_hash {
+        return Err(Problem::new(ErrorCode::GeneratorsMismatch)
+            .with_field("generators_hash")
+            .with_detail("the chunks make generators with another hash"));
+    }
+    Ok(Some(request))
+}
+
+fn store_problem(e: std::io::Error) -> Problem {
+    tracing::error!(error = %e, "Object store failed");
+    Problem::new(ErrorCode::Internal).with_detail("the object store failed")
+}
+
+/// On a server with `ServerConfig::store`, fetch the session at `key` (and its
+/// circuit `circuit`, if named) from the store unless this replica holds it.
+/// A session set up with an API key is only taken over for a request carrying
+/// that key. Sessions the store does not hold either are left for the caller to
+/// report unknown.
+async fn hydrate(
+    state: &SharedState,
+    key: &SessionKey,
+    circuit: Option<&str>,
+    headers: &HeaderMap,
+) -> Result<(), Problem> {
+    let store = {
+        let state = state.read().await;
+        let Some(store) = state.config.store.clone() else {
+            return Ok(());
+        };
+        let held = state
+            .live_session(key)
+            .is_some_and(|session| circuit.is_none_or(|name| session.circuits.contains_key(name)));
+        if held {
+            return Ok(());
+        }
+        store
+    };
+    let (fetch_key, fetch_circuit) = (key.clone(), circuit.map(String::from));
+    let fetched = tokio::task::spawn_blocking(move || {
+        fetch_session(store.as_ref(), &fetch_key, fetch_circuit.as_deref())
+    })
+    .await
+    .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+    .map_err(store_problem)?;
+    let Some((stored, fetched_circuit)) = fetched else {
+        return Ok(());
+    };
+    let account = match stored.account_hash {
+        None => None,
+        Some(account_hash) => {
+            let api_key = headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|api_key| sha256(api_key.as_bytes()) == account_hash)
+                .ok_or_else(|| Problem::new(ErrorCode::SessionForbidden))?;
+            Some(api_key.to_string())
+        }
     };
 
     let mut state = state.write().await;
-    state.sessions.insert(envelope.session_id, session);
+    state.evict_expired();
+    let session = state
+        .sessions
+        .entry(key.clone())
+        .or_insert_with(|| SessionState::new(account, stored.token_hash));
+    session.touch();
+    if let (Some(name), Some(fetched)) = (circuit, fetched_circuit) {
+        if let Entry::Vacant(entry) = session.circuits.entry(name.to_string()) {
+            let circuit = entry.insert(Arc::new(fetched)).clone();
+            state.spawn_table_build(&circuit);
+        }
+    }
+    tracing::info!("Session fetched from the object store");
+    Ok(())
+}
+
+/// The session at `key` in `store`, with its circuit `circuit` if it has one of
+/// that name.
+fn fetch_session(
+    store: &dyn ObjectStore,
+    key: &SessionKey,
+    circuit: Option<&str>,
+) -> std::io::Result<Option<(StoredSession, Option<CircuitState>)>> {
+    let tenant = key.tenant.as_deref();
+    let corrupt = |object: &str| {
+        let message = format!("corrupt object {object}");
+        std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+    };
+    let object = session_object(tenant, &key.session_id);
+    let Some(bytes) = store.get(&object)? else {
+        return Ok(None);
+    };
+    let session: StoredSession = serde_json::from_slice(&bytes).map_err(|_| corrupt(&object))?;
+    let Some(circuit) = circuit else {
+        return Ok(Some((session, None)));
+    };
+    let object = circuit_object(tenant, &key.session_id, circuit);
+    let Some(bytes) = store.get(&object)? else {
+        return Ok(Some((session, None)));
+    };
+    let stored: StoredCircuit = serde_json::from_slice(&bytes).map_err(|_| corrupt(&object))?;
 
-    StatusCode::OK
+    let object = generators_object(&stored.generators_hash);
+    let bytes = store.get(&object)?.ok_or_else(|| corrupt(&object))?;
+    let request: SetupRequest = bincode::deserialize(&bytes).map_err(|_| corrupt(&object))?;
+    drop(bytes);
+    if request.generators_hash() != stored.generators_hash {
+        return Err(corrupt(&object));
+    }
+    let mut circuit =
+        CircuitState::decode(stored.curve, &request).map_err(|_| corrupt(&object))?;
+    circuit.key_fingerprint = stored.key_fingerprint;
+    Ok(Some((session, Some(circuit))))
 }
 
-/// POST /prove: evaluate 5 MSMs on masked vectors for a session.
-async fn handle_prove(
+/// POST /keepalive: mark a session active so it outlives a long client-side pause
+/// between setup and prove. Sessions set up with an API key require the same key.
+#[utoipa::path(
+    post,
+    path = "/keepalive",
+    operation_id = "keepalive",
+    request_body(content = KeepaliveRequest, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = KeepaliveResponse, content_type = "application/octet-stream"),
+        (status = 403, description = "The session was set up with another API key"),
+        (status = 404, description = "The session was never set up, or expired"),
+    )
+)]
+async fn handle_keepalive(
     State(state): State<SharedState>,
+    headers: HeaderMap,
     body: axum::body::Bytes,
 ) -> Result<axum::body::Bytes, StatusCode> {
-    let envelope: ProveEnvelope =
+    let request: KeepaliveRequest =
         bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    record_session(&request.session_id, None);
+    let key = state
+        .read()
+        .await
+        .session_key(&headers, &request.session_id)
+        .map_err(ErrorCode::status)?;
+    hydrate(&state, &key, None, &headers)
+        .await
+        .map_err(|problem| problem.code.status())?;
+    let state = state.read().await;
+    let session = state.live_session(&key).ok_or(StatusCode::NOT_FOUND)?;
+    session.check_account(&headers)?;
+    session.check_token(&headers).map_err(ErrorCode::status)?;
+    session.touch();
 
-    let request: ProveRequest =
-        bincode::deserialize(&envelope.request).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let tables_ready = match state.config.fixed_base_window {
+        Some(_) => session
+            .circuits
+            .iter()
+            .map(|(name, circuit)| (name.clone(), circuit.tables.get().is_some()))
+            .collect(),
+        None => BTreeMap::new(),
+    };
+    let response = KeepaliveResponse {
+        expires_in_ms: session
+            .remaining(state.config.session_ttl)
+            .map(|left| left.as_millis() as u64),
+        tables_ready,
+    };
+    let bytes = bincode::serialize(&response).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(axum::body::Bytes::from(bytes))
+}
 
-    let state = state.read().await;
-    let session = state
-        .sessions
-        .get(&envelope.session_id)
-        .ok_or(StatusCode::PRECONDITION_FAILED)?;
-
-    // Deserialize masked scalars (fallible)
-    let v_h: Vec<Fr> = ark_vec_from_bytes(&request.v_h).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let v_l: Vec<Fr> = ark_vec_from_bytes(&request.v_l).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let v_a: Vec<Fr> = ark_vec_from_bytes(&request.v_a).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let v_b_g1: Vec<Fr> =
-        ark_vec_from_bytes(&request.v_b_g1).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let v_b_g2: Vec<Fr> =
-        ark_vec_from_bytes(&request.v_b_g2).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    tracing::info!("Prove [session={}]: computing 5 MSMs", envelope.session_id);
-
-    // Compute MSMs (fallible — length mismatch returns 400 instead of panic)
-    let em_h = Pedersen::<G1>::from_generators(session.h_generators.clone())
-        .commit(&v_h)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let em_l = Pedersen::<G1>::from_generators(session.l_generators.clone())
-        .commit(&v_l)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let em_a = Pedersen::<G1>::from_generators(session.a_generators.clone())
-        .commit(&v_a)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let em_b_g1 = Pedersen::<G1>::from_generators(session.b_g1_generators.clone())
-        .commit(&v_b_g1)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let em_b_g2 = Pedersen::<G2>::from_generators(session.b_g2_generators.clone())
-        .commit(&v_b_g2)
+/// POST /fft: one step of a delegated witness map over padded vectors. Like
+/// prove, it needs a live session.
+#[utoipa::path(
+    post,
+    path = "/fft",
+    operation_id = "fft",
+    request_body(content = FftRequest, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = FftResponse, content_type = "application/octet-stream"),
+        (status = 400, description = "The vectors failed to decode or have unsupported lengths"),
+        (status = 401, description = "The server has tenants and no API key was sent"),
+        (status = 403, description = "The API key belongs to no tenant"),
+        (status = 412, description = "The session was never set up, or expired"),
+        (status = 413, description = "The body is larger than the server accepts"),
+    )
+)]
+async fn handle_fft(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<axum::body::Bytes, StatusCode> {
+    let max_prove_bytes = state.read().await.config.max_prove_bytes;
+    let body = axum::body::to_bytes(body, max_prove_bytes)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let request: FftRequest = bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    drop(body);
+    record_session(&request.session_id, None);
+    let key = state
+        .read()
+        .await
+        .session_key(&headers, &request.session_id)
+        .map_err(ErrorCode::status)?;
+    hydrate(&state, &key, None, &headers)
+        .await
+        .map_err(|problem| problem.code.status())?;
+    {
+        let state = state.read().await;
+        let session = state
+            .live_session(&key)
+            .ok_or(StatusCode::PRECONDITION_FAILED)?;
+        session.check_token(&headers).map_err(ErrorCode::status)?;
+        session.touch();
+    }
+    if request.vectors.is_empty() || request.vectors.len() > 3 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut vectors = request
+        .vectors
+        .iter()
+        .map(|bytes| ark_vec_from_bytes::<Fr>(bytes))
+        .collect::<Result<Vec<_>, _>>()
         .map_err(|_| StatusCode::BAD_REQUEST)?;
+    if vectors.iter().any(|v| v.len() != vectors[0].len()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    tracing::info!(
+        step = ?request.step,
+        vectors = vectors.len(),
+        len = vectors[0].len(),
+        "Computing FFT"
+    );
+    let step = request.step;
+    let vectors = tokio::task::spawn_blocking(move || {
+        for v in &mut vectors {
+            step.apply(v)?;
+        }
+        Ok(vectors)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_: ark_relations::r1cs::SynthesisError| StatusCode::BAD_REQUEST)?;
+
+    let response = FftResponse {
+        vectors: vectors.iter().map(|v| ark_vec_to_bytes(v)).collect(),
+    };
+    let bytes = bincode::serialize(&response).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(axum::body::Bytes::from(bytes))
+}
+
+/// Which evaluation a prove envelope asks for.
+#[derive(Clone, Copy)]
+enum ProveEndpoint {
+    /// POST /prove: all five MSMs of a `ProveRequest`.
+    Prove,
+    /// POST /msm: the one MSM of an `MsmRequest`.
+    Msm,
+    /// POST /emsm/eval: the MSM of an `EvalRequest` against a generator set.
+    Eval,
+    /// POST /emsm/eval/malicious: the two MSMs of a `MaliciousEvalRequest`.
+    MaliciousEval,
+}
+
+/// The request of a decoded prove envelope.
+enum Evaluation {
+    Prove(ProveRequest),
+    Msm(MsmRequest),
+    Eval(EvalRequest),
+    MaliciousEval(MaliciousEvalRequest),
+}
+
+/// A prove or MSM response in the request's codec.
+type EncodedResponse = ([(header::HeaderName, &'static str); 1], axum::body::Bytes);
+
+/// POST /prove: evaluate 5 MSMs on masked vectors for a session. Errors are
+/// problem bodies.
+#[utoipa::path(
+    post,
+    path = "/prove",
+    operation_id = "prove",
+    params(
+        ("x-stealthsnark-scalar-encoding" = Option<String>, Header,
+            description = "Scalar encoding agreed on at setup, if not canonical"),
+        ("x-stealthsnark-qos" = Option<String>, Header,
+            description = "`interactive` (the default) or `batch`"),
+    ),
+    request_body(content(
+        (ProveEnvelope = "application/octet-stream"),
+        (ProveEnvelope = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec",
+            content(
+                (ProveResponse = "application/octet-stream"),
+                (ProveResponse = "application/json"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_prove(
+    State(state): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<EncodedResponse, Problem> {
+    serve_prove(state, request_id, &headers, body, ProveEndpoint::Prove).await
+}
+
+/// POST /msm: evaluate one MSM of a prove request split with
+/// `ProveRequest::into_msms`. Clients send the five concurrently, so they can be
+/// multiplexed over one HTTP/2 connection or spread across server instances.
+#[utoipa::path(
+    post,
+    path = "/msm",
+    operation_id = "msm",
+    params(
+        ("x-stealthsnark-scalar-encoding" = Option<String>, Header,
+            description = "Scalar encoding agreed on at setup, if not canonical"),
+        ("x-stealthsnark-qos" = Option<String>, Header,
+            description = "`interactive` (the default) or `batch`"),
+    ),
+    request_body(content(
+        (ProveEnvelope<MsmRequest> = "application/octet-stream"),
+        (ProveEnvelope<MsmRequest> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec",
+            content(
+                (MsmResponse = "application/octet-stream"),
+                (MsmResponse = "application/json"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_msm(
+    State(state): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<EncodedResponse, Problem> {
+    serve_prove(state, request_id, &headers, body, ProveEndpoint::Msm).await
+}
+
+/// POST /emsm/eval: evaluate the MSM of a masked vector against a generator set
+/// of POST /emsm/setup. The client unmasks the result itself (`emsm::decrypt`);
+/// `EmsmClient::commit` does both ends.
+#[utoipa::path(
+    post,
+    path = "/emsm/eval",
+    operation_id = "emsm_eval",
+    params(
+        ("x-stealthsnark-qos" = Option<String>, Header,
+            description = "`interactive` (the default) or `batch`"),
+    ),
+    request_body(content(
+        (ProveEnvelope<EvalRequest> = "application/octet-stream"),
+        (ProveEnvelope<EvalRequest> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec",
+            content(
+                (EvalResponse = "application/octet-stream"),
+                (EvalResponse = "application/json"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_emsm_eval(
+    State(state): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<EncodedResponse, Problem> {
+    serve_prove(state, request_id, &headers, body, ProveEndpoint::Eval).await
+}
+
+/// POST /emsm/eval/malicious: evaluate the MSMs of the main and check vectors
+/// of `malicious::malicious_encrypt` against a generator set, so that the
+/// client can detect a wrong result (`malicious::malicious_decrypt`).
+/// `EmsmClient::commit_malicious` does both ends.
+#[utoipa::path(
+    post,
+    path = "/emsm/eval/malicious",
+    operation_id = "emsm_eval_malicious",
+    params(
+        ("x-stealthsnark-qos" = Option<String>, Header,
+            description = "`interactive` (the default) or `batch`"),
+    ),
+    request_body(content(
+        (ProveEnvelope<MaliciousEvalRequest> = "application/octet-stream"),
+        (ProveEnvelope<MaliciousEvalRequest> = "application/json"),
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Answered in the request's codec",
+            content(
+                (MaliciousEvalResponse = "application/octet-stream"),
+                (MaliciousEvalResponse = "application/json"),
+            )
+        ),
+        (
+            status = "4XX",
+            description = "Refused, with the reason as a problem body",
+            body = Problem,
+            content_type = "application/problem+json"
+        ),
+    )
+)]
+async fn handle_emsm_eval_malicious(
+    State(state): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<EncodedResponse, Problem> {
+    serve_prove(
+        state,
+        request_id,
+        &headers,
+        body,
+        ProveEndpoint::MaliciousEval,
+    )
+    .await
+}
+
+/// Read a prove envelope and answer it from the cache or by evaluating it, in
+/// the codec it was sent in, auditing the outcome.
+async fn serve_prove(
+    state: SharedState,
+    request_id: RequestId,
+    headers: &HeaderMap,
+    body: Body,
+    endpoint: ProveEndpoint,
+) -> Result<EncodedResponse, Problem> {
+    let (tenant, audit, usage, metering_hook, max_prove_bytes, prove_cache, backlog) = {
+        let state = state.read().await;
+        let backlog = state
+            .scheduler
+            .as_ref()
+            .filter(|scheduler| scheduler.is_full())
+            .map(|scheduler| retry_after_secs(&state.load, scheduler));
+        (
+            state.tenants.resolve(headers),
+            state.config.audit.clone(),
+            state.usage.clone(),
+            state.config.metering_hook.clone(),
+            state.config.max_prove_bytes,
+            state.prove_cache.clone(),
+            backlog,
+        )
+    };
+    let reject = |problem: Problem| {
+        if let Some(audit) = &audit {
+            audit.record(AuditEvent::ProveRejected {
+                session_id: None,
+                status: problem.status,
+            });
+        }
+        problem
+    };
+
+    let tenant = tenant.map_err(|code| reject(Problem::new(code)))?;
+    if let Some(retry_after) = backlog {
+        tracing::warn!(retry_after, "Prove queue full");
+        return Err(reject(
+            Problem::new(ErrorCode::Overloaded).with_retry_after(retry_after),
+        ));
+    }
+    // Held until the response is sent
+    let _permit = match &tenant {
+        Some(tenant) => tenant.admit().map_err(|e| reject(e.into()))?,
+        None => None,
+    };
+    let qos = match headers.get(QOS_HEADER) {
+        None => QosClass::default(),
+        Some(value) => QosClass::from_header_value(value.as_bytes())
+            .ok_or_else(|| reject(Problem::malformed(QOS_HEADER)))?,
+    };
+    let deadline = match headers.get(DEADLINE_HEADER) {
+        None => None,
+        Some(value) => {
+            let ms: u64 = value
+                .to_str()
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+                .ok_or_else(|| reject(Problem::malformed(DEADLINE_HEADER)))?;
+            // Past what an `Instant` can hold, the client set no deadline in effect;
+            // the prove timeout still applies
+            Instant::now().checked_add(Duration::from_millis(ms))
+        }
+    };
+    let caller = Caller {
+        request_id,
+        tenant: tenant.map(|t| t.id.clone()),
+        qos,
+        deadline,
+    };
+
+    let codec = request_codec(headers).ok_or_else(|| reject(unsupported_codec()))?;
+    let encoding = match headers.get(SCALAR_ENCODING_HEADER) {
+        None => ScalarEncoding::Canonical,
+        Some(value) => ScalarEncoding::from_header_value(value.as_bytes())
+            .ok_or_else(|| reject(Problem::malformed(SCALAR_ENCODING_HEADER)))?,
+    };
+    let body = axum::body::to_bytes(body, max_prove_bytes)
+        .await
+        .map_err(|_| reject(Problem::new(ErrorCode::BodyTooLarge)))?;
+    let envelope = match endpoint {
+        ProveEndpoint::Prove => codec
+            .decode::<ProveEnvelope>(&body)
+            .map(|envelope| envelope.map(Evaluation::Prove)),
+        ProveEndpoint::Msm => codec
+            .decode::<ProveEnvelope<MsmRequest>>(&body)
+            .map(|envelope| envelope.map(Evaluation::Msm)),
+        ProveEndpoint::Eval => codec
+            .decode::<ProveEnvelope<EvalRequest>>(&body)
+            .map(|envelope| envelope.map(Evaluation::Eval)),
+        ProveEndpoint::MaliciousEval => codec
+            .decode::<ProveEnvelope<MaliciousEvalRequest>>(&body)
+            .map(|envelope| envelope.map(Evaluation::MaliciousEval)),
+    }
+    .map_err(|_| reject(Problem::malformed("envelope")))?;
+    record_session(&envelope.session_id, Some(&envelope.circuit));
+    let request_hash = sha256(&body);
+    drop(body);
+    let key = SessionKey::new(caller.tenant.as_deref(), &envelope.session_id);
+    // Generator sets are not in the store
+    let circuit = match endpoint {
+        ProveEndpoint::Eval | ProveEndpoint::MaliciousEval => None,
+        _ => Some(envelope.circuit.as_str()),
+    };
+    hydrate(&state, &key, circuit, headers)
+        .await
+        .map_err(reject)?;
+    // Checked before the prove cache, which replays responses to anyone
+    // presenting the same body. Unknown sessions are for `prove_context` to report.
+    if let Some(session) = state.read().await.live_session(&key) {
+        session
+            .check_token(headers)
+            .map_err(|code| reject(Problem::new(code)))?;
+    }
+
+    let start = Instant::now();
+    let session_id = envelope.session_id.clone();
+    let result = match prove_cache {
+        None => {
+            let hook = metering_hook.as_deref();
+            evaluate_and_meter(&state, &envelope, codec, encoding, &caller, &usage, hook).await
+        }
+        Some(cache) => {
+            let key = ProveKey {
+                tenant: caller.tenant.clone(),
+                session_id: envelope.session_id.clone(),
+                circuit: envelope.circuit.clone(),
+                generators_hash: envelope.generators_hash,
+                request_hash,
+                encoding,
+            };
+            match cache.lookup(key) {
+                CacheLookup::Hit(bytes) => {
+                    tracing::info!("Prove answered from cache");
+                    Ok(bytes)
+                }
+                CacheLookup::Pending(pending) => pending.wait().await,
+                CacheLookup::Miss(slot) => {
+                    // Evaluated on its own task, so a retry can still pick up the
+                    // result if this client goes away
+                    let pending = slot.subscribe();
+                    let state = state.clone();
+                    let evaluate = async move {
+                        let hook = metering_hook.as_deref();
+                        let outcome = evaluate_and_meter(
+                            &state, &envelope, codec, encoding, &caller, &usage, hook,
+                        )
+                        .await;
+                        slot.complete(outcome);
+                    };
+                    tokio::spawn(evaluate.in_current_span());
+                    pending.wait().await
+                }
+            }
+        }
+    };
+
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let request_hash = to_hex(&request_hash);
+    let response_hash = result.as_ref().map(|bytes| to_hex(&sha256(bytes)));
+    match &response_hash {
+        Ok(response_hash) => tracing::info!(
+            duration_ms,
+            request_hash = %request_hash,
+            response_hash = %response_hash,
+            "Prove served"
+        ),
+        Err(problem) => tracing::warn!(
+            duration_ms,
+            status = problem.status,
+            code = ?problem.code,
+            "Prove rejected"
+        ),
+    }
+    if let Some(audit) = &audit {
+        audit.record(match response_hash {
+            Ok(response_hash) => AuditEvent::ProveServed {
+                session_id,
+                request_hash,
+                response_hash,
+                duration_ms,
+            },
+            Err(problem) => AuditEvent::ProveRejected {
+                session_id: Some(session_id),
+                status: problem.status,
+            },
+        });
+    }
+    result.map(|bytes| ([(header::CONTENT_TYPE, codec.content_type())], bytes))
+}
+
+/// Seconds until the running and queued requests should have drained, at the
+/// throughput measured so far: the `Retry-After` of a request turned away by
+/// a full queue.
+fn retry_after_secs(load: &LoadTracker, scheduler: &Scheduler) -> u64 {
+    let estimate = load.estimate(scheduler.queued_units());
+    // Measured per request, so the slots each work through their share
+    let drain_ms = (estimate.queue_ms + estimate.compute_ms) / scheduler.config().slots as u64;
+    drain_ms.div_ceil(1000).max(1)
+}
+
+/// Who a prove or MSM request is served for.
+struct Caller {
+    request_id: RequestId,
+    /// Tenant whose sessions the request names, on a server with tenants.
+    tenant: Option<String>,
+    qos: QosClass,
+    /// From `DEADLINE_HEADER`.
+    deadline: Option<Instant>,
+}
+
+/// `evaluate_prove`, `evaluate_msm` or `evaluate_eval`, recording the compute
+/// units of a served request.
+async fn evaluate_and_meter(
+    state: &SharedState,
+    envelope: &ProveEnvelope<Evaluation>,
+    codec: WireCodec,
+    encoding: ScalarEncoding,
+    caller: &Caller,
+    usage: &UsageLedger,
+    metering_hook: Option<&dyn MeteringHook>,
+) -> Result<axum::body::Bytes, Problem> {
+    let (bytes, event) = match &envelope.request {
+        Evaluation::Prove(request) => {
+            evaluate_prove(state, envelope, request, codec, encoding, caller).await?
+        }
+        Evaluation::Msm(request) => {
+            evaluate_msm(state, envelope, request, codec, encoding, caller).await?
+        }
+        Evaluation::Eval(request) => {
+            let vectors = [("vector", request.vector.as_slice())];
+            let ([result], event) = evaluate_eval(state, envelope, vectors, caller).await?;
+            let response = EvalResponse {
+                result,
+                metadata: eval_metadata(&event, caller),
+            };
+            let bytes = codec
+                .encode(&response)
+                .map_err(|_| Problem::new(ErrorCode::Internal))?;
+            (axum::body::Bytes::from(bytes), event)
+        }
+        Evaluation::MaliciousEval(request) => {
+            let vectors = [
+                ("vector", request.vector.as_slice()),
+                ("check_vector", request.check_vector.as_slice()),
+            ];
+            let ([result, check_result], event) =
+                evaluate_eval(state, envelope, vectors, caller).await?;
+            let response = MaliciousEvalResponse {
+                result,
+                check_result,
+                metadata: eval_metadata(&event, caller),
+            };
+            let bytes = codec
+                .encode(&response)
+                .map_err(|_| Problem::new(ErrorCode::Internal))?;
+            (axum::body::Bytes::from(bytes), event)
+        }
+    };
+    usage.record(&event);
+    if let Some(hook) = metering_hook {
+        hook.on_prove(&event);
+    }
+    Ok(bytes)
+}
+
+/// What evaluating a prove envelope needs from the server state.
+struct ProveContext<C = CircuitState> {
+    circuit: Arc<C>,
+    account: Option<String>,
+    load: Arc<LoadTracker>,
+    scheduler: Option<Arc<Scheduler>>,
+    cancel: CancelToken,
+    parallel: ParallelConfig,
+    #[cfg(feature = "fault-injection")]
+    fault: Option<Arc<FaultInjector>>,
+}
+
+/// Look up the circuit or generator set `envelope` names among the sessions of
+/// the caller's tenant, marking its session active, and check the envelope's
+/// curve and generators hash against it. The context's token fires at the
+/// prove timeout or the caller's deadline, whichever comes first.
+async fn prove_context<C: Generators, R>(
+    state: &SharedState,
+    envelope: &ProveEnvelope<R>,
+    caller: &Caller,
+) -> Result<ProveContext<C>, Problem> {
+    let tenant = caller.tenant.as_deref();
+    #[cfg(feature = "fault-injection")]
+    let fault = state.read().await.config.fault.clone();
+    let (circuit, account, load, scheduler, prove_timeout, parallel) = {
+        let state = state.read().await;
+        let session = state
+            .live_session(&SessionKey::new(tenant, &envelope.session_id))
+            .ok_or_else(|| Problem::new(ErrorCode::UnknownSession))?;
+        session.touch();
+        (
+            C::lookup(session, &envelope.circuit)
+                .ok_or_else(|| Problem::new(ErrorCode::UnknownCircuit).with_field("circuit"))?,
+            session.account.clone(),
+            state.load.clone(),
+            state.scheduler.clone(),
+            state.config.prove_timeout,
+            state.config.parallel.clone(),
+        )
+    };
+    CurveMismatch::check(circuit.curve(), envelope.curve).map_err(|mismatch| {
+        tracing::warn!(error = %mismatch, "Curve does not match the session");
+        Problem::new(ErrorCode::CurveMismatch)
+            .with_field("curve")
+            .with_detail(mismatch.to_string())
+    })?;
+    // The session was set up again with other generators since this client's setup
+    if envelope.generators_hash != circuit.generators_hash() {
+        tracing::warn!("Generators hash does not match the session");
+        return Err(Problem::new(ErrorCode::GeneratorsMismatch).with_field("generators_hash"));
+    }
+
+    let timeout = prove_timeout.map(|timeout| Instant::now() + timeout);
+    let cancel = match timeout.into_iter().chain(caller.deadline).min() {
+        Some(deadline) => CancelToken::with_deadline(deadline),
+        None => CancelToken::new(),
+    };
+    Ok(ProveContext {
+        circuit,
+        account,
+        load,
+        scheduler,
+        cancel,
+        parallel,
+        #[cfg(feature = "fault-injection")]
+        fault,
+    })
+}
+
+/// `point` as the result of the `kind` MSM, tampered with if the server was
+/// configured with a fault.
+#[cfg(feature = "fault-injection")]
+fn tamper<G>(fault: &Option<Arc<FaultInjector>>, session_id: &str, kind: MsmKind, point: G) -> G
+where
+    G: CurveGroup + ark_serialize::CanonicalSerialize + ark_serialize::CanonicalDeserialize,
+{
+    match fault {
+        Some(fault) => fault.tamper(session_id, kind, point),
+        None => point,
+    }
+}
+
+/// Problem for a failed MSM over the `field` vector: a timeout if its token
+/// fired, otherwise a length mismatch.
+fn msm_problem(field: &str, e: PedersenError) -> Problem {
+    match e {
+        PedersenError::Cancelled(_) => Problem::new(ErrorCode::Timeout),
+        PedersenError::LengthMismatch {
+            scalars,
+            generators,
+        } => Problem::length_mismatch(field, generators, scalars),
+        e => Problem::malformed(field).with_detail(e.to_string()),
+    }
+}
+
+/// `problem`, with the `completed` of `total` MSM elements evaluated before it
+/// if it is a timeout, so the client can tell how close the request came.
+fn timeout_progress(problem: Problem, completed: usize, total: u64) -> Problem {
+    if problem.code != ErrorCode::Timeout {
+        return problem;
+    }
+    tracing::warn!(completed, total, "Prove timed out");
+    problem.with_progress(completed as u64, total)
+}
+
+/// Evaluate the 5 MSMs of a prove request against the session's generators.
+///
+/// The MSMs run on the blocking pool under a cancel token, which fires when the
+/// configured prove timeout passes or when this future is dropped because the client
+/// went away. The token is checked between MSMs.
+async fn evaluate_prove(
+    state: &SharedState,
+    envelope: &ProveEnvelope<Evaluation>,
+    request: &ProveRequest,
+    codec: WireCodec,
+    encoding: ScalarEncoding,
+    caller: &Caller,
+) -> Result<(axum::body::Bytes, MeteringEvent), Problem> {
+    let ProveContext {
+        circuit: session,
+        account,
+        load,
+        scheduler,
+        cancel,
+        parallel,
+        #[cfg(feature = "fault-injection")]
+        fault,
+    } = prove_context::<CircuitState, _>(state, envelope, caller).await?;
+
+    // Deserialize masked scalars (fallible), on the MSM pool
+    let EncryptedRequest {
+        v_h,
+        v_l,
+        v_a,
+        v_b_g1,
+        v_b_g2,
+    } = parallel
+        .install(|| request.decode(encoding))
+        .map_err(|_| Problem::malformed("request"))?;
+
+    let lens = [v_h.len(), v_l.len(), v_a.len(), v_b_g1.len(), v_b_g2.len()];
+    let mut event = MeteringEvent {
+        tenant: caller.tenant.clone(),
+        session_id: envelope.session_id.clone(),
+        account,
+        compute_units: compute_units([lens[0], lens[1], lens[2], lens[3]], lens[4]),
+        msm_elements: lens.iter().sum::<usize>() as u64,
+        compute_ms: 0,
+        msm: None,
+    };
+
+    // A hint narrows the MSMs to part of the pool, judged by how long they
+    // would take on all of it
+    let pool_threads = parallel.num_threads();
+    let threads = request.hint.map_or(pool_threads, |hint| {
+        hint.threads(pool_threads, load.estimate(event.compute_units).compute_ms)
+    });
+    let narrowed = (threads < pool_threads).then_some(threads);
+
+    let _slot = admit(scheduler.as_ref(), caller.qos, event.compute_units).await;
+    tracing::info!(
+        h = lens[0],
+        l = lens[1],
+        a = lens[2],
+        b_g1 = lens[3],
+        b_g2 = lens[4],
+        threads,
+        qos = caller.qos.header_value(),
+        "Computing 5 MSMs"
+    );
+    let load = load.begin(event.compute_units);
+    let msm_start = Instant::now();
+    let _cancel_on_drop = cancel.drop_guard();
+
+    let msms = tokio::task::spawn_blocking(move || {
+        // Length mismatch returns 400 instead of panicking; the token is
+        // checked between MSM chunks
+        let tables = session.tables.get();
+        let msm_g1 = |generators: &[G1Affine], kind, v: &[Fr], field| {
+            let table = tables.and_then(|tables| tables.g1(kind));
+            msm_narrowed::<G1>(&parallel, generators, table, v, narrowed, &cancel)
+                .map_err(|e| (field, e))
+        };
+        let em_h = msm_g1(&session.h_generators, MsmKind::H, &v_h, "v_h")?;
+        let em_l = msm_g1(&session.l_generators, MsmKind::L, &v_l, "v_l")?;
+        let em_a = msm_g1(&session.a_generators, MsmKind::A, &v_a, "v_a")?;
+        let em_b_g1 = msm_g1(&session.b_g1_generators, MsmKind::BG1, &v_b_g1, "v_b_g1")?;
+        let b_g2 = &session.b_g2_generators;
+        let table = tables.map(|tables| &tables.b_g2);
+        let em_b_g2 = msm_narrowed::<G2>(&parallel, b_g2, table, &v_b_g2, narrowed, &cancel)
+            .map_err(|e| ("v_b_g2", e))?;
+        Ok((em_h, em_l, em_a, em_b_g1, em_b_g2))
+    })
+    .await
+    .map_err(|_| Problem::new(ErrorCode::Internal))?;
+
+    // The MSMs run in order, so those before the failed one are done
+    let (em_h, em_l, em_a, em_b_g1, em_b_g2) = msms.map_err(|(field, e)| {
+        let done = ["v_h", "v_l", "v_a", "v_b_g1"]
+            .iter()
+            .take_while(|done| **done != field)
+            .count();
+        let completed = lens[..done].iter().sum();
+        timeout_progress(msm_problem(field, e), completed, event.msm_elements)
+    })?;
+    // Narrowed MSMs are slow by request and would skew the throughput average
+    if narrowed.is_none() {
+        load.complete();
+    }
+    event.compute_ms = msm_start.elapsed().as_millis() as u64;
+    tracing::info!(
+        compute_ms = event.compute_ms,
+        compute_units = event.compute_units,
+        "MSMs computed"
+    );
 
-    let response = ProveResponse {
-        em_h: ark_to_bytes(&em_h.into_affine()),
-        em_l: ark_to_bytes(&em_l.into_affine()),
-        em_a: ark_to_bytes(&em_a.into_affine()),
-        em_b_g1: ark_to_bytes(&em_b_g1.into_affine()),
-        em_b_g2: ark_to_bytes(&em_b_g2.into_affine()),
+    #[cfg(feature = "fault-injection")]
+    let (em_h, em_l, em_a, em_b_g1, em_b_g2) = {
+        let id = &envelope.session_id;
+        (
+            tamper(&fault, id, MsmKind::H, em_h),
+            tamper(&fault, id, MsmKind::L, em_l),
+            tamper(&fault, id, MsmKind::A, em_a),
+            tamper(&fault, id, MsmKind::BG1, em_b_g1),
+            tamper(&fault, id, MsmKind::BG2, em_b_g2),
+        )
     };
 
+    let response = ProveResponse::new(
+        &ServerResponse {
+            em_h,
+            em_l,
+            em_a,
+            em_b_g1,
+            em_b_g2,
+        },
+        ProveMetadata {
+            compute_units: event.compute_units,
+            server_ms: event.compute_ms,
+            request_id: caller.request_id,
+        },
+    );
+
+    let bytes = codec
+        .encode(&response)
+        .map_err(|_| Problem::new(ErrorCode::Internal))?;
+    Ok((axum::body::Bytes::from(bytes), event))
+}
+
+/// A slot to evaluate a request of `units` in, once the scheduler's turn comes
+/// to `qos`; without a scheduler, right away.
+async fn admit(
+    scheduler: Option<&Arc<Scheduler>>,
+    qos: QosClass,
+    units: u64,
+) -> Option<SlotPermit> {
+    match scheduler {
+        Some(scheduler) => Some(scheduler.admit(qos, units).await),
+        None => None,
+    }
+}
+
+/// `msm_tabled`, or `msm_on_threads` on `threads` of the pool. An empty vector
+/// is an MSM the client computes itself (`Delegation`), and evaluates to the
+/// identity.
+fn msm_narrowed<G: CurveGroup>(
+    parallel: &ParallelConfig,
+    generators: &[G::Affine],
+    table: Option<&FixedBaseTable<G>>,
+    scalars: &[G::ScalarField],
+    threads: Option<usize>,
+    cancel: &CancelToken,
+) -> Result<G, PedersenError> {
+    if scalars.is_empty() {
+        return Ok(G::zero());
+    }
+    match (threads, table) {
+        (Some(threads), Some(table)) => parallel.install(|| table.msm(scalars, threads, cancel)),
+        (Some(threads), None) => {
+            parallel.install(|| msm_on_threads(generators, scalars, threads, cancel))
+        }
+        (None, table) => msm_tabled(parallel, generators, table, scalars, cancel),
+    }
+}
+
+/// `ParallelConfig::msm` on the whole pool, over the generators' fixed-base
+/// `table` once it is built.
+fn msm_tabled<G: CurveGroup>(
+    parallel: &ParallelConfig,
+    generators: &[G::Affine],
+    table: Option<&FixedBaseTable<G>>,
+    scalars: &[G::ScalarField],
+    cancel: &CancelToken,
+) -> Result<G, PedersenError> {
+    match table {
+        Some(table) => parallel.install(|| table.msm(scalars, parallel.num_threads(), cancel)),
+        None => parallel.msm(generators, scalars, cancel),
+    }
+}
+
+/// Evaluate the one MSM of an `MsmRequest`, like `evaluate_prove` does for all five.
+async fn evaluate_msm(
+    state: &SharedState,
+    envelope: &ProveEnvelope<Evaluation>,
+    request: &MsmRequest,
+    codec: WireCodec,
+    encoding: ScalarEncoding,
+    caller: &Caller,
+) -> Result<(axum::body::Bytes, MeteringEvent), Problem> {
+    let ProveContext {
+        circuit,
+        account,
+        load,
+        scheduler,
+        cancel,
+        parallel,
+        #[cfg(feature = "fault-injection")]
+        fault,
+    } = prove_context::<CircuitState, _>(state, envelope, caller).await?;
+
+    let scalars = parallel
+        .install(|| encoding.decode(&request.vector))
+        .map_err(|_| Problem::malformed("vector"))?;
+    let kind = request.kind;
+    if kind == MsmKind::Public {
+        let generators = circuit
+            .public_generators
+            .as_ref()
+            .ok_or_else(|| Problem::new(ErrorCode::NoPublicGenerators))?;
+        // Validated at setup: a holds at least the constant term
+        if scalars.len() + 1 != generators.a.len() {
+            let expected = generators.a.len() - 1;
+            return Err(Problem::length_mismatch("vector", expected, scalars.len()));
+        }
+    }
+    let mut event = MeteringEvent {
+        tenant: caller.tenant.clone(),
+        session_id: envelope.session_id.clone(),
+        account,
+        compute_units: match kind {
+            MsmKind::BG2 => compute_units([0; 4], scalars.len()),
+            // The constant 1 is an input too, and B has a G1 and a G2 query
+            MsmKind::Public => compute_units([2 * (scalars.len() + 1), 0, 0, 0], scalars.len() + 1),
+            _ => compute_units([scalars.len(), 0, 0, 0], 0),
+        },
+        msm_elements: match kind {
+            MsmKind::Public => 3 * (scalars.len() as u64 + 1),
+            _ => scalars.len() as u64,
+        },
+        compute_ms: 0,
+        msm: Some(kind),
+    };
+
+    let _slot = admit(scheduler.as_ref(), caller.qos, event.compute_units).await;
+    tracing::info!(
+        kind = ?kind,
+        len = scalars.len(),
+        qos = caller.qos.header_value(),
+        "Computing MSM"
+    );
+    let load = load.begin(event.compute_units);
+    let msm_start = Instant::now();
+    let _cancel_on_drop = cancel.drop_guard();
+    #[cfg(feature = "fault-injection")]
+    let session_id = envelope.session_id.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let tables = circuit.tables.get();
+        let g1 = |generators: &[G1Affine]| {
+            let table = tables.and_then(|tables| tables.g1(kind));
+            msm_tabled::<G1>(&parallel, generators, table, &scalars, &cancel)
+                .map(|point| {
+                    #[cfg(feature = "fault-injection")]
+                    let point = tamper(&fault, &session_id, kind, point);
+                    ark_to_bytes(&point.into_affine())
+                })
+        };
+        match kind {
+            MsmKind::H => g1(&circuit.h_generators),
+            MsmKind::L => g1(&circuit.l_generators),
+            MsmKind::A => g1(&circuit.a_generators),
+            MsmKind::BG1 => g1(&circuit.b_g1_generators),
+            MsmKind::BG2 => {
+                let table = tables.map(|tables| &tables.b_g2);
+                msm_tabled::<G2>(&parallel, &circuit.b_g2_generators, table, &scalars, &cancel)
+                    .map(|point| {
+                        #[cfg(feature = "fault-injection")]
+                        let point = tamper(&fault, &session_id, kind, point);
+                        ark_to_bytes(&point.into_affine())
+                    })
+            }
+            MsmKind::Public => {
+                let generators = circuit.public_generators.as_ref().expect("checked above");
+                let public = parallel
+                    .install(|| generators.evaluate(&scalars))
+                    .expect("the generators were validated at setup, the input count above");
+                Ok(ark_to_bytes(&(
+                    public.a.into_affine(),
+                    public.b_g1.into_affine(),
+                    public.b_g2.into_affine(),
+                )))
+            }
+        }
+    })
+    .await
+    .map_err(|_| Problem::new(ErrorCode::Internal))?
+    .map_err(|e| timeout_progress(msm_problem("vector", e), 0, event.msm_elements))?;
+    load.complete();
+    event.compute_ms = msm_start.elapsed().as_millis() as u64;
+    tracing::info!(
+        compute_ms = event.compute_ms,
+        compute_units = event.compute_units,
+        "MSMs computed"
+    );
+
+    let response = MsmResponse {
+        kind,
+        result,
+        metadata: ProveMetadata {
+            compute_units: event.compute_units,
+            server_ms: event.compute_ms,
+            request_id: caller.request_id,
+        },
+    };
+    let bytes = codec
+        .encode(&response)
+        .map_err(|_| Problem::new(ErrorCode::Internal))?;
+    Ok((axum::body::Bytes::from(bytes), event))
+}
+
+/// Scalars of the vectors of an eval request, decoded in the scalar field of
+/// its generator set's curve.
+enum EvalScalars {
+    Bn254(Vec<Vec<Fr>>),
+    Grumpkin(Vec<Vec<grumpkin::ScalarField>>),
+}
+
+/// Evaluate the MSMs of the K vectors of an `EvalRequest` (one) or a
+/// `MaliciousEvalRequest` (two) against a generator set, like `evaluate_msm`
+/// does for a circuit's generators, dispatching on the set's curve. Each vector
+/// is named by its field in problems.
+async fn evaluate_eval<const K: usize>(
+    state: &SharedState,
+    envelope: &ProveEnvelope<Evaluation>,
+    vectors: [(&'static str, &[u8]); K],
+    caller: &Caller,
+) -> Result<([Vec<u8>; K], MeteringEvent), Problem> {
+    let ProveContext {
+        circuit: set,
+        account,
+        load,
+        scheduler,
+        cancel,
+        parallel,
+        ..
+    } = prove_context::<GeneratorSet, _>(state, envelope, caller).await?;
+
+    let fields = vectors.map(|(field, _)| field);
+    let scalars = match set.points {
+        GeneratorPoints::Bn254(_) => decode_eval_vectors(&vectors).map(EvalScalars::Bn254),
+        GeneratorPoints::Grumpkin(_) => decode_eval_vectors(&vectors).map(EvalScalars::Grumpkin),
+    }
+    .map_err(Problem::malformed)?;
+    let len = set.len();
+    let mut event = MeteringEvent {
+        tenant: caller.tenant.clone(),
+        session_id: envelope.session_id.clone(),
+        account,
+        compute_units: compute_units([len * K, 0, 0, 0], 0),
+        msm_elements: (len * K) as u64,
+        compute_ms: 0,
+        msm: None,
+    };
+
+    let _slot = admit(scheduler.as_ref(), caller.qos, event.compute_units).await;
+    tracing::info!(
+        curve = ?set.curve(),
+        len,
+        vectors = K,
+        qos = caller.qos.header_value(),
+        "Computing MSM"
+    );
+    let load = load.begin(event.compute_units);
+    let msm_start = Instant::now();
+    let _cancel_on_drop = cancel.drop_guard();
+
+    let results = tokio::task::spawn_blocking(move || match (&set.points, &scalars) {
+        (GeneratorPoints::Bn254(points), EvalScalars::Bn254(scalars)) => {
+            eval_msms::<G1, K>(&parallel, points, scalars, fields, &cancel)
+        }
+        (GeneratorPoints::Grumpkin(points), EvalScalars::Grumpkin(scalars)) => {
+            eval_msms::<GrumpkinProjective, K>(&parallel, points, scalars, fields, &cancel)
+        }
+        _ => unreachable!("scalars are decoded for the curve of the set"),
+    })
+    .await
+    .map_err(|_| Problem::new(ErrorCode::Internal))?
+    .map_err(|(field, e)| {
+        let done = fields.iter().take_while(|done| **done != field).count();
+        timeout_progress(msm_problem(field, e), done * len, event.msm_elements)
+    })?;
+    load.complete();
+    event.compute_ms = msm_start.elapsed().as_millis() as u64;
+    tracing::info!(
+        compute_ms = event.compute_ms,
+        compute_units = event.compute_units,
+        "MSMs computed"
+    );
+    Ok((results, event))
+}
+
+/// The scalars of each vector of an eval request, or the field of the first
+/// that fails to decode.
+fn decode_eval_vectors<F: ark_serialize::CanonicalDeserialize>(
+    vectors: &[(&'static str, &[u8])],
+) -> Result<Vec<Vec<F>>, &'static str> {
+    vectors
+        .iter()
+        .map(|(field, bytes)| ark_vec_from_bytes(bytes).map_err(|_| *field))
+        .collect()
+}
+
+/// Metadata of an eval response, from its metering event.
+fn eval_metadata(event: &MeteringEvent, caller: &Caller) -> ProveMetadata {
+    ProveMetadata {
+        compute_units: event.compute_units,
+        server_ms: event.compute_ms,
+        request_id: caller.request_id,
+    }
+}
+
+/// `eval_msm` of each vector, failing with the field of the first that fails.
+fn eval_msms<G: CurveGroup, const K: usize>(
+    parallel: &ParallelConfig,
+    generators: &[G::Affine],
+    vectors: &[Vec<G::ScalarField>],
+    fields: [&'static str; K],
+    cancel: &CancelToken,
+) -> Result<[Vec<u8>; K], (&'static str, PedersenError)> {
+    let mut results = Vec::with_capacity(K);
+    for (scalars, field) in vectors.iter().zip(fields) {
+        results.push(eval_msm::<G>(parallel, generators, scalars, cancel).map_err(|e| (field, e))?);
+    }
+    Ok(results.try_into().expect("one result per vector"))
+}
+
+/// The compressed MSM of `scalars` against `generators`.
+fn eval_msm<G: CurveGroup>(
+    parallel: &ParallelConfig,
+    generators: &[G::Affine],
+    scalars: &[G::ScalarField],
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, PedersenError> {
+    let point = parallel.msm::<G>(generators, scalars, cancel)?;
+    Ok(ark_to_bytes(&point.into_affine()))
+}
+
+/// POST /estimate: predict queue delay and compute time for a prove request of the
+/// given vector sizes, without sending the vectors.
+#[utoipa::path(
+    post,
+    path = "/estimate",
+    operation_id = "estimate",
+    request_body(content = EstimateRequest, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = EstimateResponse, content_type = "application/octet-stream"),
+        (status = 400, description = "The body failed to decode"),
+    )
+)]
+async fn handle_estimate(
+    State(state): State<SharedState>,
+    body: axum::body::Bytes,
+) -> Result<axum::body::Bytes, StatusCode> {
+    let request: EstimateRequest =
+        bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let units = compute_units(
+        [request.h, request.l, request.a, request.b_g1].map(|n| n as usize),
+        request.b_g2 as usize,
+    );
+    let estimate = state.read().await.load.estimate(units);
+
+    let response = EstimateResponse {
+        compute_units: units,
+        queue_ms: estimate.queue_ms,
+        compute_ms: estimate.compute_ms,
+        in_flight: estimate.in_flight,
+        calibrated: estimate.calibrated,
+    };
     let bytes = bincode::serialize(&response).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(axum::body::Bytes::from(bytes))
 }
+
+/// GET /usage: usage totals for the API key in the `x-api-key` header.
+/// Only available when setup is gated by API key.
+#[utoipa::path(
+    get,
+    path = "/usage",
+    operation_id = "account_usage",
+    responses(
+        (status = 200, body = Usage),
+        (status = 401, description = "The API key is missing or unknown"),
+        (status = 404, description = "Setup is not gated by API keys"),
+    )
+)]
+async fn handle_account_usage(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<Usage>, StatusCode> {
+    let state = state.read().await;
+    if !matches!(state.config.setup_gate, SetupGate::ApiKeys(_)) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let account = state
+        .config
+        .setup_gate
+        .check(&headers, &state.spent_pow)?
+        .account
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    Ok(Json(state.usage.account(&account)))
+}
+
+/// GET /commitment/{session_id}: `handle_commitment` for `DEFAULT_CIRCUIT`.
+#[utoipa::path(
+    get,
+    path = "/commitment/{session_id}",
+    operation_id = "default_commitment",
+    params(
+        ("session_id" = String, Path),
+    ),
+    responses(
+        (status = 200, body = CrsCommitment),
+        (status = 401, description = "The server has tenants and no API key was sent"),
+        (status = 403, description = "The API key belongs to no tenant"),
+        (status = 404, description = "The session or circuit was never set up, or expired"),
+    )
+)]
+async fn handle_default_commitment(
+    State(state): State<SharedState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<CrsCommitment>, StatusCode> {
+    let path = Path((session_id, DEFAULT_CIRCUIT.to_string()));
+    handle_commitment(State(state), path, headers).await
+}
+
+/// GET /commitment/{session_id}/{circuit}: the commitment to the generators a
+/// circuit's prove requests are evaluated against.
+#[utoipa::path(
+    get,
+    path = "/commitment/{session_id}/{circuit}",
+    operation_id = "commitment",
+    params(
+        ("session_id" = String, Path),
+        ("circuit" = String, Path),
+    ),
+    responses(
+        (status = 200, body = CrsCommitment),
+        (status = 401, description = "The server has tenants and no API key was sent"),
+        (status = 403, description = "The API key belongs to no tenant"),
+        (status = 404, description = "The session or circuit was never set up, or expired"),
+    )
+)]
+async fn handle_commitment(
+    State(state): State<SharedState>,
+    Path((session_id, circuit)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<CrsCommitment>, StatusCode> {
+    let key = state
+        .read()
+        .await
+        .session_key(&headers, &session_id)
+        .map_err(ErrorCode::status)?;
+    hydrate(&state, &key, Some(&circuit), &headers)
+        .await
+        .map_err(|problem| problem.code.status())?;
+    let state = state.read().await;
+    let session = state
+        .live_session(&key)
+        .and_then(|session| session.circuits.get(&circuit))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(CrsCommitment {
+        curve: session.curve,
+        generators_hash: session.generators_hash,
+        sizes: [
+            session.h_generators.len(),
+            session.l_generators.len(),
+            session.a_generators.len(),
+            session.b_g1_generators.len(),
+            session.b_g2_generators.len(),
+        ],
+        key_fingerprint: session.key_fingerprint,
+    }))
+}
+
+/// GET /commitment/{session_id}/{circuit}/chunks: hashes of the chunks of a
+/// circuit's generators, for a client to diff the generators of a recompiled
+/// circuit against and upload the chunks that changed with POST /setup/patch.
+#[utoipa::path(
+    get,
+    path = "/commitment/{session_id}/{circuit}/chunks",
+    operation_id = "generator_chunks",
+    params(
+        ("session_id" = String, Path),
+        ("circuit" = String, Path),
+    ),
+    responses(
+        (status = 200, body = GeneratorChunks),
+        (status = 401, description = "The server has tenants and no API key was sent"),
+        (status = 403, description = "The API key belongs to no tenant"),
+        (status = 404, description = "The session or circuit was never set up, or expired"),
+    )
+)]
+async fn handle_generator_chunks(
+    State(state): State<SharedState>,
+    Path((session_id, circuit)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<GeneratorChunks>, StatusCode> {
+    let key = state
+        .read()
+        .await
+        .session_key(&headers, &session_id)
+        .map_err(ErrorCode::status)?;
+    hydrate(&state, &key, Some(&circuit), &headers)
+        .await
+        .map_err(|problem| problem.code.status())?;
+    let circuit = state
+        .read()
+        .await
+        .live_session(&key)
+        .and_then(|session| session.circuits.get(&circuit).cloned())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let sizes = generator_point_sizes();
+    let mut hashes: [Vec<[u8; 32]>; 5] = Default::default();
+    for (i, encoded) in circuit.encoded_generators().iter().enumerate() {
+        hashes[i] = chunk_hashes(encoded, sizes[i], GENERATOR_CHUNK)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    Ok(Json(GeneratorChunks {
+        generators_hash: circuit.generators_hash,
+        chunk_len: GENERATOR_CHUNK,
+        hashes,
+    }))
+}
+
+/// GET /usage/{session_id}: usage totals for one session. Sessions set up with an
+/// API key require the same key.
+#[utoipa::path(
+    get,
+    path = "/usage/{session_id}",
+    operation_id = "session_usage",
+    params(
+        ("session_id" = String, Path),
+    ),
+    responses(
+        (status = 200, body = Usage),
+        (status = 403, description = "The session was set up with another API key"),
+        (status = 404, description = "The session was never set up, or expired"),
+    )
+)]
+async fn handle_session_usage(
+    State(state): State<SharedState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Usage>, StatusCode> {
+    let state = state.read().await;
+    let key = state
+        .session_key(&headers, &session_id)
+        .map_err(ErrorCode::status)?;
+    let session = state.live_session(&key).ok_or(StatusCode::NOT_FOUND)?;
+    session.check_account(&headers)?;
+    let usage = state.usage.session(key.tenant.as_deref(), &session_id);
+    Ok(Json(usage))
+}
+
+/// GET /admin/sessions: usage and idle time of every live session, across
+/// tenants, for capacity planning and per-customer reporting.
+#[utoipa::path(
+    get,
+    path = "/admin/sessions",
+    operation_id = "admin_sessions",
+    responses(
+        (status = 200, body = Vec<SessionStats>),
+        (status = 401, description = "No admin key was sent"),
+        (status = 403, description = "The admin key is wrong"),
+        (status = 404, description = "The server has no admin key"),
+    )
+)]
+async fn handle_admin_sessions(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionStats>>, StatusCode> {
+    let state = state.read().await;
+    check_admin_key(state.config.admin_key.as_deref(), &headers)?;
+    Ok(Json(state.session_stats()))
+}
+
+/// GET /admin/metrics: the counters of GET /admin/sessions for Prometheus.
+#[utoipa::path(
+    get,
+    path = "/admin/metrics",
+    operation_id = "admin_metrics",
+    responses(
+        (status = 200, body = String, content_type = "text/plain; version=0.0.4"),
+        (status = 401, description = "No admin key was sent"),
+        (status = 403, description = "The admin key is wrong"),
+        (status = 404, description = "The server has no admin key"),
+    )
+)]
+async fn handle_admin_metrics(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<([(header::HeaderName, &'static str); 1], String), StatusCode> {
+    let state = state.read().await;
+    check_admin_key(state.config.admin_key.as_deref(), &headers)?;
+    let text = prometheus_text(&state.session_stats());
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::audit::{sha256, to_hex};
+use super::messages::CurveId;
+
+/// Object storage shared by the replicas of a stateless deployment, e.g. an S3
+/// or GCS bucket (see `ServerConfig::store`). Keys are `/`-separated paths of
+/// hex digests. Adapters for cloud SDKs are supplied by the embedder;
+/// `DirectoryStore` covers buckets mounted as a file system. Calls run on
+/// tokio's blocking pool, so they may block.
+pub trait ObjectStore: Send + Sync {
+    /// The object at `key`, or `None` if there is none.
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+    /// Store `bytes` at `key`, replacing any object there.
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+}
+
+/// Objects as files under a directory, e.g. a bucket mounted with s3fs or
+/// gcsfuse, or a volume shared by the replicas.
+pub struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Readers on other replicas never see a partial object
+        let partial = path.with_extension(format!("partial-{}", std::process::id()));
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(&partial, &path)
+    }
+}
+
+/// Objects in memory, for tests and for replicas sharing one process.
+#[derive(Default)]
+pub struct MemoryStore {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn len(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.objects.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+/// A session as written at its first setup, JSON at `session_object`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredSession {
+    /// SHA-256 of the API key the session was set up with. The key itself is
+    /// not stored; a replica learns it from the next request that carries it.
+    pub(crate) account_hash: Option<[u8; 32]>,
+    /// `SessionToken::hash` of the session's token; absent in sessions stored
+    /// before sessions had tokens.
+    #[serde(default)]
+    pub(crate) token_hash: Option<[u8; 32]>,
+}
+
+/// A circuit of a session, JSON at `circuit_object`. Its generators are the
+/// bincode `SetupRequest` at `generators_object(generators_hash)`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredCircuit {
+    pub(crate) curve: CurveId,
+    pub(crate) generators_hash: [u8; 32],
+    /// The circuit's `SetupRequest::key_fingerprint`, which the shared
+    /// generators object holds only for the first session to upload them.
+    #[serde(default)]
+    pub(crate) key_fingerprint: Option<[u8; 32]>,
+}
+
+/// Generator sets are content-addressed, so sessions uploading the same
+/// generators share one object.
+pub(crate) fn generators_object(generators_hash: &[u8; 32]) -> String {
+    format!("generators/{}", to_hex(generators_hash))
+}
+
+/// Directory of the session `session_id` of `tenant`. Names are hashed so that
+/// no session ID or circuit name can escape it.
+fn session_prefix(tenant: Option<&str>, session_id: &str) -> String {
+    let mut name = Vec::new();
+    match tenant {
+        None => name.push(0),
+        Some(tenant) => {
+            name.push(1);
+            name.extend_from_slice(&(tenant.len() as u64).to_le_bytes());
+            name.extend_from_slice(tenant.as_bytes());
+        }
+    }
+    name.extend_from_slice(session_id.as_bytes());
+    format!("sessions/{}", to_hex(&sha256(&name)))
+}
+
+pub(crate) fn session_object(tenant: Option<&str>, session_id: &str) -> String {
+    format!("{}/session", session_prefix(tenant, session_id))
+}
+
+pub(crate) fn circuit_object(tenant: Option<&str>, session_id: &str, circuit: &str) -> String {
+    let prefix = session_prefix(tenant, session_id);
+    format!("{prefix}/circuits/{}", to_hex(&sha256(circuit.as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_store() {
+        let root = std::env::temp_dir().join(format!("stealthsnark-store-{}", std::process::id()));
+        let store = DirectoryStore::new(&root);
+        let key = circuit_object(Some("a"), "s", "../../etc");
+        assert!(key.starts_with("sessions/") && !key.contains(".."));
+        assert_eq!(store.get(&key).unwrap(), None);
+        store.put(&key, b"one").unwrap();
+        store.put(&key, b"two").unwrap();
+        assert_eq!(store.get(&key).unwrap().as_deref(), Some(&b"two"[..]));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // Tenants do not share session directories
+        assert_ne!(
+            session_object(Some("a"), "s"),
+            session_object(Some("b"), "s")
+        );
+        assert_ne!(session_object(None, "as"), session_object(Some("a"), "s"));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::gate::API_KEY_HEADER;
+use super::problem::{ErrorCode, Problem};
+
+/// An application served by a shared server. Its sessions live in a namespace of
+/// their own: requests carrying one of its API keys only see its sessions, and
+/// equal session IDs of different tenants are different sessions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tenant {
+    pub id: String,
+    /// `x-api-key` values that act for the tenant.
+    pub api_keys: HashSet<String>,
+    #[serde(default)]
+    pub limits: TenantLimits,
+}
+
+/// What one tenant may hold and run at once; `None` is unlimited. Exceeding a
+/// limit fails with `ErrorCode::TenantLimitExceeded`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TenantLimits {
+    /// Live sessions.
+    pub max_sessions: Option<usize>,
+    /// Memory taken by the generators of all its live sessions, in bytes.
+    pub max_generator_bytes: Option<usize>,
+    /// Prove and MSM requests being served.
+    pub max_concurrent_requests: Option<usize>,
+}
+
+impl TenantLimits {
+    /// Check that a tenant may hold `sessions` live sessions.
+    pub fn check_sessions(&self, sessions: usize) -> Result<(), LimitExceeded> {
+        match self.max_sessions {
+            Some(max) if sessions > max => Err(LimitExceeded::Sessions { max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that a tenant may hold generators taking `bytes` in all.
+    pub fn check_generator_bytes(&self, bytes: usize) -> Result<(), LimitExceeded> {
+        match self.max_generator_bytes {
+            Some(max) if bytes > max => Err(LimitExceeded::GeneratorBytes { bytes, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The `TenantLimits` a request would exceed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("the tenant may hold at most {max} sessions")]
+    Sessions { max: usize },
+    #[error("the tenant's generators would take {bytes} bytes, more than its {max}")]
+    GeneratorBytes { bytes: usize, max: usize },
+    #[error("the tenant may run at most {max} requests at once")]
+    ConcurrentRequests { max: usize },
+}
+
+impl From<LimitExceeded> for Problem {
+    fn from(e: LimitExceeded) -> Self {
+        Problem::new(ErrorCode::TenantLimitExceeded).with_detail(e.to_string())
+    }
+}
+
+/// A configured tenant, with the requests it is being served.
+pub(crate) struct TenantState {
+    pub(crate) id: String,
+    pub(crate) limits: TenantLimits,
+    requests: Option<Arc<Semaphore>>,
+}
+
+impl TenantState {
+    /// Admit one more prove or MSM request, held until the permit is dropped.
+    /// `None` admits it without a limit.
+    pub(crate) fn admit(&self) -> Result<Option<OwnedSemaphorePermit>, LimitExceeded> {
+        let Some(requests) = &self.requests else {
+            return Ok(None);
+        };
+        requests.clone().try_acquire_owned().map(Some).map_err(|_| {
+            let max = self.limits.max_concurrent_requests.unwrap_or_default();
+            LimitExceeded::ConcurrentRequests { max }
+        })
+    }
+}
+
+/// Tenants by API key.
+#[derive(Default)]
+pub(crate) struct TenantDirectory {
+    by_key: HashMap<String, Arc<TenantState>>,
+}
+
+impl TenantDirectory {
+    /// # Panics
+    /// If two tenants share an ID or an API key.
+    pub(crate) fn new(tenants: &[Tenant]) -> Self {
+        let mut ids = HashSet::new();
+        let mut by_key = HashMap::new();
+        for tenant in tenants {
+            assert!(ids.insert(&tenant.id), "duplicate tenant {}", tenant.id);
+            let state = Arc::new(TenantState {
+                id: tenant.id.clone(),
+                limits: tenant.limits,
+                requests: tenant
+                    .limits
+                    .max_concurrent_requests
+                    .map(|max| Arc::new(Semaphore::new(max))),
+            });
+            for key in &tenant.api_keys {
+                let previous = by_key.insert(key.clone(), state.clone());
+                assert!(previous.is_none(), "API key of {} is shared", tenant.id);
+            }
+        }
+        Self { by_key }
+    }
+
+    /// The tenant a request acts for, by its `x-api-key`. Without tenants every
+    /// request shares one namespace and `None` is returned; with tenants a
+    /// request must name one.
+    pub(crate) fn resolve(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<Arc<TenantState>>, ErrorCode> {
+        if self.by_key.is_empty() {
+            return Ok(None);
+        }
+        let key = headers
+            .get(API_KEY_HEADER)
+            .ok_or(ErrorCode::CredentialMissing)?;
+        let key = key.to_str().map_err(|_| ErrorCode::CredentialRejected)?;
+        self.by_key
+            .get(key)
+            .cloned()
+            .map(Some)
+            .ok_or(ErrorCode::CredentialRejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_tenant_directory() {
+        assert!(TenantDirectory::default()
+            .resolve(&HeaderMap::new())
+            .unwrap()
+            .is_none());
+
+        let tenants = [
+            Tenant {
+                id: "a".to_string(),
+                api_keys: HashSet::from(["a1".to_string(), "a2".to_string()]),
+                limits: TenantLimits {
+                    max_concurrent_requests: Some(1),
+                    ..Default::default()
+                },
+            },
+            Tenant {
+                id: "b".to_string(),
+                api_keys: HashSet::from(["b1".to_string()]),
+                limits: TenantLimits::default(),
+            },
+        ];
+        let directory = TenantDirectory::new(&tenants);
+        let resolve = |key: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(API_KEY_HEADER, HeaderValue::from_static(key));
+            directory.resolve(&headers).map(|t| t.unwrap().id.clone())
+        };
+        assert_eq!(resolve("a2"), Ok("a".to_string()));
+        assert_eq!(resolve("b1"), Ok("b".to_string()));
+        assert_eq!(resolve("c1"), Err(ErrorCode::CredentialRejected));
+        assert_eq!(
+            directory.resolve(&HeaderMap::new()).map(|_| ()),
+            Err(ErrorCode::CredentialMissing)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("a1"));
+        let a = directory.resolve(&headers).unwrap().unwrap();
+        let permit = a.admit().unwrap();
+        assert_eq!(
+            a.admit().unwrap_err(),
+            LimitExceeded::ConcurrentRequests { max: 1 }
+        );
+        drop(permit);
+        assert!(a.admit().is_ok());
+
+        let limits = TenantLimits {
+            max_sessions: Some(2),
+            max_generator_bytes: Some(1000),
+            ..Default::default()
+        };
+        assert!(limits.check_sessions(2).is_ok());
+        assert_eq!(
+            limits.check_sessions(3),
+            Err(LimitExceeded::Sessions { max: 2 })
+        );
+        assert_eq!(
+            Problem::from(limits.check_generator_bytes(1001).unwrap_err()).code,
+            ErrorCode::TenantLimitExceeded
+        );
+        assert!(TenantLimits::default()
+            .check_generator_bytes(usize::MAX)
+            .is_ok());
+    }
+}
@@ -0,0 +1,100 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use ark_bn254::{Bn254, Fr};
+use ark_groth16::Proof;
+use ark_relations::r1cs::ConstraintSynthesizer;
+use ark_std::rand::{CryptoRng, Rng};
+
+use super::client::{Bandwidth, EmsmClient};
+use crate::error::Result;
+use crate::groth16::server_aided::{
+    client_decrypt_async, client_decrypt_with_mode, client_encrypt_async, client_encrypt_with_mode,
+    server_evaluate_async, EncryptedRequest, ProvingMode, ServerAidedProvingKey, ServerResponse,
+};
+
+/// Carries a masked prove request to whatever evaluates its 5 MSMs.
+pub trait Transport {
+    fn evaluate(
+        &self,
+        request: EncryptedRequest,
+    ) -> impl Future<Output = Result<ServerResponse>> + Send;
+}
+
+/// `POST /prove` to the client's server, setting the session up again if the
+/// server lost it (see `EmsmClient::with_auto_resetup`).
+impl Transport for EmsmClient {
+    async fn evaluate(&self, request: EncryptedRequest) -> Result<ServerResponse> {
+        let bandwidth = &mut Bandwidth::default();
+        let response = self.send_prove_with_resetup(&request, bandwidth).await?;
+        ServerResponse::try_from(&response)
+    }
+}
+
+/// Evaluates requests in-process with `server_evaluate`: no sockets and no
+/// serialization. Benchmarking against it separates the cost of EMSM itself from
+/// that of the network.
+pub struct LocalTransport {
+    sapk: Arc<ServerAidedProvingKey>,
+}
+
+impl LocalTransport {
+    /// Evaluate against the generators of `sapk`, as a server set up with it would.
+    pub fn new(sapk: Arc<ServerAidedProvingKey>) -> Self {
+        Self { sapk }
+    }
+}
+
+impl Transport for LocalTransport {
+    async fn evaluate(&self, request: EncryptedRequest) -> Result<ServerResponse> {
+        server_evaluate_async(self.sapk.clone(), request).await
+    }
+}
+
+/// Delegated prove over any transport: encrypt, evaluate, decrypt, with the
+/// client steps on tokio's blocking pool.
+pub async fn prove<T, C, R>(
+    transport: &T,
+    sapk: Arc<ServerAidedProvingKey>,
+    circuit: C,
+    rng: R,
+) -> Result<Proof<Bn254>>
+where
+    T: Transport,
+    C: ConstraintSynthesizer<Fr> + Send + 'static,
+    R: Rng + CryptoRng + Send + 'static,
+{
+    let (request, state) = client_encrypt_async(sapk.clone(), circuit, rng).await?;
+    let response = transport.evaluate(request).await?;
+    client_decrypt_async(sapk, response, state).await
+}
+
+/// `prove` under `mode`. The requests of a checked proof go out one after the
+/// other like independent proves, and a mismatch between their results fails
+/// with `MaliciousError::ConsistencyCheckFailed`.
+pub async fn prove_with_mode<T, C, R>(
+    transport: &T,
+    sapk: Arc<ServerAidedProvingKey>,
+    circuit: C,
+    mode: ProvingMode,
+    mut rng: R,
+) -> Result<Proof<Bn254>>
+where
+    T: Transport,
+    C: ConstraintSynthesizer<Fr> + Send + 'static,
+    R: Rng + CryptoRng + Send + 'static,
+{
+    let key = sapk.clone();
+    let (requests, state) = tokio::task::spawn_blocking(move || {
+        client_encrypt_with_mode(&key, circuit, mode, &mut rng)
+    })
+    .await??;
+    let mut responses = Vec::with_capacity(requests.len());
+    for request in requests {
+        responses.push(transport.evaluate(request).await?);
+    }
+    let proof =
+        tokio::task::spawn_blocking(move || client_decrypt_with_mode(&sapk, &responses, &state))
+            .await??;
+    Ok(proof)
+}
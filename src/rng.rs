@@ -0,0 +1,19 @@
+//! The randomness the crate takes. The entry points drawing keys, codes, masks
+//! or rerandomized proofs (setup, `client_encrypt*`, `emsm::encrypt*` and the
+//! like) are generic over `R: Rng + CryptoRng` (rand 0.8, as re-exported by
+//! `ark_std`), since masking is only private if `R` is cryptographically secure:
+//! `OsRng`, or a `ChaCha20Rng` seeded from it. The sampling primitives they are
+//! built from take any `Rng`. Deterministic seeds are for tests and test vectors.
+
+pub use ark_std::rand::{CryptoRng, Rng, RngCore, SeedableRng};
+pub use rand_chacha::ChaCha20Rng;
+
+#[cfg(feature = "std")]
+pub use rand::rngs::OsRng;
+
+/// Deterministic stand-in for `ark_std::test_rng` that is also a `CryptoRng`,
+/// for tests of the functions that ask for one.
+#[cfg(test)]
+pub(crate) fn test_rng() -> ChaCha20Rng {
+    ChaCha20Rng::seed_from_u64(0)
+}
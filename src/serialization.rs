@@ -0,0 +1,113 @@
+//! Byte encodings of arkworks values: compressed canonical bytes, length-prefixed
+//! vectors with a length cap, and the serde adapters carrying them. Shared by the
+//! protocol messages, the Groth16 bundles and the EMSM transcripts.
+
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use serde::Deserialize;
+
+use crate::emsm::params::MAX_LPN_N;
+use crate::error::StealthSnarkError;
+
+/// Maximum number of elements allowed in a deserialized vector.
+/// Matches the largest vector length with LPN parameters.
+pub(crate) const MAX_VEC_LEN: u64 = MAX_LPN_N as u64;
+
+/// Serialize an arkworks type to bytes.
+pub fn ark_to_bytes<T: CanonicalSerialize>(val: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    val.serialize_compressed(&mut buf)
+        .expect("serialization failed");
+    buf
+}
+
+/// Deserialize an arkworks type from bytes.
+/// Returns an error instead of panicking on malformed input.
+pub fn ark_from_bytes<T: CanonicalDeserialize>(bytes: &[u8]) -> Result<T, StealthSnarkError> {
+    T::deserialize_compressed(bytes)
+        .map_err(|e| StealthSnarkError::Malformed(format!("deserialization failed: {e}")))
+}
+
+/// Serialize a vector of arkworks types to bytes.
+pub fn ark_vec_to_bytes<T: CanonicalSerialize>(vals: &[T]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let len = vals.len() as u64;
+    len.serialize_compressed(&mut buf).unwrap();
+    for v in vals {
+        v.serialize_compressed(&mut buf).unwrap();
+    }
+    buf
+}
+
+/// Deserialize a vector of arkworks types from bytes.
+/// Returns an error on malformed input or if the length exceeds MAX_VEC_LEN.
+pub fn ark_vec_from_bytes<T: CanonicalDeserialize>(
+    bytes: &[u8],
+) -> Result<Vec<T>, StealthSnarkError> {
+    let mut cursor = bytes;
+    let len: u64 = CanonicalDeserialize::deserialize_compressed(&mut cursor)
+        .map_err(|e| StealthSnarkError::Malformed(format!("failed to read vec length: {e}")))?;
+    check_vec_len(len)?;
+    // Every element takes at least one byte, so never reserve more than the input
+    // could hold: an attacker-controlled length prefix cannot force a huge allocation.
+    let mut vals = Vec::with_capacity((len as usize).min(cursor.len()));
+    for i in 0..len {
+        let val = T::deserialize_compressed(&mut cursor).map_err(|e| {
+            StealthSnarkError::Malformed(format!("failed to deserialize element {i}: {e}"))
+        })?;
+        vals.push(val);
+    }
+    Ok(vals)
+}
+
+pub(crate) fn check_vec_len(len: u64) -> Result<(), StealthSnarkError> {
+    if len > MAX_VEC_LEN {
+        return Err(StealthSnarkError::Malformed(format!(
+            "vec length {len} exceeds maximum {MAX_VEC_LEN}"
+        )));
+    }
+    Ok(())
+}
+
+/// Serde adapter carrying an arkworks value as its compressed canonical bytes:
+/// `#[serde(with = "ark_serde")]`.
+pub mod ark_serde {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<T: CanonicalSerialize, S: Serializer>(
+        val: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&ark_to_bytes(val))
+    }
+
+    pub fn deserialize<'de, T: CanonicalDeserialize, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        ark_from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+/// `ark_serde` for vectors, in the `ark_vec_to_bytes` format and with its
+/// length limit.
+pub mod ark_serde_vec {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<T: CanonicalSerialize, S: Serializer>(
+        vals: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&ark_vec_to_bytes(vals))
+    }
+
+    pub fn deserialize<'de, T: CanonicalDeserialize, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<T>, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        ark_vec_from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
@@ -1,47 +1,88 @@
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 use ark_bn254::{Bn254, Fr, G1Affine, G2Affine};
-use ark_groth16::r1cs_to_qap::LibsnarkReduction;
-use ark_groth16::Groth16;
+use ark_ec::{CurveGroup, VariableBaseMSM};
+use ark_groth16::{Groth16, VerifyingKey};
 use ark_snark::SNARK;
+use ark_std::UniformRand;
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 
+use stealthsnark::curves::grumpkin::{self, GrumpkinAffine, GrumpkinProjective};
+use stealthsnark::emsm::emsm::EmsmPublicParams;
+use stealthsnark::emsm::malicious::malicious_encrypt;
+use stealthsnark::error::StealthSnarkError;
 use stealthsnark::groth16::circuit::CubeCircuit;
+use stealthsnark::groth16::fingerprint::Fingerprint;
 use stealthsnark::groth16::server_aided::{
-    client_decrypt, client_encrypt, ServerAidedProvingKey,
+    client_decrypt, client_encrypt, ProvingMode, ServerAidedProvingKey,
+};
+use stealthsnark::ipa::bulletproofs::{self, IpaGenerators};
+use stealthsnark::ipa::delegated::DelegatedIpaKey;
+use stealthsnark::protocol::attestation::{
+    report_data_binding, tls_key_fingerprint, AttestationError, AttestationPolicy,
+    AttestationProvider, AttestationReport, QuoteVerifier, TeePlatform,
+};
+use stealthsnark::protocol::audit::{verify_chain, AuditEvent, AuditLog, MemoryAuditSink};
+use stealthsnark::protocol::client::{EmsmClient, ProvePath};
+use stealthsnark::protocol::gate::{
+    SetupCredential, SetupGate, ADMIN_KEY_HEADER, API_KEY_HEADER,
 };
-use stealthsnark::protocol::client::EmsmClient;
 use stealthsnark::protocol::messages::*;
-use stealthsnark::protocol::server::{create_router, ServerState};
-
-/// Full integration test: spawn axum server in-process, run client flow, verify proof.
-#[tokio::test]
-async fn test_integration_e2e() {
-    let mut rng = ChaCha20Rng::seed_from_u64(42);
+use stealthsnark::protocol::metering::{compute_units, MeteringEvent, MeteringHook, SessionStats};
+use stealthsnark::protocol::proof_cache::ProofCache;
+use stealthsnark::protocol::server::{
+    create_router, ServerConfig, ServerState, SetupEnvelope, DEFAULT_CIRCUIT,
+};
+use stealthsnark::protocol::transport::{self, LocalTransport};
 
-    // Spawn server in-process on a random port
-    let state = Arc::new(RwLock::new(ServerState::new()));
-    let app = create_router(state);
+/// Serve `app` on a random local port, returning its URL.
+async fn serve(app: axum::Router) -> String {
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
         .await
         .expect("bind failed");
-    let addr = listener.local_addr().unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
     tokio::spawn(async move {
         axum::serve(listener, app).await.unwrap();
     });
+    url
+}
 
-    let server_url = format!("http://{addr}");
-    let session_id = "test-session-42".to_string();
+/// Spawn an in-process server with `config`, returning its URL.
+async fn spawn_server(config: ServerConfig) -> String {
+    let state = Arc::new(RwLock::new(ServerState::with_config(config)));
+    serve(create_router(state)).await
+}
+
+/// URL of a port nothing listens on.
+async fn closed_port() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind failed");
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// A server-aided proving key of `CubeCircuit`, with its verifying key.
+fn cube_sapk(rng: &mut ChaCha20Rng) -> (ServerAidedProvingKey, VerifyingKey<Bn254>) {
+    let circuit = CubeCircuit::<Fr> { x: None };
+    let (pk, vk) = Groth16::<Bn254>::circuit_specific_setup(circuit, rng).unwrap();
+    (ServerAidedProvingKey::setup(pk, rng), vk)
+}
+
+/// Full integration test: spawn axum server in-process, run client flow, verify proof.
+#[tokio::test]
+async fn test_integration_e2e() {
+    let mut rng = ChaCha20Rng::seed_from_u64(42);
 
-    // Groth16 setup
-    let circuit_for_setup = CubeCircuit::<Fr> { x: None };
-    let (pk, vk) =
-        Groth16::<Bn254>::circuit_specific_setup(circuit_for_setup, &mut rng).unwrap();
+    // Spawn server in-process on a random port
+    let server_url = spawn_server(ServerConfig::default()).await;
+    let session_id = "test-session-42".to_string();
 
-    // Server-aided proving key
-    let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+    // Groth16 and server-aided proving keys
+    let (sapk, vk) = cube_sapk(&mut rng);
 
     // Send generators
     let http_client = EmsmClient::new(&server_url, session_id);
@@ -51,6 +92,8 @@ async fn test_integration_e2e() {
         a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
         b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
         b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
     };
     http_client
         .send_setup(&setup_request)
@@ -60,70 +103,623 @@ async fn test_integration_e2e() {
     // Encrypt
     let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
     let (request, state) =
-        client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit, &mut rng).unwrap();
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
 
     // Prove via server
-    let prove_request = ProveRequest {
-        v_h: ark_vec_to_bytes(&request.v_h),
-        v_l: ark_vec_to_bytes(&request.v_l),
-        v_a: ark_vec_to_bytes(&request.v_a),
-        v_b_g1: ark_vec_to_bytes(&request.v_b_g1),
-        v_b_g2: ark_vec_to_bytes(&request.v_b_g2),
-    };
+    let prove_request = ProveRequest::from(&request);
     let prove_response = http_client
         .send_prove(&prove_request)
         .await
         .expect("prove failed");
 
     // Decode response
-    let server_response = stealthsnark::groth16::server_aided::ServerResponse {
-        em_h: ark_from_bytes::<G1Affine>(&prove_response.em_h)
-            .unwrap()
-            .into(),
-        em_l: ark_from_bytes::<G1Affine>(&prove_response.em_l)
-            .unwrap()
-            .into(),
-        em_a: ark_from_bytes::<G1Affine>(&prove_response.em_a)
-            .unwrap()
-            .into(),
-        em_b_g1: ark_from_bytes::<G1Affine>(&prove_response.em_b_g1)
-            .unwrap()
-            .into(),
-        em_b_g2: ark_from_bytes::<G2Affine>(&prove_response.em_b_g2)
-            .unwrap()
-            .into(),
-    };
+    let server_response = stealthsnark::groth16::server_aided::ServerResponse::try_from(&prove_response).unwrap();
 
     // Decrypt and verify
-    let proof = client_decrypt(&sapk, &server_response, &state);
+    let proof = client_decrypt(&sapk, &server_response, &state).unwrap();
 
     let public_inputs = vec![Fr::from(35u64)];
     let valid = Groth16::<Bn254>::verify(&vk, &public_inputs, &proof).unwrap();
     assert!(valid, "Integration test: proof should verify!");
 }
 
+/// `EmsmClient::prove` runs the whole delegated flow on a single runtime worker,
+/// which also serves the in-process server.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_async_prove() {
+    let mut rng = ChaCha20Rng::seed_from_u64(8);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "async".to_string());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client.prove(sapk, circuit, rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Test that a client with local fallback still proves when the server is down
+/// or does not know the session, and reports the path taken.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_local_fallback() {
+    let mut rng = ChaCha20Rng::seed_from_u64(22);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+    // A port nothing listens on
+    let closed = closed_port().await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let generators_hash = SetupRequest::from(sapk.as_ref()).generators_hash();
+
+    let client = EmsmClient::new(&server_url, "fallback".to_string())
+        .with_local_fallback();
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let report = client
+        .prove_with_report(sapk.clone(), CubeCircuit { x: Some(Fr::from(3u64)) }, rng.clone())
+        .await
+        .unwrap();
+    assert_eq!(report.path, ProvePath::Delegated);
+    let bandwidth = report.bandwidth;
+    assert!(bandwidth.msms.iter().all(|msm| msm.scalars > 0));
+    assert_eq!(
+        bandwidth.msms.iter().map(|msm| msm.received).sum::<u64>(),
+        4 * 32 + 64
+    );
+    assert!(bandwidth.msms.iter().map(|msm| msm.sent).sum::<u64>() > bandwidth.scalar_bytes());
+    assert!(bandwidth.sent > bandwidth.scalar_bytes());
+    assert!(bandwidth.received > 4 * 32 + 64);
+
+    for client in [
+        EmsmClient::new(&closed, "fallback".to_string()),
+        EmsmClient::new(&server_url, "never-set-up".to_string()),
+    ] {
+        let client = client.with_generators_hash(generators_hash);
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        assert!(client.prove(sapk.clone(), circuit, rng.clone()).await.is_err());
+
+        let client = client.with_local_fallback();
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let report = client
+            .prove_with_report(sapk.clone(), circuit, rng.clone())
+            .await
+            .unwrap();
+        assert_eq!(report.path, ProvePath::Local);
+        assert_eq!(report.bandwidth.received, 0);
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &report.proof).unwrap());
+    }
+}
+
+/// Test that a statement proven once is answered from a shared proof cache,
+/// without reaching the server, and that other statements still go out.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_proof_cache() {
+    let mut rng = ChaCha20Rng::seed_from_u64(23);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+    let closed = closed_port().await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let generators_hash = SetupRequest::from(sapk.as_ref()).generators_hash();
+    let cache = Arc::new(ProofCache::new(8));
+
+    let client = EmsmClient::new(&server_url, "cached".to_string())
+        .with_proof_cache(cache.clone());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let first = client
+        .prove_with_report(sapk.clone(), circuit, rng.clone())
+        .await
+        .unwrap();
+    assert_eq!(first.path, ProvePath::Delegated);
+    assert_eq!(cache.len(), 1);
+
+    let offline = EmsmClient::new(&closed, "cached".to_string())
+        .with_generators_hash(generators_hash)
+        .with_proof_cache(cache.clone());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let again = offline
+        .prove_with_report(sapk.clone(), circuit, rng.clone())
+        .await
+        .unwrap();
+    assert_eq!(again.path, ProvePath::Cached);
+    assert_eq!(again.bandwidth, Default::default());
+    assert_ne!(again.proof, first.proof);
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &again.proof).unwrap());
+
+    let circuit = CubeCircuit { x: Some(Fr::from(4u64)) };
+    assert!(offline.prove(sapk.clone(), circuit, rng).await.is_err());
+    assert_eq!(cache.len(), 1);
+}
+
+/// Test that the generic prove works over both the HTTP client and the
+/// in-process transport.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_transports() {
+    let mut rng = ChaCha20Rng::seed_from_u64(23);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "transport".to_string());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = transport::prove(&client, sapk.clone(), circuit, rng.clone())
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    let local = LocalTransport::new(sapk.clone());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = transport::prove(&local, sapk.clone(), circuit, rng.clone())
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    for mode in [
+        ProvingMode::Malicious,
+        ProvingMode::Covert { deterrence: 0.25 },
+        ProvingMode::CutAndChoose { queries: 3 },
+    ] {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let proof = transport::prove_with_mode(&client, sapk.clone(), circuit, mode, rng.clone())
+            .await
+            .unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+}
+
+/// Test that a server injecting faults yields proofs that fail verification,
+/// over both the unsplit and the split prove.
+#[cfg(feature = "fault-injection")]
+#[tokio::test]
+async fn test_fault_injection() {
+    use stealthsnark::protocol::fault::FaultInjector;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(24);
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+
+    for (fault, verifies) in [
+        ("negate:h", [false, false]),
+        ("add:b_g2", [false, false]),
+        // The first answer of a session is honest, later ones are replayed
+        ("stale", [true, false]),
+    ] {
+        let config = ServerConfig {
+            fault: Some(Arc::new(FaultInjector::new(fault.parse().unwrap()))),
+            ..Default::default()
+        };
+        let server_url = spawn_server(config).await;
+
+        let client = EmsmClient::new(&server_url, fault.to_string()).with_http2();
+        client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+        for verifies in verifies {
+            let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+            let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+            let proof = transport::prove(&client, sapk.clone(), circuit, prove_rng)
+                .await
+                .unwrap();
+            assert_eq!(
+                Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap(),
+                verifies,
+                "{fault}"
+            );
+        }
+
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let response = client
+            .send_prove_split(ProveRequest::from(&request))
+            .await
+            .unwrap();
+        let response =
+            stealthsnark::groth16::server_aided::ServerResponse::try_from(&response).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(!Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+}
+
+/// Test a prove whose witness map FFTs are also delegated, over POST /fft.
+#[tokio::test]
+async fn test_delegated_witness_map() {
+    use stealthsnark::groth16::server_aided::client_encrypt_with_h;
+    use stealthsnark::groth16::witness_map::{synthesize, FftStep, WitnessMapMasks};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(25);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let client = EmsmClient::new(&server_url, "fft".to_string());
+
+    // FFTs need a live session, like proves, and a power-of-two length
+    let ones = |n| vec![Fr::from(1u64); n];
+    assert!(client.send_fft(FftStep::Extend, &[ones(4)]).await.is_err());
+    client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+    assert!(client.send_fft(FftStep::Extend, &[ones(3)]).await.is_err());
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (witness, evaluations) = synthesize(&sapk, circuit).unwrap();
+    let masks = WitnessMapMasks::generate(evaluations.domain_size(), &mut rng).unwrap();
+    let h = client.witness_map(evaluations, masks).await.unwrap();
+    let (request, state) = client_encrypt_with_h(&sapk, witness, h, &mut rng).unwrap();
+    let response = client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+    let response =
+        stealthsnark::groth16::server_aided::ServerResponse::try_from(&response).unwrap();
+    let proof = client_decrypt(&sapk, &response, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// The public-input MSMs evaluated by the server, for circuits set up with
+/// their public generators.
+#[tokio::test]
+async fn test_public_input_msm() {
+    use stealthsnark::groth16::server_aided::{
+        client_decrypt_with_public, PublicGenerators, ServerResponse,
+    };
+
+    let mut rng = ChaCha20Rng::seed_from_u64(26);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let public_inputs = [Fr::from(35u64)];
+
+    // Without public generators the server cannot evaluate them
+    let client = EmsmClient::new(&server_url, "private".to_string());
+    client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+    assert!(client.send_public_inputs(&public_inputs).await.is_err());
+
+    // Nor with queries of different lengths
+    let mut mismatched = PublicGenerators::from(&sapk);
+    mismatched.b_g2.pop();
+    let setup = SetupRequest {
+        public_generators: ark_to_bytes(&mismatched),
+        ..SetupRequest::from(&sapk)
+    };
+    let client = EmsmClient::new(&server_url, "mismatched".to_string());
+    let err = client.send_setup(&setup).await.unwrap_err();
+    assert_eq!(err.server_error().unwrap().status(), 400);
+    // Or without even the constant term
+    let empty = PublicGenerators {
+        a: vec![],
+        b_g1: vec![],
+        b_g2: vec![],
+    };
+    let setup = SetupRequest {
+        public_generators: ark_to_bytes(&empty),
+        ..SetupRequest::from(&sapk)
+    };
+    let err = client.send_setup(&setup).await.unwrap_err();
+    assert_eq!(err.server_error().unwrap().status(), 400);
+
+    let client = EmsmClient::new(&server_url, "public".to_string());
+    let setup = SetupRequest::from(&sapk).with_public_generators(&sapk);
+    client.send_setup(&setup).await.unwrap();
+    assert!(client.send_public_inputs(&[]).await.is_err());
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let response = client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+    let response = ServerResponse::try_from(&response).unwrap();
+    let public = client.send_public_inputs(state.public_inputs()).await.unwrap();
+    let proof = client_decrypt_with_public(&sapk, &response, &public, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &public_inputs, &proof).unwrap());
+}
+
+/// A client with a security policy refuses to delegate what the policy forbids,
+/// before sending anything.
+#[tokio::test]
+async fn test_security_policy() {
+    use stealthsnark::protocol::policy::{PolicyViolation, SecurityPolicy};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(27);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+
+    let plaintext = EmsmClient::new(&server_url, "tls".to_string())
+        .with_security_policy(SecurityPolicy {
+            require_tls: true,
+            ..Default::default()
+        });
+    let err = plaintext.send_setup(&SetupRequest::from(&*sapk)).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StealthSnarkError::Policy(PolicyViolation::PlaintextTransport(_))
+    ));
+    // The generators never reached the server
+    let unchecked = EmsmClient::new(&server_url, "tls".to_string());
+    assert!(unchecked.extend_session().await.is_err());
+
+    let client = EmsmClient::new(&server_url, "malicious".to_string())
+        .with_security_policy(SecurityPolicy {
+            require_malicious: true,
+            ..Default::default()
+        });
+    client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let err = client
+        .prove(sapk.clone(), circuit.clone(), ChaCha20Rng::from_rng(&mut rng).unwrap())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        StealthSnarkError::Policy(PolicyViolation::ModeNotMalicious(ProvingMode::SemiHonest))
+    ));
+    assert_eq!(sapk.emsm_h.queries(), 0);
+    let proof = client
+        .prove_with_mode(sapk.clone(), circuit, ProvingMode::Malicious, rng)
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    assert_eq!(sapk.emsm_h.queries(), 2);
+}
+
+/// Test that setup and prove errors come back as problem bodies naming the
+/// offending field.
+#[tokio::test]
+async fn test_problem_details() {
+    use stealthsnark::protocol::client::ServerError;
+    use stealthsnark::protocol::problem::ErrorCode;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(28);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let setup_req = SetupRequest::from(&sapk);
+
+    let unnamed = EmsmClient::new(&server_url, "problems".to_string()).with_circuit("");
+    let err = unnamed.send_setup(&setup_req).await.unwrap_err();
+    let err = err.server_error().unwrap();
+    assert_eq!(err.status(), 400);
+    assert_eq!(err.code(), Some(ErrorCode::InvalidCircuitName));
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let mut prove_req = ProveRequest::from(&request);
+    let unknown = EmsmClient::new(&server_url, "problems".to_string())
+        .with_generators_hash(setup_req.generators_hash());
+    let err = unknown.send_prove(&prove_req).await.err().unwrap();
+    assert_eq!(
+        err.server_error().unwrap().code(),
+        Some(ErrorCode::UnknownSession)
+    );
+
+    let client = EmsmClient::new(&server_url, "problems".to_string());
+    client.send_setup(&setup_req).await.unwrap();
+    prove_req.v_h = ark_vec_to_bytes(&request.v_h[1..]);
+    let err = client.send_prove(&prove_req).await.err().unwrap();
+    let Some(ServerError::Problem { problem, .. }) = err.server_error() else {
+        panic!("expected a problem body: {err}");
+    };
+    assert_eq!(problem.code, ErrorCode::LengthMismatch);
+    assert_eq!(problem.field.as_deref(), Some("v_h"));
+    assert_eq!(problem.expected, Some(request.v_h.len() as u64));
+    assert_eq!(problem.actual, Some(request.v_h.len() as u64 - 1));
+}
+
+/// Test that a client offering raw Montgomery scalars proves with them once the
+/// server accepts, next to a client that keeps canonical scalars.
+#[tokio::test]
+async fn test_raw_scalar_encoding() {
+    let mut rng = ChaCha20Rng::seed_from_u64(30);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let raw = EmsmClient::new(&server_url, "raw".to_string()).with_raw_scalars();
+    let canonical = EmsmClient::new(&server_url, "canonical".to_string());
+    assert_eq!(raw.scalar_encoding(), ScalarEncoding::Canonical);
+    raw.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    canonical.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    assert_eq!(raw.scalar_encoding(), ScalarEncoding::Montgomery);
+    assert_eq!(canonical.scalar_encoding(), ScalarEncoding::Canonical);
+
+    for client in [&raw, &canonical] {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+        let proof = client.prove(sapk.clone(), circuit, rng).await.unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+}
+
+/// Test a client that negotiates the JSON codec: proves, MSM sub-requests and
+/// pre-encoded bincode requests all go through, and unknown codecs get 415.
+#[tokio::test]
+async fn test_json_codec() {
+    use stealthsnark::groth16::server_aided::ServerResponse;
+    use stealthsnark::protocol::codec::WireCodec;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(31);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "json".to_string())
+        .with_codec(WireCodec::Json)
+        .with_raw_scalars();
+    client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    assert_eq!(client.codec(), WireCodec::Json);
+    assert_eq!(client.scalar_encoding(), ScalarEncoding::Montgomery);
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove(sapk.clone(), circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    for split in [false, true] {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let request = ProveRequest::from(&request);
+        let response = if split {
+            client.send_prove_split(request).await.unwrap()
+        } else {
+            let encoded = bincode::serialize(&request).unwrap();
+            client.send_prove_encoded(encoded).await.unwrap()
+        };
+        let response = ServerResponse::try_from(&response).unwrap();
+        let proof = client_decrypt(&sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+
+    let resp = reqwest::Client::new()
+        .post(format!("{server_url}/prove"))
+        .header("Content-Type", "application/cbor")
+        .body(vec![0u8; 8])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 415);
+}
+
+/// Test that request IDs reach prove responses and errors, and that the server
+/// echoes the ID it traced a request under.
+#[tokio::test]
+async fn test_request_ids() {
+
+    let mut rng = ChaCha20Rng::seed_from_u64(32);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let setup_req = SetupRequest::from(&sapk);
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+
+    let client = EmsmClient::new(&server_url, "ids".to_string())
+        .with_generators_hash(setup_req.generators_hash());
+    let err = client
+        .send_prove(&ProveRequest::from(&request))
+        .await
+        .err()
+        .unwrap();
+    let err = err.server_error().unwrap();
+    assert_ne!(err.request_id(), RequestId::default());
+    assert!(err.to_string().contains(&err.request_id().to_string()));
+
+    client.send_setup(&setup_req).await.unwrap();
+    let prove = client
+        .send_prove(&ProveRequest::from(&request))
+        .await
+        .unwrap();
+    let split = client
+        .send_prove_split(ProveRequest::from(&request))
+        .await
+        .unwrap();
+    assert_ne!(prove.metadata.request_id, RequestId::default());
+    assert_ne!(split.metadata.request_id, prove.metadata.request_id);
+
+    let http = reqwest::Client::new();
+    let id = RequestId([7; 16]);
+    let resp = http
+        .get(format!("{server_url}/commitment/ids"))
+        .header(REQUEST_ID_HEADER, id.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[REQUEST_ID_HEADER], id.to_string().as_str());
+    let resp = http
+        .get(format!("{server_url}/commitment/ids"))
+        .header(REQUEST_ID_HEADER, "not-an-id")
+        .send()
+        .await
+        .unwrap();
+    let echoed = resp.headers()[REQUEST_ID_HEADER].as_bytes();
+    assert!(RequestId::from_header_value(echoed).is_some());
+}
+
+/// Log lines written while the subscriber of `test_structured_logs` is set.
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Test that the server logs a prove with the session and circuit on its
+/// request span and the sizes, durations and hashes as fields.
+#[tokio::test]
+async fn test_structured_logs() {
+    let mut rng = ChaCha20Rng::seed_from_u64(47);
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "logged".to_string());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    client.prove(sapk.clone(), circuit, rng).await.unwrap();
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let line = |message: &str| {
+        logs.lines()
+            .find(|line| line.contains(message))
+            .unwrap_or_else(|| panic!("no {message:?} in {logs}"))
+            .to_string()
+    };
+    let computing = line("Computing 5 MSMs");
+    assert!(computing.contains("session_id=\"logged\""), "{computing}");
+    assert!(computing.contains("circuit=\"default\""), "{computing}");
+    let h = format!("h={}", sapk.emsm_h.generators.len());
+    assert!(computing.contains(&h), "{computing}");
+    let served = line("Prove served");
+    assert!(served.contains("duration_ms="), "{served}");
+    assert!(served.contains("response_hash="), "{served}");
+    assert!(line("Request served").contains("status=200"));
+}
+
 /// Test that multiple sessions are isolated from each other.
 #[tokio::test]
 async fn test_session_isolation() {
     let mut rng = ChaCha20Rng::seed_from_u64(99);
 
-    let state = Arc::new(RwLock::new(ServerState::new()));
-    let app = create_router(state);
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
-        .await
-        .expect("bind failed");
-    let addr = listener.local_addr().unwrap();
-    tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
-    });
-
-    let server_url = format!("http://{addr}");
+    let server_url = spawn_server(ServerConfig::default()).await;
 
     // Setup session A
-    let circuit_for_setup = CubeCircuit::<Fr> { x: None };
-    let (pk, vk) =
-        Groth16::<Bn254>::circuit_specific_setup(circuit_for_setup, &mut rng).unwrap();
-    let sapk = ServerAidedProvingKey::setup(pk, &mut rng);
+    let (sapk, vk) = cube_sapk(&mut rng);
 
     let client_a = EmsmClient::new(&server_url, "session-a".to_string());
     let setup_req = SetupRequest {
@@ -132,21 +728,18 @@ async fn test_session_isolation() {
         a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
         b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
         b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
     };
     client_a.send_setup(&setup_req).await.unwrap();
 
     // Client B tries to prove against session-b which was never set up
-    let client_b = EmsmClient::new(&server_url, "session-b".to_string());
+    let client_b = EmsmClient::new(&server_url, "session-b".to_string())
+        .with_generators_hash(setup_req.generators_hash());
     let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
     let (request, _state) =
-        client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit, &mut rng).unwrap();
-    let prove_req = ProveRequest {
-        v_h: ark_vec_to_bytes(&request.v_h),
-        v_l: ark_vec_to_bytes(&request.v_l),
-        v_a: ark_vec_to_bytes(&request.v_a),
-        v_b_g1: ark_vec_to_bytes(&request.v_b_g1),
-        v_b_g2: ark_vec_to_bytes(&request.v_b_g2),
-    };
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_req = ProveRequest::from(&request);
 
     let result = client_b.send_prove(&prove_req).await;
     assert!(result.is_err(), "Prove against unknown session should fail");
@@ -154,24 +747,1599 @@ async fn test_session_isolation() {
     // Client A should still work
     let circuit2 = CubeCircuit { x: Some(Fr::from(3u64)) };
     let (request2, state2) =
-        client_encrypt::<LibsnarkReduction, _, _>(&sapk, circuit2, &mut rng).unwrap();
-    let prove_req2 = ProveRequest {
-        v_h: ark_vec_to_bytes(&request2.v_h),
-        v_l: ark_vec_to_bytes(&request2.v_l),
-        v_a: ark_vec_to_bytes(&request2.v_a),
-        v_b_g1: ark_vec_to_bytes(&request2.v_b_g1),
-        v_b_g2: ark_vec_to_bytes(&request2.v_b_g2),
-    };
+        client_encrypt(&sapk, circuit2, &mut rng).unwrap();
+    let prove_req2 = ProveRequest::from(&request2);
     let prove_resp = client_a.send_prove(&prove_req2).await.unwrap();
 
-    let server_response = stealthsnark::groth16::server_aided::ServerResponse {
-        em_h: ark_from_bytes::<G1Affine>(&prove_resp.em_h).unwrap().into(),
-        em_l: ark_from_bytes::<G1Affine>(&prove_resp.em_l).unwrap().into(),
-        em_a: ark_from_bytes::<G1Affine>(&prove_resp.em_a).unwrap().into(),
-        em_b_g1: ark_from_bytes::<G1Affine>(&prove_resp.em_b_g1).unwrap().into(),
-        em_b_g2: ark_from_bytes::<G2Affine>(&prove_resp.em_b_g2).unwrap().into(),
-    };
-    let proof = client_decrypt(&sapk, &server_response, &state2);
+    let server_response = stealthsnark::groth16::server_aided::ServerResponse::try_from(&prove_resp).unwrap();
+    let proof = client_decrypt(&sapk, &server_response, &state2).unwrap();
     let valid = Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap();
     assert!(valid, "Session A should still produce valid proofs");
 }
+
+/// Test that one session holds several circuits, each proved against its own
+/// generators, and that an unknown circuit name is refused.
+#[tokio::test]
+async fn test_session_circuits() {
+    let mut rng = ChaCha20Rng::seed_from_u64(31);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    // Two independent keys for the same circuit shape, so different generators
+    let mut keys: Vec<(EmsmClient, _, _)> = Vec::new();
+    for name in ["first", "second"] {
+        let (sapk, vk) = cube_sapk(&mut rng);
+        let mut client = EmsmClient::new(&server_url, "multi".to_string()).with_circuit(name);
+        // Circuits after the first are added under the session's token
+        if let Some((first, _, _)) = keys.first() {
+            client = client.with_session_token(first.session_token().unwrap());
+        }
+        client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+        keys.push((client, sapk, vk));
+    }
+
+    // Proving for one circuit does not disturb the other
+    for (client, sapk, vk) in keys.iter().rev() {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, state) = client_encrypt(sapk, circuit, &mut rng).unwrap();
+        let prove_resp = client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+        let response =
+            stealthsnark::groth16::server_aided::ServerResponse::try_from(&prove_resp).unwrap();
+        let proof = client_decrypt(sapk, &response, &state).unwrap();
+        assert!(Groth16::<Bn254>::verify(vk, &[Fr::from(35u64)], &proof).unwrap());
+        client.audit_commitment(&SetupRequest::from(sapk)).await.unwrap();
+    }
+
+    let (first, sapk, _) = &keys[0];
+    let unknown = EmsmClient::new(&server_url, "multi".to_string())
+        .with_circuit("third")
+        .with_session_token(first.session_token().unwrap())
+        .with_generators_hash(SetupRequest::from(sapk).generators_hash());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(sapk, circuit, &mut rng).unwrap();
+    let err = match unknown.send_prove(&ProveRequest::from(&request)).await {
+        Ok(_) => panic!("prove against an unknown circuit should fail"),
+        Err(e) => e,
+    };
+    assert!(err.to_string().contains("412"), "unexpected error: {err}");
+}
+
+/// Test that a prove request is refused once its session was set up again with
+/// different generators, which takes the session's token.
+#[tokio::test]
+async fn test_overwritten_session_rejects_stale_prove() {
+    use stealthsnark::protocol::problem::ErrorCode;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(5);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let setup = |rng: &mut ChaCha20Rng| {
+        let (sapk, _vk) = cube_sapk(rng);
+        let setup_req = SetupRequest {
+            h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+            l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+            a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+            b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+            b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+            public_generators: Vec::new(),
+            key_fingerprint: None,
+        };
+        (sapk, setup_req)
+    };
+    let (sapk_1, setup_1) = setup(&mut rng);
+    let (sapk_2, setup_2) = setup(&mut rng);
+
+    let client_1 = EmsmClient::new(&server_url, "shared".to_string());
+    let response = client_1.send_setup(&setup_1).await.unwrap();
+    assert_eq!(response.generators_hash, setup_1.generators_hash());
+    // Without the session's token, the session cannot be set up again
+    let err = EmsmClient::new(&server_url, "shared".to_string())
+        .send_setup(&setup_2)
+        .await
+        .unwrap_err();
+    let err = err.server_error().unwrap();
+    assert_eq!(err.code(), Some(ErrorCode::SessionTokenRejected));
+    let client_2 = EmsmClient::new(&server_url, "shared".to_string())
+        .with_session_token(client_1.session_token().unwrap());
+    client_2.send_setup(&setup_2).await.unwrap();
+
+    let mut prove_request = |sapk: &ServerAidedProvingKey| {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, _state) =
+            client_encrypt(sapk, circuit, &mut rng).unwrap();
+        ProveRequest::from(&request)
+    };
+    let err = client_1
+        .send_prove(&prove_request(&sapk_1))
+        .await
+        .err()
+        .expect("prove against overwritten session should fail");
+    assert!(err.to_string().contains("409"), "{err}");
+    assert!(client_2.send_prove(&prove_request(&sapk_2)).await.is_ok());
+
+    // A client that never ran setup has nothing to echo
+    let fresh = EmsmClient::new(&server_url, "shared".to_string());
+    assert!(fresh.send_prove(&prove_request(&sapk_2)).await.is_err());
+}
+
+/// Test that a client can audit the server's generators against its own proving key.
+#[tokio::test]
+async fn test_commitment_audit() {
+    let mut rng = ChaCha20Rng::seed_from_u64(4);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let mut sapk = || cube_sapk(&mut rng).0;
+    let (expected, swapped) = (sapk(), sapk());
+
+    let uploader = EmsmClient::new(&server_url, "circuit".to_string());
+    assert!(uploader.fetch_commitment().await.is_err());
+    uploader
+        .send_setup(&SetupRequest::from(&expected))
+        .await
+        .unwrap();
+
+    // A client holding the same proving key accepts the published commitment
+    let auditor = EmsmClient::new(&server_url, "circuit".to_string())
+        .with_session_token(uploader.session_token().unwrap());
+    let commitment = auditor
+        .audit_commitment(&SetupRequest::from(&expected))
+        .await
+        .unwrap();
+    assert_eq!(commitment.sizes[0], expected.emsm_h.generators.len());
+    assert_eq!(commitment.key_fingerprint, Some(expected.fingerprint()));
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) =
+        client_encrypt(&expected, circuit, &mut rng).unwrap();
+    assert!(auditor.send_prove(&ProveRequest::from(&request)).await.is_ok());
+
+    // One expecting other generators refuses to delegate
+    let err = EmsmClient::new(&server_url, "circuit".to_string())
+        .audit_commitment(&SetupRequest::from(&swapped))
+        .await
+        .unwrap_err();
+    let StealthSnarkError::CommitmentMismatch(mismatch) = err else {
+        panic!("expected a commitment mismatch, got {err}");
+    };
+    assert_eq!(mismatch.published, commitment);
+
+    // The same generators from another proving key are told apart by its
+    // fingerprint
+    let mut other_key = SetupRequest::from(&expected);
+    other_key.key_fingerprint = Some(swapped.fingerprint());
+    let err = auditor.audit_commitment(&other_key).await.unwrap_err();
+    let StealthSnarkError::KeyMismatch(mismatch) = err else {
+        panic!("expected a key mismatch, got {err}");
+    };
+    assert_eq!(mismatch.actual, expected.fingerprint());
+}
+
+/// Test that a circuit set up again uploads only the generator chunks that
+/// changed, and that the patched generators prove.
+#[tokio::test]
+async fn test_setup_diff() {
+    let mut rng = ChaCha20Rng::seed_from_u64(57);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let mut sapk = || cube_sapk(&mut rng).0;
+    let (old, new) = (sapk(), sapk());
+    let (old_request, new_request) = (SetupRequest::from(&old), SetupRequest::from(&new));
+
+    // A circuit the server does not hold yet is uploaded whole
+    let client = EmsmClient::new(&server_url, "diff".to_string());
+    let response = client.send_setup_diff(&old_request).await.unwrap();
+    assert_eq!(response.generators_hash, old_request.generators_hash());
+
+    let chunks = client.fetch_generator_chunks().await.unwrap();
+    assert_eq!(chunks.generators_hash, old_request.generators_hash());
+    assert_eq!(chunks.hashes[0].len(), 1);
+    assert!(SetupPatch::new(&chunks, &old_request)
+        .unwrap()
+        .chunks
+        .is_empty());
+
+    // Then only what changed, spliced into the generators the server holds
+    let response = client.send_setup_diff(&new_request).await.unwrap();
+    assert_eq!(response.generators_hash, new_request.generators_hash());
+    let commitment = client.audit_commitment(&new_request).await.unwrap();
+    assert_eq!(commitment.key_fingerprint, Some(new.fingerprint()));
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&new, circuit, &mut rng).unwrap();
+    assert!(client.send_prove(&ProveRequest::from(&request)).await.is_ok());
+}
+
+/// Test that generators larger than the server's setup body limit are uploaded
+/// in parts, and prove.
+#[tokio::test]
+async fn test_chunked_setup() {
+    let mut rng = ChaCha20Rng::seed_from_u64(58);
+
+    let server_url = spawn_server(ServerConfig {
+        max_setup_bytes: 512,
+        ..Default::default()
+    })
+    .await;
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let setup = SetupRequest::from(&sapk);
+    let fingerprint = sapk.fingerprint();
+
+    // Whole, the upload is too large
+    let client = EmsmClient::new(&server_url, "chunked".to_string());
+    let err = client.send_setup(&setup).await.unwrap_err();
+    assert_eq!(err.server_error().unwrap().status(), 413);
+
+    // In parts it is set up, and set up again with the session's token
+    for _ in 0..2 {
+        let response = client.send_setup_chunked(&setup, 128).await.unwrap();
+        assert_eq!(response.generators_hash, setup.generators_hash());
+    }
+    let commitment = client.audit_commitment(&setup).await.unwrap();
+    assert_eq!(commitment.key_fingerprint, Some(fingerprint));
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client.prove(Arc::new(sapk), circuit, rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    // A chunk corrupted in transit is refused by its checksum
+    let mut part = SetupChunk::split(&setup, 128).unwrap().next().unwrap();
+    part.chunks[0].points[0] ^= 1;
+    let envelope = SetupEnvelope {
+        session_id: "corrupt".to_string(),
+        circuit: DEFAULT_CIRCUIT.to_string(),
+        curve: CurveId::Bn254,
+        request: part,
+    };
+    let resp = reqwest::Client::new()
+        .post(format!("{server_url}/setup/chunk"))
+        .body(bincode::serialize(&envelope).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let problem: stealthsnark::protocol::problem::Problem = resp.json().await.unwrap();
+    assert_eq!(problem.field.as_deref(), Some("chunks"));
+    assert!(problem.detail.unwrap().contains("checksum"));
+
+    // Another client cannot add parts to the session without its token
+    let intruder = EmsmClient::new(&server_url, "chunked".to_string());
+    let err = intruder.send_setup_chunked(&setup, 128).await.unwrap_err();
+    assert_eq!(err.server_error().unwrap().status(), 403);
+}
+
+/// Test that setup and prove requests tagged for another curve are refused.
+#[tokio::test]
+async fn test_curve_mismatch_rejected() {
+    let mut rng = ChaCha20Rng::seed_from_u64(6);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let setup_req = SetupRequest {
+        h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+        l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+        a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+        b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+        b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+
+    // The server only evaluates BN254, and says so
+    let bls = EmsmClient::new(&server_url, "session".to_string())
+        .with_curve(CurveId::Bls12_381)
+        .with_generators_hash(setup_req.generators_hash());
+    let capabilities = bls.capabilities().await.unwrap();
+    assert_eq!(capabilities.curves, [CurveId::Bn254, CurveId::Grumpkin]);
+    assert!(capabilities
+        .codecs
+        .contains(&"application/octet-stream".to_string()));
+    let err = bls.send_setup(&setup_req).await.unwrap_err();
+    assert!(err.to_string().contains("422"), "{err}");
+
+    // A BN254 session refuses prove requests tagged for another curve
+    let bn = EmsmClient::new(&server_url, "session".to_string());
+    bn.send_setup(&setup_req).await.unwrap();
+    let bls = bls.with_session_token(bn.session_token().unwrap());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) =
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_req = ProveRequest::from(&request);
+    let err = bls
+        .send_prove(&prove_req)
+        .await
+        .err()
+        .expect("prove tagged for the wrong curve should fail");
+    assert!(err.to_string().contains("422"), "{err}");
+    assert!(bn.send_prove(&prove_req).await.is_ok());
+}
+
+/// Test that a Pedersen commitment over Grumpkin, which has no pairing, is
+/// delegated through a generator set.
+#[tokio::test]
+async fn test_delegated_commitment() {
+    let mut rng = ChaCha20Rng::seed_from_u64(52);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let generators: Vec<GrumpkinAffine> = (0..64)
+        .map(|_| GrumpkinProjective::rand(&mut rng).into_affine())
+        .collect();
+    let witness: Vec<grumpkin::ScalarField> = (0..64)
+        .map(|_| grumpkin::ScalarField::rand(&mut rng))
+        .collect();
+    let params = EmsmPublicParams::<GrumpkinProjective>::new(generators.clone(), &mut rng);
+    let preprocessed = params.preprocess();
+
+    let client = EmsmClient::new(&server_url, "commitments".to_string()).with_circuit("pedersen");
+    client
+        .send_generator_set::<GrumpkinProjective>(&generators)
+        .await
+        .unwrap();
+    let commitment = client
+        .commit(&params, &preprocessed, &witness, &mut rng)
+        .await
+        .unwrap();
+    assert_eq!(
+        commitment,
+        GrumpkinProjective::msm(&generators, &witness).unwrap()
+    );
+
+    // The malicious-secure variant evaluates a check vector alongside
+    let checked = client
+        .commit_malicious(&params, &preprocessed, &witness, &mut rng)
+        .await
+        .unwrap();
+    assert_eq!(checked, commitment);
+    let (mut encrypted, _) = malicious_encrypt(&params, &witness, &mut rng);
+    encrypted.masked_check.pop();
+    let err = client
+        .send_malicious_eval::<GrumpkinProjective>(&encrypted)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("check_vector"), "{err}");
+
+    // An inner-product argument commits to a and b through one generator set
+    let ipa_generators = IpaGenerators::<GrumpkinProjective>::rand(16, &mut rng);
+    let key = DelegatedIpaKey::new(ipa_generators, &mut rng);
+    let ipa = EmsmClient::new(&server_url, "commitments".to_string())
+        .with_circuit("ipa")
+        .with_session_token(client.session_token().unwrap());
+    key.upload(&ipa).await.unwrap();
+    let (a, b) = (&witness[..16], &witness[16..32]);
+    let (commitment, proof) = key
+        .prove(&ipa, a.to_vec(), b.to_vec(), &mut rng)
+        .await
+        .unwrap();
+    assert_eq!(commitment, key.generators.commit(a, b));
+    assert!(bulletproofs::verify(&key.generators, &commitment, &proof));
+
+    // The set only takes Grumpkin scalars
+    let err = client
+        .send_eval::<ark_bn254::G1Projective>(&[Fr::from(1u64); 64])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("422"), "{err}");
+
+    // and Groth16 circuits need a pairing
+    let groth16 = EmsmClient::new(&server_url, "groth16".to_string()).with_curve(CurveId::Grumpkin);
+    let empty = SetupRequest {
+        h_generators: Vec::new(),
+        l_generators: Vec::new(),
+        a_generators: Vec::new(),
+        b_g1_generators: Vec::new(),
+        b_g2_generators: Vec::new(),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    let err = groth16.send_setup(&empty).await.unwrap_err();
+    assert!(err.to_string().contains("422"), "{err}");
+
+    // Generators outside the prime-order subgroup are refused before they are stored
+    let outside = std::iter::repeat_with(|| ark_bn254::Fq2::rand(&mut rng))
+        .filter_map(|x| G2Affine::get_point_from_x_unchecked(x, false))
+        .find(|point| !point.is_in_correct_subgroup_assuming_on_curve())
+        .unwrap();
+    let no_points = ark_vec_to_bytes::<ark_bn254::G1Affine>(&[]);
+    let poisoned = SetupRequest {
+        h_generators: no_points.clone(),
+        l_generators: no_points.clone(),
+        a_generators: no_points.clone(),
+        b_g1_generators: no_points,
+        b_g2_generators: ark_vec_to_bytes(&[G2Affine::rand(&mut rng), outside]),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    let bn254 = EmsmClient::new(&server_url, "poisoned".to_string());
+    let err = bn254.send_setup(&poisoned).await.unwrap_err();
+    let message = err.to_string();
+    assert!(
+        message.contains("400") && message.contains("b_g2_generators"),
+        "{message}"
+    );
+    assert!(
+        message.contains("element 1 is not in the prime-order subgroup"),
+        "{message}"
+    );
+}
+
+/// Test that a gated /setup only admits clients presenting a valid API key.
+#[tokio::test]
+async fn test_setup_gate_api_key() {
+    let mut rng = ChaCha20Rng::seed_from_u64(7);
+
+    let config = ServerConfig {
+        setup_gate: SetupGate::ApiKeys(HashSet::from(["key-1".to_string()])),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let setup_req = SetupRequest {
+        h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+        l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+        a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+        b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+        b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+
+    let anonymous = EmsmClient::new(&server_url, "gated".to_string());
+    assert!(
+        anonymous.send_setup(&setup_req).await.is_err(),
+        "missing key should be rejected"
+    );
+
+    let wrong_key = EmsmClient::new(&server_url, "gated".to_string())
+        .with_setup_credential(SetupCredential::ApiKey("key-2".to_string()));
+    assert!(
+        wrong_key.send_setup(&setup_req).await.is_err(),
+        "unknown key should be rejected"
+    );
+
+    let authorized = EmsmClient::new(&server_url, "gated".to_string())
+        .with_setup_credential(SetupCredential::ApiKey("key-1".to_string()));
+    authorized
+        .send_setup(&setup_req)
+        .await
+        .expect("valid key should be admitted");
+}
+
+/// Test that tenants get separate session namespaces, and that their session,
+/// memory and request limits are enforced.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tenants() {
+    use stealthsnark::protocol::problem::ErrorCode;
+    use stealthsnark::protocol::tenant::{Tenant, TenantLimits};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(29);
+
+    let tenant = |id: &str, limits| Tenant {
+        id: id.to_string(),
+        api_keys: HashSet::from([format!("{id}-key")]),
+        limits,
+    };
+    let config = ServerConfig {
+        tenants: vec![
+            tenant(
+                "a",
+                TenantLimits {
+                    max_sessions: Some(1),
+                    ..Default::default()
+                },
+            ),
+            tenant("b", TenantLimits::default()),
+            tenant(
+                "c",
+                TenantLimits {
+                    max_generator_bytes: Some(1024),
+                    ..Default::default()
+                },
+            ),
+        ],
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+    let client = |tenant: &str, session_id: &str| {
+        EmsmClient::new(&server_url, session_id.to_string())
+            .with_setup_credential(SetupCredential::ApiKey(format!("{tenant}-key")))
+    };
+    let code = |err: StealthSnarkError| err.server_error().unwrap().code();
+
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+        let (sapk, vk) = cube_sapk(&mut rng);
+        keys.push((Arc::new(sapk), vk));
+    }
+
+    // Both tenants hold a session "shared", each with its own generators
+    let a = client("a", "shared");
+    let b = client("b", "shared");
+    a.send_setup(&SetupRequest::from(keys[0].0.as_ref()))
+        .await
+        .unwrap();
+    b.send_setup(&SetupRequest::from(keys[1].0.as_ref()))
+        .await
+        .unwrap();
+    for (client, (sapk, vk)) in [(&a, &keys[0]), (&b, &keys[1])] {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let proof = client.prove(sapk.clone(), circuit, rng.clone()).await.unwrap();
+        assert!(Groth16::<Bn254>::verify(vk, &[Fr::from(35u64)], &proof).unwrap());
+        assert_eq!(client.session_usage().await.unwrap().proves, 1);
+    }
+
+    // Without a tenant's key no session is reachable
+    let anonymous = EmsmClient::new(&server_url, "shared".to_string())
+        .with_generators_hash(SetupRequest::from(keys[0].0.as_ref()).generators_hash());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let err = anonymous
+        .prove(keys[0].0.clone(), circuit, rng.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(code(err), Some(ErrorCode::CredentialMissing));
+
+    // Tenant a holds at most one session; replacing its circuit is fine
+    let err = client("a", "second")
+        .send_setup(&SetupRequest::from(keys[0].0.as_ref()))
+        .await
+        .unwrap_err();
+    assert_eq!(code(err), Some(ErrorCode::TenantLimitExceeded));
+    a.send_setup(&SetupRequest::from(keys[1].0.as_ref()))
+        .await
+        .unwrap();
+
+    // Tenant c cannot hold even one circuit's generators
+    let err = client("c", "shared")
+        .send_setup(&SetupRequest::from(keys[0].0.as_ref()))
+        .await
+        .unwrap_err();
+    assert_eq!(code(err), Some(ErrorCode::TenantLimitExceeded));
+}
+
+/// Test that setup and prove are recorded in a verifiable audit trail.
+#[tokio::test]
+async fn test_audit_trail() {
+    let mut rng = ChaCha20Rng::seed_from_u64(11);
+
+    let sink = Arc::new(MemoryAuditSink::default());
+    let config = ServerConfig {
+        audit: Some(Arc::new(AuditLog::new(sink.clone()))),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+
+    let client = EmsmClient::new(&server_url, "audited".to_string());
+    let setup_req = SetupRequest {
+        h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+        l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+        a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+        b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+        b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    client.send_setup(&setup_req).await.unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) =
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_req = ProveRequest::from(&request);
+    client.send_prove(&prove_req).await.unwrap();
+
+    let records = verify_chain(&sink.lines()).expect("audit chain should verify");
+    assert_eq!(records.len(), 2);
+    assert!(matches!(
+        &records[0].event,
+        AuditEvent::SessionCreated { session_id, replaced: false, .. } if session_id == "audited"
+    ));
+    assert!(matches!(&records[1].event, AuditEvent::ProveServed { .. }));
+}
+
+/// Collects metering events delivered to the hook.
+#[derive(Default)]
+struct RecordingHook {
+    events: std::sync::Mutex<Vec<MeteringEvent>>,
+}
+
+impl MeteringHook for RecordingHook {
+    fn on_prove(&self, event: &MeteringEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Test that prove responses carry compute units and usage is aggregated per session and key.
+#[tokio::test]
+async fn test_usage_metering() {
+    let mut rng = ChaCha20Rng::seed_from_u64(13);
+
+    let hook = Arc::new(RecordingHook::default());
+    let config = ServerConfig {
+        setup_gate: SetupGate::ApiKeys(HashSet::from(["key-1".to_string()])),
+        metering_hook: Some(hook.clone()),
+        admin_key: Some("admin".to_string()),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+
+    let client = EmsmClient::new(&server_url, "metered".to_string())
+        .with_setup_credential(SetupCredential::ApiKey("key-1".to_string()));
+    let setup_req = SetupRequest {
+        h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+        l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+        a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+        b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+        b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    client.send_setup(&setup_req).await.unwrap();
+
+    let expected = compute_units(
+        [
+            sapk.emsm_h.generators.len(),
+            sapk.emsm_l.generators.len(),
+            sapk.emsm_a.generators.len(),
+            sapk.emsm_b_g1.generators.len(),
+        ],
+        sapk.emsm_b_g2.generators.len(),
+    );
+
+    for _ in 0..2 {
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let (request, _state) =
+            client_encrypt(&sapk, circuit, &mut rng).unwrap();
+        let prove_req = ProveRequest::from(&request);
+        let response = client.send_prove(&prove_req).await.unwrap();
+        assert_eq!(response.metadata.compute_units, expected);
+    }
+
+    let session = client.session_usage().await.unwrap();
+    assert_eq!(session.proves, 2);
+    assert_eq!(session.compute_units, 2 * expected);
+    let elements = sapk.emsm_h.generators.len()
+        + sapk.emsm_l.generators.len()
+        + sapk.emsm_a.generators.len()
+        + sapk.emsm_b_g1.generators.len()
+        + sapk.emsm_b_g2.generators.len();
+    assert_eq!(session.msm_elements, 2 * elements as u64);
+    assert_eq!(client.account_usage().await.unwrap(), session);
+
+    // Session usage is only visible to the key that set the session up
+    let other = EmsmClient::new(&server_url, "metered".to_string());
+    assert!(other.session_usage().await.is_err());
+
+    // The admin API reports every session, to the admin key only
+    let admin = reqwest::Client::new();
+    let sessions_url = format!("{server_url}/admin/sessions");
+    let unauthorized = admin.get(&sessions_url).send().await.unwrap();
+    assert_eq!(unauthorized.status(), 401);
+    let forbidden = admin
+        .get(&sessions_url)
+        .header(ADMIN_KEY_HEADER, "key-1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forbidden.status(), 403);
+    let stats: Vec<SessionStats> = admin
+        .get(&sessions_url)
+        .header(ADMIN_KEY_HEADER, "admin")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].session_id, "metered");
+    assert_eq!(stats[0].usage, session);
+    let metrics = admin
+        .get(format!("{server_url}/admin/metrics"))
+        .header(ADMIN_KEY_HEADER, "admin")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        metrics.contains("stealthsnark_session_proves_total{tenant=\"\",session=\"metered\"} 2\n")
+    );
+
+    let events = hook.events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].account.as_deref(), Some("key-1"));
+}
+
+/// Test that a prove request exceeding the server's deadline is aborted.
+#[tokio::test]
+async fn test_prove_timeout() {
+    let mut rng = ChaCha20Rng::seed_from_u64(17);
+
+    let config = ServerConfig {
+        prove_timeout: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+
+    let client = EmsmClient::new(&server_url, "deadline".to_string());
+    let setup_req = SetupRequest {
+        h_generators: ark_vec_to_bytes(&sapk.emsm_h.generators),
+        l_generators: ark_vec_to_bytes(&sapk.emsm_l.generators),
+        a_generators: ark_vec_to_bytes(&sapk.emsm_a.generators),
+        b_g1_generators: ark_vec_to_bytes(&sapk.emsm_b_g1.generators),
+        b_g2_generators: ark_vec_to_bytes::<G2Affine>(&sapk.emsm_b_g2.generators),
+        public_generators: Vec::new(),
+        key_fingerprint: None,
+    };
+    client.send_setup(&setup_req).await.unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) =
+        client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_req = ProveRequest::from(&request);
+    let err = match client.send_prove(&prove_req).await {
+        Ok(_) => panic!("prove should exceed the deadline"),
+        Err(e) => e,
+    };
+    let StealthSnarkError::DeadlineExceeded(exceeded) = &err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(exceeded.completed_fraction(), Some(0.0));
+}
+
+/// Test that the server aborts a prove at the client's deadline and reports how
+/// much of it was done, while a prove within its deadline succeeds.
+#[tokio::test]
+async fn test_client_deadline() {
+    let mut rng = ChaCha20Rng::seed_from_u64(55);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let url = server_url;
+
+    let client = EmsmClient::new(&url, "hurried".to_string()).with_deadline(Duration::ZERO);
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let total = [
+        request.v_h.len(),
+        request.v_l.len(),
+        request.v_a.len(),
+        request.v_b_g1.len(),
+        request.v_b_g2.len(),
+    ];
+    let err = match client.send_prove(&ProveRequest::from(&request)).await {
+        Ok(_) => panic!("prove should exceed the deadline"),
+        Err(e) => e,
+    };
+    let StealthSnarkError::DeadlineExceeded(exceeded) = &err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(exceeded.completed, Some(0));
+    assert_eq!(exceeded.total, Some(total.iter().sum::<usize>() as u64));
+    assert_eq!(exceeded.completed_fraction(), Some(0.0));
+
+    // A deadline past what the server's clock can represent (u64 nanoseconds on
+    // some platforms) is as good as none, rather than overflowing
+    let client = EmsmClient::new(&url, "endless".to_string())
+        .with_deadline(Duration::from_millis(u64::MAX));
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    client
+        .send_prove(&ProveRequest::from(&request))
+        .await
+        .unwrap();
+
+    let client =
+        EmsmClient::new(&url, "patient".to_string()).with_deadline(Duration::from_secs(60));
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client.prove(sapk, circuit, rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Test a key delegating some MSMs: the server evaluates the empty vectors of
+/// the others to the identity and the client computes them, over POST /prove
+/// and a split prove alike.
+#[tokio::test]
+async fn test_partial_delegation() {
+    use stealthsnark::groth16::server_aided::Delegation;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(56);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let delegation = Delegation {
+        h: true,
+        b_g2: true,
+        ..Delegation::NONE
+    };
+    let sapk = Arc::new(sapk.with_delegation(delegation));
+    let client = EmsmClient::new(&server_url, "partial".to_string());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client
+        .prove(sapk.clone(), circuit, ChaCha20Rng::from_rng(&mut rng).unwrap())
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client
+        .prove_split(sapk, circuit, ChaCha20Rng::from_rng(&mut rng).unwrap())
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Test that prove bodies over `max_prove_bytes` are refused before parsing.
+#[tokio::test]
+async fn test_prove_body_limit() {
+    let mut rng = ChaCha20Rng::seed_from_u64(18);
+
+    let config = ServerConfig {
+        max_prove_bytes: 64,
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let client = EmsmClient::new(&server_url, "limit".to_string());
+    client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let err = match client.send_prove(&ProveRequest::from(&request)).await {
+        Ok(_) => panic!("prove body should exceed the limit"),
+        Err(e) => e,
+    };
+    assert!(err.to_string().contains("413"), "unexpected error: {err}");
+}
+
+/// Test that idle sessions expire after `session_ttl` and that keepalives extend them.
+#[tokio::test]
+async fn test_session_keepalive() {
+    let mut rng = ChaCha20Rng::seed_from_u64(19);
+
+    let ttl = Duration::from_millis(400);
+    let config = ServerConfig {
+        session_ttl: Some(ttl),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let client = EmsmClient::new(&server_url, "idle".to_string());
+    client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+
+    // Each keepalive restarts the idle timer, so the session outlives the TTL
+    for _ in 0..3 {
+        tokio::time::sleep(ttl / 2).await;
+        let left = client.extend_session().await.unwrap().unwrap();
+        assert!(left <= ttl && left > ttl / 2, "unexpected remaining time {left:?}");
+    }
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_resp = client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+    let response =
+        stealthsnark::groth16::server_aided::ServerResponse::try_from(&prove_resp).unwrap();
+    let proof = client_decrypt(&sapk, &response, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    // Left idle past the TTL, the session is gone
+    tokio::time::sleep(ttl + ttl / 2).await;
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let err = match client.send_prove(&ProveRequest::from(&request)).await {
+        Ok(_) => panic!("prove against an expired session should fail"),
+        Err(e) => e,
+    };
+    assert!(err.to_string().contains("412"), "unexpected error: {err}");
+    let err = client.extend_session().await.unwrap_err();
+    assert!(err.to_string().contains("404"), "unexpected error: {err}");
+}
+
+/// Test that fixed-base tables are built after /setup returns, that proves are
+/// served before and after they are ready, and that the session status reports
+/// their readiness.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_fixed_base_tables() {
+    let mut rng = ChaCha20Rng::seed_from_u64(37);
+
+    let plain_url = spawn_server(ServerConfig::default()).await;
+    let config = ServerConfig {
+        fixed_base_window: Some(4),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let plain = EmsmClient::new(&plain_url, "plain".to_string());
+    plain.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    assert!(plain.session_status().await.unwrap().tables_ready.is_empty());
+
+    let client = EmsmClient::new(&server_url, "tables".to_string());
+    client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove(sapk.clone(), circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    loop {
+        let status = client.session_status().await.unwrap();
+        if status.tables_ready[DEFAULT_CIRCUIT] {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "tables never became ready");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove(sapk.clone(), circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove_split(sapk, circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Test that fixed-base tables count against a tenant's generator memory, and
+/// that a circuit whose tables exceed the server's table budget is served
+/// without them.
+#[tokio::test]
+async fn test_fixed_base_table_budgets() {
+    use stealthsnark::protocol::problem::ErrorCode;
+    use stealthsnark::protocol::tenant::{Tenant, TenantLimits};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(41);
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let g1 = sapk.emsm_h.generators.len()
+        + sapk.emsm_l.generators.len()
+        + sapk.emsm_a.generators.len()
+        + sapk.emsm_b_g1.generators.len();
+    let generator_bytes = g1 * std::mem::size_of::<G1Affine>()
+        + sapk.emsm_b_g2.generators.len() * std::mem::size_of::<G2Affine>();
+
+    // Room for the generators, but not for their 4-bit tables (64 times as large)
+    let tenant = Tenant {
+        id: "t".to_string(),
+        api_keys: HashSet::from(["t-key".to_string()]),
+        limits: TenantLimits {
+            max_generator_bytes: Some(4 * generator_bytes),
+            ..Default::default()
+        },
+    };
+    for (window, fits) in [(None, true), (Some(4), false)] {
+        let config = ServerConfig {
+            tenants: vec![tenant.clone()],
+            fixed_base_window: window,
+            ..Default::default()
+        };
+        let server_url = spawn_server(config).await;
+        let client = EmsmClient::new(&server_url, "tenant".to_string())
+            .with_setup_credential(SetupCredential::ApiKey("t-key".to_string()));
+        let result = client.send_setup(&SetupRequest::from(&*sapk)).await;
+        match result {
+            Ok(_) => assert!(fits, "tables were not counted"),
+            Err(err) => {
+                assert!(!fits, "unexpected error: {err}");
+                let code = err.server_error().unwrap().code();
+                assert_eq!(code, Some(ErrorCode::TenantLimitExceeded));
+            }
+        }
+    }
+
+    let config = ServerConfig {
+        fixed_base_window: Some(4),
+        max_table_bytes: Some(generator_bytes),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+    let client = EmsmClient::new(&server_url, "over-budget".to_string());
+    client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove(sapk, circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    assert!(!client.session_status().await.unwrap().tables_ready[DEFAULT_CIRCUIT]);
+}
+
+/// Test that a client built `with_auto_resetup` sets up an expired session again
+/// and completes the prove.
+#[tokio::test]
+async fn test_auto_resetup() {
+    let mut rng = ChaCha20Rng::seed_from_u64(29);
+
+    let ttl = Duration::from_millis(200);
+    let config = ServerConfig {
+        session_ttl: Some(ttl),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let plain = EmsmClient::new(&server_url, "plain".to_string());
+    let resetup = EmsmClient::new(&server_url, "resetup".to_string()).with_auto_resetup();
+    plain.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    resetup.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+    tokio::time::sleep(ttl * 2).await;
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let rng_for = |rng: &mut ChaCha20Rng| ChaCha20Rng::from_rng(rng).unwrap();
+    let err = plain
+        .prove(sapk.clone(), circuit.clone(), rng_for(&mut rng))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("412"), "unexpected error: {err}");
+
+    let proof = resetup
+        .prove(sapk.clone(), circuit.clone(), rng_for(&mut rng))
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    // Checked proofs go through the same retry
+    tokio::time::sleep(ttl * 2).await;
+    let proof = resetup
+        .prove_with_mode(sapk, circuit, ProvingMode::Malicious, rng)
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Test that a resent prove request is answered from the cache without being
+/// evaluated or metered again.
+#[tokio::test]
+async fn test_prove_retry_cached() {
+    let mut rng = ChaCha20Rng::seed_from_u64(20);
+
+    let config = ServerConfig {
+        prove_cache_ttl: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let client = EmsmClient::new(&server_url, "retry".to_string());
+    client.send_setup(&SetupRequest::from(&sapk)).await.unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let prove_req = ProveRequest::from(&request);
+    let first = client.send_prove(&prove_req).await.unwrap();
+    let retry = client.send_prove(&prove_req).await.unwrap();
+    assert_eq!(bincode::serialize(&first).unwrap(), bincode::serialize(&retry).unwrap());
+    assert_eq!(client.session_usage().await.unwrap().proves, 1);
+
+    let response =
+        stealthsnark::groth16::server_aided::ServerResponse::try_from(&retry).unwrap();
+    let proof = client_decrypt(&sapk, &response, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    // A fresh encryption of the same witness is a new request
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, _state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+    assert_eq!(client.session_usage().await.unwrap().proves, 2);
+}
+
+/// Test that a prove split into per-MSM sub-requests over HTTP/2 yields a valid
+/// proof and is metered like the unsplit prove, also when each MSM is unmasked
+/// as it arrives.
+#[tokio::test]
+async fn test_split_prove_http2() {
+    let mut rng = ChaCha20Rng::seed_from_u64(21);
+
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "split".to_string()).with_http2();
+    client.send_setup(&SetupRequest::from(&*sapk)).await.unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let expected_units = compute_units(
+        [
+            request.v_h.len(),
+            request.v_l.len(),
+            request.v_a.len(),
+            request.v_b_g1.len(),
+        ],
+        request.v_b_g2.len(),
+    );
+    let prove_resp = client
+        .send_prove_split(ProveRequest::from(&request))
+        .await
+        .unwrap();
+    assert_eq!(prove_resp.metadata.compute_units, expected_units);
+
+    let response =
+        stealthsnark::groth16::server_aided::ServerResponse::try_from(&prove_resp).unwrap();
+    let proof = client_decrypt(&sapk, &response, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    let usage = client.session_usage().await.unwrap();
+    assert_eq!(usage.proves, 1);
+    assert_eq!(usage.compute_units, expected_units);
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let prove_rng = ChaCha20Rng::from_rng(&mut rng).unwrap();
+    let proof = client.prove_split(sapk, circuit, prove_rng).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    assert_eq!(client.session_usage().await.unwrap().proves, 2);
+}
+
+/// Test that /estimate prices a request from its vector sizes alone.
+#[tokio::test]
+async fn test_estimate() {
+    let server_url = spawn_server(ServerConfig::default()).await;
+
+    let client = EmsmClient::new(&server_url, "estimate".to_string());
+    let estimate = client
+        .estimate(&EstimateRequest {
+            h: 4096,
+            l: 4096,
+            a: 4096,
+            b_g1: 4096,
+            b_g2: 4096,
+        })
+        .await
+        .unwrap();
+    assert_eq!(estimate.compute_units, compute_units([4096; 4], 4096));
+    assert_eq!(estimate.in_flight, 0);
+    assert_eq!(estimate.queue_ms, 0);
+    assert!(estimate.compute_ms > 0);
+    assert!(!estimate.calibrated);
+}
+
+/// Prove requests with a parallelism hint run on part of the server's pool, and
+/// their narrowed MSMs do not calibrate its throughput estimate.
+#[cfg(feature = "parallel")]
+#[tokio::test]
+async fn test_parallelism_hint() {
+    use stealthsnark::emsm::parallel::ParallelConfig;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(27);
+
+    let config = ServerConfig {
+        parallel: ParallelConfig::with_threads(4).unwrap(),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let hint = ParallelismHint {
+        max_threads: Some(1),
+        ..Default::default()
+    };
+    let client = EmsmClient::new(&server_url, "hinted".to_string())
+        .with_parallelism_hint(hint);
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client.prove(sapk.clone(), circuit, rng.clone()).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+
+    let sizes = EstimateRequest {
+        h: 4,
+        l: 4,
+        a: 4,
+        b_g1: 4,
+        b_g2: 4,
+    };
+    assert!(!client.estimate(&sizes).await.unwrap().calibrated);
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (request, state) = client_encrypt(&sapk, circuit, &mut rng).unwrap();
+    let response = client.send_prove(&ProveRequest::from(&request)).await.unwrap();
+    let response =
+        stealthsnark::groth16::server_aided::ServerResponse::try_from(&response).unwrap();
+    let proof = client_decrypt(&sapk, &response, &state).unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    assert!(client.estimate(&sizes).await.unwrap().calibrated);
+}
+
+/// Test that a server with one evaluation slot serves interactive and batch
+/// clients proving at once, and rejects unknown QoS classes.
+#[tokio::test]
+async fn test_qos_classes() {
+    use stealthsnark::protocol::scheduler::{QosClass, QosWeights, SchedulerConfig, QOS_HEADER};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(28);
+
+    let config = ServerConfig {
+        scheduler: Some(SchedulerConfig {
+            slots: 1,
+            weights: QosWeights::default(),
+            max_queued: None,
+        }),
+        ..Default::default()
+    };
+    let server_url = spawn_server(config).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let interactive = EmsmClient::new(&server_url, "wallet".to_string());
+    let batch = EmsmClient::new(&server_url, "bulk".to_string()).with_qos(QosClass::Batch);
+    for client in [&interactive, &batch] {
+        client
+            .send_setup(&SetupRequest::from(sapk.as_ref()))
+            .await
+            .unwrap();
+    }
+
+    let circuit = || CubeCircuit { x: Some(Fr::from(3u64)) };
+    let (a, b) = tokio::join!(
+        interactive.prove(sapk.clone(), circuit(), rng.clone()),
+        batch.prove(sapk.clone(), circuit(), rng.clone()),
+    );
+    for proof in [a.unwrap(), b.unwrap()] {
+        assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+    }
+
+    let resp = reqwest::Client::new()
+        .post(format!("{server_url}/prove"))
+        .header(QOS_HEADER, "urgent")
+        .body(vec![0u8; 8])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let problem: stealthsnark::protocol::problem::Problem = resp.json().await.unwrap();
+    assert_eq!(problem.field.as_deref(), Some(QOS_HEADER));
+}
+
+/// Test that a client with a retry policy waits out a 429 `Retry-After` and
+/// proves, and that one without surfaces it.
+#[tokio::test]
+async fn test_retry_after() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use axum::response::IntoResponse;
+    use stealthsnark::protocol::client::RetryPolicy;
+    use stealthsnark::protocol::problem::{ErrorCode, Problem};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(29);
+
+    // Turns the first prove of every pair away, as a server with a full queue
+    let proves = Arc::new(AtomicU32::new(0));
+    let state = Arc::new(RwLock::new(ServerState::new()));
+    let app = create_router(state).layer(axum::middleware::from_fn(
+        move |request: axum::extract::Request, next: axum::middleware::Next| {
+            let proves = proves.clone();
+            async move {
+                let is_prove = request.uri().path() == "/prove";
+                if is_prove && proves.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                    let problem = Problem::new(ErrorCode::Overloaded).with_retry_after(1);
+                    return problem.into_response();
+                }
+                next.run(request).await
+            }
+        },
+    ));
+    let server_url = serve(app).await;
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let client = EmsmClient::new(&server_url, "busy".to_string())
+        .with_retry_policy(RetryPolicy::default());
+    client
+        .send_setup(&SetupRequest::from(sapk.as_ref()))
+        .await
+        .unwrap();
+
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let report = client
+        .prove_with_report(sapk.clone(), circuit, rng.clone())
+        .await
+        .unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &report.proof).unwrap());
+    // Both attempts uploaded the request
+    let request_bytes = report.bandwidth.msms.iter().map(|msm| msm.sent).sum::<u64>();
+    assert!(report.bandwidth.sent > 2 * request_bytes);
+
+    // Without a policy, or with one that will not wait a second, the 429 surfaces
+    let impatient = RetryPolicy {
+        max_delay: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let token = client.session_token().unwrap();
+    for client in [
+        EmsmClient::new(&server_url, "busy".to_string()),
+        EmsmClient::new(&server_url, "busy".to_string()).with_retry_policy(impatient),
+    ] {
+        let client = client.with_session_token(token);
+        client
+            .send_setup(&SetupRequest::from(sapk.as_ref()))
+            .await
+            .unwrap();
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        let err = client.prove(sapk.clone(), circuit, rng.clone()).await.unwrap_err();
+        let err = err.server_error().unwrap();
+        assert_eq!(err.code(), Some(ErrorCode::Overloaded));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(1)));
+        // Let the next prove through
+        let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+        client.prove(sapk.clone(), circuit, rng.clone()).await.unwrap();
+    }
+}
+
+/// Test that replicas sharing an object store serve each other's sessions, and
+/// only to the API key they were set up with.
+#[tokio::test]
+async fn test_stateless_replicas() {
+    use stealthsnark::protocol::problem::ErrorCode;
+    use stealthsnark::protocol::store::{MemoryStore, ObjectStore};
+
+    let mut rng = ChaCha20Rng::seed_from_u64(30);
+
+    let store = Arc::new(MemoryStore::default());
+    let mut urls = Vec::new();
+    for _ in 0..2 {
+        let config = ServerConfig {
+            setup_gate: SetupGate::ApiKeys(HashSet::from([
+                "key-1".to_string(),
+                "key-2".to_string(),
+            ])),
+            store: Some(store.clone() as Arc<dyn ObjectStore>),
+            ..Default::default()
+        };
+        urls.push(spawn_server(config).await);
+    }
+
+    let (sapk, vk) = cube_sapk(&mut rng);
+    let sapk = Arc::new(sapk);
+    let setup = SetupRequest::from(sapk.as_ref());
+    let credential = SetupCredential::ApiKey("key-1".to_string());
+    let owner =
+        EmsmClient::new(&urls[0], "roaming".to_string()).with_setup_credential(credential.clone());
+    owner.send_setup(&setup).await.unwrap();
+    // Generators, session and circuit
+    assert_eq!(store.len(), 3);
+
+    // The other replica never saw the setup, and does not hand the session to
+    // another API key
+    let stranger = EmsmClient::new(&urls[1], "roaming".to_string())
+        .with_setup_credential(SetupCredential::ApiKey("key-2".to_string()))
+        .with_generators_hash(setup.generators_hash());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let err = stranger.prove(sapk.clone(), circuit, rng.clone()).await.unwrap_err();
+    let err = err.server_error().unwrap();
+    assert_eq!(err.code(), Some(ErrorCode::SessionForbidden));
+
+    let client = EmsmClient::new(&urls[1], "roaming".to_string())
+        .with_setup_credential(credential)
+        .with_session_token(owner.session_token().unwrap())
+        .with_generators_hash(setup.generators_hash());
+    let commitment = client.fetch_commitment().await.unwrap();
+    assert_eq!(commitment.generators_hash, setup.generators_hash());
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    let proof = client.prove(sapk.clone(), circuit, rng.clone()).await.unwrap();
+    assert!(Groth16::<Bn254>::verify(&vk, &[Fr::from(35u64)], &proof).unwrap());
+}
+
+/// Stand-in for a TEE: the "quote" is a hash over the measurement and report data.
+struct FakeEnclave {
+    measurement: Vec<u8>,
+    /// Fingerprint of the TLS key the enclave attests.
+    tls_key_fingerprint: [u8; 32],
+}
+
+fn fake_quote(measurement: &[u8], report_data: &[u8; 32]) -> Vec<u8> {
+    let mut data = measurement.to_vec();
+    data.extend_from_slice(report_data);
+    stealthsnark::protocol::audit::sha256(&data).to_vec()
+}
+
+impl AttestationProvider for FakeEnclave {
+    fn attest(&self, nonce: &[u8; 32]) -> Result<AttestationReport, AttestationError> {
+        let report_data = report_data_binding(nonce, &self.tls_key_fingerprint);
+        Ok(AttestationReport {
+            platform: TeePlatform::SevSnp,
+            measurement: self.measurement.clone(),
+            report_data,
+            tls_key_fingerprint: self.tls_key_fingerprint,
+            quote: fake_quote(&self.measurement, &report_data),
+        })
+    }
+}
+
+struct FakeQuoteVerifier;
+
+impl QuoteVerifier for FakeQuoteVerifier {
+    fn verify_quote(&self, report: &AttestationReport) -> Result<(), AttestationError> {
+        if report.quote == fake_quote(&report.measurement, &report.report_data) {
+            Ok(())
+        } else {
+            Err(AttestationError::InvalidQuote("bad signature".to_string()))
+        }
+    }
+}
+
+/// TCP listener completing a TLS handshake with `cert` on every connection.
+struct TlsListener {
+    tcp: tokio::net::TcpListener,
+    acceptor: tokio_rustls::TlsAcceptor,
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = tokio_rustls::server::TlsStream<tokio::net::TcpStream>;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let Ok((stream, addr)) = self.tcp.accept().await else {
+                continue;
+            };
+            if let Ok(stream) = self.acceptor.accept(stream).await {
+                return (stream, addr);
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.tcp.local_addr()
+    }
+}
+
+/// Spawn an in-process server with `config` behind TLS with `cert`, returning
+/// its URL.
+async fn spawn_tls_server(config: ServerConfig, cert: &rcgen::CertifiedKey) -> String {
+    let state = Arc::new(RwLock::new(ServerState::with_config(config)));
+    spawn_tls_router(create_router(state), cert).await
+}
+
+/// Serve `app` behind TLS with `cert`, returning its URL.
+async fn spawn_tls_router(app: axum::Router, cert: &rcgen::CertifiedKey) -> String {
+    use tokio_rustls::rustls;
+
+    let key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.cert.der().clone()], key.into())
+        .unwrap();
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let listener = TlsListener {
+        tcp: tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind failed"),
+        acceptor: tokio_rustls::TlsAcceptor::from(Arc::new(tls)),
+    };
+    let url = format!("https://{}", listener.tcp.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    url
+}
+
+/// Test that the client refuses to upload anything to a server failing
+/// attestation, or reached over a connection to another TLS key than the one
+/// attested.
+#[tokio::test]
+async fn test_attestation_gates_client() {
+    let mut rng = ChaCha20Rng::seed_from_u64(13);
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let fingerprint = tls_key_fingerprint(cert.cert.der()).unwrap();
+    let enclave = |tls_key_fingerprint| {
+        let enclave = FakeEnclave {
+            measurement: vec![1u8; 48],
+            tls_key_fingerprint,
+        };
+        ServerConfig {
+            attestation: Some(Arc::new(enclave)),
+            ..Default::default()
+        }
+    };
+    let server_url = spawn_tls_server(enclave(fingerprint), &cert).await;
+
+    let (sapk, _vk) = cube_sapk(&mut rng);
+    let setup_req = SetupRequest::from(&sapk);
+
+    let policy = |measurement: Vec<u8>| AttestationPolicy {
+        platform: TeePlatform::SevSnp,
+        allowed_measurements: vec![measurement],
+        tls_key_fingerprint: Some(fingerprint),
+        verifier: Arc::new(FakeQuoteVerifier),
+    };
+
+    let untrusted_code = EmsmClient::new(&server_url, "tee".to_string())
+        .with_attestation(policy(vec![2u8; 48]));
+    assert!(
+        untrusted_code.send_setup(&setup_req).await.is_err(),
+        "unknown measurement should be refused"
+    );
+
+    let trusted = EmsmClient::new(&server_url, "tee".to_string())
+        .with_attestation(policy(vec![1u8; 48]));
+    trusted
+        .send_setup(&setup_req)
+        .await
+        .expect("attested server should receive setup");
+    let circuit = CubeCircuit { x: Some(Fr::from(3u64)) };
+    trusted.prove(Arc::new(sapk), circuit, rng).await.unwrap();
+
+    // A man in the middle relaying the enclave's report from behind a TLS key
+    // of its own
+    let relay = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let relay_url = spawn_tls_server(enclave(fingerprint), &relay).await;
+    let client = EmsmClient::new(&relay_url, "tee".to_string())
+        .with_attestation(policy(vec![1u8; 48]))
+        .with_http2();
+    let err = client.send_setup(&setup_req).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StealthSnarkError::Attestation(AttestationError::ConnectionKeyMismatch)
+    ));
+
+    // Before the report is verified, the peer gets the nonce and no credentials
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let impostor = axum::Router::new().route(
+        "/attestation",
+        axum::routing::post(
+            move |headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+                recorded.lock().unwrap().push((headers, body.len()));
+                async { axum::http::StatusCode::NOT_FOUND }
+            },
+        ),
+    );
+    let impostor_url = spawn_tls_router(impostor, &relay).await;
+    let client = EmsmClient::new(&impostor_url, "tee".to_string())
+        .with_attestation(policy(vec![1u8; 48]))
+        .with_setup_credential(SetupCredential::ApiKey("secret".to_string()))
+        .with_session_token(SessionToken::random());
+    let err = client.send_setup(&setup_req).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StealthSnarkError::Attestation(AttestationError::Unavailable)
+    ));
+    let (headers, nonce_len) = seen.lock().unwrap().remove(0);
+    assert_eq!(nonce_len, 32);
+    assert!(headers.get(API_KEY_HEADER).is_none());
+    assert!(headers.get(SESSION_TOKEN_HEADER).is_none());
+
+    // Plain HTTP has no TLS key to bind
+    let plaintext = spawn_server(enclave(fingerprint)).await;
+    let client = EmsmClient::new(&plaintext, "tee".to_string())
+        .with_attestation(policy(vec![1u8; 48]));
+    let err = client.send_setup(&setup_req).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StealthSnarkError::Attestation(AttestationError::PlaintextTransport)
+    ));
+}